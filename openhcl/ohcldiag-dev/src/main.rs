@@ -8,6 +8,7 @@
 #![forbid(unsafe_code)]
 
 mod completions;
+mod pcap_rotate;
 
 use anyhow::Context;
 use clap::ArgGroup;
@@ -281,6 +282,14 @@ enum Command {
         /// Length of the packet to capture.
         #[clap(short('s'), long, default_value = "65535", value_parser = clap::value_parser!(u16).range(1..))]
         snaplen: u16,
+        /// Start a new file when the current one reaches this many megabytes.
+        /// Rotated files have a sequence number appended to the file name.
+        #[clap(short('C'), long, value_parser = clap::value_parser!(u64).range(1..))]
+        file_size: Option<u64>,
+        /// With --file-size, keep at most this many files per NIC, deleting
+        /// the oldest.
+        #[clap(short('W'), long, requires = "file_size", value_parser = clap::value_parser!(u64).range(1..))]
+        file_count: Option<u64>,
     },
     /// Memory usage profile tracing.
     MemoryProfileTrace {
@@ -800,6 +809,8 @@ pub fn main() -> anyhow::Result<()> {
                 output,
                 seconds,
                 snaplen,
+                file_size,
+                file_count,
             } => {
                 let client = new_client(driver.clone(), &vm)?;
                 println!(
@@ -820,7 +831,15 @@ pub fn main() -> anyhow::Result<()> {
                     .map(|(i, i_stream)| {
                         new_output.set_file_name(format!("{}-{}", &file_stem, i));
                         new_output.set_extension(extension);
-                        let mut out = AllowStdIo::new(fs_err::File::create(&new_output)?);
+                        let out: Box<dyn Write + Send> = match file_size {
+                            Some(file_size) => Box::new(pcap_rotate::RotatingWriter::new(
+                                new_output.clone(),
+                                file_size * 1024 * 1024,
+                                file_count.map(|n| n as usize),
+                            )?),
+                            None => Box::new(fs_err::File::create(&new_output)?),
+                        };
+                        let mut out = AllowStdIo::new(out);
                         Ok(async move { futures::io::copy(i_stream, &mut out).await })
                     })
                     .collect::<Result<Vec<_>, std::io::Error>>()?;
@@ -0,0 +1,291 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Splitting a pcapng stream across size-limited capture files.
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+const BLOCK_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 1;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+
+/// A writer that parses a pcapng stream and starts a new file whenever the
+/// current one would exceed `max_size` bytes.
+///
+/// Each file starts with the section header and interface description blocks
+/// seen so far, so that every file can be opened on its own. If `max_files` is
+/// set, the oldest files are deleted to keep at most that many.
+pub struct RotatingWriter {
+    path: PathBuf,
+    max_size: u64,
+    max_files: Option<usize>,
+    file: fs_err::File,
+    file_len: u64,
+    index: u64,
+    files: VecDeque<PathBuf>,
+    big_endian: bool,
+    headers: Vec<u8>,
+    pending: Vec<u8>,
+}
+
+impl RotatingWriter {
+    pub fn new(path: PathBuf, max_size: u64, max_files: Option<usize>) -> io::Result<Self> {
+        let file = fs_err::File::create(&path)?;
+        Ok(Self {
+            files: [path.clone()].into(),
+            path,
+            max_size,
+            max_files,
+            file,
+            file_len: 0,
+            index: 0,
+            big_endian: false,
+            headers: Vec::new(),
+            pending: Vec::new(),
+        })
+    }
+
+    fn u32_at(&self, buf: &[u8], offset: usize) -> u32 {
+        let bytes = buf[offset..offset + 4].try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    fn rotated_path(path: &Path, index: u64) -> PathBuf {
+        let mut name = path.file_stem().unwrap_or_default().to_owned();
+        name.push(format!(".{index}"));
+        if let Some(extension) = path.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        path.with_file_name(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.index += 1;
+        let path = Self::rotated_path(&self.path, self.index);
+        self.file = fs_err::File::create(&path)?;
+        self.file.write_all(&self.headers)?;
+        self.file_len = self.headers.len() as u64;
+        self.files.push_back(path);
+        if let Some(max_files) = self.max_files {
+            while self.files.len() > max_files {
+                fs_err::remove_file(self.files.pop_front().unwrap())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes out all complete blocks in `pending`.
+    fn process(&mut self) -> io::Result<()> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+        let mut offset = 0;
+        while self.pending.len() - offset >= 12 {
+            let block = &self.pending[offset..];
+            let block_type = u32::from_ne_bytes(block[..4].try_into().unwrap());
+            // The section header type is a palindrome, so it can be
+            // recognized before the section's byte order is known.
+            if block_type == BLOCK_SECTION_HEADER {
+                let magic = block[8..12].try_into().unwrap();
+                self.big_endian = if u32::from_be_bytes(magic) == BYTE_ORDER_MAGIC {
+                    true
+                } else if u32::from_le_bytes(magic) == BYTE_ORDER_MAGIC {
+                    false
+                } else {
+                    return Err(invalid("invalid pcapng byte order magic"));
+                };
+            }
+            let block_type = self.u32_at(block, 0);
+            let len = self.u32_at(block, 4) as usize;
+            if len < 12 || len % 4 != 0 {
+                return Err(invalid("invalid pcapng block length"));
+            }
+            if block.len() < len {
+                break;
+            }
+            let block = &block[..len];
+            match block_type {
+                BLOCK_SECTION_HEADER => {
+                    self.headers.clear();
+                    self.headers.extend_from_slice(block);
+                }
+                BLOCK_INTERFACE_DESCRIPTION => self.headers.extend_from_slice(block),
+                _ => {
+                    if self.file_len > self.headers.len() as u64
+                        && self.file_len + len as u64 > self.max_size
+                    {
+                        self.rotate()?;
+                    }
+                }
+            }
+            self.file.write_all(&self.pending[offset..offset + len])?;
+            self.file_len += len as u64;
+            offset += len;
+        }
+        self.pending.drain(..offset);
+        Ok(())
+    }
+}
+
+impl Write for RotatingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        self.process()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BLOCK_INTERFACE_DESCRIPTION;
+    use super::BLOCK_SECTION_HEADER;
+    use super::BYTE_ORDER_MAGIC;
+    use super::RotatingWriter;
+    use std::io::Write;
+    use std::path::Path;
+
+    const BLOCK_ENHANCED_PACKET: u32 = 6;
+
+    /// Builds a block with the given body, in the given byte order.
+    fn block(big_endian: bool, block_type: u32, body: &[u8]) -> Vec<u8> {
+        let to_bytes = |v: u32| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+        let len = (12 + body.len()) as u32;
+        let mut block = Vec::new();
+        block.extend_from_slice(&to_bytes(block_type));
+        block.extend_from_slice(&to_bytes(len));
+        block.extend_from_slice(body);
+        block.extend_from_slice(&to_bytes(len));
+        block
+    }
+
+    /// Returns a section header and an interface description block.
+    fn headers(big_endian: bool) -> Vec<u8> {
+        let mut shb_body = Vec::new();
+        let mut idb_body = Vec::new();
+        if big_endian {
+            shb_body.extend_from_slice(&BYTE_ORDER_MAGIC.to_be_bytes());
+            shb_body.extend_from_slice(&1u16.to_be_bytes());
+            idb_body.extend_from_slice(&1u16.to_be_bytes());
+        } else {
+            shb_body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+            shb_body.extend_from_slice(&1u16.to_le_bytes());
+            idb_body.extend_from_slice(&1u16.to_le_bytes());
+        }
+        shb_body.extend_from_slice(&[0; 2]);
+        shb_body.extend_from_slice(&[0xff; 8]);
+        idb_body.extend_from_slice(&[0; 6]);
+        let mut headers = block(big_endian, BLOCK_SECTION_HEADER, &shb_body);
+        headers.extend(block(big_endian, BLOCK_INTERFACE_DESCRIPTION, &idb_body));
+        headers
+    }
+
+    /// Returns a 64-byte packet block filled with `n`.
+    fn packet(big_endian: bool, n: u8) -> Vec<u8> {
+        block(big_endian, BLOCK_ENHANCED_PACKET, &[n; 52])
+    }
+
+    fn read(dir: &Path, name: &str) -> Vec<u8> {
+        fs_err::read(dir.join(name)).unwrap()
+    }
+
+    fn check_rotation(big_endian: bool) {
+        let dir = tempfile::tempdir().unwrap();
+        let headers = headers(big_endian);
+        assert_eq!(headers.len(), 48);
+
+        // Two packets fit in each file after the headers.
+        let mut writer = RotatingWriter::new(dir.path().join("capture.pcapng"), 200, None).unwrap();
+        writer.write_all(&headers).unwrap();
+        for n in 0..5 {
+            writer.write_all(&packet(big_endian, n)).unwrap();
+        }
+
+        let expected = |packets: &[u8]| {
+            let mut data = headers.clone();
+            for &n in packets {
+                data.extend(packet(big_endian, n));
+            }
+            data
+        };
+        assert_eq!(read(dir.path(), "capture.pcapng"), expected(&[0, 1]));
+        assert_eq!(read(dir.path(), "capture.1.pcapng"), expected(&[2, 3]));
+        assert_eq!(read(dir.path(), "capture.2.pcapng"), expected(&[4]));
+        assert!(!dir.path().join("capture.3.pcapng").exists());
+    }
+
+    #[test]
+    fn test_rotate_little_endian() {
+        check_rotation(false);
+    }
+
+    #[test]
+    fn test_rotate_big_endian() {
+        check_rotation(true);
+    }
+
+    #[test]
+    fn test_max_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer =
+            RotatingWriter::new(dir.path().join("capture.pcapng"), 200, Some(2)).unwrap();
+        writer.write_all(&headers(false)).unwrap();
+        for n in 0..5 {
+            writer.write_all(&packet(false, n)).unwrap();
+        }
+
+        assert!(!dir.path().join("capture.pcapng").exists());
+        assert!(dir.path().join("capture.1.pcapng").exists());
+        assert!(dir.path().join("capture.2.pcapng").exists());
+    }
+
+    #[test]
+    fn test_split_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut stream = headers(false);
+        for n in 0..5 {
+            stream.extend(packet(false, n));
+        }
+
+        // Blocks split across writes are held until they are complete.
+        let mut writer = RotatingWriter::new(dir.path().join("split.pcapng"), 200, None).unwrap();
+        for chunk in stream.chunks(7) {
+            writer.write_all(chunk).unwrap();
+        }
+        let mut whole = RotatingWriter::new(dir.path().join("whole.pcapng"), 200, None).unwrap();
+        whole.write_all(&stream).unwrap();
+
+        for (split, whole) in [
+            ("split.pcapng", "whole.pcapng"),
+            ("split.1.pcapng", "whole.1.pcapng"),
+            ("split.2.pcapng", "whole.2.pcapng"),
+        ] {
+            assert_eq!(read(dir.path(), split), read(dir.path(), whole));
+        }
+    }
+
+    #[test]
+    fn test_invalid_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = RotatingWriter::new(dir.path().join("capture.pcapng"), 200, None).unwrap();
+        let mut shb = headers(false);
+        shb[8..12].copy_from_slice(&[0; 4]);
+        assert!(writer.write_all(&shb).is_err());
+    }
+}
@@ -65,11 +65,11 @@
 use virt_mshv_vtl::VtlCrash;
 use vm_resource::ResourceResolver;
 use vm_topology::memory::MemoryRangeWithNode;
-use vmbus_channel::channel::VmbusDevice;
 use vmcore::vmtime::VmTimeKeeper;
 use vmm_core::input_distributor::InputDistributor;
 use vmm_core::partition_unit::PartitionUnit;
 use vmm_core::vmbus_unit::ChannelUnit;
+use vmm_core::vmbus_unit::VmbusDeviceUnit;
 use vmm_core::vmbus_unit::VmbusServerHandle;
 use vmotherboard::ChipsetDevices;
 use vtl2_settings_worker::Vtl2ConfigNicRpc;
@@ -168,7 +168,7 @@ pub(crate) struct LoadedVm {
     // contain task handles which must be kept live
     pub host_vmbus_relay: Option<VmbusRelayHandle>,
     // channels are revoked when dropped, so make sure to keep them alive
-    pub _vmbus_devices: Vec<SpawnedUnit<ChannelUnit<dyn VmbusDevice>>>,
+    pub _vmbus_devices: Vec<VmbusDeviceUnit>,
     pub _ide_accel_devices: Vec<SpawnedUnit<ChannelUnit<storvsp::StorageDevice>>>,
     pub network_settings: Option<Box<dyn LoadedVmNetworkSettings>>,
     pub shutdown_relay: Option<(
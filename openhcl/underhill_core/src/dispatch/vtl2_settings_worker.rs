@@ -1431,6 +1431,8 @@ async fn make_scsi_controller_config(
             io_queue_depth: Some(controller.io_queue_depth.unwrap_or(default_io_queue_depth)),
             requests: Some(recv),
             poll_mode_queue_depth,
+            queue_affinity: Vec::new(),
+            interrupt_coalescing: None,
         },
         request: send,
         dvds,
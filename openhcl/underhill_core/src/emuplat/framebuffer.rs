@@ -1,8 +1,10 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+use framebuffer::FramebufferUpdate;
 use mesh::MeshPayload;
 use std::convert::Infallible;
+use video_core::DirtyRect;
 use video_core::FramebufferControl;
 use video_core::FramebufferFormat;
 use video_core::ResolvedFramebuffer;
@@ -13,7 +15,7 @@
 #[derive(Clone, MeshPayload)]
 pub struct FramebufferRemoteControl {
     pub get: guest_emulation_transport::GuestEmulationTransportClient,
-    pub format_send: mesh::Sender<FramebufferFormat>,
+    pub update_send: mesh::Sender<FramebufferUpdate>,
 }
 
 #[async_trait::async_trait]
@@ -37,7 +39,11 @@ async fn unmap(&mut self) {
     }
 
     async fn set_format(&mut self, format: FramebufferFormat) {
-        self.format_send.send(format);
+        self.update_send.send(FramebufferUpdate::Format(format));
+    }
+
+    async fn dirty(&mut self, rects: Vec<DirtyRect>) {
+        self.update_send.send(FramebufferUpdate::Damage(rects));
     }
 }
 
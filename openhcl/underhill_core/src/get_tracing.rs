@@ -98,6 +98,10 @@ pub fn init_tracing_backend(driver: impl 'static + SpawnDriver) -> anyhow::Resul
         async move |requests, flush| {
             if let Some(get_backend) = &mut get_backend {
                 get_backend.run(requests, kmsg, flush).await;
+            } else {
+                // Still consume the requests so that they reach any sinks
+                // configured via inspect.
+                requests.for_each(|_| async {}).await;
             }
         },
     )
@@ -312,6 +312,7 @@ async fn launch_workers(
         vmbus_force_confidential_external_memory: opt.vmbus_force_confidential_external_memory,
         vmbus_channel_unstick_delay: (opt.vmbus_channel_unstick_delay_ms != 0)
             .then(|| Duration::from_millis(opt.vmbus_channel_unstick_delay_ms)),
+        vmbus_hash_ring_pages: opt.vmbus_hash_ring_pages,
         cmdline_append: opt.cmdline_append.clone(),
         reformat_vmgs: opt.reformat_vmgs,
         vtl0_starts_paused: opt.vtl0_starts_paused,
@@ -334,6 +335,7 @@ async fn launch_workers(
         strict_encryption_policy: opt.strict_encryption_policy,
         attempt_ak_cert_callback: opt.attempt_ak_cert_callback,
         enable_vpci_relay: opt.enable_vpci_relay,
+        vpci_relay_ring_pages: opt.vpci_relay_ring_pages,
         disable_proxy_redirect: opt.disable_proxy_redirect,
         disable_lower_vtl_timer_virt: opt.disable_lower_vtl_timer_virt,
         config_timeout_in_seconds: opt.config_timeout_in_seconds,
@@ -162,6 +162,11 @@ pub struct Options {
     /// zero to disable unsticking.
     pub vmbus_channel_unstick_delay_ms: u64,
 
+    /// (OPENHCL_VMBUS_HASH_RING_PAGES=1)
+    /// Hash the ring buffer control pages of open vmbus channels at save, and verify them after
+    /// restore. For debugging memory corruption during servicing.
+    pub vmbus_hash_ring_pages: bool,
+
     /// (OPENHCL_CMDLINE_APPEND=\<string\>)
     /// Command line to append to VTL0, only used with direct boot.
     pub cmdline_append: Option<String>,
@@ -278,6 +283,10 @@ pub struct Options {
     /// (OPENHCL_ENABLE_VPCI_RELAY=1) Enable the VPCI relay.
     pub enable_vpci_relay: Option<bool>,
 
+    /// (OPENHCL_VPCI_RELAY_RING_PAGES=\<number\>) The number of pages to use
+    /// for each relayed VPCI bus channel's ring buffer.
+    pub vpci_relay_ring_pages: Option<u16>,
+
     /// (OPENHCL_DISABLE_PROXY_REDIRECT=1) Disable proxy interrupt redirection.
     pub disable_proxy_redirect: bool,
 
@@ -390,6 +399,7 @@ fn parse_number(value: Option<&OsString>) -> anyhow::Result<Option<u64>> {
             parse_env_bool("OPENHCL_VMBUS_FORCE_CONFIDENTIAL_EXTERNAL_MEMORY");
         let vmbus_channel_unstick_delay_ms =
             parse_legacy_env_number("OPENHCL_VMBUS_CHANNEL_UNSTICK_DELAY_MS")?;
+        let vmbus_hash_ring_pages = parse_env_bool("OPENHCL_VMBUS_HASH_RING_PAGES");
         let cmdline_append = read_legacy_openhcl_env("OPENHCL_CMDLINE_APPEND")
             .map(|x| x.to_string_lossy().into_owned());
         let force_load_vtl0_image = read_legacy_openhcl_env("OPENHCL_FORCE_LOAD_VTL0_IMAGE")
@@ -466,6 +476,10 @@ fn parse_number(value: Option<&OsString>) -> anyhow::Result<Option<u64>> {
         let strict_encryption_policy = parse_env_bool_opt("HCL_STRICT_ENCRYPTION_POLICY");
         let attempt_ak_cert_callback = parse_env_bool_opt("HCL_ATTEMPT_AK_CERT_CALLBACK");
         let enable_vpci_relay = parse_env_bool_opt("OPENHCL_ENABLE_VPCI_RELAY");
+        let vpci_relay_ring_pages = parse_env_number("OPENHCL_VPCI_RELAY_RING_PAGES")?
+            .map(u16::try_from)
+            .transpose()
+            .context("OPENHCL_VPCI_RELAY_RING_PAGES is out of range")?;
         let disable_proxy_redirect = parse_env_bool("OPENHCL_DISABLE_PROXY_REDIRECT");
         let disable_lower_vtl_timer_virt = parse_env_bool("OPENHCL_DISABLE_LOWER_VTL_TIMER_VIRT");
         let config_timeout_in_seconds =
@@ -511,6 +525,7 @@ fn parse_number(value: Option<&OsString>) -> anyhow::Result<Option<u64>> {
             vmbus_enable_mnf,
             vmbus_force_confidential_external_memory,
             vmbus_channel_unstick_delay_ms: vmbus_channel_unstick_delay_ms.unwrap_or(100),
+            vmbus_hash_ring_pages,
             cmdline_append,
             vnc_port: vnc_port.unwrap_or(3),
             framebuffer_gpa_base,
@@ -535,6 +550,7 @@ fn parse_number(value: Option<&OsString>) -> anyhow::Result<Option<u64>> {
             strict_encryption_policy,
             attempt_ak_cert_callback,
             enable_vpci_relay,
+            vpci_relay_ring_pages,
             disable_proxy_redirect,
             disable_lower_vtl_timer_virt,
             config_timeout_in_seconds,
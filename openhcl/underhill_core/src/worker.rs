@@ -159,6 +159,7 @@
 use vm_topology::processor::VpInfo;
 use vmbus_relay_intercept_device::SimpleVmbusClientDeviceWrapper;
 use vmbus_server::VmbusServer;
+use vmcore::guest_event::GuestEventSink;
 use vmcore::non_volatile_store::EphemeralNonVolatileStore;
 use vmcore::non_volatile_store::resources::EphemeralNonVolatileStoreHandle;
 use vmcore::vm_task::VmTaskDriverSource;
@@ -198,6 +199,9 @@
 
 const MAX_SUBCHANNELS_PER_VNIC: u16 = 32;
 
+/// Time a device may take to change state before it is reported as slow.
+const SLOW_STATE_UNIT_TIMEOUT: Duration = Duration::from_secs(5);
+
 // TODO: Move to hsm crate in future.
 // AZIHSM VPCI IDs
 const AZIHSM_VPCI_VENDOR_ID: u16 = 0x1414;
@@ -268,6 +272,8 @@ pub struct UnderhillEnvCfg {
     pub vmbus_force_confidential_external_memory: bool,
     /// Delay before unsticking a vmbus channel after it has been opened.
     pub vmbus_channel_unstick_delay: Option<Duration>,
+    /// Hash vmbus ring control pages at save and verify them after restore.
+    pub vmbus_hash_ring_pages: bool,
     /// Command line to append to VTL0 command line. Only used for linux direct.
     pub cmdline_append: Option<String>,
     /// (dev feature) Reformat VMGS file on boot
@@ -316,6 +322,8 @@ pub struct UnderhillEnvCfg {
     pub attempt_ak_cert_callback: Option<bool>,
     /// Enable the VPCI relay
     pub enable_vpci_relay: Option<bool>,
+    /// The number of pages for each relayed VPCI bus channel's ring buffer
+    pub vpci_relay_ring_pages: Option<u16>,
     /// Disable proxy interrupt redirection
     pub disable_proxy_redirect: bool,
     /// Disable lower VTL timer virtualization
@@ -2103,6 +2111,7 @@ async fn new_underhill_vm(
     let (crash_notification_send, crash_notification_recv) = mesh::channel();
 
     let mut state_units = StateUnits::new();
+    state_units.report_slow_units(tp.driver(0).clone(), SLOW_STATE_UNIT_TIMEOUT);
 
     // Process VM time timers on VP 0, since that's where most of the
     // vmtime-driven device interrupts will be triggered.
@@ -2206,6 +2215,11 @@ async fn new_underhill_vm(
 
     resolver.add_resolver(vmm_core::platform_resolvers::HaltResolver(halt_vps.clone()));
 
+    // There are no event subscribers in OpenHCL, so just log guest events.
+    resolver.add_resolver(GuestEventSink::new(|event| {
+        tracing::info!(CVM_ALLOWED, ?event, "guest event")
+    }));
+
     let bounce_buffer_tracker = {
         let size = {
             if let Some(vtl2_settings) = dps.general.vtl2_settings.as_ref() {
@@ -2319,6 +2333,7 @@ async fn new_underhill_vm(
                 processor_topology: &processor_topology,
                 mem_layout: &mem_layout,
                 cache_topology: None,
+                numa_distances: None,
                 pcie_host_bridges: &vec![],
                 with_ioapic: true, // underhill always runs with ioapic
                 with_pic: true,    // pcat always runs with pic and pit
@@ -2656,7 +2671,7 @@ async fn new_underhill_vm(
 
     let emuplat_adjust_gpa_range;
 
-    let synic = Arc::new(SynicPorts::new(partition.clone()));
+    let synic = Arc::new(SynicPorts::new(partition.clone(), driver_source.simple()));
 
     let mut chipset = vm_manifest_builder::VmManifestBuilder::new(
         match firmware_type {
@@ -3157,6 +3172,7 @@ async fn new_underhill_vm(
                     env_cfg.vmbus_force_confidential_external_memory,
                 )
                 .channel_unstick_delay(env_cfg.vmbus_channel_unstick_delay)
+                .hash_ring_pages(env_cfg.vmbus_hash_ring_pages)
                 // For saved-state compat with release/2411.
                 .send_messages_while_stopped(true)
                 .build()
@@ -3238,6 +3254,7 @@ async fn new_underhill_vm(
                             env_cfg.test_configuration,
                             Some(TestScenarioConfig::VpciTdispFlow)
                         ),
+                        ring_pages: env_cfg.vpci_relay_ring_pages,
                     },
                 );
 
@@ -3282,10 +3299,17 @@ async fn new_underhill_vm(
                 vpci_relay = Some(relay);
             }
 
-            let mut intercept_list = Vec::new();
+            let mut intercept_interfaces = Vec::new();
             if intercept_shutdown_ic {
                 let (send, recv) = mesh::channel();
-                intercept_list.push((hyperv_ic_guest::shutdown::INSTANCE_ID, send));
+                let (claim_send, claim_recv) = mesh::channel();
+                relay_driver
+                    .spawn(
+                        "shutdown ic claim",
+                        vmbus_relay::claim_first_instance(claim_recv, send),
+                    )
+                    .detach();
+                intercept_interfaces.push((hyperv_ic_guest::shutdown::INTERFACE_ID, claim_send));
                 intercepted_shutdown_ic = Some(recv);
             }
 
@@ -3296,7 +3320,8 @@ async fn new_underhill_vm(
                 hvsock_relay,
                 client.access().clone(),
                 connection,
-                intercept_list,
+                Vec::new(),
+                intercept_interfaces,
             )
             .await
             .context("failed to create host vmbus transport")?;
@@ -3439,7 +3464,7 @@ async fn new_underhill_vm(
     if let Some(framebuffer) = remote_console_cfg.framebuffer {
         resolver.add_resolver(FramebufferRemoteControl {
             get: get_client.clone(),
-            format_send: framebuffer.format_send(),
+            update_send: framebuffer.update_send(),
         });
 
         vmbus_device_handles.push(
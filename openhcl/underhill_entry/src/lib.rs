@@ -9,9 +9,13 @@
 // Use mimalloc instead of the system malloc for performance.
 // For memory profiling, DHAT allocator is needed.
 #[global_allocator]
-#[cfg(not(feature = "mem-profile-tracing"))]
+#[cfg(not(any(feature = "mem-profile-tracing", feature = "alloc-audit")))]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 #[global_allocator]
+#[cfg(all(feature = "alloc-audit", not(feature = "mem-profile-tracing")))]
+static GLOBAL: alloc_audit::AuditAlloc<mimalloc::MiMalloc> =
+    alloc_audit::AuditAlloc(mimalloc::MiMalloc);
+#[global_allocator]
 #[cfg(feature = "mem-profile-tracing")]
 static GLOBAL: dhat::Alloc = dhat::Alloc;
 
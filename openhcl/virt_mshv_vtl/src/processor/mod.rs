@@ -822,7 +822,9 @@ async fn run_vp(
             // this CPU during memory protection updates.
             minircu::global().quiesce();
 
-            T::run_vp(self, dev, &mut stop).await?;
+            alloc_audit::hot_path!("virt_mshv_vtl::run_vp")
+                .instrument(T::run_vp(self, dev, &mut stop))
+                .await?;
             self.kernel_returns += 1;
         }
     }
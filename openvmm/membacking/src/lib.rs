@@ -89,11 +89,14 @@ pub enum RemoteProcess {}
 pub type RemoteProcess = sys::RemoteProcess;
 
 pub use memory_manager::DeviceMemoryMapper;
+pub use memory_manager::DirtyPageTracker;
 pub use memory_manager::GuestMemoryBuilder;
 pub use memory_manager::GuestMemoryClient;
 pub use memory_manager::GuestMemoryManager;
 pub use memory_manager::MemoryBuildError;
 pub use memory_manager::PartitionAttachError;
+pub use memory_manager::RamHotAdd;
+pub use memory_manager::RamReclaimer;
 pub use memory_manager::RamVisibility;
 pub use memory_manager::RamVisibilityControl;
 pub use memory_manager::SharedMemoryBacking;
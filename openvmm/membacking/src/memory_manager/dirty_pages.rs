@@ -0,0 +1,337 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Tracking of guest writes to RAM for
+//! [`GuestMemoryManager`](super::GuestMemoryManager).
+
+use hvdef::HV_PAGE_SIZE;
+use memory_range::MemoryRange;
+use std::sync::Weak;
+use virt::PartitionMemoryMap;
+
+/// Tracks which pages of guest RAM the guest has written, for features such as
+/// migration and incremental snapshots.
+///
+/// This uses the hypervisor's dirty page logging in each attached partition.
+/// If any partition does not support it, every page is reported as dirty, so
+/// callers always see a superset of the written pages.
+///
+/// There is deliberately no fallback that write protects RAM and tracks the
+/// resulting faults. Writes to write-protected RAM are routed to MMIO
+/// emulation, which would drop them.
+///
+/// Only writes by guest processors are tracked. Writes made by the VMM through
+/// [`GuestMemory`](guestmem::GuestMemory), e.g. for device DMA, are not; those
+/// can be tracked with `guestmem::dirty::GuestMemoryDirtyTracker`.
+pub struct DirtyPageTracker {
+    partitions: Vec<(Weak<dyn PartitionMemoryMap>, u64)>,
+    ranges: Vec<MemoryRange>,
+    state: TrackingState,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum TrackingState {
+    Stopped,
+    Logging,
+    AllDirty,
+}
+
+impl DirtyPageTracker {
+    pub(super) fn new(
+        partitions: Vec<(Weak<dyn PartitionMemoryMap>, u64)>,
+        ranges: Vec<MemoryRange>,
+    ) -> Self {
+        Self {
+            partitions,
+            ranges,
+            state: TrackingState::Stopped,
+        }
+    }
+
+    /// Returns the tracked RAM ranges, which can be passed to
+    /// [`Self::get_dirty_pages`].
+    pub fn ranges(&self) -> &[MemoryRange] {
+        &self.ranges
+    }
+
+    /// Returns whether dirty pages are tracked by the hypervisor, as opposed to
+    /// every page being reported as dirty.
+    pub fn is_precise(&self) -> bool {
+        self.state == TrackingState::Logging
+    }
+
+    /// Starts tracking writes, forgetting any writes tracked so far.
+    pub fn start(&mut self) -> anyhow::Result<()> {
+        let mut supported = true;
+        for (partition, offset) in &self.partitions {
+            let Some(partition) = partition.upgrade() else {
+                continue;
+            };
+            for range in &self.ranges {
+                supported &=
+                    partition.set_dirty_logging(range.start() + offset, range.len(), true)?;
+            }
+        }
+        if supported {
+            self.state = TrackingState::Logging;
+        } else {
+            // There is no point in paying for logging in the partitions that
+            // support it.
+            self.set_logging(false)?;
+            self.state = TrackingState::AllDirty;
+        }
+        Ok(())
+    }
+
+    /// Stops tracking writes.
+    pub fn stop(&mut self) -> anyhow::Result<()> {
+        if self.state == TrackingState::Logging {
+            self.set_logging(false)?;
+        }
+        self.state = TrackingState::Stopped;
+        Ok(())
+    }
+
+    fn set_logging(&self, enable: bool) -> anyhow::Result<()> {
+        for (partition, offset) in &self.partitions {
+            let Some(partition) = partition.upgrade() else {
+                continue;
+            };
+            for range in &self.ranges {
+                partition.set_dirty_logging(range.start() + offset, range.len(), enable)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets and clears the set of pages in `range` written since tracking was
+    /// started or since the last call, setting one bit per 4KB page in
+    /// `bitmap`.
+    ///
+    /// `range` must be one of the ranges returned by [`Self::ranges`].
+    pub fn get_dirty_pages(&self, range: MemoryRange, bitmap: &mut [u64]) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.ranges.contains(&range),
+            "{range} is not a tracked RAM range"
+        );
+        let page_count = range.len() / HV_PAGE_SIZE;
+        let len = page_count.div_ceil(64) as usize;
+        anyhow::ensure!(bitmap.len() >= len, "dirty bitmap too small");
+        let bitmap = &mut bitmap[..len];
+
+        match self.state {
+            TrackingState::Stopped => anyhow::bail!("dirty page tracking not started"),
+            TrackingState::AllDirty => {
+                bitmap.fill(!0);
+                if page_count % 64 != 0 {
+                    bitmap[len - 1] = (1 << (page_count % 64)) - 1;
+                }
+            }
+            TrackingState::Logging => {
+                bitmap.fill(0);
+                let mut partition_bitmap = vec![0; len];
+                for (partition, offset) in &self.partitions {
+                    let Some(partition) = partition.upgrade() else {
+                        continue;
+                    };
+                    partition.get_dirty_pages(
+                        range.start() + offset,
+                        range.len(),
+                        &mut partition_bitmap,
+                    )?;
+                    for (dirty, partition_dirty) in bitmap.iter_mut().zip(&partition_bitmap) {
+                        *dirty |= partition_dirty;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DirtyPageTracker {
+    fn drop(&mut self) {
+        if let Err(err) = self.stop() {
+            tracing::warn!(
+                error = err.as_ref() as &dyn std::error::Error,
+                "failed to stop dirty page logging"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DirtyPageTracker;
+    use hvdef::HV_PAGE_SIZE;
+    use memory_range::MemoryRange;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use virt::PartitionMemoryMap;
+
+    const MB: u64 = 1 << 20;
+    const ALIAS_OFFSET: u64 = 1 << 40;
+
+    /// A partition that logs the writes made with [`TestPartition::write`].
+    struct TestPartition {
+        supported: bool,
+        logging: Mutex<Vec<MemoryRange>>,
+        dirty: Mutex<Vec<u64>>,
+    }
+
+    impl TestPartition {
+        fn new(supported: bool) -> Arc<Self> {
+            Arc::new(Self {
+                supported,
+                logging: Default::default(),
+                dirty: Default::default(),
+            })
+        }
+
+        fn write(&self, gpa: u64) {
+            self.dirty.lock().push(gpa);
+        }
+    }
+
+    // UNSAFETY: Implementing the unsafe mapping functions, which the tracker
+    // never calls.
+    #[expect(unsafe_code)]
+    impl PartitionMemoryMap for TestPartition {
+        fn unmap_range(&self, _addr: u64, _size: u64) -> anyhow::Result<()> {
+            unreachable!()
+        }
+
+        unsafe fn map_range(
+            &self,
+            _data: *mut u8,
+            _size: usize,
+            _addr: u64,
+            _writable: bool,
+            _exec: bool,
+        ) -> anyhow::Result<()> {
+            unreachable!()
+        }
+
+        fn set_dirty_logging(&self, addr: u64, size: u64, enable: bool) -> anyhow::Result<bool> {
+            if !self.supported {
+                return Ok(false);
+            }
+            let range = MemoryRange::new(addr..addr + size);
+            let mut logging = self.logging.lock();
+            if enable {
+                logging.push(range);
+            } else {
+                logging.retain(|r| r != &range);
+            }
+            Ok(true)
+        }
+
+        fn get_dirty_pages(&self, addr: u64, size: u64, bitmap: &mut [u64]) -> anyhow::Result<()> {
+            let range = MemoryRange::new(addr..addr + size);
+            anyhow::ensure!(self.logging.lock().contains(&range));
+            bitmap.fill(0);
+            self.dirty.lock().retain(|&gpa| {
+                if !range.contains_addr(gpa) {
+                    return true;
+                }
+                let page = (gpa - addr) / HV_PAGE_SIZE;
+                bitmap[page as usize / 64] |= 1 << (page % 64);
+                false
+            });
+            Ok(())
+        }
+
+        #[cfg(windows)]
+        unsafe fn map_remote_range(
+            &self,
+            _process: std::os::windows::io::BorrowedHandle<'_>,
+            _data: *mut u8,
+            _size: usize,
+            _addr: u64,
+            _writable: bool,
+            _exec: bool,
+        ) -> anyhow::Result<()> {
+            unreachable!()
+        }
+    }
+
+    /// Returns a tracker for a partition and a VTL0 alias map partition, with
+    /// a RAM range whose page count is not a multiple of 64.
+    fn tracker(
+        partition: &Arc<TestPartition>,
+        alias: &Arc<TestPartition>,
+    ) -> (DirtyPageTracker, MemoryRange, MemoryRange) {
+        let low = MemoryRange::new(0..MB);
+        let high = MemoryRange::new(4 * MB..4 * MB + 3 * HV_PAGE_SIZE);
+        let partition = Arc::downgrade(partition) as std::sync::Weak<dyn PartitionMemoryMap>;
+        let alias = Arc::downgrade(alias) as std::sync::Weak<dyn PartitionMemoryMap>;
+        let tracker =
+            DirtyPageTracker::new(vec![(partition, 0), (alias, ALIAS_OFFSET)], vec![low, high]);
+        (tracker, low, high)
+    }
+
+    #[test]
+    fn merge_partitions() {
+        let partition = TestPartition::new(true);
+        let alias = TestPartition::new(true);
+        let (mut tracker, low, high) = tracker(&partition, &alias);
+        tracker.start().unwrap();
+        assert!(tracker.is_precise());
+        assert_eq!(partition.logging.lock().len(), 2);
+        assert_eq!(alias.logging.lock().len(), 2);
+
+        partition.write(3 * HV_PAGE_SIZE);
+        alias.write(ALIAS_OFFSET + 70 * HV_PAGE_SIZE);
+        alias.write(ALIAS_OFFSET + 4 * MB + 2 * HV_PAGE_SIZE);
+
+        let mut bitmap = vec![!0; 4];
+        tracker.get_dirty_pages(low, &mut bitmap).unwrap();
+        assert_eq!(bitmap, [1 << 3, 1 << 6, 0, 0]);
+        tracker.get_dirty_pages(high, &mut bitmap).unwrap();
+        assert_eq!(bitmap[0], 1 << 2);
+
+        // The dirty pages are cleared once read.
+        tracker.get_dirty_pages(low, &mut bitmap).unwrap();
+        assert_eq!(bitmap, [0; 4]);
+
+        drop(tracker);
+        assert!(partition.logging.lock().is_empty());
+        assert!(alias.logging.lock().is_empty());
+    }
+
+    #[test]
+    fn unsupported_reports_all_dirty() {
+        let partition = TestPartition::new(true);
+        let alias = TestPartition::new(false);
+        let (mut tracker, low, high) = tracker(&partition, &alias);
+        tracker.start().unwrap();
+        assert!(!tracker.is_precise());
+        // Logging is turned back off in the partition that supports it.
+        assert!(partition.logging.lock().is_empty());
+
+        let mut bitmap = vec![0; 4];
+        tracker.get_dirty_pages(low, &mut bitmap).unwrap();
+        assert_eq!(bitmap, [!0; 4]);
+        tracker.get_dirty_pages(high, &mut bitmap).unwrap();
+        assert_eq!(bitmap[0], 0b111);
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let partition = TestPartition::new(true);
+        let alias = TestPartition::new(true);
+        let (mut tracker, low, _) = tracker(&partition, &alias);
+        let mut bitmap = vec![0; 4];
+        tracker.get_dirty_pages(low, &mut bitmap).unwrap_err();
+
+        tracker.start().unwrap();
+        tracker
+            .get_dirty_pages(MemoryRange::new(0..2 * MB), &mut bitmap)
+            .unwrap_err();
+        tracker.get_dirty_pages(low, &mut bitmap[..3]).unwrap_err();
+
+        tracker.stop().unwrap();
+        assert!(partition.logging.lock().is_empty());
+        tracker.get_dirty_pages(low, &mut bitmap).unwrap_err();
+    }
+}
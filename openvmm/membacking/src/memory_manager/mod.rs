@@ -4,8 +4,10 @@
 //! OpenVMM's memory manager.
 
 mod device_memory;
+mod dirty_pages;
 
 pub use device_memory::DeviceMemoryMapper;
+pub use dirty_pages::DirtyPageTracker;
 
 use crate::RemoteProcess;
 use crate::mapping_manager::Mappable;
@@ -17,16 +19,23 @@
 use crate::region_manager::MapParams;
 use crate::region_manager::RegionHandle;
 use crate::region_manager::RegionManager;
+use crate::region_manager::RegionManagerClient;
+use async_trait::async_trait;
 use guestmem::GuestMemory;
 use hvdef::Vtl;
 use inspect::Inspect;
 use memory_range::MemoryRange;
 use mesh::MeshPayload;
 use pal_async::DefaultPool;
+use std::ops::Range;
 use std::sync::Arc;
+use std::sync::Weak;
 use std::thread::JoinHandle;
 use thiserror::Error;
 use vm_topology::memory::MemoryLayout;
+use vmcore::memory_hot_add::HOT_ADD_ALIGNMENT;
+use vmcore::memory_hot_add::HotAddMemory;
+use vmcore::memory_reclaim::ReclaimMemory;
 
 /// The OpenVMM memory manager.
 #[derive(Debug, Inspect)]
@@ -35,9 +44,18 @@ pub struct GuestMemoryManager {
     #[inspect(skip)]
     guest_ram: Mappable,
 
+    /// Guest RAM allocation from huge pages.
+    #[inspect(skip)]
+    huge_pages: Option<HugePageBacking>,
+
+    ram_backing: RamBackingInfo,
+
     #[inspect(skip)]
     ram_regions: Arc<Vec<RamRegion>>,
 
+    #[inspect(skip)]
+    hot_add_window: Option<Arc<HotAddWindow>>,
+
     #[inspect(flatten)]
     mapping_manager: MappingManager,
 
@@ -47,6 +65,11 @@ pub struct GuestMemoryManager {
     #[inspect(skip)]
     va_mapper: Arc<VaMapper>,
 
+    /// The attached partitions and the offsets at which RAM is mapped into
+    /// them.
+    #[inspect(skip)]
+    partitions: Vec<(Weak<dyn virt::PartitionMemoryMap>, u64)>,
+
     #[inspect(skip)]
     _thread: JoinHandle<()>,
 
@@ -54,10 +77,49 @@ pub struct GuestMemoryManager {
     pin_mappings: bool,
 }
 
+/// A guest RAM allocation from host huge pages.
+#[derive(Debug, Clone, MeshPayload)]
+struct HugePageBacking {
+    memory: Mappable,
+    page_size: u64,
+}
+
+/// Statistics about how guest RAM is backed.
+#[derive(Debug, Inspect)]
+struct RamBackingInfo {
+    /// The size of the host huge pages backing RAM, if any.
+    #[inspect(hex)]
+    huge_page_size: Option<u64>,
+    /// Huge pages were requested but could not be allocated.
+    huge_page_fallback: bool,
+    /// The amount of RAM backed by huge pages.
+    #[inspect(hex)]
+    huge_page_bytes: u64,
+    /// The amount of RAM backed by regular pages.
+    #[inspect(hex)]
+    small_page_bytes: u64,
+}
+
 #[derive(Debug)]
 struct RamRegion {
     range: MemoryRange,
     handle: RegionHandle,
+    /// The guest RAM backing the region.
+    backing: Mappable,
+    /// The offset of the region within `backing`.
+    backing_offset: u64,
+}
+
+/// The guest physical address range reserved for hot-added RAM.
+#[derive(Debug)]
+struct HotAddWindow {
+    range: MemoryRange,
+    /// The guest RAM backing the window.
+    backing: Mappable,
+    /// The offset of the window within `backing`.
+    backing_offset: u64,
+    /// The RAM regions that have been hot added so far.
+    regions: futures::lock::Mutex<Vec<RamRegion>>,
 }
 
 /// Errors when attaching a partition to a [`GuestMemoryManager`].
@@ -89,6 +151,17 @@ pub enum MemoryBuildError {
     /// Memory layout incompatible with x86 legacy support.
     #[error("x86 support requires RAM to start at 0 and contain at least 1MB")]
     InvalidRamForX86,
+    /// Couldn't bind RAM to a host NUMA node.
+    #[error("failed to bind RAM for vnode {vnode} to host node {host_node}")]
+    HostNodeBinding {
+        /// The guest NUMA node.
+        vnode: u32,
+        /// The host NUMA node.
+        host_node: u32,
+        /// The underlying error.
+        #[source]
+        err: std::io::Error,
+    },
 }
 
 /// A builder for [`GuestMemoryManager`].
@@ -98,6 +171,9 @@ pub struct GuestMemoryBuilder {
     prefetch_ram: bool,
     pin_mappings: bool,
     x86_legacy_support: bool,
+    hot_add_size: u64,
+    host_nodes: Vec<Option<u32>>,
+    huge_page_size: Option<u64>,
 }
 
 impl GuestMemoryBuilder {
@@ -109,6 +185,9 @@ pub fn new() -> Self {
             pin_mappings: false,
             prefetch_ram: false,
             x86_legacy_support: false,
+            hot_add_size: 0,
+            host_nodes: Vec::new(),
+            huge_page_size: None,
         }
     }
 
@@ -158,61 +237,71 @@ pub fn x86_legacy_support(mut self, enable: bool) -> Self {
         self
     }
 
+    /// Reserves `size` bytes of guest physical address space above the memory
+    /// layout for RAM that can be added while the VM is running, via
+    /// [`GuestMemoryManager::ram_hot_add`].
+    ///
+    /// The size is rounded up to the hot add granularity. The host memory
+    /// backing for the window is allocated along with the rest of RAM, but it
+    /// is not mapped into the guest until it is hot added.
+    pub fn hot_add_size(mut self, size: u64) -> Self {
+        self.hot_add_size = size;
+        self
+    }
+
+    /// Specifies the host NUMA node to allocate each guest NUMA node's RAM
+    /// from, indexed by vnode.
+    ///
+    /// RAM in vnodes without an entry, or with a `None` entry, is allocated
+    /// according to the host's default policy.
+    pub fn host_nodes(mut self, host_nodes: Vec<Option<u32>>) -> Self {
+        self.host_nodes = host_nodes;
+        self
+    }
+
+    /// Backs RAM with host huge pages of `page_size` bytes (e.g. 2MB or 1GB).
+    ///
+    /// Parts of RAM that are not aligned to the huge page size, such as the
+    /// x86 legacy regions, are backed by regular pages. If the huge pages
+    /// cannot be allocated, all of RAM falls back to regular pages.
+    pub fn huge_page_size(mut self, page_size: Option<u64>) -> Self {
+        self.huge_page_size = page_size;
+        self
+    }
+
     /// Builds the memory backing, allocating memory if existing memory was not
     /// provided by [`existing_backing`](Self::existing_backing).
     pub async fn build(
         self,
         mem_layout: &MemoryLayout,
     ) -> Result<GuestMemoryManager, MemoryBuildError> {
-        let ram_size = mem_layout.ram_size() + mem_layout.vtl2_range().map_or(0, |r| r.len());
-
-        let memory = if let Some(memory) = self.existing_mapping {
-            memory.guest_ram
-        } else {
-            sparse_mmap::alloc_shared_memory(
-                ram_size
-                    .try_into()
-                    .map_err(|_| MemoryBuildError::RamTooLarge(ram_size))?,
-            )
-            .map_err(MemoryBuildError::AllocationFailed)?
-            .into()
-        };
-
-        // Spawn a thread to handle memory requests.
-        //
-        // FUTURE: move this to a task once the GuestMemory deadlocks are resolved.
-        let (thread, spawner) = DefaultPool::spawn_on_thread("memory_manager");
-
-        let max_addr =
+        let end_of_layout =
             (mem_layout.end_of_layout()).max(mem_layout.vtl2_range().map_or(0, |r| r.end()));
 
-        let vtl0_alias_map_offset = if let Some(offset) = self.vtl0_alias_map {
-            if max_addr > offset {
-                return Err(MemoryBuildError::AliasMapWontFit);
-            }
-            Some(offset)
+        // Place the hot add window after everything else, so that it does not
+        // perturb the layout of the rest of the VM.
+        let hot_add_range = if self.hot_add_size != 0 {
+            let start = end_of_layout.next_multiple_of(HOT_ADD_ALIGNMENT);
+            let end = self
+                .hot_add_size
+                .checked_next_multiple_of(HOT_ADD_ALIGNMENT)
+                .and_then(|len| start.checked_add(len))
+                .ok_or(MemoryBuildError::RamTooLarge(self.hot_add_size))?;
+            Some(MemoryRange::new(start..end))
         } else {
             None
         };
 
-        let mapping_manager = MappingManager::new(&spawner, max_addr);
-        let va_mapper = mapping_manager
-            .client()
-            .new_mapper()
-            .await
-            .map_err(MemoryBuildError::VaMapper)?;
-
-        let region_manager = RegionManager::new(&spawner, mapping_manager.client().clone());
-
         let mut ram_ranges = mem_layout
             .ram()
             .iter()
-            .map(|x| x.range)
-            .chain(mem_layout.vtl2_range())
+            .map(|x| (x.range, Some(x.vnode)))
+            .chain(mem_layout.vtl2_range().map(|range| (range, None)))
             .collect::<Vec<_>>();
 
         if self.x86_legacy_support {
-            if ram_ranges[0].start() != 0 || ram_ranges[0].end() < 0x100000 {
+            let (first, vnode) = ram_ranges[0];
+            if first.start() != 0 || first.end() < 0x100000 {
                 return Err(MemoryBuildError::InvalidRamForX86);
             }
 
@@ -234,7 +323,7 @@ pub async fn build(
                 0xec000,
                 0xf0000,
                 0x100000,
-                ram_ranges[0].end(),
+                first.end(),
             ];
 
             ram_ranges.splice(
@@ -242,24 +331,112 @@ pub async fn build(
                 range_starts
                     .iter()
                     .zip(range_starts.iter().skip(1))
-                    .map(|(&start, &end)| MemoryRange::new(start..end)),
+                    .map(|(&start, &end)| (MemoryRange::new(start..end), vnode)),
             );
         }
 
+        let (existing_memory, huge_pages) = match self.existing_mapping {
+            Some(backing) => (Some(backing.guest_ram), backing.huge_pages),
+            None => (
+                None,
+                self.huge_page_size.and_then(|page_size| {
+                    alloc_huge_pages(&ram_ranges, hot_add_range, page_size, &self.host_nodes)
+                }),
+            ),
+        };
+
+        let plan = BackingPlan::new(
+            &ram_ranges,
+            hot_add_range,
+            huge_pages.as_ref().map(|huge| huge.page_size),
+        );
+
+        let memory = if let Some(memory) = existing_memory {
+            memory
+        } else {
+            sparse_mmap::alloc_shared_memory(
+                plan.size
+                    .try_into()
+                    .map_err(|_| MemoryBuildError::RamTooLarge(plan.size))?,
+            )
+            .map_err(MemoryBuildError::AllocationFailed)?
+            .into()
+        };
+
+        let backing_for = |range: &PlannedRange| {
+            if range.huge {
+                huge_pages
+                    .as_ref()
+                    .expect("huge ranges are only planned with huge pages")
+                    .memory
+                    .clone()
+            } else {
+                memory.clone()
+            }
+        };
+
+        // Bind the backing before it is mapped, since the binding only applies
+        // to pages allocated afterwards. Huge pages were already placed when
+        // they were allocated.
+        for range in &plan.ranges {
+            if range.huge {
+                continue;
+            }
+            let Some(vnode) = range.vnode else { continue };
+            if let Some(&Some(host_node)) = self.host_nodes.get(vnode as usize) {
+                sparse_mmap::bind_shared_memory(
+                    backing_for(range),
+                    range.backing_offset,
+                    range.range.len(),
+                    host_node,
+                )
+                .map_err(|err| MemoryBuildError::HostNodeBinding {
+                    vnode,
+                    host_node,
+                    err,
+                })?;
+            }
+        }
+
+        // Spawn a thread to handle memory requests.
+        //
+        // FUTURE: move this to a task once the GuestMemory deadlocks are resolved.
+        let (thread, spawner) = DefaultPool::spawn_on_thread("memory_manager");
+
+        let max_addr = end_of_layout.max(hot_add_range.map_or(0, |r| r.end()));
+
+        let vtl0_alias_map_offset = if let Some(offset) = self.vtl0_alias_map {
+            if max_addr > offset {
+                return Err(MemoryBuildError::AliasMapWontFit);
+            }
+            Some(offset)
+        } else {
+            None
+        };
+
+        let mapping_manager = MappingManager::new(&spawner, max_addr);
+        let va_mapper = mapping_manager
+            .client()
+            .new_mapper()
+            .await
+            .map_err(MemoryBuildError::VaMapper)?;
+
+        let region_manager = RegionManager::new(&spawner, mapping_manager.client().clone());
+
         let mut ram_regions = Vec::new();
-        let mut start = 0;
-        for range in &ram_ranges {
+        for range in &plan.ranges {
             let region = region_manager
                 .client()
-                .new_region("ram".into(), *range, RAM_PRIORITY)
+                .new_region("ram".into(), range.range, RAM_PRIORITY)
                 .await
                 .expect("regions cannot overlap yet");
 
+            let backing = backing_for(range);
             region
                 .add_mapping(
-                    MemoryRange::new(0..range.len()),
-                    memory.clone(),
-                    start,
+                    MemoryRange::new(0..range.range.len()),
+                    backing.clone(),
+                    range.backing_offset,
                     true,
                 )
                 .await;
@@ -273,19 +450,40 @@ pub async fn build(
                 .await;
 
             ram_regions.push(RamRegion {
-                range: *range,
+                range: range.range,
                 handle: region,
+                backing,
+                backing_offset: range.backing_offset,
             });
-            start += range.len();
         }
 
+        let hot_add_window = plan.hot_add.as_ref().map(|window| {
+            Arc::new(HotAddWindow {
+                range: window.range,
+                backing: backing_for(window),
+                backing_offset: window.backing_offset,
+                regions: Default::default(),
+            })
+        });
+
+        let ram_backing = RamBackingInfo {
+            huge_page_size: huge_pages.as_ref().map(|huge| huge.page_size),
+            huge_page_fallback: self.huge_page_size.is_some() && huge_pages.is_none(),
+            huge_page_bytes: plan.huge_size,
+            small_page_bytes: plan.size,
+        };
+
         let gm = GuestMemoryManager {
             guest_ram: memory,
+            huge_pages,
+            ram_backing,
             _thread: thread,
             ram_regions: Arc::new(ram_regions),
+            hot_add_window,
             mapping_manager,
             region_manager,
             va_mapper,
+            partitions: Vec::new(),
             vtl0_alias_map_offset,
             pin_mappings: self.pin_mappings,
         };
@@ -293,10 +491,144 @@ pub async fn build(
     }
 }
 
+/// Allocates the huge page backing for the parts of RAM that can use it,
+/// returning `None` if there are none or if the allocation fails.
+///
+/// The pages of each range are allocated on the host node of its vNUMA node,
+/// since huge pages cannot be bound after they are allocated.
+fn alloc_huge_pages(
+    ram_ranges: &[(MemoryRange, Option<u32>)],
+    hot_add_range: Option<MemoryRange>,
+    page_size: u64,
+    host_nodes: &[Option<u32>],
+) -> Option<HugePageBacking> {
+    let plan = BackingPlan::new(ram_ranges, hot_add_range, Some(page_size));
+    let size = plan.huge_size;
+    if size == 0 {
+        return None;
+    }
+    let bindings = plan
+        .ranges
+        .iter()
+        .filter(|range| range.huge)
+        .filter_map(|range| {
+            let host_node = (*host_nodes.get(range.vnode? as usize)?)?;
+            Some((
+                range.backing_offset..range.backing_offset + range.range.len(),
+                host_node,
+            ))
+        })
+        .collect::<Vec<_>>();
+    let memory = usize::try_from(size)
+        .ok()
+        .zip(usize::try_from(page_size).ok())
+        .ok_or_else(|| std::io::ErrorKind::InvalidInput.into())
+        .and_then(|(size, page_size)| {
+            sparse_mmap::alloc_huge_shared_memory(size, page_size, &bindings)
+        });
+    match memory {
+        Ok(memory) => Some(HugePageBacking {
+            memory: memory.into(),
+            page_size,
+        }),
+        Err(err) => {
+            tracing::warn!(
+                size,
+                page_size,
+                error = &err as &dyn std::error::Error,
+                "failed to allocate huge pages, falling back to regular pages"
+            );
+            None
+        }
+    }
+}
+
+/// The placement of RAM within the regular and huge page backings.
+struct BackingPlan {
+    ranges: Vec<PlannedRange>,
+    hot_add: Option<PlannedRange>,
+    /// The size of the regular page backing.
+    size: u64,
+    /// The size of the huge page backing.
+    huge_size: u64,
+}
+
+struct PlannedRange {
+    range: MemoryRange,
+    vnode: Option<u32>,
+    huge: bool,
+    backing_offset: u64,
+}
+
+impl BackingPlan {
+    /// Places the huge page aligned part of each range in the huge page
+    /// backing, if `huge_page_size` is set, and the rest in the regular page
+    /// backing.
+    ///
+    /// This must be deterministic, since it is recomputed when an existing
+    /// backing is reused.
+    fn new(
+        ram_ranges: &[(MemoryRange, Option<u32>)],
+        hot_add_range: Option<MemoryRange>,
+        huge_page_size: Option<u64>,
+    ) -> Self {
+        let mut plan = Self {
+            ranges: Vec::new(),
+            hot_add: None,
+            size: 0,
+            huge_size: 0,
+        };
+        for &(range, vnode) in ram_ranges {
+            let (huge_start, huge_end) = match huge_page_size {
+                Some(page_size) => {
+                    let start = range.start().next_multiple_of(page_size).min(range.end());
+                    let end = (range.end() - range.end() % page_size).max(start);
+                    (start, end)
+                }
+                None => (range.end(), range.end()),
+            };
+            for (piece, huge) in [
+                (range.start()..huge_start, false),
+                (huge_start..huge_end, true),
+                (huge_end..range.end(), false),
+            ] {
+                if !piece.is_empty() {
+                    let planned = plan.place(MemoryRange::new(piece), vnode, huge);
+                    plan.ranges.push(planned);
+                }
+            }
+        }
+        // Hot add chunks are mapped individually, so each chunk must be made
+        // of whole huge pages.
+        plan.hot_add = hot_add_range.map(|range| {
+            let huge = huge_page_size.is_some_and(|page_size| HOT_ADD_ALIGNMENT % page_size == 0);
+            plan.place(range, None, huge)
+        });
+        plan
+    }
+
+    fn place(&mut self, range: MemoryRange, vnode: Option<u32>, huge: bool) -> PlannedRange {
+        let size = if huge {
+            &mut self.huge_size
+        } else {
+            &mut self.size
+        };
+        let backing_offset = *size;
+        *size += range.len();
+        PlannedRange {
+            range,
+            vnode,
+            huge,
+            backing_offset,
+        }
+    }
+}
+
 /// The backing objects used to transfer guest memory between processes.
 #[derive(Debug, MeshPayload)]
 pub struct SharedMemoryBacking {
     guest_ram: Mappable,
+    huge_pages: Option<HugePageBacking>,
 }
 
 /// A mesh-serializable object for providing access to guest memory.
@@ -347,6 +679,59 @@ pub fn ram_visibility_control(&self) -> RamVisibilityControl {
         }
     }
 
+    /// Returns an object for releasing the host memory backing ranges of RAM,
+    /// e.g. for pages the guest has given to a balloon device.
+    pub fn ram_reclaimer(&self) -> RamReclaimer {
+        RamReclaimer {
+            regions: self.ram_regions.clone(),
+            hot_add_window: self.hot_add_window.clone(),
+        }
+    }
+
+    /// Returns the guest physical address range reserved for hot-added RAM, if
+    /// any.
+    pub fn hot_add_range(&self) -> Option<MemoryRange> {
+        self.hot_add_window.as_ref().map(|window| window.range)
+    }
+
+    /// Returns the number of bytes of RAM that have been hot added within the
+    /// hot add window.
+    pub async fn hot_added_size(&self) -> u64 {
+        match &self.hot_add_window {
+            Some(window) => window
+                .regions
+                .lock()
+                .await
+                .iter()
+                .map(|region| region.range.len())
+                .sum(),
+            None => 0,
+        }
+    }
+
+    /// Returns an object for adding RAM to the guest within the hot add window,
+    /// or `None` if no window was reserved with
+    /// [`GuestMemoryBuilder::hot_add_size`].
+    pub fn ram_hot_add(&self) -> Option<RamHotAdd> {
+        Some(RamHotAdd {
+            region_manager: self.region_manager.client().clone(),
+            window: self.hot_add_window.clone()?,
+        })
+    }
+
+    /// Returns an object for tracking guest writes to RAM, including the hot
+    /// add window, in the partitions attached so far.
+    pub fn dirty_page_tracker(&self) -> DirtyPageTracker {
+        DirtyPageTracker::new(
+            self.partitions.clone(),
+            self.ram_regions
+                .iter()
+                .map(|region| region.range)
+                .chain(self.hot_add_window.as_ref().map(|window| window.range))
+                .collect(),
+        )
+    }
+
     /// Returns the shared memory resources that can be used to reconstruct the
     /// memory backing.
     ///
@@ -355,8 +740,10 @@ pub fn ram_visibility_control(&self) -> RamVisibilityControl {
     /// type should be managing a given memory backing at a time, though, or the
     /// guest may see unpredictable results.
     pub fn shared_memory_backing(&self) -> SharedMemoryBacking {
-        let guest_ram = self.guest_ram.clone();
-        SharedMemoryBacking { guest_ram }
+        SharedMemoryBacking {
+            guest_ram: self.guest_ram.clone(),
+            huge_pages: self.huge_pages.clone(),
+        }
     }
 
     /// Attaches the guest memory to a partition, mapping it to the guest
@@ -387,22 +774,24 @@ pub async fn attach_partition(
 
         if vtl == Vtl::Vtl2 {
             if let Some(offset) = self.vtl0_alias_map_offset {
-                let partition =
+                let mapper =
                     PartitionMapper::new(partition, va_mapper.clone(), offset, self.pin_mappings);
                 self.region_manager
                     .client()
-                    .add_partition(partition)
+                    .add_partition(mapper)
                     .await
                     .map_err(PartitionAttachError::PartitionMapper)?;
+                self.partitions.push((Arc::downgrade(partition), offset));
             }
         }
 
-        let partition = PartitionMapper::new(partition, va_mapper, 0, self.pin_mappings);
+        let mapper = PartitionMapper::new(partition, va_mapper, 0, self.pin_mappings);
         self.region_manager
             .client()
-            .add_partition(partition)
+            .add_partition(mapper)
             .await
             .map_err(PartitionAttachError::PartitionMapper)?;
+        self.partitions.push((Arc::downgrade(partition), 0));
         Ok(())
     }
 }
@@ -413,6 +802,130 @@ pub struct RamVisibilityControl {
     regions: Arc<Vec<RamRegion>>,
 }
 
+/// A client to the [`GuestMemoryManager`] used to release the host memory
+/// backing ranges of RAM.
+pub struct RamReclaimer {
+    regions: Arc<Vec<RamRegion>>,
+    hot_add_window: Option<Arc<HotAddWindow>>,
+}
+
+impl ReclaimMemory for RamReclaimer {
+    fn reclaim(&self, gpa: u64, len: u64) -> std::io::Result<()> {
+        let range = MemoryRange::try_new(gpa..gpa.wrapping_add(len))
+            .map_err(|_| std::io::ErrorKind::InvalidInput)?;
+        // The range may span multiple RAM regions, which are not necessarily
+        // adjacent in the backing, so discard each region's part separately.
+        // The hot add window is contiguous in the backing, so it acts as a
+        // single region.
+        //
+        // Only whole huge pages are released from huge page backed regions.
+        let mut parts = Vec::new();
+        let mut next = range.start();
+        for (region_range, backing, backing_offset) in self
+            .regions
+            .iter()
+            .map(|region| (region.range, &region.backing, region.backing_offset))
+            .chain(
+                self.hot_add_window
+                    .as_ref()
+                    .map(|window| (window.range, &window.backing, window.backing_offset)),
+            )
+        {
+            if !region_range.overlaps(&range) {
+                continue;
+            }
+            if region_range.start() > next {
+                break;
+            }
+            let end = region_range.end().min(range.end());
+            parts.push((
+                backing,
+                backing_offset + (next - region_range.start()),
+                end - next,
+            ));
+            next = end;
+        }
+        if next != range.end() {
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        }
+        for (backing, offset, len) in parts {
+            sparse_mmap::discard_shared_memory(backing, offset, len)?;
+        }
+        Ok(())
+    }
+}
+
+/// A client to the [`GuestMemoryManager`] used to add RAM to the guest within
+/// the hot add window.
+pub struct RamHotAdd {
+    region_manager: RegionManagerClient,
+    window: Arc<HotAddWindow>,
+}
+
+#[async_trait]
+impl HotAddMemory for RamHotAdd {
+    fn hot_add_range(&self) -> Range<u64> {
+        self.window.range.start()..self.window.range.end()
+    }
+
+    async fn hot_add(&self, gpa: u64, len: u64) -> std::io::Result<()> {
+        let range = MemoryRange::try_new(gpa..gpa.wrapping_add(len))
+            .map_err(|_| std::io::ErrorKind::InvalidInput)?;
+        if !self.window.range.contains(&range)
+            || range.start() % HOT_ADD_ALIGNMENT != 0
+            || range.len() % HOT_ADD_ALIGNMENT != 0
+        {
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        }
+
+        // Add a region per chunk so that repeated or overlapping requests can
+        // skip the chunks that are already present.
+        let mut regions = self.window.regions.lock().await;
+        let mut start = range.start();
+        while start < range.end() {
+            let chunk = MemoryRange::new(start..start + HOT_ADD_ALIGNMENT);
+            start = chunk.end();
+            if regions.iter().any(|region| region.range == chunk) {
+                continue;
+            }
+
+            let handle = self
+                .region_manager
+                .new_region("ram".into(), chunk, RAM_PRIORITY)
+                .await
+                .map_err(std::io::Error::other)?;
+
+            let backing_offset =
+                self.window.backing_offset + (chunk.start() - self.window.range.start());
+
+            handle
+                .add_mapping(
+                    MemoryRange::new(0..chunk.len()),
+                    self.window.backing.clone(),
+                    backing_offset,
+                    true,
+                )
+                .await;
+
+            handle
+                .map(MapParams {
+                    writable: true,
+                    executable: true,
+                    prefetch: false,
+                })
+                .await;
+
+            regions.push(RamRegion {
+                range: chunk,
+                handle,
+                backing: self.window.backing.clone(),
+                backing_offset,
+            });
+        }
+        Ok(())
+    }
+}
+
 /// The RAM visibility for use with [`RamVisibilityControl::set_ram_visibility`].
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum RamVisibility {
@@ -465,3 +978,55 @@ pub async fn set_ram_visibility(
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::BackingPlan;
+    use memory_range::MemoryRange;
+
+    const MB: u64 = 1 << 20;
+
+    #[test]
+    fn huge_page_plan() {
+        let ranges = [
+            (MemoryRange::new(0..MB), Some(0)),
+            (MemoryRange::new(MB..7 * MB), Some(0)),
+            (MemoryRange::new(8 * MB..12 * MB), Some(1)),
+        ];
+        let plan = BackingPlan::new(
+            &ranges,
+            Some(MemoryRange::new(128 * MB..256 * MB)),
+            Some(2 * MB),
+        );
+        let placed = plan
+            .ranges
+            .iter()
+            .map(|r| (r.range, r.vnode, r.huge, r.backing_offset))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            placed,
+            [
+                (MemoryRange::new(0..MB), Some(0), false, 0),
+                (MemoryRange::new(MB..2 * MB), Some(0), false, MB),
+                (MemoryRange::new(2 * MB..6 * MB), Some(0), true, 0),
+                (MemoryRange::new(6 * MB..7 * MB), Some(0), false, 2 * MB),
+                (MemoryRange::new(8 * MB..12 * MB), Some(1), true, 4 * MB),
+            ]
+        );
+        let hot_add = plan.hot_add.unwrap();
+        assert!(hot_add.huge);
+        assert_eq!(hot_add.backing_offset, 8 * MB);
+        assert_eq!(plan.size, 3 * MB);
+        assert_eq!(plan.huge_size, 136 * MB);
+
+        // 1GB pages cannot back individual hot add chunks.
+        let plan = BackingPlan::new(
+            &ranges,
+            Some(MemoryRange::new(128 * MB..256 * MB)),
+            Some(1024 * MB),
+        );
+        assert!(plan.ranges.iter().all(|r| !r.huge));
+        assert!(!plan.hot_add.unwrap().huge);
+        assert_eq!(plan.huge_size, 0);
+    }
+}
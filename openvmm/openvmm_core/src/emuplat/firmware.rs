@@ -8,19 +8,31 @@
 use firmware_uefi::platform::logger::UefiEvent;
 use firmware_uefi::platform::logger::UefiLogger;
 use get_resources::ged::FirmwareEvent;
+use openvmm_defs::rpc::VmEvent;
 
-/// Forwards UEFI and PCAT events to via the provided [`mesh::Sender`].
+/// Forwards UEFI and PCAT events to via the provided [`mesh::Sender`], and
+/// reports boot handoffs as [`VmEvent::FirmwareHandoff`].
 #[derive(Debug)]
 pub struct MeshLogger {
     sender: Option<mesh::Sender<FirmwareEvent>>,
+    vm_events: mesh::Sender<VmEvent>,
 }
 
 impl MeshLogger {
-    pub fn new(sender: Option<mesh::Sender<FirmwareEvent>>) -> Self {
-        Self { sender }
+    pub fn new(
+        sender: Option<mesh::Sender<FirmwareEvent>>,
+        vm_events: mesh::Sender<VmEvent>,
+    ) -> Self {
+        Self { sender, vm_events }
     }
 
     fn send(&self, event: FirmwareEvent) {
+        if matches!(
+            event,
+            FirmwareEvent::BootSuccess | FirmwareEvent::BootAttempt
+        ) {
+            self.vm_events.send(VmEvent::FirmwareHandoff);
+        }
         if let Some(sender) = &self.sender {
             sender.send(event);
         }
@@ -5,7 +5,13 @@
 use crate::partition::BindHvliteVp;
 use crate::partition::HvlitePartition;
 use crate::vmgs_non_volatile_store::HvLiteVmgsNonVolatileStore;
+#[cfg(feature = "migration_precopy")]
+use crate::worker::migrate::DeviceDirtyPages;
+use crate::worker::migrate::DirtyPages;
+use crate::worker::migrate::dirty_page_refs;
+use crate::worker::numa;
 use crate::worker::rom::RomBuilder;
+use crate::worker::vp_pinning;
 use acpi::dsdt;
 use anyhow::Context;
 use cfg_if::cfg_if;
@@ -22,6 +28,12 @@
 use futures::future::try_join_all;
 use futures_concurrency::prelude::*;
 use guestmem::GuestMemory;
+#[cfg(feature = "migration_precopy")]
+use guestmem::dirty::GuestMemoryDirtyTracker;
+#[cfg(feature = "memory_watchpoints")]
+use guestmem::watch::GuestMemoryWatcher;
+#[cfg(feature = "memory_watchpoints")]
+use guestmem::watch::Watchpoint;
 use hvdef::HV_PAGE_SIZE;
 use hvdef::Vtl;
 use ide_resources::GuestMedia;
@@ -60,13 +72,17 @@
 use openvmm_defs::config::ProcessorTopologyConfig;
 use openvmm_defs::config::VirtioBus;
 use openvmm_defs::config::VmbusConfig;
+use openvmm_defs::config::VpPinningConfig;
 use openvmm_defs::config::VpciDeviceConfig;
 use openvmm_defs::config::Vtl2BaseAddressType;
 use openvmm_defs::config::Vtl2Config;
 use openvmm_defs::config::X2ApicConfig;
 use openvmm_defs::config::X86TopologyConfig;
+use openvmm_defs::rpc::MigrateParams;
 use openvmm_defs::rpc::PulseSaveRestoreError;
+use openvmm_defs::rpc::VmEvent;
 use openvmm_defs::rpc::VmRpc;
+use openvmm_defs::rpc::WatchpointParams;
 use openvmm_defs::worker::VM_WORKER;
 use openvmm_defs::worker::VmWorkerParameters;
 use openvmm_pcat_locator::RomFileLocation;
@@ -90,6 +106,7 @@
 use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::Duration;
 use storvsp::ScsiControllerDisk;
 use virt::ProtoPartition;
 use virt::VpIndex;
@@ -113,13 +130,16 @@
 use vm_topology::processor::aarch64::Aarch64Topology;
 use vm_topology::processor::aarch64::GicInfo;
 use vm_topology::processor::x86::X86Topology;
-use vmbus_channel::channel::VmbusDevice;
+use vmbus_channel::bus::PerfHints;
+use vmbus_core::trace::TraceWriter;
 use vmbus_server::HvsockRelayChannel;
 use vmbus_server::VmbusServer;
 use vmbus_server::hvsock::HvsockRelay;
+use vmcore::guest_event::GuestEventSink;
+use vmcore::memory_hot_add::MemoryHotAdd;
+use vmcore::memory_reclaim::MemoryReclaim;
 use vmcore::save_restore::SavedStateRoot;
 use vmcore::vm_task::VmTaskDriverSource;
-use vmcore::vm_task::thread::ThreadDriverBackend;
 use vmcore::vmtime::VmTime;
 use vmcore::vmtime::VmTimeKeeper;
 use vmcore::vmtime::VmTimeSource;
@@ -133,6 +153,7 @@
 use vmm_core::partition_unit::block_on_vp;
 use vmm_core::synic::SynicPorts;
 use vmm_core::vmbus_unit::ChannelUnit;
+use vmm_core::vmbus_unit::VmbusDeviceUnit;
 use vmm_core::vmbus_unit::VmbusServerHandle;
 use vmm_core::vmbus_unit::offer_channel_unit;
 use vmm_core::vmbus_unit::offer_vmbus_device_handle_unit;
@@ -155,6 +176,9 @@
 
 const WDAT_PORT: u16 = 0x30;
 
+/// Time a device may take to change state before it is reported as slow.
+const SLOW_STATE_UNIT_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Creates a thread to run low-performance devices on.
 pub fn new_device_thread() -> (JoinHandle<()>, DefaultDriver) {
     DefaultPool::spawn_on_thread("basic_device_thread")
@@ -173,6 +197,7 @@ fn from_config(config: Config) -> Self {
             hypervisor: config.hypervisor,
             memory: config.memory,
             processor_topology: config.processor_topology,
+            vp_pinning: config.vp_pinning,
             chipset: config.chipset,
             #[cfg(windows)]
             kernel_vmnics: config.kernel_vmnics,
@@ -219,6 +244,7 @@ pub struct Manifest {
     vpci_devices: Vec<VpciDeviceConfig>,
     memory: MemoryConfig,
     processor_topology: ProcessorTopologyConfig,
+    vp_pinning: Option<VpPinningConfig>,
     hypervisor: HypervisorConfig,
     chipset: BaseChipsetManifest,
     #[cfg(windows)]
@@ -310,19 +336,49 @@ fn new(parameters: Self::Parameters) -> anyhow::Result<Self> {
         };
 
         let vm = block_on(InitializedVm::new(
-            VmTaskDriverSource::new(ThreadDriverBackend::new(device_driver)),
+            vp_pinning::driver_source(device_driver, manifest.vp_pinning.as_ref()),
             hypervisor,
             manifest,
             None,
         ))?;
-        let saved_state = parameters
-            .saved_state
-            .map(|m| m.parse())
-            .transpose()
-            .context("failed to decode saved state")?;
+        let restore_sources = [
+            parameters.saved_state.is_some(),
+            parameters.snapshot.is_some(),
+            parameters.incoming_migration.is_some(),
+        ];
+        if restore_sources.into_iter().filter(|&x| x).count() > 1 {
+            anyhow::bail!("only one of saved state, a snapshot, or a migration can be restored");
+        }
+        let saved_state = if let Some(saved_state) = parameters.saved_state {
+            Some(
+                saved_state
+                    .parse()
+                    .context("failed to decode saved state")?,
+            )
+        } else if let Some(file) = parameters.snapshot {
+            let gm = vm.gm.clone();
+            let mem_layout = vm.mem_layout.clone();
+            Some(
+                block_on(blocking::unblock(move || {
+                    super::snapshot::read(&file, &gm, &mem_layout)
+                }))
+                .context("failed to restore snapshot")?,
+            )
+        } else if let Some(stream) = &parameters.incoming_migration {
+            Some(
+                super::migrate::receive(stream, &vm.gm, &vm.mem_layout)
+                    .context("failed to receive migration")?,
+            )
+        } else {
+            None
+        };
 
         let vm = block_with_io(|_| vm.load(saved_state, parameters.notify))?;
 
+        if let Some(stream) = &parameters.incoming_migration {
+            super::migrate::complete(stream).context("failed to complete migration")?;
+        }
+
         LOADED_VM.store(&vm);
 
         Ok(Self {
@@ -345,7 +401,7 @@ fn restart(state: Self::State) -> anyhow::Result<Self> {
         let (device_thread, device_driver) = new_device_thread();
 
         let vm = block_on(InitializedVm::new(
-            VmTaskDriverSource::new(ThreadDriverBackend::new(device_driver)),
+            vp_pinning::driver_source(device_driver, manifest.vp_pinning.as_ref()),
             hypervisor,
             manifest,
             Some(shared_memory),
@@ -386,6 +442,10 @@ struct InitializedVm {
     vmtime_source: VmTimeSource,
     memory_manager: GuestMemoryManager,
     gm: GuestMemory,
+    #[cfg(feature = "memory_watchpoints")]
+    memory_watcher: Option<GuestMemoryWatcher>,
+    #[cfg(feature = "migration_precopy")]
+    dirty_tracker: Option<GuestMemoryDirtyTracker>,
     cfg: Manifest,
     mem_layout: MemoryLayout,
     processor_topology: ProcessorTopology,
@@ -394,7 +454,11 @@ struct InitializedVm {
 }
 
 trait BuildTopology<T: ArchTopology + Inspect> {
-    fn to_topology(&self, hypervisor: Hypervisor) -> anyhow::Result<ProcessorTopology<T>>;
+    fn to_topology(
+        &self,
+        hypervisor: Hypervisor,
+        vnodes: Option<Vec<u32>>,
+    ) -> anyhow::Result<ProcessorTopology<T>>;
 }
 
 trait ExtractTopologyConfig {
@@ -426,6 +490,7 @@ impl BuildTopology<X86Topology> for ProcessorTopologyConfig {
     fn to_topology(
         &self,
         _hypervisor: Hypervisor,
+        vnodes: Option<Vec<u32>>,
     ) -> anyhow::Result<ProcessorTopology<X86Topology>> {
         use vm_topology::processor::x86::X2ApicState;
 
@@ -452,6 +517,9 @@ fn to_topology(
             X2ApicConfig::Enabled => X2ApicState::Enabled,
         };
         builder.x2apic(x2apic);
+        if let Some(vnodes) = vnodes {
+            builder.vnodes(vnodes);
+        }
         Ok(builder.build(self.proc_count)?)
     }
 }
@@ -477,6 +545,7 @@ impl BuildTopology<Aarch64Topology> for ProcessorTopologyConfig {
     fn to_topology(
         &self,
         hypervisor: Hypervisor,
+        vnodes: Option<Vec<u32>>,
     ) -> anyhow::Result<ProcessorTopology<Aarch64Topology>> {
         let arch = match &self.arch {
             None => Default::default(),
@@ -515,6 +584,9 @@ fn to_topology(
         } else {
             builder.vps_per_socket(self.proc_count);
         }
+        if let Some(vnodes) = vnodes {
+            builder.vnodes(vnodes);
+        }
         Ok(builder.build(self.proc_count)?)
     }
 }
@@ -526,6 +598,12 @@ pub(crate) struct LoadedVm {
     state_units: StateUnits,
     inner: LoadedVmInner,
     running: bool,
+    /// Subscribers to VM lifecycle events. These do not survive a worker
+    /// restart.
+    event_subscribers: Vec<mesh::Sender<VmEvent>>,
+    /// Whether the firmware has been loaded but the VM has not run since, so
+    /// that the next resume starts the boot.
+    boot_pending: bool,
 }
 
 /// Most of the VM state for [`LoadedVm`], excluding things that are necessary
@@ -541,6 +619,10 @@ struct LoadedVmInner {
     _scsi_devices: Vec<SpawnedUnit<ChannelUnit<storvsp::StorageDevice>>>,
     memory_manager: GuestMemoryManager,
     gm: GuestMemory,
+    #[cfg(feature = "memory_watchpoints")]
+    memory_watcher: Option<GuestMemoryWatcher>,
+    #[cfg(feature = "migration_precopy")]
+    dirty_tracker: Option<GuestMemoryDirtyTracker>,
     vtl0_hvsock_relay: Option<HvsockRelay>,
     vtl2_hvsock_relay: Option<HvsockRelay>,
     vmbus_server: Option<VmbusServerHandle>,
@@ -552,9 +634,10 @@ struct LoadedVmInner {
     memory_cfg: MemoryConfig,
     mem_layout: MemoryLayout,
     processor_topology: ProcessorTopology,
+    vp_pinning: Option<VpPinningConfig>,
     hypervisor_cfg: HypervisorConfig,
     vmbus_redirect: bool,
-    vmbus_devices: Vec<SpawnedUnit<ChannelUnit<dyn VmbusDevice>>>,
+    vmbus_devices: Vec<(DeviceVtl, VmbusDeviceUnit)>,
 
     input_distributor: SpawnedUnit<InputDistributor>,
     vtl2_framebuffer_gpa_base: Option<u64>,
@@ -577,6 +660,8 @@ struct LoadedVmInner {
 
     // relay halt messages, intercepting reset if configured.
     halt_recv: mesh::Receiver<HaltReason>,
+    /// Events reported by devices and firmware, forwarded to event subscribers.
+    vm_event_recv: mesh::Receiver<VmEvent>,
     client_notify_send: mesh::Sender<HaltReason>,
     /// allow the guest to reset without notifying the client
     automatic_guest_reset: bool,
@@ -824,7 +909,17 @@ async fn new_with_hypervisor<P, H>(
             None
         };
 
-        let processor_topology = cfg.processor_topology.to_topology(hypervisor_type)?;
+        numa::validate(
+            &cfg.memory.numa_nodes,
+            cfg.memory.mem_size,
+            cfg.processor_topology.proc_count,
+        )
+        .context("invalid vnode configuration")?;
+
+        let processor_topology = cfg.processor_topology.to_topology(
+            hypervisor_type,
+            numa::vp_vnodes(&cfg.memory.numa_nodes, cfg.processor_topology.proc_count),
+        )?;
 
         let proto = hypervisor
             .new_partition(virt::ProtoPartitionConfig {
@@ -874,8 +969,8 @@ async fn new_with_hypervisor<P, H>(
         };
 
         // Choose the memory layout of the VM.
-        let mem_layout = MemoryLayout::new(
-            cfg.memory.mem_size,
+        let mem_layout = MemoryLayout::new_with_nodes(
+            &numa::ram_sizes(&cfg.memory.numa_nodes).unwrap_or_else(|| vec![cfg.memory.mem_size]),
             &cfg.memory.mmio_gaps,
             &cfg.memory.pci_ecam_gaps,
             &cfg.memory.pci_mmio_gaps,
@@ -905,6 +1000,14 @@ async fn new_with_hypervisor<P, H>(
             .existing_backing(shared_memory)
             .vtl0_alias_map(vtl0_alias_map)
             .prefetch_ram(cfg.memory.prefetch_memory)
+            .huge_page_size(cfg.memory.huge_page_size)
+            .host_nodes(numa::host_nodes(&cfg.memory.numa_nodes))
+            .hot_add_size(
+                cfg.memory
+                    .dynamic_memory
+                    .as_ref()
+                    .map_or(0, |dm| dm.max_size.saturating_sub(cfg.memory.mem_size)),
+            )
             .x86_legacy_support(
                 matches!(cfg.load_mode, LoadMode::Pcat { .. }) || cfg.chipset.with_hyperv_vga,
             );
@@ -934,11 +1037,44 @@ async fn new_with_hypervisor<P, H>(
             .await
             .context("failed to build guest memory")?;
 
+        if let Some(range) = memory_manager.hot_add_range() {
+            if range.end() > 1 << physical_address_size {
+                anyhow::bail!(
+                    "hot add window ends at {:#x}, which exceeds the address width of {} bits",
+                    range.end(),
+                    physical_address_size
+                );
+            }
+        }
+
         let gm = memory_manager
             .client()
             .guest_memory()
             .await
             .context("failed to get guest memory")?;
+
+        // Track the writes made through `gm`, by devices and by processor
+        // emulation, so that migration can find every page that changed.
+        // Watchpoints and the vmbus kernel proxy need memory without access
+        // bitmaps, so migration uses stop-and-copy with them.
+        #[cfg(feature = "migration_precopy")]
+        let (gm, dirty_tracker) = {
+            #[cfg(windows)]
+            let vmbus_proxy = cfg
+                .vmbus
+                .as_ref()
+                .is_some_and(|vmbus| vmbus.vmbusproxy_handle.is_some());
+            #[cfg(not(windows))]
+            let vmbus_proxy = false;
+            match (!cfg.memory.watchpoints && !vmbus_proxy)
+                .then(|| GuestMemoryDirtyTracker::new(&gm))
+                .flatten()
+            {
+                Some((tracker, gm)) => (gm, Some(tracker)),
+                None => (gm, None),
+            }
+        };
+
         let mut cpuid = Vec::new();
 
         // Add in Hyper-V VMM CPUID leaves.
@@ -990,6 +1126,19 @@ async fn new_with_hypervisor<P, H>(
                 .context("failed to attach memory to VTL2")?;
         }
 
+        #[cfg(feature = "memory_watchpoints")]
+        let (gm, memory_watcher) = if cfg.memory.watchpoints {
+            let (watcher, gm) = GuestMemoryWatcher::new(&gm)
+                .context("guest memory does not support watchpoints")?;
+            (gm, Some(watcher))
+        } else {
+            (gm, None)
+        };
+        #[cfg(not(feature = "memory_watchpoints"))]
+        if cfg.memory.watchpoints {
+            anyhow::bail!("openvmm was built without the memory_watchpoints feature");
+        }
+
         Ok(Self {
             hypervisor: hypervisor_type,
             partition,
@@ -998,6 +1147,10 @@ async fn new_with_hypervisor<P, H>(
             vmtime_source,
             memory_manager,
             gm,
+            #[cfg(feature = "memory_watchpoints")]
+            memory_watcher,
+            #[cfg(feature = "migration_precopy")]
+            dirty_tracker,
             cfg,
             mem_layout,
             processor_topology,
@@ -1025,6 +1178,10 @@ async fn load(
             vmtime_source,
             memory_manager,
             gm,
+            #[cfg(feature = "memory_watchpoints")]
+            memory_watcher,
+            #[cfg(feature = "migration_precopy")]
+            dirty_tracker,
             cfg,
             mem_layout,
             processor_topology,
@@ -1047,6 +1204,21 @@ async fn load(
             }
         }
 
+        // Allow devices such as the balloon to return guest RAM to the host.
+        resolver.add_resolver(MemoryReclaim::new(memory_manager.ram_reclaimer()));
+
+        // Allow the dynamic memory device to add RAM to the guest.
+        if let Some(hot_add) = memory_manager.ram_hot_add() {
+            resolver.add_resolver(MemoryHotAdd::new(hot_add));
+        }
+
+        // Allow devices to report guest lifecycle events to event subscribers.
+        let (vm_event_send, vm_event_recv) = mesh::channel();
+        resolver.add_resolver(GuestEventSink::new({
+            let vm_event_send = vm_event_send.clone();
+            move |event| vm_event_send.send(VmEvent::Guest(event))
+        }));
+
         if cfg
             .vmgs
             .as_ref()
@@ -1112,12 +1284,32 @@ async fn load(
         let (halt_vps, halt_request_recv) = Halt::new();
         let halt_vps = Arc::new(halt_vps);
 
+        #[cfg(feature = "memory_watchpoints")]
+        if let Some(watcher) = &memory_watcher {
+            let halt_vps = halt_vps.clone();
+            watcher.set_hit_handler(move |hit| {
+                tracing::info!(
+                    id = hit.id,
+                    gpa = hit.gpa,
+                    len = hit.len,
+                    write = hit.write,
+                    thread = hit.thread.as_deref(),
+                    backtrace = %hit.backtrace,
+                    "guest memory watchpoint hit"
+                );
+                if hit.break_on_hit {
+                    halt_vps.halt(HaltReason::DebugBreak { vp: None });
+                }
+            });
+        }
+
         resolver.add_resolver(vmm_core::platform_resolvers::HaltResolver(halt_vps.clone()));
 
         let generation_id_recv = cfg.generation_id_recv.unwrap_or_else(|| mesh::channel().1);
 
         let logger = Box::new(emuplat::firmware::MeshLogger::new(
             cfg.firmware_event_send.clone(),
+            vm_event_send,
         ));
 
         let mapper = memory_manager.device_memory_mapper();
@@ -1219,6 +1411,7 @@ async fn load(
                             processor_topology: &processor_topology,
                             mem_layout: &mem_layout,
                             cache_topology: None,
+                            numa_distances: None,
                             pcie_host_bridges: &Vec::new(),
                             with_ioapic: cfg.chipset.with_generic_ioapic,
                             with_pic: cfg.chipset.with_generic_pic,
@@ -1279,7 +1472,7 @@ async fn load(
             _ => {}
         };
 
-        let synic = Arc::new(SynicPorts::new(partition.clone()));
+        let synic = Arc::new(SynicPorts::new(partition.clone(), driver_source.simple()));
 
         let vtl2_framebuffer_gpa_base = if cfg.vtl2_gfx {
             // calculate a safe place to put the framebuffer mapping in GPA space
@@ -1301,7 +1494,8 @@ async fn load(
             None
         };
 
-        let state_units = StateUnits::new();
+        let mut state_units = StateUnits::new();
+        state_units.report_slow_units(driver_source.simple(), SLOW_STATE_UNIT_TIMEOUT);
 
         let vmtime = state_units
             .add("vmtime")
@@ -1880,7 +2074,17 @@ async fn load(
             };
 
             let vmbus_driver = driver_source.simple();
-            let vmbus = VmbusServer::builder(vmbus_driver.clone(), synic.clone(), gm.clone())
+            let mut vmbus = VmbusServer::builder(vmbus_driver.clone(), synic.clone(), gm.clone());
+            for hints in vmbus_cfg.channel_perf_hints {
+                vmbus = vmbus.channel_perf_hints(
+                    hints.instance_id,
+                    PerfHints {
+                        latency: hints.latency,
+                        throughput_mbps: hints.throughput_mbps,
+                    },
+                );
+            }
+            let vmbus = vmbus
                 .hvsock_notify(Some(hvsock_channel.server_half))
                 .external_server(vtl2_request_send)
                 .use_message_redirect(vmbus_cfg.vtl2_redirect)
@@ -1891,6 +2095,13 @@ async fn load(
                 )
                 .delay_max_version(matches!(cfg.load_mode, LoadMode::Uefi { .. }))
                 .enable_mnf(true)
+                .message_trace(
+                    vmbus_cfg
+                        .message_trace
+                        .map(|file| TraceWriter::new(std::io::BufWriter::new(file)))
+                        .transpose()
+                        .context("failed to write vmbus message trace")?,
+                )
                 .build()
                 .context("failed to create vmbus server")?;
 
@@ -2100,7 +2311,8 @@ fn make_ids(
                     .context("failed to find vmbus for vtl2"),
             }
             .with_context(|| format!("failed to resolve vmbus resource {}", resource.id()))?;
-            vmbus_devices.push(
+            vmbus_devices.push((
+                vtl,
                 offer_vmbus_device_handle_unit(
                     &driver_source,
                     &state_units,
@@ -2109,7 +2321,7 @@ fn make_ids(
                     resource,
                 )
                 .await?,
-            );
+            ));
         }
 
         // add virtio devices
@@ -2237,20 +2449,26 @@ fn make_ids(
             |(vp_index, (mut vp, runner))| {
                 let partition = partition.clone();
                 let chipset = chipset.clone();
+                let vp_pinning = cfg.vp_pinning.clone();
                 let (send, recv) = mesh::oneshot();
                 thread::Builder::new()
                     .name(format!("vp-{}", vp_index))
-                    .spawn(move || match vp.bind() {
-                        Ok(mut vp) => {
-                            send.send(Ok(()));
-                            block_on_vp(
-                                partition,
-                                VpIndex::new(vp_index as u32),
-                                vp.run(runner, &chipset),
-                            )
+                    .spawn(move || {
+                        if let Some(vp_pinning) = &vp_pinning {
+                            vp_pinning::pin_vp_thread(vp_pinning, vp_index as u32);
                         }
-                        Err(err) => {
-                            send.send(Err(err));
+                        match vp.bind() {
+                            Ok(mut vp) => {
+                                send.send(Ok(()));
+                                block_on_vp(
+                                    partition,
+                                    VpIndex::new(vp_index as u32),
+                                    vp.run(runner, &chipset),
+                                )
+                            }
+                            Err(err) => {
+                                send.send(Err(err));
+                            }
                         }
                     })
                     .unwrap();
@@ -2267,6 +2485,8 @@ fn make_ids(
         let mut this = LoadedVm {
             state_units,
             running: false,
+            event_subscribers: Vec::new(),
+            boot_pending: false,
             inner: LoadedVmInner {
                 driver_source,
                 resolver,
@@ -2278,6 +2498,10 @@ fn make_ids(
                 _scsi_devices: scsi_devices,
                 memory_manager,
                 gm,
+                #[cfg(feature = "memory_watchpoints")]
+                memory_watcher,
+                #[cfg(feature = "migration_precopy")]
+                dirty_tracker,
                 vtl0_hvsock_relay,
                 vtl2_hvsock_relay,
                 vmbus_server,
@@ -2286,6 +2510,7 @@ fn make_ids(
                 memory_cfg: cfg.memory,
                 mem_layout,
                 processor_topology,
+                vp_pinning: cfg.vp_pinning,
                 vmbus_redirect,
                 input_distributor,
                 vtl2_framebuffer_gpa_base,
@@ -2305,6 +2530,7 @@ fn make_ids(
                 _vmgs_task: vmgs_task,
                 vmgs_client_inspect_handle,
                 halt_recv,
+                vm_event_recv,
                 client_notify_send,
                 automatic_guest_reset: cfg.automatic_guest_reset,
                 pcie_host_bridges,
@@ -2317,6 +2543,7 @@ fn make_ids(
                 .context("loadedvm restore failed")?;
         } else {
             this.inner.load_firmware(false).await?;
+            this.boot_pending = true;
         }
 
         Ok(this)
@@ -2333,10 +2560,12 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
         } else {
             None
         };
+        let numa_distances = numa::distances(&self.memory_cfg.numa_nodes);
         let acpi_builder = AcpiTablesBuilder {
             processor_topology: &self.processor_topology,
             mem_layout: &self.mem_layout,
             cache_topology: cache_topology.as_ref(),
+            numa_distances: numa_distances.as_deref(),
             pcie_host_bridges: &self.pcie_host_bridges,
             with_ioapic: self.chipset_cfg.with_generic_ioapic,
             with_psp: self.chipset_cfg.with_generic_psp,
@@ -2478,6 +2707,7 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
             } => {
                 let madt = acpi_builder.build_madt();
                 let srat = acpi_builder.build_srat();
+                let slit = numa_distances.is_some().then(|| acpi_builder.build_slit());
                 const ENTROPY_SIZE: usize = 64;
                 let mut entropy = [0u8; ENTROPY_SIZE];
                 getrandom::fill(&mut entropy).unwrap();
@@ -2491,7 +2721,7 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                     acpi_tables: super::vm_loaders::igvm::AcpiTables {
                         madt: &madt,
                         srat: &srat,
-                        slit: None,
+                        slit: slit.as_deref(),
                         pptt: None,
                     },
                     vtl2_base_address,
@@ -2550,15 +2780,63 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
 
         Ok(())
     }
+
+    #[cfg(feature = "memory_watchpoints")]
+    fn memory_watcher(&self) -> anyhow::Result<&GuestMemoryWatcher> {
+        self.memory_watcher
+            .as_ref()
+            .context("memory watchpoints are not enabled for this VM")
+    }
+
+    #[cfg(feature = "memory_watchpoints")]
+    fn add_watchpoint(&self, params: WatchpointParams) -> anyhow::Result<u64> {
+        let id = self.memory_watcher()?.add(Watchpoint {
+            gpa: params.gpa,
+            len: params.len,
+            read: params.read,
+            write: params.write,
+            break_on_hit: params.break_on_hit,
+        })?;
+        Ok(id)
+    }
+
+    #[cfg(feature = "memory_watchpoints")]
+    fn remove_watchpoint(&self, id: u64) -> anyhow::Result<()> {
+        if !self.memory_watcher()?.remove(id) {
+            anyhow::bail!("no watchpoint with id {id}");
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "memory_watchpoints"))]
+    fn add_watchpoint(&self, _params: WatchpointParams) -> anyhow::Result<u64> {
+        anyhow::bail!("memory watchpoints are not enabled for this VM")
+    }
+
+    #[cfg(not(feature = "memory_watchpoints"))]
+    fn remove_watchpoint(&self, _id: u64) -> anyhow::Result<()> {
+        anyhow::bail!("memory watchpoints are not enabled for this VM")
+    }
 }
 
 impl LoadedVm {
+    fn send_event(&mut self, event: VmEvent) {
+        self.event_subscribers.retain(|send| !send.is_closed());
+        for send in &self.event_subscribers {
+            send.send(event.clone());
+        }
+    }
+
     async fn resume(&mut self) -> bool {
         if self.running {
             return false;
         }
         self.state_units.start().await;
         self.running = true;
+        self.send_event(VmEvent::Resumed);
+        if std::mem::take(&mut self.boot_pending) {
+            self.send_event(VmEvent::BootStarted);
+        }
         true
     }
 
@@ -2568,6 +2846,7 @@ async fn pause(&mut self) -> bool {
         }
         self.state_units.stop().await;
         self.running = false;
+        self.send_event(VmEvent::Paused);
         true
     }
 
@@ -2581,6 +2860,7 @@ enum Event {
             WorkerRpc(Result<WorkerRpc<RestartState>, mesh::RecvError>),
             VmRpc(Result<VmRpc, mesh::RecvError>),
             Halt(Result<HaltReason, mesh::RecvError>),
+            VmEvent(Result<VmEvent, mesh::RecvError>),
         }
 
         // Start a task to handle state unit inspections by filtering the worker
@@ -2608,7 +2888,8 @@ enum Event {
                 let a = rpc_recv.recv().map(Event::VmRpc);
                 let b = worker_rpc.recv().map(Event::WorkerRpc);
                 let c = self.inner.halt_recv.recv().map(Event::Halt);
-                (a, b, c).race().await
+                let d = self.inner.vm_event_recv.recv().map(Event::VmEvent);
+                (a, b, c, d).race().await
             };
 
             match event {
@@ -2649,6 +2930,8 @@ enum Event {
                             .field("memory_layout", &self.inner.mem_layout)
                             .field("resolver", &self.inner.resolver)
                             .field("vmgs", &self.inner.vmgs_client_inspect_handle);
+                        #[cfg(feature = "memory_watchpoints")]
+                        resp.field("memory_watchpoints", &self.inner.memory_watcher);
                     }),
                 },
                 Event::VmRpc(Err(_)) => break,
@@ -2666,6 +2949,14 @@ enum Event {
                         rpc.handle_failable(async |()| self.save().await.map(ProtobufMessage::new))
                             .await
                     }
+                    VmRpc::SaveSnapshot(rpc) => {
+                        rpc.handle_failable(async |file| self.save_snapshot(file).await)
+                            .await
+                    }
+                    VmRpc::Migrate(rpc) => {
+                        rpc.handle_failable(async |params| self.migrate(params).await)
+                            .await
+                    }
                     VmRpc::Nmi(rpc) => rpc.handle_sync(|vpindex| {
                         if vpindex < self.inner.processor_topology.vp_count() {
                             // Send an NMI MSI to the processor. We could raise
@@ -2707,12 +2998,28 @@ enum Event {
                                 resource,
                             )
                             .await?;
-                            self.inner.vmbus_devices.push(device);
+                            self.inner.vmbus_devices.push((vtl, device));
                             self.state_units.start_stopped_units().await;
                             anyhow::Ok(())
                         })
                         .await
                     }
+                    VmRpc::RemoveVmbusDevice(rpc) => {
+                        rpc.handle_failable(async |(vtl, instance_id)| {
+                            let index = self
+                                .inner
+                                .vmbus_devices
+                                .iter()
+                                .position(|(device_vtl, device)| {
+                                    *device_vtl == vtl && device.key().instance_id == instance_id
+                                })
+                                .with_context(|| format!("no vmbus device {instance_id}"))?;
+                            let (_, device) = self.inner.vmbus_devices.swap_remove(index);
+                            device.revoke().await;
+                            anyhow::Ok(())
+                        })
+                        .await
+                    }
                     VmRpc::ConnectHvsock(rpc) => {
                         let ((mut ctx, service_id, vtl), response) = rpc.split();
                         if let Some(relay) = self.hvsock_relay(vtl) {
@@ -2764,6 +3071,12 @@ enum Event {
                     VmRpc::WriteMemory(rpc) => rpc.handle_failable_sync(|(gpa, bytes)| {
                         self.inner.gm.write_at(gpa, bytes.as_slice())
                     }),
+                    VmRpc::AddWatchpoint(rpc) => rpc.handle_failable_sync(|watchpoint| {
+                        self.inner.add_watchpoint(watchpoint)
+                    }),
+                    VmRpc::RemoveWatchpoint(rpc) => rpc.handle_failable_sync(|id| {
+                        self.inner.remove_watchpoint(id)
+                    }),
                     VmRpc::UpdateCliParams(rpc) => {
                         rpc.handle_failable_sync(|params| match &mut self.inner.load_mode {
                             LoadMode::Igvm { cmdline, .. } => {
@@ -2775,6 +3088,9 @@ enum Event {
                             ),
                         })
                     }
+                    VmRpc::SubscribeEvents(rpc) => {
+                        rpc.handle_sync(|send| self.event_subscribers.push(send))
+                    }
                 },
                 Event::Halt(Err(_)) => break,
                 Event::Halt(Ok(reason)) => {
@@ -2785,9 +3101,12 @@ enum Event {
                             break;
                         }
                     } else {
+                        self.send_event(VmEvent::Halted(reason.clone()));
                         self.inner.client_notify_send.send(reason);
                     }
                 }
+                Event::VmEvent(Err(_)) => break,
+                Event::VmEvent(Ok(event)) => self.send_event(event),
             }
         }
 
@@ -2881,6 +3200,155 @@ async fn save(&mut self) -> anyhow::Result<SavedState> {
         })
     }
 
+    /// Pauses the VM and writes a snapshot of its device state and guest RAM
+    /// to `file`, resuming the VM afterwards if it was running.
+    ///
+    /// Fails if RAM has been hot added, since the snapshot only covers the RAM
+    /// in the VM's memory layout.
+    async fn save_snapshot(&mut self, file: File) -> anyhow::Result<()> {
+        let paused = self.pause().await;
+        let result = async {
+            let hot_added = self.inner.memory_manager.hot_added_size().await;
+            if hot_added != 0 {
+                anyhow::bail!("cannot snapshot a vm with {hot_added:#x} bytes of hot added ram");
+            }
+            let state = self.save().await?;
+            let gm = self.inner.gm.clone();
+            let mem_layout = self.inner.mem_layout.clone();
+            blocking::unblock(move || super::snapshot::write(&file, state, &gm, &mem_layout)).await
+        }
+        .await;
+        if paused {
+            self.resume().await;
+        }
+        result
+    }
+
+    /// Migrates the VM to another openvmm instance.
+    ///
+    /// Guest RAM is pre-copied while the VM runs, if all writes to it can be
+    /// tracked, and then the VM is paused to send the rest. On success the VM
+    /// is left paused, since it is now running on the destination. On failure
+    /// it is resumed if it was running.
+    async fn migrate(&mut self, params: MigrateParams) -> anyhow::Result<()> {
+        let MigrateParams { stream, progress } = params;
+        let mut sender = super::migrate::Sender::new(
+            stream,
+            self.inner.gm.clone(),
+            &self.inner.mem_layout,
+            progress,
+        )?;
+        let mut trackers = self.start_dirty_tracking()?;
+        if !trackers.is_empty() {
+            (sender, trackers) = blocking::unblock(move || {
+                sender.precopy(&dirty_page_refs(&trackers))?;
+                anyhow::Ok((sender, trackers))
+            })
+            .await?;
+        }
+
+        let paused = self.pause().await;
+        let result = async {
+            let hot_added = self.inner.memory_manager.hot_added_size().await;
+            if hot_added != 0 {
+                anyhow::bail!("cannot snapshot a vm with {hot_added:#x} bytes of hot added ram");
+            }
+            let state = self.save().await?;
+            let gm = self.inner.gm.clone();
+            let mem_layout = self.inner.mem_layout.clone();
+            blocking::unblock(move || super::snapshot::write(&file, state, &gm, &mem_layout)).await
+        }
+        .await;
+        if paused {
+            self.resume().await;
+        }
+        result
+    }
+
+    /// Migrates the VM to another openvmm instance.
+    ///
+    /// Guest RAM is pre-copied while the VM runs, if all writes to it can be
+    /// tracked, and then the VM is paused to send the rest. On success the VM
+    /// is left paused, since it is now running on the destination. On failure
+    /// it is resumed if it was running.
+    async fn migrate(&mut self, params: MigrateParams) -> anyhow::Result<()> {
+        let MigrateParams { stream, progress } = params;
+        let mut sender = super::migrate::Sender::new(
+            stream,
+            self.inner.gm.clone(),
+            &self.inner.mem_layout,
+            progress,
+        )?;
+        let mut tracker = self.inner.memory_manager.dirty_page_tracker();
+        tracker
+            .start()
+            .context("failed to start dirty page tracking")?;
+        let mut trackers = None;
+        if !tracker.is_precise() {
+            tracing::info!("dirty page tracking unavailable, using stop-and-copy migration");
+            drop(tracker);
+        } else if let Some(dirty_tracker) = self.inner.dirty_tracker.clone() {
+            let device_pages =
+                super::migrate::DeviceDirtyPages::start(dirty_tracker, &self.inner.mem_layout);
+            let (new_sender, guest_pages, device_pages) = blocking::unblock(move || {
+                let dirty_pages: [&dyn DirtyPages; 2] = [&tracker, &device_pages];
+                sender.precopy(&dirty_pages)?;
+                anyhow::Ok((sender, tracker, device_pages))
+            })
+            .await?;
+            sender = new_sender;
+            trackers = Some((guest_pages, device_pages));
+        } else {
+            tracing::info!(
+                "device writes to guest memory are not tracked, using stop-and-copy migration"
+            );
+            drop(tracker);
+        }
+
+        let paused = self.pause().await;
+        let result = async {
+            let hot_added = self.inner.memory_manager.hot_added_size().await;
+            if hot_added != 0 {
+                anyhow::bail!("cannot migrate a vm with {hot_added:#x} bytes of hot added ram");
+            }
+            let state = self.save().await?;
+            blocking::unblock(move || sender.finish(&dirty_page_refs(&trackers), state)).await
+        }
+        .await;
+        if result.is_err() && paused {
+            self.resume().await;
+        }
+        result
+    }
+
+    /// Starts tracking writes to guest RAM for pre-copy migration, returning
+    /// the trackers, or nothing if some writes cannot be tracked.
+    #[cfg(feature = "migration_precopy")]
+    fn start_dirty_tracking(&self) -> anyhow::Result<Vec<Box<dyn DirtyPages + Send>>> {
+        let Some(dirty_tracker) = self.inner.dirty_tracker.clone() else {
+            tracing::info!(
+                "device writes to guest memory are not tracked, using stop-and-copy migration"
+            );
+            return Ok(Vec::new());
+        };
+        let mut tracker = self.inner.memory_manager.dirty_page_tracker();
+        tracker
+            .start()
+            .context("failed to start dirty page tracking")?;
+        if !tracker.is_precise() {
+            tracing::info!("dirty page tracking unavailable, using stop-and-copy migration");
+            return Ok(Vec::new());
+        }
+        let device_pages = DeviceDirtyPages::start(dirty_tracker, &self.inner.mem_layout);
+        Ok(vec![Box::new(tracker), Box::new(device_pages)])
+    }
+
+    #[cfg(not(feature = "migration_precopy"))]
+    fn start_dirty_tracking(&self) -> anyhow::Result<Vec<Box<dyn DirtyPages + Send>>> {
+        tracing::info!("pre-copy migration is not enabled, using stop-and-copy migration");
+        Ok(Vec::new())
+    }
+
     /// Restore state on the VM.
     async fn restore(&mut self, state: SavedState) -> anyhow::Result<()> {
         self.state_units.restore(state.units).await?;
@@ -2919,6 +3387,7 @@ async fn serialize(
             vpci_devices: vec![],        // TODO
             memory: self.inner.memory_cfg,
             processor_topology: self.inner.processor_topology.to_config(),
+            vp_pinning: self.inner.vp_pinning,
             chipset: self.inner.chipset_cfg,
             vmbus: None,      // TODO
             vtl2_vmbus: None, // TODO
@@ -2965,8 +3434,10 @@ async fn reset(&mut self, reload_firmware: bool) -> anyhow::Result<()> {
         // Load again
         if reload_firmware {
             self.inner.load_firmware(false).await?;
+            self.boot_pending = true;
         }
 
+        self.send_event(VmEvent::Reset);
         if resume {
             self.resume().await;
         }
@@ -0,0 +1,740 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Migration of a VM to another openvmm instance over a TCP connection.
+//!
+//! Guest RAM is migrated with pre-copy. The source copies all of RAM while the
+//! VM keeps running, then repeatedly copies the pages that the guest wrote
+//! during the previous pass, as reported by the hypervisor's dirty page
+//! logging. Once a pass is small enough, or the passes stop shrinking, the
+//! source pauses the VM and sends the remaining changed pages followed by the
+//! device state. The destination restores from the stream and then replies
+//! with a single byte to acknowledge that the VM has been loaded. If the
+//! destination fails, it closes the connection instead, and the source resumes
+//! the VM.
+//!
+//! Dirty page logging only sees writes by guest processors, so writes made by
+//! devices through [`GuestMemory`] are tracked separately, with a
+//! `GuestMemoryDirtyTracker` when the `migration_precopy` feature is enabled.
+//! Each pass sends the pages reported by either source, including the final
+//! pass, which only sends the pages written since the last one.
+//!
+//! If the hypervisor cannot log dirty pages, or device writes cannot be
+//! tracked, this falls back to stop-and-copy, and the VM stays paused for the
+//! entire memory transfer.
+//!
+//! The stream starts with a [`StreamHeader`] and the RAM range table, followed
+//! by records. Each record is a [`RecordHeader`] followed by its data: either a
+//! page of guest RAM or, as the last record, the device state.
+
+use super::dispatch::SavedState;
+use super::snapshot;
+use anyhow::Context;
+use guestmem::GuestMemory;
+#[cfg(feature = "migration_precopy")]
+use guestmem::dirty::GuestMemoryDirtyTracker;
+use hvdef::HV_PAGE_SIZE;
+use membacking::DirtyPageTracker;
+use memory_range::MemoryRange;
+use openvmm_defs::rpc::MigrationProgress;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::net::Shutdown;
+use std::net::TcpStream;
+use vm_topology::memory::MemoryLayout;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+const MIGRATION_MAGIC: [u8; 8] = *b"OVMMMIGR";
+const MIGRATION_VERSION: u32 = 1;
+
+const RECORD_PAGE: u32 = 1;
+const RECORD_STATE: u32 = 2;
+
+/// The amount of guest memory read at a time.
+const CHUNK_SIZE: u64 = 1 << 20;
+
+/// The number of bytes sent between progress updates.
+const PROGRESS_INTERVAL: u64 = 64 << 20;
+
+/// The most passes over the dirty pages after the initial copy.
+const MAX_PRECOPY_PASSES: u32 = 8;
+
+/// Pre-copy stops once no more than this many bytes remain to be sent.
+const STOP_COPY_THRESHOLD: u64 = 32 << 20;
+
+/// The byte the destination sends once it has restored the VM.
+const ACK: u8 = 1;
+
+#[repr(C)]
+#[derive(IntoBytes, FromBytes, Immutable, KnownLayout)]
+struct StreamHeader {
+    magic: [u8; 8],
+    version: u32,
+    range_count: u32,
+}
+
+#[repr(C)]
+#[derive(IntoBytes, FromBytes, Immutable, KnownLayout)]
+struct StreamRange {
+    gpa: u64,
+    len: u64,
+}
+
+#[repr(C)]
+#[derive(IntoBytes, FromBytes, Immutable, KnownLayout)]
+struct RecordHeader {
+    kind: u32,
+    reserved: u32,
+    gpa: u64,
+    len: u64,
+}
+
+/// A source of the guest RAM pages written since the last query.
+pub(crate) trait DirtyPages {
+    /// The ranges that can be queried.
+    fn ranges(&self) -> &[MemoryRange];
+
+    /// Gets and clears the pages in `range` written since the last call,
+    /// setting one bit per page in `bitmap`.
+    fn get_dirty_pages(&self, range: MemoryRange, bitmap: &mut [u64]) -> anyhow::Result<()>;
+}
+
+impl DirtyPages for DirtyPageTracker {
+    fn ranges(&self) -> &[MemoryRange] {
+        self.ranges()
+    }
+
+    fn get_dirty_pages(&self, range: MemoryRange, bitmap: &mut [u64]) -> anyhow::Result<()> {
+        self.get_dirty_pages(range, bitmap)
+    }
+}
+
+/// Borrows each of `trackers` as a [`DirtyPages`] source for [`Sender`].
+pub(crate) fn dirty_page_refs(trackers: &[Box<dyn DirtyPages + Send>]) -> Vec<&dyn DirtyPages> {
+    trackers
+        .iter()
+        .map(|tracker| &**tracker as &dyn DirtyPages)
+        .collect()
+}
+
+/// The guest RAM pages written by devices, as tracked by a
+/// [`GuestMemoryDirtyTracker`]. Tracking runs while this object exists.
+#[cfg(feature = "migration_precopy")]
+pub(crate) struct DeviceDirtyPages {
+    tracker: GuestMemoryDirtyTracker,
+    ranges: Vec<MemoryRange>,
+}
+
+#[cfg(feature = "migration_precopy")]
+impl DeviceDirtyPages {
+    /// Starts tracking the device writes to the RAM in `mem_layout`.
+    pub fn start(tracker: GuestMemoryDirtyTracker, mem_layout: &MemoryLayout) -> Self {
+        tracker.start();
+        Self {
+            tracker,
+            ranges: snapshot::ram_ranges(mem_layout),
+        }
+    }
+}
+
+#[cfg(feature = "migration_precopy")]
+impl Drop for DeviceDirtyPages {
+    fn drop(&mut self) {
+        self.tracker.stop();
+    }
+}
+
+#[cfg(feature = "migration_precopy")]
+impl DirtyPages for DeviceDirtyPages {
+    fn ranges(&self) -> &[MemoryRange] {
+        &self.ranges
+    }
+
+    fn get_dirty_pages(&self, range: MemoryRange, bitmap: &mut [u64]) -> anyhow::Result<()> {
+        self.tracker
+            .get_dirty_pages(range.start(), range.len(), bitmap);
+        Ok(())
+    }
+}
+
+/// The source side of a migration.
+pub(crate) struct Sender {
+    writer: BufWriter<TcpStream>,
+    gm: GuestMemory,
+    ranges: Vec<MemoryRange>,
+    /// The pages left to send in the final pass, or `None` if pre-copy did not
+    /// run and all of RAM must be sent.
+    pending: Option<Vec<Vec<u64>>>,
+    progress: mesh::Sender<MigrationProgress>,
+    pass: u32,
+}
+
+impl Sender {
+    /// Starts a migration of the guest RAM in `gm` to the destination at the
+    /// other end of `stream`.
+    pub fn new(
+        stream: TcpStream,
+        gm: GuestMemory,
+        mem_layout: &MemoryLayout,
+        progress: mesh::Sender<MigrationProgress>,
+    ) -> anyhow::Result<Self> {
+        let ranges = snapshot::ram_ranges(mem_layout);
+        let mut writer = BufWriter::new(stream);
+        writer.write_all(
+            StreamHeader {
+                magic: MIGRATION_MAGIC,
+                version: MIGRATION_VERSION,
+                range_count: ranges.len() as u32,
+            }
+            .as_bytes(),
+        )?;
+        for range in &ranges {
+            writer.write_all(
+                StreamRange {
+                    gpa: range.start(),
+                    len: range.len(),
+                }
+                .as_bytes(),
+            )?;
+        }
+        Ok(Self {
+            writer,
+            gm,
+            ranges,
+            pending: None,
+            progress,
+            pass: 0,
+        })
+    }
+
+    /// Copies guest RAM while the VM is running, until few enough pages are
+    /// being written to send the rest with the VM paused.
+    ///
+    /// `dirty_pages` must track every write to guest RAM, by both the guest
+    /// and devices, and tracking must have started before this is called.
+    pub fn precopy(&mut self, dirty_pages: &[&dyn DirtyPages]) -> anyhow::Result<()> {
+        let mut pages = self.all_pages();
+        let mut remaining = u64::MAX;
+        for _ in 0..=MAX_PRECOPY_PASSES {
+            self.send_pass(&pages)?;
+            self.collect_dirty(dirty_pages, &mut pages)?;
+            let dirty = count_pages(&pages) * HV_PAGE_SIZE;
+            tracing::debug!(pass = self.pass, dirty, "pre-copy pass complete");
+            if dirty <= STOP_COPY_THRESHOLD || dirty >= remaining {
+                break;
+            }
+            remaining = dirty;
+        }
+        self.pending = Some(pages);
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Sends the guest RAM that changed since it was last sent, followed by
+    /// `state`, and waits for the destination to acknowledge that the VM has
+    /// been restored.
+    ///
+    /// If [`Self::precopy`] ran, `dirty_pages` must be the same trackers that
+    /// were passed to it. Otherwise all of RAM is sent.
+    ///
+    /// The VM must be paused.
+    pub fn finish(
+        mut self,
+        dirty_pages: &[&dyn DirtyPages],
+        state: SavedState,
+    ) -> anyhow::Result<()> {
+        let pages = match self.pending.take() {
+            Some(mut pending) => {
+                let mut dirty = self.all_pages();
+                self.collect_dirty(dirty_pages, &mut dirty)?;
+                for (pending, dirty) in pending.iter_mut().flatten().zip(dirty.iter().flatten()) {
+                    *pending |= dirty;
+                }
+                pending
+            }
+            None => self.all_pages(),
+        };
+        self.send_pass(&pages)?;
+
+        let state = mesh::payload::encode(state);
+        self.writer.write_all(
+            RecordHeader {
+                kind: RECORD_STATE,
+                reserved: 0,
+                gpa: 0,
+                len: state.len() as u64,
+            }
+            .as_bytes(),
+        )?;
+        self.writer.write_all(&state)?;
+        self.writer.flush()?;
+
+        let stream = self.writer.into_inner().map_err(|err| err.into_error())?;
+        stream.shutdown(Shutdown::Write)?;
+        let mut ack = [0];
+        (&stream)
+            .read_exact(&mut ack)
+            .context("destination failed to restore the vm")?;
+        if ack[0] != ACK {
+            anyhow::bail!("invalid acknowledgement from destination: {:#x}", ack[0]);
+        }
+        Ok(())
+    }
+
+    /// Returns a bitmap per range with every page set.
+    fn all_pages(&self) -> Vec<Vec<u64>> {
+        self.ranges
+            .iter()
+            .map(|range| {
+                let page_count = range.len() / HV_PAGE_SIZE;
+                let mut bitmap = vec![!0; page_count.div_ceil(64) as usize];
+                if page_count % 64 != 0 {
+                    *bitmap.last_mut().unwrap() = (1 << (page_count % 64)) - 1;
+                }
+                bitmap
+            })
+            .collect()
+    }
+
+    /// Replaces `pages` with the pages that any of `dirty_pages` reports as
+    /// written since the last call.
+    fn collect_dirty(
+        &self,
+        dirty_pages: &[&dyn DirtyPages],
+        pages: &mut [Vec<u64>],
+    ) -> anyhow::Result<()> {
+        for bitmap in &mut *pages {
+            bitmap.fill(0);
+        }
+        // The tracked ranges need not match the migrated ones, e.g. the hot
+        // add window is tracked but never migrated, so map each dirty page by
+        // its address.
+        let mut tracked_bitmap = Vec::new();
+        for dirty_pages in dirty_pages {
+            for &tracked in dirty_pages.ranges() {
+                if !self.ranges.iter().any(|range| range.overlaps(&tracked)) {
+                    continue;
+                }
+                tracked_bitmap.clear();
+                tracked_bitmap.resize((tracked.len() / HV_PAGE_SIZE).div_ceil(64) as usize, 0);
+                dirty_pages.get_dirty_pages(tracked, &mut tracked_bitmap)?;
+                for (i, &word) in tracked_bitmap.iter().enumerate() {
+                    let mut word = word;
+                    while word != 0 {
+                        let gpa = tracked.start()
+                            + (i as u64 * 64 + word.trailing_zeros() as u64) * HV_PAGE_SIZE;
+                        word &= word - 1;
+                        if let Some((range, bitmap)) = self
+                            .ranges
+                            .iter()
+                            .zip(&mut *pages)
+                            .find(|(range, _)| range.contains_addr(gpa))
+                        {
+                            let page = (gpa - range.start()) / HV_PAGE_SIZE;
+                            bitmap[page as usize / 64] |= 1 << (page % 64);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the pages set in `pages`.
+    fn send_pass(&mut self, pages: &[Vec<u64>]) -> anyhow::Result<()> {
+        let total = count_pages(pages) * HV_PAGE_SIZE;
+        let mut sent = 0;
+        let mut reported = 0;
+        let mut buf = vec![0; CHUNK_SIZE as usize];
+        for (range, bitmap) in self.ranges.iter().zip(pages) {
+            let mut offset = 0;
+            while offset < range.len() {
+                let len = (range.len() - offset).min(CHUNK_SIZE);
+                let first_page = offset / HV_PAGE_SIZE;
+                let page_count = len / HV_PAGE_SIZE;
+                offset += len;
+                let is_set = |page: u64| bitmap[page as usize / 64] & (1 << (page % 64)) != 0;
+                if !(first_page..first_page + page_count).any(is_set) {
+                    continue;
+                }
+                let gpa = range.start() + first_page * HV_PAGE_SIZE;
+                let buf = &mut buf[..len as usize];
+                self.gm
+                    .read_at(gpa, buf)
+                    .with_context(|| format!("failed to read guest memory at {gpa:#x}"))?;
+                for (n, data) in buf.chunks_exact(HV_PAGE_SIZE as usize).enumerate() {
+                    let page = first_page + n as u64;
+                    if !is_set(page) {
+                        continue;
+                    }
+                    self.writer.write_all(
+                        RecordHeader {
+                            kind: RECORD_PAGE,
+                            reserved: 0,
+                            gpa: range.start() + page * HV_PAGE_SIZE,
+                            len: HV_PAGE_SIZE,
+                        }
+                        .as_bytes(),
+                    )?;
+                    self.writer.write_all(data)?;
+                    sent += HV_PAGE_SIZE;
+                }
+                if sent - reported >= PROGRESS_INTERVAL {
+                    self.report(sent, total);
+                    reported = sent;
+                }
+            }
+        }
+        self.report(sent, total);
+        tracing::debug!(pass = self.pass, sent, "migration pass sent");
+        self.pass += 1;
+        Ok(())
+    }
+
+    fn report(&self, transferred: u64, total: u64) {
+        self.progress.send(MigrationProgress {
+            pass: self.pass,
+            transferred,
+            total,
+        });
+    }
+}
+
+fn count_pages(pages: &[Vec<u64>]) -> u64 {
+    pages
+        .iter()
+        .flatten()
+        .map(|word| word.count_ones() as u64)
+        .sum()
+}
+
+/// Receives the guest RAM and device state sent by [`Sender`], writing the RAM
+/// to `gm` and returning the device state.
+pub(crate) fn receive(
+    stream: &TcpStream,
+    gm: &GuestMemory,
+    mem_layout: &MemoryLayout,
+) -> anyhow::Result<SavedState> {
+    receive_stream(BufReader::new(stream), gm, mem_layout)
+}
+
+fn receive_stream(
+    mut reader: impl Read,
+    gm: &GuestMemory,
+    mem_layout: &MemoryLayout,
+) -> anyhow::Result<SavedState> {
+    let mut header = StreamHeader::new_zeroed();
+    reader
+        .read_exact(header.as_mut_bytes())
+        .context("failed to read migration header")?;
+    if header.magic != MIGRATION_MAGIC {
+        anyhow::bail!("not a migration stream");
+    }
+    if header.version != MIGRATION_VERSION {
+        anyhow::bail!("unsupported migration version {}", header.version);
+    }
+
+    let expected_ranges = snapshot::ram_ranges(mem_layout);
+    let mut ranges = Vec::new();
+    for _ in 0..header.range_count {
+        let mut range = StreamRange::new_zeroed();
+        reader
+            .read_exact(range.as_mut_bytes())
+            .context("failed to read migration range table")?;
+        ranges.push(
+            MemoryRange::try_new(range.gpa..range.gpa.wrapping_add(range.len))
+                .context("invalid migration memory range")?,
+        );
+    }
+    if ranges != expected_ranges {
+        anyhow::bail!(
+            "source memory ranges {ranges:x?} do not match the VM's memory ranges {expected_ranges:x?}"
+        );
+    }
+
+    let mut page = vec![0; HV_PAGE_SIZE as usize];
+    loop {
+        let mut record = RecordHeader::new_zeroed();
+        reader
+            .read_exact(record.as_mut_bytes())
+            .context("failed to read migration record")?;
+        match record.kind {
+            RECORD_PAGE => {
+                let gpa = record.gpa;
+                if record.len != HV_PAGE_SIZE
+                    || gpa % HV_PAGE_SIZE != 0
+                    || !ranges.iter().any(|range| range.contains_addr(gpa))
+                {
+                    anyhow::bail!("invalid migration page {gpa:#x}, length {:#x}", record.len);
+                }
+                reader
+                    .read_exact(&mut page)
+                    .context("failed to read migration page")?;
+                gm.write_at(gpa, &page)
+                    .with_context(|| format!("failed to write guest memory at {gpa:#x}"))?;
+            }
+            RECORD_STATE => {
+                let mut state = Vec::new();
+                (&mut reader)
+                    .take(record.len)
+                    .read_to_end(&mut state)
+                    .context("failed to read migration device state")?;
+                if state.len() as u64 != record.len {
+                    anyhow::bail!("migration device state is truncated");
+                }
+                return mesh::payload::decode(&state)
+                    .context("failed to decode migration device state");
+            }
+            kind => anyhow::bail!("unknown migration record {kind}"),
+        }
+    }
+}
+
+/// Acknowledges to the source that the VM has been restored.
+pub(crate) fn complete(stream: &TcpStream) -> std::io::Result<()> {
+    (&*stream).write_all(&[ACK])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    const MB: u64 = 1 << 20;
+
+    /// A dirty page source that reports the writes made with
+    /// [`TestDirtyPages::write`].
+    struct TestDirtyPages {
+        ranges: Vec<MemoryRange>,
+        gm: GuestMemory,
+        dirty: parking_lot::Mutex<Vec<u64>>,
+    }
+
+    impl TestDirtyPages {
+        fn write(&self, gpa: u64, data: &[u8]) {
+            self.gm.write_at(gpa, data).unwrap();
+            self.dirty.lock().push(gpa);
+        }
+    }
+
+    impl DirtyPages for TestDirtyPages {
+        fn ranges(&self) -> &[MemoryRange] {
+            &self.ranges
+        }
+
+        fn get_dirty_pages(&self, range: MemoryRange, bitmap: &mut [u64]) -> anyhow::Result<()> {
+            bitmap.fill(0);
+            self.dirty.lock().retain(|&gpa| {
+                if !range.contains_addr(gpa) {
+                    return true;
+                }
+                let page = (gpa - range.start()) / HV_PAGE_SIZE;
+                bitmap[page as usize / 64] |= 1 << (page % 64);
+                false
+            });
+            Ok(())
+        }
+    }
+
+    /// Returns a layout with RAM split around an MMIO gap.
+    fn test_layout() -> MemoryLayout {
+        MemoryLayout::new(2 * MB, &[MemoryRange::new(MB..2 * MB)], &[], &[], None).unwrap()
+    }
+
+    fn test_memory(mem_layout: &MemoryLayout) -> GuestMemory {
+        let gm = GuestMemory::allocate(3 * MB as usize);
+        for range in snapshot::ram_ranges(mem_layout) {
+            let data = (0..range.len())
+                .map(|i| (i / HV_PAGE_SIZE) as u8 ^ (range.start() >> 20) as u8)
+                .collect::<Vec<_>>();
+            gm.write_at(range.start(), &data).unwrap();
+        }
+        gm
+    }
+
+    fn connect() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let source = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (destination, _) = listener.accept().unwrap();
+        (source, destination)
+    }
+
+    /// Receives a migration into new guest memory and checks that it matches
+    /// `gm`.
+    fn receive_and_check(destination: TcpStream, gm: &GuestMemory, mem_layout: &MemoryLayout) {
+        let restored = GuestMemory::allocate(3 * MB as usize);
+        let state = receive(&destination, &restored, mem_layout).unwrap();
+        assert!(state.units.is_empty());
+        complete(&destination).unwrap();
+
+        for range in snapshot::ram_ranges(mem_layout) {
+            let mut expected = vec![0; range.len() as usize];
+            let mut actual = vec![0; range.len() as usize];
+            gm.read_at(range.start(), &mut expected).unwrap();
+            restored.read_at(range.start(), &mut actual).unwrap();
+            assert!(expected == actual, "{range} differs");
+        }
+    }
+
+    #[test]
+    fn precopy() {
+        let mem_layout = test_layout();
+        let gm = test_memory(&mem_layout);
+        let dirty_pages = || TestDirtyPages {
+            ranges: snapshot::ram_ranges(&mem_layout),
+            gm: gm.clone(),
+            dirty: Default::default(),
+        };
+        let (guest_pages, device_pages) = (dirty_pages(), dirty_pages());
+        let (source, destination) = connect();
+        let (progress_send, mut progress_recv) = mesh::channel();
+
+        let source = std::thread::spawn({
+            let gm = gm.clone();
+            let mem_layout = mem_layout.clone();
+            move || {
+                let trackers: [&dyn DirtyPages; 2] = [&guest_pages, &device_pages];
+                let mut sender = Sender::new(source, gm, &mem_layout, progress_send)?;
+                // Writes made by the guest and by a device while the VM runs.
+                guest_pages.write(0x3000, b"guest write");
+                device_pages.write(2 * MB + 0x5000, b"device write");
+                sender.precopy(&trackers)?;
+                guest_pages.write(2 * MB + 0x1000, b"guest write while paused");
+                device_pages.write(0x7000, b"device write while paused");
+                sender.finish(&trackers, SavedState { units: Vec::new() })
+            }
+        });
+
+        receive_and_check(destination, &gm, &mem_layout);
+        source.join().unwrap().unwrap();
+
+        let progress = std::iter::from_fn(|| progress_recv.try_recv().ok()).collect::<Vec<_>>();
+        // The initial copy sends all of RAM.
+        let first = progress.first().unwrap();
+        assert_eq!((first.pass, first.total), (0, 2 * MB));
+        // The final pass only sends the pages written during the initial copy
+        // and while paused.
+        let last = progress.last().unwrap();
+        assert_eq!(
+            (last.pass, last.transferred, last.total),
+            (1, 4 * HV_PAGE_SIZE, 4 * HV_PAGE_SIZE)
+        );
+    }
+
+    #[test]
+    fn stop_and_copy() {
+        let mem_layout = test_layout();
+        let gm = test_memory(&mem_layout);
+        let (source, destination) = connect();
+        let (progress_send, mut progress_recv) = mesh::channel();
+
+        let source = std::thread::spawn({
+            let gm = gm.clone();
+            let mem_layout = mem_layout.clone();
+            move || {
+                Sender::new(source, gm, &mem_layout, progress_send)?
+                    .finish(&[], SavedState { units: Vec::new() })
+            }
+        });
+
+        receive_and_check(destination, &gm, &mem_layout);
+        source.join().unwrap().unwrap();
+
+        // All of RAM is sent in a single pass.
+        let last = std::iter::from_fn(|| progress_recv.try_recv().ok())
+            .last()
+            .unwrap();
+        assert_eq!((last.pass, last.transferred), (0, 2 * MB));
+    }
+
+    #[test]
+    fn destination_failure() {
+        let mem_layout = test_layout();
+        let gm = test_memory(&mem_layout);
+        let (source, destination) = connect();
+        let (progress_send, _progress_recv) = mesh::channel();
+
+        let source = std::thread::spawn({
+            let gm = gm.clone();
+            let mem_layout = mem_layout.clone();
+            move || {
+                Sender::new(source, gm, &mem_layout, progress_send)?
+                    .finish(&[], SavedState { units: Vec::new() })
+            }
+        });
+
+        // A destination with a different layout rejects the stream and closes
+        // the connection without acknowledging.
+        let other_layout = MemoryLayout::new(3 * MB, &[], &[], &[], None).unwrap();
+        let restored = GuestMemory::allocate(3 * MB as usize);
+        receive(&destination, &restored, &other_layout).unwrap_err();
+        drop(destination);
+        source.join().unwrap().unwrap_err();
+    }
+
+    #[test]
+    fn invalid_records() {
+        let mem_layout = test_layout();
+        let gm = GuestMemory::allocate(3 * MB as usize);
+
+        let mut stream = Vec::new();
+        stream.extend_from_slice(
+            StreamHeader {
+                magic: MIGRATION_MAGIC,
+                version: MIGRATION_VERSION,
+                range_count: 2,
+            }
+            .as_bytes(),
+        );
+        for range in snapshot::ram_ranges(&mem_layout) {
+            stream.extend_from_slice(
+                StreamRange {
+                    gpa: range.start(),
+                    len: range.len(),
+                }
+                .as_bytes(),
+            );
+        }
+
+        let with_record = |kind, gpa, len| {
+            let mut stream = stream.clone();
+            stream.extend_from_slice(
+                RecordHeader {
+                    kind,
+                    reserved: 0,
+                    gpa,
+                    len,
+                }
+                .as_bytes(),
+            );
+            stream.resize(stream.len() + HV_PAGE_SIZE as usize, 0);
+            stream
+        };
+
+        let mut bad_magic = stream.clone();
+        bad_magic[0] ^= 1;
+        // A page in the MMIO gap.
+        let gap_page = with_record(RECORD_PAGE, MB, HV_PAGE_SIZE);
+        let unaligned_page = with_record(RECORD_PAGE, 0x800, HV_PAGE_SIZE);
+        let long_page = with_record(RECORD_PAGE, 0, 2 * HV_PAGE_SIZE);
+        let unknown = with_record(3, 0, HV_PAGE_SIZE);
+        let truncated_state = with_record(RECORD_STATE, 0, u64::MAX);
+        for stream in [
+            bad_magic,
+            stream.clone(),
+            gap_page,
+            unaligned_page,
+            long_page,
+            unknown,
+            truncated_state,
+        ] {
+            receive_stream(stream.as_slice(), &gm, &mem_layout).unwrap_err();
+        }
+    }
+}
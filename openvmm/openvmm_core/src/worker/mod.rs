@@ -2,5 +2,9 @@
 // Licensed under the MIT License.
 
 pub mod dispatch;
+mod migrate;
+mod numa;
 mod rom;
+mod snapshot;
 pub mod vm_loaders;
+mod vp_pinning;
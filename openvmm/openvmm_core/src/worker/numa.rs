@@ -0,0 +1,226 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtual NUMA node configuration.
+
+use anyhow::Context;
+use openvmm_defs::config::NumaNodeConfig;
+
+/// The SLIT distance from a node to itself.
+const LOCAL_DISTANCE: u8 = 10;
+/// The SLIT distance between nodes when none is configured.
+const DEFAULT_REMOTE_DISTANCE: u8 = 20;
+
+/// Validates the vNUMA configuration against the VM's RAM size and processor
+/// count.
+pub(crate) fn validate(
+    nodes: &[NumaNodeConfig],
+    mem_size: u64,
+    proc_count: u32,
+) -> anyhow::Result<()> {
+    if nodes.is_empty() {
+        return Ok(());
+    }
+
+    let total = nodes
+        .iter()
+        .try_fold(0u64, |total, node| total.checked_add(node.mem_size))
+        .context("vnode memory sizes overflow")?;
+    if total != mem_size {
+        anyhow::bail!(
+            "vnode memory sizes add up to {total:#x} bytes, but the VM has {mem_size:#x} bytes of memory"
+        );
+    }
+
+    let mut assigned = vec![false; proc_count as usize];
+    for (vnode, node) in nodes.iter().enumerate() {
+        for &vp in &node.vps {
+            let assigned = assigned
+                .get_mut(vp as usize)
+                .with_context(|| format!("vnode {vnode} contains nonexistent processor {vp}"))?;
+            if std::mem::replace(assigned, true) {
+                anyhow::bail!("processor {vp} is in more than one vnode");
+            }
+        }
+    }
+    if let Some(vp) = assigned.iter().position(|&assigned| !assigned) {
+        anyhow::bail!("processor {vp} is not in any vnode");
+    }
+
+    if nodes.iter().any(|node| !node.distances.is_empty()) {
+        for (vnode, node) in nodes.iter().enumerate() {
+            if node.distances.len() != nodes.len() {
+                anyhow::bail!(
+                    "vnode {vnode} has {} distances, expected {}",
+                    node.distances.len(),
+                    nodes.len()
+                );
+            }
+            for (other, &distance) in node.distances.iter().enumerate() {
+                let valid = if other == vnode {
+                    distance == LOCAL_DISTANCE
+                } else {
+                    distance > LOCAL_DISTANCE
+                };
+                if !valid {
+                    anyhow::bail!(
+                        "invalid distance {distance} from vnode {vnode} to vnode {other}"
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the RAM size of each vnode, or `None` if vNUMA is not configured.
+pub(crate) fn ram_sizes(nodes: &[NumaNodeConfig]) -> Option<Vec<u64>> {
+    (!nodes.is_empty()).then(|| nodes.iter().map(|node| node.mem_size).collect())
+}
+
+/// Returns the vnode of each processor, or `None` if vNUMA is not configured.
+///
+/// `nodes` must have been validated with [`validate`].
+pub(crate) fn vp_vnodes(nodes: &[NumaNodeConfig], proc_count: u32) -> Option<Vec<u32>> {
+    if nodes.is_empty() {
+        return None;
+    }
+    let mut vnodes = vec![0; proc_count as usize];
+    for (vnode, node) in nodes.iter().enumerate() {
+        for &vp in &node.vps {
+            vnodes[vp as usize] = vnode as u32;
+        }
+    }
+    Some(vnodes)
+}
+
+/// Returns the host node to allocate each vnode's RAM from, indexed by vnode.
+pub(crate) fn host_nodes(nodes: &[NumaNodeConfig]) -> Vec<Option<u32>> {
+    nodes.iter().map(|node| node.host_node).collect()
+}
+
+/// Returns the distance matrix to report in the SLIT, or `None` if vNUMA is
+/// not configured.
+pub(crate) fn distances(nodes: &[NumaNodeConfig]) -> Option<Vec<Vec<u8>>> {
+    if nodes.is_empty() {
+        return None;
+    }
+    let distances = if nodes.iter().any(|node| !node.distances.is_empty()) {
+        nodes.iter().map(|node| node.distances.clone()).collect()
+    } else {
+        (0..nodes.len())
+            .map(|vnode| {
+                (0..nodes.len())
+                    .map(|other| {
+                        if other == vnode {
+                            LOCAL_DISTANCE
+                        } else {
+                            DEFAULT_REMOTE_DISTANCE
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    };
+    Some(distances)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(mem_size: u64, vps: &[u32], distances: &[u8]) -> NumaNodeConfig {
+        NumaNodeConfig {
+            mem_size,
+            vps: vps.to_vec(),
+            distances: distances.to_vec(),
+            host_node: None,
+        }
+    }
+
+    #[test]
+    fn validate_accepts_valid_config() {
+        validate(&[], 0x1000, 4).unwrap();
+        validate(
+            &[node(0x1000, &[0, 2], &[]), node(0x2000, &[1, 3], &[])],
+            0x3000,
+            4,
+        )
+        .unwrap();
+        validate(
+            &[node(0x1000, &[0], &[10, 21]), node(0x1000, &[1], &[21, 10])],
+            0x2000,
+            2,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn validate_rejects_memory_mismatch() {
+        validate(
+            &[node(0x1000, &[0], &[]), node(0x1000, &[1], &[])],
+            0x3000,
+            2,
+        )
+        .unwrap_err();
+        validate(&[node(u64::MAX, &[0], &[]), node(1, &[1], &[])], 0, 2).unwrap_err();
+    }
+
+    #[test]
+    fn validate_rejects_bad_processor_assignment() {
+        // Nonexistent processor.
+        validate(
+            &[node(0x1000, &[0, 2], &[]), node(0x1000, &[1], &[])],
+            0x2000,
+            2,
+        )
+        .unwrap_err();
+        // Processor in two nodes.
+        validate(
+            &[node(0x1000, &[0, 1], &[]), node(0x1000, &[1], &[])],
+            0x2000,
+            2,
+        )
+        .unwrap_err();
+        // Processor in no node.
+        validate(
+            &[node(0x1000, &[0], &[]), node(0x1000, &[2], &[])],
+            0x2000,
+            3,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn validate_rejects_bad_distances() {
+        // Distances for only some nodes.
+        validate(
+            &[node(0x1000, &[0], &[10, 20]), node(0x1000, &[1], &[])],
+            0x2000,
+            2,
+        )
+        .unwrap_err();
+        // Non-local self distance.
+        validate(
+            &[node(0x1000, &[0], &[11, 20]), node(0x1000, &[1], &[20, 10])],
+            0x2000,
+            2,
+        )
+        .unwrap_err();
+        // Remote distance no greater than local.
+        validate(
+            &[node(0x1000, &[0], &[10, 10]), node(0x1000, &[1], &[20, 10])],
+            0x2000,
+            2,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn default_distances() {
+        let nodes = [node(0x1000, &[0], &[]), node(0x1000, &[1], &[])];
+        assert_eq!(distances(&nodes), Some(vec![vec![10, 20], vec![20, 10]]));
+        assert_eq!(vp_vnodes(&nodes, 2), Some(vec![0, 1]));
+    }
+}
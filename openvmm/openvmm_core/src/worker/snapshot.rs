@@ -0,0 +1,291 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The VM snapshot file format.
+//!
+//! A snapshot file contains, in order:
+//!
+//! 1. A [`SnapshotHeader`].
+//! 2. `range_count` [`SnapshotRange`]s describing the guest RAM ranges.
+//! 3. `state_len` bytes of protobuf-encoded [`SavedState`].
+//! 4. The contents of each RAM range, in the order of the range table.
+
+use super::dispatch::SavedState;
+use anyhow::Context;
+use guestmem::GuestMemory;
+use memory_range::MemoryRange;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use vm_topology::memory::MemoryLayout;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+const SNAPSHOT_MAGIC: [u8; 8] = *b"OVMMSNAP";
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// The amount of guest memory copied at a time.
+const CHUNK_SIZE: usize = 1 << 20;
+
+#[repr(C)]
+#[derive(IntoBytes, FromBytes, Immutable, KnownLayout)]
+struct SnapshotHeader {
+    magic: [u8; 8],
+    version: u32,
+    range_count: u32,
+    state_len: u64,
+}
+
+#[repr(C)]
+#[derive(IntoBytes, FromBytes, Immutable, KnownLayout)]
+struct SnapshotRange {
+    gpa: u64,
+    len: u64,
+}
+
+/// Returns the guest RAM ranges that are saved in a snapshot.
+pub(crate) fn ram_ranges(mem_layout: &MemoryLayout) -> Vec<MemoryRange> {
+    mem_layout
+        .ram()
+        .iter()
+        .map(|range| range.range)
+        .chain(mem_layout.vtl2_range())
+        .collect()
+}
+
+/// Writes a snapshot of `state` and the guest RAM in `gm` to `file`.
+///
+/// The VM must be paused so that neither changes while this runs.
+pub(crate) fn write(
+    file: &File,
+    state: SavedState,
+    gm: &GuestMemory,
+    mem_layout: &MemoryLayout,
+) -> anyhow::Result<()> {
+    write_stream(BufWriter::new(file), state, gm, mem_layout)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Writes a snapshot in the same format as [`write`] to `writer`.
+fn write_stream(
+    mut writer: impl Write,
+    state: SavedState,
+    gm: &GuestMemory,
+    mem_layout: &MemoryLayout,
+) -> anyhow::Result<()> {
+    let ranges = ram_ranges(mem_layout);
+    let state = mesh::payload::encode(state);
+
+    writer.write_all(
+        SnapshotHeader {
+            magic: SNAPSHOT_MAGIC,
+            version: SNAPSHOT_VERSION,
+            range_count: ranges.len() as u32,
+            state_len: state.len() as u64,
+        }
+        .as_bytes(),
+    )?;
+    for range in &ranges {
+        writer.write_all(
+            SnapshotRange {
+                gpa: range.start(),
+                len: range.len(),
+            }
+            .as_bytes(),
+        )?;
+    }
+    writer.write_all(&state)?;
+
+    let mut buf = vec![0; CHUNK_SIZE];
+    for range in &ranges {
+        let mut gpa = range.start();
+        while gpa < range.end() {
+            let buf = &mut buf[..(range.end() - gpa).min(CHUNK_SIZE as u64) as usize];
+            gm.read_at(gpa, buf)
+                .with_context(|| format!("failed to read guest memory at {gpa:#x}"))?;
+            writer.write_all(buf)?;
+            gpa += buf.len() as u64;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads a snapshot from `file`, restoring its guest RAM contents to `gm` and
+/// returning the saved device state.
+///
+/// The snapshot's RAM ranges must match `mem_layout`, so the VM must be
+/// configured the same way as when the snapshot was taken.
+pub(crate) fn read(
+    file: &File,
+    gm: &GuestMemory,
+    mem_layout: &MemoryLayout,
+) -> anyhow::Result<SavedState> {
+    read_stream(BufReader::new(file), gm, mem_layout)
+}
+
+/// Reads a snapshot in the same format as [`read`] from `reader`.
+fn read_stream(
+    mut reader: impl Read,
+    gm: &GuestMemory,
+    mem_layout: &MemoryLayout,
+) -> anyhow::Result<SavedState> {
+    let mut header = SnapshotHeader::new_zeroed();
+    reader
+        .read_exact(header.as_mut_bytes())
+        .context("failed to read snapshot header")?;
+    if header.magic != SNAPSHOT_MAGIC {
+        anyhow::bail!("not a snapshot file");
+    }
+    if header.version != SNAPSHOT_VERSION {
+        anyhow::bail!("unsupported snapshot version {}", header.version);
+    }
+
+    let expected_ranges = ram_ranges(mem_layout);
+    let mut ranges = Vec::new();
+    for _ in 0..header.range_count {
+        let mut range = SnapshotRange::new_zeroed();
+        reader
+            .read_exact(range.as_mut_bytes())
+            .context("failed to read snapshot range table")?;
+        ranges.push(
+            MemoryRange::try_new(range.gpa..range.gpa.wrapping_add(range.len))
+                .context("invalid snapshot memory range")?,
+        );
+    }
+    if ranges != expected_ranges {
+        anyhow::bail!(
+            "snapshot memory ranges {ranges:x?} do not match the VM's memory ranges {expected_ranges:x?}"
+        );
+    }
+
+    // Don't trust the header's length for the allocation, so that a corrupt
+    // header fails with an error instead of exhausting memory.
+    let mut state = Vec::new();
+    (&mut reader)
+        .take(header.state_len)
+        .read_to_end(&mut state)
+        .context("failed to read snapshot device state")?;
+    if state.len() as u64 != header.state_len {
+        anyhow::bail!("snapshot device state is truncated");
+    }
+    let state = mesh::payload::decode(&state).context("failed to decode snapshot device state")?;
+
+    let mut buf = vec![0; CHUNK_SIZE];
+    for range in &ranges {
+        let mut gpa = range.start();
+        while gpa < range.end() {
+            let buf = &mut buf[..(range.end() - gpa).min(CHUNK_SIZE as u64) as usize];
+            reader
+                .read_exact(buf)
+                .context("failed to read snapshot memory")?;
+            gm.write_at(gpa, buf)
+                .with_context(|| format!("failed to write guest memory at {gpa:#x}"))?;
+            gpa += buf.len() as u64;
+        }
+    }
+
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state_unit::SavedStateUnit;
+    use vmcore::save_restore::SavedStateBlob;
+
+    const MB: u64 = 1 << 20;
+
+    #[derive(mesh::payload::Protobuf, vmcore::save_restore::SavedStateRoot)]
+    #[mesh(package = "openvmm.test")]
+    struct TestState {
+        #[mesh(1)]
+        value: u32,
+    }
+
+    /// Returns a layout with RAM split around an MMIO gap, so that the
+    /// snapshot has more than one range.
+    fn test_layout() -> MemoryLayout {
+        MemoryLayout::new(2 * MB, &[MemoryRange::new(MB..2 * MB)], &[], &[], None).unwrap()
+    }
+
+    fn test_state() -> SavedState {
+        SavedState {
+            units: vec![SavedStateUnit {
+                name: "test".into(),
+                state: SavedStateBlob::new(TestState { value: 42 }),
+            }],
+        }
+    }
+
+    fn write_test_snapshot(gm: &GuestMemory, mem_layout: &MemoryLayout) -> Vec<u8> {
+        let mut data = Vec::new();
+        write_stream(&mut data, test_state(), gm, mem_layout).unwrap();
+        data
+    }
+
+    #[test]
+    fn round_trip() {
+        let mem_layout = test_layout();
+        let gm = GuestMemory::allocate(3 * MB as usize);
+        gm.write_at(0x1000, b"low ram").unwrap();
+        gm.write_at(MB + 0x1000, b"mmio").unwrap();
+        gm.write_at(3 * MB - 8, b"high ram").unwrap();
+
+        let data = write_test_snapshot(&gm, &mem_layout);
+
+        let restored = GuestMemory::allocate(3 * MB as usize);
+        let state = read_stream(data.as_slice(), &restored, &mem_layout).unwrap();
+        assert_eq!(state.units.len(), 1);
+        assert_eq!(state.units[0].name, "test");
+        assert_eq!(state.units[0].state.parse::<TestState>().unwrap().value, 42);
+
+        let mut buf = [0; 8];
+        restored.read_at(0x1000, &mut buf[..7]).unwrap();
+        assert_eq!(&buf[..7], b"low ram");
+        restored.read_at(3 * MB - 8, &mut buf).unwrap();
+        assert_eq!(&buf, b"high ram");
+        // The MMIO gap is not part of the snapshot.
+        restored.read_at(MB + 0x1000, &mut buf[..4]).unwrap();
+        assert_eq!(&buf[..4], &[0; 4]);
+    }
+
+    #[test]
+    fn corrupt_header() {
+        let mem_layout = test_layout();
+        let gm = GuestMemory::allocate(3 * MB as usize);
+        let data = write_test_snapshot(&gm, &mem_layout);
+
+        let mut bad_magic = data.clone();
+        bad_magic[0] ^= 1;
+        assert!(read_stream(bad_magic.as_slice(), &gm, &mem_layout).is_err());
+
+        let mut bad_version = data.clone();
+        bad_version[8] = 2;
+        assert!(read_stream(bad_version.as_slice(), &gm, &mem_layout).is_err());
+
+        let mut bad_state_len = data.clone();
+        bad_state_len[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(read_stream(bad_state_len.as_slice(), &gm, &mem_layout).is_err());
+
+        assert!(read_stream(&data[..size_of::<SnapshotHeader>() - 1], &gm, &mem_layout).is_err());
+        assert!(read_stream(&data[..data.len() - 1], &gm, &mem_layout).is_err());
+    }
+
+    #[test]
+    fn layout_mismatch() {
+        let mem_layout = test_layout();
+        let gm = GuestMemory::allocate(3 * MB as usize);
+        let data = write_test_snapshot(&gm, &mem_layout);
+
+        let other_layout = MemoryLayout::new(3 * MB, &[], &[], &[], None).unwrap();
+        assert!(read_stream(data.as_slice(), &gm, &other_layout).is_err());
+    }
+}
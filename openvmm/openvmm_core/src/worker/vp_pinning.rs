@@ -0,0 +1,124 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Pinning of VP threads, and the device threads serving them, to host CPUs.
+
+use cache_topology::CacheTopology;
+use openvmm_defs::config::VpPinningConfig;
+use pal_async::DefaultDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+use vmcore::vm_task::thread::ThreadDriverBackend;
+
+/// Returns a driver source for device tasks, pinning threads that target a VP
+/// near that VP's host CPU if requested by `config`.
+pub(crate) fn driver_source(
+    device_driver: DefaultDriver,
+    config: Option<&VpPinningConfig>,
+) -> VmTaskDriverSource {
+    let backend = ThreadDriverBackend::new(device_driver);
+    let backend = match config {
+        Some(config) if config.device_threads => {
+            let neighbors = host_cpu_neighbors(config);
+            backend.with_vp_affinity(move |vp_index| pin_device_thread(&neighbors, vp_index))
+        }
+        _ => backend,
+    };
+    VmTaskDriverSource::new(backend)
+}
+
+/// Pins the calling thread to the host CPU assigned to `vp_index`.
+pub(crate) fn pin_vp_thread(config: &VpPinningConfig, vp_index: u32) {
+    let Some(cpu) = host_cpu(config, vp_index) else {
+        return;
+    };
+    if let Err(err) = sys::pin_to_cpus(&[cpu]) {
+        tracing::warn!(
+            vp_index,
+            cpu,
+            error = &err as &dyn std::error::Error,
+            "failed to pin VP thread"
+        );
+    }
+}
+
+/// Returns, for each configured host CPU, the host CPUs sharing its
+/// last-level cache.
+fn host_cpu_neighbors(config: &VpPinningConfig) -> Vec<Vec<u32>> {
+    let topology = CacheTopology::from_host()
+        .inspect_err(|err| {
+            tracing::warn!(
+                error = err as &dyn std::error::Error,
+                "failed to get cache topology, pinning device threads to VP host CPUs"
+            );
+        })
+        .ok();
+
+    config
+        .host_cpus
+        .iter()
+        .map(|&cpu| {
+            topology
+                .as_ref()
+                .and_then(|topology| topology.last_level_cache_cpus(cpu))
+                .map_or_else(|| vec![cpu], |cpus| cpus.to_vec())
+        })
+        .collect()
+}
+
+/// Pins the calling thread to the host CPUs near the host CPU assigned to
+/// `vp_index`.
+fn pin_device_thread(neighbors: &[Vec<u32>], vp_index: u32) {
+    let Some(cpus) = for_vp(neighbors, vp_index) else {
+        return;
+    };
+    if let Err(err) = sys::pin_to_cpus(cpus) {
+        tracing::warn!(
+            vp_index,
+            ?cpus,
+            error = &err as &dyn std::error::Error,
+            "failed to pin device thread"
+        );
+    }
+}
+
+fn host_cpu(config: &VpPinningConfig, vp_index: u32) -> Option<u32> {
+    for_vp(&config.host_cpus, vp_index).copied()
+}
+
+/// Returns the entry for `vp_index`, wrapping around if there are fewer
+/// entries than VPs.
+fn for_vp<T>(per_cpu: &[T], vp_index: u32) -> Option<&T> {
+    if per_cpu.is_empty() {
+        return None;
+    }
+    Some(&per_cpu[vp_index as usize % per_cpu.len()])
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use pal::unix::affinity;
+    use std::io;
+
+    pub fn pin_to_cpus(cpus: &[u32]) -> io::Result<()> {
+        let mut set = affinity::CpuSet::new();
+        for &cpu in cpus {
+            if cpu >= affinity::max_procs() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "host CPU out of range",
+                ));
+            }
+            set.set(cpu);
+        }
+        affinity::set_current_thread_affinity(&set)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+
+    pub fn pin_to_cpus(_cpus: &[u32]) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
@@ -12,6 +12,7 @@
 use openvmm_pcat_locator::RomFileLocation;
 use std::fmt;
 use std::fs::File;
+use std::time::Duration;
 use vm_resource::Resource;
 use vm_resource::kind::PciDeviceHandleKind;
 use vm_resource::kind::VirtioDeviceHandle;
@@ -31,6 +32,7 @@ pub struct Config {
     pub vpci_devices: Vec<VpciDeviceConfig>,
     pub memory: MemoryConfig,
     pub processor_topology: ProcessorTopologyConfig,
+    pub vp_pinning: Option<VpPinningConfig>,
     pub hypervisor: HypervisorConfig,
     pub chipset: BaseChipsetManifest,
     pub vmbus: Option<VmbusConfig>,
@@ -219,6 +221,18 @@ pub struct ProcessorTopologyConfig {
     pub arch: Option<ArchTopologyConfig>,
 }
 
+/// Placement of VP threads, and the device threads serving them, on host CPUs.
+#[derive(Debug, Protobuf, Clone)]
+pub struct VpPinningConfig {
+    /// The host CPU to run each VP on, indexed by VP index. VPs beyond the
+    /// end of the list wrap around to the beginning.
+    pub host_cpus: Vec<u32>,
+    /// Pin device threads that target a VP (such as per-queue network and
+    /// storage threads) to the host CPUs sharing a cache with that VP's host
+    /// CPU.
+    pub device_threads: bool,
+}
+
 #[derive(Debug, Protobuf, Default, Clone)]
 pub struct X86TopologyConfig {
     pub apic_id_offset: u32,
@@ -274,6 +288,44 @@ pub struct MemoryConfig {
     pub pci_ecam_gaps: Vec<MemoryRange>,
     pub pci_mmio_gaps: Vec<MemoryRange>,
     pub prefetch_memory: bool,
+    /// Back RAM with host huge pages of this size, falling back to regular
+    /// pages if they cannot be allocated.
+    pub huge_page_size: Option<u64>,
+    /// The dynamic memory policy, with `mem_size` as the startup size.
+    pub dynamic_memory: Option<DynamicMemoryConfig>,
+    /// The virtual NUMA nodes, indexed by vnode. If empty, all RAM is in
+    /// vnode 0 and processors are assigned to a vnode per socket.
+    pub numa_nodes: Vec<NumaNodeConfig>,
+    /// Route device accesses to guest memory through a watcher so that
+    /// watchpoints can be set on guest physical address ranges. This slows
+    /// down accesses to watched pages only. Requires the `memory_watchpoints`
+    /// feature of `openvmm_core`.
+    pub watchpoints: bool,
+}
+
+#[derive(Debug, MeshPayload, Clone)]
+pub struct NumaNodeConfig {
+    /// The amount of RAM in the node. The sizes of all nodes must add up to
+    /// `mem_size`.
+    pub mem_size: u64,
+    /// The indexes of the processors in the node. Each processor must be in
+    /// exactly one node.
+    pub vps: Vec<u32>,
+    /// The ACPI SLIT distance from this node to each node, indexed by vnode.
+    /// If empty for all nodes, distances of 10 (local) and 20 (remote) are
+    /// used.
+    pub distances: Vec<u8>,
+    /// The host NUMA node to allocate the node's RAM from.
+    pub host_node: Option<u32>,
+}
+
+#[derive(Debug, MeshPayload)]
+pub struct DynamicMemoryConfig {
+    /// The smallest size the guest's RAM can be ballooned down to.
+    pub min_size: u64,
+    /// The largest size the guest's RAM can grow to. A hot add window is
+    /// reserved for the RAM above `mem_size`.
+    pub max_size: u64,
 }
 
 #[derive(Debug, MeshPayload, Default)]
@@ -284,6 +336,21 @@ pub struct VmbusConfig {
     #[cfg(windows)]
     pub vmbusproxy_handle: Option<vmbus_proxy::ProxyHandle>,
     pub vtl2_redirect: bool,
+    /// A file to record control-plane messages to, in the format of
+    /// `vmbus_core::trace`.
+    pub message_trace: Option<File>,
+    /// Performance hints to offer to the guest for specific channels.
+    pub channel_perf_hints: Vec<ChannelPerfHintsConfig>,
+}
+
+/// Performance hints for the channels with a given instance ID.
+#[derive(Debug, Clone, PartialEq, Eq, MeshPayload)]
+pub struct ChannelPerfHintsConfig {
+    pub instance_id: Guid,
+    /// The target latency for a single request.
+    pub latency: Option<Duration>,
+    /// The expected peak throughput, in megabytes per second.
+    pub throughput_mbps: Option<u32>,
 }
 
 #[derive(Debug, MeshPayload, Default)]
@@ -13,27 +13,108 @@
 use mesh::rpc::Rpc;
 use std::fmt;
 use std::fs::File;
+use std::net::TcpStream;
 use vm_resource::Resource;
 use vm_resource::kind::VmbusDeviceHandleKind;
+use vmcore::guest_event::GuestEvent;
+use vmm_core_defs::HaltReason;
 
 #[derive(MeshPayload)]
 pub enum VmRpc {
     Save(FailableRpc<(), ProtobufMessage>),
+    SaveSnapshot(FailableRpc<File, ()>),
+    /// Migrates the VM to the openvmm instance at the other end of the
+    /// connection, copying guest RAM while the VM runs before pausing it to
+    /// send the rest. On success, the VM is left paused and should be stopped;
+    /// on failure, it is resumed if it was running.
+    Migrate(FailableRpc<MigrateParams, ()>),
     Resume(Rpc<(), bool>),
     Pause(Rpc<(), bool>),
     ClearHalt(Rpc<(), bool>),
     Reset(FailableRpc<(), ()>),
     Nmi(Rpc<u32, ()>),
     AddVmbusDevice(FailableRpc<(DeviceVtl, Resource<VmbusDeviceHandleKind>), ()>),
+    /// Revokes the vmbus device with the given instance ID and removes it
+    /// from the VM.
+    RemoveVmbusDevice(FailableRpc<(DeviceVtl, Guid), ()>),
     ConnectHvsock(FailableRpc<(CancelContext, Guid, DeviceVtl), unix_socket::UnixStream>),
     PulseSaveRestore(Rpc<(), Result<(), PulseSaveRestoreError>>),
     StartReloadIgvm(FailableRpc<File, ()>),
     CompleteReloadIgvm(FailableRpc<bool, ()>),
     ReadMemory(FailableRpc<(u64, usize), Vec<u8>>),
     WriteMemory(FailableRpc<(u64, Vec<u8>), ()>),
+    /// Adds a guest memory watchpoint, returning its ID. Fails unless the VM
+    /// was configured with memory watchpoints enabled.
+    AddWatchpoint(FailableRpc<WatchpointParams, u64>),
+    RemoveWatchpoint(FailableRpc<u64, ()>),
     /// Updates the command line parameters that will be passed to the boot shim
     /// on the *next* VM load. This will replace the existing command line parameters.
     UpdateCliParams(FailableRpc<String, ()>),
+    /// Subscribes the sender to [`VmEvent`]s, starting with the next event.
+    /// The subscription ends when the receiver is dropped.
+    SubscribeEvents(Rpc<mesh::Sender<VmEvent>, ()>),
+}
+
+/// A VM lifecycle event.
+#[derive(Debug, Clone, MeshPayload)]
+pub enum VmEvent {
+    /// The VM's VPs and devices started running.
+    Resumed,
+    /// The VM's VPs and devices stopped running.
+    Paused,
+    /// The VM was reset and its firmware reloaded, so the guest will boot
+    /// again once resumed. This includes guest-initiated resets that are
+    /// handled automatically.
+    Reset,
+    /// The VM halted, for example because the guest powered off or crashed.
+    Halted(HaltReason),
+    /// The VM started running its firmware, for the first time since the VM
+    /// was created or reset.
+    BootStarted,
+    /// The firmware handed off control to the guest OS loader.
+    FirmwareHandoff,
+    /// A device observed a guest lifecycle event.
+    Guest(GuestEvent),
+}
+
+/// Parameters for [`VmRpc::Migrate`].
+#[derive(MeshPayload)]
+pub struct MigrateParams {
+    /// A connection to the destination, which must have been started with the
+    /// same configuration and be waiting for an incoming migration.
+    ///
+    /// Shutting down the connection cancels the migration.
+    pub stream: TcpStream,
+    /// Receives progress updates during the memory transfer.
+    pub progress: mesh::Sender<MigrationProgress>,
+}
+
+/// The progress of a migration's memory transfer.
+#[derive(Debug, Copy, Clone, MeshPayload)]
+pub struct MigrationProgress {
+    /// The pass over guest RAM. Pass 0 copies all of RAM, later passes copy
+    /// the pages written since the previous one, and the last pass runs with
+    /// the VM paused.
+    pub pass: u32,
+    /// The number of bytes of guest RAM processed so far in this pass.
+    pub transferred: u64,
+    /// The total number of bytes of guest RAM to process in this pass.
+    pub total: u64,
+}
+
+/// Parameters for [`VmRpc::AddWatchpoint`].
+#[derive(Debug, Copy, Clone, MeshPayload)]
+pub struct WatchpointParams {
+    /// The first guest physical address to watch.
+    pub gpa: u64,
+    /// The number of bytes to watch.
+    pub len: u64,
+    /// Report device reads of the range.
+    pub read: bool,
+    /// Report device writes to the range.
+    pub write: bool,
+    /// Halt the VM with a debug break when the watchpoint is hit.
+    pub break_on_hit: bool,
 }
 
 #[derive(Debug, MeshPayload, thiserror::Error)]
@@ -55,18 +136,24 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = match self {
             VmRpc::Reset(_) => "Reset",
             VmRpc::Save(_) => "Save",
+            VmRpc::SaveSnapshot(_) => "SaveSnapshot",
+            VmRpc::Migrate(_) => "Migrate",
             VmRpc::Resume(_) => "Resume",
             VmRpc::Pause(_) => "Pause",
             VmRpc::ClearHalt(_) => "ClearHalt",
             VmRpc::Nmi(_) => "Nmi",
             VmRpc::AddVmbusDevice(_) => "AddVmbusDevice",
+            VmRpc::RemoveVmbusDevice(_) => "RemoveVmbusDevice",
             VmRpc::ConnectHvsock(_) => "ConnectHvsock",
             VmRpc::PulseSaveRestore(_) => "PulseSaveRestore",
             VmRpc::StartReloadIgvm(_) => "StartReloadIgvm",
             VmRpc::CompleteReloadIgvm(_) => "CompleteReloadIgvm",
             VmRpc::ReadMemory(_) => "ReadMemory",
             VmRpc::WriteMemory(_) => "WriteMemory",
+            VmRpc::AddWatchpoint(_) => "AddWatchpoint",
+            VmRpc::RemoveWatchpoint(_) => "RemoveWatchpoint",
             VmRpc::UpdateCliParams(_) => "UpdateCliParams",
+            VmRpc::SubscribeEvents(_) => "SubscribeEvents",
         };
         f.pad(s)
     }
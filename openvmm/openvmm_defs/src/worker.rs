@@ -9,6 +9,8 @@
 use mesh::MeshPayload;
 use mesh::payload::message::ProtobufMessage;
 use mesh_worker::WorkerId;
+use std::fs::File;
+use std::net::TcpStream;
 use vmm_core_defs::HaltReason;
 
 pub const VM_WORKER: WorkerId<VmWorkerParameters> = WorkerId::new("VmWorker");
@@ -22,6 +24,13 @@ pub struct VmWorkerParameters {
     pub cfg: Config,
     /// The saved state.
     pub saved_state: Option<ProtobufMessage>,
+    /// A snapshot file to restore the VM from, written by
+    /// [`VmRpc::SaveSnapshot`]. Mutually exclusive with `saved_state`.
+    pub snapshot: Option<File>,
+    /// A connection from a source VM that is migrating to this one via
+    /// [`VmRpc::Migrate`]. Mutually exclusive with `saved_state` and
+    /// `snapshot`.
+    pub incoming_migration: Option<TcpStream>,
     /// The VM RPC channel.
     pub rpc: mesh::Receiver<VmRpc>,
     /// The notification channel.
@@ -21,6 +21,7 @@
 use anyhow::Context;
 use clap::Parser;
 use clap::ValueEnum;
+use guid::Guid;
 use openvmm_defs::config::DEFAULT_PCAT_BOOT_ORDER;
 use openvmm_defs::config::DeviceVtl;
 use openvmm_defs::config::Hypervisor;
@@ -39,6 +40,11 @@
 /// versions.
 #[derive(Parser)]
 pub struct Options {
+    /// read the VM configuration from a TOML or JSON file; options on the
+    /// command line override the file's
+    #[clap(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
     /// processor count
     #[clap(short = 'p', long, value_name = "COUNT", default_value = "1")]
     pub processors: u32,
@@ -53,6 +59,11 @@ pub struct Options {
     )]
     pub memory: u64,
 
+    /// enable dynamic memory, letting the guest's RAM shrink to MIN and grow to
+    /// MAX (e.g. 512M:4G); control it with the `dm` interactive command
+    #[clap(long, value_name = "MIN:MAX")]
+    pub dynamic_memory: Option<DynamicMemoryCli>,
+
     /// use shared memory segment
     #[clap(short = 'M', long)]
     pub shared_memory: bool,
@@ -61,6 +72,17 @@ pub struct Options {
     #[clap(long)]
     pub prefetch: bool,
 
+    /// back guest RAM with host huge pages of the given size (2M | 1G),
+    /// falling back to regular pages if not enough are available
+    #[clap(long, value_name = "SIZE", value_parser = parse_huge_page_size)]
+    pub huge_pages: Option<u64>,
+
+    /// allow setting watchpoints on guest memory ranges to trace device
+    /// accesses to them (see the `watch` interactive command). Requires
+    /// openvmm to be built with the `memory_watchpoints` feature
+    #[clap(long)]
+    pub memory_watchpoints: bool,
+
     /// start in paused state
     #[clap(short = 'P', long)]
     pub paused: bool,
@@ -138,6 +160,12 @@ pub struct Options {
         <disk>: lower disk, e.g.: `file:base.img`
     `file:<path>`                  file-backed disk
         <path>: path to file
+    `cache:<mode>:<disk>`          disk with an explicit write cache policy
+        <mode>: `writeback`, `writethrough`, or `unsafe`
+    `nvme-tcp:<addr>/<nqn>/<nsid>[;qd=<n>]` namespace on a remote NVMe/TCP target
+        <addr>: target address, e.g.: `10.0.0.1:4420`
+        <nqn>: subsystem NQN
+        <n>: I/O queue depth, default 32
 
 flags:
     `ro`                           open disk as read-only
@@ -162,6 +190,12 @@ pub struct Options {
         <disk>: lower disk, e.g.: `file:base.img`
     `file:<path>`                  file-backed disk
         <path>: path to file
+    `cache:<mode>:<disk>`          disk with an explicit write cache policy
+        <mode>: `writeback`, `writethrough`, or `unsafe`
+    `nvme-tcp:<addr>/<nqn>/<nsid>[;qd=<n>]` namespace on a remote NVMe/TCP target
+        <addr>: target address, e.g.: `10.0.0.1:4420`
+        <nqn>: subsystem NQN
+        <n>: I/O queue depth, default 32
 
 flags:
     `ro`                           open disk as read-only
@@ -179,11 +213,23 @@ pub struct Options {
     #[clap(long, value_name = "COUNT", default_value = "0")]
     pub scsi_sub_channels: u16,
 
+    /// process SCSI channel requests on these VPs (\<vp list\>, e.g. 0-3,8)
+    /// instead of on each channel's guest-selected target VP. Channel N uses
+    /// the Nth VP in the list, wrapping around.
+    #[clap(long, value_name = "VPS")]
+    pub scsi_queue_affinity: Option<VpListCli>,
+
+    /// coalesce SCSI channel interrupts to the guest, delivering at most one
+    /// per channel every specified number of microseconds
+    #[clap(long, value_name = "INTERVAL_US")]
+    pub scsi_interrupt_coalescing: Option<u64>,
+
     /// expose a virtual NIC
     #[clap(long)]
     pub nic: bool,
 
-    /// expose a virtual NIC with the given backend (consomme | dio | tap | none)
+    /// expose a virtual NIC with the given backend (consomme | dio | tap |
+    /// xdp:<interface>[:<queue>[:<xskmap path>]] | none)
     ///
     /// Prefix with `uh:` to add this NIC via Mana emulation through OpenHCL,
     /// or `vtl2:` to assign this NIC to VTL2.
@@ -225,6 +271,23 @@ pub struct Options {
     #[clap(long, default_value = "auto")]
     pub smt: SmtConfigCli,
 
+    /// pin VP threads to host CPUs (auto | \<cpu list\>, e.g. 0-3,8). `auto`
+    /// uses the CPUs the process is allowed to run on, in order.
+    #[clap(long, value_name = "CPUS")]
+    pub vp_pinning: Option<VpPinningCli>,
+
+    /// also pin each VP's device threads (e.g. network queues, storage
+    /// completions) to host CPUs sharing a cache with the VP's host CPU
+    #[clap(long, requires("vp_pinning"))]
+    pub pin_device_threads: bool,
+
+    /// add a virtual NUMA node, in vnode order
+    /// (\<size\>;vps=\<cpu list\>\[;distances=\<list\>\]\[;host=\<node\>\], e.g.
+    /// 2G;vps=0-3;distances=10,20;host=0). The node sizes must add up to the
+    /// guest RAM size, and each processor must be in exactly one node.
+    #[clap(long, value_name = "NODE")]
+    pub numa_node: Vec<NumaNodeCli>,
+
     /// configure x2apic (auto | supported | off | on)
     #[cfg(guest_arch = "x86_64")]
     #[clap(long, default_value = "auto", value_parser = parse_x2apic)]
@@ -316,6 +379,14 @@ pub struct Options {
     #[clap(long)]
     pub virtio_9p_debug: bool,
 
+    /// share a host directory over a vmbus 9p device (e.g. myfs,C:\)
+    ///
+    /// All shares are served by a single device. The guest selects a share by
+    /// passing its name as the 9p attach name (aname); a share with an empty
+    /// name is used for any name that does not match another share.
+    #[clap(long, value_name = "name,root_path")]
+    pub vmbus_9p: Vec<FsArgs>,
+
     /// add a virtio_fs device (e.g. myfs,C:\,uid=1000,gid=2000)
     #[clap(long, value_name = "tag,root_path,[options]")]
     pub virtio_fs: Vec<FsArgsWithOptions>,
@@ -332,6 +403,25 @@ pub struct Options {
     #[clap(long, value_name = "PATH")]
     pub virtio_pmem: Option<String>,
 
+    /// add a virtio balloon device, controlled with the `balloon` interactive
+    /// command
+    #[clap(long)]
+    pub virtio_balloon: bool,
+
+    /// add a virtio vsock device, relaying connections using the hybrid vsock
+    /// model with the listener at PATH
+    #[clap(long, value_name = "PATH")]
+    pub virtio_vsock_path: Option<String>,
+
+    /// the guest CID for the virtio vsock device
+    #[clap(
+        long,
+        value_name = "CID",
+        default_value = "3",
+        requires("virtio_vsock_path")
+    )]
+    pub virtio_vsock_cid: u64,
+
     /// expose a virtio network with the given backend (dio | vmnic | tap |
     /// none)
     ///
@@ -352,6 +442,11 @@ pub struct Options {
     #[clap(long, value_name = "SOCKETPATH", conflicts_with("ttrpc"))]
     pub grpc: Option<PathBuf>,
 
+    /// serve a subset of the QEMU Machine Protocol (QMP) on the specified
+    /// Unix socket
+    #[clap(long, value_name = "SOCKETPATH")]
+    pub qmp: Option<PathBuf>,
+
     /// do not launch child processes
     #[clap(long)]
     pub single_process: bool,
@@ -384,6 +479,28 @@ pub struct Options {
     #[clap(long, value_parser = vmbus_core::parse_vmbus_version)]
     pub vmbus_max_version: Option<u32>,
 
+    /// record the VTL0 vmbus control-plane messages to a trace file, for
+    /// debugging or replay
+    #[clap(long, value_name = "PATH")]
+    pub vmbus_message_trace: Option<PathBuf>,
+
+    /// offer performance hints to the guest for the VTL0 vmbus channels with
+    /// the given instance ID
+    ///
+    /// options:
+    ///     `latency_us=<US>`        the target latency for a single request
+    ///     `throughput_mbps=<MBPS>` the expected peak throughput
+    #[clap(long, value_name = "INSTANCE_ID[,OPTIONS]")]
+    pub vmbus_perf_hints: Vec<PerfHintsCli>,
+
+    /// offer a vmbus latency probe device to VTL0, sending a probe to the
+    /// guest every specified number of milliseconds.
+    ///
+    /// Round-trip latency percentiles are available via inspect. The guest
+    /// must be running pipette to respond to the probes.
+    #[clap(long, value_name = "INTERVAL_MS")]
+    pub vmbus_latency_probe: Option<u64>,
+
     /// The disk to use for the VMGS.
     ///
     /// If this is not provided, guest state will be stored in memory.
@@ -483,6 +600,12 @@ pub struct Options {
         <disk>: lower disk, e.g.: `file:base.img`
     `file:<path>`                  file-backed disk
         <path>: path to file
+    `cache:<mode>:<disk>`          disk with an explicit write cache policy
+        <mode>: `writeback`, `writethrough`, or `unsafe`
+    `nvme-tcp:<addr>/<nqn>/<nsid>[;qd=<n>]` namespace on a remote NVMe/TCP target
+        <addr>: target address, e.g.: `10.0.0.1:4420`
+        <nqn>: subsystem NQN
+        <n>: I/O queue depth, default 32
 
 flags:
     `ro`                           open disk as read-only
@@ -525,6 +648,18 @@ pub struct Options {
     #[clap(long)]
     pub halt_on_reset: bool,
 
+    /// restore the VM from a snapshot file written by the `snapshot`
+    /// interactive command. The VM must be configured as it was when the
+    /// snapshot was taken.
+    #[clap(long, value_name = "FILE")]
+    pub restore_snapshot: Option<PathBuf>,
+
+    /// wait for a migration from another openvmm instance on the specified
+    /// `host:port` before starting the VM. The VM must be configured as it is
+    /// on the source.
+    #[clap(long, value_name = "ADDRESS", conflicts_with("restore_snapshot"))]
+    pub migrate_listen: Option<String>,
+
     /// write saved state .proto files to the specified path
     #[clap(long)]
     pub write_saved_state_proto: Option<PathBuf>,
@@ -724,7 +859,7 @@ pub enum SecureBootTemplateCli {
     UefiCa,
 }
 
-fn parse_memory(s: &str) -> anyhow::Result<u64> {
+pub(crate) fn parse_memory(s: &str) -> anyhow::Result<u64> {
     if s == "VMGS_DEFAULT" {
         Ok(vmgs_format::VMGS_DEFAULT_CAPACITY)
     } else {
@@ -807,6 +942,25 @@ pub enum DiskCliKind {
         delay_ms: u64,
         disk: Box<DiskCliKind>,
     },
+    // cache:<mode>:<kind>
+    CacheMode {
+        mode: DiskCacheMode,
+        disk: Box<DiskCliKind>,
+    },
+    // nvme-tcp:<addr>/<nqn>/<nsid>[;qd=<n>]
+    NvmeTcp {
+        address: String,
+        subsystem_nqn: String,
+        nsid: u32,
+        queue_depth: u16,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum DiskCacheMode {
+    Writeback,
+    Writethrough,
+    Unsafe,
 }
 
 #[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
@@ -923,6 +1077,35 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
                         disk: Box::new(kind.parse()?),
                     }
                 }
+                "cache" => {
+                    let (mode, kind) = arg.split_once(':').context("expected mode:kind")?;
+                    DiskCliKind::CacheMode {
+                        mode: ValueEnum::from_str(mode, false)
+                            .map_err(|err| anyhow::anyhow!("invalid cache mode: {err}"))?,
+                        disk: Box::new(kind.parse()?),
+                    }
+                }
+                "nvme-tcp" => {
+                    let (target, queue_depth) = match arg.split_once(';') {
+                        Some((target, opt)) => {
+                            let Some(qd) = opt.strip_prefix("qd=") else {
+                                anyhow::bail!("invalid syntax after ';', expected 'qd=<n>'")
+                            };
+                            (target, qd.parse().context("invalid queue depth")?)
+                        }
+                        None => (arg, 32),
+                    };
+                    let (address, (subsystem_nqn, nsid)) = target
+                        .split_once('/')
+                        .and_then(|(address, rest)| Some((address, rest.rsplit_once('/')?)))
+                        .context("expected addr/nqn/nsid")?;
+                    DiskCliKind::NvmeTcp {
+                        address: address.to_string(),
+                        subsystem_nqn: subsystem_nqn.to_string(),
+                        nsid: nsid.parse().context("invalid nsid")?,
+                        queue_depth,
+                    }
+                }
                 kind => {
                     // here's a fun edge case: what if the user passes `--disk d:\path\to\disk.img`?
                     //
@@ -944,6 +1127,35 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
     }
 }
 
+fn parse_huge_page_size(s: &str) -> anyhow::Result<u64> {
+    let size = parse_memory(s)?;
+    if size != 2 << 20 && size != 1 << 30 {
+        anyhow::bail!("huge page size must be 2M or 1G");
+    }
+    Ok(size)
+}
+
+// <min>:<max>
+#[derive(Clone, Debug, PartialEq)]
+pub struct DynamicMemoryCli {
+    pub min_size: u64,
+    pub max_size: u64,
+}
+
+impl FromStr for DynamicMemoryCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (min, max) = s.split_once(':').context("expected <min>:<max>")?;
+        let min_size = parse_memory(min)?;
+        let max_size = parse_memory(max)?;
+        if min_size > max_size {
+            anyhow::bail!("minimum size is larger than the maximum size");
+        }
+        Ok(DynamicMemoryCli { min_size, max_size })
+    }
+}
+
 #[derive(Clone)]
 pub struct VmgsCli {
     pub kind: DiskCliKind,
@@ -1251,9 +1463,20 @@ fn parse_keyvalues(s: &str) -> Result<Vec<(String, Option<String>)>, String> {
 #[derive(Clone, Debug, PartialEq)]
 pub enum EndpointConfigCli {
     None,
-    Consomme { cidr: Option<String> },
-    Dio { id: Option<String> },
-    Tap { name: String },
+    Consomme {
+        cidr: Option<String>,
+    },
+    Dio {
+        id: Option<String>,
+    },
+    Tap {
+        name: String,
+    },
+    Xdp {
+        interface: String,
+        queue_id: u32,
+        xsk_map: Option<String>,
+    },
 }
 
 impl FromStr for EndpointConfigCli {
@@ -1271,6 +1494,18 @@ fn from_str(s: &str) -> Result<Self, Self::Err> {
             ["tap", name] => EndpointConfigCli::Tap {
                 name: (*name).to_owned(),
             },
+            ["xdp", interface, rest @ ..] => EndpointConfigCli::Xdp {
+                interface: (*interface).to_owned(),
+                queue_id: match rest.first() {
+                    Some(queue_id) => queue_id.parse().map_err(|_| "invalid xdp queue id")?,
+                    None => 0,
+                },
+                // The map path is the remainder, which may contain colons.
+                xsk_map: rest
+                    .get(1..)
+                    .filter(|path| !path.is_empty())
+                    .map(|path| path.join(":")),
+            },
             _ => return Err("invalid network backend".into()),
         };
 
@@ -1416,6 +1651,145 @@ fn from_str(s: &str) -> Result<Self, Self::Err> {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum VpPinningCli {
+    Auto,
+    Cpus(Vec<u32>),
+}
+
+#[derive(Debug, Error)]
+#[error("expected auto or a CPU list such as 0-3,8")]
+pub struct BadVpPinning;
+
+impl FromStr for VpPinningCli {
+    type Err = BadVpPinning;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(Self::Auto);
+        }
+        Ok(Self::Cpus(parse_cpu_list(s).ok_or(BadVpPinning)?))
+    }
+}
+
+/// Performance hints for the vmbus channels with a given instance ID.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PerfHintsCli {
+    pub instance_id: Guid,
+    pub latency_us: Option<u64>,
+    pub throughput_mbps: Option<u32>,
+}
+
+impl FromStr for PerfHintsCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut opts = s.split(',');
+        let instance_id = opts
+            .next()
+            .unwrap()
+            .parse()
+            .context("invalid instance id")?;
+        let mut hints = Self {
+            instance_id,
+            latency_us: None,
+            throughput_mbps: None,
+        };
+        for opt in opts {
+            match opt.split_once('=') {
+                Some(("latency_us", v)) => {
+                    hints.latency_us = Some(v.parse().context("invalid latency")?)
+                }
+                Some(("throughput_mbps", v)) => {
+                    hints.throughput_mbps = Some(v.parse().context("invalid throughput")?)
+                }
+                _ => anyhow::bail!("unknown perf hint option: {opt}"),
+            }
+        }
+        Ok(hints)
+    }
+}
+
+/// A list of VP indexes, such as 0-3,8.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VpListCli(pub Vec<u32>);
+
+#[derive(Debug, Error)]
+#[error("expected a VP list such as 0-3,8")]
+pub struct BadVpList;
+
+impl FromStr for VpListCli {
+    type Err = BadVpList;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(parse_cpu_list(s).ok_or(BadVpList)?))
+    }
+}
+
+/// Parses a CPU list such as 0-3,8.
+fn parse_cpu_list(s: &str) -> Option<Vec<u32>> {
+    let mut cpus = Vec::new();
+    for range in s.split(',') {
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (start, end),
+            None => (range, range),
+        };
+        let start: u32 = start.parse().ok()?;
+        let end: u32 = end.parse().ok()?;
+        if start > end {
+            return None;
+        }
+        cpus.extend(start..=end);
+    }
+    Some(cpus)
+}
+
+// <size>;vps=<cpu list>[;distances=<list>][;host=<node>]
+#[derive(Clone, Debug, PartialEq)]
+pub struct NumaNodeCli {
+    pub mem_size: u64,
+    pub vps: Vec<u32>,
+    pub distances: Vec<u8>,
+    pub host_node: Option<u32>,
+}
+
+impl FromStr for NumaNodeCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut opts = s.split(';');
+        let mem_size = parse_memory(opts.next().unwrap())?;
+        let mut vps = None;
+        let mut distances = Vec::new();
+        let mut host_node = None;
+        for opt in opts {
+            let (key, value) = opt
+                .split_once('=')
+                .with_context(|| format!("expected <key>=<value>, found {opt}"))?;
+            match key {
+                "vps" => {
+                    vps = Some(parse_cpu_list(value).context("invalid processor list")?);
+                }
+                "distances" => {
+                    distances = value
+                        .split(',')
+                        .map(|d| d.parse())
+                        .collect::<Result<_, _>>()
+                        .context("invalid distance list")?;
+                }
+                "host" => host_node = Some(value.parse().context("invalid host node")?),
+                key => anyhow::bail!("unknown numa node option {key}"),
+            }
+        }
+        Ok(NumaNodeCli {
+            mem_size,
+            vps: vps.context("missing vps=<cpu list>")?,
+            distances,
+            host_node,
+        })
+    }
+}
+
 #[cfg_attr(not(guest_arch = "x86_64"), expect(dead_code))]
 fn parse_x2apic(s: &str) -> Result<X2ApicConfig, &'static str> {
     let r = match s {
@@ -1794,6 +2168,93 @@ fn with_env_var<F, R>(name: &str, value: &str, f: F) -> R
         result
     }
 
+    #[test]
+    fn test_parse_vp_pinning() {
+        assert_eq!(VpPinningCli::from_str("auto").unwrap(), VpPinningCli::Auto);
+        assert_eq!(
+            VpPinningCli::from_str("0-3,8").unwrap(),
+            VpPinningCli::Cpus(vec![0, 1, 2, 3, 8])
+        );
+        assert!(VpPinningCli::from_str("3-1").is_err());
+        assert!(VpPinningCli::from_str("0,").is_err());
+    }
+
+    #[test]
+    fn test_parse_vp_list() {
+        assert_eq!(
+            VpListCli::from_str("0-3,8").unwrap(),
+            VpListCli(vec![0, 1, 2, 3, 8])
+        );
+        assert!(VpListCli::from_str("auto").is_err());
+        assert!(VpListCli::from_str("").is_err());
+
+        let opt = Options::try_parse_from(["openvmm", "--scsi-queue-affinity", "2,4"]).unwrap();
+        assert_eq!(opt.scsi_queue_affinity, Some(VpListCli(vec![2, 4])));
+    }
+
+    #[test]
+    fn test_parse_perf_hints() {
+        let instance_id = "ba6163d9-04a1-4d29-b605-72e2ffb1dc7f";
+        assert_eq!(
+            PerfHintsCli::from_str(instance_id).unwrap(),
+            PerfHintsCli {
+                instance_id: instance_id.parse().unwrap(),
+                latency_us: None,
+                throughput_mbps: None,
+            }
+        );
+        assert_eq!(
+            PerfHintsCli::from_str(&format!("{instance_id},latency_us=50,throughput_mbps=2000"))
+                .unwrap(),
+            PerfHintsCli {
+                instance_id: instance_id.parse().unwrap(),
+                latency_us: Some(50),
+                throughput_mbps: Some(2000),
+            }
+        );
+        assert!(PerfHintsCli::from_str("not-a-guid").is_err());
+        assert!(PerfHintsCli::from_str(&format!("{instance_id},latency=50")).is_err());
+        assert!(PerfHintsCli::from_str(&format!("{instance_id},latency_us=x")).is_err());
+    }
+
+    #[test]
+    fn test_parse_dynamic_memory() {
+        assert_eq!(
+            DynamicMemoryCli::from_str("512M:4G").unwrap(),
+            DynamicMemoryCli {
+                min_size: 512 * 1024 * 1024,
+                max_size: 4 * 1024 * 1024 * 1024,
+            }
+        );
+        assert!(DynamicMemoryCli::from_str("4G").is_err());
+        assert!(DynamicMemoryCli::from_str("4G:512M").is_err());
+    }
+
+    #[test]
+    fn test_parse_numa_node() {
+        assert_eq!(
+            NumaNodeCli::from_str("2G;vps=0-3,8").unwrap(),
+            NumaNodeCli {
+                mem_size: 2 * 1024 * 1024 * 1024,
+                vps: vec![0, 1, 2, 3, 8],
+                distances: vec![],
+                host_node: None,
+            }
+        );
+        assert_eq!(
+            NumaNodeCli::from_str("512M;vps=1;distances=20,10;host=1").unwrap(),
+            NumaNodeCli {
+                mem_size: 512 * 1024 * 1024,
+                vps: vec![1],
+                distances: vec![20, 10],
+                host_node: Some(1),
+            }
+        );
+        assert!(NumaNodeCli::from_str("2G").is_err());
+        assert!(NumaNodeCli::from_str("2G;vps=0;bad=1").is_err());
+        assert!(NumaNodeCli::from_str("2G;vps=0;distances=10,300").is_err());
+    }
+
     #[test]
     fn test_parse_file_disk_with_create() {
         let s = "file:test.vhd;create=1G";
@@ -1887,6 +2348,52 @@ fn test_parse_memory_diff_disk() {
         }
     }
 
+    #[test]
+    fn test_parse_cache_mode_disk() {
+        let disk = DiskCliKind::from_str("cache:writethrough:file:disk.img").unwrap();
+        match disk {
+            DiskCliKind::CacheMode { mode, disk } => {
+                assert_eq!(mode, DiskCacheMode::Writethrough);
+                assert!(matches!(*disk, DiskCliKind::File { .. }));
+            }
+            _ => panic!("Expected CacheMode variant"),
+        }
+    }
+
+    #[test]
+    fn test_parse_nvme_tcp_disk() {
+        let disk =
+            DiskCliKind::from_str("nvme-tcp:10.0.0.1:4420/nqn.2014-08.org.example:target/1;qd=64")
+                .unwrap();
+        match disk {
+            DiskCliKind::NvmeTcp {
+                address,
+                subsystem_nqn,
+                nsid,
+                queue_depth,
+            } => {
+                assert_eq!(address, "10.0.0.1:4420");
+                assert_eq!(subsystem_nqn, "nqn.2014-08.org.example:target");
+                assert_eq!(nsid, 1);
+                assert_eq!(queue_depth, 64);
+            }
+            _ => panic!("Expected NvmeTcp variant"),
+        }
+
+        let disk = DiskCliKind::from_str("nvme-tcp:host:4420/nqn/2").unwrap();
+        assert!(matches!(
+            disk,
+            DiskCliKind::NvmeTcp {
+                nsid: 2,
+                queue_depth: 32,
+                ..
+            }
+        ));
+
+        assert!(DiskCliKind::from_str("nvme-tcp:host:4420/1").is_err());
+        assert!(DiskCliKind::from_str("nvme-tcp:host:4420/nqn/1;depth=4").is_err());
+    }
+
     #[test]
     fn test_parse_sqlite_disk() {
         let s = "sql:db.sqlite;create=2G";
@@ -2015,6 +2522,9 @@ fn test_parse_errors() {
         // Invalid format for crypt (missing parts)
         assert!(DiskCliKind::from_str("crypt:xts-aes-256:key.bin").is_err());
 
+        // Invalid cache mode
+        assert!(DiskCliKind::from_str("cache:invalid:file:disk.vhd").is_err());
+
         // Invalid disk kind
         assert!(DiskCliKind::from_str("invalid:path").is_err());
 
@@ -2171,6 +2681,25 @@ fn test_endpoint_config_from_str() {
             _ => panic!("Expected Tap variant"),
         }
 
+        // Test xdp
+        assert_eq!(
+            EndpointConfigCli::from_str("xdp:eth0").unwrap(),
+            EndpointConfigCli::Xdp {
+                interface: "eth0".into(),
+                queue_id: 0,
+                xsk_map: None,
+            }
+        );
+        assert_eq!(
+            EndpointConfigCli::from_str("xdp:eth0:3:/sys/fs/bpf/xsks_map").unwrap(),
+            EndpointConfigCli::Xdp {
+                interface: "eth0".into(),
+                queue_id: 3,
+                xsk_map: Some("/sys/fs/bpf/xsks_map".into()),
+            }
+        );
+        assert!(EndpointConfigCli::from_str("xdp:eth0:x").is_err());
+
         // Test error case
         assert!(EndpointConfigCli::from_str("invalid").is_err());
     }
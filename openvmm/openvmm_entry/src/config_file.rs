@@ -0,0 +1,517 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Declarative VM configuration files.
+//!
+//! A configuration file describes a VM in TOML or JSON instead of on the
+//! command line. The file's structure is checked by serde (unknown keys and
+//! mistyped values are rejected with their location in the file), and then the
+//! file is translated into the equivalent command-line arguments so that each
+//! value is validated by exactly the same parsers as the CLI. Options passed
+//! on the command line replace the file's for single-valued options and add
+//! to them for list-valued ones.
+
+use crate::cli_args::Options;
+use anyhow::Context;
+use clap::ArgAction;
+use clap::CommandFactory;
+use clap::Parser;
+use clap::parser::ValueSource;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Parses the process command line, expanding `--config` if present.
+pub(crate) fn parse_options() -> anyhow::Result<Options> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let opt = Options::parse_from(&args);
+    let Some(path) = &opt.config else {
+        return Ok(opt);
+    };
+
+    let file_args = load(path)?;
+    match Options::try_parse_from(merge_args(&args, &file_args)?) {
+        Ok(opt) => Ok(opt),
+        Err(err) => {
+            // Blame the file if it is invalid on its own, since clap's error
+            // refers to the options by their command-line names.
+            let bin = args.first().context("missing binary name")?;
+            let file_only =
+                std::iter::once(bin.clone()).chain(file_args.iter().flat_map(FileArg::to_args));
+            if let Err(file_err) = Options::try_parse_from(file_only) {
+                return Err(anyhow::Error::from(file_err))
+                    .with_context(|| format!("invalid VM configuration in {}", path.display()));
+            }
+            err.exit()
+        }
+    }
+}
+
+/// Merges the options from a configuration file into the command line
+/// `args`, which must be valid on their own.
+///
+/// A single-valued option or flag given on the command line replaces the
+/// file's value. A list-valued option is passed with the file's values first,
+/// then the command line's.
+fn merge_args(args: &[OsString], file_args: &[FileArg]) -> anyhow::Result<Vec<OsString>> {
+    let (bin, cli_args) = args.split_first().context("missing binary name")?;
+    let mut command = Options::command();
+    command.build();
+    let matches = command.clone().try_get_matches_from(args)?;
+    let overridden = |name: &str| {
+        command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(name))
+            .is_some_and(|arg| {
+                !matches!(arg.get_action(), ArgAction::Append | ArgAction::Count)
+                    && matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+            })
+    };
+    Ok(std::iter::once(bin.clone())
+        .chain(
+            file_args
+                .iter()
+                .filter(|arg| !overridden(&arg.name))
+                .flat_map(FileArg::to_args),
+        )
+        .chain(cli_args.iter().cloned())
+        .collect())
+}
+
+/// Reads the configuration file at `path` and returns the equivalent
+/// command-line options.
+fn load(path: &Path) -> anyhow::Result<Vec<FileArg>> {
+    let contents = fs_err::read_to_string(path)?;
+    let config = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => VmConfigFile::from_toml(&contents),
+        Some("json") => VmConfigFile::from_json(&contents),
+        _ => anyhow::bail!(
+            "unknown format for VM configuration file {}, expected a .toml or .json extension",
+            path.display()
+        ),
+    }
+    .with_context(|| format!("failed to parse VM configuration file {}", path.display()))?;
+    config.to_args()
+}
+
+/// The contents of a VM configuration file.
+///
+/// Keys are named after the command-line options they correspond to, and
+/// values use the same syntax as the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct VmConfigFile {
+    /// `--processors`
+    processors: Option<u32>,
+    /// `--memory`, e.g. `"4GB"`.
+    memory: Option<String>,
+    /// `--hv`
+    #[serde(default)]
+    hv: bool,
+    firmware: Option<FirmwareConfig>,
+    /// `--disk`, one entry per disk.
+    #[serde(default)]
+    disks: Vec<String>,
+    /// `--nvme`, one entry per namespace.
+    #[serde(default)]
+    nvme: Vec<String>,
+    /// `--net`, one entry per vmbus NIC.
+    #[serde(default)]
+    nics: Vec<String>,
+    #[serde(default)]
+    vmbus: VmbusConfig,
+    #[serde(default)]
+    virtio: VirtioConfig,
+    #[serde(default)]
+    serial: SerialConfig,
+    /// Any other command-line option, keyed by its long name without the
+    /// leading `--`.
+    #[serde(default)]
+    options: BTreeMap<String, OptionValue>,
+}
+
+/// The boot firmware, selected by its `type` key.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", deny_unknown_fields, rename_all = "kebab-case")]
+enum FirmwareConfig {
+    /// Boot a Linux kernel directly.
+    Linux {
+        /// `--kernel`
+        kernel: Option<PathBuf>,
+        /// `--initrd`
+        initrd: Option<PathBuf>,
+        /// `--cmdline`
+        cmdline: Option<String>,
+    },
+    /// `--uefi`
+    Uefi {
+        /// `--uefi-firmware`
+        firmware: Option<PathBuf>,
+        /// `--secure-boot`
+        #[serde(default)]
+        secure_boot: bool,
+        /// `--secure-boot-template`
+        secure_boot_template: Option<String>,
+    },
+    /// `--pcat`
+    Pcat {
+        /// `--pcat-firmware`
+        firmware: Option<PathBuf>,
+        /// `--pcat-boot-order`
+        boot_order: Option<String>,
+    },
+    /// `--igvm`
+    Igvm {
+        file: PathBuf,
+        /// `--cmdline`
+        cmdline: Option<String>,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct VmbusConfig {
+    /// `--vmbus-9p`
+    #[serde(default, rename = "9p")]
+    p9: Vec<String>,
+    /// `--vmbus-max-version`
+    max_version: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct VirtioConfig {
+    /// `--virtio-net`
+    #[serde(default)]
+    net: Vec<String>,
+    /// `--virtio-fs`
+    #[serde(default)]
+    fs: Vec<String>,
+    /// `--virtio-9p`
+    #[serde(default, rename = "9p")]
+    p9: Vec<String>,
+    /// `--virtio-pmem`
+    pmem: Option<String>,
+    /// `--virtio-balloon`
+    #[serde(default)]
+    balloon: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct SerialConfig {
+    com1: Option<String>,
+    com2: Option<String>,
+    com3: Option<String>,
+    com4: Option<String>,
+    /// `--vmbus-com1-serial`
+    vmbus_com1: Option<String>,
+    /// `--vmbus-com2-serial`
+    vmbus_com2: Option<String>,
+    debugcon: Option<String>,
+}
+
+/// The value of an entry in the `options` table.
+#[derive(Debug, Deserialize)]
+#[serde(
+    untagged,
+    expecting = "a boolean, an integer, a string, or a list of strings"
+)]
+enum OptionValue {
+    /// A flag, passed if true.
+    Flag(bool),
+    Integer(i64),
+    String(String),
+    /// An option passed once per element.
+    List(Vec<String>),
+}
+
+impl VmConfigFile {
+    fn from_toml(s: &str) -> anyhow::Result<Self> {
+        Ok(toml_edit::de::from_str(s)?)
+    }
+
+    fn from_json(s: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(s)?)
+    }
+
+    fn to_args(&self) -> anyhow::Result<Vec<FileArg>> {
+        let mut args = Args::default();
+        let Self {
+            processors,
+            memory,
+            hv,
+            firmware,
+            disks,
+            nvme,
+            nics,
+            vmbus,
+            virtio,
+            serial,
+            options,
+        } = self;
+
+        args.opt("processors", processors.map(|n| n.to_string()));
+        args.opt("memory", memory.as_ref());
+        args.flag("hv", *hv);
+
+        if let Some(firmware) = firmware {
+            match firmware {
+                FirmwareConfig::Linux {
+                    kernel,
+                    initrd,
+                    cmdline,
+                } => {
+                    args.opt("kernel", kernel.as_ref());
+                    args.opt("initrd", initrd.as_ref());
+                    args.opt("cmdline", cmdline.as_ref());
+                }
+                FirmwareConfig::Uefi {
+                    firmware,
+                    secure_boot,
+                    secure_boot_template,
+                } => {
+                    args.flag("uefi", true);
+                    args.opt("uefi-firmware", firmware.as_ref());
+                    args.flag("secure-boot", *secure_boot);
+                    args.opt("secure-boot-template", secure_boot_template.as_ref());
+                }
+                FirmwareConfig::Pcat {
+                    firmware,
+                    boot_order,
+                } => {
+                    args.flag("pcat", true);
+                    args.opt("pcat-firmware", firmware.as_ref());
+                    args.opt("pcat-boot-order", boot_order.as_ref());
+                }
+                FirmwareConfig::Igvm { file, cmdline } => {
+                    args.opt("igvm", Some(file));
+                    args.opt("cmdline", cmdline.as_ref());
+                }
+            }
+        }
+
+        args.list("disk", disks);
+        args.list("nvme", nvme);
+        args.list("net", nics);
+
+        let VmbusConfig { p9, max_version } = vmbus;
+        args.list("vmbus-9p", p9);
+        args.opt("vmbus-max-version", max_version.as_ref());
+
+        let VirtioConfig {
+            net,
+            fs,
+            p9,
+            pmem,
+            balloon,
+        } = virtio;
+        args.list("virtio-net", net);
+        args.list("virtio-fs", fs);
+        args.list("virtio-9p", p9);
+        args.opt("virtio-pmem", pmem.as_ref());
+        args.flag("virtio-balloon", *balloon);
+
+        let SerialConfig {
+            com1,
+            com2,
+            com3,
+            com4,
+            vmbus_com1,
+            vmbus_com2,
+            debugcon,
+        } = serial;
+        args.opt("com1", com1.as_ref());
+        args.opt("com2", com2.as_ref());
+        args.opt("com3", com3.as_ref());
+        args.opt("com4", com4.as_ref());
+        args.opt("vmbus-com1-serial", vmbus_com1.as_ref());
+        args.opt("vmbus-com2-serial", vmbus_com2.as_ref());
+        args.opt("debugcon", debugcon.as_ref());
+
+        let command = Options::command();
+        for (name, value) in options {
+            if name == "config" {
+                anyhow::bail!("configuration files cannot include other configuration files");
+            }
+            let known = command
+                .get_arguments()
+                .any(|arg| !arg.is_hide_set() && arg.get_long() == Some(name.as_str()));
+            if !known {
+                anyhow::bail!("unknown option `{name}` in the options table");
+            }
+            match value {
+                OptionValue::Flag(set) => args.flag(name, *set),
+                OptionValue::Integer(n) => args.opt(name, Some(n.to_string())),
+                OptionValue::String(s) => args.opt(name, Some(s)),
+                OptionValue::List(values) => args.list(name, values),
+            }
+        }
+
+        Ok(args.0)
+    }
+}
+
+/// A command-line option taken from a configuration file.
+#[derive(Debug)]
+struct FileArg {
+    /// The option's long name.
+    name: String,
+    value: Option<OsString>,
+}
+
+impl FileArg {
+    fn to_args(&self) -> impl Iterator<Item = OsString> + use<'_> {
+        std::iter::once(format!("--{}", self.name).into()).chain(self.value.clone())
+    }
+}
+
+#[derive(Default)]
+struct Args(Vec<FileArg>);
+
+impl Args {
+    fn flag(&mut self, name: &str, set: bool) {
+        if set {
+            self.0.push(FileArg {
+                name: name.to_owned(),
+                value: None,
+            });
+        }
+    }
+
+    fn opt(&mut self, name: &str, value: Option<impl Into<OsString>>) {
+        if let Some(value) = value {
+            self.0.push(FileArg {
+                name: name.to_owned(),
+                value: Some(value.into()),
+            });
+        }
+    }
+
+    fn list(&mut self, name: &str, values: &[String]) {
+        for value in values {
+            self.opt(name, Some(value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli_args::SerialConfigCli;
+
+    /// Parses `cli_args` with the options from `config` merged in.
+    fn parse(config: &VmConfigFile, cli_args: &[&str]) -> anyhow::Result<Options> {
+        let args = std::iter::once("openvmm")
+            .chain(cli_args.iter().copied())
+            .map(OsString::from)
+            .collect::<Vec<_>>();
+        Ok(Options::try_parse_from(merge_args(
+            &args,
+            &config.to_args()?,
+        )?)?)
+    }
+
+    #[test]
+    fn toml_to_args() {
+        let config = VmConfigFile::from_toml(
+            r#"
+processors = 4
+memory = "2GB"
+hv = true
+disks = ["file:disk.img"]
+
+[firmware]
+type = "uefi"
+secure-boot = true
+
+[virtio]
+9p = ["tag,/tmp"]
+
+[serial]
+com1 = "console"
+
+[options]
+vnc-port = 5901
+gfx = true
+tpm = false
+"#,
+        )
+        .unwrap();
+
+        let opt = parse(&config, &[]).unwrap();
+        assert_eq!(opt.processors, 4);
+        assert_eq!(opt.memory, 2 << 30);
+        assert!(opt.hv && opt.uefi && opt.secure_boot && opt.gfx);
+        assert!(!opt.tpm);
+        assert_eq!(opt.vnc_port, 5901);
+        assert_eq!(opt.disk.len(), 1);
+        assert_eq!(opt.virtio_9p.len(), 1);
+        assert!(opt.com1.is_some());
+    }
+
+    #[test]
+    fn json_rejects_unknown_keys() {
+        assert!(VmConfigFile::from_json(r#"{"processors": 2}"#).is_ok());
+        assert!(VmConfigFile::from_json(r#"{"procesors": 2}"#).is_err());
+        assert!(
+            VmConfigFile::from_json(r#"{"firmware": {"type": "uefi", "kernel": "x"}}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn unknown_option() {
+        let config = VmConfigFile::from_toml("[options]\nno-such-option = true").unwrap();
+        assert!(config.to_args().is_err());
+        let config = VmConfigFile::from_toml("[options]\nconfig = \"other.toml\"").unwrap();
+        assert!(config.to_args().is_err());
+    }
+
+    #[test]
+    fn command_line_overrides_file() {
+        let config = VmConfigFile::from_toml(
+            r#"
+processors = 4
+memory = "2GB"
+hv = true
+disks = ["file:a.img"]
+
+[serial]
+com1 = "console"
+"#,
+        )
+        .unwrap();
+
+        // Single-valued options and flags replace the file's, list-valued
+        // options add to them.
+        let opt = parse(
+            &config,
+            &["-p", "2", "--hv", "--disk", "file:b.img", "--com1=none"],
+        )
+        .unwrap();
+        assert_eq!(opt.processors, 2);
+        assert_eq!(opt.memory, 2 << 30);
+        assert!(opt.hv);
+        assert_eq!(opt.disk.len(), 2);
+        assert!(matches!(opt.com1, Some(SerialConfigCli::None)));
+
+        // Options are still rejected if repeated on the command line.
+        assert!(parse(&config, &["-p", "2", "-p", "3"]).is_err());
+    }
+
+    #[test]
+    fn file_options_cannot_repeat() {
+        let config = VmConfigFile::from_toml(
+            r#"
+processors = 4
+
+[options]
+processors = 2
+"#,
+        )
+        .unwrap();
+        assert!(parse(&config, &[]).is_err());
+    }
+}
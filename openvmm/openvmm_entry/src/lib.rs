@@ -8,9 +8,11 @@
 #![cfg_attr(not(test), forbid(unsafe_code))]
 
 mod cli_args;
+mod config_file;
 mod crash_dump;
 mod kvp;
 mod meshworker;
+mod qmp;
 mod serial_io;
 mod storage_builder;
 mod tracing_init;
@@ -38,7 +40,9 @@
 use cli_args::VirtioBusCli;
 use cli_args::VmgsCli;
 use crash_dump::spawn_dump_handler;
+use disk_backend_resources::CacheModeDiskHandle;
 use disk_backend_resources::DelayDiskHandle;
+use disk_backend_resources::DiskCacheMode;
 use disk_backend_resources::DiskLayerDescription;
 use disk_backend_resources::layer::DiskLayerHandle;
 use disk_backend_resources::layer::RamDiskLayerHandle;
@@ -76,6 +80,7 @@
 use net_backend_resources::mac_address::MacAddress;
 use nvme_resources::NamespaceDefinition;
 use nvme_resources::NvmeControllerRequest;
+use openvmm_defs::config::ChannelPerfHintsConfig;
 use openvmm_defs::config::Config;
 use openvmm_defs::config::DEFAULT_MMIO_GAPS_AARCH64;
 use openvmm_defs::config::DEFAULT_MMIO_GAPS_AARCH64_WITH_VTL2;
@@ -89,6 +94,7 @@
 use openvmm_defs::config::LateMapVtl0MemoryPolicy;
 use openvmm_defs::config::LoadMode;
 use openvmm_defs::config::MemoryConfig;
+use openvmm_defs::config::NumaNodeConfig;
 use openvmm_defs::config::PcieDeviceConfig;
 use openvmm_defs::config::PcieRootComplexConfig;
 use openvmm_defs::config::PcieRootPortConfig;
@@ -97,11 +103,15 @@
 use openvmm_defs::config::SerialInformation;
 use openvmm_defs::config::VirtioBus;
 use openvmm_defs::config::VmbusConfig;
+use openvmm_defs::config::VpPinningConfig;
 use openvmm_defs::config::VpciDeviceConfig;
 use openvmm_defs::config::Vtl2BaseAddressType;
 use openvmm_defs::config::Vtl2Config;
+use openvmm_defs::rpc::MigrateParams;
+use openvmm_defs::rpc::MigrationProgress;
 use openvmm_defs::rpc::PulseSaveRestoreError;
 use openvmm_defs::rpc::VmRpc;
+use openvmm_defs::rpc::WatchpointParams;
 use openvmm_defs::worker::VM_WORKER;
 use openvmm_defs::worker::VmWorkerParameters;
 use openvmm_helpers::disk::create_disk_type;
@@ -124,7 +134,9 @@
 #[cfg(unix)]
 use std::io::IsTerminal;
 use std::io::Write;
+use std::net::Shutdown;
 use std::net::TcpListener;
+use std::net::TcpStream;
 use std::path::Path;
 use std::path::PathBuf;
 use std::pin::pin;
@@ -178,6 +190,8 @@ pub fn openvmm_main() {
         }
     };
 
+    tracing_init::shutdown_tracing();
+
     // Restore the terminal to its initial state.
     #[cfg(unix)]
     if let Some(orig_termios) = orig_termios {
@@ -199,6 +213,9 @@ struct VmResources {
     framebuffer_access: Option<FramebufferAccess>,
     shutdown_ic: Option<mesh::Sender<hyperv_ic_resources::shutdown::ShutdownRpc>>,
     kvp_ic: Option<mesh::Sender<hyperv_ic_resources::kvp::KvpConnectRpc>>,
+    vss_ic: Option<mesh::Sender<hyperv_ic_resources::vss::VssRpc>>,
+    balloon: Option<mesh::Sender<virtio_resources::balloon::BalloonRpc>>,
+    dynamic_memory: Option<mesh::Sender<hyperv_dm_resources::DynamicMemoryRpc>>,
     scsi_rpc: Option<mesh::Sender<ScsiControllerRequest>>,
     nvme_vtl2_rpc: Option<mesh::Sender<NvmeControllerRequest>>,
     ged_rpc: Option<mesh::Sender<get_resources::ged::GuestEmulationRequest>>,
@@ -590,6 +607,15 @@ async fn vm_config_from_command_line(
         );
     }
 
+    if let Some(affinity) = &opt.scsi_queue_affinity
+        && let Some(vp) = affinity.0.iter().find(|&&vp| vp >= opt.processors)
+    {
+        bail!(
+            "invalid SCSI queue affinity: VP {vp} is out of range for {} processors",
+            opt.processors
+        );
+    }
+
     let with_get = opt.get || (opt.vtl2 && !opt.no_get);
 
     let mut storage = storage_builder::StorageBuilder::new(with_get.then_some(openhcl_vtl));
@@ -1404,6 +1430,8 @@ async fn vm_config_from_command_line(
         resources.shutdown_ic = Some(shutdown_send);
         let (kvp_send, kvp_recv) = mesh::channel();
         resources.kvp_ic = Some(kvp_send);
+        let (vss_send, vss_recv) = mesh::channel();
+        resources.vss_ic = Some(vss_send);
         vmbus_devices.extend(
             [
                 hyperv_ic_resources::shutdown::ShutdownIcHandle {
@@ -1411,12 +1439,41 @@ async fn vm_config_from_command_line(
                 }
                 .into_resource(),
                 hyperv_ic_resources::kvp::KvpIcHandle { recv: kvp_recv }.into_resource(),
+                hyperv_ic_resources::vss::VssIcHandle { recv: vss_recv }.into_resource(),
                 hyperv_ic_resources::timesync::TimesyncIcHandle.into_resource(),
+                hyperv_ic_resources::heartbeat::HeartbeatIcHandle.into_resource(),
             ]
             .map(|r| (DeviceVtl::Vtl0, r)),
         );
     }
 
+    let dynamic_memory = if let Some(dm) = &opt.dynamic_memory {
+        if !with_hv {
+            anyhow::bail!("dynamic memory requires --hv");
+        }
+        if !(dm.min_size..=dm.max_size).contains(&opt.memory) {
+            anyhow::bail!("dynamic memory range must include the guest RAM size");
+        }
+        let (send, recv) = mesh::channel();
+        resources.dynamic_memory = Some(send);
+        vmbus_devices.push((
+            DeviceVtl::Vtl0,
+            hyperv_dm_resources::DynamicMemoryHandle {
+                startup_size: opt.memory,
+                min_size: dm.min_size,
+                max_size: dm.max_size,
+                recv,
+            }
+            .into_resource(),
+        ));
+        Some(openvmm_defs::config::DynamicMemoryConfig {
+            min_size: dm.min_size,
+            max_size: dm.max_size,
+        })
+    } else {
+        None
+    };
+
     if let Some(hive_path) = &opt.imc {
         let file = fs_err::File::open(hive_path).context("failed to open imc hive")?;
         vmbus_devices.push((
@@ -1425,6 +1482,34 @@ async fn vm_config_from_command_line(
         ));
     }
 
+    if !opt.vmbus_9p.is_empty() {
+        vmbus_devices.push((
+            DeviceVtl::Vtl0,
+            vmbus_p9_resources::VmbusPlan9Handle {
+                exports: opt
+                    .vmbus_9p
+                    .iter()
+                    .map(|args| vmbus_p9_resources::Plan9Export {
+                        name: args.tag.clone(),
+                        path: args.path.clone(),
+                    })
+                    .collect(),
+                debug: opt.virtio_9p_debug,
+            }
+            .into_resource(),
+        ));
+    }
+
+    if let Some(interval_ms) = opt.vmbus_latency_probe {
+        vmbus_devices.push((
+            DeviceVtl::Vtl0,
+            vmbus_latency_probe_resources::LatencyProbeHandle {
+                interval: Duration::from_millis(interval_ms),
+            }
+            .into_resource(),
+        ));
+    }
+
     let mut virtio_devices = Vec::new();
     let mut add_virtio_device = |bus, resource: Resource<VirtioDeviceHandle>| {
         let bus = match bus {
@@ -1514,6 +1599,31 @@ async fn vm_config_from_command_line(
         );
     }
 
+    if opt.virtio_balloon {
+        let (send, recv) = mesh::channel();
+        resources.balloon = Some(send);
+        add_virtio_device(
+            VirtioBusCli::Auto,
+            virtio_resources::balloon::VirtioBalloonHandle {
+                deflate_on_oom: true,
+                recv,
+            }
+            .into_resource(),
+        );
+    }
+
+    if let Some(path) = &opt.virtio_vsock_path {
+        add_virtio_device(
+            VirtioBusCli::Auto,
+            virtio_resources::vsock::VirtioVsockHandle {
+                guest_cid: opt.virtio_vsock_cid,
+                base_path: Some(path.clone()),
+                listener: vsock_listener(Some(path))?,
+            }
+            .into_resource(),
+        );
+    }
+
     let mut cfg = Config {
         chipset,
         load_mode,
@@ -1529,6 +1639,19 @@ async fn vm_config_from_command_line(
             pci_ecam_gaps,
             pci_mmio_gaps,
             prefetch_memory: opt.prefetch,
+            huge_page_size: opt.huge_pages,
+            dynamic_memory,
+            numa_nodes: opt
+                .numa_node
+                .iter()
+                .map(|node| NumaNodeConfig {
+                    mem_size: node.mem_size,
+                    vps: node.vps.clone(),
+                    distances: node.distances.clone(),
+                    host_node: node.host_node,
+                })
+                .collect(),
+            watchpoints: opt.memory_watchpoints,
         },
         processor_topology: ProcessorTopologyConfig {
             proc_count: opt.processors,
@@ -1540,6 +1663,19 @@ async fn vm_config_from_command_line(
             },
             arch: Some(topology_arch),
         },
+        vp_pinning: opt
+            .vp_pinning
+            .as_ref()
+            .map(|pinning| {
+                anyhow::Ok(VpPinningConfig {
+                    host_cpus: match pinning {
+                        cli_args::VpPinningCli::Auto => allowed_host_cpus()?,
+                        cli_args::VpPinningCli::Cpus(cpus) => cpus.clone(),
+                    },
+                    device_threads: opt.pin_device_threads,
+                })
+            })
+            .transpose()?,
         hypervisor: HypervisorConfig {
             with_hv,
             with_vtl2: opt.vtl2.then_some(Vtl2Config {
@@ -1569,6 +1705,24 @@ async fn vm_config_from_command_line(
             vsock_path: opt.vsock_path.clone(),
             vtl2_redirect: opt.vmbus_redirect,
             vmbus_max_version: opt.vmbus_max_version,
+            message_trace: opt
+                .vmbus_message_trace
+                .as_ref()
+                .map(|path| {
+                    std::fs::File::create(path).with_context(|| {
+                        format!("failed to create vmbus message trace {}", path.display())
+                    })
+                })
+                .transpose()?,
+            channel_perf_hints: opt
+                .vmbus_perf_hints
+                .iter()
+                .map(|hints| ChannelPerfHintsConfig {
+                    instance_id: hints.instance_id,
+                    latency: hints.latency_us.map(Duration::from_micros),
+                    throughput_mbps: hints.throughput_mbps,
+                })
+                .collect(),
             #[cfg(windows)]
             vmbusproxy_handle,
         }),
@@ -1598,10 +1752,36 @@ async fn vm_config_from_command_line(
         },
     };
 
-    storage.build_config(&mut cfg, &mut resources, opt.scsi_sub_channels)?;
+    storage.build_config(
+        &mut cfg,
+        &mut resources,
+        opt.scsi_sub_channels,
+        opt.scsi_queue_affinity
+            .as_ref()
+            .map(|a| a.0.as_slice())
+            .unwrap_or_default(),
+        opt.scsi_interrupt_coalescing.map(Duration::from_micros),
+    )?;
     Ok((cfg, resources))
 }
 
+/// Returns the host CPUs this process is allowed to run on, in order.
+#[cfg(target_os = "linux")]
+fn allowed_host_cpus() -> anyhow::Result<Vec<u32>> {
+    use pal::unix::affinity;
+
+    let mut set = affinity::CpuSet::new();
+    affinity::get_current_thread_affinity(&mut set).context("failed to get thread affinity")?;
+    Ok((0..affinity::max_procs())
+        .filter(|&cpu| set.is_set(cpu))
+        .collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allowed_host_cpus() -> anyhow::Result<Vec<u32>> {
+    bail!("automatic VP pinning is only supported on Linux")
+}
+
 /// Gets the terminal to use for externally launched console windows.
 fn openvmm_terminal_app() -> Option<PathBuf> {
     std::env::var_os("OPENVMM_TERM")
@@ -1683,6 +1863,16 @@ fn parse_endpoint(
         EndpointConfigCli::Tap { name } => {
             net_backend_resources::tap::TapHandle { name: name.clone() }.into_resource()
         }
+        EndpointConfigCli::Xdp {
+            interface,
+            queue_id,
+            xsk_map,
+        } => net_backend_resources::xdp::XdpHandle {
+            interface: interface.clone(),
+            queue_id: *queue_id,
+            xsk_map: xsk_map.clone(),
+        }
+        .into_resource(),
     };
 
     // Pick a random MAC address.
@@ -1808,6 +1998,26 @@ fn disk<T: IntoResource<DiskHandleKind>>(disk: T) -> LayerOrDisk {
             delay: CellUpdater::new(Duration::from_millis(*delay_ms)).cell(),
             disk: disk_open(inner, read_only)?,
         })),
+        DiskCliKind::CacheMode { mode, disk: inner } => layers.push(disk(CacheModeDiskHandle {
+            mode: match mode {
+                cli_args::DiskCacheMode::Writeback => DiskCacheMode::WriteBack,
+                cli_args::DiskCacheMode::Writethrough => DiskCacheMode::WriteThrough,
+                cli_args::DiskCacheMode::Unsafe => DiskCacheMode::Unsafe,
+            },
+            disk: disk_open(inner, read_only)?,
+        })),
+        DiskCliKind::NvmeTcp {
+            address,
+            subsystem_nqn,
+            nsid,
+            queue_depth,
+        } => layers.push(disk(disk_backend_resources::NvmeTcpDiskHandle {
+            address: address.clone(),
+            subsystem_nqn: subsystem_nqn.clone(),
+            host_nqn: None,
+            nsid: *nsid,
+            queue_depth: *queue_depth,
+        })),
         DiskCliKind::Crypt {
             disk: inner,
             cipher,
@@ -1908,7 +2118,7 @@ fn do_main() -> anyhow::Result<()> {
     // not return). Any worker host setup errors are return and bubbled up.
     meshworker::run_vmm_mesh_host()?;
 
-    let opt = Options::parse();
+    let opt = config_file::parse_options()?;
     if let Some(path) = &opt.write_saved_state_proto {
         mesh::payload::protofile::DescriptorWriter::new(vmcore::save_restore::saved_state_roots())
             .write_to_path(path)
@@ -2006,6 +2216,20 @@ enum InteractiveCommand {
     #[clap(visible_alias = "psr")]
     PulseSaveRestore,
 
+    /// Save a snapshot of the VM's device state and memory to a file.
+    ///
+    /// The VM is paused while the snapshot is written. Use
+    /// `--restore-snapshot` to start a VM from the snapshot.
+    #[clap(visible_alias = "snap")]
+    Snapshot {
+        /// The snapshot file to write.
+        path: PathBuf,
+    },
+
+    /// Migrate the VM to another openvmm instance.
+    #[clap(subcommand)]
+    Migrate(MigrateCommand),
+
     /// Schedule a pulsed save restore (pause, save, reset, restore, resume) to the VM.
     #[clap(visible_alias = "spsr")]
     SchedulePulseSaveRestore {
@@ -2094,6 +2318,19 @@ enum InteractiveCommand {
         update: Option<String>,
     },
 
+    /// Set a writable inspect value, such as `trace_filter`.
+    ///
+    /// This is the same as `inspect -u VALUE PATH`.
+    Set {
+        /// Target the paravisor.
+        #[clap(short = 'v', long)]
+        paravisor: bool,
+        /// The element path to update.
+        element: String,
+        /// The new value.
+        value: String,
+    },
+
     /// Restart the VNC worker.
     #[clap(visible_alias = "V")]
     RestartVnc,
@@ -2139,6 +2376,10 @@ enum InteractiveCommand {
         /// Tell the guest to force the power state transition.
         #[clap(long, short = 'f')]
         force: bool,
+        /// Reset or power off the VM if the guest has not shut down within
+        /// this many seconds.
+        #[clap(long, short = 't')]
+        timeout: Option<u64>,
     },
 
     /// Clears the current halt condition, resuming the VPs if the VM is
@@ -2193,11 +2434,98 @@ enum InteractiveCommand {
         file: Option<PathBuf>,
     },
 
+    /// Watch device accesses to guest memory. Requires `--memory-watchpoints`.
+    ///
+    /// Hits are logged and the most recent ones are available via inspect at
+    /// `vm/memory_watchpoints`.
+    #[clap(subcommand)]
+    Watch(WatchCommand),
+
     /// Inject an artificial panic into OpenVMM
     Panic,
 
     /// Use KVP to interact with the guest.
     Kvp(kvp::KvpCommand),
+
+    /// Use VSS to freeze or thaw guest applications and file systems.
+    #[clap(subcommand)]
+    Vss(VssCommand),
+
+    /// Show the virtio balloon state, or set its target size.
+    Balloon {
+        /// The amount of memory the guest should hand back to the host.
+        #[clap(value_parser = cli_args::parse_memory)]
+        target: Option<u64>,
+    },
+
+    /// Show the dynamic memory state, or set the guest's target RAM size.
+    Dm {
+        /// The amount of RAM the guest should have.
+        #[clap(value_parser = cli_args::parse_memory)]
+        target: Option<u64>,
+    },
+}
+
+/// Subcommands for the VSS IC.
+#[derive(clap::Subcommand)]
+enum VssCommand {
+    /// Check whether the guest supports VSS backups.
+    Check,
+    /// Freeze guest applications and file systems, then quiesce the SCSI
+    /// disks so that their backing stores can be snapshotted.
+    Freeze,
+    /// Thaw the SCSI disks, then guest applications and file systems.
+    Thaw,
+}
+
+/// Subcommands for managing a migration.
+#[derive(clap::Subcommand)]
+enum MigrateCommand {
+    /// Start migrating the VM to a destination that was started with
+    /// `--migrate-listen`.
+    ///
+    /// Memory is copied while the VM keeps running, and then the VM is paused
+    /// to transfer the rest of its memory and its device state. If the
+    /// hypervisor cannot track dirty pages, the VM is paused for the entire
+    /// transfer. Once the destination has restored the VM, this instance exits.
+    Start {
+        /// The destination address, as `host:port`.
+        address: String,
+    },
+    /// Show the progress of the current migration.
+    Status,
+    /// Cancel the current migration, resuming the VM if it was running.
+    Cancel,
+}
+
+/// Subcommands for managing guest memory watchpoints.
+#[derive(clap::Subcommand)]
+enum WatchCommand {
+    /// Add a watchpoint, printing its ID. Watches both reads and writes
+    /// unless `--read` or `--write` is given.
+    Add {
+        /// Guest physical address to start at.
+        #[clap(value_parser=maybe_with_radix_u64)]
+        gpa: u64,
+        /// How many bytes to watch.
+        #[clap(value_parser=maybe_with_radix_u64)]
+        size: u64,
+        /// Watch reads.
+        #[clap(long)]
+        read: bool,
+        /// Watch writes.
+        #[clap(long)]
+        write: bool,
+        /// Halt the VM when the watchpoint is hit.
+        #[clap(long = "break")]
+        break_on_hit: bool,
+    },
+    /// Remove a watchpoint.
+    #[clap(visible_alias = "rm")]
+    Remove {
+        /// The watchpoint ID.
+        id: u64,
+    },
 }
 
 /// Subcommands for managing VTL2 settings.
@@ -2278,6 +2606,49 @@ fn new_hvsock_service_id(port: u32) -> Guid {
     }
 }
 
+/// Freezes the guest and then quiesces the disks, so that a snapshot of the
+/// backing stores is application consistent.
+async fn vss_freeze(
+    vss: &mesh::Sender<hyperv_ic_resources::vss::VssRpc>,
+    scsi: Option<&mesh::Sender<ScsiControllerRequest>>,
+) -> anyhow::Result<()> {
+    vss.call_failable(hyperv_ic_resources::vss::VssRpc::Freeze, ())
+        .await
+        .context("failed to freeze the guest")?;
+    if let Some(scsi) = scsi {
+        if let Err(err) = scsi.call_failable(ScsiControllerRequest::Quiesce, ()).await {
+            // Don't leave the guest frozen.
+            if let Err(err) = vss
+                .call_failable(hyperv_ic_resources::vss::VssRpc::Thaw, ())
+                .await
+            {
+                tracing::warn!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to thaw the guest"
+                );
+            }
+            return Err(err).context("failed to quiesce disks");
+        }
+    }
+    Ok(())
+}
+
+/// Undoes [`vss_freeze`], thawing the guest even if the disks fail to thaw.
+async fn vss_thaw(
+    vss: &mesh::Sender<hyperv_ic_resources::vss::VssRpc>,
+    scsi: Option<&mesh::Sender<ScsiControllerRequest>>,
+) -> anyhow::Result<()> {
+    let disks = if let Some(scsi) = scsi {
+        scsi.call_failable(ScsiControllerRequest::Thaw, ()).await
+    } else {
+        Ok(())
+    };
+    vss.call_failable(hyperv_ic_resources::vss::VssRpc::Thaw, ())
+        .await
+        .context("failed to thaw the guest")?;
+    disks.context("failed to thaw disks")
+}
+
 async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> anyhow::Result<()> {
     let (mut vm_config, mut resources) = vm_config_from_command_line(driver, mesh, &opt).await?;
 
@@ -2346,6 +2717,19 @@ async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> an
         None
     };
 
+    let incoming_migration = if let Some(address) = &opt.migrate_listen {
+        let listener = TcpListener::bind(address)
+            .with_context(|| format!("binding to migration address {address}"))?;
+        tracing::info!(%address, "waiting for incoming migration");
+        let (stream, peer) = listener
+            .accept()
+            .context("failed to accept migration connection")?;
+        tracing::info!(%peer, "receiving migration");
+        Some(stream)
+    } else {
+        None
+    };
+
     // spin up the VM
     let (vm_rpc, rpc_recv) = mesh::channel();
     let (notify_send, notify_recv) = mesh::channel();
@@ -2356,6 +2740,16 @@ async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> an
             hypervisor: opt.hypervisor,
             cfg: vm_config,
             saved_state: None,
+            snapshot: opt
+                .restore_snapshot
+                .as_ref()
+                .map(|path| {
+                    fs_err::File::open(path)
+                        .map(Into::into)
+                        .context("failed to open snapshot")
+                })
+                .transpose()?,
+            incoming_migration,
             rpc: rpc_recv,
             notify: notify_send,
         };
@@ -2369,6 +2763,28 @@ async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> an
         vm_rpc.call(VmRpc::Resume, ()).await?;
     }
 
+    let mut qmp_server = if let Some(path) = &opt.qmp {
+        let listener = unix_socket::UnixListener::bind(path)
+            .with_context(|| format!("binding to QMP socket {}", path.display()))?;
+        let resources = qmp::QmpResources {
+            vm_rpc: vm_rpc.clone(),
+            scsi_rpc: resources.scsi_rpc.clone(),
+            shutdown_ic: resources.shutdown_ic.clone(),
+            running: !opt.paused,
+        };
+        let driver = driver.clone();
+        Some(driver.clone().spawn("qmp", async move {
+            if let Err(err) = qmp::run_qmp_server(driver, listener, resources).await {
+                tracing::error!(
+                    error = err.as_ref() as &dyn std::error::Error,
+                    "qmp server failed"
+                );
+            }
+        }))
+    } else {
+        None
+    };
+
     let paravisor_diag = Arc::new(diag_client::DiagClient::from_dialer(
         driver.clone(),
         DiagDialer {
@@ -2525,14 +2941,27 @@ async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> an
         .unwrap();
 
     let mut state_change_task = None::<Task<Result<StateChange, RpcError>>>;
+    let mut migration = None::<Migration>;
     let mut pulse_save_restore_interval: Option<Duration> = None;
     let mut pending_shutdown = None;
+    let mut shutdown_fallback = None::<(
+        mesh::Receiver<hyperv_ic_resources::shutdown::ShutdownProgress>,
+        mesh::OneshotSender<()>,
+    )>;
+
+    struct Migration {
+        stream: TcpStream,
+        progress: mesh::Receiver<MigrationProgress>,
+        last_progress: Option<MigrationProgress>,
+    }
 
     enum StateChange {
         Pause(bool),
         Resume(bool),
         Reset(Result<(), RemoteError>),
         PulseSaveRestore(Result<(), PulseSaveRestoreError>),
+        Snapshot(anyhow::Result<()>),
+        Migrate(anyhow::Result<()>),
         ServiceVtl2(anyhow::Result<Duration>),
     }
 
@@ -2548,6 +2977,7 @@ enum Event {
         VncWorker(WorkerEvent),
         StateChange(Result<StateChange, RpcError>),
         ShutdownResult(Result<hyperv_ic_resources::shutdown::ShutdownResult, RpcError>),
+        ShutdownProgress(hyperv_ic_resources::shutdown::ShutdownProgress),
     }
 
     let mut console_command_recv = console_command_recv
@@ -2586,6 +3016,10 @@ enum Event {
                     pending().await
                 }
             });
+            let shutdown_progress =
+                futures::stream::iter(shutdown_fallback.as_mut().map(|(progress, _)| progress))
+                    .flatten()
+                    .map(Event::ShutdownProgress);
 
             (
                 &mut console_command_recv,
@@ -2596,6 +3030,7 @@ enum Event {
                 vnc,
                 change,
                 shutdown.into_stream(),
+                shutdown_progress,
             )
                 .merge()
                 .next()
@@ -2629,6 +3064,8 @@ enum Event {
             Event::Quit => break,
             Event::Halt(reason) => {
                 tracing::info!(?reason, "guest halted");
+                // Dropping the cancel sender cancels any pending fallback.
+                shutdown_fallback = None;
                 continue;
             }
             Event::PulseSaveRestore => {
@@ -2713,6 +3150,30 @@ enum Event {
                                 "pulse save/restore failed"
                             ),
                         },
+                        StateChange::Snapshot(r) => match r {
+                            Ok(()) => tracing::info!("snapshot complete"),
+                            Err(err) => tracing::error!(
+                                error = err.as_ref() as &dyn std::error::Error,
+                                "snapshot failed"
+                            ),
+                        },
+                        StateChange::Migrate(r) => {
+                            migration = None;
+                            match r {
+                                Ok(()) => {
+                                    tracing::info!("migration complete, stopping vm");
+                                    drop(qmp_server.take());
+                                    resources.scsi_rpc = None;
+                                    resources.nvme_vtl2_rpc = None;
+                                    vm_worker.stop();
+                                    quit = true;
+                                }
+                                Err(err) => tracing::error!(
+                                    error = err.as_ref() as &dyn std::error::Error,
+                                    "migration failed"
+                                ),
+                            }
+                        }
                         StateChange::ServiceVtl2(r) => match r {
                             Ok(dur) => {
                                 tracing::info!(
@@ -2762,6 +3223,25 @@ enum Event {
                 pending_shutdown = None;
                 continue;
             }
+            Event::ShutdownProgress(progress) => {
+                match progress {
+                    hyperv_ic_resources::shutdown::ShutdownProgress::Requested(result) => {
+                        tracing::info!(?result, "shutdown requested");
+                        continue;
+                    }
+                    hyperv_ic_resources::shutdown::ShutdownProgress::ChannelClosed => {
+                        // Keep the fallback armed until the VM halts.
+                        tracing::info!("guest is shutting down");
+                        continue;
+                    }
+                    hyperv_ic_resources::shutdown::ShutdownProgress::Forced => {
+                        tracing::warn!("guest did not shut down in time, forced");
+                    }
+                    hyperv_ic_resources::shutdown::ShutdownProgress::Cancelled => {}
+                }
+                shutdown_fallback = None;
+                continue;
+            }
         };
 
         fn inspect_obj<'a>(
@@ -2778,7 +3258,8 @@ fn inspect_obj<'a>(
                     resp.field("mesh", mesh)
                         .field("vm", vm_worker)
                         .field("vnc", vnc_worker)
-                        .field("gdb", gdb_worker);
+                        .field("gdb", gdb_worker)
+                        .field_mut("trace_filter", &mut tracing_init::TraceFilter);
                 }
                 InspectTarget::Paravisor => {
                     diag_inspector.inspect_mut(req);
@@ -2786,6 +3267,25 @@ fn inspect_obj<'a>(
             })
         }
 
+        async fn inspect_update(element: &str, value: &str, obj: impl InspectMut) {
+            let value = async {
+                let update = inspect::update(element, value, obj);
+                let value = CancelContext::new()
+                    .with_timeout(Duration::from_secs(1))
+                    .until_cancelled(update)
+                    .await??;
+                anyhow::Ok(value)
+            }
+            .await;
+            match value {
+                Ok(node) => match &node.kind {
+                    inspect::ValueKind::String(s) => println!("{s}"),
+                    _ => println!("{:#}", node),
+                },
+                Err(err) => println!("error: {:#}", err),
+            }
+        }
+
         fn state_change<U: 'static + Send>(
             driver: impl Spawn,
             vm_rpc: &mesh::Sender<VmRpc>,
@@ -2848,6 +3348,95 @@ fn state_change<U: 'static + Send>(
                     StateChange::PulseSaveRestore,
                 );
             }
+            InteractiveCommand::Snapshot { path } => {
+                let vm_rpc = vm_rpc.clone();
+                let r = async move {
+                    let file = fs_err::File::create(path)?;
+                    vm_rpc
+                        .call_failable(VmRpc::SaveSnapshot, file.into())
+                        .await?;
+                    Ok(())
+                }
+                .map(|r| Ok(StateChange::Snapshot(r)));
+                if state_change_task.is_some() {
+                    tracing::error!("state change already in progress");
+                } else {
+                    state_change_task = Some(driver.spawn("state-change", r));
+                }
+            }
+            InteractiveCommand::Migrate(cmd) => match cmd {
+                MigrateCommand::Start { address } => {
+                    if state_change_task.is_some() {
+                        tracing::error!("state change already in progress");
+                    } else {
+                        match TcpStream::connect(&address)
+                            .and_then(|stream| Ok((stream.try_clone()?, stream)))
+                        {
+                            Ok((cancel_stream, stream)) => {
+                                let (progress_send, progress) = mesh::channel();
+                                migration = Some(Migration {
+                                    stream: cancel_stream,
+                                    progress,
+                                    last_progress: None,
+                                });
+                                let rpc = vm_rpc.call_failable(
+                                    VmRpc::Migrate,
+                                    MigrateParams {
+                                        stream,
+                                        progress: progress_send,
+                                    },
+                                );
+                                state_change_task =
+                                    Some(driver.spawn("state-change", async move {
+                                        Ok(StateChange::Migrate(
+                                            rpc.await.map_err(anyhow::Error::from),
+                                        ))
+                                    }));
+                            }
+                            Err(err) => tracing::error!(
+                                error = &err as &dyn std::error::Error,
+                                %address,
+                                "failed to connect to migration destination"
+                            ),
+                        }
+                    }
+                }
+                MigrateCommand::Status => {
+                    if let Some(migration) = &mut migration {
+                        while let Ok(progress) = migration.progress.try_recv() {
+                            migration.last_progress = Some(progress);
+                        }
+                        match migration.last_progress {
+                            Some(MigrationProgress {
+                                pass,
+                                transferred,
+                                total,
+                            }) => {
+                                println!(
+                                    "pass {pass}: transferred {} of {} MB",
+                                    transferred >> 20,
+                                    total >> 20
+                                );
+                            }
+                            None => println!("starting dirty page tracking"),
+                        }
+                    } else {
+                        println!("no migration in progress");
+                    }
+                }
+                MigrateCommand::Cancel => {
+                    if let Some(migration) = &migration {
+                        if let Err(err) = migration.stream.shutdown(Shutdown::Both) {
+                            tracing::error!(
+                                error = &err as &dyn std::error::Error,
+                                "failed to cancel migration"
+                            );
+                        }
+                    } else {
+                        println!("no migration in progress");
+                    }
+                }
+            },
             InteractiveCommand::SchedulePulseSaveRestore { interval } => {
                 pulse_save_restore_interval = match interval {
                     Some(seconds) if seconds != 0 => Some(Duration::from_secs(seconds)),
@@ -2861,8 +3450,9 @@ fn state_change<U: 'static + Send>(
                 reboot,
                 hibernate,
                 force,
+                timeout,
             } => {
-                if pending_shutdown.is_some() {
+                if pending_shutdown.is_some() || shutdown_fallback.is_some() {
                     println!("shutdown already in progress");
                 } else if let Some(ic) = &resources.shutdown_ic {
                     let params = hyperv_ic_resources::shutdown::ShutdownParams {
@@ -2875,8 +3465,24 @@ fn state_change<U: 'static + Send>(
                         },
                         force,
                     };
-                    pending_shutdown =
-                        Some(ic.call(hyperv_ic_resources::shutdown::ShutdownRpc::Shutdown, params));
+                    if let Some(timeout) = timeout {
+                        let (cancel_send, cancel_recv) = mesh::oneshot();
+                        let progress = ic
+                            .call(
+                                hyperv_ic_resources::shutdown::ShutdownRpc::ShutdownWithFallback,
+                                hyperv_ic_resources::shutdown::FallbackShutdownParams {
+                                    shutdown: params,
+                                    timeout: Duration::from_secs(timeout),
+                                    cancel: cancel_recv,
+                                },
+                            )
+                            .await?;
+                        shutdown_fallback = Some((progress, cancel_send));
+                    } else {
+                        pending_shutdown = Some(
+                            ic.call(hyperv_ic_resources::shutdown::ShutdownRpc::Shutdown, params),
+                        );
+                    }
                 } else {
                     println!("no shutdown ic configured");
                 }
@@ -3175,23 +3781,7 @@ fn state_change<U: 'static + Send>(
                     let Some(element) = element else {
                         anyhow::bail!("must provide element for update")
                     };
-
-                    let value = async {
-                        let update = inspect::update(&element, &value, obj);
-                        let value = CancelContext::new()
-                            .with_timeout(Duration::from_secs(1))
-                            .until_cancelled(update)
-                            .await??;
-                        anyhow::Ok(value)
-                    }
-                    .await;
-                    match value {
-                        Ok(node) => match &node.kind {
-                            inspect::ValueKind::String(s) => println!("{s}"),
-                            _ => println!("{:#}", node),
-                        },
-                        Err(err) => println!("error: {:#}", err),
-                    }
+                    inspect_update(&element, &value, obj).await;
                 } else {
                     let element = element.unwrap_or_default();
                     let depth = if recursive { limit } else { Some(0) };
@@ -3209,6 +3799,25 @@ fn state_change<U: 'static + Send>(
                     println!("{:#}", node);
                 }
             }
+            InteractiveCommand::Set {
+                paravisor,
+                element,
+                value,
+            } => {
+                let obj = inspect_obj(
+                    if paravisor {
+                        InspectTarget::Paravisor
+                    } else {
+                        InspectTarget::Host
+                    },
+                    mesh,
+                    &vm_worker,
+                    vnc_worker.as_ref(),
+                    gdb_worker.as_ref(),
+                    &mut diag_inspector,
+                );
+                inspect_update(&element, &value, obj).await;
+            }
             InteractiveCommand::RestartVnc => {
                 if let Some(vnc) = &mut vnc_worker {
                     let action = async {
@@ -3309,6 +3918,7 @@ fn state_change<U: 'static + Send>(
                 tracing::info!("quitting");
                 // Work around the detached SCSI task holding up worker stop.
                 // TODO: Fix the underlying bug
+                drop(qmp_server.take());
                 resources.scsi_rpc = None;
                 resources.nvme_vtl2_rpc = None;
 
@@ -3426,6 +4036,119 @@ fn state_change<U: 'static + Send>(
                     eprintln!("error: {err:#}");
                 }
             }
+            InteractiveCommand::Watch(command) => {
+                let result = match command {
+                    WatchCommand::Add {
+                        gpa,
+                        size,
+                        read,
+                        write,
+                        break_on_hit,
+                    } => {
+                        let both = !read && !write;
+                        vm_rpc
+                            .call_failable(
+                                VmRpc::AddWatchpoint,
+                                WatchpointParams {
+                                    gpa,
+                                    len: size,
+                                    read: read || both,
+                                    write: write || both,
+                                    break_on_hit,
+                                },
+                            )
+                            .await
+                            .map(|id| println!("watchpoint {id}"))
+                    }
+                    WatchCommand::Remove { id } => {
+                        vm_rpc.call_failable(VmRpc::RemoveWatchpoint, id).await
+                    }
+                };
+                if let Err(err) = result {
+                    eprintln!("error: {err:#}");
+                }
+            }
+            InteractiveCommand::Vss(command) => {
+                let Some(vss) = &resources.vss_ic else {
+                    eprintln!("error: no vss ic configured");
+                    continue;
+                };
+                let scsi = resources.scsi_rpc.as_ref();
+                let result = match command {
+                    VssCommand::Check => vss
+                        .call_failable(hyperv_ic_resources::vss::VssRpc::CheckHotBackup, ())
+                        .await
+                        .map_err(anyhow::Error::from),
+                    VssCommand::Freeze => vss_freeze(vss, scsi).await,
+                    VssCommand::Thaw => vss_thaw(vss, scsi).await,
+                };
+                if let Err(err) = result {
+                    eprintln!("error: {err:#}");
+                }
+            }
+            InteractiveCommand::Balloon { target } => {
+                let Some(balloon) = &resources.balloon else {
+                    eprintln!("error: no balloon configured");
+                    continue;
+                };
+                let result = async {
+                    if let Some(target) = target {
+                        balloon
+                            .call(virtio_resources::balloon::BalloonRpc::SetTarget, target)
+                            .await?;
+                    }
+                    let status = balloon
+                        .call(virtio_resources::balloon::BalloonRpc::Status, ())
+                        .await?;
+                    println!(
+                        "target: {} MB, actual: {} MB",
+                        status.target >> 20,
+                        status.actual >> 20
+                    );
+                    for stat in &status.stats {
+                        match stat.name() {
+                            Some(name) => println!("{name}: {}", stat.value),
+                            None => println!("{}: {}", stat.tag, stat.value),
+                        }
+                    }
+                    anyhow::Ok(())
+                };
+                if let Err(err) = result.await {
+                    eprintln!("error: {err:#}");
+                }
+            }
+            InteractiveCommand::Dm { target } => {
+                let Some(dm) = &resources.dynamic_memory else {
+                    eprintln!("error: dynamic memory not configured");
+                    continue;
+                };
+                let result = async {
+                    if let Some(target) = target {
+                        dm.call(hyperv_dm_resources::DynamicMemoryRpc::SetTarget, target)
+                            .await?;
+                    }
+                    let status = dm
+                        .call(hyperv_dm_resources::DynamicMemoryRpc::Status, ())
+                        .await?;
+                    println!(
+                        "target: {} MB, current: {} MB, ballooned: {} MB, hot added: {} MB",
+                        status.target >> 20,
+                        status.current >> 20,
+                        status.ballooned >> 20,
+                        status.hot_added >> 20
+                    );
+                    if let Some(committed) = status.guest_committed {
+                        println!("guest committed: {} MB", committed >> 20);
+                    }
+                    if let Some(available) = status.guest_available {
+                        println!("guest available: {} MB", available >> 20);
+                    }
+                    anyhow::Ok(())
+                };
+                if let Err(err) = result.await {
+                    eprintln!("error: {err:#}");
+                }
+            }
             InteractiveCommand::Input { .. } | InteractiveCommand::InputMode => unreachable!(),
         }
     }
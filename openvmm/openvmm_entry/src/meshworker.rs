@@ -19,6 +19,9 @@
 pub(crate) fn run_vmm_mesh_host() -> anyhow::Result<()> {
     try_run_mesh_host("openvmm", async |params: MeshHostParams| {
         params.runner.run(RegisteredWorkers).await;
+        // The worker process exits without returning to `openvmm_main`, so
+        // flush tracing output here.
+        crate::tracing_init::shutdown_tracing();
         Ok(())
     })
 }
@@ -65,11 +68,13 @@ pub async fn make_host(
 
         let host = if let Some(mesh) = &self.mesh {
             let (host, runner) = mesh_worker::worker_host();
-            mesh.launch_host(
-                ProcessConfig::new(name).stderr(log_file),
-                MeshHostParams { runner },
-            )
-            .await?;
+            let name = name.into();
+            #[cfg(feature = "otel")]
+            let env = crate::tracing_init::otel::worker_env(&name);
+            let config = ProcessConfig::new(name).stderr(log_file);
+            #[cfg(feature = "otel")]
+            let config = config.env(env);
+            mesh.launch_host(config, MeshHostParams { runner }).await?;
             host
         } else {
             self.local_host.clone()
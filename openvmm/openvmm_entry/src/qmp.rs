@@ -0,0 +1,904 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A server for a subset of the QEMU Machine Protocol (QMP), so that tooling
+//! written for QEMU can drive openvmm.
+//!
+//! The supported commands are listed in [`COMMANDS`]. Disks are attached the
+//! way QEMU does it: `blockdev-add` names a backing file, and `device_add`
+//! attaches it to the SCSI controller as a `scsi-hd` or `scsi-cd` device.
+//! Commands that openvmm cannot implement, such as `blockdev-snapshot`, fail
+//! with a `GenericError` rather than `CommandNotFound`, so that clients can
+//! tell the difference.
+
+use anyhow::Context;
+use futures::AsyncBufReadExt;
+use futures::AsyncReadExt;
+use futures::AsyncWriteExt;
+use futures::FutureExt;
+use futures::StreamExt;
+use futures::io::BufReader;
+use futures_concurrency::stream::Merge;
+use mesh::rpc::RpcSend;
+use openvmm_defs::rpc::VmEvent;
+use openvmm_defs::rpc::VmRpc;
+use openvmm_helpers::disk::open_disk_type;
+use pal_async::DefaultDriver;
+use pal_async::socket::PolledSocket;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use parking_lot::Mutex;
+use scsidisk_resources::SimpleScsiDiskHandle;
+use scsidisk_resources::SimpleScsiDvdHandle;
+use serde::Deserialize;
+use serde_json::Value;
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+use storvsp_resources::ScsiControllerRequest;
+use storvsp_resources::ScsiDeviceAndPath;
+use storvsp_resources::ScsiPath;
+use unix_socket::UnixListener;
+use unix_socket::UnixStream;
+use vm_resource::IntoResource;
+use vmcore::guest_event::GuestEvent;
+use vmm_core_defs::HaltReason;
+
+/// The commands this server implements.
+const COMMANDS: &[&str] = &[
+    "qmp_capabilities",
+    "query-commands",
+    "query-version",
+    "query-status",
+    "stop",
+    "cont",
+    "system_reset",
+    "system_powerdown",
+    "blockdev-add",
+    "blockdev-del",
+    "blockdev-snapshot",
+    "device_add",
+    "device_del",
+];
+
+/// The handles the QMP server uses to control the VM.
+pub(crate) struct QmpResources {
+    pub vm_rpc: mesh::Sender<VmRpc>,
+    pub scsi_rpc: Option<mesh::Sender<ScsiControllerRequest>>,
+    pub shutdown_ic: Option<mesh::Sender<hyperv_ic_resources::shutdown::ShutdownRpc>>,
+    /// Whether the VM is running when the server starts.
+    pub running: bool,
+}
+
+struct Qmp {
+    resources: QmpResources,
+    state: Mutex<QmpState>,
+}
+
+struct QmpState {
+    status: RunStatus,
+    block_nodes: HashMap<String, BlockNode>,
+    devices: HashMap<String, Device>,
+}
+
+/// The QMP `RunState` of the VM.
+#[derive(Copy, Clone)]
+enum RunStatus {
+    Running,
+    Paused,
+    Shutdown,
+    Debug,
+    InternalError,
+}
+
+impl RunStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            RunStatus::Running => "running",
+            RunStatus::Paused => "paused",
+            RunStatus::Shutdown => "shutdown",
+            RunStatus::Debug => "debug",
+            RunStatus::InternalError => "internal-error",
+        }
+    }
+
+    /// Returns the status after `event`.
+    fn after(self, event: &VmEvent) -> Self {
+        match event {
+            VmEvent::Resumed => RunStatus::Running,
+            // Pausing a halted VM leaves it halted.
+            VmEvent::Paused => match self {
+                RunStatus::Running => RunStatus::Paused,
+                status => status,
+            },
+            // Resetting a halted VM leaves it paused.
+            VmEvent::Reset => match self {
+                RunStatus::Running => RunStatus::Running,
+                _ => RunStatus::Paused,
+            },
+            VmEvent::Halted(reason) => match reason {
+                HaltReason::PowerOff | HaltReason::Hibernate => RunStatus::Shutdown,
+                HaltReason::Reset => RunStatus::Paused,
+                HaltReason::DebugBreak { .. }
+                | HaltReason::SingleStep { .. }
+                | HaltReason::HwBreakpoint { .. } => RunStatus::Debug,
+                HaltReason::TripleFault { .. } => RunStatus::InternalError,
+            },
+            VmEvent::BootStarted | VmEvent::FirmwareHandoff | VmEvent::Guest(_) => self,
+        }
+    }
+}
+
+struct BlockNode {
+    path: PathBuf,
+    read_only: bool,
+}
+
+struct Device {
+    node_name: String,
+    path: ScsiPath,
+}
+
+struct QmpError {
+    class: &'static str,
+    desc: String,
+}
+
+impl QmpError {
+    fn generic(desc: impl Into<String>) -> Self {
+        Self {
+            class: "GenericError",
+            desc: desc.into(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for QmpError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::generic(format!("{err:#}"))
+    }
+}
+
+impl From<serde_json::Error> for QmpError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::generic(format!("invalid arguments: {err}"))
+    }
+}
+
+#[derive(Deserialize)]
+struct Command {
+    execute: String,
+    #[serde(default)]
+    arguments: Value,
+    id: Option<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct BlockdevOptions {
+    driver: String,
+    node_name: Option<String>,
+    filename: Option<PathBuf>,
+    file: Option<BlockdevRef>,
+    #[serde(default)]
+    read_only: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BlockdevRef {
+    Node(String),
+    Options(Box<BlockdevOptions>),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct BlockdevDel {
+    node_name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct DeviceAdd {
+    driver: String,
+    id: String,
+    drive: String,
+    #[serde(default)]
+    lun: u8,
+    #[serde(default)]
+    scsi_id: u8,
+    #[serde(default)]
+    channel: u8,
+}
+
+#[derive(Deserialize)]
+struct DeviceDel {
+    id: String,
+}
+
+/// Runs the QMP server on `listener` until the returned future is dropped.
+pub(crate) async fn run_qmp_server(
+    driver: DefaultDriver,
+    listener: UnixListener,
+    resources: QmpResources,
+) -> anyhow::Result<()> {
+    let mut listener = PolledSocket::new(&driver, listener)?;
+    let (event_send, mut events) = mesh::channel();
+    resources
+        .vm_rpc
+        .call(VmRpc::SubscribeEvents, event_send)
+        .await
+        .context("failed to subscribe to vm events")?;
+
+    let qmp = Arc::new(Qmp {
+        state: Mutex::new(QmpState {
+            status: if resources.running {
+                RunStatus::Running
+            } else {
+                RunStatus::Paused
+            },
+            block_nodes: HashMap::new(),
+            devices: HashMap::new(),
+        }),
+        resources,
+    });
+
+    let mut connections = HashMap::<u64, (mesh::Sender<VmEvent>, Task<()>)>::new();
+    let mut next_id = 0;
+    let (closed_send, mut closed) = mesh::channel();
+    loop {
+        futures::select! { // merge semantics
+            r = listener.accept().fuse() => {
+                let (stream, _) = r.context("failed to accept qmp connection")?;
+                let socket = PolledSocket::new(&driver, stream)?;
+                let (send, recv) = mesh::channel();
+                let id = next_id;
+                next_id += 1;
+                let task = driver.spawn("qmp-connection", {
+                    let qmp = qmp.clone();
+                    let closed_send = closed_send.clone();
+                    async move {
+                        if let Err(err) = qmp.serve(socket, recv).await {
+                            tracing::warn!(
+                                error = err.as_ref() as &dyn std::error::Error,
+                                "qmp connection failed"
+                            );
+                        }
+                        closed_send.send(id);
+                    }
+                });
+                connections.insert(id, (send, task));
+            }
+            event = events.select_next_some() => {
+                {
+                    let mut state = qmp.state.lock();
+                    state.status = state.status.after(&event);
+                }
+                for (send, _) in connections.values() {
+                    send.send(event.clone());
+                }
+            }
+            id = closed.select_next_some() => {
+                connections.remove(&id);
+            }
+        }
+    }
+}
+
+impl Qmp {
+    async fn serve(
+        &self,
+        socket: PolledSocket<UnixStream>,
+        events: mesh::Receiver<VmEvent>,
+    ) -> anyhow::Result<()> {
+        enum Input {
+            Line(std::io::Result<String>),
+            Event(VmEvent),
+            Closed,
+        }
+
+        let (reader, mut writer) = socket.split();
+        write_message(
+            &mut writer,
+            &json!({
+                "QMP": {
+                    "version": version(),
+                    "capabilities": [],
+                }
+            }),
+        )
+        .await?;
+
+        let lines = BufReader::new(reader)
+            .lines()
+            .map(Input::Line)
+            .chain(futures::stream::iter([Input::Closed]));
+        let mut inputs = (lines, events.map(Input::Event)).merge();
+
+        let mut negotiated = false;
+        while let Some(input) = inputs.next().await {
+            match input {
+                Input::Line(line) => {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let (id, result, events) = match serde_json::from_str::<Command>(&line) {
+                        Ok(command) => {
+                            let (result, events) = self.execute(&command, &mut negotiated).await;
+                            (command.id, result, events)
+                        }
+                        Err(err) => (
+                            None,
+                            Err(QmpError::generic(format!("invalid command: {err}"))),
+                            Vec::new(),
+                        ),
+                    };
+                    let mut response = match result {
+                        Ok(value) => json!({ "return": value }),
+                        Err(err) => json!({ "error": { "class": err.class, "desc": err.desc } }),
+                    };
+                    if let Some(id) = id {
+                        response["id"] = id;
+                    }
+                    write_message(&mut writer, &response).await?;
+                    for event in events {
+                        write_message(&mut writer, &event).await?;
+                    }
+                }
+                Input::Event(event) => {
+                    if negotiated {
+                        if let Some(event) = vm_event(&event) {
+                            write_message(&mut writer, &event).await?;
+                        }
+                    }
+                }
+                Input::Closed => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes a command, returning its result and any events to send after
+    /// the response.
+    async fn execute(
+        &self,
+        command: &Command,
+        negotiated: &mut bool,
+    ) -> (Result<Value, QmpError>, Vec<Value>) {
+        let mut events = Vec::new();
+        let r = if command.execute == "qmp_capabilities" {
+            *negotiated = true;
+            Ok(json!({}))
+        } else if !*negotiated {
+            Err(QmpError {
+                class: "CommandNotFound",
+                desc: "Expecting capabilities negotiation with 'qmp_capabilities'".into(),
+            })
+        } else {
+            self.execute_negotiated(command, &mut events).await
+        };
+        (r, events)
+    }
+
+    async fn execute_negotiated(
+        &self,
+        command: &Command,
+        events: &mut Vec<Value>,
+    ) -> Result<Value, QmpError> {
+        let arguments = || command.arguments.clone();
+        match command.execute.as_str() {
+            "query-commands" => Ok(COMMANDS
+                .iter()
+                .map(|name| json!({ "name": name }))
+                .collect()),
+            "query-version" => Ok(version()),
+            "query-status" => {
+                let status = self.state.lock().status;
+                Ok(json!({
+                    "running": matches!(status, RunStatus::Running),
+                    "singlestep": false,
+                    "status": status.name(),
+                }))
+            }
+            "stop" => {
+                self.resources
+                    .vm_rpc
+                    .call(VmRpc::Pause, ())
+                    .await
+                    .context("pause failed")?;
+                self.state.lock().status = RunStatus::Paused;
+                Ok(json!({}))
+            }
+            "cont" => {
+                self.resources
+                    .vm_rpc
+                    .call(VmRpc::Resume, ())
+                    .await
+                    .context("resume failed")?;
+                self.state.lock().status = RunStatus::Running;
+                Ok(json!({}))
+            }
+            "system_reset" => {
+                self.resources
+                    .vm_rpc
+                    .call_failable(VmRpc::Reset, ())
+                    .await
+                    .context("reset failed")?;
+                Ok(json!({}))
+            }
+            "system_powerdown" => {
+                self.powerdown().await?;
+                Ok(json!({}))
+            }
+            "blockdev-add" => {
+                let options = serde_json::from_value::<BlockdevOptions>(arguments())?;
+                let node_name = options
+                    .node_name
+                    .clone()
+                    .ok_or_else(|| QmpError::generic("node-name is required"))?;
+                let node = self.resolve_blockdev(options)?;
+                let mut state = self.state.lock();
+                if state.block_nodes.contains_key(&node_name) {
+                    return Err(QmpError::generic(format!(
+                        "duplicate node name '{node_name}'"
+                    )));
+                }
+                state.block_nodes.insert(node_name, node);
+                Ok(json!({}))
+            }
+            "blockdev-del" => {
+                let args = serde_json::from_value::<BlockdevDel>(arguments())?;
+                let mut state = self.state.lock();
+                if state
+                    .devices
+                    .values()
+                    .any(|d| d.node_name == args.node_name)
+                {
+                    return Err(QmpError::generic(format!(
+                        "node '{}' is in use",
+                        args.node_name
+                    )));
+                }
+                state.block_nodes.remove(&args.node_name).ok_or_else(|| {
+                    QmpError::generic(format!("cannot find node '{}'", args.node_name))
+                })?;
+                Ok(json!({}))
+            }
+            "blockdev-snapshot" => Err(QmpError::generic(
+                "blockdev-snapshot is not supported: disks cannot be layered at runtime",
+            )),
+            "device_add" => {
+                let args = serde_json::from_value::<DeviceAdd>(arguments())?;
+                self.device_add(args).await?;
+                Ok(json!({}))
+            }
+            "device_del" => {
+                let args = serde_json::from_value::<DeviceDel>(arguments())?;
+                self.device_del(&args.id).await?;
+                events.push(event("DEVICE_DELETED", json!({ "device": args.id })));
+                Ok(json!({}))
+            }
+            name => Err(QmpError {
+                class: "CommandNotFound",
+                desc: format!("The command {name} has not been found"),
+            }),
+        }
+    }
+
+    async fn powerdown(&self) -> anyhow::Result<()> {
+        use hyperv_ic_resources::shutdown::ShutdownParams;
+        use hyperv_ic_resources::shutdown::ShutdownResult;
+        use hyperv_ic_resources::shutdown::ShutdownRpc;
+        use hyperv_ic_resources::shutdown::ShutdownType;
+
+        let ic = self
+            .resources
+            .shutdown_ic
+            .as_ref()
+            .context("no shutdown ic configured")?;
+        let result = ic
+            .call(
+                ShutdownRpc::Shutdown,
+                ShutdownParams {
+                    shutdown_type: ShutdownType::PowerOff,
+                    force: false,
+                },
+            )
+            .await?;
+        match result {
+            ShutdownResult::Ok => Ok(()),
+            result => anyhow::bail!("shutdown failed: {result:?}"),
+        }
+    }
+
+    fn resolve_blockdev(&self, options: BlockdevOptions) -> Result<BlockNode, QmpError> {
+        match options.driver.as_str() {
+            "file" | "host_device" => Ok(BlockNode {
+                path: options
+                    .filename
+                    .ok_or_else(|| QmpError::generic("filename is required"))?,
+                read_only: options.read_only,
+            }),
+            "raw" => {
+                let file = options
+                    .file
+                    .ok_or_else(|| QmpError::generic("file is required"))?;
+                let node = match file {
+                    BlockdevRef::Node(name) => {
+                        let state = self.state.lock();
+                        let node = state.block_nodes.get(&name).ok_or_else(|| {
+                            QmpError::generic(format!("cannot find node '{name}'"))
+                        })?;
+                        BlockNode {
+                            path: node.path.clone(),
+                            read_only: node.read_only,
+                        }
+                    }
+                    BlockdevRef::Options(options) => self.resolve_blockdev(*options)?,
+                };
+                Ok(BlockNode {
+                    path: node.path,
+                    read_only: node.read_only || options.read_only,
+                })
+            }
+            driver => Err(QmpError::generic(format!(
+                "unsupported block driver '{driver}'"
+            ))),
+        }
+    }
+
+    async fn device_add(&self, args: DeviceAdd) -> anyhow::Result<()> {
+        let scsi = self
+            .resources
+            .scsi_rpc
+            .as_ref()
+            .context("no scsi controller")?;
+        let path = ScsiPath {
+            path: args.channel,
+            target: args.scsi_id,
+            lun: args.lun,
+        };
+        let (disk_path, read_only) = {
+            let state = self.state.lock();
+            if state.devices.contains_key(&args.id) {
+                anyhow::bail!("duplicate device id '{}'", args.id);
+            }
+            let node = state
+                .block_nodes
+                .get(&args.drive)
+                .with_context(|| format!("cannot find node '{}'", args.drive))?;
+            (node.path.clone(), node.read_only)
+        };
+        let disk = open_disk_type(&disk_path, read_only)
+            .with_context(|| format!("failed to open {}", disk_path.display()))?;
+        let device = match args.driver.as_str() {
+            "scsi-hd" => SimpleScsiDiskHandle {
+                disk,
+                read_only,
+                parameters: Default::default(),
+            }
+            .into_resource(),
+            "scsi-cd" => SimpleScsiDvdHandle {
+                media: Some(disk),
+                requests: None,
+            }
+            .into_resource(),
+            driver => anyhow::bail!("unsupported device driver '{driver}'"),
+        };
+        scsi.call_failable(
+            ScsiControllerRequest::AddDevice,
+            ScsiDeviceAndPath { path, device },
+        )
+        .await?;
+        self.state.lock().devices.insert(
+            args.id,
+            Device {
+                node_name: args.drive,
+                path,
+            },
+        );
+        Ok(())
+    }
+
+    async fn device_del(&self, id: &str) -> Result<(), QmpError> {
+        let scsi = self
+            .resources
+            .scsi_rpc
+            .as_ref()
+            .ok_or_else(|| QmpError::generic("no scsi controller"))?;
+        let path = self
+            .state
+            .lock()
+            .devices
+            .get(id)
+            .map(|device| device.path)
+            .ok_or_else(|| QmpError {
+                class: "DeviceNotFound",
+                desc: format!("Device '{id}' not found"),
+            })?;
+        scsi.call_failable(ScsiControllerRequest::RemoveDevice, path)
+            .await
+            .map_err(anyhow::Error::from)?;
+        self.state.lock().devices.remove(id);
+        Ok(())
+    }
+}
+
+/// Returns the QMP event for a VM event, if there is one.
+fn vm_event(vm_event: &VmEvent) -> Option<Value> {
+    let e = match vm_event {
+        VmEvent::Resumed => event("RESUME", json!({})),
+        VmEvent::Paused => event("STOP", json!({})),
+        VmEvent::Reset => event("RESET", json!({ "guest": false, "reason": "host-qmp" })),
+        VmEvent::Halted(reason) => match reason {
+            HaltReason::PowerOff | HaltReason::Hibernate => event(
+                "SHUTDOWN",
+                json!({ "guest": true, "reason": "guest-shutdown" }),
+            ),
+            // Guest-initiated resets that are not handled automatically.
+            HaltReason::Reset => event("RESET", json!({ "guest": true, "reason": "guest-reset" })),
+            HaltReason::TripleFault { .. } => event("GUEST_PANICKED", json!({ "action": "pause" })),
+            HaltReason::DebugBreak { .. }
+            | HaltReason::SingleStep { .. }
+            | HaltReason::HwBreakpoint { .. } => return None,
+        },
+        VmEvent::Guest(GuestEvent::ShutdownRequested) => event("POWERDOWN", json!({})),
+        VmEvent::Guest(GuestEvent::Crashed) => event("GUEST_PANICKED", json!({ "action": "run" })),
+        VmEvent::BootStarted
+        | VmEvent::FirmwareHandoff
+        | VmEvent::Guest(GuestEvent::HeartbeatUp) => return None,
+    };
+    Some(e)
+}
+
+fn event(name: &str, data: Value) -> Value {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    json!({
+        "event": name,
+        "data": data,
+        "timestamp": {
+            "seconds": now.as_secs(),
+            "microseconds": now.subsec_micros(),
+        },
+    })
+}
+
+fn version() -> Value {
+    json!({
+        "qemu": { "major": 0, "minor": 0, "micro": 0 },
+        "package": concat!("openvmm ", env!("CARGO_PKG_VERSION")),
+    })
+}
+
+async fn write_message(
+    writer: &mut (impl futures::AsyncWrite + Unpin),
+    message: &Value,
+) -> std::io::Result<()> {
+    let mut data = message.to_string();
+    data.push_str("\r\n");
+    writer.write_all(data.as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pal_async::async_test;
+
+    fn qmp() -> Qmp {
+        let (vm_rpc, _) = mesh::channel();
+        Qmp {
+            resources: QmpResources {
+                vm_rpc,
+                scsi_rpc: None,
+                shutdown_ic: None,
+                running: false,
+            },
+            state: Mutex::new(QmpState {
+                status: RunStatus::Paused,
+                block_nodes: HashMap::new(),
+                devices: HashMap::new(),
+            }),
+        }
+    }
+
+    fn command(execute: &str, arguments: Value) -> Command {
+        serde_json::from_value(json!({ "execute": execute, "arguments": arguments })).unwrap()
+    }
+
+    #[test]
+    fn run_status_after() {
+        let cases = [
+            (RunStatus::Paused, VmEvent::Resumed, RunStatus::Running),
+            (RunStatus::Running, VmEvent::Paused, RunStatus::Paused),
+            (RunStatus::Shutdown, VmEvent::Paused, RunStatus::Shutdown),
+            (RunStatus::Running, VmEvent::Reset, RunStatus::Running),
+            (RunStatus::Shutdown, VmEvent::Reset, RunStatus::Paused),
+            (
+                RunStatus::Running,
+                VmEvent::Halted(HaltReason::PowerOff),
+                RunStatus::Shutdown,
+            ),
+            (
+                RunStatus::Running,
+                VmEvent::Halted(HaltReason::Reset),
+                RunStatus::Paused,
+            ),
+            (
+                RunStatus::Running,
+                VmEvent::Halted(HaltReason::DebugBreak { vp: None }),
+                RunStatus::Debug,
+            ),
+            (
+                RunStatus::Running,
+                VmEvent::Halted(HaltReason::TripleFault {
+                    vp: 0,
+                    registers: None,
+                }),
+                RunStatus::InternalError,
+            ),
+            (RunStatus::Running, VmEvent::BootStarted, RunStatus::Running),
+        ];
+        for (status, event, expected) in cases {
+            assert_eq!(status.after(&event).name(), expected.name(), "{event:?}");
+        }
+    }
+
+    #[async_test]
+    async fn negotiation(_driver: DefaultDriver) {
+        let qmp = qmp();
+        let mut negotiated = false;
+        let (r, _) = qmp
+            .execute(&command("query-version", json!({})), &mut negotiated)
+            .await;
+        assert_eq!(r.err().unwrap().class, "CommandNotFound");
+
+        let (r, _) = qmp
+            .execute(&command("qmp_capabilities", json!({})), &mut negotiated)
+            .await;
+        assert!(r.is_ok());
+        assert!(negotiated);
+
+        let (r, _) = qmp
+            .execute(&command("query-version", json!({})), &mut negotiated)
+            .await;
+        assert!(r.is_ok());
+        let (r, _) = qmp
+            .execute(&command("query-foo", json!({})), &mut negotiated)
+            .await;
+        assert_eq!(r.err().unwrap().class, "CommandNotFound");
+        let (r, _) = qmp
+            .execute(&command("blockdev-snapshot", json!({})), &mut negotiated)
+            .await;
+        assert_eq!(r.err().unwrap().class, "GenericError");
+    }
+
+    #[async_test]
+    async fn blockdev(_driver: DefaultDriver) {
+        let qmp = qmp();
+        let mut events = Vec::new();
+        qmp.execute_negotiated(
+            &command(
+                "blockdev-add",
+                json!({
+                    "driver": "file",
+                    "node-name": "file0",
+                    "filename": "disk.img",
+                }),
+            ),
+            &mut events,
+        )
+        .await
+        .unwrap();
+        qmp.execute_negotiated(
+            &command(
+                "blockdev-add",
+                json!({
+                    "driver": "raw",
+                    "node-name": "disk0",
+                    "file": "file0",
+                    "read-only": true,
+                }),
+            ),
+            &mut events,
+        )
+        .await
+        .unwrap();
+        qmp.execute_negotiated(
+            &command(
+                "blockdev-add",
+                json!({
+                    "driver": "raw",
+                    "node-name": "disk1",
+                    "file": { "driver": "file", "filename": "other.img" },
+                }),
+            ),
+            &mut events,
+        )
+        .await
+        .unwrap();
+        {
+            let state = qmp.state.lock();
+            let disk0 = &state.block_nodes["disk0"];
+            assert_eq!(disk0.path, PathBuf::from("disk.img"));
+            assert!(disk0.read_only);
+            let disk1 = &state.block_nodes["disk1"];
+            assert_eq!(disk1.path, PathBuf::from("other.img"));
+            assert!(!disk1.read_only);
+        }
+
+        // Duplicate names, missing nodes and unknown drivers fail.
+        for arguments in [
+            json!({ "driver": "file", "node-name": "disk0", "filename": "x.img" }),
+            json!({ "driver": "raw", "node-name": "disk2", "file": "missing" }),
+            json!({ "driver": "qcow2", "node-name": "disk2", "file": "file0" }),
+            json!({ "driver": "file", "filename": "x.img" }),
+        ] {
+            let err = qmp
+                .execute_negotiated(&command("blockdev-add", arguments), &mut events)
+                .await
+                .err()
+                .unwrap();
+            assert_eq!(err.class, "GenericError");
+        }
+
+        qmp.execute_negotiated(
+            &command("blockdev-del", json!({ "node-name": "disk1" })),
+            &mut events,
+        )
+        .await
+        .unwrap();
+        assert!(!qmp.state.lock().block_nodes.contains_key("disk1"));
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn device_args() {
+        let args = serde_json::from_value::<DeviceAdd>(json!({
+            "driver": "scsi-hd",
+            "id": "dev0",
+            "drive": "disk0",
+        }))
+        .unwrap();
+        assert_eq!(args.driver, "scsi-hd");
+        assert_eq!(args.id, "dev0");
+        assert_eq!(args.drive, "disk0");
+        assert_eq!((args.channel, args.scsi_id, args.lun), (0, 0, 0));
+
+        let args = serde_json::from_value::<DeviceAdd>(json!({
+            "driver": "scsi-cd",
+            "id": "dev1",
+            "drive": "disk1",
+            "channel": 1,
+            "scsi-id": 2,
+            "lun": 3,
+        }))
+        .unwrap();
+        assert_eq!((args.channel, args.scsi_id, args.lun), (1, 2, 3));
+
+        assert!(serde_json::from_value::<DeviceAdd>(json!({ "driver": "scsi-hd" })).is_err());
+
+        let args = serde_json::from_value::<DeviceDel>(json!({ "id": "dev0" })).unwrap();
+        assert_eq!(args.id, "dev0");
+        assert!(serde_json::from_value::<DeviceDel>(json!({})).is_err());
+    }
+
+    #[async_test]
+    async fn device_del_unknown(_driver: DefaultDriver) {
+        let (scsi_rpc, _) = mesh::channel();
+        let mut qmp = qmp();
+        qmp.resources.scsi_rpc = Some(scsi_rpc);
+        let mut events = Vec::new();
+        let err = qmp
+            .execute_negotiated(&command("device_del", json!({ "id": "dev0" })), &mut events)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.class, "DeviceNotFound");
+        assert!(events.is_empty());
+    }
+}
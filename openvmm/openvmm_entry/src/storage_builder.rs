@@ -22,6 +22,7 @@
 use scsidisk_resources::SimpleScsiDiskHandle;
 use scsidisk_resources::SimpleScsiDvdHandle;
 use std::collections::BTreeMap;
+use std::time::Duration;
 use storvsp_resources::ScsiControllerHandle;
 use storvsp_resources::ScsiDeviceAndPath;
 use storvsp_resources::ScsiPath;
@@ -329,6 +330,8 @@ pub fn build_config(
         config: &mut Config,
         resources: &mut VmResources,
         scsi_sub_channels: u16,
+        scsi_queue_affinity: &[u32],
+        scsi_interrupt_coalescing: Option<Duration>,
     ) -> anyhow::Result<()> {
         config.ide_disks.append(&mut self.vtl0_ide_disks);
 
@@ -344,6 +347,8 @@ pub fn build_config(
                     io_queue_depth: None,
                     requests: Some(recv),
                     poll_mode_queue_depth: None,
+                    queue_affinity: scsi_queue_affinity.to_vec(),
+                    interrupt_coalescing: scsi_interrupt_coalescing,
                 }
                 .into_resource(),
             ));
@@ -368,6 +373,8 @@ pub fn build_config(
                     io_queue_depth: None,
                     requests: None,
                     poll_mode_queue_depth: None,
+                    queue_affinity: scsi_queue_affinity.to_vec(),
+                    interrupt_coalescing: scsi_interrupt_coalescing,
                 }
                 .into_resource(),
             ));
@@ -1,12 +1,19 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+#[cfg(feature = "otel")]
+pub mod otel;
+
 use anyhow::Context as _;
 use anyhow::anyhow;
 use std::io::IsTerminal;
+use std::sync::OnceLock;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::Registry;
 use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::fmt::format::Format;
 use tracing_subscriber::fmt::time::uptime;
+use tracing_subscriber::reload;
 
 #[cfg(windows)]
 const OPENVMM_PROVIDER_GUID: guid::Guid = guid::guid!("22bc55fe-2116-5adc-12fb-3fadfd7e360c");
@@ -64,9 +71,10 @@ pub fn enable_tracing() -> anyhow::Result<()> {
         .log_internal_errors(true)
         .with_writer(writer);
 
-    let sub = tracing_subscriber::Registry::default()
-        .with(fmt_layer)
-        .with(filter);
+    let (filter, filter_handle) = reload::Layer::new(filter);
+    let _ = FILTER_HANDLE.set(filter_handle);
+
+    let sub = Registry::default().with(filter).with(fmt_layer);
 
     // Enable an ETW layer on Windows.
     // TODO: include the process name and maybe a VM ID?
@@ -82,8 +90,111 @@ pub fn enable_tracing() -> anyhow::Result<()> {
         sub.with(etw)
     };
 
+    // Export spans to an OpenTelemetry collector, if configured.
+    #[cfg(feature = "otel")]
+    let sub = sub.with(otel::layer()?);
+
     sub.try_init()
         .map_err(|e| anyhow!(e).context("failed to enable tracing"))?;
 
     Ok(())
 }
+
+static FILTER_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// The trace filter for this process, in `OPENVMM_LOG` syntax. Writable via
+/// inspect to change the filter at runtime.
+pub struct TraceFilter;
+
+impl inspect::InspectMut for TraceFilter {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        if let Some(handle) = FILTER_HANDLE.get() {
+            inspect_filter(handle, req);
+        } else {
+            req.ignore();
+        }
+    }
+}
+
+/// Inspects the filter behind `handle`, replacing it on update.
+fn inspect_filter<S>(handle: &reload::Handle<EnvFilter, S>, req: inspect::Request<'_>) {
+    match req.update() {
+        Ok(req) => {
+            let result = EnvFilter::try_new(req.new_value())
+                .context("invalid filter")
+                .and_then(|filter| {
+                    let value = filter.to_string();
+                    handle.reload(filter)?;
+                    tracing::info!(filter = value.as_str(), "updated trace filter");
+                    Ok(value)
+                });
+            match result {
+                Ok(value) => req.succeed(value),
+                Err(err) => req.fail(err),
+            }
+        }
+        Err(req) => match handle.with_current(|filter| filter.to_string()) {
+            Ok(value) => req.value(value),
+            Err(_) => req.ignore(),
+        },
+    }
+}
+
+/// Flushes any tracing output that is sent asynchronously. Called before the
+/// process exits.
+pub fn shutdown_tracing() {
+    #[cfg(feature = "otel")]
+    otel::shutdown();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::inspect_filter;
+    use futures::FutureExt;
+    use tracing::Level;
+    use tracing_subscriber::EnvFilter;
+    use tracing_subscriber::Registry;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::reload;
+
+    #[test]
+    fn update_trace_filter() {
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let dispatch = tracing::Dispatch::new(Registry::default().with(filter));
+        let debug_enabled =
+            || tracing::dispatcher::with_default(&dispatch, || tracing::enabled!(Level::DEBUG));
+        let update = |value: &str| {
+            inspect::update(
+                "",
+                value,
+                inspect::adhoc_mut(|req| inspect_filter(&handle, req)),
+            )
+            .now_or_never()
+            .unwrap()
+        };
+        let current =
+            || match inspect::inspect("", inspect::adhoc_mut(|req| inspect_filter(&handle, req)))
+                .results()
+            {
+                inspect::Node::Value(inspect::Value {
+                    kind: inspect::ValueKind::String(s),
+                    ..
+                }) => s,
+                node => panic!("unexpected node {node:?}"),
+            };
+
+        assert_eq!(current(), "info");
+        assert!(!debug_enabled());
+
+        // A valid filter takes effect immediately.
+        let value = update("debug").unwrap();
+        assert!(matches!(value.kind, inspect::ValueKind::String(s) if s == "debug"));
+        assert_eq!(current(), "debug");
+        assert!(debug_enabled());
+
+        // An invalid filter is rejected, leaving the current one in place.
+        update("openvmm=bogus").unwrap_err();
+        assert_eq!(current(), "debug");
+        assert!(debug_enabled());
+    }
+}
@@ -0,0 +1,86 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Export of tracing spans to an OpenTelemetry collector.
+//!
+//! Export is enabled by setting `OPENVMM_OTLP_ENDPOINT` to the collector's
+//! OTLP/HTTP endpoint. Spans from all processes of a VM carry the same
+//! `openvmm.vm_id` resource attribute, taken from `OPENVMM_VM_ID` or
+//! generated by the main process, and an `openvmm.worker_role` attribute
+//! naming the process.
+
+use anyhow::Context as _;
+use opentelemetry::KeyValue;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use std::ffi::OsString;
+use std::sync::OnceLock;
+use tracing_subscriber::Layer;
+use tracing_subscriber::registry::LookupSpan;
+
+const ENDPOINT_ENV: &str = "OPENVMM_OTLP_ENDPOINT";
+const VM_ID_ENV: &str = "OPENVMM_VM_ID";
+const WORKER_ROLE_ENV: &str = "OPENVMM_WORKER_ROLE";
+
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+static VM_ID: OnceLock<String> = OnceLock::new();
+
+fn vm_id() -> &'static str {
+    VM_ID.get_or_init(|| {
+        std::env::var(VM_ID_ENV).unwrap_or_else(|_| guid::Guid::new_random().to_string())
+    })
+}
+
+/// Returns a layer that exports spans to the configured collector, or `None`
+/// if export is not configured.
+pub fn layer<S>() -> anyhow::Result<Option<impl Layer<S>>>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    let Ok(endpoint) = std::env::var(ENDPOINT_ENV) else {
+        return Ok(None);
+    };
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .context("failed to create OTLP span exporter")?;
+
+    let worker_role = std::env::var(WORKER_ROLE_ENV).unwrap_or_else(|_| "main".to_owned());
+    let resource = Resource::builder()
+        .with_service_name("openvmm")
+        .with_attributes([
+            KeyValue::new("openvmm.vm_id", vm_id().to_owned()),
+            KeyValue::new("openvmm.worker_role", worker_role),
+        ])
+        .build();
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    let tracer = provider.tracer("openvmm");
+    let _ = PROVIDER.set(provider);
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Returns the environment variables for a worker process launched with
+/// `role`, so that its spans are attributed to the same VM.
+pub fn worker_env(role: &str) -> [(OsString, OsString); 2] {
+    [
+        (VM_ID_ENV.into(), vm_id().into()),
+        (WORKER_ROLE_ENV.into(), role.into()),
+    ]
+}
+
+/// Exports any buffered spans and stops exporting.
+pub fn shutdown() {
+    if let Some(provider) = PROVIDER.get()
+        && let Err(err) = provider.shutdown()
+    {
+        eprintln!("failed to shut down span export: {err}");
+    }
+}
@@ -7,13 +7,14 @@
 
 use self::vmservice::nic_config::Backend;
 use crate::serial_io::bind_serial;
+use crate::tracing_init::TraceFilter;
 use anyhow::Context;
 use anyhow::anyhow;
 use anyhow::bail;
 use futures::FutureExt;
 use futures::StreamExt;
 use guid::Guid;
-use inspect::Inspect;
+use inspect::InspectMut;
 use inspect::InspectionBuilder;
 use inspect_proto::InspectResponse2;
 use inspect_proto::InspectService;
@@ -39,6 +40,7 @@
 use openvmm_defs::config::VirtioBus;
 use openvmm_defs::config::VmbusConfig;
 use openvmm_defs::config::VpciDeviceConfig;
+use openvmm_defs::rpc::VmEvent;
 use openvmm_defs::rpc::VmRpc;
 use openvmm_defs::worker::VM_WORKER;
 use openvmm_defs::worker::VmWorkerParameters;
@@ -63,6 +65,7 @@
 use vm_resource::IntoResource;
 use vm_resource::Resource;
 use vm_resource::kind::VmbusDeviceHandleKind;
+use vmcore::guest_event::GuestEvent;
 use vmm_core_defs::HaltReason;
 
 #[derive(mesh::MeshPayload)]
@@ -101,6 +104,10 @@ pub struct TtrpcWorker {
 
 pub const TTRPC_WORKER: WorkerId<Parameters> = WorkerId::new("TtrpcWorker");
 
+/// The version of the vmservice API, reported by `CapabilitiesVM`. Increment
+/// this when adding requests or fields.
+const API_VERSION: u32 = 1;
+
 impl Worker for TtrpcWorker {
     type Parameters = Parameters;
     type State = ();
@@ -259,6 +266,7 @@ struct Vm {
     worker_rpc: mesh::Sender<VmRpc>,
     scsi_rpc: Option<mesh::Sender<ScsiControllerRequest>>,
     notify_recv: Mutex<Option<mesh::Receiver<HaltReason>>>,
+    events_recv: Mutex<Option<mesh::Receiver<VmEvent>>>,
 }
 
 struct VmService {
@@ -308,6 +316,7 @@ async fn handle(&mut self, ctx: mesh::CancelContext, request: vmservice::Vm) ->
                 response.send(map_grpc(self.teardown_vm().await))
             }
             vmservice::Vm::Quit((), response) => return HandleAction::Quit(response),
+            vmservice::Vm::CapabilitiesVm((), response) => response.send(Ok(capabilities())),
             request => {
                 let vm = match &self.vm {
                     Some(vm) => vm.clone(),
@@ -325,23 +334,35 @@ async fn handle(&mut self, ctx: mesh::CancelContext, request: vmservice::Vm) ->
                         let r = Ok(self.resume_vm(&vm));
                         self.start_rpc(response, r);
                     }
+                    vmservice::Vm::ResetVm((), response) => {
+                        let r = Ok(self.reset_vm(&vm));
+                        self.start_rpc(response, r);
+                    }
+                    vmservice::Vm::SaveSnapshotVm(request, response) => {
+                        let r = self.save_snapshot_vm(&vm, request);
+                        self.start_rpc(response, r);
+                    }
                     vmservice::Vm::WaitVm((), response) => {
                         let r = self.wait_vm(ctx, vm);
                         self.start_rpc(response, r);
                     }
+                    vmservice::Vm::WaitEventsVm((), response) => {
+                        let r = self.wait_events_vm(ctx, vm);
+                        self.start_rpc(response, r);
+                    }
                     vmservice::Vm::ModifyResource(request, response) => {
                         let r = self.modify_resource(&vm, request);
                         self.start_rpc(response, r);
                     }
 
-                    r @ vmservice::Vm::CapabilitiesVm(_, _)
-                    | r @ vmservice::Vm::PropertiesVm(_, _) => {
+                    r @ vmservice::Vm::PropertiesVm(_, _) => {
                         r.fail(grpc_error(anyhow!("not supported")))
                     }
 
                     vmservice::Vm::CreateVm(_, _)
                     | vmservice::Vm::TeardownVm(_, _)
-                    | vmservice::Vm::Quit(_, _) => unreachable!(),
+                    | vmservice::Vm::Quit(_, _)
+                    | vmservice::Vm::CapabilitiesVm(_, _) => unreachable!(),
                 };
             }
         }
@@ -359,6 +380,16 @@ async fn handle_inspect(&mut self, ctx: mesh::CancelContext, request: InspectSer
         }
     }
 
+    /// Returns the root of the tree exposed by `InspectService`: the VM
+    /// worker's tree, plus this process's writable `trace_filter`.
+    fn inspect_root(&self) -> impl InspectMut + '_ {
+        inspect::adhoc_mut(|req| {
+            req.respond()
+                .merge(&self.worker_handle)
+                .field_mut("trace_filter", &mut TraceFilter);
+        })
+    }
+
     fn inspect(
         &self,
         ctx: mesh::CancelContext,
@@ -366,11 +397,7 @@ fn inspect(
     ) -> impl Future<Output = anyhow::Result<InspectResponse2>> + use<> {
         let mut inspection = InspectionBuilder::new(&request.path)
             .depth(Some(request.depth as usize))
-            .inspect(inspect::adhoc(|req| {
-                if let Some(worker) = &self.worker_handle {
-                    worker.inspect(req)
-                }
-            }));
+            .inspect(self.inspect_root());
         async move {
             let _ = ctx
                 .with_timeout(Duration::from_secs(1))
@@ -387,15 +414,7 @@ fn update(
         ctx: mesh::CancelContext,
         request: inspect_proto::UpdateRequest,
     ) -> impl Future<Output = anyhow::Result<UpdateResponse2>> + use<> {
-        let update = inspect::update(
-            &request.path,
-            &request.value,
-            inspect::adhoc(|req| {
-                if let Some(worker) = &self.worker_handle {
-                    worker.inspect(req)
-                }
-            }),
-        );
+        let update = inspect::update(&request.path, &request.value, self.inspect_root());
         async move {
             let new_value = ctx
                 .with_timeout(Duration::from_secs(1))
@@ -413,6 +432,14 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             bail!("VM already created");
         }
 
+        let snapshot = if request.restore_snapshot_path.is_empty() {
+            None
+        } else {
+            Some(File::open(&request.restore_snapshot_path).with_context(|| {
+                format!("failed to open snapshot: {}", request.restore_snapshot_path)
+            })?)
+        };
+
         let load_mode = match req_config
             .boot_config
             .context("missing boot configuration")?
@@ -472,6 +499,10 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
                 pci_ecam_gaps: vec![],
                 pci_mmio_gaps: vec![],
                 prefetch_memory: false,
+                huge_page_size: None,
+                dynamic_memory: None,
+                numa_nodes: Vec::new(),
+                watchpoints: false,
             },
             chipset: chipset.chipset,
             processor_topology: ProcessorTopologyConfig {
@@ -484,6 +515,7 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
                 enable_smt: None,
                 arch: Default::default(),
             },
+            vp_pinning: None,
             hypervisor: HypervisorConfig {
                 with_hv: true,
                 ..Default::default()
@@ -497,7 +529,10 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             virtio_devices: vec![],
             vmbus: Some(VmbusConfig::default()),
             vtl2_vmbus: None,
-            vmbus_devices: vec![],
+            vmbus_devices: vec![(
+                DeviceVtl::Vtl0,
+                hyperv_ic_resources::heartbeat::HeartbeatIcHandle.into_resource(),
+            )],
             #[cfg(windows)]
             vpci_resources: vec![],
             vmgs: None,
@@ -529,6 +564,8 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
                         io_queue_depth: None,
                         requests: Some(recv),
                         poll_mode_queue_depth: None,
+                        queue_affinity: Vec::new(),
+                        interrupt_coalescing: None,
                     }
                     .into_resource(),
                 ));
@@ -585,16 +622,24 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
                     hypervisor: None,
                     cfg: config,
                     saved_state: None,
+                    snapshot,
+                    incoming_migration: None,
                     rpc: recv,
                     notify: notify_send,
                 },
             )
             .await?;
 
+        let (event_send, events_recv) = mesh::channel();
+        send.call(VmRpc::SubscribeEvents, event_send)
+            .await
+            .context("failed to subscribe to vm events")?;
+
         self.worker_handle = Some(worker);
         self.vm = Some(Arc::new(Vm {
             scsi_rpc,
             notify_recv: Mutex::new(Some(notify_recv)),
+            events_recv: Mutex::new(Some(events_recv)),
             worker_rpc: send,
         }));
         Ok(())
@@ -618,6 +663,22 @@ fn resume_vm(&mut self, vm: &Vm) -> impl Future<Output = anyhow::Result<()>> + u
         async move { recv.await.map(drop).context("resume failed") }
     }
 
+    fn reset_vm(&mut self, vm: &Vm) -> impl Future<Output = anyhow::Result<()>> + use<> {
+        let recv = vm.worker_rpc.call_failable(VmRpc::Reset, ());
+        async move { recv.await.context("reset failed") }
+    }
+
+    fn save_snapshot_vm(
+        &mut self,
+        vm: &Vm,
+        request: vmservice::SaveSnapshotVmRequest,
+    ) -> anyhow::Result<impl Future<Output = anyhow::Result<()>> + use<>> {
+        let file = File::create(&request.path)
+            .with_context(|| format!("failed to create snapshot file: {}", request.path))?;
+        let recv = vm.worker_rpc.call_failable(VmRpc::SaveSnapshot, file);
+        Ok(async move { recv.await.context("snapshot failed") })
+    }
+
     fn wait_vm(
         &mut self,
         mut ctx: mesh::CancelContext,
@@ -643,6 +704,38 @@ fn wait_vm(
         })
     }
 
+    fn wait_events_vm(
+        &mut self,
+        mut ctx: mesh::CancelContext,
+        vm: Arc<Vm>,
+    ) -> anyhow::Result<impl Future<Output = anyhow::Result<vmservice::WaitEventsVmResponse>> + use<>>
+    {
+        let mut events_recv = vm
+            .events_recv
+            .lock()
+            .take()
+            .context("wait events already in flight")?;
+        Ok(async move {
+            let r = futures::select! { // race semantics
+                r = events_recv.recv().fuse() => {
+                    r.context("VM worker communication failure")
+                }
+                reason = ctx.cancelled().fuse() => {
+                    Err(anyhow::Error::new(reason))
+                }
+            };
+            let r = r.map(|first| {
+                let mut events = vec![vm_event(first)];
+                while let Ok(event) = events_recv.try_recv() {
+                    events.push(vm_event(event));
+                }
+                vmservice::WaitEventsVmResponse { events }
+            });
+            *vm.events_recv.lock() = Some(events_recv);
+            r
+        })
+    }
+
     fn modify_resource(
         &mut self,
         vm: &Vm,
@@ -680,11 +773,28 @@ fn modify_resource(
                 }
             }
             Resource::NicConfig(nic) => {
-                if request.r#type != vmservice::ModifyType::Add as i32 {
-                    anyhow::bail!("not supported yet");
+                if request.r#type == vmservice::ModifyType::Add as i32 {
+                    let config = parse_nic_config(nic)?;
+                    let recv = vm.worker_rpc.call_failable(VmRpc::AddVmbusDevice, config);
+                    Ok(async move { recv.await.map_err(anyhow::Error::from) }.boxed())
+                } else if request.r#type == vmservice::ModifyType::Remove as i32 {
+                    let instance_id = nic.nic_id.parse().context("invalid instance ID")?;
+                    let recv = vm
+                        .worker_rpc
+                        .call_failable(VmRpc::RemoveVmbusDevice, (DeviceVtl::Vtl0, instance_id));
+                    Ok(async move { recv.await.map_err(anyhow::Error::from) }.boxed())
+                } else {
+                    anyhow::bail!("unsupported request type {}", request.r#type);
+                }
+            }
+            Resource::VmbusDevice(device) => {
+                if request.r#type != vmservice::ModifyType::Remove as i32 {
+                    anyhow::bail!("unsupported request type {}", request.r#type);
                 }
-                let config = parse_nic_config(nic)?;
-                let recv = vm.worker_rpc.call_failable(VmRpc::AddVmbusDevice, config);
+                let instance_id = device.instance_id.parse().context("invalid instance ID")?;
+                let recv = vm
+                    .worker_rpc
+                    .call_failable(VmRpc::RemoveVmbusDevice, (DeviceVtl::Vtl0, instance_id));
                 Ok(async move { recv.await.map_err(anyhow::Error::from) }.boxed())
             }
             Resource::VpmemDisk(_) => anyhow::bail!("vpmem not supported"),
@@ -696,6 +806,58 @@ fn modify_resource(
     }
 }
 
+fn vm_event(event: VmEvent) -> vmservice::VmEvent {
+    use vmservice::vm_event::Type;
+    let (ty, halt_reason) = match event {
+        VmEvent::Resumed => (Type::Resumed, ""),
+        VmEvent::Paused => (Type::Paused, ""),
+        VmEvent::Reset => (Type::Reset, ""),
+        VmEvent::Halted(reason) => (
+            Type::Halted,
+            match reason {
+                HaltReason::PowerOff => "power_off",
+                HaltReason::Reset => "reset",
+                HaltReason::Hibernate => "hibernate",
+                HaltReason::DebugBreak { .. } => "debug_break",
+                HaltReason::TripleFault { .. } => "triple_fault",
+                HaltReason::SingleStep { .. } => "single_step",
+                HaltReason::HwBreakpoint { .. } => "hw_breakpoint",
+            },
+        ),
+        VmEvent::BootStarted => (Type::BootStarted, ""),
+        VmEvent::FirmwareHandoff => (Type::FirmwareHandoff, ""),
+        VmEvent::Guest(GuestEvent::HeartbeatUp) => (Type::HeartbeatUp, ""),
+        VmEvent::Guest(GuestEvent::ShutdownRequested) => (Type::ShutdownRequested, ""),
+        VmEvent::Guest(GuestEvent::Crashed) => (Type::Crashed, ""),
+    };
+    vmservice::VmEvent {
+        r#type: ty.into(),
+        halt_reason: halt_reason.to_owned(),
+    }
+}
+
+fn capabilities() -> vmservice::CapabilitiesVmResponse {
+    use vmservice::capabilities_vm_response::Resource;
+    use vmservice::capabilities_vm_response::SupportedGuestOs;
+    use vmservice::capabilities_vm_response::SupportedResource;
+
+    let resource = |resource: Resource, add| SupportedResource {
+        add,
+        remove: true,
+        update: false,
+        resource: resource as i32,
+    };
+    vmservice::CapabilitiesVmResponse {
+        supported_resources: vec![
+            resource(Resource::Scsi, true),
+            resource(Resource::VmNic, true),
+            resource(Resource::VmbusDevice, false),
+        ],
+        supported_guest_os: vec![SupportedGuestOs::Linux as i32],
+        api_version: API_VERSION,
+    }
+}
+
 fn parse_nic_config(
     nic: vmservice::NicConfig,
 ) -> anyhow::Result<(DeviceVtl, Resource<VmbusDeviceHandleKind>)> {
@@ -26,7 +26,8 @@ fn disk_open_error(path: &Path, verb: &str) -> String {
 ///
 /// If the file ends with .vhd and is a fixed VHD1, it will be opened using
 /// the user-mode VHD parser. Otherwise, if the file ends with .vhd or
-/// .vhdx, the file will be opened using the kernel-mode VHD parser.
+/// .vhdx, the file will be opened using the kernel-mode VHD parser on Windows,
+/// or the user-mode VHDX parser elsewhere.
 pub fn open_disk_type(path: &Path, read_only: bool) -> anyhow::Result<Resource<DiskHandleKind>> {
     Ok(match path.extension().and_then(|s| s.to_str()) {
         Some("vhd") => {
@@ -63,7 +64,15 @@ pub fn open_disk_type(path: &Path, read_only: bool) -> anyhow::Result<Resource<D
                 ))
             }
             #[cfg(not(windows))]
-            anyhow::bail!("VHDX not supported on Linux");
+            {
+                let file = std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(!read_only)
+                    .open(path)
+                    .with_context(|| disk_open_error(path, "failed to open"))?;
+
+                Resource::new(disk_backend_resources::VhdxDiskHandle(file))
+            }
         }
         Some("iso") if !read_only => {
             anyhow::bail!("iso file cannot be opened as read/write")
@@ -108,7 +117,16 @@ pub fn create_disk_type(path: &Path, size: u64) -> anyhow::Result<Resource<DiskH
             Resource::new(disk_backend_resources::FixedVhd1DiskHandle(file))
         }
         Some("vhdx") => {
-            anyhow::bail!("creating vhdx not supported")
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .read(true)
+                .write(true)
+                .open(path)
+                .with_context(|| disk_open_error(path, "failed to create"))?;
+
+            disk_vhdx::VhdxDisk::create(&file, size, disk_vhdx::DEFAULT_BLOCK_SIZE)?;
+            Resource::new(disk_backend_resources::VhdxDiskHandle(file))
         }
         Some("iso") => {
             anyhow::bail!("creating iso not supported")
@@ -38,6 +38,8 @@
     net_consomme::resolver::ConsommeResolver,
     #[cfg(all(feature = "net_tap", target_os = "linux"))]
     net_tap::resolver::TapResolver,
+    #[cfg(target_os = "linux")]
+    net_xdp::resolver::XdpResolver,
     #[cfg(windows)]
     net_dio::resolver::DioResolver,
 
@@ -48,7 +50,10 @@
     disk_file::FileDiskResolver,
     disk_prwrap::DiskWithReservationsResolver,
     disk_delay::resolver::DelayDiskResolver,
+    disk_cache_mode::resolver::CacheModeDiskResolver,
+    disk_nvme_tcp::resolver::NvmeTcpDiskResolver,
     disk_vhd1::Vhd1Resolver,
+    disk_vhdx::VhdxResolver,
     #[cfg(windows)]
     disk_vhdmp::VhdmpDiskResolver,
     #[cfg(feature = "disk_blob")]
@@ -69,24 +74,32 @@
     scsidisk::resolver::SimpleScsiResolver,
 
     // Virtio devices
+    virtio_balloon::resolver::VirtioBalloonResolver,
     #[cfg(any(windows, target_os = "linux"))]
     virtiofs::resolver::VirtioFsResolver,
     #[cfg(any(windows, target_os = "linux"))]
     virtio_p9::resolver::VirtioPlan9Resolver,
     virtio_net::resolver::VirtioNetResolver,
     virtio_pmem::resolver::VirtioPmemResolver,
+    virtio_vsock::resolver::VirtioVsockResolver,
 
     // Vmbus devices
     guest_crash_device::resolver::GuestCrashDeviceResolver,
     guest_emulation_device::resolver::GuestEmulationDeviceResolver,
     guest_emulation_log::resolver::GuestEmulationLogResolver,
+    hyperv_dm::resolver::DynamicMemoryResolver,
+    hyperv_ic::resolver::HeartbeatIcResolver,
     hyperv_ic::resolver::KvpIcResolver,
     hyperv_ic::resolver::ShutdownIcResolver,
     hyperv_ic::resolver::TimesyncIcResolver,
+    hyperv_ic::resolver::VssIcResolver,
     netvsp::resolver::NetvspResolver,
     storvsp::resolver::StorvspResolver,
     uidevices::resolver::VmbusUiResolver,
     vmbfs::resolver::VmbfsResolver,
+    vmbus_latency_probe::resolver::LatencyProbeResolver,
+    #[cfg(any(windows, target_os = "linux"))]
+    vmbus_p9::resolver::VmbusPlan9Resolver,
     vmbus_serial_host::resolver::VmbusSerialDeviceResolver,
 }
 
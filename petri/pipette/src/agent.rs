@@ -66,6 +66,14 @@ pub async fn new(driver: DefaultDriver) -> anyhow::Result<Self> {
     }
 
     pub async fn run(mut self) -> anyhow::Result<()> {
+        #[cfg(target_os = "linux")]
+        self.driver
+            .spawn(
+                "latency-probe",
+                crate::latency_probe::run_responder(self.driver.clone()),
+            )
+            .detach();
+
         let mut tasks = FuturesUnordered::new();
         loop {
             futures::select! {
@@ -0,0 +1,74 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Guest responder for the VMBus latency probe device, which echoes each
+//! probe back to the host.
+
+use anyhow::Context;
+use pal_async::DefaultDriver;
+use pal_async::timer::PolledTimer;
+use std::path::Path;
+use std::time::Duration;
+use vmbus_async::async_dgram::AsyncRecvExt;
+use vmbus_async::async_dgram::AsyncSendExt;
+use vmbus_latency_probe_protocol as protocol;
+
+/// Echoes probes until the channel is closed. Returns immediately if the host
+/// did not offer the device.
+pub async fn run_responder(driver: DefaultDriver) {
+    let device = format!("/sys/bus/vmbus/devices/{}", protocol::INSTANCE_ID);
+    if !Path::new(&device).exists() {
+        tracing::debug!("no latency probe device");
+        return;
+    }
+    if let Err(err) = respond(&driver).await {
+        tracing::error!(
+            error = err.as_ref() as &dyn std::error::Error,
+            "latency probe responder failed"
+        );
+    }
+}
+
+async fn respond(driver: &DefaultDriver) -> anyhow::Result<()> {
+    // Bind the device to the user-mode vmbus driver. This fails if another
+    // instance already did, which is fine.
+    let _ = fs_err::write(
+        "/sys/bus/vmbus/drivers/uio_hv_generic/new_id",
+        protocol::INTERFACE_ID.to_string(),
+    );
+
+    // The uio device is created asynchronously after binding.
+    let mut timer = PolledTimer::new(driver);
+    let mut attempts = 0;
+    let file = loop {
+        match vmbus_user_channel::open_uio_device(&protocol::INSTANCE_ID) {
+            Ok(file) => break file,
+            Err(err) if attempts < 10 => {
+                tracing::debug!(
+                    error = &err as &dyn std::error::Error,
+                    "waiting for uio device"
+                );
+                attempts += 1;
+                timer.sleep(Duration::from_millis(500)).await;
+            }
+            Err(err) => return Err(err).context("failed to open uio device"),
+        }
+    };
+
+    let mut pipe = vmbus_user_channel::message_pipe(driver, file).context("failed to open pipe")?;
+    tracing::info!("responding to latency probes");
+    let mut buf = [0; protocol::MAX_MESSAGE_SIZE];
+    loop {
+        let n = pipe
+            .recv(&mut buf)
+            .await
+            .context("failed to receive probe")?;
+        if n == 0 {
+            break;
+        }
+        pipe.send(&buf[..n])
+            .await
+            .context("failed to send response")?;
+    }
+    Ok(())
+}
@@ -12,6 +12,8 @@
 mod crash;
 #[cfg(any(target_os = "linux", windows))]
 mod execute;
+#[cfg(target_os = "linux")]
+mod latency_probe;
 #[cfg(any(target_os = "linux", windows))]
 mod shutdown;
 #[cfg(any(target_os = "linux", windows))]
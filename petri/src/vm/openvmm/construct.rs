@@ -212,6 +212,8 @@ pub async fn new(
                     vsock_path: Some(vtl2_vsock_path.to_string_lossy().into_owned()),
                     vmbus_max_version: None,
                     vtl2_redirect: false,
+                    message_trace: None,
+                    channel_perf_hints: Vec::new(),
                     #[cfg(windows)]
                     vmbusproxy_handle: None,
                 }),
@@ -266,6 +268,12 @@ pub async fn new(
             hyperv_ic_resources::timesync::TimesyncIcHandle.into_resource(),
         ));
 
+        // Add the Hyper-V heartbeat IC
+        vmbus_devices.push((
+            DeviceVtl::Vtl0,
+            hyperv_ic_resources::heartbeat::HeartbeatIcHandle.into_resource(),
+        ));
+
         // Make a vmbus vsock path for pipette connections
         let (vmbus_vsock_listener, vmbus_vsock_path) = make_vsock_listener()?;
 
@@ -305,6 +313,10 @@ pub async fn new(
                 pci_ecam_gaps: vec![],
                 pci_mmio_gaps: vec![],
                 prefetch_memory: false,
+                huge_page_size: None,
+                dynamic_memory: None,
+                numa_nodes: Vec::new(),
+                watchpoints: false,
             }
         };
 
@@ -397,6 +409,7 @@ pub async fn new(
             // CPU and RAM
             memory,
             processor_topology,
+            vp_pinning: None,
 
             // Base chipset
             chipset,
@@ -419,6 +432,8 @@ pub async fn new(
                 vsock_path: Some(vmbus_vsock_path.to_string_lossy().into_owned()),
                 vmbus_max_version: None,
                 vtl2_redirect: firmware.openhcl_config().is_some_and(|c| c.vmbus_redirect),
+                message_trace: None,
+                channel_perf_hints: Vec::new(),
                 #[cfg(windows)]
                 vmbusproxy_handle: None,
             }),
@@ -1097,6 +1112,8 @@ fn vmbus_storage_controllers_to_openvmm(
                         devices,
                         requests: None,
                         poll_mode_queue_depth: None,
+                        queue_affinity: Vec::new(),
+                        interrupt_coalescing: None,
                     }
                     .into_resource(),
                 ));
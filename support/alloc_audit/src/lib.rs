@@ -0,0 +1,281 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Opt-in auditing of heap allocations made in designated hot paths.
+//!
+//! Hot paths are marked with [`hot_path!`]. When this crate's `enable` feature
+//! is on and the binary uses [`AuditAlloc`] as its global allocator, each
+//! allocation made while a hot path is active on the current thread is
+//! counted against that hot path. The counts are reported per call site by
+//! [`inspect_hot_paths`], to find the allocations that need to be designed
+//! out.
+//!
+//! Without the `enable` feature, entering a hot path does nothing.
+
+// UNSAFETY: implementing GlobalAlloc and projecting pinned futures.
+#![expect(unsafe_code)]
+
+use inspect::Inspect;
+use std::alloc::GlobalAlloc;
+use std::alloc::Layout;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Poll;
+
+/// Returns the `&'static` [`HotPath`] for this call site, named `$name`.
+///
+/// Names are used as inspect keys, so they should be unique.
+///
+/// ```
+/// let _audit = alloc_audit::hot_path!("queue::write").enter();
+/// ```
+#[macro_export]
+macro_rules! hot_path {
+    ($name:literal) => {{
+        static HOT_PATH: $crate::HotPath = $crate::HotPath::new($name, file!(), line!());
+        &HOT_PATH
+    }};
+}
+
+/// A code path whose heap allocations are counted.
+#[derive(Debug)]
+pub struct HotPath {
+    #[cfg_attr(not(feature = "enable"), expect(dead_code))]
+    name: &'static str,
+    file: &'static str,
+    line: u32,
+    #[cfg_attr(not(feature = "enable"), expect(dead_code))]
+    registered: AtomicBool,
+    entries: AtomicU64,
+    allocations: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Inspect for HotPath {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        req.respond()
+            .field("location", format!("{}:{}", self.file, self.line))
+            .counter("entries", self.entries.load(Ordering::Relaxed))
+            .counter("allocations", self.allocations.load(Ordering::Relaxed))
+            .counter("bytes", self.bytes.load(Ordering::Relaxed));
+    }
+}
+
+impl HotPath {
+    #[doc(hidden)]
+    pub const fn new(name: &'static str, file: &'static str, line: u32) -> Self {
+        Self {
+            name,
+            file,
+            line,
+            registered: AtomicBool::new(false),
+            entries: AtomicU64::new(0),
+            allocations: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts allocations made by this thread against this hot path until the
+    /// returned guard is dropped.
+    ///
+    /// The guard must not be held across an await point, since other tasks
+    /// may run on the thread in the meantime. Use [`Self::instrument`] for
+    /// async code.
+    #[inline]
+    pub fn enter(&'static self) -> HotPathGuard {
+        HotPathGuard {
+            #[cfg(feature = "enable")]
+            prev: imp::enter(self),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Counts allocations made while polling `fut` against this hot path.
+    pub fn instrument<F: Future>(&'static self, fut: F) -> Instrumented<F> {
+        Instrumented {
+            hot_path: self,
+            fut,
+        }
+    }
+}
+
+/// A guard returned by [`HotPath::enter`].
+#[must_use]
+pub struct HotPathGuard {
+    #[cfg(feature = "enable")]
+    prev: Option<&'static HotPath>,
+    // The hot path is tracked per thread.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for HotPathGuard {
+    #[inline]
+    fn drop(&mut self) {
+        #[cfg(feature = "enable")]
+        imp::exit(self.prev);
+    }
+}
+
+/// A future returned by [`HotPath::instrument`].
+pub struct Instrumented<F> {
+    hot_path: &'static HotPath,
+    fut: F,
+}
+
+impl<F: Future> Future for Instrumented<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let _guard = self.hot_path.enter();
+        // SAFETY: `fut` is structurally pinned; it is never moved out of
+        // `self`.
+        let fut = unsafe { self.map_unchecked_mut(|this| &mut this.fut) };
+        fut.poll(cx)
+    }
+}
+
+/// A global allocator that counts allocations made in hot paths and forwards
+/// them to `A`.
+///
+/// Counting only happens with the `enable` feature; otherwise this is a
+/// transparent wrapper.
+#[derive(Debug)]
+pub struct AuditAlloc<A>(pub A);
+
+// SAFETY: all operations are forwarded to the inner allocator unchanged.
+unsafe impl<A: GlobalAlloc> GlobalAlloc for AuditAlloc<A> {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        // SAFETY: forwarded from the caller.
+        unsafe { self.0.alloc(layout) }
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        // SAFETY: forwarded from the caller.
+        unsafe { self.0.alloc_zeroed(layout) }
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(new_size);
+        // SAFETY: forwarded from the caller.
+        unsafe { self.0.realloc(ptr, layout, new_size) }
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: forwarded from the caller.
+        unsafe { self.0.dealloc(ptr, layout) }
+    }
+}
+
+#[inline]
+fn record(size: usize) {
+    #[cfg(feature = "enable")]
+    imp::record(size);
+    #[cfg(not(feature = "enable"))]
+    let _ = size;
+}
+
+/// Returns an object that inspects every hot path entered so far, keyed by
+/// name.
+pub fn inspect_hot_paths() -> impl Inspect {
+    inspect::adhoc(|req| {
+        #[cfg_attr(not(feature = "enable"), expect(unused_mut))]
+        let mut resp = req.respond();
+        #[cfg(feature = "enable")]
+        for hot_path in imp::REGISTRY.lock().unwrap().iter() {
+            resp.field(hot_path.name, hot_path);
+        }
+    })
+}
+
+#[cfg(feature = "enable")]
+mod imp {
+    use super::HotPath;
+    use std::cell::Cell;
+    use std::sync::Mutex;
+    use std::sync::atomic::Ordering;
+
+    thread_local! {
+        // No destructor, so this remains accessible from the allocator during
+        // thread teardown.
+        static CURRENT: Cell<Option<&'static HotPath>> = const { Cell::new(None) };
+    }
+
+    pub(super) static REGISTRY: Mutex<Vec<&'static HotPath>> = Mutex::new(Vec::new());
+
+    pub(super) fn enter(hot_path: &'static HotPath) -> Option<&'static HotPath> {
+        // Register before becoming current so that the registry's own
+        // allocation is not counted.
+        if !hot_path.registered.swap(true, Ordering::Relaxed) {
+            REGISTRY.lock().unwrap().push(hot_path);
+        }
+        hot_path.entries.fetch_add(1, Ordering::Relaxed);
+        CURRENT.with(|current| current.replace(Some(hot_path)))
+    }
+
+    pub(super) fn exit(prev: Option<&'static HotPath>) {
+        CURRENT.with(|current| current.set(prev));
+    }
+
+    pub(super) fn record(size: usize) {
+        let _ = CURRENT.try_with(|current| {
+            if let Some(hot_path) = current.get() {
+                hot_path.allocations.fetch_add(1, Ordering::Relaxed);
+                hot_path.bytes.fetch_add(size as u64, Ordering::Relaxed);
+            }
+        });
+    }
+}
+
+#[cfg(all(test, feature = "enable"))]
+mod tests {
+    use super::AuditAlloc;
+    use std::alloc::System;
+    use std::sync::atomic::Ordering;
+
+    #[global_allocator]
+    static ALLOC: AuditAlloc<System> = AuditAlloc(System);
+
+    #[test]
+    fn test_count() {
+        let hot_path = hot_path!("test_count");
+        let outside = vec![0u8; 16];
+        {
+            let _audit = hot_path.enter();
+            let inside = std::hint::black_box(vec![0u8; 32]);
+            drop(inside);
+        }
+        drop(outside);
+        assert_eq!(hot_path.entries.load(Ordering::Relaxed), 1);
+        assert_eq!(hot_path.allocations.load(Ordering::Relaxed), 1);
+        assert_eq!(hot_path.bytes.load(Ordering::Relaxed), 32);
+    }
+
+    #[test]
+    fn test_instrument() {
+        let hot_path = hot_path!("test_instrument");
+        let v = block_on(hot_path.instrument(async { vec![0u8; 8] }));
+        assert_eq!(v.len(), 8);
+        assert_eq!(hot_path.allocations.load(Ordering::Relaxed), 1);
+    }
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = std::pin::pin!(fut);
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        loop {
+            if let std::task::Poll::Ready(r) = fut.as_mut().poll(&mut cx) {
+                break r;
+            }
+        }
+    }
+}
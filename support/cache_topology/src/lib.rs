@@ -57,6 +57,16 @@ pub fn from_host() -> Result<Self, HostTopologyError> {
         caches.dedup();
         Ok(Self { caches })
     }
+
+    /// Returns the CPUs that share the last-level cache with `cpu`, including
+    /// `cpu` itself, or `None` if no cache is reported for `cpu`.
+    pub fn last_level_cache_cpus(&self, cpu: u32) -> Option<&[u32]> {
+        self.caches
+            .iter()
+            .filter(|cache| cache.cpus.contains(&cpu))
+            .max_by_key(|cache| cache.level)
+            .map(|cache| cache.cpus.as_slice())
+    }
 }
 
 #[cfg(windows)]
@@ -273,4 +283,27 @@ fn test_host_cache_topology() {
         assert!(!topology.caches.is_empty());
         println!("{topology:?}");
     }
+
+    #[test]
+    fn test_last_level_cache_cpus() {
+        let cache = |level, cpus: &[u32]| super::Cache {
+            level,
+            cache_type: super::CacheType::Unified,
+            cpus: cpus.to_vec(),
+            size: 0,
+            associativity: None,
+            line_size: 64,
+        };
+        let topology = super::CacheTopology {
+            caches: vec![
+                cache(2, &[0, 1]),
+                cache(2, &[2, 3]),
+                cache(3, &[0, 1, 2, 3]),
+                cache(2, &[4, 5]),
+            ],
+        };
+        assert_eq!(topology.last_level_cache_cpus(2), Some(&[0, 1, 2, 3][..]));
+        assert_eq!(topology.last_level_cache_cpus(5), Some(&[4, 5][..]));
+        assert_eq!(topology.last_level_cache_cpus(6), None);
+    }
 }
@@ -8,6 +8,9 @@
 use inspect::Inspect;
 use pal_async::task::TaskData;
 use pal_async::task::TaskList;
+use pal_async::task::long_poll_threshold;
+use pal_async::task::set_long_poll_threshold;
+use std::time::Duration;
 
 struct Wrap(Vec<TaskData>);
 
@@ -20,6 +23,8 @@ fn inspect(&self, req: inspect::Request<'_>) {
                     .field("name", task.name())
                     .field("executor", task.executor())
                     .display("state", &task.state())
+                    .counter("long_polls", task.long_polls())
+                    .field("longest_poll", task.longest_poll())
                     .field(
                         "location",
                         format!("{}:{}", task.location().file(), task.location().line()),
@@ -34,3 +39,19 @@ fn inspect(&self, req: inspect::Request<'_>) {
 pub fn inspect_task_list() -> impl Inspect {
     Wrap(TaskList::global().tasks())
 }
+
+/// Returns an inspectable value for the threshold, in microseconds, at or
+/// above which a task poll is reported as long. Writing zero disables poll
+/// timing.
+pub fn inspect_long_poll_threshold() -> impl Inspect {
+    inspect::adhoc(|req| match req.update() {
+        Ok(req) => match req.new_value().parse::<u64>() {
+            Ok(us) => {
+                set_long_poll_threshold((us != 0).then(|| Duration::from_micros(us)));
+                req.succeed(us);
+            }
+            Err(err) => req.fail(err),
+        },
+        Err(req) => req.value(long_poll_threshold().map_or(0, |t| t.as_micros() as u64)),
+    })
+}
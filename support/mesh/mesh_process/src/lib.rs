@@ -506,7 +506,12 @@ enum HostRequest {
 }
 
 fn inspect_host(resp: &mut inspect::Response<'_>) {
-    resp.field("tasks", inspect_task::inspect_task_list());
+    resp.field("tasks", inspect_task::inspect_task_list())
+        .field(
+            "long_poll_threshold_us",
+            inspect_task::inspect_long_poll_threshold(),
+        )
+        .field("alloc_audit", alloc_audit::inspect_hot_paths());
 }
 
 #[derive(Inspect)]
@@ -7,10 +7,14 @@
 #![forbid(unsafe_code)]
 
 mod bounded;
+#[cfg(feature = "otel")]
+mod otlp;
+mod sinks;
 
 use self::bounded::BoundedReceiver;
 use self::bounded::BoundedSender;
 use self::bounded::bounded;
+use self::sinks::Sinks;
 use anyhow::Context as _;
 use futures::FutureExt;
 use futures::Stream;
@@ -251,6 +255,10 @@ pub struct TracingBackend {
     perf_filter: MeshFilterUpdater,
     #[inspect(rename = "perf/flush", mut)]
     perf_flush: MeshFlusher,
+    /// Additional outputs for trace events, which can be reconfigured at
+    /// runtime.
+    #[inspect(mut)]
+    sinks: Sinks,
 }
 
 struct BackendState {
@@ -265,22 +273,20 @@ struct BackendState {
 pub struct TracingRequestStream {
     new_receivers: mesh::Receiver<BoundedReceiver<TracingRequest>>,
     receivers: Vec<BoundedReceiver<TracingRequest>>,
+    sinks: Sinks,
 }
 
-impl Stream for TracingRequestStream {
-    type Item = TracingRequest;
-
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let this = self.get_mut();
+impl TracingRequestStream {
+    fn poll_receivers(&mut self, cx: &mut Context<'_>) -> Poll<Option<TracingRequest>> {
         let mut i = 0;
-        while let Poll::Ready(Some(recv)) = Pin::new(&mut this.new_receivers).poll_next(cx) {
-            this.receivers.push(recv);
+        while let Poll::Ready(Some(recv)) = Pin::new(&mut self.new_receivers).poll_next(cx) {
+            self.receivers.push(recv);
         }
-        while i < this.receivers.len() {
-            match Pin::new(&mut this.receivers[i]).poll_next(cx) {
+        while i < self.receivers.len() {
+            match Pin::new(&mut self.receivers[i]).poll_next(cx) {
                 r @ Poll::Ready(Some(_)) => return r,
                 Poll::Ready(None) => {
-                    this.receivers.swap_remove(i);
+                    self.receivers.swap_remove(i);
                 }
                 Poll::Pending => {}
             }
@@ -290,6 +296,24 @@ fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::It
     }
 }
 
+impl Stream for TracingRequestStream {
+    type Item = TracingRequest;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let request = std::task::ready!(this.poll_receivers(cx));
+            // Requests consumed by the sinks are not passed on.
+            if request
+                .as_ref()
+                .is_none_or(|request| this.sinks.write(&request.message))
+            {
+                return Poll::Ready(request);
+            }
+        }
+    }
+}
+
 impl TracingBackend {
     /// Spawns worker that sends traces to the host
     pub fn new<Fut, F>(
@@ -317,12 +341,14 @@ pub fn new<Fut, F>(
             .context("failed to open underhill.perfetto")?;
 
         let (flush_send, flush_recv) = mesh::channel();
+        let sinks = Sinks::new();
         let task = driver.spawn(
             "log write",
             handle_requests(
                 TracingRequestStream {
                     new_receivers: recv,
                     receivers: Vec::new(),
+                    sinks: sinks.clone(),
                 },
                 flush_recv,
             ),
@@ -342,6 +368,7 @@ pub fn new<Fut, F>(
                 spawn: Box::new(driver),
                 remotes: Vec::new(),
             },
+            sinks,
         })
     }
 
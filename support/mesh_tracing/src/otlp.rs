@@ -0,0 +1,59 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Export of received trace events to an OpenTelemetry collector, as OTLP log
+//! records sent over HTTP.
+
+use anyhow::Context as _;
+use opentelemetry::logs::AnyValue;
+use opentelemetry::logs::LogRecord as _;
+use opentelemetry::logs::Logger as _;
+use opentelemetry::logs::LoggerProvider as _;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::logs::SdkLogger;
+use opentelemetry_sdk::logs::SdkLoggerProvider;
+
+/// Exports events to the collector at a fixed endpoint.
+///
+/// Events are batched and sent from a background thread. Dropping the exporter
+/// sends any buffered events.
+pub(crate) struct OtlpExporter {
+    endpoint: String,
+    // Kept alive so that the batch processor keeps running.
+    _provider: SdkLoggerProvider,
+    logger: SdkLogger,
+}
+
+impl OtlpExporter {
+    pub fn new(endpoint: String) -> anyhow::Result<Self> {
+        let exporter = opentelemetry_otlp::LogExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint.clone())
+            .build()
+            .context("failed to create OTLP log exporter")?;
+        let provider = SdkLoggerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name("openhcl").build())
+            .build();
+        let logger = provider.logger("mesh_tracing");
+        Ok(Self {
+            endpoint,
+            _provider: provider,
+            logger,
+        })
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Queues `message`, a JSON-formatted event, for export.
+    pub fn export(&self, message: &[u8]) {
+        let mut record = self.logger.create_log_record();
+        record.set_body(AnyValue::from(
+            String::from_utf8_lossy(message).into_owned(),
+        ));
+        self.logger.emit(record);
+    }
+}
@@ -0,0 +1,381 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Runtime-configurable outputs for the trace events received by the backend.
+//!
+//! Events from all processes pass through the backend's tracing task, so the
+//! outputs can be changed via inspect without touching the processes that
+//! emit the events.
+//!
+//! With the `otel` feature, events can also be sent to an OpenTelemetry
+//! collector, by setting `otlp_endpoint` and then switching `mode` to `otlp`.
+
+#[cfg(feature = "otel")]
+use crate::otlp::OtlpExporter;
+use anyhow::Context as _;
+use inspect::InspectMut;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// The number of events kept by the flight recorder.
+const FLIGHT_RECORDER_CAPACITY: usize = 1024;
+
+/// The default size at which the trace file is rotated.
+const DEFAULT_MAX_FILE_SIZE: u64 = 16 << 20;
+
+/// The default number of rotated trace files to keep, in addition to the
+/// current one.
+const DEFAULT_MAX_ROTATED_FILES: u32 = 4;
+
+/// Where received events are sent.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Mode {
+    /// Events go to the backend's output and, if configured, the trace file.
+    Primary,
+    /// Events are only kept in memory by the flight recorder.
+    FlightRecorder,
+    /// Events are only sent to the OTLP collector.
+    #[cfg(feature = "otel")]
+    Otlp,
+}
+
+impl Mode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Primary => "primary",
+            Mode::FlightRecorder => "flight_recorder",
+            #[cfg(feature = "otel")]
+            Mode::Otlp => "otlp",
+        }
+    }
+}
+
+impl std::str::FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "primary" => Ok(Mode::Primary),
+            "flight_recorder" => Ok(Mode::FlightRecorder),
+            #[cfg(feature = "otel")]
+            "otlp" => Ok(Mode::Otlp),
+            #[cfg(not(feature = "otel"))]
+            "otlp" => anyhow::bail!("otlp mode requires the otel feature"),
+            _ => anyhow::bail!("invalid mode {s:?}, expected primary, flight_recorder or otlp"),
+        }
+    }
+}
+
+struct SinkState {
+    mode: Mode,
+    file: Option<RotatingFile>,
+    max_file_size: u64,
+    max_rotated_files: u32,
+    flight_recorder: VecDeque<String>,
+    #[cfg(feature = "otel")]
+    otlp: Option<OtlpExporter>,
+}
+
+/// A file that is rotated when it grows past a maximum size.
+///
+/// The current file is at `path`, and older files are at `path.1`, `path.2`,
+/// and so on, with `path.1` being the most recent.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    rotations: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let file = File::options()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        let size = file.metadata().map_or(0, |m| m.len());
+        Ok(Self {
+            path,
+            file,
+            size,
+            rotations: 0,
+        })
+    }
+
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    fn rotate(&mut self, max_rotated_files: u32) -> anyhow::Result<()> {
+        if max_rotated_files == 0 {
+            self.file
+                .set_len(0)
+                .context("failed to truncate trace file")?;
+        } else {
+            for index in (1..max_rotated_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(index + 1))
+                        .with_context(|| format!("failed to rename {}", from.display()))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))
+                .context("failed to rename trace file")?;
+            self.file = File::options()
+                .append(true)
+                .create(true)
+                .open(&self.path)
+                .context("failed to reopen trace file")?;
+        }
+        self.size = 0;
+        self.rotations += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, message: &[u8], max_size: u64, max_rotated_files: u32) {
+        if self.size > 0 && self.size + message.len() as u64 + 1 > max_size {
+            // Keep writing to the current file if rotation fails, rather than
+            // dropping events.
+            let _ = self.rotate(max_rotated_files);
+        }
+        if self
+            .file
+            .write_all(message)
+            .and_then(|()| self.file.write_all(b"\n"))
+            .is_ok()
+        {
+            self.size += message.len() as u64 + 1;
+        }
+    }
+}
+
+impl SinkState {
+    #[cfg(feature = "otel")]
+    fn set_otlp_endpoint(&mut self, endpoint: &str) -> anyhow::Result<()> {
+        if endpoint.is_empty() {
+            if self.mode == Mode::Otlp {
+                anyhow::bail!("cannot clear otlp_endpoint in otlp mode");
+            }
+            self.otlp = None;
+        } else {
+            self.otlp = Some(OtlpExporter::new(endpoint.into())?);
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "otel"))]
+    fn set_otlp_endpoint(&mut self, _endpoint: &str) -> anyhow::Result<()> {
+        anyhow::bail!("otlp export requires the otel feature")
+    }
+
+    fn otlp_endpoint(&self) -> String {
+        #[cfg(feature = "otel")]
+        if let Some(otlp) = &self.otlp {
+            return otlp.endpoint().to_owned();
+        }
+        String::new()
+    }
+}
+
+/// The outputs for received events, shared between the tracing task and the
+/// inspect handler that reconfigures them.
+#[derive(Clone)]
+pub(crate) struct Sinks(Arc<Mutex<SinkState>>);
+
+impl Sinks {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(SinkState {
+            mode: Mode::Primary,
+            file: None,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_rotated_files: DEFAULT_MAX_ROTATED_FILES,
+            flight_recorder: VecDeque::new(),
+            #[cfg(feature = "otel")]
+            otlp: None,
+        })))
+    }
+
+    /// Writes `message` to the configured sinks. Returns true if the event
+    /// should also be passed to the backend's output.
+    pub fn write(&self, message: &[u8]) -> bool {
+        let state = &mut *self.0.lock();
+        match state.mode {
+            Mode::Primary => {
+                if let Some(file) = &mut state.file {
+                    file.write(message, state.max_file_size, state.max_rotated_files);
+                }
+                true
+            }
+            Mode::FlightRecorder => {
+                if state.flight_recorder.len() == FLIGHT_RECORDER_CAPACITY {
+                    state.flight_recorder.pop_front();
+                }
+                state
+                    .flight_recorder
+                    .push_back(String::from_utf8_lossy(message).into_owned());
+                false
+            }
+            #[cfg(feature = "otel")]
+            Mode::Otlp => {
+                if let Some(otlp) = &state.otlp {
+                    otlp.export(message);
+                }
+                false
+            }
+        }
+    }
+}
+
+impl InspectMut for Sinks {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        let state = &mut *self.0.lock();
+        let mut resp = req.respond();
+        resp.field_mut_with("mode", |new_mode| {
+            if let Some(new_mode) = new_mode {
+                let new_mode = new_mode.parse()?;
+                #[cfg(feature = "otel")]
+                if new_mode == Mode::Otlp && state.otlp.is_none() {
+                    anyhow::bail!("otlp_endpoint must be set first");
+                }
+                state.mode = new_mode;
+            }
+            anyhow::Ok(state.mode.as_str())
+        })
+        .field_mut_with("file", |new_path| {
+            if let Some(new_path) = new_path {
+                state.file = if new_path.is_empty() {
+                    None
+                } else {
+                    Some(RotatingFile::open(new_path.into())?)
+                };
+            }
+            anyhow::Ok(
+                state
+                    .file
+                    .as_ref()
+                    .map_or(String::new(), |f| f.path.display().to_string()),
+            )
+        })
+        .field_mut_with("file_rotate", |rotate| {
+            if rotate.is_some() {
+                let file = state.file.as_mut().context("no trace file")?;
+                file.rotate(state.max_rotated_files)?;
+            }
+            anyhow::Ok(false)
+        })
+        .field_mut_with("max_file_size", |new_size| {
+            if let Some(new_size) = new_size {
+                state.max_file_size = new_size.parse()?;
+            }
+            anyhow::Ok(state.max_file_size)
+        })
+        .field_mut_with("max_rotated_files", |new_count| {
+            if let Some(new_count) = new_count {
+                state.max_rotated_files = new_count.parse()?;
+            }
+            anyhow::Ok(state.max_rotated_files)
+        })
+        .field(
+            "file_size",
+            state.file.as_ref().map(|f| inspect::AsBytes(f.size)),
+        )
+        .field("file_rotations", state.file.as_ref().map(|f| f.rotations))
+        .field_mut_with("otlp_endpoint", |new_endpoint| {
+            if let Some(new_endpoint) = new_endpoint {
+                state.set_otlp_endpoint(new_endpoint)?;
+            }
+            anyhow::Ok(state.otlp_endpoint())
+        })
+        .field_mut_with("flight_recorder_clear", |clear| {
+            if clear.is_some() {
+                state.flight_recorder.clear();
+            }
+            anyhow::Ok(false)
+        })
+        .fields(
+            "flight_recorder",
+            state
+                .flight_recorder
+                .iter()
+                .enumerate()
+                .map(|(i, event)| (i, event.as_str())),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.log");
+        let mut file = RotatingFile::open(path.clone()).unwrap();
+        for i in 0..10 {
+            file.write(format!("event{i}").as_bytes(), 14, 2);
+        }
+        // Each file holds two events.
+        assert_eq!(file.rotations, 4);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "event8\nevent9\n");
+        assert_eq!(
+            std::fs::read_to_string(file.rotated_path(1)).unwrap(),
+            "event6\nevent7\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(file.rotated_path(2)).unwrap(),
+            "event4\nevent5\n"
+        );
+        assert!(!file.rotated_path(3).exists());
+    }
+
+    #[test]
+    fn test_flight_recorder() {
+        let sinks = Sinks::new();
+        assert!(sinks.write(b"forwarded"));
+        sinks.0.lock().mode = "flight_recorder".parse().unwrap();
+        for i in 0..FLIGHT_RECORDER_CAPACITY + 1 {
+            assert!(!sinks.write(format!("event{i}").as_bytes()));
+        }
+        let state = sinks.0.lock();
+        assert_eq!(state.flight_recorder.len(), FLIGHT_RECORDER_CAPACITY);
+        assert_eq!(state.flight_recorder[0], "event1");
+    }
+
+    #[cfg(feature = "otel")]
+    #[test]
+    fn test_otlp_mode() {
+        let sinks = Sinks::new();
+        let state = &mut *sinks.0.lock();
+        // Clearing an unset endpoint is fine, but an endpoint cannot be cleared
+        // while it is in use.
+        assert!(state.set_otlp_endpoint("").is_ok());
+        state
+            .set_otlp_endpoint("http://127.0.0.1:4318/v1/logs")
+            .unwrap();
+        assert_eq!(state.otlp_endpoint(), "http://127.0.0.1:4318/v1/logs");
+        state.mode = Mode::Otlp;
+        assert!(state.set_otlp_endpoint("").is_err());
+    }
+
+    #[cfg(feature = "otel")]
+    #[test]
+    fn test_otlp_not_forwarded() {
+        let sinks = Sinks::new();
+        {
+            let state = &mut *sinks.0.lock();
+            state
+                .set_otlp_endpoint("http://127.0.0.1:4318/v1/logs")
+                .unwrap();
+            state.mode = "otlp".parse().unwrap();
+        }
+        assert!(!sinks.write(b"exported"));
+    }
+}
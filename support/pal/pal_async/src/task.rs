@@ -18,8 +18,11 @@
 use std::sync::Weak;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicU64;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
 
 /// A handle to a task.
 pub type Task<T> = async_task::Task<T, TaskMetadata>;
@@ -42,6 +45,10 @@ pub struct TaskMetadata {
     /// or something, but keep this separate to make the codegen straightforward
     /// for all state updates.
     dropped: AtomicBool,
+    /// The number of polls that took longer than the long poll threshold.
+    long_polls: AtomicU64,
+    /// The duration of the longest measured poll, in nanoseconds.
+    longest_poll_ns: AtomicU64,
     scheduler: Weak<dyn Schedule>,
     id: AtomicUsize,
     _no_pin: std::marker::PhantomPinned,
@@ -57,6 +64,8 @@ fn new(name: Arc<str>) -> Self {
             location: Location::caller(),
             state: AtomicU32::new(TASK_STATE_READY),
             dropped: AtomicBool::new(false),
+            long_polls: AtomicU64::new(0),
+            longest_poll_ns: AtomicU64::new(0),
             scheduler: Weak::<Scheduler>::new(),
             id: AtomicUsize::new(Self::NO_ID),
             _no_pin: std::marker::PhantomPinned,
@@ -87,6 +96,21 @@ fn run(&self) {
         self.state.store(TASK_STATE_RUNNING, Ordering::Relaxed);
     }
 
+    fn record_poll(&self, elapsed: Duration, threshold_ns: u64) {
+        let elapsed_ns = elapsed.as_nanos().try_into().unwrap_or(u64::MAX);
+        self.longest_poll_ns
+            .fetch_max(elapsed_ns, Ordering::Relaxed);
+        if elapsed_ns >= threshold_ns {
+            self.long_polls.fetch_add(1, Ordering::Relaxed);
+            tracelimit::warn_ratelimited!(
+                task = &*self.name,
+                location = %self.location,
+                duration = ?elapsed,
+                "long task poll stalled the executor"
+            );
+        }
+    }
+
     /// The name of the spawned task.
     pub fn name(&self) -> &Arc<str> {
         &self.name
@@ -261,7 +285,12 @@ fn poll(
         this.metadata.run();
         // SAFETY: the future is pinned since `self` is pinned.
         let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        let threshold_ns = LONG_POLL_THRESHOLD_NS.load(Ordering::Relaxed);
+        let start = (threshold_ns != 0).then(Instant::now);
         let r = CURRENT_TASK.with(|task| task.lend(this.metadata, || future.poll(cx)));
+        if let Some(start) = start {
+            this.metadata.record_poll(start.elapsed(), threshold_ns);
+        }
         if r.is_pending() {
             this.metadata.pend();
         } else {
@@ -345,6 +374,30 @@ fn spawn_local<T: 'static>(
     }
 }
 
+/// The poll duration at or above which a poll is considered long, in
+/// nanoseconds. Zero disables poll timing.
+static LONG_POLL_THRESHOLD_NS: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the duration at or above which a single poll of a task is considered
+/// to have stalled its executor.
+///
+/// Long polls are counted per task, reported in [`TaskData`], and logged.
+/// Pass `None` (the default) to disable poll timing entirely.
+pub fn set_long_poll_threshold(threshold: Option<Duration>) {
+    let threshold_ns = threshold.map_or(0, |threshold| {
+        u64::try_from(threshold.as_nanos())
+            .unwrap_or(u64::MAX)
+            .max(1)
+    });
+    LONG_POLL_THRESHOLD_NS.store(threshold_ns, Ordering::Relaxed);
+}
+
+/// Returns the threshold set by [`set_long_poll_threshold`].
+pub fn long_poll_threshold() -> Option<Duration> {
+    let threshold_ns = LONG_POLL_THRESHOLD_NS.load(Ordering::Relaxed);
+    (threshold_ns != 0).then(|| Duration::from_nanos(threshold_ns))
+}
+
 thread_local! {
     static CURRENT_TASK: LoanCell<TaskMetadata> = const { LoanCell::new() };
 }
@@ -461,6 +514,10 @@ pub fn tasks(&self) -> Vec<TaskData> {
                     location: task.location,
                     state: task.state(),
                     executor: scheduler,
+                    long_polls: task.long_polls.load(Ordering::Relaxed),
+                    longest_poll: Duration::from_nanos(
+                        task.longest_poll_ns.load(Ordering::Relaxed),
+                    ),
                 }
             })
             .collect()
@@ -475,6 +532,8 @@ pub struct TaskData {
     location: &'static Location<'static>,
     state: TaskState,
     executor: Option<Arc<str>>,
+    long_polls: u64,
+    longest_poll: Duration,
 }
 
 impl TaskData {
@@ -504,4 +563,59 @@ pub fn location(&self) -> &'static Location<'static> {
     pub fn state(&self) -> TaskState {
         self.state
     }
+
+    /// The number of polls of the task that took at least the threshold set
+    /// by [`set_long_poll_threshold`].
+    pub fn long_polls(&self) -> u64 {
+        self.long_polls
+    }
+
+    /// The duration of the longest poll of the task measured while a long
+    /// poll threshold was set.
+    pub fn longest_poll(&self) -> Duration {
+        self.longest_poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Spawn;
+    use super::long_poll_threshold;
+    use super::set_long_poll_threshold;
+    use crate::DefaultDriver;
+    use pal_async_test::async_test;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    /// Sets the process-wide long poll threshold, restoring the previous one
+    /// when dropped, even if the test panics.
+    struct ThresholdGuard(Option<Duration>);
+
+    impl ThresholdGuard {
+        fn set(threshold: Duration) -> Self {
+            let guard = Self(long_poll_threshold());
+            set_long_poll_threshold(Some(threshold));
+            guard
+        }
+    }
+
+    impl Drop for ThresholdGuard {
+        fn drop(&mut self) {
+            set_long_poll_threshold(self.0);
+        }
+    }
+
+    #[async_test]
+    async fn long_poll(driver: DefaultDriver) {
+        let guard = ThresholdGuard::set(Duration::from_millis(10));
+        let mut task = driver.spawn("long poll", async {
+            std::thread::sleep(Duration::from_millis(20));
+        });
+        (&mut task).await;
+        drop(guard);
+
+        let metadata = task.metadata();
+        assert_eq!(metadata.long_polls.load(Ordering::Relaxed), 1);
+        assert!(metadata.longest_poll_ns.load(Ordering::Relaxed) >= 20_000_000);
+    }
 }
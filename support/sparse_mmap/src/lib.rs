@@ -16,7 +16,10 @@
 pub use sys::Mappable;
 pub use sys::MappableRef;
 pub use sys::SparseMapping;
+pub use sys::alloc_huge_shared_memory;
 pub use sys::alloc_shared_memory;
+pub use sys::bind_shared_memory;
+pub use sys::discard_shared_memory;
 pub use sys::new_mappable_from_file;
 
 use std::mem::MaybeUninit;
@@ -10,6 +10,7 @@
 use std::fs::File;
 use std::io;
 use std::io::Error;
+use std::ops::Range;
 use std::os::unix::prelude::*;
 use std::ptr::null_mut;
 use std::sync::atomic::AtomicUsize;
@@ -407,3 +408,183 @@ pub fn alloc_shared_memory(size: usize) -> io::Result<OwnedFd> {
     fd.set_len(size as u64)?;
     Ok(fd.into())
 }
+
+/// Allocates a mappable shared memory object of `size` bytes, backed by host
+/// huge pages of `page_size` bytes.
+///
+/// `size` must be a multiple of `page_size`. The huge pages are allocated up
+/// front, so this fails if the host does not have enough free huge pages of
+/// the requested size. Views of the object must be aligned to `page_size`.
+///
+/// The pages for each `(range, node)` in `bindings` are allocated from host
+/// NUMA node `node`. Huge page objects do not keep a policy set by
+/// [`bind_shared_memory`], so this is the only way to place them.
+pub fn alloc_huge_shared_memory(
+    size: usize,
+    page_size: usize,
+    bindings: &[(Range<u64>, u32)],
+) -> io::Result<OwnedFd> {
+    #[cfg(target_os = "linux")]
+    {
+        if !page_size.is_power_of_two() || size % page_size != 0 {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        let flags = libc::MFD_CLOEXEC
+            | libc::MFD_HUGETLB
+            | (page_size.trailing_zeros() << libc::MFD_HUGE_SHIFT);
+        // SAFETY: creating a new file descriptor according to the documented
+        // contract.
+        let fd = unsafe {
+            let fd = libc::memfd_create(c"mem".as_ptr(), flags).syscall_result()?;
+            File::from_raw_fd(fd)
+        };
+        fd.set_len(size as u64)?;
+        // Fault in the bound ranges through a bound mapping, since the pages
+        // are allocated according to the policy of the faulting mapping.
+        for (range, node) in bindings {
+            with_bound_mapping(
+                fd.as_fd(),
+                range.start,
+                range.end - range.start,
+                *node,
+                libc::PROT_READ | libc::PROT_WRITE,
+                |address, len| {
+                    // SAFETY: populating the mapping owned by
+                    // `with_bound_mapping` has no memory safety requirements.
+                    unsafe {
+                        libc::madvise(address, len, MADV_POPULATE_WRITE).syscall_result()?;
+                    }
+                    Ok(())
+                },
+            )?;
+        }
+        // Allocate the rest of the pages now, so that a shortage is reported
+        // here instead of as a fault when the guest first touches the memory.
+        //
+        // SAFETY: allocating backing for a file has no memory safety
+        // requirements.
+        unsafe {
+            libc::fallocate(fd.as_raw_fd(), 0, 0, size as libc::off_t).syscall_result()?;
+        }
+        Ok(fd.into())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (size, page_size, bindings);
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Releases the memory backing `offset..offset + len` of a shared memory
+/// object allocated by [`alloc_shared_memory`].
+///
+/// The range reads as zero afterwards, in this and all other mappings of the
+/// object, and memory is allocated for it again on demand.
+pub fn discard_shared_memory(memory: impl AsFd, offset: u64, len: u64) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: punching a hole has no memory safety requirements. Any
+        // mappings of the range remain valid and read zeroes.
+        unsafe {
+            libc::fallocate(
+                memory.as_fd().as_raw_fd(),
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                offset as libc::off_t,
+                len as libc::off_t,
+            )
+            .syscall_result()?;
+        }
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (memory, offset, len);
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Requires that the memory backing `offset..offset + len` of a shared memory
+/// object allocated by [`alloc_shared_memory`] be allocated from host NUMA
+/// node `node`.
+///
+/// This applies to pages allocated after the call, in all mappings of the
+/// object, so it should be called before the range is first accessed.
+pub fn bind_shared_memory(memory: impl AsFd, offset: u64, len: u64, node: u32) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        // The policy of a shared memory object is stored with the object, but
+        // it can only be set through a mapping, so map the range temporarily.
+        with_bound_mapping(
+            memory.as_fd(),
+            offset,
+            len,
+            node,
+            libc::PROT_NONE,
+            |_, _| Ok(()),
+        )
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (memory, offset, len, node);
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(target_os = "linux")]
+const MADV_POPULATE_WRITE: libc::c_int = 23;
+
+/// Maps `offset..offset + len` of `memory` with protection `prot`, binds the
+/// mapping to host NUMA node `node`, and calls `f` with the mapping's address
+/// and length before unmapping it.
+#[cfg(target_os = "linux")]
+fn with_bound_mapping(
+    memory: BorrowedFd<'_>,
+    offset: u64,
+    len: u64,
+    node: u32,
+    prot: libc::c_int,
+    f: impl FnOnce(*mut c_void, usize) -> io::Result<()>,
+) -> io::Result<()> {
+    const MPOL_BIND: libc::c_int = 2;
+    const BITS: usize = libc::c_ulong::BITS as usize;
+
+    let len = len.try_into().map_err(|_| io::ErrorKind::InvalidInput)?;
+    let node = node as usize;
+    let mut node_mask = vec![0 as libc::c_ulong; node / BITS + 1];
+    node_mask[node / BITS] |= 1 << (node % BITS);
+
+    // SAFETY: mapping a new range at an address chosen by the kernel does not
+    // alias any existing memory.
+    let address = unsafe {
+        mmap(
+            null_mut(),
+            len,
+            prot,
+            libc::MAP_SHARED,
+            memory.as_raw_fd(),
+            offset as i64,
+        )?
+    };
+    // SAFETY: the mapping was just created and is owned by this function.
+    // The kernel reads `maxnode - 1` bits from the node mask.
+    let result = unsafe {
+        if libc::syscall(
+            libc::SYS_mbind,
+            address,
+            len,
+            MPOL_BIND,
+            node_mask.as_ptr(),
+            node_mask.len() * BITS + 1,
+            0,
+        ) < 0
+        {
+            Err(Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+    .and_then(|()| f(address, len));
+    // SAFETY: the mapping is no longer used.
+    unsafe { munmap(address, len)? };
+    result
+}
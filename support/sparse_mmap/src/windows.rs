@@ -30,6 +30,7 @@
 use std::ffi::c_void;
 use std::io;
 use std::io::Error;
+use std::ops::Range;
 use std::os::windows::prelude::*;
 use std::ptr::null;
 use std::ptr::null_mut;
@@ -682,6 +683,47 @@ pub fn alloc_shared_memory(size: usize) -> io::Result<OwnedHandle> {
     }
 }
 
+/// Allocates a mappable shared memory object of `size` bytes, backed by host
+/// huge pages of `page_size` bytes.
+///
+/// Views of large page sections must be mapped with `MEM_LARGE_PAGES`, which
+/// [`SparseMapping`] does not support yet, so this is not supported on
+/// Windows.
+pub fn alloc_huge_shared_memory(
+    size: usize,
+    page_size: usize,
+    bindings: &[(Range<u64>, u32)],
+) -> io::Result<OwnedHandle> {
+    let _ = (size, page_size, bindings);
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Releases the memory backing `offset..offset + len` of a shared memory
+/// object allocated by [`alloc_shared_memory`].
+///
+/// Pagefile-backed sections cannot release part of their backing, so this is
+/// not supported on Windows.
+pub fn discard_shared_memory(memory: impl AsHandle, offset: u64, len: u64) -> io::Result<()> {
+    let _ = (memory, offset, len);
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Requires that the memory backing `offset..offset + len` of a shared memory
+/// object allocated by [`alloc_shared_memory`] be allocated from host NUMA
+/// node `node`.
+///
+/// Section NUMA preference is set per view rather than per section, so this is
+/// not supported on Windows.
+pub fn bind_shared_memory(
+    memory: impl AsHandle,
+    offset: u64,
+    len: u64,
+    node: u32,
+) -> io::Result<()> {
+    let _ = (memory, offset, len, node);
+    Err(io::ErrorKind::Unsupported.into())
+}
+
 #[cfg(test)]
 mod tests {
     use super::SparseMapping;
@@ -15,6 +15,7 @@
 pub mod madt;
 pub mod mcfg;
 pub mod pptt;
+pub mod slit;
 pub mod srat;
 
 #[expect(non_camel_case_types)]
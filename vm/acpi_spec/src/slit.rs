@@ -0,0 +1,45 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use super::Table;
+use crate::packed_nums::*;
+use core::mem::size_of;
+use static_assertions::const_assert_eq;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+use zerocopy::Unaligned;
+
+/// The fixed portion of the SLIT, followed by a `localities` x `localities`
+/// matrix of byte-sized distances.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes, Unaligned)]
+pub struct SlitHeader {
+    pub localities: u64_ne,
+}
+
+const_assert_eq!(size_of::<SlitHeader>(), 8);
+
+impl SlitHeader {
+    pub fn new(localities: u64) -> Self {
+        Self {
+            localities: localities.into(),
+        }
+    }
+}
+
+impl Table for SlitHeader {
+    const SIGNATURE: [u8; 4] = *b"SLIT";
+}
+
+pub const SLIT_REVISION: u8 = 1;
+
+/// The distance from a locality to itself.
+pub const SLIT_LOCAL_DISTANCE: u8 = 10;
+
+/// The distance reported between two distinct localities by default.
+pub const SLIT_DEFAULT_REMOTE_DISTANCE: u8 = 20;
+
+/// Marks a locality as unreachable from another.
+pub const SLIT_UNREACHABLE_DISTANCE: u8 = 0xff;
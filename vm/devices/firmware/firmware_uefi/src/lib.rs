@@ -340,6 +340,42 @@ fn inspect_extra(&mut self, resp: &mut inspect::Response<'_>) {
 
             Result::<_, std::convert::Infallible>::Ok(output.unwrap_or_else(|| USAGE.to_string()))
         });
+
+        // The boot order, as a comma-separated list of hex `Boot####` numbers.
+        // Updates are persisted to nvram and take effect on the next boot.
+        resp.field_mut_with("boot_order", |v| {
+            use service::nvram::boot_order::format_boot_order;
+            use service::nvram::boot_order::parse_boot_order;
+
+            let nvram = &mut self.service.nvram;
+            block_on(async {
+                if let Some(v) = v {
+                    nvram.set_boot_order(&parse_boot_order(v)?).await?;
+                }
+                Ok::<_, service::nvram::boot_order::BootOrderError>(
+                    match nvram.boot_order().await {
+                        Ok(order) => format_boot_order(&order),
+                        Err(err) => format!("error reading boot order: {err}"),
+                    },
+                )
+            })
+        });
+
+        resp.child("boot_entries", |req| {
+            match block_on(self.service.nvram.boot_entries()) {
+                Ok(entries) => {
+                    let mut resp = req.respond();
+                    for entry in entries {
+                        resp.child(&format!("{:04X}", entry.number), |req| {
+                            req.respond()
+                                .field("description", entry.description)
+                                .field("active", entry.active);
+                        });
+                    }
+                }
+                Err(err) => req.value(format!("error reading boot entries: {err}")),
+            }
+        });
     }
 }
 
@@ -5,7 +5,8 @@
 //!
 //! This service handles processing of the EFI diagnostics buffer,
 //! producing friendly logs for any telemetry during the UEFI boot
-//! process.
+//! process, and a summary of the buffer (error counts, recent errors, and
+//! per-phase boot timing) available via inspect.
 //!
 //! The EFI diagnostics buffer follows the specification of Project Mu's
 //! Advanced Logger package, whose relevant types are defined in the Hyper-V
@@ -21,6 +22,7 @@
 use log::Log;
 use mesh::payload::Protobuf;
 use processor::ProcessingError;
+use summary::DiagnosticsSummary;
 use uefi_specs::hyperv::debug_level::DEBUG_ERROR;
 use uefi_specs::hyperv::debug_level::DEBUG_INFO;
 use uefi_specs::hyperv::debug_level::DEBUG_WARN;
@@ -30,6 +32,7 @@
 mod header;
 mod log;
 mod processor;
+mod summary;
 
 /// Default number of EfiDiagnosticsLogs emitted per period
 pub const DEFAULT_LOGS_PER_PERIOD: u32 = 150;
@@ -160,6 +163,9 @@ pub struct DiagnosticsServices {
     processed: bool,
     /// Log level used for filtering
     log_level: LogLevel,
+    /// Summary of the most recently processed buffer, kept across resets so
+    /// that a failed boot can be examined after the VM restarts
+    last_summary: Option<DiagnosticsSummary>,
 }
 
 impl DiagnosticsServices {
@@ -169,6 +175,7 @@ pub fn new(log_level: LogLevel) -> DiagnosticsServices {
             gpa: None,
             processed: false,
             log_level,
+            last_summary: None,
         }
     }
 
@@ -213,7 +220,14 @@ pub fn process_diagnostics<F>(
         let effective_log_level = log_level_override.unwrap_or(self.log_level);
 
         // Delegate to the processor module
-        processor::process_diagnostics_internal(self.gpa, gm, effective_log_level, log_handler)
+        let summary = processor::process_diagnostics_internal(
+            self.gpa,
+            gm,
+            effective_log_level,
+            log_handler,
+        )?;
+        self.last_summary = Some(summary);
+        Ok(())
     }
 }
 
@@ -10,6 +10,7 @@
 use crate::service::diagnostics::header::LogBufferHeader;
 use crate::service::diagnostics::log::Log;
 use crate::service::diagnostics::log::LogParseError;
+use crate::service::diagnostics::summary::DiagnosticsSummary;
 use guestmem::GuestMemory;
 use std::collections::BTreeMap;
 use thiserror::Error;
@@ -87,12 +88,15 @@ pub enum ProcessingError {
 /// * `gm` - Guest memory to read diagnostics from
 /// * `log_level` - Log level for filtering
 /// * `log_handler` - Function to handle each parsed log entry
+///
+/// # Returns
+/// A summary of all entries in the buffer, regardless of `log_level`.
 pub fn process_diagnostics_internal<F>(
     gpa: Option<Gpa>,
     gm: &GuestMemory,
     log_level: LogLevel,
     log_handler: F,
-) -> Result<(), ProcessingError>
+) -> Result<DiagnosticsSummary, ProcessingError>
 where
     F: FnMut(&Log),
 {
@@ -104,7 +108,7 @@ pub fn process_diagnostics_internal<F>(
         tracelimit::info_ratelimited!(
             "EFI diagnostics' used log buffer size is 0, ending processing"
         );
-        return Ok(());
+        return Ok(DiagnosticsSummary::default());
     }
 
     // Read the log buffer from guest memory
@@ -128,6 +132,8 @@ struct LogProcessor {
     entries_emitted: usize,
     /// Number of bytes read from buffer
     bytes_read: usize,
+    /// Summary of all complete entries
+    summary: DiagnosticsSummary,
 }
 
 impl LogProcessor {
@@ -138,6 +144,7 @@ fn new() -> Self {
             entries_processed: 0,
             entries_emitted: 0,
             bytes_read: 0,
+            summary: DiagnosticsSummary::default(),
         }
     }
 
@@ -161,13 +168,23 @@ fn log_summary(&self) {
             entries_processed = self.entries_processed,
             entries_emitted = self.entries_emitted,
             bytes_read = self.bytes_read,
+            errors = self.summary.errors,
+            warnings = self.summary.warnings,
             "processed EFI log entries"
         );
+        self.summary.trace_phases();
     }
 
-    /// Check if a log should be emitted based on level and suppression
-    fn should_emit(&mut self, log: &Log, log_level: LogLevel) -> bool {
-        log_level.should_log(log.debug_level) && !self.should_suppress(log)
+    /// Record a complete log in the summary and check if it should be
+    /// emitted based on level and suppression
+    fn record_and_check(&mut self, log: &Log, log_level: LogLevel) -> bool {
+        self.entries_processed += 1;
+        if self.should_suppress(log) {
+            self.summary.suppressed += 1;
+            return false;
+        }
+        self.summary.record(log);
+        log_level.should_log(log.debug_level)
     }
 
     /// Process the log buffer and emit completed log entries
@@ -175,7 +192,7 @@ fn process_buffer<F>(
         buffer_data: &[u8],
         log_level: LogLevel,
         mut log_handler: F,
-    ) -> Result<(), ProcessingError>
+    ) -> Result<DiagnosticsSummary, ProcessingError>
     where
         F: FnMut(&Log),
     {
@@ -194,8 +211,7 @@ fn process_buffer<F>(
             processor.accumulator.feed(log)?;
 
             if let Some(complete_log) = processor.accumulator.take() {
-                processor.entries_processed += 1;
-                if processor.should_emit(&complete_log, log_level) {
+                if processor.record_and_check(&complete_log, log_level) {
                     processor.entries_emitted += 1;
                     log_handler(&complete_log);
                 }
@@ -203,14 +219,13 @@ fn process_buffer<F>(
         }
 
         if let Some(final_log) = processor.accumulator.clear() {
-            processor.entries_processed += 1;
-            if processor.should_emit(&final_log, log_level) {
+            if processor.record_and_check(&final_log, log_level) {
                 processor.entries_emitted += 1;
                 log_handler(&final_log);
             }
         }
 
         processor.log_summary();
-        Ok(())
+        Ok(processor.summary)
     }
 }
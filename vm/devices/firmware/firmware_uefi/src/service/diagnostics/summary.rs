@@ -0,0 +1,166 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! This module provides `DiagnosticsSummary`, which condenses a processed
+//! diagnostics buffer into counts, per-phase boot timing, and recent errors
+//! that can be inspected after the fact.
+
+use crate::service::diagnostics::log::Log;
+use crate::service::diagnostics::log::phase_to_string;
+use inspect::Inspect;
+use std::collections::VecDeque;
+use uefi_specs::hyperv::debug_level::DEBUG_ERROR;
+use uefi_specs::hyperv::debug_level::DEBUG_WARN;
+
+/// Maximum number of error messages retained in the summary
+const MAX_RECENT_ERRORS: usize = 16;
+
+/// Hypervisor reference ticks per millisecond
+const TICKS_PER_MS: u64 = 10_000;
+
+/// Timing of a single boot phase, derived from the timestamps of the log
+/// entries it produced.
+#[derive(Debug, Clone, Inspect)]
+pub struct PhaseTiming {
+    /// The boot phase
+    #[inspect(skip)]
+    pub phase: u16,
+    /// Timestamp of the phase's first entry, in hypervisor reference ticks
+    pub first_ticks: u64,
+    /// Timestamp of the phase's last entry, in hypervisor reference ticks
+    pub last_ticks: u64,
+    /// Number of entries produced by the phase
+    pub entries: usize,
+}
+
+impl PhaseTiming {
+    /// Time between the phase's first and last entries, in milliseconds.
+    pub fn duration_ms(&self) -> u64 {
+        self.last_ticks.saturating_sub(self.first_ticks) / TICKS_PER_MS
+    }
+}
+
+/// A summary of the most recently processed diagnostics buffer.
+#[derive(Debug, Default, Clone, Inspect)]
+pub struct DiagnosticsSummary {
+    /// Number of complete entries in the buffer, regardless of log level
+    pub entries: usize,
+    /// Number of error entries
+    pub errors: usize,
+    /// Number of warning entries
+    pub warnings: usize,
+    /// Number of entries matching known, benign messages
+    pub suppressed: usize,
+    /// Per-phase timing, in the order the phases first logged
+    #[inspect(with = "inspect_phases")]
+    pub phases: Vec<PhaseTiming>,
+    /// The most recent error messages
+    #[inspect(with = "|x| inspect::iter_by_index(x)")]
+    pub recent_errors: VecDeque<String>,
+}
+
+fn inspect_phases(phases: &[PhaseTiming]) -> impl Inspect + '_ {
+    inspect::adhoc(move |req| {
+        let mut resp = req.respond();
+        for timing in phases {
+            resp.child(phase_to_string(timing.phase), |req| {
+                req.respond()
+                    .merge(timing)
+                    .field("duration_ms", timing.duration_ms());
+            });
+        }
+    })
+}
+
+impl DiagnosticsSummary {
+    /// Record a complete log entry
+    pub fn record(&mut self, log: &Log) {
+        self.entries += 1;
+        if log.debug_level & DEBUG_ERROR != 0 {
+            self.errors += 1;
+            if self.recent_errors.len() == MAX_RECENT_ERRORS {
+                self.recent_errors.pop_front();
+            }
+            self.recent_errors
+                .push_back(log.message_trimmed().to_owned());
+        } else if log.debug_level & DEBUG_WARN != 0 {
+            self.warnings += 1;
+        }
+
+        let ticks = log.ticks();
+        match self.phases.iter_mut().find(|t| t.phase == log.phase) {
+            Some(timing) => {
+                timing.first_ticks = timing.first_ticks.min(ticks);
+                timing.last_ticks = timing.last_ticks.max(ticks);
+                timing.entries += 1;
+            }
+            None => self.phases.push(PhaseTiming {
+                phase: log.phase,
+                first_ticks: ticks,
+                last_ticks: ticks,
+                entries: 1,
+            }),
+        }
+    }
+
+    /// Emit the per-phase boot timing to tracing
+    pub fn trace_phases(&self) {
+        for timing in &self.phases {
+            tracelimit::info_ratelimited!(
+                phase = phase_to_string(timing.phase),
+                first_ticks = timing.first_ticks,
+                duration_ms = timing.duration_ms(),
+                entries = timing.entries,
+                "EFI boot phase timing"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uefi_specs::hyperv::advanced_logger::DXE_PHASE;
+    use uefi_specs::hyperv::advanced_logger::PEI64_PHASE;
+    use uefi_specs::hyperv::debug_level::DEBUG_INFO;
+
+    fn log(debug_level: u32, phase: u16, time_stamp: u64, message: &str) -> Log {
+        Log {
+            debug_level,
+            time_stamp,
+            phase,
+            message: message.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut summary = DiagnosticsSummary::default();
+        summary.record(&log(DEBUG_INFO, PEI64_PHASE, 100, "pei\n"));
+        summary.record(&log(DEBUG_WARN, PEI64_PHASE, 20_100, "warn\n"));
+        summary.record(&log(DEBUG_ERROR, DXE_PHASE, 30_000, "oops\n"));
+        summary.record(&log(DEBUG_INFO, DXE_PHASE, 60_000, "dxe\n"));
+
+        assert_eq!(summary.entries, 4);
+        assert_eq!(summary.errors, 1);
+        assert_eq!(summary.warnings, 1);
+        assert_eq!(summary.recent_errors, ["oops"]);
+        assert_eq!(summary.phases.len(), 2);
+        assert_eq!(summary.phases[0].phase, PEI64_PHASE);
+        assert_eq!(summary.phases[0].duration_ms(), 2);
+        assert_eq!(summary.phases[1].phase, DXE_PHASE);
+        assert_eq!(summary.phases[1].entries, 2);
+        assert_eq!(summary.phases[1].duration_ms(), 3);
+    }
+
+    #[test]
+    fn test_recent_errors_bounded() {
+        let mut summary = DiagnosticsSummary::default();
+        for i in 0..MAX_RECENT_ERRORS + 2 {
+            summary.record(&log(DEBUG_ERROR, DXE_PHASE, i as u64, &format!("{i}\n")));
+        }
+        assert_eq!(summary.errors, MAX_RECENT_ERRORS + 2);
+        assert_eq!(summary.recent_errors.len(), MAX_RECENT_ERRORS);
+        assert_eq!(summary.recent_errors.front().unwrap(), "2");
+    }
+}
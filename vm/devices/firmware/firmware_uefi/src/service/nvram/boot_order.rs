@@ -0,0 +1,168 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Host management of the guest's UEFI boot order.
+//!
+//! The boot order is stored in the `BootOrder` variable as a list of
+//! `Boot####` load option numbers. Since nvram is backed by the VMGS, changes
+//! made here persist, and take effect the next time the guest boots.
+
+use super::NvramError;
+use super::NvramServices;
+use super::NvramServicesExt;
+use std::collections::BTreeSet;
+use thiserror::Error;
+use uefi_nvram_specvars::boot_order;
+use uefi_specs::uefi::common::EfiStatus;
+use uefi_specs::uefi::nvram::EfiVariableAttributes;
+use uefi_specs::uefi::nvram::vars::BOOT_ORDER;
+use uefi_specs::uefi::nvram::vars::EFI_GLOBAL_VARIABLE;
+use zerocopy::IntoBytes;
+
+#[derive(Debug, Error)]
+pub enum BootOrderError {
+    #[error("could not access '{0}': {1:?}")]
+    Variable(String, EfiStatus, #[source] Option<NvramError>),
+    #[error("could not parse '{0}'")]
+    Parse(String, #[source] boot_order::Error),
+    #[error("invalid boot option number '{0}'")]
+    InvalidNumber(String),
+    #[error("boot option Boot{0:04X} does not exist")]
+    MissingOption(u16),
+    #[error("boot option Boot{0:04X} is listed more than once")]
+    DuplicateOption(u16),
+}
+
+/// A `Boot####` load option.
+#[derive(Debug)]
+pub struct BootEntry {
+    pub number: u16,
+    pub description: String,
+    /// Whether the firmware will attempt to boot this option
+    pub active: bool,
+}
+
+/// `LOAD_OPTION_ACTIVE`, from UEFI spec 3.1.3
+const LOAD_OPTION_ACTIVE: u32 = 0x1;
+
+fn boot_option_name(number: u16) -> String {
+    format!("Boot{number:04X}")
+}
+
+/// Parses a comma-separated list of hex boot option numbers, e.g. `0003,0001`.
+pub fn parse_boot_order(s: &str) -> Result<Vec<u16>, BootOrderError> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .map(|n| {
+            let digits = n.strip_prefix("Boot").unwrap_or(n);
+            u16::from_str_radix(digits, 16).map_err(|_| BootOrderError::InvalidNumber(n.into()))
+        })
+        .collect()
+}
+
+/// Formats a boot order in the form accepted by [`parse_boot_order`].
+pub fn format_boot_order(order: &[u16]) -> String {
+    order
+        .iter()
+        .map(|n| format!("{n:04X}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl NvramServices {
+    /// Returns the current boot order. A missing `BootOrder` variable is
+    /// treated as an empty boot order.
+    pub async fn boot_order(&mut self) -> Result<Vec<u16>, BootOrderError> {
+        let (vendor, name) = BOOT_ORDER();
+        let data = match self.services.get_variable_ucs2(vendor, name).await {
+            Ok((_, data)) => data,
+            Err((EfiStatus::NOT_FOUND, _)) => return Ok(Vec::new()),
+            Err((status, err)) => {
+                return Err(BootOrderError::Variable(name.to_string(), status, err));
+            }
+        };
+
+        Ok(boot_order::parse_boot_order(&data)
+            .map_err(|err| BootOrderError::Parse(name.to_string(), err))?
+            .collect())
+    }
+
+    /// Returns the load option with the given number, if it exists.
+    async fn boot_entry(&mut self, number: u16) -> Result<Option<BootEntry>, BootOrderError> {
+        let name = boot_option_name(number);
+        let data = match self.services.get_variable(EFI_GLOBAL_VARIABLE, &name).await {
+            Ok((_, data)) => data,
+            Err((EfiStatus::NOT_FOUND, _)) => return Ok(None),
+            Err((status, err)) => return Err(BootOrderError::Variable(name, status, err)),
+        };
+
+        let option = boot_order::EfiLoadOption::parse(&data)
+            .map_err(|err| BootOrderError::Parse(name, err))?;
+
+        Ok(Some(BootEntry {
+            number,
+            description: option.description.to_string(),
+            active: option.attributes & LOAD_OPTION_ACTIVE != 0,
+        }))
+    }
+
+    /// Returns the load options referenced by the boot order, in boot order.
+    ///
+    /// Entries that are listed but missing are skipped, as the firmware does.
+    pub async fn boot_entries(&mut self) -> Result<Vec<BootEntry>, BootOrderError> {
+        let mut entries = Vec::new();
+        for number in self.boot_order().await? {
+            if let Some(entry) = self.boot_entry(number).await? {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Replaces the boot order. Every listed load option must exist.
+    pub async fn set_boot_order(&mut self, order: &[u16]) -> Result<(), BootOrderError> {
+        let mut seen = BTreeSet::new();
+        for &number in order {
+            if !seen.insert(number) {
+                return Err(BootOrderError::DuplicateOption(number));
+            }
+            if self.boot_entry(number).await?.is_none() {
+                return Err(BootOrderError::MissingOption(number));
+            }
+        }
+
+        let (vendor, name) = BOOT_ORDER();
+
+        // Keep the attributes of the existing variable, since the firmware
+        // rejects attribute changes to an existing variable.
+        let attr = match self.services.get_variable_ucs2(vendor, name).await {
+            Ok((attr, _)) => attr,
+            Err(_) => EfiVariableAttributes::DEFAULT_ATTRIBUTES.into(),
+        };
+
+        self.services
+            .set_variable_ucs2(vendor, name, attr, order.as_bytes().to_vec())
+            .await
+            .map_err(|(status, err)| BootOrderError::Variable(name.to_string(), status, err))?;
+
+        tracing::info!(boot_order = format_boot_order(order), "updated boot order");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_boot_order() {
+        assert_eq!(parse_boot_order("0003, 0001,Boot000A").unwrap(), [3, 1, 10]);
+        assert_eq!(parse_boot_order("").unwrap(), []);
+        assert!(matches!(
+            parse_boot_order("0001,xyz"),
+            Err(BootOrderError::InvalidNumber(_))
+        ));
+        assert_eq!(format_boot_order(&[3, 1, 10]), "0003,0001,000A");
+    }
+}
@@ -29,6 +29,8 @@
 use uefi_specs::uefi::nvram::EfiVariableAttributes;
 use zerocopy::IntoBytes;
 
+pub mod boot_order;
+
 #[cfg(feature = "fuzzing")]
 pub mod spec_services;
 #[cfg(not(feature = "fuzzing"))]
@@ -195,6 +195,8 @@ pub mod vars {
     defn_nvram_var!(SECURE_BOOT = (EFI_GLOBAL_VARIABLE, "SecureBoot"));
     defn_nvram_var!(SETUP_MODE = (EFI_GLOBAL_VARIABLE, "SetupMode"));
 
+    defn_nvram_var!(BOOT_ORDER = (EFI_GLOBAL_VARIABLE, "BootOrder"));
+
     defn_nvram_var!(PK = (EFI_GLOBAL_VARIABLE, "PK"));
     defn_nvram_var!(KEK = (EFI_GLOBAL_VARIABLE, "KEK"));
     defn_nvram_var!(DBDEFAULT = (EFI_GLOBAL_VARIABLE, "dbDefault"));
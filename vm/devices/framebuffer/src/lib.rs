@@ -13,9 +13,12 @@
 //! and a [`FramebufferLocalControl`] which share state using an inner mutex.
 //! The latter implements [`FramebufferControl`] which provides the necessary
 //! interfaces for a video device to control the framebuffer.
-//! In Underhill, the format sender is extracted from the framebuffer and used
+//! In Underhill, the update sender is extracted from the framebuffer and used
 //! to create a different struct that implements the same trait.
 //!
+//! A UI front end in another process can instead take a
+//! [`FramebufferExport`] and render directly from the shared memory.
+//!
 //! This is separate from the synthetic device because its lifetime is separate
 //! from that of the synthetic video VMBus channel.
 
@@ -37,6 +40,9 @@
 use std::convert::Infallible;
 use std::io;
 use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use video_core::DirtyRect;
 use video_core::FramebufferControl;
 use video_core::FramebufferFormat;
 use video_core::ResolvedFramebuffer;
@@ -84,28 +90,38 @@ pub fn framebuffer(
         "no framebuffer size flexibility for now"
     );
 
-    let (send, recv) = mesh::channel();
+    let (update_send, update_recv) = mesh::channel();
 
     let fb = Framebuffer {
         vram: vram.try_clone()?,
         len,
-        format_send: send,
+        update_send,
     };
     let access = FramebufferAccess {
         vram,
         len,
-        format_recv: recv,
+        update_recv,
         offset,
     };
     Ok((fb, access))
 }
 
+/// A change to the framebuffer reported by the video device.
+#[derive(Debug, Clone, MeshPayload)]
+pub enum FramebufferUpdate {
+    /// The guest changed the framebuffer format.
+    Format(FramebufferFormat),
+    /// The guest updated these regions of the framebuffer, in the current
+    /// format.
+    Damage(Vec<DirtyRect>),
+}
+
 /// The video framebuffer to be provided to the device.
 #[derive(Debug, MeshPayload)]
 pub struct Framebuffer {
     vram: Mappable,
     len: usize,
-    format_send: mesh::Sender<FramebufferFormat>,
+    update_send: mesh::Sender<FramebufferUpdate>,
 }
 
 impl Framebuffer {
@@ -114,9 +130,9 @@ pub fn len(&self) -> usize {
         self.len
     }
 
-    /// Extract format sender, consuming the framebuffer
-    pub fn format_send(self) -> mesh::Sender<FramebufferFormat> {
-        self.format_send
+    /// Extract update sender, consuming the framebuffer
+    pub fn update_send(self) -> mesh::Sender<FramebufferUpdate> {
+        self.update_send
     }
 }
 
@@ -125,7 +141,7 @@ pub fn format_send(self) -> mesh::Sender<FramebufferFormat> {
 pub struct FramebufferAccess {
     vram: Mappable,
     len: usize,
-    format_recv: mesh::Receiver<FramebufferFormat>,
+    update_recv: mesh::Receiver<FramebufferUpdate>,
     offset: u64,
 }
 
@@ -136,21 +152,65 @@ pub fn view(self) -> io::Result<View> {
         mapping.map_file(0, self.len, &self.vram, self.offset, false)?;
         Ok(View {
             mapping,
-            format_recv: self.format_recv,
+            update_recv: self.update_recv,
             format: None,
+            dirty: None,
             vram: self.vram,
             len: self.len,
             offset: self.offset,
         })
     }
+
+    /// Exports the framebuffer memory and update stream, for a UI front end
+    /// that maps and renders the framebuffer itself.
+    pub fn export(self) -> FramebufferExport {
+        FramebufferExport {
+            vram: self.vram,
+            len: self.len,
+            offset: self.offset,
+            updates: self.update_recv,
+        }
+    }
+}
+
+/// The framebuffer's shared memory and update stream, exported for a UI
+/// front end, typically in another process.
+///
+/// The front end maps `len` bytes of `vram` starting at `offset` and renders
+/// directly from it, using the format and damage reported on `updates` to
+/// decide what to draw. No pixel data is sent through mesh.
+#[derive(Debug, MeshPayload)]
+pub struct FramebufferExport {
+    /// The framebuffer memory.
+    pub vram: Mappable,
+    /// The size of the framebuffer memory in bytes.
+    pub len: usize,
+    /// The offset of the framebuffer within `vram`.
+    pub offset: u64,
+    /// Format changes and damage reported by the guest. The current format,
+    /// if any, is the first update.
+    pub updates: mesh::Receiver<FramebufferUpdate>,
+}
+
+impl FramebufferExport {
+    /// Converts the export back into an accessor.
+    pub fn into_access(self) -> FramebufferAccess {
+        FramebufferAccess {
+            vram: self.vram,
+            len: self.len,
+            update_recv: self.updates,
+            offset: self.offset,
+        }
+    }
 }
 
 /// A mapped view of the framebuffer.
 #[derive(Debug)]
 pub struct View {
     mapping: SparseMapping,
-    format_recv: mesh::Receiver<FramebufferFormat>,
+    update_recv: mesh::Receiver<FramebufferUpdate>,
     format: Option<FramebufferFormat>,
+    dirty: Option<DirtyRect>,
     vram: Mappable,
     len: usize,
     offset: u64,
@@ -174,32 +234,102 @@ pub fn read_line(&mut self, line: u16, data: &mut [u8]) {
 
     /// Returns the current resolution.
     pub fn resolution(&mut self) -> (u16, u16) {
-        // Get any framebuffer updates.
-        //
-        // FUTURE-use a channel/port type that throws away all but the last
-        // message to avoid possible high memory use.
-        while let Ok(format) = self.format_recv.try_recv() {
-            self.format = Some(format);
-        }
-        if let Some(format) = &self.format {
+        if let Some(format) = self.format() {
             (format.width as u16, format.height as u16)
         } else {
             (1, 1)
         }
     }
 
+    /// Returns the current framebuffer format, or `None` if the guest has not
+    /// set one yet.
+    pub fn format(&mut self) -> Option<FramebufferFormat> {
+        self.drain_updates();
+        self.format
+    }
+
+    /// Polls for a change to the framebuffer format, returning the new format.
+    ///
+    /// This allows frontends to resize as soon as the guest changes mode,
+    /// rather than noticing the change on their next [`Self::resolution`]
+    /// call.
+    pub fn poll_format_change(&mut self, cx: &mut Context<'_>) -> Poll<FramebufferFormat> {
+        loop {
+            match self.update_recv.poll_recv(cx) {
+                Poll::Ready(Ok(update)) => {
+                    if let Some(format) = self.apply_update(update) {
+                        break Poll::Ready(format);
+                    }
+                }
+                // No more changes will arrive once the device is gone.
+                Poll::Ready(Err(_)) | Poll::Pending => break Poll::Pending,
+            }
+        }
+    }
+
+    /// Returns the bounding box of the regions the guest has reported as
+    /// updated since the last call, clipped to the current resolution.
+    ///
+    /// Returns `None` if the guest has not reported any updates, in which case
+    /// the caller must assume the whole framebuffer may have changed, since
+    /// not all guests report updates.
+    pub fn take_dirty(&mut self) -> Option<DirtyRect> {
+        self.drain_updates();
+        let dirty = self.dirty.take()?;
+        let format = self.format?;
+        let dirty = DirtyRect {
+            left: dirty.left,
+            top: dirty.top,
+            right: dirty.right.min(format.width as u32),
+            bottom: dirty.bottom.min(format.height as u32),
+        };
+        (!dirty.is_empty()).then_some(dirty)
+    }
+
+    fn drain_updates(&mut self) {
+        // FUTURE-use a channel/port type that coalesces updates to avoid
+        // possible high memory use when the view is not being polled.
+        while let Ok(update) = self.update_recv.try_recv() {
+            self.apply_update(update);
+        }
+    }
+
+    /// Applies `update`, returning the new format if it changed.
+    fn apply_update(&mut self, update: FramebufferUpdate) -> Option<FramebufferFormat> {
+        match update {
+            FramebufferUpdate::Format(format) => {
+                self.format = Some(format);
+                // Regions reported so far refer to the previous format.
+                self.dirty = None;
+                Some(format)
+            }
+            FramebufferUpdate::Damage(rects) => {
+                for rect in rects {
+                    self.dirty = Some(match self.dirty {
+                        Some(dirty) => dirty.union(&rect),
+                        None => rect,
+                    });
+                }
+                None
+            }
+        }
+    }
+
     /// Gets the framebuffer access back.
     pub fn access(self) -> FramebufferAccess {
-        // Put the current format at the head of the channel.
+        // Put the current state at the head of the channel.
         let (send, recv) = mesh::channel();
         if let Some(format) = self.format {
-            send.send(format);
+            send.send(FramebufferUpdate::Format(format));
+        }
+        if let Some(dirty) = self.dirty {
+            send.send(FramebufferUpdate::Damage(vec![dirty]));
         }
-        send.bridge(self.format_recv);
+        send.bridge(self.update_recv);
         FramebufferAccess {
             vram: self.vram,
             len: self.len,
-            format_recv: recv,
+            update_recv: recv,
             offset: self.offset,
         }
     }
@@ -291,7 +421,9 @@ pub fn new(
 
         // Send the initial framebuffer format.
         let format = default_framebuffer_format();
-        framebuffer.format_send.send(format);
+        framebuffer
+            .update_send
+            .send(FramebufferUpdate::Format(format));
 
         Ok(Self {
             inner: Arc::new(Mutex::new(FramebufferInner {
@@ -374,8 +506,8 @@ fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
             .framebuffer
             .as_mut()
             .unwrap()
-            .format_send
-            .send(inner.format);
+            .update_send
+            .send(FramebufferUpdate::Format(inner.format));
         Ok(())
     }
 }
@@ -457,7 +589,9 @@ pub fn set_format(&mut self, format: FramebufferFormat) {
         if inner.format != format {
             inner.format = format;
             if let Some(framebuffer) = &mut inner.framebuffer {
-                framebuffer.format_send.send(inner.format);
+                framebuffer
+                    .update_send
+                    .send(FramebufferUpdate::Format(inner.format));
             }
         }
     }
@@ -489,6 +623,13 @@ async fn unmap(&mut self) {
     async fn set_format(&mut self, format: FramebufferFormat) {
         self.set_format(format);
     }
+    async fn dirty(&mut self, rects: Vec<DirtyRect>) {
+        if let Some(framebuffer) = &self.inner.lock().framebuffer {
+            framebuffer
+                .update_send
+                .send(FramebufferUpdate::Damage(rects));
+        }
+    }
 }
 
 impl ResolveResource<FramebufferHandleKind, SharedFramebufferHandle> for FramebufferLocalControl {
@@ -31,6 +31,8 @@
 use vmbus_channel::gpadl_ring::GpadlRingMem;
 use vmbus_channel::simple::SaveRestoreSimpleVmbusDevice;
 use vmbus_channel::simple::SimpleVmbusDevice;
+use vmcore::guest_event::GuestEvent;
+use vmcore::guest_event::GuestEventSink;
 use vmcore::save_restore::SavedStateNotSupported;
 use zerocopy::FromBytes;
 use zerocopy::Immutable;
@@ -43,6 +45,8 @@ pub struct GuestCrashDevice {
     #[inspect(skip)]
     request_dump: mesh::Sender<FailableRpc<mesh::OneshotReceiver<()>, File>>,
     max_dump_size: u64,
+    #[inspect(skip)]
+    events: GuestEventSink,
 }
 
 /// The internal guest crash channel.
@@ -115,14 +119,16 @@ impl GuestCrashDevice {
     /// When the guest requests a crash dump, the device will send a request to
     /// `request_dump` to retrieve the file to write to. When the dump completes
     /// successfully, the device will send an empty message to the provided
-    /// oneshot channel.
+    /// oneshot channel. Each crash is also reported to `events`.
     pub fn new(
         request_dump: mesh::Sender<FailableRpc<mesh::OneshotReceiver<()>, File>>,
         max_dump_size: u64,
+        events: GuestEventSink,
     ) -> Self {
         Self {
             request_dump,
             max_dump_size,
+            events,
         }
     }
 
@@ -133,8 +139,9 @@ pub fn into_inner(
     ) -> (
         mesh::Sender<FailableRpc<mesh::OneshotReceiver<()>, File>>,
         u64,
+        GuestEventSink,
     ) {
-        (self.request_dump, self.max_dump_size)
+        (self.request_dump, self.max_dump_size, self.events)
     }
 }
 
@@ -228,6 +235,7 @@ async fn process_inner(&mut self, channel: &mut GuestCrashChannel) -> anyhow::Re
                             })?;
                         }
                         crash::MessageType::REQUEST_NIX_DUMP_START_V1 => {
+                            self.events.report(GuestEvent::Crashed);
                             let (send, recv) = mesh::oneshot();
                             let recv = self.request_dump.call_failable(|x| x, recv);
                             channel.state = ProtocolState::DumpRequested {
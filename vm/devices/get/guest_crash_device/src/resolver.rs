@@ -4,35 +4,49 @@
 //! Resolver implementation for the guest crash device.
 
 use crate::GuestCrashDevice;
+use anyhow::Context as _;
+use async_trait::async_trait;
 use get_resources::crash::GuestCrashDeviceHandle;
-use std::convert::Infallible;
-use vm_resource::ResolveResource;
-use vm_resource::declare_static_resolver;
+use vm_resource::AsyncResolveResource;
+use vm_resource::IntoResource;
+use vm_resource::PlatformResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
 use vm_resource::kind::VmbusDeviceHandleKind;
 use vmbus_channel::resources::ResolveVmbusDeviceHandleParams;
 use vmbus_channel::resources::ResolvedVmbusDevice;
 use vmbus_channel::simple::SimpleDeviceWrapper;
+use vmcore::guest_event::GuestEventKind;
 
 /// Resource resolver for [`GuestCrashDeviceHandle`].
 pub struct GuestCrashDeviceResolver;
 
-declare_static_resolver!(
+declare_static_async_resolver!(
     GuestCrashDeviceResolver,
     (VmbusDeviceHandleKind, GuestCrashDeviceHandle)
 );
 
-impl ResolveResource<VmbusDeviceHandleKind, GuestCrashDeviceHandle> for GuestCrashDeviceResolver {
+#[async_trait]
+impl AsyncResolveResource<VmbusDeviceHandleKind, GuestCrashDeviceHandle>
+    for GuestCrashDeviceResolver
+{
     type Output = ResolvedVmbusDevice;
-    type Error = Infallible;
+    type Error = anyhow::Error;
 
-    fn resolve(
+    async fn resolve(
         &self,
+        resolver: &ResourceResolver,
         resource: GuestCrashDeviceHandle,
         input: ResolveVmbusDeviceHandleParams<'_>,
     ) -> Result<Self::Output, Self::Error> {
+        let events = resolver
+            .resolve::<GuestEventKind, _>(PlatformResource.into_resource(), ())
+            .await
+            .context("failed to resolve guest event sink")?;
+
         Ok(SimpleDeviceWrapper::new(
             input.driver_source.simple(),
-            GuestCrashDevice::new(resource.request_dump, resource.max_dump_size),
+            GuestCrashDevice::new(resource.request_dump, resource.max_dump_size, events),
         )
         .into())
     }
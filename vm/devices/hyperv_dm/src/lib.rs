@@ -0,0 +1,1236 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Hyper-V dynamic memory device.
+//!
+//! This implements the host side of the Hyper-V dynamic memory protocol,
+//! which resizes a running guest's RAM toward a host-selected target:
+//!
+//! * To shrink the guest, the device asks the guest's balloon driver for
+//!   pages. The guest hands back page ranges it no longer needs, and the
+//!   device releases their host memory backing via the partition's
+//!   [`MemoryReclaim`] hook.
+//! * To grow the guest, the device first hands ballooned pages back to the
+//!   guest. Past the startup size, it adds RAM from the platform's hot add
+//!   window via [`MemoryHotAdd`] and asks the guest to online it.
+//!
+//! The target is clamped to the configured minimum and maximum sizes. The
+//! device stops working toward a target when the guest fails to make progress,
+//! until the host sets a new one. Hot removal is not supported, so memory that
+//! has been hot added can only be ballooned.
+//!
+//! The balloon and the hot added memory are saved across a restore. The
+//! ballooned ranges are reclaimed again and the hot added chunks are added back
+//! before the device resumes, so the restored guest sees the same memory
+//! layout it negotiated before the save.
+
+#![forbid(unsafe_code)]
+
+mod protocol;
+pub mod resolver;
+
+use async_trait::async_trait;
+use futures::FutureExt;
+use futures::StreamExt;
+use futures_concurrency::future::Race;
+use hyperv_dm_resources::DynamicMemoryRpc;
+use hyperv_dm_resources::DynamicMemoryStatus;
+use inspect::Inspect;
+use inspect::InspectMut;
+use protocol::PAGE_SIZE;
+use std::future::pending;
+use std::io::IoSlice;
+use task_control::Cancelled;
+use task_control::StopTask;
+use thiserror::Error;
+use vmbus_async::async_dgram::AsyncRecvExt;
+use vmbus_async::async_dgram::AsyncSend;
+use vmbus_async::async_dgram::AsyncSendExt;
+use vmbus_async::pipe::MessagePipe;
+use vmbus_channel::RawAsyncChannel;
+use vmbus_channel::bus::OfferParams;
+use vmbus_channel::channel::ChannelOpenError;
+use vmbus_channel::gpadl_ring::GpadlRingMem;
+use vmbus_channel::simple::SaveRestoreSimpleVmbusDevice;
+use vmbus_channel::simple::SimpleVmbusDevice;
+use vmbus_ring::RingMem;
+use vmcore::memory_hot_add::HOT_ADD_ALIGNMENT;
+use vmcore::memory_hot_add::MemoryHotAdd;
+use vmcore::memory_reclaim::MemoryReclaim;
+use zerocopy::FromBytes;
+use zerocopy::IntoBytes;
+
+/// The most page ranges to send in a single unballoon message, keeping the
+/// message within the guest's receive buffer.
+const MAX_UNBALLOON_RANGES: usize = 256;
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error("channel i/o error")]
+    Io(#[source] std::io::Error),
+    #[error("bad packet")]
+    BadPacket,
+    #[error("unexpected message type {0}")]
+    UnexpectedMessage(u16),
+    #[error("failed to add back hot added memory")]
+    HotAdd(#[source] std::io::Error),
+}
+
+/// A Hyper-V dynamic memory device.
+#[derive(InspectMut)]
+pub struct DynamicMemory {
+    #[inspect(skip)]
+    recv: mesh::Receiver<DynamicMemoryRpc>,
+    #[inspect(skip)]
+    reclaim: MemoryReclaim,
+    #[inspect(skip)]
+    hot_add: Option<MemoryHotAdd>,
+    #[inspect(hex)]
+    startup_size: u64,
+    #[inspect(hex)]
+    min_size: u64,
+    #[inspect(hex)]
+    max_size: u64,
+    #[inspect(hex)]
+    target: u64,
+}
+
+impl DynamicMemory {
+    /// Returns a new dynamic memory device, using `recv` to receive requests
+    /// from the host.
+    ///
+    /// The guest starts with `startup_size` bytes of RAM and can be resized
+    /// between `min_size` and `max_size`. Growing past `startup_size` requires
+    /// `hot_add`, and is limited to the size of its hot add window.
+    pub fn new(
+        recv: mesh::Receiver<DynamicMemoryRpc>,
+        reclaim: MemoryReclaim,
+        hot_add: Option<MemoryHotAdd>,
+        startup_size: u64,
+        min_size: u64,
+        max_size: u64,
+    ) -> Self {
+        let hot_add_size = hot_add.as_ref().map_or(0, |hot_add| {
+            let range = hot_add.hot_add_range();
+            range.end - range.start
+        });
+        Self {
+            recv,
+            reclaim,
+            hot_add,
+            startup_size,
+            min_size: min_size.min(startup_size),
+            max_size: max_size.clamp(startup_size, startup_size + hot_add_size),
+            target: startup_size,
+        }
+    }
+
+    fn handle_rpc(&mut self, rpc: DynamicMemoryRpc, state: &mut ChannelState) {
+        let ready = match state {
+            ChannelState::Ready(ready) => Some(ready),
+            _ => None,
+        };
+        match rpc {
+            DynamicMemoryRpc::SetTarget(rpc) => rpc.handle_sync(|target| {
+                self.target = target.clamp(self.min_size, self.max_size);
+                if let Some(ready) = ready {
+                    ready.stalled = false;
+                }
+            }),
+            DynamicMemoryRpc::Status(rpc) => rpc.handle_sync(|()| {
+                let startup_pages = self.startup_size / PAGE_SIZE;
+                let (current, ballooned, hot_added, status) =
+                    ready.map_or((startup_pages, 0, 0, None), |ready| {
+                        (
+                            ready.current_pages(startup_pages),
+                            ready.ballooned_pages,
+                            ready.hot_added_pages,
+                            ready.status,
+                        )
+                    });
+                DynamicMemoryStatus {
+                    target: self.target,
+                    current: current * PAGE_SIZE,
+                    ballooned: ballooned * PAGE_SIZE,
+                    hot_added: hot_added * PAGE_SIZE,
+                    guest_committed: status.map(|status| status.num_committed * PAGE_SIZE),
+                    guest_available: status.map(|status| status.num_available * PAGE_SIZE),
+                }
+            }),
+        }
+    }
+
+    fn reclaim(&self, range: protocol::PageRange) {
+        let gpa = range.start_page() * PAGE_SIZE;
+        let len = range.page_count() as u64 * PAGE_SIZE;
+        if let Err(err) = self.reclaim.reclaim(gpa, len) {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                gpa,
+                len,
+                "failed to reclaim ballooned memory"
+            );
+        }
+    }
+}
+
+#[doc(hidden)]
+pub struct DynamicMemoryChannel<T: RingMem = GpadlRingMem> {
+    pipe: MessagePipe<T>,
+    state: ChannelState,
+}
+
+#[derive(Inspect)]
+#[inspect(external_tag)]
+enum ChannelState {
+    Version,
+    Capabilities {
+        #[inspect(hex)]
+        version: u32,
+    },
+    Ready(#[inspect(rename = "state")] ReadyState),
+    Failed,
+}
+
+#[derive(Inspect)]
+struct ReadyState {
+    #[inspect(hex)]
+    version: u32,
+    #[inspect(debug)]
+    capabilities: protocol::Capabilities,
+    /// The page ranges the guest has handed to the balloon.
+    #[inspect(with = "Vec::len")]
+    ballooned: Vec<protocol::PageRange>,
+    ballooned_pages: u64,
+    /// The number of pages of the hot add window the guest has onlined. The
+    /// window is always added in order, so this is also the offset of the
+    /// next page to hot add.
+    hot_added_pages: u64,
+    operation: Operation,
+    #[inspect(debug)]
+    status: Option<protocol::StatusReport>,
+    /// The guest failed to make progress toward the target.
+    stalled: bool,
+    next_transaction_id: u32,
+    /// The hot added memory must be added back before the next operation,
+    /// since the channel was restored.
+    pending_readd: bool,
+}
+
+#[derive(Inspect)]
+#[inspect(external_tag)]
+enum Operation {
+    Idle,
+    Balloon { requested: u64, received: u64 },
+    Unballoon { pages: u64 },
+    HotAdd { start_page: u64, page_count: u64 },
+}
+
+impl<T: RingMem + Unpin> DynamicMemoryChannel<T> {
+    fn new(pipe: MessagePipe<T>) -> Self {
+        Self {
+            pipe,
+            state: ChannelState::Version,
+        }
+    }
+
+    async fn process(&mut self, dm: &mut DynamicMemory) -> ! {
+        enum Event {
+            Message(std::io::Result<usize>),
+            Request(DynamicMemoryRpc),
+        }
+
+        let (mut recv, mut send) = MessagePipe::split(&mut self.pipe);
+        let mut buf = vec![0; protocol::MAX_MESSAGE_SIZE];
+        loop {
+            let r = async {
+                if let ChannelState::Ready(ready) = &mut self.state {
+                    ready.start_operation(dm, &mut send).await?;
+                }
+                let failed = matches!(self.state, ChannelState::Failed);
+                let event = (
+                    async {
+                        if failed {
+                            pending().await
+                        } else {
+                            Event::Message(recv.recv(&mut buf).await)
+                        }
+                    },
+                    async {
+                        match dm.recv.next().await {
+                            Some(rpc) => Event::Request(rpc),
+                            None => pending().await,
+                        }
+                    },
+                )
+                    .race()
+                    .await;
+                match event {
+                    Event::Message(r) => {
+                        let n = r.map_err(Error::Io)?;
+                        self.state.handle_message(dm, &mut send, &buf[..n]).await?;
+                    }
+                    Event::Request(rpc) => dm.handle_rpc(rpc, &mut self.state),
+                }
+                Ok::<_, Error>(())
+            }
+            .await;
+            if let Err(err) = r {
+                tracing::error!(
+                    error = &err as &dyn std::error::Error,
+                    "dynamic memory error"
+                );
+                self.state = ChannelState::Failed;
+            }
+        }
+    }
+}
+
+impl ChannelState {
+    async fn handle_message(
+        &mut self,
+        dm: &DynamicMemory,
+        send: &mut impl AsyncSend,
+        buf: &[u8],
+    ) -> Result<(), Error> {
+        let (header, _) =
+            protocol::MessageHeader::read_from_prefix(buf).map_err(|_| Error::BadPacket)?; // TODO: zerocopy: map_err (https://github.com/microsoft/openvmm/issues/759)
+        let body = buf
+            .get(size_of_val(&header)..header.size as usize)
+            .ok_or(Error::BadPacket)?;
+
+        match self {
+            ChannelState::Version => {
+                if header.message_type != protocol::MESSAGE_VERSION_REQUEST {
+                    return Err(Error::UnexpectedMessage(header.message_type));
+                }
+                let (request, _) = protocol::VersionRequest::read_from_prefix(body)
+                    .map_err(|_| Error::BadPacket)?; // TODO: zerocopy: map_err (https://github.com/microsoft/openvmm/issues/759)
+                let accepted = matches!(
+                    request.version,
+                    protocol::VERSION_WIN8 | protocol::VERSION_WIN10
+                );
+                send_message(
+                    send,
+                    protocol::MESSAGE_VERSION_RESPONSE,
+                    header.transaction_id,
+                    protocol::VersionResponse {
+                        is_accepted: accepted.into(),
+                    }
+                    .as_bytes(),
+                    &[],
+                )
+                .await?;
+                if accepted {
+                    tracing::info!(version = request.version, "dynamic memory negotiated");
+                    *self = ChannelState::Capabilities {
+                        version: request.version,
+                    };
+                } else {
+                    tracelimit::warn_ratelimited!(
+                        version = request.version,
+                        "unsupported dynamic memory version"
+                    );
+                }
+            }
+            ChannelState::Capabilities { version } => {
+                if header.message_type != protocol::MESSAGE_CAPABILITIES_REPORT {
+                    return Err(Error::UnexpectedMessage(header.message_type));
+                }
+                let (report, _) = protocol::CapabilitiesReport::read_from_prefix(body)
+                    .map_err(|_| Error::BadPacket)?; // TODO: zerocopy: map_err (https://github.com/microsoft/openvmm/issues/759)
+                send_message(
+                    send,
+                    protocol::MESSAGE_CAPABILITIES_RESPONSE,
+                    header.transaction_id,
+                    protocol::CapabilitiesResponse {
+                        flags: protocol::CapabilitiesResponseFlags::new().with_is_accepted(true),
+                    }
+                    .as_bytes(),
+                    &[],
+                )
+                .await?;
+                tracing::info!(capabilities = ?report.capabilities, "dynamic memory ready");
+                *self = ChannelState::Ready(ReadyState {
+                    version: *version,
+                    capabilities: report.capabilities,
+                    ballooned: Vec::new(),
+                    ballooned_pages: 0,
+                    hot_added_pages: 0,
+                    operation: Operation::Idle,
+                    status: None,
+                    stalled: false,
+                    next_transaction_id: 0,
+                    pending_readd: false,
+                });
+            }
+            ChannelState::Ready(ready) => ready.handle_message(dm, header.message_type, body)?,
+            ChannelState::Failed => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+impl ReadyState {
+    fn current_pages(&self, startup_pages: u64) -> u64 {
+        startup_pages + self.hot_added_pages - self.ballooned_pages
+    }
+
+    fn handle_message(
+        &mut self,
+        dm: &DynamicMemory,
+        message_type: u16,
+        body: &[u8],
+    ) -> Result<(), Error> {
+        match (message_type, &mut self.operation) {
+            (protocol::MESSAGE_STATUS_REPORT, _) => {
+                let (status, _) =
+                    protocol::StatusReport::read_from_prefix(body).map_err(|_| Error::BadPacket)?; // TODO: zerocopy: map_err (https://github.com/microsoft/openvmm/issues/759)
+                self.status = Some(status);
+            }
+            (protocol::MESSAGE_INFO, _) => {}
+            (
+                protocol::MESSAGE_BALLOON_RESPONSE,
+                Operation::Balloon {
+                    requested,
+                    received,
+                },
+            ) => {
+                let (response, ranges) = protocol::BalloonResponse::read_from_prefix(body)
+                    .map_err(|_| Error::BadPacket)?; // TODO: zerocopy: map_err (https://github.com/microsoft/openvmm/issues/759)
+                let ranges = ranges
+                    .get(..response.flags.range_count() as usize * size_of::<protocol::PageRange>())
+                    .ok_or(Error::BadPacket)?;
+                for range in ranges
+                    .chunks_exact(size_of::<protocol::PageRange>())
+                    .map(|range| protocol::PageRange::read_from_bytes(range).unwrap())
+                {
+                    if range.page_count() == 0 {
+                        continue;
+                    }
+                    dm.reclaim(range);
+                    self.ballooned.push(range);
+                    self.ballooned_pages += range.page_count() as u64;
+                    *received += range.page_count() as u64;
+                }
+                if !response.flags.more_pages() {
+                    tracing::debug!(requested, received, "balloon request complete");
+                    self.stalled = *received == 0;
+                    self.operation = Operation::Idle;
+                }
+            }
+            (protocol::MESSAGE_UNBALLOON_RESPONSE, Operation::Unballoon { pages }) => {
+                tracing::debug!(pages, "unballoon request complete");
+                self.operation = Operation::Idle;
+            }
+            (
+                protocol::MESSAGE_HOT_ADD_RESPONSE,
+                &mut Operation::HotAdd {
+                    start_page,
+                    page_count,
+                },
+            ) => {
+                let (response, _) = protocol::HotAddResponse::read_from_prefix(body)
+                    .map_err(|_| Error::BadPacket)?; // TODO: zerocopy: map_err (https://github.com/microsoft/openvmm/issues/759)
+                let added = if response.result == 1 {
+                    (response.page_count as u64).min(page_count)
+                } else {
+                    0
+                };
+                tracing::debug!(start_page, page_count, added, "hot add request complete");
+                self.hot_added_pages += added;
+                self.stalled = added == 0;
+                self.operation = Operation::Idle;
+            }
+            (message_type, _) => return Err(Error::UnexpectedMessage(message_type)),
+        }
+        Ok(())
+    }
+
+    /// Starts the next operation toward the target, if there is one.
+    async fn start_operation(
+        &mut self,
+        dm: &DynamicMemory,
+        send: &mut impl AsyncSend,
+    ) -> Result<(), Error> {
+        if self.pending_readd {
+            self.readd_hot_added(dm).await?;
+        }
+        if !matches!(self.operation, Operation::Idle) || self.stalled {
+            return Ok(());
+        }
+        let current = self.current_pages(dm.startup_size / PAGE_SIZE);
+        let target = dm.target / PAGE_SIZE;
+        if target < current {
+            if !self.capabilities.balloon() {
+                return Ok(());
+            }
+            let num_pages = (current - target).try_into().unwrap_or(u32::MAX);
+            self.send_request(
+                send,
+                protocol::MESSAGE_BALLOON_REQUEST,
+                protocol::BalloonRequest {
+                    num_pages,
+                    reserved: 0,
+                }
+                .as_bytes(),
+                &[],
+            )
+            .await?;
+            self.operation = Operation::Balloon {
+                requested: num_pages.into(),
+                received: 0,
+            };
+        } else if target > current {
+            if self.ballooned_pages > 0 {
+                let ranges = self.take_ballooned(target - current);
+                let pages = ranges.iter().map(|r| r.page_count() as u64).sum();
+                let count = ranges.len().div_ceil(MAX_UNBALLOON_RANGES);
+                for (i, ranges) in ranges.chunks(MAX_UNBALLOON_RANGES).enumerate() {
+                    self.send_request(
+                        send,
+                        protocol::MESSAGE_UNBALLOON_REQUEST,
+                        protocol::UnballoonRequest {
+                            flags: protocol::UnballoonRequestFlags::new()
+                                .with_more_pages(i + 1 < count),
+                            range_count: ranges.len() as u32,
+                        }
+                        .as_bytes(),
+                        ranges.as_bytes(),
+                    )
+                    .await?;
+                }
+                self.operation = Operation::Unballoon { pages };
+            } else if let Some(hot_add) = &dm.hot_add {
+                if !self.capabilities.hot_add()
+                    || 1u64 << (20 + self.capabilities.hot_add_alignment()) > HOT_ADD_ALIGNMENT
+                {
+                    return Ok(());
+                }
+                let window = hot_add.hot_add_range();
+                let start_page = window.start / PAGE_SIZE + self.hot_added_pages;
+                let chunk_pages = HOT_ADD_ALIGNMENT / PAGE_SIZE;
+                let chunk_start = start_page / chunk_pages * chunk_pages;
+                // Don't cross into the next chunk, so that the guest sees
+                // ranges that fit its hot add granularity.
+                let page_count = (target - current)
+                    .min(chunk_start + chunk_pages - start_page)
+                    .min((window.end / PAGE_SIZE).saturating_sub(start_page));
+                if page_count == 0 {
+                    return Ok(());
+                }
+                if let Err(err) = hot_add
+                    .hot_add(chunk_start * PAGE_SIZE, HOT_ADD_ALIGNMENT)
+                    .await
+                {
+                    tracelimit::error_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        gpa = chunk_start * PAGE_SIZE,
+                        "failed to hot add memory"
+                    );
+                    self.stalled = true;
+                    return Ok(());
+                }
+                self.send_request(
+                    send,
+                    protocol::MESSAGE_HOT_ADD_REQUEST,
+                    protocol::HotAddRequest {
+                        range: protocol::PageRange::new()
+                            .with_start_page(start_page)
+                            .with_page_count(page_count as u32),
+                    }
+                    .as_bytes(),
+                    &[],
+                )
+                .await?;
+                self.operation = Operation::HotAdd {
+                    start_page,
+                    page_count,
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds back the chunks of the hot add window that the guest onlined, or
+    /// was asked to online, before the channel was restored.
+    async fn readd_hot_added(&mut self, dm: &DynamicMemory) -> Result<(), Error> {
+        let Some(hot_add) = &dm.hot_add else {
+            self.pending_readd = false;
+            return Ok(());
+        };
+        let window = hot_add.hot_add_range();
+        let mut end_page = window.start / PAGE_SIZE + self.hot_added_pages;
+        if let Operation::HotAdd {
+            start_page,
+            page_count,
+        } = self.operation
+        {
+            end_page = end_page.max(start_page + page_count);
+        }
+        let mut gpa = window.start / HOT_ADD_ALIGNMENT * HOT_ADD_ALIGNMENT;
+        while gpa < end_page * PAGE_SIZE {
+            hot_add
+                .hot_add(gpa, HOT_ADD_ALIGNMENT)
+                .await
+                .map_err(Error::HotAdd)?;
+            gpa += HOT_ADD_ALIGNMENT;
+        }
+        self.pending_readd = false;
+        Ok(())
+    }
+
+    /// Removes up to `pages` pages from the balloon, most recently ballooned
+    /// first.
+    fn take_ballooned(&mut self, mut pages: u64) -> Vec<protocol::PageRange> {
+        let mut ranges = Vec::new();
+        while pages > 0 {
+            let Some(last) = self.ballooned.last_mut() else {
+                break;
+            };
+            let n = pages.min(last.page_count().into()) as u32;
+            let remaining = last.page_count() - n;
+            ranges.push(
+                protocol::PageRange::new()
+                    .with_start_page(last.start_page() + remaining as u64)
+                    .with_page_count(n),
+            );
+            if remaining == 0 {
+                self.ballooned.pop();
+            } else {
+                last.set_page_count(remaining);
+            }
+            pages -= n as u64;
+            self.ballooned_pages -= n as u64;
+        }
+        ranges
+    }
+
+    async fn send_request(
+        &mut self,
+        send: &mut impl AsyncSend,
+        message_type: u16,
+        body: &[u8],
+        trailer: &[u8],
+    ) -> Result<(), Error> {
+        let transaction_id = self.next_transaction_id;
+        self.next_transaction_id = self.next_transaction_id.wrapping_add(1);
+        send_message(send, message_type, transaction_id, body, trailer).await
+    }
+}
+
+async fn send_message(
+    send: &mut impl AsyncSend,
+    message_type: u16,
+    transaction_id: u32,
+    body: &[u8],
+    trailer: &[u8],
+) -> Result<(), Error> {
+    let size = size_of::<protocol::MessageHeader>() + body.len() + trailer.len();
+    let header = protocol::MessageHeader {
+        message_type,
+        size: size as u16,
+        transaction_id,
+    };
+    send.send_vectored(&[
+        IoSlice::new(header.as_bytes()),
+        IoSlice::new(body),
+        IoSlice::new(trailer),
+    ])
+    .await
+    .map_err(Error::Io)
+}
+
+#[async_trait]
+impl SimpleVmbusDevice for DynamicMemory {
+    type SavedState = save_restore::state::SavedState;
+    type Runner = DynamicMemoryChannel;
+
+    fn offer(&self) -> OfferParams {
+        OfferParams {
+            interface_name: "dynamic_memory".to_owned(),
+            instance_id: protocol::INSTANCE_ID,
+            interface_id: protocol::INTERFACE_ID,
+            ..Default::default()
+        }
+    }
+
+    fn inspect(&mut self, req: inspect::Request<'_>, runner: Option<&mut Self::Runner>) {
+        req.respond()
+            .merge(self)
+            .merge(runner.map(|runner| &runner.state));
+    }
+
+    fn open(
+        &mut self,
+        channel: RawAsyncChannel<GpadlRingMem>,
+        _guest_memory: guestmem::GuestMemory,
+    ) -> Result<Self::Runner, ChannelOpenError> {
+        let pipe = MessagePipe::new_raw(channel)?;
+        Ok(DynamicMemoryChannel::new(pipe))
+    }
+
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        runner: &mut Self::Runner,
+    ) -> Result<(), Cancelled> {
+        stop.until_stopped(async { runner.process(self).await })
+            .await
+    }
+
+    fn supports_save_restore(
+        &mut self,
+    ) -> Option<
+        &mut dyn SaveRestoreSimpleVmbusDevice<SavedState = Self::SavedState, Runner = Self::Runner>,
+    > {
+        Some(self)
+    }
+}
+
+mod save_restore {
+    use super::*;
+
+    pub mod state {
+        use mesh::payload::Protobuf;
+        use vmcore::save_restore::SavedStateRoot;
+
+        #[derive(Protobuf)]
+        #[mesh(package = "hyperv_dm")]
+        pub enum ChannelState {
+            #[mesh(1)]
+            Version,
+            #[mesh(2)]
+            Capabilities {
+                #[mesh(1)]
+                version: u32,
+            },
+            #[mesh(3)]
+            Ready(ReadyState),
+            #[mesh(4)]
+            Failed,
+        }
+
+        #[derive(Protobuf)]
+        #[mesh(package = "hyperv_dm")]
+        pub struct ReadyState {
+            #[mesh(1)]
+            pub version: u32,
+            #[mesh(2)]
+            pub capabilities: u64,
+            /// The ballooned page ranges, in protocol format.
+            #[mesh(3)]
+            pub ballooned: Vec<u64>,
+            #[mesh(4)]
+            pub hot_added_pages: u64,
+            #[mesh(5)]
+            pub operation: Operation,
+            #[mesh(6)]
+            pub stalled: bool,
+            #[mesh(7)]
+            pub next_transaction_id: u32,
+        }
+
+        #[derive(Protobuf)]
+        #[mesh(package = "hyperv_dm")]
+        pub enum Operation {
+            #[mesh(1)]
+            Idle,
+            #[mesh(2)]
+            Balloon {
+                #[mesh(1)]
+                requested: u64,
+                #[mesh(2)]
+                received: u64,
+            },
+            #[mesh(3)]
+            Unballoon {
+                #[mesh(1)]
+                pages: u64,
+            },
+            #[mesh(4)]
+            HotAdd {
+                #[mesh(1)]
+                start_page: u64,
+                #[mesh(2)]
+                page_count: u64,
+            },
+        }
+
+        #[derive(Protobuf, SavedStateRoot)]
+        #[mesh(package = "hyperv_dm")]
+        pub struct SavedState {
+            #[mesh(1)]
+            pub target: u64,
+            #[mesh(2)]
+            pub state: ChannelState,
+        }
+    }
+
+    impl DynamicMemory {
+        pub(crate) fn save_channel<T: RingMem>(
+            &self,
+            channel: &DynamicMemoryChannel<T>,
+        ) -> state::SavedState {
+            let state = match &channel.state {
+                ChannelState::Version => state::ChannelState::Version,
+                &ChannelState::Capabilities { version } => {
+                    state::ChannelState::Capabilities { version }
+                }
+                ChannelState::Ready(ready) => state::ChannelState::Ready(state::ReadyState {
+                    version: ready.version,
+                    capabilities: ready.capabilities.into_bits(),
+                    ballooned: ready.ballooned.iter().map(|r| r.into_bits()).collect(),
+                    hot_added_pages: ready.hot_added_pages,
+                    operation: match ready.operation {
+                        Operation::Idle => state::Operation::Idle,
+                        Operation::Balloon {
+                            requested,
+                            received,
+                        } => state::Operation::Balloon {
+                            requested,
+                            received,
+                        },
+                        Operation::Unballoon { pages } => state::Operation::Unballoon { pages },
+                        Operation::HotAdd {
+                            start_page,
+                            page_count,
+                        } => state::Operation::HotAdd {
+                            start_page,
+                            page_count,
+                        },
+                    },
+                    stalled: ready.stalled,
+                    next_transaction_id: ready.next_transaction_id,
+                }),
+                ChannelState::Failed => state::ChannelState::Failed,
+            };
+            state::SavedState {
+                target: self.target,
+                state,
+            }
+        }
+
+        pub(crate) fn restore_channel<T: RingMem + Unpin>(
+            &mut self,
+            saved_state: state::SavedState,
+            pipe: MessagePipe<T>,
+        ) -> DynamicMemoryChannel<T> {
+            let state::SavedState { target, state } = saved_state;
+            self.target = target.clamp(self.min_size, self.max_size);
+            let state = match state {
+                state::ChannelState::Version => ChannelState::Version,
+                state::ChannelState::Capabilities { version } => {
+                    ChannelState::Capabilities { version }
+                }
+                state::ChannelState::Ready(ready) => {
+                    let ballooned: Vec<_> = ready
+                        .ballooned
+                        .into_iter()
+                        .map(protocol::PageRange::from_bits)
+                        .collect();
+                    // The restored memory may be backed again, so release the
+                    // ballooned pages as if the guest had just returned them.
+                    for &range in &ballooned {
+                        self.reclaim(range);
+                    }
+                    ChannelState::Ready(ReadyState {
+                        version: ready.version,
+                        capabilities: protocol::Capabilities::from_bits(ready.capabilities),
+                        ballooned_pages: ballooned.iter().map(|r| r.page_count() as u64).sum(),
+                        ballooned,
+                        hot_added_pages: ready.hot_added_pages,
+                        operation: match ready.operation {
+                            state::Operation::Idle => Operation::Idle,
+                            state::Operation::Balloon {
+                                requested,
+                                received,
+                            } => Operation::Balloon {
+                                requested,
+                                received,
+                            },
+                            state::Operation::Unballoon { pages } => Operation::Unballoon { pages },
+                            state::Operation::HotAdd {
+                                start_page,
+                                page_count,
+                            } => Operation::HotAdd {
+                                start_page,
+                                page_count,
+                            },
+                        },
+                        status: None,
+                        stalled: ready.stalled,
+                        next_transaction_id: ready.next_transaction_id,
+                        pending_readd: true,
+                    })
+                }
+                state::ChannelState::Failed => ChannelState::Failed,
+            };
+            DynamicMemoryChannel { pipe, state }
+        }
+    }
+
+    impl SaveRestoreSimpleVmbusDevice for DynamicMemory {
+        fn save_open(&mut self, runner: &Self::Runner) -> state::SavedState {
+            self.save_channel(runner)
+        }
+
+        fn restore_open(
+            &mut self,
+            saved_state: Self::SavedState,
+            channel: RawAsyncChannel<GpadlRingMem>,
+        ) -> Result<Self::Runner, ChannelOpenError> {
+            let pipe = MessagePipe::new_raw(channel)?;
+            Ok(self.restore_channel(saved_state, pipe))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::rpc::RpcSend;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use pal_async::task::Spawn;
+    use parking_lot::Mutex;
+    use std::ops::Range;
+    use std::sync::Arc;
+    use test_with_tracing::test;
+    use vmbus_async::async_dgram::AsyncRecv;
+    use vmbus_async::pipe::connected_raw_message_pipes;
+    use vmcore::memory_hot_add::HotAddMemory;
+    use vmcore::memory_reclaim::ReclaimMemory;
+
+    const MB: u64 = 1 << 20;
+    const WINDOW: Range<u64> = 0x10_0000_0000..0x10_0000_0000 + 256 * MB;
+
+    struct TestReclaim(Arc<Mutex<Vec<(u64, u64)>>>);
+
+    impl ReclaimMemory for TestReclaim {
+        fn reclaim(&self, gpa: u64, len: u64) -> std::io::Result<()> {
+            self.0.lock().push((gpa, len));
+            Ok(())
+        }
+    }
+
+    struct TestHotAdd(Arc<Mutex<Vec<(u64, u64)>>>);
+
+    #[async_trait]
+    impl HotAddMemory for TestHotAdd {
+        fn hot_add_range(&self) -> Range<u64> {
+            WINDOW
+        }
+
+        async fn hot_add(&self, gpa: u64, len: u64) -> std::io::Result<()> {
+            self.0.lock().push((gpa, len));
+            Ok(())
+        }
+    }
+
+    async fn recv_message(guest: &mut (impl AsyncRecv + Unpin)) -> (u16, Vec<u8>) {
+        let mut buf = vec![0; protocol::MAX_MESSAGE_SIZE];
+        let n = guest.recv(&mut buf).await.unwrap();
+        let (header, _) = protocol::MessageHeader::read_from_prefix(&buf[..n]).unwrap();
+        assert_eq!(header.size as usize, n);
+        (header.message_type, buf[size_of_val(&header)..n].to_vec())
+    }
+
+    async fn negotiate(guest: &mut MessagePipe<impl RingMem + Unpin>, version: u32) -> bool {
+        send_message(
+            guest,
+            protocol::MESSAGE_VERSION_REQUEST,
+            0,
+            protocol::VersionRequest {
+                version,
+                flags: protocol::VersionRequestFlags::new(),
+            }
+            .as_bytes(),
+            &[],
+        )
+        .await
+        .unwrap();
+        let (message_type, body) = recv_message(guest).await;
+        assert_eq!(message_type, protocol::MESSAGE_VERSION_RESPONSE);
+        if body != [1] {
+            return false;
+        }
+
+        send_message(
+            guest,
+            protocol::MESSAGE_CAPABILITIES_REPORT,
+            1,
+            protocol::CapabilitiesReport {
+                capabilities: protocol::Capabilities::new()
+                    .with_balloon(true)
+                    .with_hot_add(true)
+                    .with_hot_add_alignment(7),
+                min_page_count: 0,
+                max_page_number: u64::MAX,
+            }
+            .as_bytes(),
+            &[],
+        )
+        .await
+        .unwrap();
+        let (message_type, body) = recv_message(guest).await;
+        assert_eq!(message_type, protocol::MESSAGE_CAPABILITIES_RESPONSE);
+        assert!(
+            protocol::CapabilitiesResponse::read_from_bytes(&body)
+                .unwrap()
+                .flags
+                .is_accepted()
+        );
+        true
+    }
+
+    fn start_device(
+        driver: &DefaultDriver,
+        host: MessagePipe<impl RingMem + Unpin + Send + 'static>,
+        mut dm: DynamicMemory,
+    ) -> pal_async::task::Task<()> {
+        driver.spawn("dynamic memory", async move {
+            DynamicMemoryChannel::new(host).process(&mut dm).await
+        })
+    }
+
+    #[async_test]
+    async fn reject_old_version(driver: DefaultDriver) {
+        let (host, mut guest) = connected_raw_message_pipes(16384);
+        let (_send, recv) = mesh::channel();
+        let reclaim = MemoryReclaim::new(TestReclaim(Default::default()));
+        let dm = DynamicMemory::new(recv, reclaim, None, 1024 * MB, 1024 * MB, 1024 * MB);
+        let _task = start_device(&driver, host, dm);
+
+        assert!(!negotiate(&mut guest, protocol::VERSION_WIN7).await);
+        assert!(negotiate(&mut guest, protocol::VERSION_WIN8).await);
+    }
+
+    #[async_test]
+    async fn resize(driver: DefaultDriver) {
+        let (host, mut guest) = connected_raw_message_pipes(16384);
+        let reclaimed = Arc::new(Mutex::new(Vec::new()));
+        let hot_added = Arc::new(Mutex::new(Vec::new()));
+        let (send, recv) = mesh::channel();
+        let dm = DynamicMemory::new(
+            recv,
+            MemoryReclaim::new(TestReclaim(reclaimed.clone())),
+            Some(MemoryHotAdd::new(TestHotAdd(hot_added.clone()))),
+            1024 * MB,
+            512 * MB,
+            2048 * MB,
+        );
+        let _task = start_device(&driver, host, dm);
+
+        assert!(negotiate(&mut guest, protocol::VERSION_WIN10).await);
+
+        // Shrink the guest.
+        send.call(DynamicMemoryRpc::SetTarget, 768 * MB)
+            .await
+            .unwrap();
+        let (message_type, body) = recv_message(&mut guest).await;
+        assert_eq!(message_type, protocol::MESSAGE_BALLOON_REQUEST);
+        let request = protocol::BalloonRequest::read_from_bytes(&body).unwrap();
+        assert_eq!(request.num_pages as u64, 256 * MB / PAGE_SIZE);
+        let range = protocol::PageRange::new()
+            .with_start_page(0x10000)
+            .with_page_count(request.num_pages);
+        send_message(
+            &mut guest,
+            protocol::MESSAGE_BALLOON_RESPONSE,
+            2,
+            protocol::BalloonResponse {
+                reserved: 0,
+                flags: protocol::RangeListFlags::new().with_range_count(1),
+            }
+            .as_bytes(),
+            range.as_bytes(),
+        )
+        .await
+        .unwrap();
+
+        // Grow the guest past its startup size. The ballooned pages are
+        // returned first.
+        send.call(DynamicMemoryRpc::SetTarget, 1088 * MB)
+            .await
+            .unwrap();
+        let (message_type, body) = recv_message(&mut guest).await;
+        assert_eq!(message_type, protocol::MESSAGE_UNBALLOON_REQUEST);
+        let (request, ranges) = protocol::UnballoonRequest::read_from_prefix(&body).unwrap();
+        assert!(!request.flags.more_pages());
+        assert_eq!(request.range_count, 1);
+        assert_eq!(
+            protocol::PageRange::read_from_bytes(ranges)
+                .unwrap()
+                .into_bits(),
+            range.into_bits()
+        );
+        assert_eq!(*reclaimed.lock(), [(0x10000 * PAGE_SIZE, 256 * MB)]);
+        send_message(
+            &mut guest,
+            protocol::MESSAGE_UNBALLOON_RESPONSE,
+            3,
+            &[],
+            &[],
+        )
+        .await
+        .unwrap();
+
+        let (message_type, body) = recv_message(&mut guest).await;
+        assert_eq!(message_type, protocol::MESSAGE_HOT_ADD_REQUEST);
+        let request = protocol::HotAddRequest::read_from_bytes(&body).unwrap();
+        assert_eq!(request.range.start_page(), WINDOW.start / PAGE_SIZE);
+        assert_eq!(request.range.page_count() as u64, 64 * MB / PAGE_SIZE);
+        assert_eq!(*hot_added.lock(), [(WINDOW.start, HOT_ADD_ALIGNMENT)]);
+        send_message(
+            &mut guest,
+            protocol::MESSAGE_HOT_ADD_RESPONSE,
+            4,
+            protocol::HotAddResponse {
+                page_count: request.range.page_count(),
+                result: 1,
+            }
+            .as_bytes(),
+            &[],
+        )
+        .await
+        .unwrap();
+
+        // The next hot add continues where the last one left off.
+        send.call(DynamicMemoryRpc::SetTarget, 1088 * MB + PAGE_SIZE)
+            .await
+            .unwrap();
+        let (message_type, body) = recv_message(&mut guest).await;
+        assert_eq!(message_type, protocol::MESSAGE_HOT_ADD_REQUEST);
+        let request = protocol::HotAddRequest::read_from_bytes(&body).unwrap();
+        assert_eq!(
+            request.range.start_page(),
+            (WINDOW.start + 64 * MB) / PAGE_SIZE
+        );
+        assert_eq!(request.range.page_count(), 1);
+
+        let status = send.call(DynamicMemoryRpc::Status, ()).await.unwrap();
+        assert_eq!(status.target, 1088 * MB + PAGE_SIZE);
+        assert_eq!(status.current, 1088 * MB);
+        assert_eq!(status.ballooned, 0);
+        assert_eq!(status.hot_added, 64 * MB);
+    }
+
+    #[async_test]
+    async fn save_restore(driver: DefaultDriver) {
+        let (host, mut guest) = connected_raw_message_pipes(16384);
+        let (send, recv) = mesh::channel();
+        let mut dm = DynamicMemory::new(
+            recv,
+            MemoryReclaim::new(TestReclaim(Default::default())),
+            Some(MemoryHotAdd::new(TestHotAdd(Default::default()))),
+            1024 * MB,
+            512 * MB,
+            2048 * MB,
+        );
+        let (stop_send, stop_recv) = mesh::oneshot::<()>();
+        let task = driver.spawn("dynamic memory", async move {
+            let mut channel = DynamicMemoryChannel::new(host);
+            {
+                let process = std::pin::pin!(channel.process(&mut dm));
+                futures::future::select(process, stop_recv).await;
+            }
+            (dm, channel)
+        });
+
+        assert!(negotiate(&mut guest, protocol::VERSION_WIN10).await);
+
+        // Hot add a chunk, then balloon more than was added.
+        send.call(DynamicMemoryRpc::SetTarget, 1088 * MB)
+            .await
+            .unwrap();
+        let (message_type, body) = recv_message(&mut guest).await;
+        assert_eq!(message_type, protocol::MESSAGE_HOT_ADD_REQUEST);
+        let request = protocol::HotAddRequest::read_from_bytes(&body).unwrap();
+        send_message(
+            &mut guest,
+            protocol::MESSAGE_HOT_ADD_RESPONSE,
+            2,
+            protocol::HotAddResponse {
+                page_count: request.range.page_count(),
+                result: 1,
+            }
+            .as_bytes(),
+            &[],
+        )
+        .await
+        .unwrap();
+
+        send.call(DynamicMemoryRpc::SetTarget, 768 * MB)
+            .await
+            .unwrap();
+        let (message_type, body) = recv_message(&mut guest).await;
+        assert_eq!(message_type, protocol::MESSAGE_BALLOON_REQUEST);
+        let request = protocol::BalloonRequest::read_from_bytes(&body).unwrap();
+        assert_eq!(request.num_pages as u64, 320 * MB / PAGE_SIZE);
+        let range = protocol::PageRange::new()
+            .with_start_page(0x10000)
+            .with_page_count(request.num_pages);
+        send_message(
+            &mut guest,
+            protocol::MESSAGE_BALLOON_RESPONSE,
+            3,
+            protocol::BalloonResponse {
+                reserved: 0,
+                flags: protocol::RangeListFlags::new().with_range_count(1),
+            }
+            .as_bytes(),
+            range.as_bytes(),
+        )
+        .await
+        .unwrap();
+        let status = send.call(DynamicMemoryRpc::Status, ()).await.unwrap();
+        assert_eq!(status.ballooned, 320 * MB);
+
+        stop_send.send(());
+        let (dm, channel) = task.await;
+        let saved_state = dm.save_channel(&channel);
+
+        // Restore into a new device, which must release the ballooned pages
+        // and add back the hot added chunk.
+        let reclaimed = Arc::new(Mutex::new(Vec::new()));
+        let hot_added = Arc::new(Mutex::new(Vec::new()));
+        let (send, recv) = mesh::channel();
+        let mut dm = DynamicMemory::new(
+            recv,
+            MemoryReclaim::new(TestReclaim(reclaimed.clone())),
+            Some(MemoryHotAdd::new(TestHotAdd(hot_added.clone()))),
+            1024 * MB,
+            512 * MB,
+            2048 * MB,
+        );
+        let channel = dm.restore_channel(saved_state, channel.pipe);
+        assert_eq!(*reclaimed.lock(), [(0x10000 * PAGE_SIZE, 320 * MB)]);
+        let _task = driver.spawn("dynamic memory", async move {
+            let mut channel = channel;
+            channel.process(&mut dm).await
+        });
+
+        let status = send.call(DynamicMemoryRpc::Status, ()).await.unwrap();
+        assert_eq!(status.target, 768 * MB);
+        assert_eq!(status.current, 768 * MB);
+        assert_eq!(status.ballooned, 320 * MB);
+        assert_eq!(status.hot_added, 64 * MB);
+        assert_eq!(*hot_added.lock(), [(WINDOW.start, HOT_ADD_ALIGNMENT)]);
+
+        // The restored balloon is returned to the guest.
+        send.call(DynamicMemoryRpc::SetTarget, 1088 * MB)
+            .await
+            .unwrap();
+        let (message_type, body) = recv_message(&mut guest).await;
+        assert_eq!(message_type, protocol::MESSAGE_UNBALLOON_REQUEST);
+        let (request, ranges) = protocol::UnballoonRequest::read_from_prefix(&body).unwrap();
+        assert_eq!(request.range_count, 1);
+        assert_eq!(
+            protocol::PageRange::read_from_bytes(ranges)
+                .unwrap()
+                .into_bits(),
+            range.into_bits()
+        );
+    }
+}
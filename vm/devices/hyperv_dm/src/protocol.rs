@@ -0,0 +1,193 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Definitions for the Hyper-V dynamic memory protocol.
+
+#![expect(dead_code)]
+
+use bitfield_struct::bitfield;
+use guid::Guid;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+/// The interface ID for the dynamic memory device.
+pub const INTERFACE_ID: Guid = guid::guid!("525074dc-8985-46e2-8057-a307dc18a502");
+
+/// The instance ID for the dynamic memory device.
+pub const INSTANCE_ID: Guid = guid::guid!("9b3d2a4e-4f26-4a9b-8a6c-2f1c4d7e8b35");
+
+/// The protocol always uses 4KB pages, regardless of the guest's page size.
+pub const PAGE_SIZE: u64 = 4096;
+
+const fn make_version(major: u16, minor: u16) -> u32 {
+    (major as u32) << 16 | minor as u32
+}
+
+pub const VERSION_WIN7: u32 = make_version(0, 3);
+pub const VERSION_WIN8: u32 = make_version(1, 0);
+pub const VERSION_WIN10: u32 = make_version(2, 0);
+
+/// The largest message either side sends. The Linux driver receives into a
+/// single page.
+pub const MAX_MESSAGE_SIZE: usize = 4096;
+
+pub const MESSAGE_ERROR: u16 = 0;
+pub const MESSAGE_VERSION_REQUEST: u16 = 1;
+pub const MESSAGE_VERSION_RESPONSE: u16 = 2;
+pub const MESSAGE_CAPABILITIES_REPORT: u16 = 3;
+pub const MESSAGE_CAPABILITIES_RESPONSE: u16 = 4;
+pub const MESSAGE_STATUS_REPORT: u16 = 5;
+pub const MESSAGE_BALLOON_REQUEST: u16 = 6;
+pub const MESSAGE_BALLOON_RESPONSE: u16 = 7;
+pub const MESSAGE_UNBALLOON_REQUEST: u16 = 8;
+pub const MESSAGE_UNBALLOON_RESPONSE: u16 = 9;
+pub const MESSAGE_HOT_ADD_REQUEST: u16 = 10;
+pub const MESSAGE_HOT_ADD_RESPONSE: u16 = 11;
+pub const MESSAGE_INFO: u16 = 12;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct MessageHeader {
+    pub message_type: u16,
+    /// The size of the message, including the header.
+    pub size: u16,
+    pub transaction_id: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VersionRequest {
+    pub version: u32,
+    pub flags: VersionRequestFlags,
+}
+
+#[bitfield(u32)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VersionRequestFlags {
+    pub is_last_attempt: bool,
+    #[bits(31)]
+    _reserved: u32,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VersionResponse {
+    pub is_accepted: u8,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct CapabilitiesReport {
+    pub capabilities: Capabilities,
+    /// The minimum number of pages the guest needs to operate.
+    pub min_page_count: u64,
+    /// The highest page number the guest can address.
+    pub max_page_number: u64,
+}
+
+#[bitfield(u64)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct Capabilities {
+    pub balloon: bool,
+    pub hot_add: bool,
+    /// The required hot add alignment, as a power of two number of megabytes.
+    #[bits(4)]
+    pub hot_add_alignment: u8,
+    #[bits(58)]
+    _reserved: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct CapabilitiesResponse {
+    pub flags: CapabilitiesResponseFlags,
+}
+
+#[bitfield(u64)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct CapabilitiesResponseFlags {
+    pub is_accepted: bool,
+    pub hot_remove: bool,
+    pub suppress_pressure_reports: bool,
+    #[bits(61)]
+    _reserved: u64,
+}
+
+/// A periodic report of the guest's memory state, in pages.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct StatusReport {
+    pub num_available: u64,
+    pub num_committed: u64,
+    pub page_file_size: u64,
+    pub zero_free: u64,
+    pub page_file_writes: u32,
+    pub io_diff: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct BalloonRequest {
+    pub num_pages: u32,
+    pub reserved: u32,
+}
+
+/// Followed by `range_count` [`PageRange`]s.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct BalloonResponse {
+    pub reserved: u32,
+    pub flags: RangeListFlags,
+}
+
+#[bitfield(u32)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct RangeListFlags {
+    /// More messages follow to complete the operation.
+    pub more_pages: bool,
+    #[bits(31)]
+    pub range_count: u32,
+}
+
+/// Followed by `range_count` [`PageRange`]s.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct UnballoonRequest {
+    pub flags: UnballoonRequestFlags,
+    pub range_count: u32,
+}
+
+#[bitfield(u32)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct UnballoonRequestFlags {
+    /// More messages follow to complete the operation.
+    pub more_pages: bool,
+    #[bits(31)]
+    _reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct HotAddRequest {
+    pub range: PageRange,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct HotAddResponse {
+    /// The number of pages the guest added.
+    pub page_count: u32,
+    /// 1 on success.
+    pub result: u32,
+}
+
+#[bitfield(u64)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct PageRange {
+    #[bits(40)]
+    pub start_page: u64,
+    #[bits(24)]
+    pub page_count: u32,
+}
@@ -0,0 +1,67 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource resolver for the dynamic memory device.
+
+use crate::DynamicMemory;
+use anyhow::Context as _;
+use async_trait::async_trait;
+use hyperv_dm_resources::DynamicMemoryHandle;
+use vm_resource::AsyncResolveResource;
+use vm_resource::IntoResource;
+use vm_resource::PlatformResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::VmbusDeviceHandleKind;
+use vmbus_channel::resources::ResolveVmbusDeviceHandleParams;
+use vmbus_channel::resources::ResolvedVmbusDevice;
+use vmbus_channel::simple::SimpleDeviceWrapper;
+use vmcore::memory_hot_add::MemoryHotAddKind;
+use vmcore::memory_reclaim::MemoryReclaimKind;
+
+/// Resource resolver for the dynamic memory device.
+pub struct DynamicMemoryResolver;
+
+declare_static_async_resolver! {
+    DynamicMemoryResolver,
+    (VmbusDeviceHandleKind, DynamicMemoryHandle),
+}
+
+#[async_trait]
+impl AsyncResolveResource<VmbusDeviceHandleKind, DynamicMemoryHandle> for DynamicMemoryResolver {
+    type Output = ResolvedVmbusDevice;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: DynamicMemoryHandle,
+        input: ResolveVmbusDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let reclaim = resolver
+            .resolve::<MemoryReclaimKind, _>(PlatformResource.into_resource(), ())
+            .await
+            .context("failed to resolve memory reclaim")?;
+
+        let hot_add = if resource.max_size > resource.startup_size {
+            Some(
+                resolver
+                    .resolve::<MemoryHotAddKind, _>(PlatformResource.into_resource(), ())
+                    .await
+                    .context("failed to resolve memory hot add")?,
+            )
+        } else {
+            None
+        };
+
+        let device = DynamicMemory::new(
+            resource.recv,
+            reclaim,
+            hot_add,
+            resource.startup_size,
+            resource.min_size,
+            resource.max_size,
+        );
+        Ok(SimpleDeviceWrapper::new(input.driver_source.simple(), device).into())
+    }
+}
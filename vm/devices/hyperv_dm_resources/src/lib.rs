@@ -0,0 +1,62 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource definitions for the Hyper-V dynamic memory device.
+
+#![forbid(unsafe_code)]
+
+use mesh::MeshPayload;
+use mesh::rpc::Rpc;
+use vm_resource::ResourceId;
+use vm_resource::kind::VmbusDeviceHandleKind;
+
+/// A handle to a dynamic memory device.
+#[derive(MeshPayload)]
+pub struct DynamicMemoryHandle {
+    /// The amount of RAM the VM starts with, in bytes.
+    pub startup_size: u64,
+    /// The smallest amount of RAM the guest can be ballooned down to, in bytes.
+    pub min_size: u64,
+    /// The largest amount of RAM the guest can grow to, in bytes.
+    ///
+    /// If this is larger than `startup_size`, then the platform must provide a
+    /// hot add window of at least the difference.
+    pub max_size: u64,
+    /// The channel by which to receive requests from the host.
+    pub recv: mesh::Receiver<DynamicMemoryRpc>,
+}
+
+impl ResourceId<VmbusDeviceHandleKind> for DynamicMemoryHandle {
+    const ID: &'static str = "dynamic_memory";
+}
+
+/// A request to the dynamic memory device.
+#[derive(MeshPayload)]
+pub enum DynamicMemoryRpc {
+    /// Sets the amount of RAM the guest should have, in bytes.
+    ///
+    /// The target is clamped to the device's minimum and maximum sizes. The
+    /// device balloons the guest down or hands memory back (by unballooning
+    /// and then hot adding) until the guest reaches the target or refuses to
+    /// make progress.
+    SetTarget(Rpc<u64, ()>),
+    /// Gets the current state of the device.
+    Status(Rpc<(), DynamicMemoryStatus>),
+}
+
+/// The state of the dynamic memory device.
+#[derive(Debug, MeshPayload)]
+pub struct DynamicMemoryStatus {
+    /// The requested amount of guest RAM, in bytes.
+    pub target: u64,
+    /// The amount of RAM currently available to the guest, in bytes.
+    pub current: u64,
+    /// The amount of RAM held by the guest's balloon, in bytes.
+    pub ballooned: u64,
+    /// The amount of RAM hot added to the guest, in bytes.
+    pub hot_added: u64,
+    /// The guest's most recently reported committed memory, in bytes.
+    pub guest_committed: Option<u64>,
+    /// The guest's most recently reported available memory, in bytes.
+    pub guest_available: Option<u64>,
+}
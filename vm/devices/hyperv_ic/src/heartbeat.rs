@@ -0,0 +1,357 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The heartbeat IC.
+//!
+//! The guest is periodically sent a heartbeat request containing a sequence
+//! number, and it responds with the sequence number incremented by one. The
+//! first valid response after the channel is opened is reported as a
+//! [`GuestEvent::HeartbeatUp`] event.
+
+use crate::common::IcPipe;
+use crate::common::NegotiateState;
+use crate::common::Versions;
+use anyhow::Context;
+use async_trait::async_trait;
+use guestmem::GuestMemory;
+use hyperv_ic_protocol::heartbeat as proto;
+use inspect::Inspect;
+use inspect::InspectMut;
+use pal_async::driver::Driver;
+use pal_async::timer::Instant;
+use pal_async::timer::PolledTimer;
+use std::future::pending;
+use task_control::Cancelled;
+use task_control::StopTask;
+use vmbus_channel::RawAsyncChannel;
+use vmbus_channel::bus::ChannelType;
+use vmbus_channel::bus::OfferParams;
+use vmbus_channel::channel::ChannelOpenError;
+use vmbus_channel::gpadl_ring::GpadlRingMem;
+use vmbus_channel::simple::SaveRestoreSimpleVmbusDevice;
+use vmbus_channel::simple::SimpleVmbusDevice;
+use vmcore::guest_event::GuestEvent;
+use vmcore::guest_event::GuestEventSink;
+use zerocopy::FromBytes;
+use zerocopy::IntoBytes;
+
+const HEARTBEAT_VERSIONS: &[hyperv_ic_protocol::Version] =
+    &[proto::HEARTBEAT_VERSION_3, proto::HEARTBEAT_VERSION_1];
+
+/// Send a heartbeat request every second.
+const HEARTBEAT_PERIOD: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Heartbeat IC device.
+#[derive(InspectMut)]
+#[non_exhaustive]
+pub struct HeartbeatIc {
+    #[inspect(skip)]
+    timer: PolledTimer,
+    #[inspect(skip)]
+    events: GuestEventSink,
+}
+
+#[doc(hidden)]
+#[derive(InspectMut)]
+pub struct HeartbeatChannel {
+    #[inspect(mut)]
+    pipe: IcPipe,
+    state: ChannelState,
+    /// The sequence number of the last request sent to the guest.
+    sequence_number: u64,
+    /// Whether the guest has responded to a heartbeat request since the
+    /// channel was opened.
+    up: bool,
+}
+
+#[derive(Inspect)]
+#[inspect(external_tag)]
+enum ChannelState {
+    Negotiate(#[inspect(rename = "state")] NegotiateState),
+    Ready {
+        versions: Versions,
+        state: ReadyState,
+    },
+    Failed,
+}
+
+#[derive(Inspect)]
+#[inspect(external_tag)]
+enum ReadyState {
+    SleepUntilNextRequest {
+        #[inspect(skip)]
+        next_request: Instant,
+    },
+    SendRequest,
+    WaitForResponse,
+}
+
+impl HeartbeatIc {
+    /// Create a new heartbeat IC, reporting to `events` when the guest starts
+    /// responding.
+    pub fn new(driver: &(impl Driver + ?Sized), events: GuestEventSink) -> Self {
+        Self {
+            timer: PolledTimer::new(driver),
+            events,
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleVmbusDevice for HeartbeatIc {
+    type SavedState = save_restore::state::SavedState;
+    type Runner = HeartbeatChannel;
+
+    fn offer(&self) -> OfferParams {
+        OfferParams {
+            interface_name: "heartbeat_ic".to_owned(),
+            instance_id: proto::INSTANCE_ID,
+            interface_id: proto::INTERFACE_ID,
+            channel_type: ChannelType::Pipe { message_mode: true },
+            ..Default::default()
+        }
+    }
+
+    fn inspect(&mut self, req: inspect::Request<'_>, runner: Option<&mut Self::Runner>) {
+        req.respond().merge(self).merge(runner);
+    }
+
+    fn open(
+        &mut self,
+        channel: RawAsyncChannel<GpadlRingMem>,
+        _guest_memory: GuestMemory,
+    ) -> Result<Self::Runner, ChannelOpenError> {
+        HeartbeatChannel::new(channel, None, 0, false)
+    }
+
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        runner: &mut Self::Runner,
+    ) -> Result<(), Cancelled> {
+        stop.until_stopped(async { runner.process(self).await })
+            .await
+    }
+
+    fn supports_save_restore(
+        &mut self,
+    ) -> Option<
+        &mut dyn SaveRestoreSimpleVmbusDevice<SavedState = Self::SavedState, Runner = Self::Runner>,
+    > {
+        Some(self)
+    }
+}
+
+impl HeartbeatChannel {
+    fn new(
+        channel: RawAsyncChannel<GpadlRingMem>,
+        restore_state: Option<ChannelState>,
+        sequence_number: u64,
+        up: bool,
+    ) -> Result<Self, ChannelOpenError> {
+        let pipe = IcPipe::new(channel)?;
+        Ok(Self {
+            pipe,
+            state: restore_state.unwrap_or(ChannelState::Negotiate(NegotiateState::default())),
+            sequence_number,
+            up,
+        })
+    }
+
+    async fn process(&mut self, ic: &mut HeartbeatIc) -> ! {
+        loop {
+            if let Err(err) = self.process_state_machine(ic).await {
+                tracing::error!(
+                    error = err.as_ref() as &dyn std::error::Error,
+                    "heartbeat ic error"
+                );
+                self.state = ChannelState::Failed;
+            }
+        }
+    }
+
+    async fn process_state_machine(&mut self, ic: &mut HeartbeatIc) -> anyhow::Result<()> {
+        match self.state {
+            ChannelState::Negotiate(ref mut state) => {
+                if let Some(versions) = self.pipe.negotiate(state, HEARTBEAT_VERSIONS).await? {
+                    tracelimit::info_ratelimited!(
+                        framework = %versions.framework_version,
+                        version = %versions.message_version,
+                        "heartbeat versions negotiated"
+                    );
+                    self.state = ChannelState::Ready {
+                        versions,
+                        state: ReadyState::SendRequest,
+                    };
+                }
+            }
+            ChannelState::Ready {
+                ref versions,
+                ref mut state,
+            } => match *state {
+                ReadyState::SleepUntilNextRequest { next_request } => {
+                    ic.timer.sleep_until(next_request).await;
+                    *state = ReadyState::SendRequest;
+                }
+                ReadyState::SendRequest => {
+                    self.sequence_number = self.sequence_number.wrapping_add(1);
+                    let message = proto::HeartbeatMessage {
+                        sequence_number: self.sequence_number,
+                        application_state: proto::ApplicationState::HEALTHY,
+                        reserved: [0; 4],
+                    };
+                    self.pipe
+                        .write_message(
+                            versions,
+                            hyperv_ic_protocol::MessageType::HEARTBEAT,
+                            hyperv_ic_protocol::HeaderFlags::new()
+                                .with_request(true)
+                                .with_transaction(true),
+                            message.as_bytes(),
+                        )
+                        .await?;
+                    *state = ReadyState::WaitForResponse;
+                }
+                ReadyState::WaitForResponse => {
+                    let (_, body) = self.pipe.read_response().await?;
+                    let (response, _) = proto::HeartbeatMessage::read_from_prefix(body)
+                        .ok()
+                        .context("heartbeat response too short")?;
+                    if response.sequence_number == self.sequence_number.wrapping_add(1) {
+                        if !self.up {
+                            tracing::info!(
+                                application_state = response.application_state.0,
+                                "guest heartbeat up"
+                            );
+                            self.up = true;
+                            ic.events.report(GuestEvent::HeartbeatUp);
+                        }
+                    } else {
+                        tracelimit::warn_ratelimited!(
+                            expected = self.sequence_number.wrapping_add(1),
+                            actual = response.sequence_number,
+                            "unexpected heartbeat sequence number"
+                        );
+                    }
+                    *state = ReadyState::SleepUntilNextRequest {
+                        next_request: Instant::now() + HEARTBEAT_PERIOD,
+                    };
+                }
+            },
+            ChannelState::Failed => pending().await,
+        }
+        Ok(())
+    }
+}
+
+mod save_restore {
+    use super::*;
+
+    pub mod state {
+        use mesh::payload::Protobuf;
+        use vmcore::save_restore::SavedStateRoot;
+
+        #[derive(Copy, Clone, Eq, PartialEq, Protobuf)]
+        #[mesh(package = "heartbeat_ic")]
+        pub struct Version {
+            #[mesh(1)]
+            pub major: u16,
+            #[mesh(2)]
+            pub minor: u16,
+        }
+
+        impl From<hyperv_ic_protocol::Version> for Version {
+            fn from(version: hyperv_ic_protocol::Version) -> Self {
+                Self {
+                    major: version.major,
+                    minor: version.minor,
+                }
+            }
+        }
+
+        impl From<Version> for hyperv_ic_protocol::Version {
+            fn from(version: Version) -> Self {
+                Self {
+                    major: version.major,
+                    minor: version.minor,
+                }
+            }
+        }
+
+        #[derive(Protobuf, SavedStateRoot)]
+        #[mesh(package = "heartbeat_ic")]
+        pub struct SavedState {
+            #[mesh(1)]
+            pub version: Option<(Version, Version)>,
+            #[mesh(2)]
+            pub waiting_on_version: bool,
+            #[mesh(3)]
+            pub waiting_on_response: bool,
+            #[mesh(4)]
+            pub sequence_number: u64,
+            #[mesh(5)]
+            pub up: bool,
+        }
+    }
+
+    impl SaveRestoreSimpleVmbusDevice for HeartbeatIc {
+        fn save_open(&mut self, runner: &Self::Runner) -> state::SavedState {
+            let (version, waiting_on_response) = match runner.state {
+                ChannelState::Ready {
+                    versions,
+                    ref state,
+                } => (
+                    Some((
+                        versions.framework_version.into(),
+                        versions.message_version.into(),
+                    )),
+                    matches!(state, ReadyState::WaitForResponse),
+                ),
+                ChannelState::Negotiate(_) | ChannelState::Failed => (None, false),
+            };
+            let waiting_on_version = matches!(
+                runner.state,
+                ChannelState::Negotiate(NegotiateState::WaitVersion)
+            );
+            state::SavedState {
+                version,
+                waiting_on_version,
+                waiting_on_response,
+                sequence_number: runner.sequence_number,
+                up: runner.up,
+            }
+        }
+
+        fn restore_open(
+            &mut self,
+            saved_state: Self::SavedState,
+            channel: RawAsyncChannel<GpadlRingMem>,
+        ) -> Result<Self::Runner, ChannelOpenError> {
+            let state = if let Some((framework, message)) = saved_state.version {
+                ChannelState::Ready {
+                    versions: Versions {
+                        framework_version: framework.into(),
+                        message_version: message.into(),
+                    },
+                    state: if saved_state.waiting_on_response {
+                        ReadyState::WaitForResponse
+                    } else {
+                        ReadyState::SendRequest
+                    },
+                }
+            } else {
+                ChannelState::Negotiate(if saved_state.waiting_on_version {
+                    NegotiateState::WaitVersion
+                } else {
+                    NegotiateState::SendVersion
+                })
+            };
+            HeartbeatChannel::new(
+                channel,
+                Some(state),
+                saved_state.sequence_number,
+                saved_state.up,
+            )
+        }
+    }
+}
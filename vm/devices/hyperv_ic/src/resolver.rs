@@ -3,14 +3,19 @@
 
 //! Resource resolvers for the ICs.
 
+use crate::heartbeat::HeartbeatIc;
 use crate::kvp::KvpIc;
 use crate::shutdown::ShutdownIc;
 use crate::timesync::TimesyncIc;
+use crate::vss::VssIc;
 use anyhow::Context as _;
 use async_trait::async_trait;
+use hyperv_ic_resources::heartbeat::HeartbeatIcHandle;
 use hyperv_ic_resources::kvp::KvpIcHandle;
 use hyperv_ic_resources::shutdown::ShutdownIcHandle;
 use hyperv_ic_resources::timesync::TimesyncIcHandle;
+use hyperv_ic_resources::vss::VssIcHandle;
+use power_resources::PowerRequestHandleKind;
 use std::convert::Infallible;
 use vm_resource::AsyncResolveResource;
 use vm_resource::IntoResource;
@@ -23,29 +28,41 @@
 use vmbus_channel::resources::ResolveVmbusDeviceHandleParams;
 use vmbus_channel::resources::ResolvedVmbusDevice;
 use vmbus_channel::simple::SimpleDeviceWrapper;
+use vmcore::guest_event::GuestEventKind;
 use vmcore::reference_time::ReferenceTimeSourceKind;
 
 /// Resource resolver for the shutdown IC.
 pub struct ShutdownIcResolver;
 
-declare_static_resolver! {
+declare_static_async_resolver! {
     ShutdownIcResolver,
     (VmbusDeviceHandleKind, ShutdownIcHandle),
 }
 
-impl ResolveResource<VmbusDeviceHandleKind, ShutdownIcHandle> for ShutdownIcResolver {
+#[async_trait]
+impl AsyncResolveResource<VmbusDeviceHandleKind, ShutdownIcHandle> for ShutdownIcResolver {
     type Output = ResolvedVmbusDevice;
-    type Error = Infallible;
+    type Error = anyhow::Error;
 
-    fn resolve(
+    async fn resolve(
         &self,
+        resolver: &ResourceResolver,
         resource: ShutdownIcHandle,
         input: ResolveVmbusDeviceHandleParams<'_>,
     ) -> Result<Self::Output, Self::Error> {
-        Ok(
-            SimpleDeviceWrapper::new(input.driver_source.simple(), ShutdownIc::new(resource.recv))
-                .into(),
-        )
+        let power = resolver
+            .resolve::<PowerRequestHandleKind, _>(PlatformResource.into_resource(), ())
+            .await
+            .context("failed to resolve power request client")?;
+
+        let events = resolver
+            .resolve::<GuestEventKind, _>(PlatformResource.into_resource(), ())
+            .await
+            .context("failed to resolve guest event sink")?;
+
+        let driver = input.driver_source.simple();
+        let ic = ShutdownIc::new(&driver, resource.recv, power, events);
+        Ok(SimpleDeviceWrapper::new(driver, ic).into())
     }
 }
 
@@ -73,6 +90,30 @@ fn resolve(
     }
 }
 
+/// Resource resolver for the VSS IC.
+pub struct VssIcResolver;
+
+declare_static_resolver! {
+    VssIcResolver,
+    (VmbusDeviceHandleKind, VssIcHandle),
+}
+
+impl ResolveResource<VmbusDeviceHandleKind, VssIcHandle> for VssIcResolver {
+    type Output = ResolvedVmbusDevice;
+    type Error = Infallible;
+
+    fn resolve(
+        &self,
+        resource: VssIcHandle,
+        input: ResolveVmbusDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(
+            SimpleDeviceWrapper::new(input.driver_source.simple(), VssIc::new(resource.recv))
+                .into(),
+        )
+    }
+}
+
 /// Resource resolver for the timesync IC.
 pub struct TimesyncIcResolver;
 
@@ -104,3 +145,35 @@ async fn resolve(
         .into())
     }
 }
+
+/// Resource resolver for the heartbeat IC.
+pub struct HeartbeatIcResolver;
+
+declare_static_async_resolver! {
+    HeartbeatIcResolver,
+    (VmbusDeviceHandleKind, HeartbeatIcHandle),
+}
+
+#[async_trait]
+impl AsyncResolveResource<VmbusDeviceHandleKind, HeartbeatIcHandle> for HeartbeatIcResolver {
+    type Output = ResolvedVmbusDevice;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        HeartbeatIcHandle: HeartbeatIcHandle,
+        input: ResolveVmbusDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let events = resolver
+            .resolve::<GuestEventKind, _>(PlatformResource.into_resource(), ())
+            .await
+            .context("failed to resolve guest event sink")?;
+
+        Ok(SimpleDeviceWrapper::new(
+            input.driver_source.simple(),
+            HeartbeatIc::new(&input.driver_source.simple(), events),
+        )
+        .into())
+    }
+}
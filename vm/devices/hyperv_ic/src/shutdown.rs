@@ -10,19 +10,29 @@
 use futures::FutureExt;
 use futures::StreamExt;
 use futures::stream::once;
+use futures_concurrency::future::Race;
 use futures_concurrency::stream::Merge;
 use hyperv_ic_protocol::Status;
 use hyperv_ic_protocol::shutdown::SHUTDOWN_VERSION_1;
 use hyperv_ic_protocol::shutdown::SHUTDOWN_VERSION_3;
 use hyperv_ic_protocol::shutdown::SHUTDOWN_VERSION_3_1;
 use hyperv_ic_protocol::shutdown::SHUTDOWN_VERSION_3_2;
+use hyperv_ic_resources::shutdown::FallbackShutdownParams;
 use hyperv_ic_resources::shutdown::ShutdownParams;
+use hyperv_ic_resources::shutdown::ShutdownProgress;
 use hyperv_ic_resources::shutdown::ShutdownResult;
 use hyperv_ic_resources::shutdown::ShutdownRpc;
 use hyperv_ic_resources::shutdown::ShutdownType;
 use inspect::Inspect;
 use inspect::InspectMut;
 use mesh::rpc::Rpc;
+use mesh::rpc::RpcSend;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use pal_async::timer::Instant;
+use pal_async::timer::PolledTimer;
+use power_resources::PowerRequest;
+use power_resources::PowerRequestClient;
 use std::pin::pin;
 use task_control::Cancelled;
 use task_control::StopTask;
@@ -33,6 +43,9 @@
 use vmbus_channel::gpadl_ring::GpadlRingMem;
 use vmbus_channel::simple::SaveRestoreSimpleVmbusDevice;
 use vmbus_channel::simple::SimpleVmbusDevice;
+use vmcore::guest_event::GuestEvent;
+use vmcore::guest_event::GuestEventSink;
+use vmcore::vm_task::VmTaskDriver;
 use zerocopy::IntoBytes;
 
 const SHUTDOWN_VERSIONS: &[hyperv_ic_protocol::Version] = &[
@@ -49,6 +62,10 @@ pub struct ShutdownIc {
     recv: mesh::Receiver<ShutdownRpc>,
     #[inspect(skip)]
     wait_ready: Vec<Rpc<(), mesh::OneshotReceiver<()>>>,
+    #[inspect(skip)]
+    events: GuestEventSink,
+    #[inspect(skip)]
+    _fallback_task: Task<()>,
 }
 
 #[doc(hidden)]
@@ -83,11 +100,138 @@ enum ReadyState {
 
 impl ShutdownIc {
     /// Returns a new shutdown IC, using `recv` to receive shutdown requests.
-    pub fn new(recv: mesh::Receiver<ShutdownRpc>) -> Self {
+    ///
+    /// `power` is used to force the power state change when the guest does not
+    /// comply with a [`ShutdownRpc::ShutdownWithFallback`] request in time.
+    /// Shutdown requests accepted by the guest are reported to `events`.
+    pub fn new(
+        driver: &VmTaskDriver,
+        recv: mesh::Receiver<ShutdownRpc>,
+        power: PowerRequestClient,
+        events: GuestEventSink,
+    ) -> Self {
+        // Fallback requests are handled by a separate task, since they must
+        // make progress even if the guest never opens the channel.
+        let (send, ic_recv) = mesh::channel();
+        let fallback_task = driver.spawn(
+            "shutdown-ic-fallback",
+            handle_fallback_requests(driver.clone(), recv, send, power),
+        );
         Self {
-            recv,
+            recv: ic_recv,
             wait_ready: Vec::new(),
+            events,
+            _fallback_task: fallback_task,
+        }
+    }
+}
+
+/// Forwards requests to the IC, handling fallback requests along the way.
+async fn handle_fallback_requests(
+    driver: VmTaskDriver,
+    mut recv: mesh::Receiver<ShutdownRpc>,
+    ic: mesh::Sender<ShutdownRpc>,
+    power: PowerRequestClient,
+) {
+    let mut _fallback = None;
+    while let Some(rpc) = recv.next().await {
+        match rpc {
+            ShutdownRpc::ShutdownWithFallback(rpc) => {
+                let (params, rpc) = rpc.split();
+                let (progress_send, progress_recv) = mesh::channel();
+                rpc.complete(progress_recv);
+                // Dropping any previous fallback task cancels it.
+                _fallback = Some(driver.spawn(
+                    "shutdown-fallback",
+                    shutdown_with_fallback(
+                        driver.clone(),
+                        ic.clone(),
+                        power.clone(),
+                        params,
+                        progress_send,
+                    ),
+                ));
+            }
+            rpc => ic.send(rpc),
+        }
+    }
+}
+
+async fn shutdown_with_fallback(
+    driver: VmTaskDriver,
+    ic: mesh::Sender<ShutdownRpc>,
+    power: PowerRequestClient,
+    params: FallbackShutdownParams,
+    progress: mesh::Sender<ShutdownProgress>,
+) {
+    enum Outcome {
+        Force,
+        Cancelled,
+    }
+
+    let FallbackShutdownParams {
+        shutdown,
+        timeout,
+        cancel,
+    } = params;
+
+    let power_request = match shutdown.shutdown_type {
+        ShutdownType::PowerOff | ShutdownType::Hibernate => PowerRequest::PowerOff,
+        ShutdownType::Reboot => PowerRequest::Reset,
+    };
+
+    let deadline = Instant::now() + timeout;
+    let mut timer = PolledTimer::new(&driver);
+
+    let request = async {
+        // The returned receiver closes when the channel is closed, which the
+        // guest does as it shuts down or restarts.
+        let closed = ic.call(ShutdownRpc::WaitReady, ()).await?;
+        let result = ic.call(ShutdownRpc::Shutdown, shutdown).await?;
+        let delivered = matches!(
+            result,
+            ShutdownResult::Ok | ShutdownResult::AlreadyInProgress
+        );
+        progress.send(ShutdownProgress::Requested(result));
+        if delivered {
+            let _ = closed.await;
+            progress.send(ShutdownProgress::ChannelClosed);
+            // The guest may still hang after closing the channel, so keep the
+            // deadline armed until the caller cancels when the VM halts.
+            std::future::pending::<()>().await;
         }
+        Ok::<_, mesh::rpc::RpcError>(())
+    };
+
+    let outcome = (
+        async {
+            // Force the power state change right away if the request could
+            // not be delivered.
+            let _ = request.await;
+            Outcome::Force
+        },
+        async {
+            timer.sleep_until(deadline).await;
+            Outcome::Force
+        },
+        async {
+            let _ = cancel.await;
+            Outcome::Cancelled
+        },
+    )
+        .race()
+        .await;
+
+    match outcome {
+        Outcome::Force => {
+            tracing::warn!(
+                ?power_request,
+                "guest did not shut down in time, forcing power state change"
+            );
+            power.power_request(power_request);
+            progress.send(ShutdownProgress::Forced);
+        }
+        Outcome::Cancelled => progress.send(ShutdownProgress::Cancelled),
     }
 }
 
@@ -114,7 +258,7 @@ enum Event {
             let event = pin!(
                 (
                     once(
-                        self.process_state_machine(&mut ic.wait_ready)
+                        self.process_state_machine(&mut ic.wait_ready, &ic.events)
                             .map(Event::StateMachine)
                     ),
                     (&mut ic.recv).map(Event::Request),
@@ -151,6 +295,9 @@ enum Event {
                             }
                         },
                     },
+                    ShutdownRpc::ShutdownWithFallback(_) => {
+                        unreachable!("handled by handle_fallback_requests")
+                    }
                 },
             }
         }
@@ -159,6 +306,7 @@ enum Event {
     async fn process_state_machine(
         &mut self,
         wait_ready: &mut Vec<Rpc<(), mesh::OneshotReceiver<()>>>,
+        events: &GuestEventSink,
     ) -> anyhow::Result<()> {
         match self.state {
             ChannelState::Negotiate(ref mut state) => {
@@ -217,6 +365,7 @@ async fn process_state_machine(
                 ReadyState::WaitShutdown => {
                     let (status, _) = self.pipe.read_response().await?;
                     let result = if status == Status::SUCCESS {
+                        events.report(GuestEvent::ShutdownRequested);
                         ShutdownResult::Ok
                     } else {
                         ShutdownResult::Failed(status.0)
@@ -457,3 +606,201 @@ fn restore_open(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::shutdown_with_fallback;
+    use futures::StreamExt;
+    use hyperv_ic_resources::shutdown::FallbackShutdownParams;
+    use hyperv_ic_resources::shutdown::ShutdownParams;
+    use hyperv_ic_resources::shutdown::ShutdownProgress;
+    use hyperv_ic_resources::shutdown::ShutdownResult;
+    use hyperv_ic_resources::shutdown::ShutdownRpc;
+    use hyperv_ic_resources::shutdown::ShutdownType;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use pal_async::task::Spawn;
+    use parking_lot::Mutex;
+    use power_resources::PowerRequest;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use vmcore::vm_task::SingleDriverBackend;
+    use vmcore::vm_task::VmTaskDriverSource;
+
+    /// Starts a fake shutdown IC that responds to the shutdown request with
+    /// `result`, closing the channel afterward if `close` is set.
+    fn start_ic(
+        driver: &DefaultDriver,
+        result: ShutdownResult,
+        close: bool,
+    ) -> mesh::Sender<ShutdownRpc> {
+        let (send, mut recv) = mesh::channel();
+        driver
+            .spawn("test-shutdown-ic", async move {
+                let mut ready = None;
+                let mut result = Some(result);
+                while let Some(rpc) = recv.next().await {
+                    match rpc {
+                        ShutdownRpc::WaitReady(rpc) => {
+                            let (send, recv) = mesh::oneshot();
+                            ready = Some(send);
+                            rpc.complete(recv);
+                        }
+                        ShutdownRpc::Shutdown(rpc) => {
+                            rpc.complete(result.take().unwrap());
+                            if close {
+                                drop(ready.take());
+                            }
+                        }
+                        ShutdownRpc::ShutdownWithFallback(_) => unreachable!(),
+                    }
+                }
+            })
+            .detach();
+        send
+    }
+
+    struct Fallback {
+        progress: mesh::Receiver<ShutdownProgress>,
+        cancel: mesh::OneshotSender<()>,
+        power: Arc<Mutex<Vec<PowerRequest>>>,
+    }
+
+    fn start_fallback(
+        driver: &DefaultDriver,
+        ic: mesh::Sender<ShutdownRpc>,
+        shutdown_type: ShutdownType,
+        timeout: Duration,
+    ) -> Fallback {
+        let power = Arc::new(Mutex::new(Vec::new()));
+        let (progress_send, progress) = mesh::channel();
+        let (cancel, cancel_recv) = mesh::oneshot();
+        let params = FallbackShutdownParams {
+            shutdown: ShutdownParams {
+                shutdown_type,
+                force: false,
+            },
+            timeout,
+            cancel: cancel_recv,
+        };
+        driver
+            .spawn(
+                "test-shutdown-fallback",
+                shutdown_with_fallback(
+                    VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone())).simple(),
+                    ic,
+                    {
+                        let power = power.clone();
+                        (move |request: PowerRequest| power.lock().push(request)).into()
+                    },
+                    params,
+                    progress_send,
+                ),
+            )
+            .detach();
+        Fallback {
+            progress,
+            cancel,
+            power,
+        }
+    }
+
+    #[async_test]
+    async fn test_forced_after_channel_close(driver: DefaultDriver) {
+        let ic = start_ic(&driver, ShutdownResult::Ok, true);
+        let Fallback {
+            progress,
+            cancel: _cancel,
+            power,
+        } = start_fallback(
+            &driver,
+            ic,
+            ShutdownType::PowerOff,
+            Duration::from_millis(50),
+        );
+
+        // Closing the channel does not disarm the fallback.
+        assert_eq!(
+            progress.collect::<Vec<_>>().await,
+            [
+                ShutdownProgress::Requested(ShutdownResult::Ok),
+                ShutdownProgress::ChannelClosed,
+                ShutdownProgress::Forced,
+            ]
+        );
+        assert_eq!(*power.lock(), [PowerRequest::PowerOff]);
+    }
+
+    #[async_test]
+    async fn test_forced_when_guest_hangs(driver: DefaultDriver) {
+        let ic = start_ic(&driver, ShutdownResult::Ok, false);
+        let Fallback {
+            progress,
+            cancel: _cancel,
+            power,
+        } = start_fallback(&driver, ic, ShutdownType::Reboot, Duration::from_millis(50));
+
+        assert_eq!(
+            progress.collect::<Vec<_>>().await,
+            [
+                ShutdownProgress::Requested(ShutdownResult::Ok),
+                ShutdownProgress::Forced,
+            ]
+        );
+        assert_eq!(*power.lock(), [PowerRequest::Reset]);
+    }
+
+    #[async_test]
+    async fn test_forced_when_rejected(driver: DefaultDriver) {
+        let ic = start_ic(&driver, ShutdownResult::Failed(1), false);
+        let Fallback {
+            progress,
+            cancel: _cancel,
+            power,
+        } = start_fallback(
+            &driver,
+            ic,
+            ShutdownType::Hibernate,
+            Duration::from_secs(3600),
+        );
+
+        // The failure is escalated without waiting for the timeout.
+        assert_eq!(
+            progress.collect::<Vec<_>>().await,
+            [
+                ShutdownProgress::Requested(ShutdownResult::Failed(1)),
+                ShutdownProgress::Forced,
+            ]
+        );
+        assert_eq!(*power.lock(), [PowerRequest::PowerOff]);
+    }
+
+    #[async_test]
+    async fn test_cancelled_on_halt(driver: DefaultDriver) {
+        let ic = start_ic(&driver, ShutdownResult::Ok, true);
+        let Fallback {
+            mut progress,
+            cancel,
+            power,
+        } = start_fallback(
+            &driver,
+            ic,
+            ShutdownType::PowerOff,
+            Duration::from_secs(3600),
+        );
+
+        assert_eq!(
+            progress.next().await,
+            Some(ShutdownProgress::Requested(ShutdownResult::Ok))
+        );
+        assert_eq!(progress.next().await, Some(ShutdownProgress::ChannelClosed));
+
+        // The VM halted.
+        drop(cancel);
+        assert_eq!(
+            progress.collect::<Vec<_>>().await,
+            [ShutdownProgress::Cancelled]
+        );
+        assert!(power.lock().is_empty());
+    }
+}
@@ -3,11 +3,11 @@
 
 //! The timesync IC.
 //!
-//! TODO:
-//! * When the device is paused+resumed, this is an indicator that time may have
-//!   stopped for the guest. We should send another sync message to update the
-//!   guest, or potentially just reoffer the vmbus channel like Hyper-V does.
-//! * Saved state support.
+//! The guest is sent a sync message, which steps its clock, after version
+//! negotiation and after restore. After that, samples are sent periodically so
+//! that the guest can discipline its clock. If the host time drifts from the
+//! VM's reference time (such as when the VM is paused and resumed), another
+//! sync message is sent instead of a sample.
 
 use crate::common::IcPipe;
 use crate::common::NegotiateState;
@@ -31,7 +31,6 @@
 use vmbus_channel::simple::SaveRestoreSimpleVmbusDevice;
 use vmbus_channel::simple::SimpleVmbusDevice;
 use vmcore::reference_time::ReferenceTimeSource;
-use vmcore::save_restore::NoSavedState;
 use zerocopy::IntoBytes;
 
 const TIMESYNC_VERSIONS: &[hyperv_ic_protocol::Version] = &[proto::TIMESYNC_VERSION_4];
@@ -39,6 +38,11 @@
 /// Send samples every 5 seconds.
 const SAMPLE_PERIOD: std::time::Duration = std::time::Duration::from_secs(5);
 
+/// Send a sync instead of a sample if the host time has drifted from the
+/// reference time by more than 1 second since the last message, in 100ns
+/// units.
+const MAX_SAMPLE_DRIFT: u64 = 10_000_000;
+
 /// Timesync IC device.
 #[derive(InspectMut)]
 #[non_exhaustive]
@@ -55,6 +59,10 @@ pub struct TimesyncChannel {
     #[inspect(mut)]
     pipe: IcPipe,
     state: ChannelState,
+    /// The reference time and host time, in 100ns units, of the last message
+    /// sent to the guest.
+    #[inspect(skip)]
+    last_message: Option<(u64, u64)>,
 }
 
 #[derive(Inspect)]
@@ -104,7 +112,7 @@ pub fn new(driver: &(impl Driver + ?Sized), ref_time: ReferenceTimeSource) -> Se
 
 #[async_trait]
 impl SimpleVmbusDevice for TimesyncIc {
-    type SavedState = NoSavedState;
+    type SavedState = save_restore::state::SavedState;
     type Runner = TimesyncChannel;
 
     fn offer(&self) -> OfferParams {
@@ -126,7 +134,7 @@ fn open(
         channel: RawAsyncChannel<GpadlRingMem>,
         _guest_memory: GuestMemory,
     ) -> Result<Self::Runner, ChannelOpenError> {
-        TimesyncChannel::new(channel, None)
+        TimesyncChannel::new(channel, None, None)
     }
 
     async fn run(
@@ -143,7 +151,7 @@ fn supports_save_restore(
     ) -> Option<
         &mut dyn SaveRestoreSimpleVmbusDevice<SavedState = Self::SavedState, Runner = Self::Runner>,
     > {
-        None
+        Some(self)
     }
 }
 
@@ -151,11 +159,13 @@ impl TimesyncChannel {
     fn new(
         channel: RawAsyncChannel<GpadlRingMem>,
         restore_state: Option<ChannelState>,
+        last_message: Option<(u64, u64)>,
     ) -> Result<Self, ChannelOpenError> {
         let pipe = IcPipe::new(channel)?;
         Ok(Self {
             pipe,
             state: restore_state.unwrap_or(ChannelState::Negotiate(NegotiateState::default())),
+            last_message,
         })
     }
 
@@ -208,10 +218,24 @@ async fn process_state_machine(&mut self, ic: &mut TimesyncIc) -> anyhow::Result
                     let r = ic.ref_time.now();
                     let ref_time = r.ref_time;
                     let time = r.system_time.unwrap_or_else(jiff::Timestamp::now);
+                    let parent_time = (time.duration_since(proto::EPOCH).as_nanos() / 100) as u64;
+
+                    // If time has passed on the host but not in the VM (or
+                    // vice versa), step the guest clock instead of letting it
+                    // slowly converge.
+                    let is_sync = is_sync
+                        || self
+                            .last_message
+                            .is_some_and(|(last_ref_time, last_parent_time)| {
+                                let host_elapsed =
+                                    parent_time.wrapping_sub(last_parent_time) as i64;
+                                let ref_elapsed = ref_time.wrapping_sub(last_ref_time) as i64;
+                                host_elapsed.abs_diff(ref_elapsed) > MAX_SAMPLE_DRIFT
+                            });
+                    self.last_message = Some((ref_time, parent_time));
 
                     let message = proto::TimesyncMessageV4 {
-                        parent_time: ((time.duration_since(proto::EPOCH).as_nanos() / 100) as u64)
-                            .into(),
+                        parent_time: parent_time.into(),
                         vm_reference_time: ref_time,
                         flags: proto::TimesyncFlags::new()
                             .with_sync(is_sync)
@@ -255,3 +279,110 @@ async fn process_state_machine(&mut self, ic: &mut TimesyncIc) -> anyhow::Result
         Ok(())
     }
 }
+
+mod save_restore {
+    use super::*;
+
+    pub mod state {
+        use mesh::payload::Protobuf;
+        use vmcore::save_restore::SavedStateRoot;
+
+        #[derive(Copy, Clone, Eq, PartialEq, Protobuf)]
+        #[mesh(package = "timesync_ic")]
+        pub struct Version {
+            #[mesh(1)]
+            pub major: u16,
+            #[mesh(2)]
+            pub minor: u16,
+        }
+
+        impl From<hyperv_ic_protocol::Version> for Version {
+            fn from(version: hyperv_ic_protocol::Version) -> Self {
+                Self {
+                    major: version.major,
+                    minor: version.minor,
+                }
+            }
+        }
+
+        impl From<Version> for hyperv_ic_protocol::Version {
+            fn from(version: Version) -> Self {
+                Self {
+                    major: version.major,
+                    minor: version.minor,
+                }
+            }
+        }
+
+        #[derive(Protobuf, SavedStateRoot)]
+        #[mesh(package = "timesync_ic")]
+        pub struct SavedState {
+            #[mesh(1)]
+            pub version: Option<(Version, Version)>,
+            #[mesh(2)]
+            pub waiting_on_version: bool,
+            #[mesh(3)]
+            pub waiting_on_response: bool,
+            /// The reference time and host time of the last message.
+            #[mesh(4)]
+            pub last_message: Option<(u64, u64)>,
+        }
+    }
+
+    impl SaveRestoreSimpleVmbusDevice for TimesyncIc {
+        fn save_open(&mut self, runner: &Self::Runner) -> state::SavedState {
+            let (version, waiting_on_response) = match runner.state {
+                ChannelState::Ready {
+                    versions,
+                    ref state,
+                } => (
+                    Some((
+                        versions.framework_version.into(),
+                        versions.message_version.into(),
+                    )),
+                    matches!(state, ReadyState::WaitForResponse),
+                ),
+                ChannelState::Negotiate(_) | ChannelState::Failed => (None, false),
+            };
+            let waiting_on_version = matches!(
+                runner.state,
+                ChannelState::Negotiate(NegotiateState::WaitVersion)
+            );
+            state::SavedState {
+                version,
+                waiting_on_version,
+                waiting_on_response,
+                last_message: runner.last_message,
+            }
+        }
+
+        fn restore_open(
+            &mut self,
+            saved_state: Self::SavedState,
+            channel: RawAsyncChannel<GpadlRingMem>,
+        ) -> Result<Self::Runner, ChannelOpenError> {
+            let state = if let Some((framework, message)) = saved_state.version {
+                ChannelState::Ready {
+                    versions: Versions {
+                        framework_version: framework.into(),
+                        message_version: message.into(),
+                    },
+                    // Time has likely passed on the host since the save, so
+                    // resync the guest's clock as soon as possible.
+                    state: if saved_state.waiting_on_response {
+                        ReadyState::WaitForResponse
+                    } else {
+                        ReadyState::SendMessage { is_sync: true }
+                    },
+                }
+            } else {
+                ChannelState::Negotiate(if saved_state.waiting_on_version {
+                    NegotiateState::WaitVersion
+                } else {
+                    NegotiateState::SendVersion
+                })
+            };
+            TimesyncChannel::new(channel, Some(state), saved_state.last_message)
+        }
+    }
+}
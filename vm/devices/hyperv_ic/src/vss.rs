@@ -0,0 +1,267 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The VSS (Volume Shadow Service) IC.
+
+use crate::common::IcPipe;
+use crate::common::NegotiateState;
+use crate::common::Versions;
+use async_trait::async_trait;
+use futures::FutureExt;
+use futures::StreamExt;
+use futures::stream::once;
+use futures_concurrency::stream::Merge;
+use hyperv_ic_protocol::HeaderFlags;
+use hyperv_ic_protocol::Status;
+use hyperv_ic_protocol::vss as proto;
+use hyperv_ic_resources::vss::VssRpc;
+use inspect::Inspect;
+use inspect::InspectMut;
+use std::pin::pin;
+use task_control::Cancelled;
+use task_control::StopTask;
+use thiserror::Error;
+use vmbus_channel::RawAsyncChannel;
+use vmbus_channel::bus::ChannelType;
+use vmbus_channel::bus::OfferParams;
+use vmbus_channel::channel::ChannelOpenError;
+use vmbus_channel::gpadl_ring::GpadlRingMem;
+use vmbus_channel::simple::SaveRestoreSimpleVmbusDevice;
+use vmbus_channel::simple::SimpleVmbusDevice;
+use vmcore::save_restore::NoSavedState;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+/// Application freeze and thaw requests are only available starting with
+/// version 5.0.
+const VSS_VERSIONS: &[hyperv_ic_protocol::Version] = &[proto::VSS_VERSION_WINBLUE];
+
+/// A VSS IC device.
+#[derive(InspectMut)]
+pub struct VssIc {
+    #[inspect(skip)]
+    recv: mesh::Receiver<VssRpc>,
+}
+
+#[doc(hidden)]
+#[derive(InspectMut)]
+pub struct VssChannel {
+    #[inspect(mut)]
+    pipe: IcPipe,
+    state: ChannelState,
+}
+
+#[derive(Inspect)]
+#[inspect(external_tag)]
+enum ChannelState {
+    Negotiate(#[inspect(rename = "state")] NegotiateState),
+    Ready {
+        versions: Versions,
+        state: ReadyState,
+    },
+    Failed,
+}
+
+#[derive(Inspect)]
+#[inspect(external_tag)]
+enum ReadyState {
+    Ready,
+    SendingRequest(#[inspect(skip)] VssRpc),
+    WaitingResponse(#[inspect(skip)] VssRpc),
+}
+
+#[derive(Debug, Error)]
+#[error("VSS error: {0:x?}")]
+struct RequestError(Status);
+
+impl VssIc {
+    /// Returns a new VSS IC, using `recv` to receive VSS requests.
+    pub fn new(recv: mesh::Receiver<VssRpc>) -> Self {
+        Self { recv }
+    }
+}
+
+impl VssChannel {
+    fn new(
+        channel: RawAsyncChannel<GpadlRingMem>,
+        restore_state: Option<ChannelState>,
+    ) -> Result<Self, ChannelOpenError> {
+        let pipe = IcPipe::new(channel)?;
+        Ok(Self {
+            pipe,
+            state: restore_state.unwrap_or(ChannelState::Negotiate(NegotiateState::default())),
+        })
+    }
+
+    async fn process(&mut self, ic: &mut VssIc) -> ! {
+        enum Event {
+            StateMachine(anyhow::Result<()>),
+            Request(VssRpc),
+        }
+
+        loop {
+            let event = pin!(
+                (
+                    once(self.process_state_machine().map(Event::StateMachine)),
+                    (&mut ic.recv).map(Event::Request),
+                )
+                    .merge()
+            )
+            .next()
+            .await
+            .unwrap();
+            match event {
+                Event::StateMachine(r) => {
+                    if let Err(err) = r {
+                        tracing::error!(
+                            error = err.as_ref() as &dyn std::error::Error,
+                            "vss ic error"
+                        );
+                        if let ChannelState::Ready {
+                            state:
+                                ReadyState::SendingRequest(rpc) | ReadyState::WaitingResponse(rpc),
+                            ..
+                        } = std::mem::replace(&mut self.state, ChannelState::Failed)
+                        {
+                            fail(rpc, anyhow::anyhow!("vss channel failed"));
+                        }
+                    }
+                }
+                Event::Request(rpc) => match &mut self.state {
+                    ChannelState::Negotiate(_) => {
+                        fail(rpc, anyhow::anyhow!("vss ic not ready"));
+                    }
+                    ChannelState::Ready { state, .. } => match state {
+                        ReadyState::Ready => *state = ReadyState::SendingRequest(rpc),
+                        ReadyState::SendingRequest(_) | ReadyState::WaitingResponse(_) => {
+                            fail(rpc, anyhow::anyhow!("vss request already in progress"));
+                        }
+                    },
+                    ChannelState::Failed => {
+                        fail(rpc, anyhow::anyhow!("vss channel failed"));
+                    }
+                },
+            }
+        }
+    }
+
+    async fn process_state_machine(&mut self) -> anyhow::Result<()> {
+        match self.state {
+            ChannelState::Negotiate(ref mut state) => {
+                if let Some(versions) = self.pipe.negotiate(state, VSS_VERSIONS).await? {
+                    self.state = ChannelState::Ready {
+                        versions,
+                        state: ReadyState::Ready,
+                    };
+                }
+            }
+            ChannelState::Ready {
+                ref versions,
+                ref mut state,
+            } => match state {
+                ReadyState::Ready => std::future::pending().await,
+                ReadyState::SendingRequest(rpc) => {
+                    let mut message = proto::VssMessage::new_zeroed();
+                    message.header.operation = operation(rpc);
+                    self.pipe
+                        .write_message(
+                            versions,
+                            hyperv_ic_protocol::MessageType::VSS,
+                            HeaderFlags::new().with_request(true).with_transaction(true),
+                            message.as_bytes(),
+                        )
+                        .await?;
+
+                    let ReadyState::SendingRequest(rpc) =
+                        std::mem::replace(state, ReadyState::Ready)
+                    else {
+                        unreachable!()
+                    };
+                    *state = ReadyState::WaitingResponse(rpc);
+                }
+                ReadyState::WaitingResponse(_) => {
+                    let (status, _) = self.pipe.read_response().await?;
+                    let ReadyState::WaitingResponse(rpc) =
+                        std::mem::replace(state, ReadyState::Ready)
+                    else {
+                        unreachable!()
+                    };
+                    if status == Status::SUCCESS {
+                        complete(rpc);
+                    } else {
+                        fail(rpc, RequestError(status).into());
+                    }
+                }
+            },
+            ChannelState::Failed => std::future::pending().await,
+        }
+        Ok(())
+    }
+}
+
+fn operation(rpc: &VssRpc) -> proto::Operation {
+    match rpc {
+        VssRpc::CheckHotBackup(_) => proto::Operation::CHECK_HOT_BACKUP,
+        VssRpc::Freeze(_) => proto::Operation::FREEZE_APPLICATIONS,
+        VssRpc::Thaw(_) => proto::Operation::THAW_APPLICATIONS,
+    }
+}
+
+fn complete(rpc: VssRpc) {
+    match rpc {
+        VssRpc::CheckHotBackup(rpc) | VssRpc::Freeze(rpc) | VssRpc::Thaw(rpc) => {
+            rpc.complete(Ok(()))
+        }
+    }
+}
+
+fn fail(rpc: VssRpc, err: anyhow::Error) {
+    match rpc {
+        VssRpc::CheckHotBackup(rpc) | VssRpc::Freeze(rpc) | VssRpc::Thaw(rpc) => rpc.fail(err),
+    }
+}
+
+#[async_trait]
+impl SimpleVmbusDevice for VssIc {
+    type SavedState = NoSavedState;
+    type Runner = VssChannel;
+
+    fn offer(&self) -> OfferParams {
+        OfferParams {
+            interface_name: "vss_ic".to_owned(),
+            instance_id: proto::INSTANCE_ID,
+            interface_id: proto::INTERFACE_ID,
+            channel_type: ChannelType::Pipe { message_mode: true },
+            ..Default::default()
+        }
+    }
+
+    fn inspect(&mut self, req: inspect::Request<'_>, runner: Option<&mut Self::Runner>) {
+        req.respond().merge(self).merge(runner);
+    }
+
+    fn open(
+        &mut self,
+        channel: RawAsyncChannel<GpadlRingMem>,
+        _guest_memory: guestmem::GuestMemory,
+    ) -> Result<Self::Runner, ChannelOpenError> {
+        VssChannel::new(channel, None)
+    }
+
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        runner: &mut Self::Runner,
+    ) -> Result<(), Cancelled> {
+        stop.until_stopped(async { runner.process(self).await })
+            .await
+    }
+
+    fn supports_save_restore(
+        &mut self,
+    ) -> Option<
+        &mut dyn SaveRestoreSimpleVmbusDevice<SavedState = Self::SavedState, Runner = Self::Runner>,
+    > {
+        None
+    }
+}
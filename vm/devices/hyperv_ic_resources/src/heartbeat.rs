@@ -0,0 +1,16 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource definitions for the heartbeat IC.
+
+use mesh::MeshPayload;
+use vm_resource::ResourceId;
+use vm_resource::kind::VmbusDeviceHandleKind;
+
+/// A handle to the heartbeat IC.
+#[derive(MeshPayload)]
+pub struct HeartbeatIcHandle;
+
+impl ResourceId<VmbusDeviceHandleKind> for HeartbeatIcHandle {
+    const ID: &'static str = "heartbeat_ic";
+}
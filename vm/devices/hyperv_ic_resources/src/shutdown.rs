@@ -5,6 +5,7 @@
 
 use mesh::MeshPayload;
 use mesh::rpc::Rpc;
+use std::time::Duration;
 use vm_resource::ResourceId;
 use vm_resource::kind::VmbusDeviceHandleKind;
 
@@ -32,6 +33,12 @@ pub enum ShutdownRpc {
     WaitReady(Rpc<(), mesh::OneshotReceiver<()>>),
     /// Send a shutdown request to the guest.
     Shutdown(Rpc<ShutdownParams, ShutdownResult>),
+    /// Send a shutdown request to the guest, and force the power state change
+    /// if the guest does not comply in time.
+    ///
+    /// Returns a receiver for progress updates. A new request supersedes any
+    /// previous one.
+    ShutdownWithFallback(Rpc<FallbackShutdownParams, mesh::Receiver<ShutdownProgress>>),
 }
 
 /// Guest shutdown parameters.
@@ -43,6 +50,23 @@ pub struct ShutdownParams {
     pub force: bool,
 }
 
+/// Parameters for a guest shutdown with a forced fallback.
+#[derive(Debug, MeshPayload)]
+pub struct FallbackShutdownParams {
+    /// The shutdown request to send to the guest.
+    pub shutdown: ShutdownParams,
+    /// How long to wait for the guest to shut down before forcing the power
+    /// state change. A reboot is forced by resetting the VM; a power off or
+    /// hibernate is forced by powering off the VM.
+    ///
+    /// The guest closing the shutdown IC channel does not stop the timeout,
+    /// since the guest may still hang on the way down.
+    pub timeout: Duration,
+    /// Cancels the fallback when signaled or dropped. The caller should do
+    /// this when the VM halts.
+    pub cancel: mesh::OneshotReceiver<()>,
+}
+
 /// The shutdown type.
 #[derive(Debug, MeshPayload)]
 pub enum ShutdownType {
@@ -66,3 +90,18 @@ pub enum ShutdownResult {
     /// The shutdown failed with the given error code.
     Failed(u32),
 }
+
+/// Progress of a shutdown request with a forced fallback.
+#[derive(MeshPayload, Debug, PartialEq)]
+pub enum ShutdownProgress {
+    /// The shutdown request was sent to the guest.
+    Requested(ShutdownResult),
+    /// The guest closed the shutdown IC channel, indicating that it is
+    /// shutting down or restarting. The fallback remains armed until it is
+    /// cancelled.
+    ChannelClosed,
+    /// The guest did not comply in time, so the power state change was forced.
+    Forced,
+    /// The fallback was cancelled.
+    Cancelled,
+}
@@ -0,0 +1,40 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource definitions for the VSS (Volume Shadow Service) IC.
+
+use mesh::MeshPayload;
+use mesh::rpc::FailableRpc;
+use vm_resource::ResourceId;
+use vm_resource::kind::VmbusDeviceHandleKind;
+
+/// A handle to a VSS IC.
+#[derive(MeshPayload)]
+pub struct VssIcHandle {
+    /// The channel by which to receive VSS requests.
+    pub recv: mesh::Receiver<VssRpc>,
+}
+
+impl ResourceId<VmbusDeviceHandleKind> for VssIcHandle {
+    const ID: &'static str = "vss_ic";
+}
+
+/// An RPC request to the VSS IC.
+///
+/// To take an application-consistent snapshot of the VM's disks, the host
+/// freezes the guest's applications and file systems with
+/// [`Freeze`](Self::Freeze), quiesces the disk backends, snapshots them,
+/// thaws the disk backends, and finally thaws the guest with
+/// [`Thaw`](Self::Thaw).
+///
+/// The guest may time out and thaw itself if too much time passes between the
+/// freeze and thaw requests.
+#[derive(MeshPayload)]
+pub enum VssRpc {
+    /// Queries whether the guest supports VSS backups.
+    CheckHotBackup(FailableRpc<(), ()>),
+    /// Freezes guest applications and file systems.
+    Freeze(FailableRpc<(), ()>),
+    /// Thaws guest applications and file systems.
+    Thaw(FailableRpc<(), ()>),
+}
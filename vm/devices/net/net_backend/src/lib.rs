@@ -9,6 +9,7 @@
 
 pub mod loopback;
 pub mod null;
+pub mod offload;
 pub mod resolve;
 pub mod tests;
 
@@ -0,0 +1,337 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Software implementations of transmit offloads.
+//!
+//! Backends whose transport cannot compute checksums or segment TCP packets
+//! can use these to advertise the offloads to the guest anyway, moving the
+//! per-packet work out of the guest's network stack.
+
+use crate::TxMetadata;
+use thiserror::Error;
+
+const IPV4_PROTOCOL_TCP: u8 = 6;
+const IPV4_PROTOCOL_UDP: u8 = 17;
+const IPV6_HEADER_LEN: usize = 40;
+const TCP_CHECKSUM_OFFSET: usize = 16;
+const UDP_CHECKSUM_OFFSET: usize = 6;
+const TCP_FLAG_FIN: u8 = 0x01;
+const TCP_FLAG_PSH: u8 = 0x08;
+const TCP_FLAG_CWR: u8 = 0x80;
+
+/// An error applying a software offload to a packet.
+#[derive(Debug, Error)]
+pub enum OffloadError {
+    /// The packet is too short for the header lengths in the metadata.
+    #[error("packet headers are truncated")]
+    Truncated,
+    /// The metadata does not specify whether the packet is IPv4 or IPv6.
+    #[error("unknown ip version")]
+    UnknownIpVersion,
+    /// The maximum segment size is zero.
+    #[error("invalid tcp segment size")]
+    InvalidSegmentSize,
+}
+
+/// Adds the big-endian 16-bit words of `data` to a ones' complement sum.
+fn sum(data: &[u8], mut acc: u64) -> u64 {
+    let mut chunks = data.chunks_exact(2);
+    for word in &mut chunks {
+        acc += u16::from_be_bytes([word[0], word[1]]) as u64;
+    }
+    if let [b] = chunks.remainder() {
+        acc += (*b as u64) << 8;
+    }
+    acc
+}
+
+/// Folds a ones' complement sum and returns its complement.
+fn fold(mut acc: u64) -> u16 {
+    while acc > 0xffff {
+        acc = (acc & 0xffff) + (acc >> 16);
+    }
+    !(acc as u16)
+}
+
+/// Describes where the headers of a packet are.
+struct Layout {
+    l2_len: usize,
+    l3_len: usize,
+    is_ipv4: bool,
+}
+
+impl Layout {
+    fn new(packet: &[u8], metadata: &TxMetadata) -> Result<Self, OffloadError> {
+        let is_ipv4 = if metadata.flags.is_ipv4() {
+            true
+        } else if metadata.flags.is_ipv6() {
+            false
+        } else {
+            return Err(OffloadError::UnknownIpVersion);
+        };
+        let layout = Self {
+            l2_len: metadata.l2_len.into(),
+            l3_len: metadata.l3_len.into(),
+            is_ipv4,
+        };
+        let min_l3_len = if is_ipv4 { 20 } else { IPV6_HEADER_LEN };
+        if layout.l3_len < min_l3_len || packet.len() < layout.l4_offset() {
+            return Err(OffloadError::Truncated);
+        }
+        Ok(layout)
+    }
+
+    fn l4_offset(&self) -> usize {
+        self.l2_len + self.l3_len
+    }
+
+    /// Returns the end of the IP packet, excluding any Ethernet padding.
+    fn ip_end(&self, packet: &[u8]) -> usize {
+        let ip = &packet[self.l2_len..];
+        let len = if self.is_ipv4 {
+            u16::from_be_bytes([ip[2], ip[3]]) as usize
+        } else {
+            IPV6_HEADER_LEN + u16::from_be_bytes([ip[4], ip[5]]) as usize
+        };
+        // Fall back to the frame length if the IP length is not set, as it may
+        // not be for segmentation offload.
+        if len > self.l3_len && self.l2_len + len <= packet.len() {
+            self.l2_len + len
+        } else {
+            packet.len()
+        }
+    }
+
+    fn set_ipv4_header_checksum(&self, packet: &mut [u8]) {
+        let ip = &mut packet[self.l2_len..self.l4_offset()];
+        ip[10..12].fill(0);
+        let checksum = fold(sum(ip, 0));
+        ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    /// Computes the TCP or UDP checksum over `packet[l4_offset..end]`, with
+    /// the checksum field at `checksum_offset` within the L4 header.
+    fn set_l4_checksum(&self, packet: &mut [u8], end: usize, protocol: u8, checksum_offset: usize) {
+        let l4_offset = self.l4_offset();
+        let l4_len = end - l4_offset;
+        let ip = &packet[self.l2_len..];
+        let mut acc = if self.is_ipv4 {
+            sum(&ip[12..20], 0)
+        } else {
+            sum(&ip[8..40], 0)
+        };
+        acc += protocol as u64 + l4_len as u64;
+        packet[l4_offset + checksum_offset..][..2].fill(0);
+        let mut checksum = fold(sum(&packet[l4_offset..end], acc));
+        if protocol == IPV4_PROTOCOL_UDP && checksum == 0 {
+            checksum = 0xffff;
+        }
+        packet[l4_offset + checksum_offset..][..2].copy_from_slice(&checksum.to_be_bytes());
+    }
+}
+
+/// Computes the checksums requested by the checksum offload flags in
+/// `metadata`, in place.
+pub fn compute_checksums(packet: &mut [u8], metadata: &TxMetadata) -> Result<(), OffloadError> {
+    let flags = metadata.flags;
+    if !(flags.offload_ip_header_checksum()
+        || flags.offload_tcp_checksum()
+        || flags.offload_udp_checksum())
+    {
+        return Ok(());
+    }
+    let layout = Layout::new(packet, metadata)?;
+    if flags.offload_ip_header_checksum() && layout.is_ipv4 {
+        layout.set_ipv4_header_checksum(packet);
+    }
+    let end = layout.ip_end(packet);
+    if flags.offload_tcp_checksum() {
+        if end < layout.l4_offset() + 20 {
+            return Err(OffloadError::Truncated);
+        }
+        layout.set_l4_checksum(packet, end, IPV4_PROTOCOL_TCP, TCP_CHECKSUM_OFFSET);
+    } else if flags.offload_udp_checksum() {
+        if end < layout.l4_offset() + 8 {
+            return Err(OffloadError::Truncated);
+        }
+        layout.set_l4_checksum(packet, end, IPV4_PROTOCOL_UDP, UDP_CHECKSUM_OFFSET);
+    }
+    Ok(())
+}
+
+/// Splits a packet with TCP segmentation offload into frames carrying at most
+/// `metadata.max_tcp_segment_size` bytes of payload each, calling `f` with
+/// each frame in order.
+///
+/// Each frame gets its own IP length, IPv4 identification, TCP sequence
+/// number, and checksums. FIN and PSH are only set on the last frame, and CWR
+/// only on the first.
+pub fn segment_tcp(
+    packet: &[u8],
+    metadata: &TxMetadata,
+    mut f: impl FnMut(&[u8]),
+) -> Result<(), OffloadError> {
+    let layout = Layout::new(packet, metadata)?;
+    let mss = metadata.max_tcp_segment_size as usize;
+    if mss == 0 {
+        return Err(OffloadError::InvalidSegmentSize);
+    }
+    let l4_offset = layout.l4_offset();
+    let header_len = l4_offset + metadata.l4_len as usize;
+    if metadata.l4_len < 20 || packet.len() < header_len {
+        return Err(OffloadError::Truncated);
+    }
+    let (headers, payload) = packet.split_at(header_len);
+    let ip = &headers[layout.l2_len..];
+    let ipv4_id = u16::from_be_bytes([ip[4], ip[5]]);
+    let tcp = &headers[l4_offset..];
+    let seq = u32::from_be_bytes(tcp[4..8].try_into().unwrap());
+    let tcp_flags = tcp[13];
+
+    let count = payload.len().div_ceil(mss).max(1);
+    let mut frame = Vec::with_capacity(header_len + mss);
+    for i in 0..count {
+        let chunk = &payload[(i * mss).min(payload.len())..((i + 1) * mss).min(payload.len())];
+        frame.clear();
+        frame.extend_from_slice(headers);
+        frame.extend_from_slice(chunk);
+
+        let ip = &mut frame[layout.l2_len..];
+        if layout.is_ipv4 {
+            let total_len = (header_len - layout.l2_len + chunk.len()) as u16;
+            ip[2..4].copy_from_slice(&total_len.to_be_bytes());
+            ip[4..6].copy_from_slice(&ipv4_id.wrapping_add(i as u16).to_be_bytes());
+        } else {
+            let payload_len = (header_len - layout.l2_len - IPV6_HEADER_LEN + chunk.len()) as u16;
+            ip[4..6].copy_from_slice(&payload_len.to_be_bytes());
+        }
+
+        let tcp = &mut frame[l4_offset..];
+        let offset = (i * mss) as u32;
+        tcp[4..8].copy_from_slice(&seq.wrapping_add(offset).to_be_bytes());
+        let mut flags = tcp_flags;
+        if i != 0 {
+            flags &= !TCP_FLAG_CWR;
+        }
+        if i + 1 != count {
+            flags &= !(TCP_FLAG_FIN | TCP_FLAG_PSH);
+        }
+        tcp[13] = flags;
+
+        if layout.is_ipv4 {
+            layout.set_ipv4_header_checksum(&mut frame);
+        }
+        let end = frame.len();
+        layout.set_l4_checksum(&mut frame, end, IPV4_PROTOCOL_TCP, TCP_CHECKSUM_OFFSET);
+        f(&frame);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compute_checksums;
+    use super::fold;
+    use super::segment_tcp;
+    use super::sum;
+    use crate::TxFlags;
+    use crate::TxMetadata;
+
+    const L2_LEN: usize = 14;
+
+    /// Builds an Ethernet + IPv4 + TCP frame with zeroed checksums.
+    fn ipv4_tcp_frame(payload: &[u8], tcp_flags: u8) -> Vec<u8> {
+        let mut frame = vec![0; L2_LEN];
+        frame[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+        let total_len = (20 + 20 + payload.len()) as u16;
+        frame.extend_from_slice(&[0x45, 0, 0, 0, 0x12, 0x34, 0x40, 0, 64, 6, 0, 0]);
+        frame[L2_LEN + 2..L2_LEN + 4].copy_from_slice(&total_len.to_be_bytes());
+        frame.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        let mut tcp = [0u8; 20];
+        tcp[0..2].copy_from_slice(&1234u16.to_be_bytes());
+        tcp[2..4].copy_from_slice(&80u16.to_be_bytes());
+        tcp[4..8].copy_from_slice(&1000u32.to_be_bytes());
+        tcp[12] = 5 << 4;
+        tcp[13] = tcp_flags;
+        frame.extend_from_slice(&tcp);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn metadata(flags: TxFlags) -> TxMetadata {
+        TxMetadata {
+            flags: flags.with_is_ipv4(true),
+            l2_len: L2_LEN as u8,
+            l3_len: 20,
+            l4_len: 20,
+            ..Default::default()
+        }
+    }
+
+    /// Verifies the IPv4 header and TCP checksums of a frame.
+    fn check_ipv4_tcp(frame: &[u8]) {
+        let ip = &frame[L2_LEN..L2_LEN + 20];
+        assert_eq!(fold(sum(ip, 0)), 0, "ip header checksum");
+        let tcp = &frame[L2_LEN + 20..];
+        let pseudo = sum(&ip[12..20], 0) + 6 + tcp.len() as u64;
+        assert_eq!(fold(sum(tcp, pseudo)), 0, "tcp checksum");
+    }
+
+    #[test]
+    fn test_checksums() {
+        let mut frame = ipv4_tcp_frame(b"hello, world", 0x18);
+        compute_checksums(
+            &mut frame,
+            &metadata(
+                TxFlags::new()
+                    .with_offload_ip_header_checksum(true)
+                    .with_offload_tcp_checksum(true),
+            ),
+        )
+        .unwrap();
+        check_ipv4_tcp(&frame);
+    }
+
+    #[test]
+    fn test_segment_tcp() {
+        let payload = (0..250).map(|i| i as u8).collect::<Vec<_>>();
+        let packet = ipv4_tcp_frame(&payload, 0x80 | 0x18 | 0x01);
+        let mut metadata = metadata(TxFlags::new().with_offload_tcp_segmentation(true));
+        metadata.max_tcp_segment_size = 100;
+
+        let mut frames = Vec::new();
+        segment_tcp(&packet, &metadata, |frame| frames.push(frame.to_vec())).unwrap();
+        assert_eq!(frames.len(), 3);
+
+        let mut reassembled = Vec::new();
+        for (i, frame) in frames.iter().enumerate() {
+            check_ipv4_tcp(frame);
+            let ip = &frame[L2_LEN..];
+            assert_eq!(
+                u16::from_be_bytes([ip[2], ip[3]]) as usize,
+                frame.len() - L2_LEN
+            );
+            assert_eq!(u16::from_be_bytes([ip[4], ip[5]]), 0x1234 + i as u16);
+            let tcp = &frame[L2_LEN + 20..];
+            assert_eq!(
+                u32::from_be_bytes(tcp[4..8].try_into().unwrap()),
+                1000 + 100 * i as u32
+            );
+            let flags = tcp[13];
+            assert_eq!(flags & 0x80 != 0, i == 0);
+            assert_eq!(flags & 0x09 != 0, i == 2);
+            assert_eq!(flags & 0x10, 0x10);
+            reassembled.extend_from_slice(&tcp[20..]);
+        }
+        assert_eq!(reassembled, payload);
+    }
+
+    #[test]
+    fn test_segment_tcp_invalid() {
+        let packet = ipv4_tcp_frame(b"data", 0);
+        let mut metadata = metadata(TxFlags::new().with_offload_tcp_segmentation(true));
+        assert!(segment_tcp(&packet, &metadata, |_| ()).is_err());
+        metadata.max_tcp_segment_size = 100;
+        assert!(segment_tcp(&packet[..30], &metadata, |_| ()).is_err());
+    }
+}
@@ -90,3 +90,26 @@ impl ResourceId<NetEndpointHandleKind> for TapHandle {
         const ID: &'static str = "tap";
     }
 }
+
+/// Linux AF_XDP backend.
+pub mod xdp {
+    use mesh::MeshPayload;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::NetEndpointHandleKind;
+
+    /// A handle to an AF_XDP socket bound to a network interface queue.
+    #[derive(MeshPayload)]
+    pub struct XdpHandle {
+        /// The name of the host network interface.
+        pub interface: String,
+        /// The interface queue to bind to.
+        pub queue_id: u32,
+        /// The path to a pinned XSKMAP to insert the socket into, for use with
+        /// an externally loaded XDP program.
+        pub xsk_map: Option<String>,
+    }
+
+    impl ResourceId<NetEndpointHandleKind> for XdpHandle {
+        const ID: &'static str = "xdp";
+    }
+}
@@ -21,8 +21,11 @@
 use net_backend::RxMetadata;
 use net_backend::TxError;
 use net_backend::TxId;
+use net_backend::TxOffloadSupport;
 use net_backend::TxSegment;
 use net_backend::linearize;
+use net_backend::next_packet;
+use net_backend::offload;
 use pal_async::driver::Driver;
 use parking_lot::Mutex;
 use std::collections::VecDeque;
@@ -91,6 +94,17 @@ async fn stop(&mut self) {
     fn is_ordered(&self) -> bool {
         true
     }
+
+    fn tx_offload_support(&self) -> TxOffloadSupport {
+        // The TAP interface is opened without virtio-net headers, so these are
+        // all performed in software before writing to the interface.
+        TxOffloadSupport {
+            ipv4_header: true,
+            tcp: true,
+            udp: true,
+            tso: true,
+        }
+    }
 }
 
 struct TapQueue {
@@ -142,6 +156,30 @@ fn new(
     }
 }
 
+fn write_frame(tap: &mut tap::PolledTap, frame: &[u8]) {
+    match tap.write(frame) {
+        Ok(bytes_written) => {
+            assert_eq!(bytes_written, frame.len(), "TAP should never partial write");
+        }
+        Err(err) if err.kind() == ErrorKind::WouldBlock => {
+            // dropped packet: buffer is full
+
+            // TODO: return partial transmit here. This relies on
+            // remembering this condition and polling for POLLOUT in
+            // poll_ready().
+        }
+        Err(err) if err.raw_os_error() == Some(libc::EIO) => {
+            // dropped packet: interface is not up
+        }
+        Err(err) => {
+            tracing::warn!(
+                error = &err as &dyn std::error::Error,
+                "write to TAP interface failed"
+            );
+        }
+    }
+}
+
 impl Queue for TapQueue {
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
         if !self.inner.rx_ready.is_empty() {
@@ -203,31 +241,20 @@ fn tx_avail(&mut self, mut segments: &[TxSegment]) -> anyhow::Result<(bool, usiz
         // Synchronously send packets received from the guest to host's network.
         if let Some(tap) = self.tap.as_mut() {
             while !segments.is_empty() {
-                let packet = linearize(self.inner.pool.as_ref(), &mut segments)?;
-                match tap.write(&packet) {
-                    Ok(bytes_written) => {
-                        assert_eq!(
-                            bytes_written,
-                            packet.len(),
-                            "TAP should never partial write"
-                        );
-                    }
-                    Err(err) if err.kind() == ErrorKind::WouldBlock => {
-                        // dropped packet: buffer is full
-
-                        // TODO: return partial transmit here. This relies on
-                        // remembering this condition and polling for POLLOUT in
-                        // poll_ready().
-                    }
-                    Err(err) if err.raw_os_error() == Some(libc::EIO) => {
-                        // dropped packet: interface is not up
-                    }
-                    Err(err) => {
-                        tracing::warn!(
-                            error = &err as &dyn std::error::Error,
-                            "write to TAP interface failed"
-                        );
-                    }
+                let metadata = next_packet(segments).0.clone();
+                let mut packet = linearize(self.inner.pool.as_ref(), &mut segments)?;
+                let result = if metadata.flags.offload_tcp_segmentation() {
+                    offload::segment_tcp(&packet, &metadata, |frame| write_frame(tap, frame))
+                } else {
+                    offload::compute_checksums(&mut packet, &metadata)
+                        .map(|()| write_frame(tap, &packet))
+                };
+                if let Err(err) = result {
+                    // dropped packet: the guest provided invalid offload metadata
+                    tracelimit::warn_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "invalid offload request for TAP transmit"
+                    );
                 }
             }
         }
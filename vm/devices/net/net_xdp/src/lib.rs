@@ -0,0 +1,298 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! An endpoint based on an AF_XDP socket bound to a host network interface
+//! queue.
+//!
+//! Packets are exchanged with the kernel through a UMEM region owned by the
+//! endpoint rather than through the netvsp receive buffers. AF_XDP requires
+//! the UMEM to be a single host mapping divided into equal, aligned chunks that
+//! the kernel can own independently, while the receive buffer is guest memory
+//! suballocated by the guest at MTU granularity, so packets are copied between
+//! the two. This still avoids the kernel network stack and per-packet
+//! syscalls on receive.
+//!
+//! An XDP program must redirect the interface's traffic into the socket for
+//! anything to be received. The endpoint does not load one itself; instead, it
+//! can insert its socket into a pinned XSKMAP used by an externally loaded
+//! program.
+
+#![cfg(target_os = "linux")]
+#![expect(missing_docs)]
+
+pub mod resolver;
+mod xsk;
+
+use async_trait::async_trait;
+use inspect::InspectMut;
+use inspect_counters::Counter;
+use net_backend::BufferAccess;
+use net_backend::Endpoint;
+use net_backend::Queue;
+use net_backend::QueueConfig;
+use net_backend::RssConfig;
+use net_backend::RxId;
+use net_backend::RxMetadata;
+use net_backend::TxError;
+use net_backend::TxId;
+use net_backend::TxOffloadSupport;
+use net_backend::TxSegment;
+use net_backend::linearize;
+use net_backend::next_packet;
+use net_backend::offload;
+use pal_async::driver::Driver;
+use pal_async::driver::PollImpl;
+use pal_async::fd::PollFdReady;
+use pal_async::interest::InterestSlot;
+use pal_async::interest::PollEvents;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("AF_XDP socket error")]
+    Socket(#[source] xsk::Error),
+}
+
+/// An endpoint based on an AF_XDP socket.
+pub struct XdpEndpoint {
+    xsk: Arc<Mutex<Option<xsk::Xsk>>>,
+}
+
+impl XdpEndpoint {
+    /// Binds a new socket to queue `queue_id` of `interface`, optionally
+    /// inserting it into the pinned XSKMAP at `xsk_map`.
+    pub fn new(interface: &str, queue_id: u32, xsk_map: Option<&str>) -> Result<Self, Error> {
+        let xsk = xsk::Xsk::new(interface, queue_id).map_err(Error::Socket)?;
+        if let Some(path) = xsk_map {
+            xsk.insert_into_map(path, queue_id).map_err(Error::Socket)?;
+        }
+        Ok(Self {
+            xsk: Arc::new(Mutex::new(Some(xsk))),
+        })
+    }
+}
+
+impl InspectMut for XdpEndpoint {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        req.respond();
+    }
+}
+
+#[async_trait]
+impl Endpoint for XdpEndpoint {
+    fn endpoint_type(&self) -> &'static str {
+        "xdp"
+    }
+
+    async fn get_queues(
+        &mut self,
+        mut config: Vec<QueueConfig<'_>>,
+        _rss: Option<&RssConfig<'_>>,
+        queues: &mut Vec<Box<dyn Queue>>,
+    ) -> anyhow::Result<()> {
+        assert_eq!(config.len(), 1);
+        let config = config.drain(..).next().unwrap();
+        queues.push(Box::new(XdpQueue::new(
+            config.driver.as_ref(),
+            self.xsk.clone(),
+            config.pool,
+            config.initial_rx,
+        )?));
+        Ok(())
+    }
+
+    async fn stop(&mut self) {
+        assert!(self.xsk.lock().is_some(), "queue has not been dropped");
+    }
+
+    fn is_ordered(&self) -> bool {
+        true
+    }
+
+    fn tx_offload_support(&self) -> TxOffloadSupport {
+        // AF_XDP transmits raw frames, so these are all performed in software
+        // before copying into the UMEM.
+        TxOffloadSupport {
+            ipv4_header: true,
+            tcp: true,
+            udp: true,
+            tso: true,
+        }
+    }
+}
+
+struct XdpQueue {
+    slot: Arc<Mutex<Option<xsk::Xsk>>>,
+    xsk: Option<xsk::Xsk>,
+    fd_ready: PollImpl<dyn PollFdReady>,
+    pool: Box<dyn BufferAccess>,
+    rx_free: VecDeque<RxId>,
+    rx_ready: VecDeque<RxId>,
+    stats: Stats,
+}
+
+#[derive(Default, inspect::Inspect)]
+struct Stats {
+    rx_dropped_too_large: Counter,
+    tx_dropped: Counter,
+}
+
+impl InspectMut for XdpQueue {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        req.respond().merge(&self.stats);
+    }
+}
+
+impl Drop for XdpQueue {
+    fn drop(&mut self) {
+        *self.slot.lock() = self.xsk.take();
+    }
+}
+
+impl XdpQueue {
+    fn new(
+        driver: &dyn Driver,
+        slot: Arc<Mutex<Option<xsk::Xsk>>>,
+        pool: Box<dyn BufferAccess>,
+        initial_rx: &[RxId],
+    ) -> anyhow::Result<Self> {
+        let xsk = slot.lock().take().expect("queue is already in use");
+        let fd_ready = match driver.new_dyn_fd_ready(xsk.as_raw_fd()) {
+            Ok(fd_ready) => fd_ready,
+            Err(err) => {
+                *slot.lock() = Some(xsk);
+                return Err(err.into());
+            }
+        };
+        Ok(Self {
+            slot,
+            xsk: Some(xsk),
+            fd_ready,
+            pool,
+            rx_free: initial_rx.iter().copied().collect(),
+            rx_ready: VecDeque::new(),
+            stats: Default::default(),
+        })
+    }
+
+    fn send(xsk: &mut xsk::Xsk, stats: &mut Stats, frame: &[u8]) {
+        if !xsk.send(frame) {
+            // dropped packet: the frame is too large or the kernel has not
+            // finished transmitting earlier frames.
+            stats.tx_dropped.increment();
+        }
+    }
+}
+
+impl Queue for XdpQueue {
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let Some(xsk) = self.xsk.as_mut() else {
+            return Poll::Pending;
+        };
+
+        while let Some(&rx) = self.rx_free.front() {
+            let capacity = self.pool.capacity(rx) as usize;
+            let mut delivered = false;
+            let received = xsk.recv(|data| {
+                if data.len() > capacity {
+                    return;
+                }
+                self.pool.write_packet(
+                    rx,
+                    &RxMetadata {
+                        offset: 0,
+                        len: data.len(),
+                        ..Default::default()
+                    },
+                    data,
+                );
+                delivered = true;
+            });
+            if !received {
+                if !self.rx_ready.is_empty() {
+                    break;
+                }
+                if self
+                    .fd_ready
+                    .poll_fd_ready(cx, InterestSlot::Read, PollEvents::IN)
+                    .is_pending()
+                {
+                    break;
+                }
+                // The socket was signaled, but the ring may have been drained
+                // already. Clear the readiness before checking the ring again so
+                // that no wakeup is lost.
+                self.fd_ready.clear_fd_ready(InterestSlot::Read);
+                continue;
+            }
+            if delivered {
+                self.rx_free.pop_front();
+                self.rx_ready.push_back(rx);
+            } else {
+                self.stats.rx_dropped_too_large.increment();
+            }
+        }
+
+        if !self.rx_ready.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn rx_avail(&mut self, done: &[RxId]) {
+        self.rx_free.extend(done);
+    }
+
+    fn rx_poll(&mut self, packets: &mut [RxId]) -> anyhow::Result<usize> {
+        let n = std::cmp::min(self.rx_ready.len(), packets.len());
+        for (done, id) in packets[..n].iter_mut().zip(self.rx_ready.drain(..n)) {
+            *done = id;
+        }
+        Ok(n)
+    }
+
+    fn tx_avail(&mut self, mut segments: &[TxSegment]) -> anyhow::Result<(bool, usize)> {
+        let n = segments.len();
+        // Packets are copied into the UMEM, so they can be completed to the
+        // guest immediately.
+        if let Some(xsk) = self.xsk.as_mut() {
+            while !segments.is_empty() {
+                let metadata = next_packet(segments).0.clone();
+                let mut packet = linearize(self.pool.as_ref(), &mut segments)?;
+                let stats = &mut self.stats;
+                let result = if metadata.flags.offload_tcp_segmentation() {
+                    offload::segment_tcp(&packet, &metadata, |frame| Self::send(xsk, stats, frame))
+                } else {
+                    offload::compute_checksums(&mut packet, &metadata)
+                        .map(|()| Self::send(xsk, stats, &packet))
+                };
+                if let Err(err) = result {
+                    // dropped packet: the guest provided invalid offload metadata
+                    tracelimit::warn_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "invalid offload request for AF_XDP transmit"
+                    );
+                }
+            }
+            xsk.flush();
+        }
+        let completed_synchronously = true;
+        Ok((completed_synchronously, n))
+    }
+
+    fn tx_poll(&mut self, _done: &mut [TxId]) -> Result<usize, TxError> {
+        // Packets are completed synchronously in tx_avail.
+        Ok(0)
+    }
+
+    fn buffer_access(&mut self) -> Option<&mut dyn BufferAccess> {
+        Some(self.pool.as_mut())
+    }
+}
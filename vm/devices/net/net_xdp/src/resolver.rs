@@ -0,0 +1,35 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::XdpEndpoint;
+use net_backend::resolve::ResolveEndpointParams;
+use net_backend::resolve::ResolvedEndpoint;
+use net_backend_resources::xdp::XdpHandle;
+use vm_resource::ResolveResource;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::NetEndpointHandleKind;
+
+pub struct XdpResolver;
+
+declare_static_resolver! {
+    XdpResolver,
+    (NetEndpointHandleKind, XdpHandle),
+}
+
+impl ResolveResource<NetEndpointHandleKind, XdpHandle> for XdpResolver {
+    type Output = ResolvedEndpoint;
+    type Error = super::Error;
+
+    fn resolve(
+        &self,
+        resource: XdpHandle,
+        _input: ResolveEndpointParams,
+    ) -> Result<Self::Output, Self::Error> {
+        let endpoint = XdpEndpoint::new(
+            &resource.interface,
+            resource.queue_id,
+            resource.xsk_map.as_deref(),
+        )?;
+        Ok(endpoint.into())
+    }
+}
@@ -0,0 +1,706 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A minimal AF_XDP socket with its own UMEM and rings.
+
+// UNSAFETY: Calling socket, mmap, and bpf syscalls and accessing memory shared
+// with the kernel through the mapped UMEM and rings.
+#![expect(unsafe_code)]
+
+use std::ffi::CString;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::os::fd::RawFd;
+use std::ptr::NonNull;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use thiserror::Error;
+
+// Definitions from linux/if_xdp.h and linux/bpf.h, which are not in libc.
+const AF_XDP: libc::c_int = 44;
+const SOL_XDP: libc::c_int = 283;
+
+const XDP_MMAP_OFFSETS: libc::c_int = 1;
+const XDP_RX_RING: libc::c_int = 2;
+const XDP_TX_RING: libc::c_int = 3;
+const XDP_UMEM_REG: libc::c_int = 4;
+const XDP_UMEM_FILL_RING: libc::c_int = 5;
+const XDP_UMEM_COMPLETION_RING: libc::c_int = 6;
+
+const XDP_PGOFF_RX_RING: libc::off_t = 0;
+const XDP_PGOFF_TX_RING: libc::off_t = 0x80000000;
+const XDP_UMEM_PGOFF_FILL_RING: libc::off_t = 0x100000000;
+const XDP_UMEM_PGOFF_COMPLETION_RING: libc::off_t = 0x180000000;
+
+const BPF_MAP_UPDATE_ELEM: libc::c_long = 2;
+const BPF_OBJ_GET: libc::c_long = 7;
+
+#[repr(C)]
+struct SockaddrXdp {
+    sxdp_family: u16,
+    sxdp_flags: u16,
+    sxdp_ifindex: u32,
+    sxdp_queue_id: u32,
+    sxdp_shared_umem_fd: u32,
+}
+
+#[repr(C)]
+struct XdpUmemReg {
+    addr: u64,
+    len: u64,
+    chunk_size: u32,
+    headroom: u32,
+    flags: u32,
+    tx_metadata_len: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct XdpRingOffset {
+    producer: u64,
+    consumer: u64,
+    desc: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct XdpMmapOffsets {
+    rx: XdpRingOffset,
+    tx: XdpRingOffset,
+    fr: XdpRingOffset,
+    cr: XdpRingOffset,
+}
+
+/// A packet descriptor on the rx or tx ring.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct XdpDesc {
+    addr: u64,
+    len: u32,
+    options: u32,
+}
+
+#[repr(C)]
+struct BpfObjGetAttr {
+    pathname: u64,
+    bpf_fd: u32,
+    file_flags: u32,
+}
+
+#[repr(C)]
+struct BpfMapUpdateAttr {
+    map_fd: u32,
+    pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+/// The size of each UMEM frame, which bounds the largest packet that can be
+/// sent or received.
+pub const FRAME_SIZE: usize = 4096;
+const FRAME_COUNT: usize = 4096;
+/// Half of the frames are used for receive and half for transmit.
+const RING_SIZE: u32 = (FRAME_COUNT / 2) as u32;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid interface name")]
+    InvalidInterfaceName,
+    #[error("interface {0} not found")]
+    InterfaceNotFound(String),
+    #[error("failed to create AF_XDP socket")]
+    Socket(#[source] io::Error),
+    #[error("failed to allocate UMEM")]
+    AllocateUmem(#[source] io::Error),
+    #[error("failed to configure AF_XDP socket option {0}")]
+    SetOption(&'static str, #[source] io::Error),
+    #[error("failed to query ring offsets")]
+    RingOffsets(#[source] io::Error),
+    #[error("failed to map ring")]
+    MapRing(#[source] io::Error),
+    #[error("failed to bind to queue {1} of {0}")]
+    Bind(String, u32, #[source] io::Error),
+    #[error("failed to open XSKMAP {0}")]
+    OpenMap(String, #[source] io::Error),
+    #[error("failed to insert socket into XSKMAP")]
+    UpdateMap(#[source] io::Error),
+}
+
+/// A memory mapping, unmapped on drop.
+struct Mapping {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: Option<RawFd>, offset: libc::off_t, len: usize) -> io::Result<Self> {
+        let (flags, fd) = match fd {
+            Some(fd) => (libc::MAP_SHARED | libc::MAP_POPULATE, fd),
+            None => (libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1),
+        };
+        // SAFETY: creating a new mapping with no address hint, which does not
+        // alias any existing memory.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: NonNull::new(ptr.cast()).unwrap(),
+            len,
+        })
+    }
+
+    fn at<T>(&self, offset: u64) -> *mut T {
+        assert!(offset as usize + size_of::<T>() <= self.len);
+        // SAFETY: the offset is within the mapping.
+        unsafe { self.ptr.as_ptr().add(offset as usize).cast() }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created by `Mapping::new` and is no longer
+        // referenced.
+        unsafe {
+            libc::munmap(self.ptr.as_ptr().cast(), self.len);
+        }
+    }
+}
+
+/// A single-producer, single-consumer ring shared with the kernel.
+struct Ring<T> {
+    _map: Mapping,
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    descs: *mut T,
+    cached_prod: u32,
+    cached_cons: u32,
+}
+
+impl<T: Copy> Ring<T> {
+    fn map(fd: RawFd, pgoff: libc::off_t, offsets: &XdpRingOffset) -> io::Result<Self> {
+        let map = Mapping::new(Some(fd), pgoff, Self::map_len(offsets))?;
+        Ok(Self::new(map, offsets))
+    }
+
+    /// Returns the length of the mapping of a ring at `offsets`.
+    fn map_len(offsets: &XdpRingOffset) -> usize {
+        offsets.desc as usize + RING_SIZE as usize * size_of::<T>()
+    }
+
+    /// Wraps a mapping of a ring at `offsets`.
+    fn new(map: Mapping, offsets: &XdpRingOffset) -> Self {
+        let producer = map.at::<AtomicU32>(offsets.producer);
+        let consumer = map.at::<AtomicU32>(offsets.consumer);
+        let descs = map.at::<T>(offsets.desc);
+        let mut ring = Self {
+            _map: map,
+            producer,
+            consumer,
+            descs,
+            cached_prod: 0,
+            cached_cons: 0,
+        };
+        ring.cached_prod = ring.producer().load(Ordering::Relaxed);
+        ring.cached_cons = ring.consumer().load(Ordering::Relaxed);
+        ring
+    }
+
+    fn producer(&self) -> &AtomicU32 {
+        // SAFETY: the pointer is within the mapping, which lives as long as
+        // `self`, and is only accessed atomically.
+        unsafe { &*self.producer }
+    }
+
+    fn consumer(&self) -> &AtomicU32 {
+        // SAFETY: as above.
+        unsafe { &*self.consumer }
+    }
+
+    fn slot(&self, index: u32) -> *mut T {
+        // SAFETY: the index is masked to the ring size, so the resulting
+        // pointer is within the mapping.
+        unsafe { self.descs.add((index & (RING_SIZE - 1)) as usize) }
+    }
+
+    /// Returns the number of free entries, for a ring produced by user mode.
+    fn free(&mut self) -> u32 {
+        self.cached_cons = self.consumer().load(Ordering::Acquire);
+        RING_SIZE - self.cached_prod.wrapping_sub(self.cached_cons)
+    }
+
+    /// Writes an entry to a ring produced by user mode. The entry is not
+    /// visible to the kernel until [`Self::submit`] is called.
+    ///
+    /// The caller must have checked that there is a free entry.
+    fn push(&mut self, value: T) {
+        // SAFETY: the slot is free, so the kernel is not accessing it.
+        unsafe { self.slot(self.cached_prod).write_volatile(value) };
+        self.cached_prod = self.cached_prod.wrapping_add(1);
+    }
+
+    fn submit(&mut self) {
+        self.producer().store(self.cached_prod, Ordering::Release);
+    }
+
+    /// Reads an entry from a ring consumed by user mode.
+    fn pop(&mut self) -> Option<T> {
+        if self.cached_cons == self.cached_prod {
+            self.cached_prod = self.producer().load(Ordering::Acquire);
+            if self.cached_cons == self.cached_prod {
+                return None;
+            }
+        }
+        // SAFETY: the slot has been produced by the kernel and not yet
+        // released back to it.
+        let value = unsafe { self.slot(self.cached_cons).read_volatile() };
+        self.cached_cons = self.cached_cons.wrapping_add(1);
+        self.consumer().store(self.cached_cons, Ordering::Release);
+        Some(value)
+    }
+}
+
+/// An AF_XDP socket bound to a single queue of a network interface.
+pub struct Xsk {
+    // N.B. The rings must be unmapped before the socket is closed.
+    rx: Ring<XdpDesc>,
+    tx: Ring<XdpDesc>,
+    fill: Ring<u64>,
+    completion: Ring<u64>,
+    umem: Mapping,
+    fd: OwnedFd,
+    tx_free: Vec<u64>,
+    tx_pending: bool,
+}
+
+// SAFETY: the raw pointers refer to mappings owned by this object, and the
+// rings are only accessed through `&mut self`.
+unsafe impl Send for Xsk {}
+// SAFETY: no shared access is possible through `&self`.
+unsafe impl Sync for Xsk {}
+
+fn setsockopt<T>(fd: &OwnedFd, name: libc::c_int, value: &T) -> io::Result<()> {
+    // SAFETY: passing a valid buffer of the specified length.
+    let r = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            SOL_XDP,
+            name,
+            std::ptr::from_ref(value).cast(),
+            size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl Xsk {
+    /// Creates a socket bound to queue `queue_id` of interface `interface`.
+    pub fn new(interface: &str, queue_id: u32) -> Result<Self, Error> {
+        let name = CString::new(interface).map_err(|_| Error::InvalidInterfaceName)?;
+        // SAFETY: passing a valid nul-terminated string.
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(Error::InterfaceNotFound(interface.to_owned()));
+        }
+
+        // SAFETY: creating a new socket, whose fd is owned by the result.
+        let fd = unsafe {
+            let fd = libc::socket(
+                AF_XDP,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                0,
+            );
+            if fd < 0 {
+                return Err(Error::Socket(io::Error::last_os_error()));
+            }
+            OwnedFd::from_raw_fd(fd)
+        };
+
+        let umem = Mapping::new(None, 0, FRAME_SIZE * FRAME_COUNT).map_err(Error::AllocateUmem)?;
+        setsockopt(
+            &fd,
+            XDP_UMEM_REG,
+            &XdpUmemReg {
+                addr: umem.ptr.as_ptr() as u64,
+                len: umem.len as u64,
+                chunk_size: FRAME_SIZE as u32,
+                headroom: 0,
+                flags: 0,
+                tx_metadata_len: 0,
+            },
+        )
+        .map_err(|err| Error::SetOption("umem", err))?;
+        for (name, option) in [
+            ("fill ring", XDP_UMEM_FILL_RING),
+            ("completion ring", XDP_UMEM_COMPLETION_RING),
+            ("rx ring", XDP_RX_RING),
+            ("tx ring", XDP_TX_RING),
+        ] {
+            setsockopt(&fd, option, &RING_SIZE).map_err(|err| Error::SetOption(name, err))?;
+        }
+
+        let mut offsets = XdpMmapOffsets::default();
+        let mut len = size_of_val(&offsets) as libc::socklen_t;
+        // SAFETY: passing a valid buffer of the specified length.
+        let r = unsafe {
+            libc::getsockopt(
+                fd.as_raw_fd(),
+                SOL_XDP,
+                XDP_MMAP_OFFSETS,
+                std::ptr::from_mut(&mut offsets).cast(),
+                &mut len,
+            )
+        };
+        if r < 0 {
+            return Err(Error::RingOffsets(io::Error::last_os_error()));
+        }
+        if len as usize != size_of_val(&offsets) {
+            // Kernels before 5.4 do not report the ring flags offsets.
+            return Err(Error::RingOffsets(io::ErrorKind::Unsupported.into()));
+        }
+
+        let raw = fd.as_raw_fd();
+        let mut xsk = Self {
+            rx: Ring::map(raw, XDP_PGOFF_RX_RING, &offsets.rx).map_err(Error::MapRing)?,
+            tx: Ring::map(raw, XDP_PGOFF_TX_RING, &offsets.tx).map_err(Error::MapRing)?,
+            fill: Ring::map(raw, XDP_UMEM_PGOFF_FILL_RING, &offsets.fr).map_err(Error::MapRing)?,
+            completion: Ring::map(raw, XDP_UMEM_PGOFF_COMPLETION_RING, &offsets.cr)
+                .map_err(Error::MapRing)?,
+            umem,
+            fd,
+            tx_free: Vec::new(),
+            tx_pending: false,
+        };
+
+        // Give the first half of the frames to the kernel for receive, and
+        // keep the rest for transmit.
+        for i in 0..RING_SIZE as u64 {
+            xsk.fill.push(i * FRAME_SIZE as u64);
+        }
+        xsk.fill.submit();
+        xsk.tx_free = (RING_SIZE as u64..FRAME_COUNT as u64)
+            .map(|i| i * FRAME_SIZE as u64)
+            .collect();
+
+        let addr = SockaddrXdp {
+            sxdp_family: AF_XDP as u16,
+            sxdp_flags: 0,
+            sxdp_ifindex: ifindex,
+            sxdp_queue_id: queue_id,
+            sxdp_shared_umem_fd: 0,
+        };
+        // SAFETY: passing a valid address of the specified length.
+        let r = unsafe {
+            libc::bind(
+                xsk.fd.as_raw_fd(),
+                std::ptr::from_ref(&addr).cast(),
+                size_of_val(&addr) as libc::socklen_t,
+            )
+        };
+        if r < 0 {
+            return Err(Error::Bind(
+                interface.to_owned(),
+                queue_id,
+                io::Error::last_os_error(),
+            ));
+        }
+        Ok(xsk)
+    }
+
+    /// Inserts this socket into the pinned XSKMAP at `path`, keyed by
+    /// `queue_id`, so that an XDP program redirecting into the map delivers
+    /// packets to it.
+    pub fn insert_into_map(&self, path: &str, queue_id: u32) -> Result<(), Error> {
+        let open_err = |err| Error::OpenMap(path.to_owned(), err);
+        let pathname =
+            CString::new(path).map_err(|_| open_err(io::ErrorKind::InvalidInput.into()))?;
+        let attr = BpfObjGetAttr {
+            pathname: pathname.as_ptr() as u64,
+            bpf_fd: 0,
+            file_flags: 0,
+        };
+        // SAFETY: calling the bpf syscall with a valid attribute buffer. The
+        // returned fd is owned by the result.
+        let map = unsafe {
+            let fd = libc::syscall(
+                libc::SYS_bpf,
+                BPF_OBJ_GET,
+                &attr,
+                size_of_val(&attr) as libc::c_uint,
+            );
+            if fd < 0 {
+                return Err(open_err(io::Error::last_os_error()));
+            }
+            OwnedFd::from_raw_fd(fd as RawFd)
+        };
+        let value = self.fd.as_raw_fd() as u32;
+        let attr = BpfMapUpdateAttr {
+            map_fd: map.as_raw_fd() as u32,
+            pad: 0,
+            key: std::ptr::from_ref(&queue_id) as u64,
+            value: std::ptr::from_ref(&value) as u64,
+            flags: 0,
+        };
+        // SAFETY: calling the bpf syscall with a valid attribute buffer whose
+        // key and value pointers are valid for the duration of the call.
+        let r = unsafe {
+            libc::syscall(
+                libc::SYS_bpf,
+                BPF_MAP_UPDATE_ELEM,
+                &attr,
+                size_of_val(&attr) as libc::c_uint,
+            )
+        };
+        if r < 0 {
+            return Err(Error::UpdateMap(io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    /// Calls `f` with the next received packet, if any, then returns its frame
+    /// to the kernel.
+    pub fn recv(&mut self, f: impl FnOnce(&[u8])) -> bool {
+        let Some(desc) = self.rx.pop() else {
+            return false;
+        };
+        // SAFETY: the frame was produced on the rx ring and is owned by user
+        // mode until it is returned on the fill ring.
+        f(unsafe { rx_data(&self.umem, &desc) });
+        // The fill ring is the same size as the number of receive frames, so
+        // there is always room to return a frame.
+        self.fill.push(desc.addr & !(FRAME_SIZE as u64 - 1));
+        self.fill.submit();
+        true
+    }
+
+    /// Copies `frame` into a free transmit frame and queues it for send.
+    ///
+    /// Returns false if the frame is too large or there are no free transmit
+    /// frames.
+    pub fn send(&mut self, frame: &[u8]) -> bool {
+        while let Some(addr) = self.completion.pop() {
+            self.tx_free.push(addr);
+        }
+        if frame.len() > FRAME_SIZE || self.tx.free() == 0 {
+            return false;
+        }
+        let Some(addr) = self.tx_free.pop() else {
+            return false;
+        };
+        // SAFETY: the frame is on the free list, so the kernel is not
+        // accessing it.
+        let buf = unsafe { std::slice::from_raw_parts_mut(self.umem.at::<u8>(addr), FRAME_SIZE) };
+        buf[..frame.len()].copy_from_slice(frame);
+        self.tx.push(XdpDesc {
+            addr,
+            len: frame.len() as u32,
+            options: 0,
+        });
+        self.tx_pending = true;
+        true
+    }
+
+    /// Makes queued transmit frames visible to the kernel and starts
+    /// transmit.
+    pub fn flush(&mut self) {
+        if !std::mem::take(&mut self.tx_pending) {
+            return;
+        }
+        self.tx.submit();
+        // SAFETY: a zero-length send with no buffer is how AF_XDP sockets are
+        // told to process the tx ring.
+        let r = unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                std::ptr::null(),
+                0,
+                libc::MSG_DONTWAIT,
+                std::ptr::null(),
+                0,
+            )
+        };
+        if r < 0 {
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                // The kernel is still processing earlier frames and will pick
+                // these up.
+                Some(libc::EAGAIN | libc::EBUSY | libc::ENOBUFS) => {}
+                _ => {
+                    tracelimit::warn_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "AF_XDP transmit kick failed"
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Returns the received data described by `desc`, truncated to the end of its
+/// frame.
+///
+/// # Safety
+/// The caller must own the frame.
+unsafe fn rx_data<'a>(umem: &'a Mapping, desc: &XdpDesc) -> &'a [u8] {
+    let len = (desc.len as usize).min(FRAME_SIZE - (desc.addr as usize % FRAME_SIZE));
+    // SAFETY: the frame is within the mapping, since `at` checks that its
+    // start is and UMEM frames do not straddle the end of the mapping. The
+    // caller guarantees that the kernel is not accessing it.
+    unsafe { std::slice::from_raw_parts(umem.at::<u8>(desc.addr).cast_const(), len) }
+}
+
+impl AsRawFd for Xsk {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FRAME_SIZE;
+    use super::Mapping;
+    use super::RING_SIZE;
+    use super::Ring;
+    use super::XdpDesc;
+    use super::XdpRingOffset;
+    use super::rx_data;
+    use std::sync::atomic::Ordering;
+
+    const OFFSETS: XdpRingOffset = XdpRingOffset {
+        producer: 0,
+        consumer: 64,
+        desc: 128,
+        flags: 192,
+    };
+
+    /// Creates a ring on an anonymous mapping, with the producer and consumer
+    /// indexes starting at `start`.
+    fn ring<T: Copy>(start: u32) -> Ring<T> {
+        let map = Mapping::new(None, 0, Ring::<T>::map_len(&OFFSETS)).unwrap();
+        // SAFETY: the indexes are within the mapping, which is not shared.
+        unsafe {
+            map.at::<u32>(OFFSETS.producer).write(start);
+            map.at::<u32>(OFFSETS.consumer).write(start);
+        }
+        Ring::new(map, &OFFSETS)
+    }
+
+    #[test]
+    fn produce_wraparound() {
+        // Start close to the end of the index space so that the indexes wrap.
+        let start = u32::MAX - 2;
+        let mut ring = ring::<u64>(start);
+        assert_eq!(ring.free(), RING_SIZE);
+        for i in 0..RING_SIZE as u64 {
+            ring.push(i);
+        }
+        assert_eq!(ring.free(), 0);
+        // Nothing is visible to the consumer until submitted.
+        assert_eq!(ring.producer().load(Ordering::Relaxed), start);
+        ring.submit();
+        assert_eq!(
+            ring.producer().load(Ordering::Relaxed),
+            start.wrapping_add(RING_SIZE)
+        );
+        // The entries are in ring order, starting at the masked index.
+        for i in 0..RING_SIZE {
+            let index = start.wrapping_add(i);
+            // SAFETY: the slot is within the mapping, and there is no
+            // concurrent consumer.
+            assert_eq!(unsafe { ring.slot(index).read() }, i as u64);
+        }
+
+        // Consuming entries frees them.
+        ring.consumer()
+            .store(start.wrapping_add(5), Ordering::Relaxed);
+        assert_eq!(ring.free(), 5);
+        ring.push(100);
+        ring.submit();
+        // SAFETY: as above.
+        assert_eq!(unsafe { ring.slot(start).read() }, 100);
+    }
+
+    #[test]
+    fn consume_wraparound() {
+        let start = u32::MAX - 1;
+        let mut ring = ring::<XdpDesc>(start);
+        assert!(ring.pop().is_none());
+        for i in 0..4 {
+            // SAFETY: the slot is within the mapping, and there is no
+            // concurrent consumer.
+            unsafe {
+                ring.slot(start.wrapping_add(i)).write(XdpDesc {
+                    addr: i as u64 * FRAME_SIZE as u64,
+                    len: i,
+                    options: 0,
+                })
+            };
+        }
+        // Entries are not consumed until the producer publishes them.
+        assert!(ring.pop().is_none());
+        ring.producer()
+            .store(start.wrapping_add(4), Ordering::Relaxed);
+        for i in 0..4 {
+            let desc = ring.pop().unwrap();
+            assert_eq!((desc.addr, desc.len), (i as u64 * FRAME_SIZE as u64, i));
+            assert_eq!(
+                ring.consumer().load(Ordering::Relaxed),
+                start.wrapping_add(i + 1)
+            );
+        }
+        assert!(ring.pop().is_none());
+    }
+
+    #[test]
+    fn rx_data_bounds() {
+        let umem = Mapping::new(None, 0, FRAME_SIZE * 2).unwrap();
+        let desc = |addr: usize, len: usize| XdpDesc {
+            addr: addr as u64,
+            len: len as u32,
+            options: 0,
+        };
+        // SAFETY: the frames are not shared with a kernel.
+        unsafe {
+            assert_eq!(rx_data(&umem, &desc(FRAME_SIZE, 100)).len(), 100);
+            // Lengths are truncated at the end of the frame, even in the last
+            // frame of the UMEM.
+            assert_eq!(
+                rx_data(&umem, &desc(FRAME_SIZE + 100, FRAME_SIZE)).len(),
+                FRAME_SIZE - 100
+            );
+            assert_eq!(
+                rx_data(&umem, &desc(100, FRAME_SIZE * 2)).len(),
+                FRAME_SIZE - 100
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn rx_data_out_of_range() {
+        let umem = Mapping::new(None, 0, FRAME_SIZE).unwrap();
+        let desc = XdpDesc {
+            addr: FRAME_SIZE as u64,
+            len: 1,
+            options: 0,
+        };
+        // SAFETY: the frame is not shared with a kernel.
+        unsafe { rx_data(&umem, &desc) };
+    }
+}
@@ -36,10 +36,8 @@
 use guestmem::ranges::PagedRanges;
 use guestmem::ranges::PagedRangesReader;
 use guid::Guid;
+use hvdef::hypercall::GuestOsFamily;
 use hvdef::hypercall::HvGuestOsId;
-use hvdef::hypercall::HvGuestOsMicrosoft;
-use hvdef::hypercall::HvGuestOsMicrosoftIds;
-use hvdef::hypercall::HvGuestOsOpenSourceType;
 use inspect::Inspect;
 use inspect::InspectMut;
 use inspect::SensitivityLevel;
@@ -657,8 +655,8 @@ struct PrimaryChannelState {
     pending_link_action: PendingLinkAction,
 }
 
-impl Inspect for PrimaryChannelState {
-    fn inspect(&self, req: inspect::Request<'_>) {
+impl InspectMut for PrimaryChannelState {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
         req.respond()
             .sensitivity_field(
                 "guest_vf_state",
@@ -696,7 +694,27 @@ fn inspect(&self, req: inspect::Request<'_>) {
                 SensitivityLevel::Safe,
                 self.tx_spread_sent,
             )
-            .sensitivity_field("guest_link_up", SensitivityLevel::Safe, self.guest_link_up)
+            .sensitivity_field_mut(
+                "guest_link_up",
+                SensitivityLevel::Safe,
+                // Writing queues a link status indication to the guest, as if
+                // the endpoint had reported a link change. Writing the current
+                // value cancels any pending change instead.
+                inspect::adhoc_mut(|req| match req.update() {
+                    Ok(req) => match req.new_value().parse() {
+                        Ok(up) => {
+                            self.pending_link_action = if up == self.guest_link_up {
+                                PendingLinkAction::Default
+                            } else {
+                                PendingLinkAction::Active(up)
+                            };
+                            req.succeed(up);
+                        }
+                        Err(err) => req.fail(err),
+                    },
+                    Err(req) => req.value(self.guest_link_up),
+                }),
+            )
             .sensitivity_field(
                 "pending_link_action",
                 SensitivityLevel::Safe,
@@ -1188,23 +1206,18 @@ fn can_use_ring_opt<T: RingMem>(queue: &mut Queue<T>, guest_os_id: Option<HvGues
         return false;
     }
 
-    let Some(open_source_os) = guest_os_id.open_source() else {
-        // guest os is proprietary (ex: Windows)
-        return true;
-    };
-
-    match HvGuestOsOpenSourceType(open_source_os.os_type()) {
+    match guest_os_id.family() {
         // Although FreeBSD indicates support for `pending send size`, it doesn't
         // implement it correctly. This was fixed in FreeBSD version `1400097`.
-        HvGuestOsOpenSourceType::FREEBSD => open_source_os.version() >= 1400097,
+        GuestOsFamily::FreeBsd { version } => version >= 1400097,
         // Linux kernels prior to 3.11 have issues with pending send size optimization
         // which can affect certain Asynchronous I/O (AIO) network operations.
         // Disable ring size optimization for these older kernels to avoid flow control issues.
-        HvGuestOsOpenSourceType::LINUX => {
-            // Linux version is encoded as: ((major << 16) | (minor << 8) | patch)
+        GuestOsFamily::Linux { version } => {
             // Linux 3.11.0 = (3 << 16) | (11 << 8) | 0 = 199424
-            open_source_os.version() >= 199424
+            version >= 199424
         }
+        // Proprietary (ex: Windows) and other open source guests.
         _ => true,
     }
 }
@@ -2122,7 +2135,28 @@ fn parse_packet<'a, T: RingMem>(
         }
     };
 
-    let mut reader = packet.reader();
+    // Read the message straight from guest memory when the ring is backed by
+    // it.
+    let data = match packet.read_packet_in_place() {
+        Some(reader) => parse_data_packet(reader, send_buffer, version, external_data)?,
+        None => parse_data_packet(packet.reader(), send_buffer, version, external_data)?,
+    };
+    packet
+        .read_external_ranges(external_data)
+        .map_err(PacketError::ExternalData)?;
+    Ok(Packet {
+        data,
+        transaction_id: packet.transaction_id(),
+        external_data,
+    })
+}
+
+fn parse_data_packet(
+    mut reader: impl MemoryRead,
+    send_buffer: Option<&SendBuffer>,
+    version: Option<Version>,
+    external_data: &mut MultiPagedRangeBuf,
+) -> Result<PacketData, PacketError> {
     let header: protocol::MessageHeader = reader.read_plain().map_err(PacketError::Access)?;
     let data = match header.message_type {
         protocol::MESSAGE_TYPE_INIT => PacketData::Init(read_packet_data(&mut reader)?),
@@ -2173,14 +2207,7 @@ fn parse_packet<'a, T: RingMem>(
         }
         typ => return Err(PacketError::UnknownType(typ)),
     };
-    packet
-        .read_external_ranges(external_data)
-        .map_err(PacketError::ExternalData)?;
-    Ok(Packet {
-        data,
-        transaction_id: packet.transaction_id(),
-        external_data,
-    })
+    Ok(data)
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -3254,12 +3281,7 @@ fn get_guest_vf_serial_number(&self, vfid: u32) -> u32 {
         if let Some(guest_os_id) = self.get_guest_os_id.as_ref().map(|f| f()) {
             // For enlightened guests (which is only Windows at the moment), send the
             // adapter index, which was previously set as the vport serial number.
-            if guest_os_id
-                .microsoft()
-                .unwrap_or(HvGuestOsMicrosoft::from(0))
-                .os_id()
-                == HvGuestOsMicrosoftIds::WINDOWS_NT.0
-            {
+            if guest_os_id.family() == GuestOsFamily::Windows {
                 self.adapter_index
             } else {
                 vfid
@@ -3919,16 +3941,16 @@ fn inspect_mut(
             resp.merge(inspect::adhoc_mut(|req| {
                 let deferred = req.defer();
                 coordinator.workers[0].update_with(|_, worker| {
-                    let Some(worker) = worker.as_deref() else {
+                    let Some(worker) = worker.as_deref_mut() else {
                         return;
                     };
-                    if let Some(state) = worker.state.ready() {
+                    if let Some(state) = worker.state.ready_mut() {
                         deferred.respond(|resp| {
                             resp.merge(&state.buffers);
-                            resp.sensitivity_field(
+                            resp.sensitivity_field_mut(
                                 "primary_channel_state",
                                 SensitivityLevel::Safe,
-                                &state.state.primary,
+                                &mut state.state.primary,
                             )
                             .sensitivity_field(
                                 "packet_filter",
@@ -788,7 +788,8 @@ pub async fn restore(
                                         })
                                     })
                                 }
-                                vmbus_channel::bus::ChannelServerRequest::Revoke(_) => (),
+                                vmbus_channel::bus::ChannelServerRequest::Revoke(_)
+                                | vmbus_channel::bus::ChannelServerRequest::Inspect(_) => (),
                             }
                         }
                     }
@@ -6022,3 +6023,54 @@ async fn subchannel_request_equal_to_max_queues_rejected(driver: DefaultDriver)
         .await
         .expect("completion message");
 }
+
+#[async_test]
+async fn inspect_guest_link_up(_driver: DefaultDriver) {
+    let checksum = ChecksumOffloadConfig {
+        ipv4_header: false,
+        tcp4: false,
+        udp4: false,
+        tcp6: false,
+        udp6: false,
+    };
+    let mut primary = PrimaryChannelState::new(OffloadConfig {
+        checksum_tx: checksum.clone(),
+        checksum_rx: checksum,
+        lso4: false,
+        lso6: false,
+    });
+    assert!(primary.guest_link_up);
+
+    // Writing the current value does not flap the link.
+    inspect::update("guest_link_up", "true", &mut primary)
+        .await
+        .unwrap();
+    assert!(matches!(
+        primary.pending_link_action,
+        PendingLinkAction::Default
+    ));
+
+    inspect::update("guest_link_up", "false", &mut primary)
+        .await
+        .unwrap();
+    assert!(matches!(
+        primary.pending_link_action,
+        PendingLinkAction::Active(false)
+    ));
+
+    // Writing the current value again cancels the pending change.
+    inspect::update("guest_link_up", "true", &mut primary)
+        .await
+        .unwrap();
+    assert!(matches!(
+        primary.pending_link_action,
+        PendingLinkAction::Default
+    ));
+
+    assert!(
+        inspect::update("guest_link_up", "maybe", &mut primary)
+            .await
+            .is_err()
+    );
+    assert!(primary.guest_link_up);
+}
@@ -63,12 +63,19 @@ pub trait CreateMemoryAccess: 'static + Send + Sync {
 /// The size of the MMIO region required for each VPCI device.
 pub const VPCI_RELAY_MMIO_PER_DEVICE: u64 = vpci_client::MMIO_SIZE;
 
+/// The default number of pages used for each VPCI bus channel's ring buffer.
+pub const DEFAULT_RING_PAGES: u16 = 20;
+
 /// Flags for controlling optional behavior of the VPCI relay.
 #[derive(Inspect, Debug, Default, Copy, Clone)]
 pub struct VpciRelayOptions {
     /// When set, the relay will exercise a mock TDISP flow for emulated TDISP
     /// devices produced by OpenVMM tests.
     pub test_tdisp_flow: bool,
+    /// The number of pages to use for each VPCI bus channel's ring buffer,
+    /// split evenly between the incoming and outgoing rings. If `None`,
+    /// [`DEFAULT_RING_PAGES`] is used.
+    pub ring_pages: Option<u16>,
 }
 
 /// Virtual PCI relay.
@@ -98,6 +105,7 @@ pub struct VpciRelay {
 #[derive(Inspect)]
 struct RelayedDevice {
     bus_instance_id: Guid,
+    ring: OpenParams,
     bus_client: VpciClient,
     #[inspect(skip)]
     removed: VpciDeviceEject,
@@ -264,13 +272,11 @@ async fn relay_vpci_bus(
             self.mmio_range.start() + (entry.key() as u64) * vpci_client::MMIO_SIZE,
         )?;
 
+        let ring = OpenParams::split_evenly(self.options.ring_pages.unwrap_or(DEFAULT_RING_PAGES));
         let channel = vmbus_client::driver::open_channel(
             self.driver_source.simple(),
             offer_info,
-            OpenParams {
-                ring_pages: 20,
-                ring_offset_in_pages: 10,
-            },
+            ring,
             self.dma_client.as_ref(),
         )
         .await?;
@@ -356,6 +362,7 @@ async fn relay_vpci_bus(
 
         entry.insert(RelayedDevice {
             bus_instance_id: instance_id,
+            ring,
             bus_client: vpci_client,
             removed,
             bus_unit,
@@ -441,3 +448,16 @@ fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
         match state {}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::DEFAULT_RING_PAGES;
+    use vmbus_client::driver::OpenParams;
+
+    #[test]
+    fn test_default_ring_layout() {
+        OpenParams::split_evenly(DEFAULT_RING_PAGES)
+            .validate()
+            .unwrap();
+    }
+}
@@ -108,6 +108,14 @@ pub trait DiskIo: 'static + Send + Sync + Inspect {
     /// committed to disk.
     fn is_fua_respected(&self) -> bool;
 
+    /// Returns true if completed writes may be held in a volatile cache until
+    /// [`DiskIo::sync_cache`] is called, so the guest should be told to flush.
+    ///
+    /// This must not change at runtime.
+    fn has_volatile_write_cache(&self) -> bool {
+        true
+    }
+
     /// Returns true if the disk is read only.
     fn is_read_only(&self) -> bool;
 
@@ -164,6 +172,24 @@ fn write_vectored(
     /// Issues an asynchronous flush operation to the disk.
     fn sync_cache(&self) -> impl Future<Output = Result<(), DiskError>> + Send;
 
+    /// Quiesces the disk in preparation for a snapshot of the backing store.
+    ///
+    /// When this completes successfully, all previously completed writes have
+    /// been committed to the backing store. The caller must not issue further
+    /// writes until [`DiskIo::thaw`] is called; typically the guest has
+    /// already frozen its file systems at this point.
+    ///
+    /// The default implementation flushes the disk with
+    /// [`DiskIo::sync_cache`].
+    fn quiesce(&self) -> impl Future<Output = Result<(), DiskError>> + Send {
+        self.sync_cache()
+    }
+
+    /// Resumes a disk previously quiesced with [`DiskIo::quiesce`].
+    fn thaw(&self) -> impl Future<Output = Result<(), DiskError>> + Send {
+        ready(Ok(()))
+    }
+
     /// Waits for the disk sector size to be different than the specified value.
     fn wait_resize(&self, sector_count: u64) -> impl Future<Output = u64> + Send {
         let _ = sector_count;
@@ -201,6 +227,7 @@ struct DiskInner<T: ?Sized = dyn DynDisk> {
     physical_sector_size: u32,
     disk_id: Option<[u8; 16]>,
     is_fua_respected: bool,
+    has_volatile_write_cache: bool,
     is_read_only: bool,
     unmap_behavior: UnmapBehavior,
     optimal_unmap_sectors: u32,
@@ -239,6 +266,7 @@ pub fn new(disk: impl 'static + DiskIo) -> Result<Self, InvalidDisk> {
             physical_sector_size,
             disk_id: disk.disk_id(),
             is_fua_respected: disk.is_fua_respected(),
+            has_volatile_write_cache: disk.has_volatile_write_cache(),
             is_read_only: disk.is_read_only(),
             optimal_unmap_sectors: disk.optimal_unmap_sectors(),
             unmap_behavior: disk.unmap_behavior(),
@@ -284,6 +312,12 @@ pub fn is_fua_respected(&self) -> bool {
         self.0.is_fua_respected
     }
 
+    /// Returns true if completed writes may be held in a volatile cache until
+    /// [`sync_cache`](Self::sync_cache) is called.
+    pub fn has_volatile_write_cache(&self) -> bool {
+        self.0.has_volatile_write_cache
+    }
+
     /// Returns true if the disk is read only.
     pub fn is_read_only(&self) -> bool {
         self.0.is_read_only
@@ -359,6 +393,18 @@ pub fn sync_cache(&self) -> impl use<'_> + Future<Output = Result<(), DiskError>
         self.0.disk.sync_cache()
     }
 
+    /// Quiesces the disk in preparation for a snapshot of the backing store.
+    ///
+    /// See [`DiskIo::quiesce`] for details.
+    pub fn quiesce(&self) -> impl use<'_> + Future<Output = Result<(), DiskError>> + Send {
+        self.0.disk.quiesce()
+    }
+
+    /// Resumes a disk previously quiesced with [`quiesce`](Self::quiesce).
+    pub fn thaw(&self) -> impl use<'_> + Future<Output = Result<(), DiskError>> + Send {
+        self.0.disk.thaw()
+    }
+
     /// Waits for the disk sector size to be different than the specified value.
     pub fn wait_resize(&self, sector_count: u64) -> impl use<'_> + Future<Output = u64> {
         self.0.disk.wait_resize(sector_count)
@@ -404,6 +450,10 @@ fn write_vectored<'a>(
 
     fn sync_cache(&self) -> IoFuture<'_>;
 
+    fn quiesce(&self) -> IoFuture<'_>;
+
+    fn thaw(&self) -> IoFuture<'_>;
+
     fn wait_resize<'a>(
         &'a self,
         sector_count: u64,
@@ -455,4 +505,12 @@ fn write_vectored<'a>(
     fn sync_cache(&self) -> IoFuture<'_> {
         StackFuture::from_or_box(self.sync_cache())
     }
+
+    fn quiesce(&self) -> IoFuture<'_> {
+        StackFuture::from_or_box(self.quiesce())
+    }
+
+    fn thaw(&self) -> IoFuture<'_> {
+        StackFuture::from_or_box(self.thaw())
+    }
 }
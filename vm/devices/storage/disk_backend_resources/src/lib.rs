@@ -48,6 +48,50 @@ impl ResourceId<DiskHandleKind> for DelayDiskHandle {
     const ID: &'static str = "delay";
 }
 
+/// Disk handle for a disk with an explicit write cache policy.
+#[derive(MeshPayload)]
+pub struct CacheModeDiskHandle {
+    /// The underlying disk resource.
+    pub disk: Resource<DiskHandleKind>,
+    /// How writes and flushes are passed to the underlying disk.
+    pub mode: DiskCacheMode,
+}
+
+impl ResourceId<DiskHandleKind> for CacheModeDiskHandle {
+    const ID: &'static str = "cache_mode";
+}
+
+/// The write cache policy for a disk.
+#[derive(MeshPayload, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DiskCacheMode {
+    /// Writes may be cached. Flushes and FUA writes are made durable, emulating
+    /// FUA with a flush if the underlying disk does not support it.
+    WriteBack,
+    /// Every write is made durable before it completes.
+    WriteThrough,
+    /// Flushes and FUA are ignored. Data may be lost if the host crashes.
+    Unsafe,
+}
+
+/// Disk handle for a namespace on a remote NVMe/TCP controller.
+#[derive(MeshPayload)]
+pub struct NvmeTcpDiskHandle {
+    /// The controller address, as `host:port`.
+    pub address: String,
+    /// The NQN of the subsystem to connect to.
+    pub subsystem_nqn: String,
+    /// The host NQN. If not set, one is generated from a random host ID.
+    pub host_nqn: Option<String>,
+    /// The namespace ID.
+    pub nsid: u32,
+    /// The I/O queue depth to request.
+    pub queue_depth: u16,
+}
+
+impl ResourceId<DiskHandleKind> for NvmeTcpDiskHandle {
+    const ID: &'static str = "nvme_tcp";
+}
+
 /// Disk handle for a fixed VHD1 disk.
 #[derive(MeshPayload)]
 pub struct FixedVhd1DiskHandle(pub std::fs::File);
@@ -56,6 +100,14 @@ impl ResourceId<DiskHandleKind> for FixedVhd1DiskHandle {
     const ID: &'static str = "fixed_vhd1";
 }
 
+/// Disk handle for a fixed or dynamic VHDX disk, opened in user mode.
+#[derive(MeshPayload)]
+pub struct VhdxDiskHandle(pub std::fs::File);
+
+impl ResourceId<DiskHandleKind> for VhdxDiskHandle {
+    const ID: &'static str = "vhdx";
+}
+
 /// Disk configuration for a striped disk.
 #[derive(MeshPayload)]
 pub struct StripedDiskHandle {
@@ -0,0 +1,342 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A disk wrapper that applies an explicit write cache policy to an inner disk,
+//! so that FUA and SYNCHRONIZE CACHE behave the same regardless of backend.
+
+#![forbid(unsafe_code)]
+
+/// Provides a disk with an explicit write cache policy.
+pub mod resolver;
+
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::UnmapBehavior;
+use disk_backend_resources::DiskCacheMode;
+use inspect::Inspect;
+use inspect_counters::SharedCounter;
+use scsi_buffers::RequestBuffers;
+
+/// A disk that applies a write cache policy to an inner disk.
+#[derive(Inspect)]
+pub struct CacheModeDisk {
+    #[inspect(debug)]
+    mode: DiskCacheMode,
+    inner: Disk,
+    /// FUA writes emulated with a flush because the inner disk ignores FUA.
+    emulated_fua: SharedCounter,
+    /// Flushes dropped because the cache mode is unsafe.
+    ignored_flushes: SharedCounter,
+}
+
+impl CacheModeDisk {
+    /// Creates a new disk applying `mode` to `inner`.
+    pub fn new(mode: DiskCacheMode, inner: Disk) -> Self {
+        Self {
+            mode,
+            inner,
+            emulated_fua: SharedCounter::new(),
+            ignored_flushes: SharedCounter::new(),
+        }
+    }
+
+    /// Writes to the inner disk and makes the data durable before returning.
+    async fn write_durable(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        if self.inner.is_fua_respected() {
+            self.inner.write_vectored(buffers, sector, true).await
+        } else {
+            self.inner.write_vectored(buffers, sector, false).await?;
+            self.emulated_fua.increment();
+            self.inner.sync_cache().await
+        }
+    }
+}
+
+impl DiskIo for CacheModeDisk {
+    fn disk_type(&self) -> &str {
+        "cache_mode"
+    }
+
+    /// Passthrough
+    fn sector_count(&self) -> u64 {
+        self.inner.sector_count()
+    }
+
+    /// Passthrough
+    fn sector_size(&self) -> u32 {
+        self.inner.sector_size()
+    }
+
+    /// Passthrough
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        self.inner.disk_id()
+    }
+
+    /// Passthrough
+    fn physical_sector_size(&self) -> u32 {
+        self.inner.physical_sector_size()
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        match self.mode {
+            // FUA is emulated with a flush when the inner disk ignores it.
+            DiskCacheMode::WriteBack | DiskCacheMode::WriteThrough => true,
+            DiskCacheMode::Unsafe => false,
+        }
+    }
+
+    fn has_volatile_write_cache(&self) -> bool {
+        match self.mode {
+            DiskCacheMode::WriteBack => self.inner.has_volatile_write_cache(),
+            // Every write is durable, or flushing would do nothing.
+            DiskCacheMode::WriteThrough | DiskCacheMode::Unsafe => false,
+        }
+    }
+
+    /// Passthrough
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    /// Passthrough
+    fn pr(&self) -> Option<&dyn disk_backend::pr::PersistentReservation> {
+        self.inner.pr()
+    }
+
+    /// Passthrough
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        self.inner.read_vectored(buffers, sector).await
+    }
+
+    async fn write_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        match self.mode {
+            DiskCacheMode::WriteBack if fua => self.write_durable(buffers, sector).await,
+            DiskCacheMode::WriteBack | DiskCacheMode::Unsafe => {
+                self.inner.write_vectored(buffers, sector, false).await
+            }
+            DiskCacheMode::WriteThrough => self.write_durable(buffers, sector).await,
+        }
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        match self.mode {
+            DiskCacheMode::WriteBack | DiskCacheMode::WriteThrough => self.inner.sync_cache().await,
+            DiskCacheMode::Unsafe => {
+                self.ignored_flushes.increment();
+                Ok(())
+            }
+        }
+    }
+
+    /// Passthrough, even in unsafe mode, since a snapshot of the backing
+    /// store must include all completed writes.
+    async fn quiesce(&self) -> Result<(), DiskError> {
+        self.inner.quiesce().await
+    }
+
+    /// Passthrough
+    async fn thaw(&self) -> Result<(), DiskError> {
+        self.inner.thaw().await
+    }
+
+    /// Passthrough
+    async fn wait_resize(&self, sector_count: u64) -> u64 {
+        self.inner.wait_resize(sector_count).await
+    }
+
+    /// Passthrough
+    fn unmap(
+        &self,
+        sector: u64,
+        count: u64,
+        block_level_only: bool,
+    ) -> impl Future<Output = Result<(), DiskError>> + Send {
+        self.inner.unmap(sector, count, block_level_only)
+    }
+
+    /// Passthrough
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        self.inner.unmap_behavior()
+    }
+
+    /// Passthrough
+    fn optimal_unmap_sectors(&self) -> u32 {
+        self.inner.optimal_unmap_sectors()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use parking_lot::Mutex;
+    use scsi_buffers::OwnedRequestBuffers;
+    use std::sync::Arc;
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Op {
+        Write { fua: bool },
+        Flush,
+    }
+
+    /// A disk that records the writes and flushes issued to it.
+    #[derive(Inspect)]
+    struct RecordingDisk {
+        fua_respected: bool,
+        #[inspect(skip)]
+        ops: Arc<Mutex<Vec<Op>>>,
+    }
+
+    impl DiskIo for RecordingDisk {
+        fn disk_type(&self) -> &str {
+            "recording"
+        }
+
+        fn sector_count(&self) -> u64 {
+            8
+        }
+
+        fn sector_size(&self) -> u32 {
+            512
+        }
+
+        fn disk_id(&self) -> Option<[u8; 16]> {
+            None
+        }
+
+        fn physical_sector_size(&self) -> u32 {
+            512
+        }
+
+        fn is_fua_respected(&self) -> bool {
+            self.fua_respected
+        }
+
+        fn is_read_only(&self) -> bool {
+            false
+        }
+
+        async fn read_vectored(
+            &self,
+            _buffers: &RequestBuffers<'_>,
+            _sector: u64,
+        ) -> Result<(), DiskError> {
+            Ok(())
+        }
+
+        async fn write_vectored(
+            &self,
+            _buffers: &RequestBuffers<'_>,
+            _sector: u64,
+            fua: bool,
+        ) -> Result<(), DiskError> {
+            self.ops.lock().push(Op::Write { fua });
+            Ok(())
+        }
+
+        async fn sync_cache(&self) -> Result<(), DiskError> {
+            self.ops.lock().push(Op::Flush);
+            Ok(())
+        }
+
+        async fn unmap(
+            &self,
+            _sector: u64,
+            _count: u64,
+            _block_level_only: bool,
+        ) -> Result<(), DiskError> {
+            Ok(())
+        }
+
+        fn unmap_behavior(&self) -> UnmapBehavior {
+            UnmapBehavior::Ignored
+        }
+    }
+
+    fn cache_mode_disk(mode: DiskCacheMode, fua_respected: bool) -> (Disk, Arc<Mutex<Vec<Op>>>) {
+        let ops = Arc::new(Mutex::new(Vec::new()));
+        let inner = Disk::new(RecordingDisk {
+            fua_respected,
+            ops: ops.clone(),
+        })
+        .unwrap();
+        (Disk::new(CacheModeDisk::new(mode, inner)).unwrap(), ops)
+    }
+
+    /// Issues a write and a flush and returns what reached the inner disk.
+    async fn write_and_flush(disk: &Disk, ops: &Mutex<Vec<Op>>, fua: bool) -> Vec<Op> {
+        let mem = GuestMemory::allocate(4096);
+        let buffers = OwnedRequestBuffers::linear(0, 512, false);
+        disk.write_vectored(&buffers.buffer(&mem), 0, fua)
+            .await
+            .unwrap();
+        disk.sync_cache().await.unwrap();
+        std::mem::take(&mut *ops.lock())
+    }
+
+    #[async_test]
+    async fn test_write_back() {
+        let (disk, ops) = cache_mode_disk(DiskCacheMode::WriteBack, true);
+        assert!(disk.is_fua_respected());
+        assert!(disk.has_volatile_write_cache());
+        assert_eq!(
+            write_and_flush(&disk, &ops, false).await,
+            [Op::Write { fua: false }, Op::Flush]
+        );
+        assert_eq!(
+            write_and_flush(&disk, &ops, true).await,
+            [Op::Write { fua: true }, Op::Flush]
+        );
+
+        // FUA is emulated with a flush when the inner disk ignores it.
+        let (disk, ops) = cache_mode_disk(DiskCacheMode::WriteBack, false);
+        assert!(disk.is_fua_respected());
+        assert_eq!(
+            write_and_flush(&disk, &ops, true).await,
+            [Op::Write { fua: false }, Op::Flush, Op::Flush]
+        );
+    }
+
+    #[async_test]
+    async fn test_write_through() {
+        let (disk, ops) = cache_mode_disk(DiskCacheMode::WriteThrough, true);
+        assert!(disk.is_fua_respected());
+        assert!(!disk.has_volatile_write_cache());
+        assert_eq!(
+            write_and_flush(&disk, &ops, false).await,
+            [Op::Write { fua: true }, Op::Flush]
+        );
+
+        let (disk, ops) = cache_mode_disk(DiskCacheMode::WriteThrough, false);
+        assert_eq!(
+            write_and_flush(&disk, &ops, false).await,
+            [Op::Write { fua: false }, Op::Flush, Op::Flush]
+        );
+    }
+
+    #[async_test]
+    async fn test_unsafe() {
+        let (disk, ops) = cache_mode_disk(DiskCacheMode::Unsafe, true);
+        assert!(!disk.is_fua_respected());
+        assert!(!disk.has_volatile_write_cache());
+        assert_eq!(
+            write_and_flush(&disk, &ops, true).await,
+            [Op::Write { fua: false }]
+        );
+    }
+}
@@ -0,0 +1,34 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::CacheModeDisk;
+use async_trait::async_trait;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::CacheModeDiskHandle;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::DiskHandleKind;
+
+/// A resolver for CacheModeDisk.
+pub struct CacheModeDiskResolver;
+declare_static_async_resolver!(CacheModeDiskResolver, (DiskHandleKind, CacheModeDiskHandle));
+
+#[async_trait]
+impl AsyncResolveResource<DiskHandleKind, CacheModeDiskHandle> for CacheModeDiskResolver {
+    type Output = ResolvedDisk;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        rsrc: CacheModeDiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let inner = resolver.resolve(rsrc.disk, input).await?;
+
+        ResolvedDisk::new(CacheModeDisk::new(rsrc.mode, inner.0))
+            .map_err(|e| anyhow::anyhow!("failed to create the cache mode disk: {}", e))
+    }
+}
@@ -85,6 +85,10 @@ fn is_fua_respected(&self) -> bool {
         self.inner.is_fua_respected()
     }
 
+    fn has_volatile_write_cache(&self) -> bool {
+        self.inner.has_volatile_write_cache()
+    }
+
     fn is_read_only(&self) -> bool {
         self.inner.is_read_only()
     }
@@ -159,6 +163,14 @@ async fn sync_cache(&self) -> Result<(), DiskError> {
         self.inner.sync_cache().await
     }
 
+    async fn quiesce(&self) -> Result<(), DiskError> {
+        self.inner.quiesce().await
+    }
+
+    async fn thaw(&self) -> Result<(), DiskError> {
+        self.inner.thaw().await
+    }
+
     /// Waits for the disk sector size to be different than the specified value.
     async fn wait_resize(&self, sector_count: u64) -> u64 {
         self.inner.wait_resize(sector_count).await
@@ -195,9 +207,98 @@ fn crypto_error(err: block_crypto::Error) -> DiskError {
 mod tests {
     use crate::CryptDisk;
     use disk_backend::Disk;
+    use disk_backend::DiskError;
+    use disk_backend::DiskIo;
+    use disk_backend::UnmapBehavior;
     use guestmem::GuestMemory;
+    use inspect::Inspect;
     use pal_async::async_test;
     use scsi_buffers::OwnedRequestBuffers;
+    use scsi_buffers::RequestBuffers;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+
+    /// A disk that only tracks whether it is quiesced.
+    #[derive(Inspect)]
+    struct QuiesceDisk {
+        #[inspect(skip)]
+        quiesced: Arc<AtomicBool>,
+    }
+
+    impl DiskIo for QuiesceDisk {
+        fn disk_type(&self) -> &str {
+            "quiesce"
+        }
+
+        fn sector_count(&self) -> u64 {
+            0x1000
+        }
+
+        fn sector_size(&self) -> u32 {
+            512
+        }
+
+        fn disk_id(&self) -> Option<[u8; 16]> {
+            None
+        }
+
+        fn physical_sector_size(&self) -> u32 {
+            512
+        }
+
+        fn is_fua_respected(&self) -> bool {
+            false
+        }
+
+        fn is_read_only(&self) -> bool {
+            false
+        }
+
+        async fn unmap(
+            &self,
+            _sector: u64,
+            _count: u64,
+            _block_level_only: bool,
+        ) -> Result<(), DiskError> {
+            Ok(())
+        }
+
+        fn unmap_behavior(&self) -> UnmapBehavior {
+            UnmapBehavior::Ignored
+        }
+
+        async fn read_vectored(
+            &self,
+            _buffers: &RequestBuffers<'_>,
+            _sector: u64,
+        ) -> Result<(), DiskError> {
+            unreachable!()
+        }
+
+        async fn write_vectored(
+            &self,
+            _buffers: &RequestBuffers<'_>,
+            _sector: u64,
+            _fua: bool,
+        ) -> Result<(), DiskError> {
+            unreachable!()
+        }
+
+        async fn sync_cache(&self) -> Result<(), DiskError> {
+            Ok(())
+        }
+
+        async fn quiesce(&self) -> Result<(), DiskError> {
+            assert!(!self.quiesced.swap(true, Ordering::Relaxed));
+            Ok(())
+        }
+
+        async fn thaw(&self) -> Result<(), DiskError> {
+            assert!(self.quiesced.swap(false, Ordering::Relaxed));
+            Ok(())
+        }
+    }
 
     #[async_test]
     async fn test_basic_read_write() {
@@ -228,4 +329,26 @@ async fn test_basic_read_write() {
         disk.read_vectored(&buffers.buffer(&mem), 10).await.unwrap();
         assert_eq!(mem.inner_buf_mut().unwrap(), &pattern);
     }
+
+    #[async_test]
+    async fn test_quiesce_passthrough() {
+        let quiesced = Arc::new(AtomicBool::new(false));
+        let inner = Disk::new(QuiesceDisk {
+            quiesced: quiesced.clone(),
+        })
+        .unwrap();
+        let key = [[0u8; 32], [1; 32]];
+        let disk = CryptDisk::new(
+            disk_crypt_resources::Cipher::XtsAes256,
+            key.as_flattened(),
+            inner,
+        )
+        .unwrap();
+        let disk = Disk::new(disk).unwrap();
+
+        disk.quiesce().await.unwrap();
+        assert!(quiesced.load(Ordering::Relaxed));
+        disk.thaw().await.unwrap();
+        assert!(!quiesced.load(Ordering::Relaxed));
+    }
 }
@@ -70,6 +70,11 @@ fn is_fua_respected(&self) -> bool {
         self.inner.is_fua_respected()
     }
 
+    /// Passthrough
+    fn has_volatile_write_cache(&self) -> bool {
+        self.inner.has_volatile_write_cache()
+    }
+
     /// Passthrough
     fn is_read_only(&self) -> bool {
         self.inner.is_read_only()
@@ -106,6 +111,16 @@ async fn sync_cache(&self) -> Result<(), DiskError> {
         self.inner.sync_cache().await
     }
 
+    /// Passthrough
+    fn quiesce(&self) -> impl Future<Output = Result<(), DiskError>> + Send {
+        self.inner.quiesce()
+    }
+
+    /// Passthrough
+    fn thaw(&self) -> impl Future<Output = Result<(), DiskError>> + Send {
+        self.inner.thaw()
+    }
+
     /// Passthrough
     async fn wait_resize(&self, sector_count: u64) -> u64 {
         self.inner.wait_resize(sector_count).await
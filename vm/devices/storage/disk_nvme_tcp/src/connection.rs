@@ -0,0 +1,1184 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! NVMe/TCP controller setup and the task that runs the I/O queue.
+
+use crate::protocol;
+use crate::protocol::CommonHeader;
+use crate::protocol::PduType;
+use anyhow::Context as _;
+use futures::AsyncRead;
+use futures::AsyncReadExt;
+use futures::AsyncWriteExt;
+use futures::FutureExt;
+use futures::StreamExt;
+use futures::future::Either;
+use inspect::Inspect;
+use nvme_spec::Cap;
+use nvme_spec::Cc;
+use nvme_spec::Cdw0;
+use nvme_spec::Command;
+use nvme_spec::Completion;
+use nvme_spec::Csts;
+use nvme_spec::IdentifyController;
+use nvme_spec::Register;
+use nvme_spec::Status;
+use nvme_spec::nvm::Cdw12ReadWrite;
+use nvme_spec::nvm::IdentifyNamespace;
+use nvme_spec::nvm::NvmOpcode;
+use pal_async::driver::Driver;
+use pal_async::socket::PolledSocket;
+use pal_async::socket::ReadHalf;
+use pal_async::socket::WriteHalf;
+use pal_async::timer::PolledTimer;
+use parking_lot::Mutex;
+use slab::Slab;
+use socket2::Socket;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::pin::pin;
+use std::time::Duration;
+use std::time::Instant;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+/// The largest PDU accepted from the controller.
+const MAX_PDU_SIZE: usize = 16 * 1024 * 1024;
+/// The largest transfer issued in a single command.
+const MAX_TRANSFER: u32 = 1024 * 1024;
+const ADMIN_QUEUE_SIZE: u16 = 32;
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// The parameters for connecting to a remote namespace.
+#[derive(Debug, Clone, Inspect)]
+pub struct ConnectParams {
+    #[inspect(display)]
+    pub addr: SocketAddr,
+    pub subsystem_nqn: String,
+    pub host_nqn: String,
+    pub host_id: guid::Guid,
+    pub nsid: u32,
+    /// The requested I/O queue depth, limited by the controller's maximum.
+    pub queue_depth: u16,
+}
+
+/// The namespace and queue properties discovered when connecting.
+#[derive(Debug, Copy, Clone, Inspect)]
+pub struct Geometry {
+    pub sector_count: u64,
+    pub sector_shift: u32,
+    /// The maximum transfer size of a single command, in bytes.
+    pub max_transfer: u32,
+    pub queue_depth: u16,
+}
+
+/// An I/O command submitted to the queue task.
+pub struct Io {
+    pub opcode: NvmOpcode,
+    pub slba: u64,
+    pub fua: bool,
+    /// The data to write, or a buffer of the length to read.
+    pub data: Vec<u8>,
+    /// Receives the data buffer on success, or the failing NVMe status.
+    pub respond: mesh::OneshotSender<Result<Vec<u8>, Status>>,
+}
+
+/// A connected NVMe/TCP queue.
+struct Queue {
+    socket: PolledSocket<Socket>,
+    /// The controller's required PDU data alignment, in dwords, zero based.
+    cpda: u8,
+    /// The maximum data length of an H2CData PDU.
+    maxh2cdata: u32,
+}
+
+/// Returns the offset of the data in a PDU with header length `hlen`, padded
+/// to the controller's required alignment.
+fn data_offset(hlen: usize, cpda: u8) -> usize {
+    hlen.next_multiple_of((cpda as usize + 1) * 4)
+}
+
+async fn read_pdu(reader: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<Vec<u8>> {
+    let mut pdu = vec![0; protocol::COMMON_HEADER_SIZE];
+    reader
+        .read_exact(&mut pdu)
+        .await
+        .context("failed to read pdu header")?;
+    let ch = CommonHeader::read_from_bytes(&pdu).unwrap();
+    let plen = ch.plen as usize;
+    if plen < (ch.hlen as usize).max(protocol::COMMON_HEADER_SIZE) || plen > MAX_PDU_SIZE {
+        anyhow::bail!("invalid pdu length {plen}");
+    }
+    pdu.resize(plen, 0);
+    reader
+        .read_exact(&mut pdu[protocol::COMMON_HEADER_SIZE..])
+        .await
+        .context("failed to read pdu")?;
+    Ok(pdu)
+}
+
+/// Parses the header of `pdu` as a `T`.
+fn parse<T: FromBytes + Immutable + KnownLayout>(pdu: &[u8]) -> anyhow::Result<T> {
+    let ch = CommonHeader::read_from_prefix(pdu).unwrap().0;
+    if (ch.hlen as usize) < size_of::<T>() {
+        anyhow::bail!("pdu header too short for {:?}", ch.pdu_type);
+    }
+    Ok(T::read_from_prefix(pdu).unwrap().0)
+}
+
+/// Parses a C2HData PDU, returning its header and data.
+fn parse_data(pdu: &[u8]) -> anyhow::Result<(protocol::DataHeader, &[u8])> {
+    let hdr = parse::<protocol::DataHeader>(pdu)?;
+    let data = pdu
+        .get(hdr.ch.pdo as usize..)
+        .and_then(|d| d.get(..hdr.datal as usize))
+        .context("c2h data out of range")?;
+    Ok((hdr, data))
+}
+
+/// Builds a command capsule PDU, with optional in-capsule data.
+fn capsule(sqe: Command, data: &[u8], cpda: u8) -> Vec<u8> {
+    let hlen = size_of::<protocol::CapsuleCmd>();
+    let pdo = if data.is_empty() {
+        0
+    } else {
+        data_offset(hlen, cpda)
+    };
+    let cmd = protocol::CapsuleCmd {
+        ch: CommonHeader {
+            pdu_type: PduType::CAPSULE_CMD,
+            flags: 0,
+            hlen: hlen as u8,
+            pdo: pdo as u8,
+            plen: (pdo.max(hlen) + data.len()) as u32,
+        },
+        sqe,
+    };
+    let mut pdu = cmd.as_bytes().to_vec();
+    pdu.resize(pdo.max(hlen), 0);
+    pdu.extend_from_slice(data);
+    pdu
+}
+
+/// Builds an H2CData PDU carrying `data` at offset `datao` of the command's
+/// transfer.
+fn h2c_data(cccid: u16, ttag: u16, datao: u32, data: &[u8], last: bool, cpda: u8) -> Vec<u8> {
+    let hlen = size_of::<protocol::DataHeader>();
+    let pdo = data_offset(hlen, cpda);
+    let hdr = protocol::DataHeader {
+        ch: CommonHeader {
+            pdu_type: PduType::H2C_DATA,
+            flags: if last { protocol::FLAG_LAST_PDU } else { 0 },
+            hlen: hlen as u8,
+            pdo: pdo as u8,
+            plen: (pdo + data.len()) as u32,
+        },
+        cccid,
+        ttag,
+        datao,
+        datal: data.len() as u32,
+        reserved: 0,
+    };
+    let mut pdu = hdr.as_bytes().to_vec();
+    pdu.resize(pdo, 0);
+    pdu.extend_from_slice(data);
+    pdu
+}
+
+fn check_status(cqe: &Completion) -> Result<(), Status> {
+    match Status(cqe.status.status()) {
+        Status::SUCCESS => Ok(()),
+        status => Err(status),
+    }
+}
+
+fn fabrics_command(fctype: protocol::FabricsCommandType) -> Command {
+    Command {
+        cdw0: Cdw0::new().with_opcode(protocol::FABRICS_OPCODE),
+        nsid: fctype.0.into(),
+        ..FromZeros::new_zeroed()
+    }
+}
+
+fn copy_nqn(dest: &mut [u8; 256], nqn: &str) -> anyhow::Result<()> {
+    // The NQN must be NUL terminated.
+    if nqn.len() >= dest.len() {
+        anyhow::bail!("nqn too long: {nqn}");
+    }
+    dest[..nqn.len()].copy_from_slice(nqn.as_bytes());
+    Ok(())
+}
+
+impl Queue {
+    /// Connects a new queue to the controller.
+    ///
+    /// Returns the queue and the controller ID.
+    async fn connect(
+        driver: &(impl ?Sized + Driver),
+        params: &ConnectParams,
+        qid: u16,
+        sqsize: u16,
+        cntlid: u16,
+    ) -> anyhow::Result<(Self, u16)> {
+        let socket = Socket::new(
+            socket2::Domain::for_address(params.addr),
+            socket2::Type::STREAM,
+            Some(socket2::Protocol::TCP),
+        )?;
+        socket.set_tcp_nodelay(true)?;
+        let mut socket = PolledSocket::new(driver, socket)?;
+        socket
+            .connect(&params.addr.into())
+            .await
+            .with_context(|| format!("failed to connect to {}", params.addr))?;
+
+        let hlen = size_of::<protocol::IcReq>();
+        let req = protocol::IcReq {
+            ch: CommonHeader {
+                pdu_type: PduType::IC_REQ,
+                flags: 0,
+                hlen: hlen as u8,
+                pdo: 0,
+                plen: hlen as u32,
+            },
+            pfv: 0,
+            hpda: 0,
+            dgst: 0,
+            maxr2t: 0,
+            reserved: [0; 112],
+        };
+        socket.write_all(req.as_bytes()).await?;
+        let pdu = read_pdu(&mut socket).await?;
+        let ch = parse::<CommonHeader>(&pdu)?;
+        if ch.pdu_type != PduType::IC_RESP {
+            anyhow::bail!(
+                "unexpected pdu type {:?} for connection response",
+                ch.pdu_type
+            );
+        }
+        let resp = parse::<protocol::IcResp>(&pdu)?;
+        if resp.pfv != 0 {
+            anyhow::bail!("unsupported pdu format version {}", resp.pfv);
+        }
+        if resp.dgst != 0 {
+            anyhow::bail!("controller enabled digests, which are not supported");
+        }
+        if resp.maxh2cdata < 4096 {
+            anyhow::bail!("invalid maxh2cdata {}", resp.maxh2cdata);
+        }
+        let mut queue = Self {
+            socket,
+            cpda: resp.cpda,
+            maxh2cdata: resp.maxh2cdata,
+        };
+
+        let mut data = protocol::ConnectData::new_zeroed();
+        data.hostid = params.host_id.into();
+        data.cntlid = cntlid;
+        copy_nqn(&mut data.subnqn, &params.subsystem_nqn)?;
+        copy_nqn(&mut data.hostnqn, &params.host_nqn)?;
+        let mut sqe = fabrics_command(protocol::FabricsCommandType::CONNECT);
+        sqe.cdw10 = (qid as u32) << 16;
+        sqe.cdw11 = sqsize.into();
+        let (cqe, _) = queue.execute(sqe, data.as_bytes(), 0).await?;
+        check_status(&cqe).map_err(|status| anyhow::anyhow!("connect failed: {status:?}"))?;
+        Ok((queue, cqe.dw0 as u16))
+    }
+
+    /// Executes a single command and waits for it to complete.
+    ///
+    /// `data_out` is sent in the command capsule. This is only used before the
+    /// queue starts processing I/O, so it does not handle R2Ts.
+    async fn execute(
+        &mut self,
+        mut sqe: Command,
+        data_out: &[u8],
+        data_in_len: usize,
+    ) -> anyhow::Result<(Completion, Vec<u8>)> {
+        sqe.cdw0 = sqe.cdw0.with_cid(0).with_psdt(1);
+        sqe.dptr = if data_out.is_empty() {
+            protocol::sgl(protocol::SGL_TRANSPORT_DATA_BLOCK, data_in_len as u32)
+        } else {
+            protocol::sgl(protocol::SGL_DATA_BLOCK_OFFSET, data_out.len() as u32)
+        };
+        self.socket
+            .write_all(&capsule(sqe, data_out, self.cpda))
+            .await?;
+        let mut data = vec![0; data_in_len];
+        loop {
+            let pdu = read_pdu(&mut self.socket).await?;
+            let ch = parse::<CommonHeader>(&pdu)?;
+            match ch.pdu_type {
+                PduType::C2H_DATA => {
+                    let (hdr, pdu_data) = parse_data(&pdu)?;
+                    data.get_mut(hdr.datao as usize..)
+                        .and_then(|d| d.get_mut(..pdu_data.len()))
+                        .context("c2h data out of range")?
+                        .copy_from_slice(pdu_data);
+                    if ch.flags & protocol::FLAG_SUCCESS != 0 {
+                        return Ok((Completion::new_zeroed(), data));
+                    }
+                }
+                PduType::CAPSULE_RESP => {
+                    let resp = parse::<protocol::CapsuleResp>(&pdu)?;
+                    return Ok((resp.cqe, data));
+                }
+                PduType::C2H_TERM_REQ => anyhow::bail!("controller terminated the connection"),
+                ty => anyhow::bail!("unexpected pdu type {ty:?}"),
+            }
+        }
+    }
+
+    async fn get_property(&mut self, register: Register) -> anyhow::Result<u64> {
+        let mut sqe = fabrics_command(protocol::FabricsCommandType::PROPERTY_GET);
+        sqe.cdw10 = protocol::PROPERTY_SIZE_8;
+        sqe.cdw11 = register.0.into();
+        let (cqe, _) = self.execute(sqe, &[], 0).await?;
+        check_status(&cqe)
+            .map_err(|status| anyhow::anyhow!("get property {register:?} failed: {status:?}"))?;
+        Ok(cqe.dw0 as u64 | (cqe.dw1 as u64) << 32)
+    }
+
+    async fn set_property(&mut self, register: Register, value: u32) -> anyhow::Result<()> {
+        let mut sqe = fabrics_command(protocol::FabricsCommandType::PROPERTY_SET);
+        sqe.cdw11 = register.0.into();
+        sqe.cdw12 = value;
+        let (cqe, _) = self.execute(sqe, &[], 0).await?;
+        check_status(&cqe)
+            .map_err(|status| anyhow::anyhow!("set property {register:?} failed: {status:?}"))
+    }
+
+    async fn identify<T: FromBytes>(&mut self, cns: u8, nsid: u32) -> anyhow::Result<T> {
+        let sqe = Command {
+            cdw0: Cdw0::new().with_opcode(nvme_spec::AdminOpcode::IDENTIFY.0),
+            nsid,
+            cdw10: nvme_spec::Cdw10Identify::new().with_cns(cns).into(),
+            ..FromZeros::new_zeroed()
+        };
+        let (cqe, data) = self.execute(sqe, &[], 4096).await?;
+        check_status(&cqe)
+            .map_err(|status| anyhow::anyhow!("identify cns {cns} failed: {status:?}"))?;
+        Ok(T::read_from_prefix(&data).unwrap().0)
+    }
+}
+
+/// An associated controller, with its admin queue and I/O queue.
+pub struct Controller {
+    /// The admin queue is idle after setup but must stay connected to keep
+    /// the association alive.
+    _admin: Queue,
+    io: Queue,
+    pub geometry: Geometry,
+}
+
+impl Controller {
+    /// Connects to the controller, enables it, and connects an I/O queue.
+    pub async fn establish(
+        driver: &(impl ?Sized + Driver),
+        params: &ConnectParams,
+    ) -> anyhow::Result<Self> {
+        let (mut admin, cntlid) = Queue::connect(
+            driver,
+            params,
+            0,
+            ADMIN_QUEUE_SIZE - 1,
+            protocol::CNTLID_DYNAMIC,
+        )
+        .await
+        .context("failed to connect admin queue")?;
+
+        let cap = Cap::from(admin.get_property(Register::CAP).await?);
+        admin
+            .set_property(
+                Register::CC,
+                Cc::new().with_en(true).with_iosqes(6).with_iocqes(4).into(),
+            )
+            .await?;
+
+        // CAP.TO is in 500ms units.
+        let timeout = Duration::from_millis(500) * cap.to().max(1).into();
+        let start = Instant::now();
+        let mut timer = PolledTimer::new(driver);
+        loop {
+            let csts = Csts::from(admin.get_property(Register::CSTS).await? as u32);
+            if csts.cfs() {
+                anyhow::bail!("controller fatal status");
+            }
+            if csts.rdy() {
+                break;
+            }
+            if start.elapsed() > timeout {
+                anyhow::bail!("timed out waiting for controller ready");
+            }
+            timer.sleep(Duration::from_millis(10)).await;
+        }
+
+        let ctrl: IdentifyController = admin.identify(1, 0).await?;
+        let ns: IdentifyNamespace = admin.identify(0, params.nsid).await?;
+        if ns.nsze == 0 {
+            anyhow::bail!("namespace {} not found", params.nsid);
+        }
+        if ns.flbas.inband_metadata() {
+            anyhow::bail!("namespaces with extended lbas are not supported");
+        }
+        let lbaf = ns.lbaf[ns.flbas.low_index() as usize];
+        let sector_shift = lbaf.lbads() as u32;
+        if !(9..=16).contains(&sector_shift) {
+            anyhow::bail!("unsupported sector size 2^{sector_shift}");
+        }
+        let max_transfer = if ctrl.mdts == 0 {
+            MAX_TRANSFER
+        } else {
+            (4096u32 << cap.mpsmin())
+                .checked_shl(ctrl.mdts.into())
+                .unwrap_or(MAX_TRANSFER)
+                .min(MAX_TRANSFER)
+        };
+        let queue_depth = params.queue_depth.clamp(1, cap.mqes_z().saturating_add(1));
+
+        let (io, _) = Queue::connect(driver, params, 1, queue_depth - 1, cntlid)
+            .await
+            .context("failed to connect io queue")?;
+
+        Ok(Self {
+            _admin: admin,
+            io,
+            geometry: Geometry {
+                sector_count: ns.nsze,
+                sector_shift,
+                max_transfer,
+                queue_depth,
+            },
+        })
+    }
+}
+
+fn io_command(io: &Io, cid: u16, nsid: u32, sector_shift: u32) -> Command {
+    let mut sqe = Command {
+        cdw0: Cdw0::new()
+            .with_opcode(io.opcode.0)
+            .with_cid(cid)
+            .with_psdt(1),
+        nsid,
+        dptr: protocol::sgl(protocol::SGL_TRANSPORT_DATA_BLOCK, io.data.len() as u32),
+        ..FromZeros::new_zeroed()
+    };
+    if io.opcode != NvmOpcode::FLUSH {
+        let nlb = io.data.len() >> sector_shift;
+        sqe.cdw10 = io.slba as u32;
+        sqe.cdw11 = (io.slba >> 32) as u32;
+        sqe.cdw12 = Cdw12ReadWrite::new()
+            .with_nlb_z((nlb - 1) as u16)
+            .with_fua(io.fua)
+            .into();
+    }
+    sqe
+}
+
+/// Runs the I/O queue, reconnecting to the controller if the connection
+/// fails.
+///
+/// Commands that were outstanding when the connection failed are reissued on
+/// the new connection, so I/O stalls rather than fails while the target is
+/// unreachable.
+pub async fn run(
+    driver: impl Driver,
+    params: ConnectParams,
+    mut controller: Controller,
+    mut requests: mesh::Receiver<Io>,
+) {
+    let pending = Mutex::new(Slab::new());
+    let mut reissue = VecDeque::new();
+    loop {
+        match run_session(controller, &params, &pending, &mut reissue, &mut requests).await {
+            Ok(()) => break,
+            Err(err) => {
+                tracing::warn!(
+                    addr = %params.addr,
+                    error = err.as_ref() as &dyn std::error::Error,
+                    "nvme/tcp connection failed, reconnecting"
+                );
+            }
+        }
+        // Reissue the outstanding commands ahead of any that were never
+        // issued. The new connection may have a smaller queue depth, so they
+        // are submitted like new requests rather than all at once.
+        let mut outstanding = pending.lock().drain().collect::<VecDeque<_>>();
+        outstanding.append(&mut reissue);
+        reissue = outstanding;
+        let mut delay = INITIAL_RECONNECT_DELAY;
+        let mut timer = PolledTimer::new(&driver);
+        controller = loop {
+            timer.sleep(delay).await;
+            match Controller::establish(&driver, &params).await {
+                Ok(controller) => break controller,
+                Err(err) => {
+                    tracelimit::warn_ratelimited!(
+                        addr = %params.addr,
+                        error = err.as_ref() as &dyn std::error::Error,
+                        "nvme/tcp reconnect failed"
+                    );
+                    delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            }
+        };
+        tracing::info!(addr = %params.addr, "nvme/tcp connection restored");
+    }
+}
+
+/// Processes I/O on a connected controller until the connection fails or the
+/// disk is dropped.
+async fn run_session(
+    controller: Controller,
+    params: &ConnectParams,
+    pending: &Mutex<Slab<Io>>,
+    reissue: &mut VecDeque<Io>,
+    requests: &mut mesh::Receiver<Io>,
+) -> anyhow::Result<()> {
+    let Controller {
+        _admin,
+        io: Queue {
+            socket,
+            cpda,
+            maxh2cdata,
+        },
+        geometry,
+    } = controller;
+    let (mut reader, mut writer) = socket.split();
+    let (event_send, mut event_recv) = mesh::channel();
+    let session = Session {
+        nsid: params.nsid,
+        geometry,
+        cpda,
+        maxh2cdata,
+        pending,
+    };
+    let recv = pin!(session.recv_loop(&mut reader, &event_send));
+    let send = pin!(session.send_loop(&mut writer, reissue, requests, &mut event_recv));
+    match futures::future::select(recv, send).await {
+        Either::Left((err, _)) => Err(err),
+        Either::Right((r, _)) => r,
+    }
+}
+
+/// An event from the receive loop for the send loop.
+enum SendEvent {
+    /// The controller is ready for write data.
+    R2t(protocol::R2t),
+    /// A command completed, so there may be room for another.
+    Completed,
+}
+
+struct Session<'a> {
+    nsid: u32,
+    geometry: Geometry,
+    cpda: u8,
+    maxh2cdata: u32,
+    pending: &'a Mutex<Slab<Io>>,
+}
+
+impl Session<'_> {
+    /// Receives PDUs from the controller until the connection fails.
+    async fn recv_loop(
+        &self,
+        reader: &mut ReadHalf<Socket>,
+        events: &mesh::Sender<SendEvent>,
+    ) -> anyhow::Error {
+        loop {
+            if let Err(err) = self.recv_pdu(reader, events).await {
+                break err;
+            }
+        }
+    }
+
+    async fn recv_pdu(
+        &self,
+        reader: &mut ReadHalf<Socket>,
+        events: &mesh::Sender<SendEvent>,
+    ) -> anyhow::Result<()> {
+        let pdu = read_pdu(reader).await?;
+        let ch = parse::<CommonHeader>(&pdu)?;
+        match ch.pdu_type {
+            PduType::C2H_DATA => {
+                let (hdr, data) = parse_data(&pdu)?;
+                let mut pending = self.pending.lock();
+                let io = pending
+                    .get_mut(hdr.cccid.into())
+                    .context("c2h data for unknown command")?;
+                io.data
+                    .get_mut(hdr.datao as usize..)
+                    .and_then(|d| d.get_mut(..data.len()))
+                    .context("c2h data out of range")?
+                    .copy_from_slice(data);
+                if ch.flags & protocol::FLAG_SUCCESS != 0 {
+                    let io = pending.remove(hdr.cccid.into());
+                    io.respond.send(Ok(io.data));
+                    events.send(SendEvent::Completed);
+                }
+            }
+            PduType::CAPSULE_RESP => {
+                let resp = parse::<protocol::CapsuleResp>(&pdu)?;
+                let io = self
+                    .pending
+                    .lock()
+                    .try_remove(resp.cqe.cid.into())
+                    .context("response for unknown command")?;
+                io.respond.send(check_status(&resp.cqe).map(|()| io.data));
+                events.send(SendEvent::Completed);
+            }
+            PduType::R2T => events.send(SendEvent::R2t(parse::<protocol::R2t>(&pdu)?)),
+            PduType::C2H_TERM_REQ => anyhow::bail!("controller terminated the connection"),
+            ty => anyhow::bail!("unexpected pdu type {ty:?}"),
+        }
+        Ok(())
+    }
+
+    /// Submits commands, starting with those in `reissue`, and sends write
+    /// data until the connection fails or the disk is dropped.
+    async fn send_loop(
+        &self,
+        writer: &mut WriteHalf<Socket>,
+        reissue: &mut VecDeque<Io>,
+        requests: &mut mesh::Receiver<Io>,
+        events: &mut mesh::Receiver<SendEvent>,
+    ) -> anyhow::Result<()> {
+        loop {
+            let can_submit = self.pending.lock().len() < self.geometry.queue_depth.into();
+            let next_io = async {
+                if !can_submit {
+                    // Wait for a completion to make room.
+                    std::future::pending().await
+                } else if let Some(io) = reissue.pop_front() {
+                    Some(io)
+                } else {
+                    requests.next().await
+                }
+            };
+            futures::select_biased! {
+                event = events.select_next_some() => match event {
+                    SendEvent::R2t(r2t) => self.send_data(writer, &r2t).await?,
+                    SendEvent::Completed => {}
+                },
+                io = next_io.fuse() => {
+                    let Some(io) = io else {
+                        // The disk has been dropped.
+                        break Ok(());
+                    };
+                    let pdu = {
+                        let mut pending = self.pending.lock();
+                        let entry = pending.vacant_entry();
+                        let pdu = self.io_capsule(&io, entry.key());
+                        entry.insert(io);
+                        pdu
+                    };
+                    writer.write_all(&pdu).await?;
+                }
+            }
+        }
+    }
+
+    fn io_capsule(&self, io: &Io, cid: usize) -> Vec<u8> {
+        let sqe = io_command(io, cid as u16, self.nsid, self.geometry.sector_shift);
+        capsule(sqe, &[], self.cpda)
+    }
+
+    /// Sends the write data requested by an R2T.
+    async fn send_data(
+        &self,
+        writer: &mut WriteHalf<Socket>,
+        r2t: &protocol::R2t,
+    ) -> anyhow::Result<()> {
+        let pdus = {
+            let pending = self.pending.lock();
+            let io = pending
+                .get(r2t.cccid.into())
+                .context("r2t for unknown command")?;
+            let data = io
+                .data
+                .get(r2t.r2to as usize..)
+                .and_then(|d| d.get(..r2t.r2tl as usize))
+                .context("r2t out of range")?;
+            let chunk_size = self.maxh2cdata as usize;
+            let chunks = data.len().div_ceil(chunk_size);
+            data.chunks(chunk_size)
+                .enumerate()
+                .map(|(i, chunk)| {
+                    h2c_data(
+                        r2t.cccid,
+                        r2t.ttag,
+                        r2t.r2to + (i * chunk_size) as u32,
+                        chunk,
+                        i + 1 == chunks,
+                        self.cpda,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        for pdu in pdus {
+            writer.write_all(&pdu).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::FabricsCommandType;
+    use futures::AsyncWrite;
+    use nvme_spec::AdminOpcode;
+    use nvme_spec::CompletionStatus;
+    use nvme_spec::nvm::Lbaf;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use pal_async::task::Spawn;
+    use pal_async::task::Task;
+    use std::sync::Arc;
+
+    const SECTOR_SIZE: usize = 512;
+
+    #[test]
+    fn capsule_layout() {
+        let sqe = Command::new_zeroed();
+        let pdu = capsule(sqe.clone(), &[], 0);
+        let ch = parse::<CommonHeader>(&pdu).unwrap();
+        assert_eq!(pdu.len(), size_of::<protocol::CapsuleCmd>());
+        assert_eq!(
+            (ch.hlen as usize, ch.pdo, ch.plen as usize),
+            (pdu.len(), 0, pdu.len())
+        );
+
+        // In-capsule data is padded to the controller's 16-byte alignment.
+        let pdu = capsule(sqe, &[0xcc; 100], 3);
+        let ch = parse::<CommonHeader>(&pdu).unwrap();
+        let pdo = size_of::<protocol::CapsuleCmd>().next_multiple_of(16);
+        assert_eq!((ch.pdo as usize, ch.plen as usize), (pdo, pdo + 100));
+        assert_eq!(pdu.len(), pdo + 100);
+        assert!(pdu[pdo..].iter().all(|&b| b == 0xcc));
+    }
+
+    #[test]
+    fn h2c_data_layout() {
+        let pdu = h2c_data(5, 7, 0x1000, &[0xcc; 100], true, 7);
+        let (hdr, data) = parse_data(&pdu).unwrap();
+        assert_eq!(hdr.ch.pdu_type, PduType::H2C_DATA);
+        assert_eq!(hdr.ch.flags, protocol::FLAG_LAST_PDU);
+        assert_eq!((hdr.ch.pdo, hdr.ch.plen as usize), (32, 132));
+        assert_eq!(
+            (hdr.cccid, hdr.ttag, hdr.datao, hdr.datal),
+            (5, 7, 0x1000, 100)
+        );
+        assert_eq!(data, [0xcc; 100]);
+
+        let pdu = h2c_data(5, 7, 0, &[0; 100], false, 0);
+        let (hdr, _) = parse_data(&pdu).unwrap();
+        assert_eq!(
+            (hdr.ch.flags, hdr.ch.pdo),
+            (0, size_of::<protocol::DataHeader>() as u8)
+        );
+
+        // Data that extends past the end of the PDU is rejected.
+        let mut pdu = h2c_data(5, 7, 0, &[0; 100], false, 0);
+        pdu.truncate(pdu.len() - 1);
+        parse_data(&pdu).unwrap_err();
+    }
+
+    fn header(hlen: u8, plen: u32) -> Vec<u8> {
+        CommonHeader {
+            pdu_type: PduType::CAPSULE_RESP,
+            flags: 0,
+            hlen,
+            pdo: 0,
+            plen,
+        }
+        .as_bytes()
+        .to_vec()
+    }
+
+    #[async_test]
+    async fn read_pdu_bounds() {
+        let mut pdu = header(24, 32);
+        pdu.extend_from_slice(&[0xcc; 24]);
+        assert_eq!(read_pdu(&mut pdu.as_slice()).await.unwrap(), pdu);
+
+        // Shorter than the common header or the PDU header.
+        read_pdu(&mut header(8, 4).as_slice()).await.unwrap_err();
+        read_pdu(&mut header(24, 16).as_slice()).await.unwrap_err();
+        // Longer than the maximum PDU size.
+        read_pdu(&mut header(24, MAX_PDU_SIZE as u32 + 1).as_slice())
+            .await
+            .unwrap_err();
+        // Truncated.
+        read_pdu(&mut header(24, 32).as_slice()).await.unwrap_err();
+    }
+
+    /// How the mock target handles an I/O queue connection.
+    #[derive(Copy, Clone)]
+    enum IoBehavior {
+        /// Complete commands once this many are outstanding.
+        Batch(usize),
+        /// Drop the connection without completing anything once this many
+        /// commands have been received.
+        DropAfter(usize),
+    }
+
+    struct MockConfig {
+        /// CAP.MQES for each association, in order. The last value is reused.
+        mqes_z: Vec<u16>,
+        /// The behavior of each I/O queue connection, in order. The last value
+        /// is reused.
+        io: Vec<IoBehavior>,
+        maxh2cdata: u32,
+        sector_count: usize,
+    }
+
+    #[derive(Default)]
+    struct MockStats {
+        associations: usize,
+        io_connections: usize,
+        /// Whether the host issued more commands than the queue depth.
+        overflowed: bool,
+        /// The data length of each H2CData PDU received.
+        h2c_lengths: Vec<u32>,
+    }
+
+    /// An in-process NVMe/TCP target with one namespace backed by memory.
+    struct MockTarget {
+        config: MockConfig,
+        disk: Mutex<Vec<u8>>,
+        stats: Mutex<MockStats>,
+    }
+
+    fn start_target(
+        driver: &DefaultDriver,
+        config: MockConfig,
+    ) -> (SocketAddr, Arc<MockTarget>, Task<()>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut listener = PolledSocket::new(driver, listener).unwrap();
+        let target = Arc::new(MockTarget {
+            disk: Mutex::new(vec![0; config.sector_count * SECTOR_SIZE]),
+            config,
+            stats: Default::default(),
+        });
+        let task = driver.spawn("nvme-tcp-target", {
+            let driver = driver.clone();
+            let target = target.clone();
+            async move {
+                let mut connections = Vec::new();
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let socket = PolledSocket::new(&driver, Socket::from(stream)).unwrap();
+                    let target = target.clone();
+                    let conn_driver = driver.clone();
+                    connections.push(driver.spawn("nvme-tcp-target-conn", async move {
+                        // Errors just close the connection.
+                        let _ = target.serve(&conn_driver, socket).await;
+                    }));
+                }
+            }
+        });
+        (addr, target, task)
+    }
+
+    fn params(addr: SocketAddr, queue_depth: u16) -> ConnectParams {
+        ConnectParams {
+            addr,
+            subsystem_nqn: "nqn.2014-08.org.nvmexpress:test".into(),
+            host_nqn: "nqn.2014-08.org.nvmexpress:host".into(),
+            host_id: guid::Guid::new_random(),
+            nsid: 1,
+            queue_depth,
+        }
+    }
+
+    async fn write_capsule_resp(
+        socket: &mut (impl AsyncWrite + Unpin),
+        cid: u16,
+        dw0: u32,
+        dw1: u32,
+    ) -> anyhow::Result<()> {
+        let hlen = size_of::<protocol::CapsuleResp>();
+        let resp = protocol::CapsuleResp {
+            ch: CommonHeader {
+                pdu_type: PduType::CAPSULE_RESP,
+                flags: 0,
+                hlen: hlen as u8,
+                pdo: 0,
+                plen: hlen as u32,
+            },
+            cqe: Completion {
+                dw0,
+                dw1,
+                sqhd: 0,
+                sqid: 0,
+                cid,
+                status: CompletionStatus::new(),
+            },
+        };
+        socket.write_all(resp.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Sends `data` in a single successful C2HData PDU.
+    async fn write_c2h_data(
+        socket: &mut (impl AsyncWrite + Unpin),
+        cid: u16,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        let mut pdu = h2c_data(cid, 0, 0, data, true, 0);
+        pdu[0] = PduType::C2H_DATA.0;
+        pdu[1] |= protocol::FLAG_SUCCESS;
+        socket.write_all(&pdu).await?;
+        Ok(())
+    }
+
+    impl MockTarget {
+        async fn serve(
+            &self,
+            driver: &DefaultDriver,
+            mut socket: PolledSocket<Socket>,
+        ) -> anyhow::Result<()> {
+            let mut timer = PolledTimer::new(driver);
+            let pdu = read_pdu(&mut socket).await?;
+            assert_eq!(parse::<CommonHeader>(&pdu)?.pdu_type, PduType::IC_REQ);
+            let hlen = size_of::<protocol::IcResp>();
+            let resp = protocol::IcResp {
+                ch: CommonHeader {
+                    pdu_type: PduType::IC_RESP,
+                    flags: 0,
+                    hlen: hlen as u8,
+                    pdo: 0,
+                    plen: hlen as u32,
+                },
+                pfv: 0,
+                cpda: 0,
+                dgst: 0,
+                maxh2cdata: self.config.maxh2cdata,
+                reserved: [0; 112],
+            };
+            socket.write_all(resp.as_bytes()).await?;
+
+            let mut mqes_z = 0;
+            let mut io_queue = None;
+            let mut outstanding = Vec::new();
+            // Capsules received while waiting for write data.
+            let mut backlog = VecDeque::new();
+            loop {
+                let pdu = match backlog.pop_front() {
+                    Some(pdu) => pdu,
+                    None => read_pdu(&mut socket).await?,
+                };
+                let sqe = parse::<protocol::CapsuleCmd>(&pdu)?.sqe;
+                let cid = sqe.cdw0.cid();
+                let opcode = sqe.cdw0.opcode();
+                if opcode == protocol::FABRICS_OPCODE {
+                    let (dw0, dw1) = match FabricsCommandType(sqe.nsid as u8) {
+                        FabricsCommandType::CONNECT => {
+                            let mut stats = self.stats.lock();
+                            if sqe.cdw10 >> 16 == 0 {
+                                let n = stats.associations;
+                                stats.associations += 1;
+                                mqes_z = self.config.mqes_z[n.min(self.config.mqes_z.len() - 1)];
+                            } else {
+                                let n = stats.io_connections;
+                                stats.io_connections += 1;
+                                let behavior = self.config.io[n.min(self.config.io.len() - 1)];
+                                io_queue = Some((behavior, (sqe.cdw11 & 0xffff) as usize + 1));
+                            }
+                            (1, 0)
+                        }
+                        FabricsCommandType::PROPERTY_GET => {
+                            let value: u64 = if sqe.cdw11 == u32::from(Register::CAP.0) {
+                                Cap::new().with_mqes_z(mqes_z).with_to(1).into()
+                            } else if sqe.cdw11 == u32::from(Register::CSTS.0) {
+                                u32::from(Csts::new().with_rdy(true)).into()
+                            } else {
+                                0u64
+                            };
+                            (value as u32, (value >> 32) as u32)
+                        }
+                        _ => (0, 0),
+                    };
+                    write_capsule_resp(&mut socket, cid, dw0, dw1).await?;
+                } else if io_queue.is_none() {
+                    assert_eq!(opcode, AdminOpcode::IDENTIFY.0);
+                    let data = if sqe.cdw10 & 0xff == 1 {
+                        IdentifyController::new_zeroed().as_bytes().to_vec()
+                    } else {
+                        let mut ns = IdentifyNamespace::new_zeroed();
+                        ns.nsze = self.config.sector_count as u64;
+                        ns.lbaf[0] = Lbaf::new().with_lbads(SECTOR_SIZE.trailing_zeros() as u8);
+                        ns.as_bytes().to_vec()
+                    };
+                    write_c2h_data(&mut socket, cid, &data).await?;
+                } else {
+                    let (behavior, depth) = io_queue.unwrap();
+                    outstanding.push(sqe);
+                    if outstanding.len() >= depth && backlog.is_empty() {
+                        // Give a host that ignores the queue depth a chance
+                        // to send another command before completing any.
+                        let read = pin!(read_pdu(&mut socket));
+                        let sleep = pin!(timer.sleep(Duration::from_millis(50)));
+                        match futures::future::select(read, sleep).await {
+                            Either::Left((pdu, _)) => backlog.push_back(pdu?),
+                            Either::Right(_) => {}
+                        }
+                    }
+                    if outstanding.len() + backlog.len() > depth {
+                        self.stats.lock().overflowed = true;
+                    }
+                    match behavior {
+                        IoBehavior::DropAfter(n) => {
+                            if outstanding.len() == n {
+                                return Ok(());
+                            }
+                        }
+                        IoBehavior::Batch(n) => {
+                            if outstanding.len() == n {
+                                for sqe in outstanding.drain(..) {
+                                    self.complete_io(&mut socket, &sqe, &mut backlog).await?;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        async fn complete_io(
+            &self,
+            socket: &mut PolledSocket<Socket>,
+            sqe: &Command,
+            backlog: &mut VecDeque<Vec<u8>>,
+        ) -> anyhow::Result<()> {
+            let cid = sqe.cdw0.cid();
+            let offset = (sqe.cdw10 as usize | (sqe.cdw11 as usize) << 32) * SECTOR_SIZE;
+            let len = ((sqe.cdw12 & 0xffff) as usize + 1) * SECTOR_SIZE;
+            match NvmOpcode(sqe.cdw0.opcode()) {
+                NvmOpcode::READ => {
+                    let data = self.disk.lock()[offset..][..len].to_vec();
+                    write_c2h_data(socket, cid, &data).await?;
+                }
+                NvmOpcode::WRITE => {
+                    let hlen = size_of::<protocol::R2t>();
+                    let r2t = protocol::R2t {
+                        ch: CommonHeader {
+                            pdu_type: PduType::R2T,
+                            flags: 0,
+                            hlen: hlen as u8,
+                            pdo: 0,
+                            plen: hlen as u32,
+                        },
+                        cccid: cid,
+                        ttag: cid,
+                        r2to: 0,
+                        r2tl: len as u32,
+                        reserved: 0,
+                    };
+                    socket.write_all(r2t.as_bytes()).await?;
+                    let mut data = Vec::new();
+                    loop {
+                        let pdu = read_pdu(socket).await?;
+                        if parse::<CommonHeader>(&pdu)?.pdu_type == PduType::CAPSULE_CMD {
+                            backlog.push_back(pdu);
+                            continue;
+                        }
+                        let (hdr, pdu_data) = parse_data(&pdu)?;
+                        assert_eq!((hdr.cccid, hdr.ttag), (cid, cid));
+                        assert_eq!(hdr.datao as usize, data.len());
+                        self.stats.lock().h2c_lengths.push(hdr.datal);
+                        data.extend_from_slice(pdu_data);
+                        if hdr.ch.flags & protocol::FLAG_LAST_PDU != 0 {
+                            break;
+                        }
+                    }
+                    assert_eq!(data.len(), len);
+                    self.disk.lock()[offset..][..len].copy_from_slice(&data);
+                    write_capsule_resp(socket, cid, 0, 0).await?;
+                }
+                _ => write_capsule_resp(socket, cid, 0, 0).await?,
+            }
+            Ok(())
+        }
+    }
+
+    async fn io(send: &mesh::Sender<Io>, opcode: NvmOpcode, slba: u64, data: Vec<u8>) -> Vec<u8> {
+        let (respond, recv) = mesh::oneshot();
+        send.send(Io {
+            opcode,
+            slba,
+            fua: false,
+            data,
+            respond,
+        });
+        recv.await.unwrap().unwrap()
+    }
+
+    #[async_test]
+    async fn r2t_splitting(driver: DefaultDriver) {
+        let (addr, target, _task) = start_target(
+            &driver,
+            MockConfig {
+                mqes_z: vec![3],
+                io: vec![IoBehavior::Batch(1)],
+                maxh2cdata: 4096,
+                sector_count: 1024,
+            },
+        );
+        let params = params(addr, 4);
+        let controller = Controller::establish(&driver, &params).await.unwrap();
+        assert_eq!(controller.geometry.queue_depth, 4);
+        assert_eq!(controller.geometry.sector_count, 1024);
+        let (send, recv) = mesh::channel();
+        let _run = driver.spawn("nvme-tcp", run(driver.clone(), params, controller, recv));
+
+        let data = (0..0x4000).map(|i| i as u8).collect::<Vec<_>>();
+        io(&send, NvmOpcode::WRITE, 8, data.clone()).await;
+        // The write data is split at maxh2cdata.
+        assert_eq!(target.stats.lock().h2c_lengths, [4096; 4]);
+        assert_eq!(io(&send, NvmOpcode::READ, 8, vec![0; 0x4000]).await, data);
+        io(&send, NvmOpcode::FLUSH, 0, Vec::new()).await;
+    }
+
+    #[async_test]
+    async fn reissue_after_reconnect(driver: DefaultDriver) {
+        // The first connection drops with four reads outstanding. The target
+        // then comes back with a queue depth of two.
+        let (addr, target, _task) = start_target(
+            &driver,
+            MockConfig {
+                mqes_z: vec![3, 1],
+                io: vec![IoBehavior::DropAfter(4), IoBehavior::Batch(2)],
+                maxh2cdata: 4096,
+                sector_count: 1024,
+            },
+        );
+        target
+            .disk
+            .lock()
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, b)| *b = (i / SECTOR_SIZE) as u8);
+
+        let params = params(addr, 4);
+        let controller = Controller::establish(&driver, &params).await.unwrap();
+        let (send, recv) = mesh::channel();
+        let _run = driver.spawn("nvme-tcp", run(driver.clone(), params, controller, recv));
+
+        let reads = futures::future::join_all(
+            (0..4).map(|i| io(&send, NvmOpcode::READ, i, vec![0; SECTOR_SIZE])),
+        )
+        .await;
+        for (i, data) in reads.iter().enumerate() {
+            assert_eq!(*data, [i as u8; SECTOR_SIZE]);
+        }
+        let stats = target.stats.lock();
+        assert_eq!(stats.io_connections, 2);
+        assert!(!stats.overflowed);
+    }
+}
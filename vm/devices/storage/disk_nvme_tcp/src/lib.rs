@@ -0,0 +1,203 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A disk backend that connects to a remote NVMe namespace over the NVMe/TCP
+//! transport.
+//!
+//! This is a host-side initiator: it associates with a remote controller,
+//! connects a single I/O queue, and issues reads, writes, and flushes to one
+//! namespace. If the connection drops, the disk reconnects in the background
+//! and reissues outstanding commands, so guest I/O stalls rather than fails
+//! while the target is unreachable.
+//!
+//! Header and data digests, TLS, and in-band authentication are not supported.
+
+#![forbid(unsafe_code)]
+
+mod connection;
+mod protocol;
+pub mod resolver;
+
+pub use connection::ConnectParams;
+
+use connection::Controller;
+use connection::Geometry;
+use connection::Io;
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::MediumErrorDetails;
+use disk_backend::UnmapBehavior;
+use guestmem::MemoryRead;
+use guestmem::MemoryWrite;
+use inspect::Inspect;
+use nvme_spec::Status;
+use nvme_spec::nvm::NvmOpcode;
+use pal_async::driver::SpawnDriver;
+use pal_async::task::Task;
+use scsi_buffers::RequestBuffers;
+use std::io;
+
+/// A disk backed by a namespace on a remote NVMe/TCP controller.
+#[derive(Inspect)]
+pub struct NvmeTcpDisk {
+    params: ConnectParams,
+    geometry: Geometry,
+    #[inspect(skip)]
+    send: mesh::Sender<Io>,
+    #[inspect(skip)]
+    _task: Task<()>,
+}
+
+impl NvmeTcpDisk {
+    /// Connects to the remote controller and namespace described by `params`.
+    pub async fn connect(
+        driver: impl SpawnDriver + Clone,
+        params: ConnectParams,
+    ) -> anyhow::Result<Self> {
+        let controller = Controller::establish(&driver, &params).await?;
+        let geometry = controller.geometry;
+        let (send, recv) = mesh::channel();
+        let task = driver.spawn(
+            format!("nvme-tcp-{}", params.addr),
+            connection::run(driver.clone(), params.clone(), controller, recv),
+        );
+        Ok(Self {
+            params,
+            geometry,
+            send,
+            _task: task,
+        })
+    }
+
+    async fn issue(
+        &self,
+        opcode: NvmOpcode,
+        slba: u64,
+        fua: bool,
+        data: Vec<u8>,
+    ) -> Result<Vec<u8>, DiskError> {
+        let (respond, recv) = mesh::oneshot();
+        self.send.send(Io {
+            opcode,
+            slba,
+            fua,
+            data,
+            respond,
+        });
+        recv.await
+            .map_err(|err| DiskError::Io(io::Error::new(io::ErrorKind::NotConnected, err)))?
+            .map_err(map_nvme_status)
+    }
+
+    /// Splits a request into chunks no larger than the maximum transfer size,
+    /// returning the buffer offset and starting sector of each.
+    fn chunks(&self, len: usize, sector: u64) -> impl Iterator<Item = (usize, usize, u64)> {
+        let max_transfer = self.geometry.max_transfer as usize;
+        let sector_shift = self.geometry.sector_shift;
+        (0..len).step_by(max_transfer).map(move |offset| {
+            (
+                offset,
+                max_transfer.min(len - offset),
+                sector + (offset >> sector_shift) as u64,
+            )
+        })
+    }
+}
+
+fn map_nvme_status(status: Status) -> DiskError {
+    let err = || io::Error::other(format!("nvme status {status:?}"));
+    match status {
+        Status::RESERVATION_CONFLICT => DiskError::ReservationConflict,
+        Status::INVALID_FIELD_IN_COMMAND => DiskError::InvalidInput,
+        Status::LBA_OUT_OF_RANGE => DiskError::IllegalBlock,
+        Status::MEDIA_WRITE_FAULT => DiskError::MediumError(err(), MediumErrorDetails::WriteFault),
+        Status::MEDIA_UNRECOVERED_READ_ERROR => {
+            DiskError::MediumError(err(), MediumErrorDetails::UnrecoveredReadError)
+        }
+        _ => DiskError::Io(err()),
+    }
+}
+
+impl DiskIo for NvmeTcpDisk {
+    fn disk_type(&self) -> &str {
+        "nvme_tcp"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.geometry.sector_count
+    }
+
+    fn sector_size(&self) -> u32 {
+        1 << self.geometry.sector_shift
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        None
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        self.sector_size()
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        true
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        futures::future::try_join_all(self.chunks(buffers.len(), sector).map(
+            |(offset, len, sector)| async move {
+                let data = self
+                    .issue(NvmOpcode::READ, sector, false, vec![0; len])
+                    .await?;
+                buffers.subrange(offset, len).writer().write(&data)?;
+                Ok::<_, DiskError>(())
+            },
+        ))
+        .await?;
+        Ok(())
+    }
+
+    async fn write_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        futures::future::try_join_all(self.chunks(buffers.len(), sector).map(
+            |(offset, len, sector)| async move {
+                let mut data = vec![0; len];
+                buffers.subrange(offset, len).reader().read(&mut data)?;
+                self.issue(NvmOpcode::WRITE, sector, fua, data).await?;
+                Ok::<_, DiskError>(())
+            },
+        ))
+        .await?;
+        Ok(())
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        self.issue(NvmOpcode::FLUSH, 0, false, Vec::new()).await?;
+        Ok(())
+    }
+
+    async fn unmap(
+        &self,
+        _sector: u64,
+        _count: u64,
+        _block_level_only: bool,
+    ) -> Result<(), DiskError> {
+        Ok(())
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        UnmapBehavior::Ignored
+    }
+}
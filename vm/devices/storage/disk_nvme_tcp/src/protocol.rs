@@ -0,0 +1,154 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! NVMe/TCP PDU definitions, as defined by the NVMe over TCP transport
+//! specification, and the fabrics commands used to set up a controller.
+
+use open_enum::open_enum;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+open_enum! {
+    #[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+    pub enum PduType: u8 {
+        IC_REQ = 0x00,
+        IC_RESP = 0x01,
+        H2C_TERM_REQ = 0x02,
+        C2H_TERM_REQ = 0x03,
+        CAPSULE_CMD = 0x04,
+        CAPSULE_RESP = 0x05,
+        H2C_DATA = 0x06,
+        C2H_DATA = 0x07,
+        R2T = 0x09,
+    }
+}
+
+/// Set on the last data PDU of a transfer.
+pub const FLAG_LAST_PDU: u8 = 1 << 2;
+/// Set on the last C2HData PDU if the command succeeded and no capsule
+/// response will follow.
+pub const FLAG_SUCCESS: u8 = 1 << 3;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct CommonHeader {
+    pub pdu_type: PduType,
+    pub flags: u8,
+    /// The length of the PDU header, including this common header.
+    pub hlen: u8,
+    /// The offset of the PDU data, or zero if there is no data.
+    pub pdo: u8,
+    /// The total length of the PDU, including the header and data.
+    pub plen: u32,
+}
+
+pub const COMMON_HEADER_SIZE: usize = size_of::<CommonHeader>();
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct IcReq {
+    pub ch: CommonHeader,
+    pub pfv: u16,
+    /// Host PDU data alignment, in dwords, zero based.
+    pub hpda: u8,
+    /// Bit 0 enables header digests, bit 1 data digests.
+    pub dgst: u8,
+    /// The maximum number of outstanding R2Ts per command, zero based.
+    pub maxr2t: u32,
+    pub reserved: [u8; 112],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct IcResp {
+    pub ch: CommonHeader,
+    pub pfv: u16,
+    /// Controller PDU data alignment, in dwords, zero based.
+    pub cpda: u8,
+    pub dgst: u8,
+    /// The maximum data length of an H2CData PDU.
+    pub maxh2cdata: u32,
+    pub reserved: [u8; 112],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct CapsuleCmd {
+    pub ch: CommonHeader,
+    pub sqe: nvme_spec::Command,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct CapsuleResp {
+    pub ch: CommonHeader,
+    pub cqe: nvme_spec::Completion,
+}
+
+/// The header of an H2CData or C2HData PDU.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct DataHeader {
+    pub ch: CommonHeader,
+    pub cccid: u16,
+    /// The transfer tag from the R2T, for H2CData.
+    pub ttag: u16,
+    pub datao: u32,
+    pub datal: u32,
+    pub reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct R2t {
+    pub ch: CommonHeader,
+    pub cccid: u16,
+    pub ttag: u16,
+    pub r2to: u32,
+    pub r2tl: u32,
+    pub reserved: u32,
+}
+
+/// The fabrics command opcode.
+pub const FABRICS_OPCODE: u8 = 0x7f;
+
+open_enum! {
+    /// The fabrics command type, stored in the low byte of the NSID field.
+    pub enum FabricsCommandType: u8 {
+        PROPERTY_SET = 0x00,
+        CONNECT = 0x01,
+        PROPERTY_GET = 0x04,
+    }
+}
+
+/// The data for a connect command, sent in the command capsule.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct ConnectData {
+    pub hostid: [u8; 16],
+    pub cntlid: u16,
+    pub reserved: [u8; 238],
+    pub subnqn: [u8; 256],
+    pub hostnqn: [u8; 256],
+    pub reserved2: [u8; 256],
+}
+
+const _: () = assert!(size_of::<ConnectData>() == 1024);
+
+/// Requests that the controller allocate a controller ID.
+pub const CNTLID_DYNAMIC: u16 = 0xffff;
+
+/// Property attribute for an 8-byte property.
+pub const PROPERTY_SIZE_8: u32 = 1;
+
+/// SGL identifier for data in the command capsule, at an offset.
+pub const SGL_DATA_BLOCK_OFFSET: u8 = 0x01;
+/// SGL identifier for data transferred with data PDUs.
+pub const SGL_TRANSPORT_DATA_BLOCK: u8 = 0x5a;
+
+/// Returns the data pointer for an SGL data block descriptor.
+pub fn sgl(identifier: u8, len: u32) -> [u64; 2] {
+    [0, len as u64 | (identifier as u64) << 56]
+}
@@ -0,0 +1,54 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::ConnectParams;
+use crate::NvmeTcpDisk;
+use anyhow::Context as _;
+use async_trait::async_trait;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::NvmeTcpDiskHandle;
+use std::net::ToSocketAddrs;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::DiskHandleKind;
+
+/// A resolver for NvmeTcpDisk.
+pub struct NvmeTcpDiskResolver;
+declare_static_async_resolver!(NvmeTcpDiskResolver, (DiskHandleKind, NvmeTcpDiskHandle));
+
+#[async_trait]
+impl AsyncResolveResource<DiskHandleKind, NvmeTcpDiskHandle> for NvmeTcpDiskResolver {
+    type Output = ResolvedDisk;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        _resolver: &ResourceResolver,
+        rsrc: NvmeTcpDiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let addr = rsrc
+            .address
+            .to_socket_addrs()
+            .with_context(|| format!("failed to resolve {}", rsrc.address))?
+            .next()
+            .with_context(|| format!("no addresses for {}", rsrc.address))?;
+        let host_id = guid::Guid::new_random();
+        let params = ConnectParams {
+            addr,
+            subsystem_nqn: rsrc.subsystem_nqn,
+            host_nqn: rsrc
+                .host_nqn
+                .unwrap_or_else(|| format!("nqn.2014-08.org.nvmexpress:uuid:{host_id}")),
+            host_id,
+            nsid: rsrc.nsid,
+            queue_depth: rsrc.queue_depth,
+        };
+        let disk = NvmeTcpDisk::connect(input.driver_source.simple(), params)
+            .await
+            .context("failed to connect nvme/tcp disk")?;
+        ResolvedDisk::new(disk).context("invalid nvme/tcp disk")
+    }
+}
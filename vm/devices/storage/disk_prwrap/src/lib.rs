@@ -123,6 +123,10 @@ fn is_fua_respected(&self) -> bool {
         self.inner.is_fua_respected()
     }
 
+    fn has_volatile_write_cache(&self) -> bool {
+        self.inner.has_volatile_write_cache()
+    }
+
     fn is_read_only(&self) -> bool {
         self.inner.is_read_only()
     }
@@ -168,6 +172,14 @@ async fn write_vectored(
     fn sync_cache(&self) -> impl Future<Output = Result<(), DiskError>> + Send {
         self.inner.sync_cache()
     }
+
+    fn quiesce(&self) -> impl Future<Output = Result<(), DiskError>> + Send {
+        self.inner.quiesce()
+    }
+
+    fn thaw(&self) -> impl Future<Output = Result<(), DiskError>> + Send {
+        self.inner.thaw()
+    }
 }
 
 #[async_trait]
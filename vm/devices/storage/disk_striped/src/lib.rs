@@ -403,6 +403,38 @@ async fn sync_cache(&self) -> Result<(), DiskError> {
         Ok(())
     }
 
+    async fn quiesce(&self) -> Result<(), DiskError> {
+        let all_futures = self
+            .block_devices
+            .iter()
+            .enumerate()
+            .map(|(disk_index, disk)| async move {
+                disk.quiesce().await.map_err(|err| LowerError {
+                    index: disk_index,
+                    err,
+                })
+            })
+            .collect();
+        await_all_and_check(all_futures).await?;
+        Ok(())
+    }
+
+    async fn thaw(&self) -> Result<(), DiskError> {
+        let all_futures = self
+            .block_devices
+            .iter()
+            .enumerate()
+            .map(|(disk_index, disk)| async move {
+                disk.thaw().await.map_err(|err| LowerError {
+                    index: disk_index,
+                    err,
+                })
+            })
+            .collect();
+        await_all_and_check(all_futures).await?;
+        Ok(())
+    }
+
     async fn unmap(
         &self,
         start_sector: u64,
@@ -0,0 +1,257 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! VHDX on-disk structures, as defined by the MS-VHDX specification.
+
+use guid::Guid;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+pub const KB: u64 = 1024;
+pub const MB: u64 = 1024 * KB;
+
+pub const FILE_IDENTIFIER_SIGNATURE: u64 = u64::from_le_bytes(*b"vhdxfile");
+pub const HEADER_SIGNATURE: u32 = u32::from_le_bytes(*b"head");
+pub const REGION_TABLE_SIGNATURE: u32 = u32::from_le_bytes(*b"regi");
+pub const METADATA_TABLE_SIGNATURE: u64 = u64::from_le_bytes(*b"metadata");
+
+/// The offsets of the two copies of the header.
+pub const HEADER_OFFSETS: [u64; 2] = [64 * KB, 128 * KB];
+pub const HEADER_SIZE: usize = 4 * KB as usize;
+/// The offsets of the two copies of the region table.
+pub const REGION_TABLE_OFFSETS: [u64; 2] = [192 * KB, 256 * KB];
+pub const REGION_TABLE_SIZE: usize = 64 * KB as usize;
+pub const METADATA_TABLE_SIZE: usize = 64 * KB as usize;
+
+/// All regions and payload blocks are aligned to 1MB.
+pub const REGION_ALIGNMENT: u64 = MB;
+
+pub const HEADER_VERSION: u16 = 1;
+
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct FileIdentifier {
+    pub signature: u64,
+    /// UTF-16 name of the creating application.
+    pub creator: [u16; 256],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct Header {
+    pub signature: u32,
+    pub checksum: u32,
+    pub sequence_number: u64,
+    pub file_write_guid: Guid,
+    pub data_write_guid: Guid,
+    /// Identifies the active log entries. Zero if the log is empty.
+    pub log_guid: Guid,
+    pub log_version: u16,
+    pub version: u16,
+    pub log_length: u32,
+    pub log_offset: u64,
+}
+
+pub const LOG_ENTRY_SIGNATURE: u32 = u32::from_le_bytes(*b"loge");
+pub const LOG_ZERO_SIGNATURE: u32 = u32::from_le_bytes(*b"zero");
+pub const LOG_DATA_DESCRIPTOR_SIGNATURE: u32 = u32::from_le_bytes(*b"desc");
+pub const LOG_DATA_SECTOR_SIGNATURE: u32 = u32::from_le_bytes(*b"data");
+
+/// Log entries, descriptors and data are made of 4KB sectors.
+pub const LOG_SECTOR_SIZE: usize = 4 * KB as usize;
+
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct LogEntryHeader {
+    pub signature: u32,
+    pub checksum: u32,
+    pub entry_length: u32,
+    /// The offset within the log of the first entry of this entry's
+    /// sequence.
+    pub tail: u32,
+    pub sequence_number: u64,
+    pub descriptor_count: u32,
+    pub reserved: u32,
+    pub log_guid: Guid,
+    /// The file size at the time the entry was written, which must be
+    /// durable for the entry to be valid.
+    pub flushed_file_offset: u64,
+    /// The file size the file must be extended to when replaying the entry.
+    pub last_file_offset: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct LogZeroDescriptor {
+    pub signature: u32,
+    pub reserved: u32,
+    pub zero_length: u64,
+    pub file_offset: u64,
+    pub sequence_number: u64,
+}
+
+/// Describes a logged sector. The sector's first eight and last four bytes
+/// are stored here, since the data sector uses them for its own fields.
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct LogDataDescriptor {
+    pub signature: u32,
+    pub trailing_bytes: u32,
+    pub leading_bytes: u64,
+    pub file_offset: u64,
+    pub sequence_number: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct RegionTableHeader {
+    pub signature: u32,
+    pub checksum: u32,
+    pub entry_count: u32,
+    pub reserved: u32,
+}
+
+/// The maximum number of region table entries.
+pub const REGION_TABLE_MAX_ENTRIES: u32 = 2047;
+
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct RegionTableEntry {
+    pub guid: Guid,
+    pub file_offset: u64,
+    pub length: u32,
+    /// Bit 0 is set if the region must be understood to open the file.
+    pub required: u32,
+}
+
+pub const REGION_BAT: Guid = guid::guid!("2dc27766-f623-4200-9d64-115e9bfd4a08");
+pub const REGION_METADATA: Guid = guid::guid!("8b7ca206-4790-4b9a-b8fe-575f050f886e");
+
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct MetadataTableHeader {
+    pub signature: u64,
+    pub reserved: u16,
+    pub entry_count: u16,
+    pub reserved2: [u32; 5],
+}
+
+/// The maximum number of metadata table entries.
+pub const METADATA_TABLE_MAX_ENTRIES: u16 = 2047;
+
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct MetadataTableEntry {
+    pub item_id: Guid,
+    /// The offset of the item from the start of the metadata region.
+    pub offset: u32,
+    pub length: u32,
+    pub flags: u32,
+    pub reserved: u32,
+}
+
+pub const METADATA_FLAG_IS_VIRTUAL_DISK: u32 = 1 << 1;
+pub const METADATA_FLAG_IS_REQUIRED: u32 = 1 << 2;
+
+pub const METADATA_FILE_PARAMETERS: Guid = guid::guid!("caa16737-fa36-4d43-b3b6-33f0aa44e76b");
+pub const METADATA_VIRTUAL_DISK_SIZE: Guid = guid::guid!("2fa54224-cd1b-4876-b211-5dbed83bf4b8");
+pub const METADATA_PAGE_83_DATA: Guid = guid::guid!("beca12ab-b2e6-4523-93ef-c309e000c746");
+pub const METADATA_LOGICAL_SECTOR_SIZE: Guid = guid::guid!("8141bf1d-a96f-4709-ba47-f233a8faab5f");
+pub const METADATA_PHYSICAL_SECTOR_SIZE: Guid = guid::guid!("cda348c7-445d-4471-9cc9-e9885251c556");
+
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct FileParameters {
+    pub block_size: u32,
+    pub flags: u32,
+}
+
+pub const FILE_PARAMETERS_HAS_PARENT: u32 = 1 << 1;
+
+pub const MIN_BLOCK_SIZE: u32 = MB as u32;
+pub const MAX_BLOCK_SIZE: u32 = 256 * MB as u32;
+
+/// The maximum virtual disk size, 64TB.
+pub const MAX_DISK_SIZE: u64 = 64 * 1024 * 1024 * MB;
+
+/// The number of sectors described by each sector bitmap block.
+pub const SECTORS_PER_BITMAP_BLOCK: u64 = 1 << 23;
+
+/// The state of a block, stored in the low bits of a BAT entry.
+pub const BAT_STATE_MASK: u64 = 0x7;
+/// The file offset of a block, stored in the high bits of a BAT entry in 1MB
+/// units.
+pub const BAT_OFFSET_MASK: u64 = !(MB - 1);
+
+pub const PAYLOAD_BLOCK_NOT_PRESENT: u64 = 0;
+pub const PAYLOAD_BLOCK_UNDEFINED: u64 = 1;
+pub const PAYLOAD_BLOCK_ZERO: u64 = 2;
+pub const PAYLOAD_BLOCK_UNMAPPED: u64 = 3;
+pub const PAYLOAD_BLOCK_FULLY_PRESENT: u64 = 6;
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f63b78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32c_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc = CRC32C_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// Computes the CRC-32C checksum of a header, region table or log entry,
+/// treating the checksum field at offset 4 as zero.
+pub fn checksum(buf: &[u8]) -> u32 {
+    let crc = crc32c_update(!0, &buf[..4]);
+    let crc = crc32c_update(crc, &[0; 4]);
+    !crc32c_update(crc, &buf[8..])
+}
+
+/// Returns the number of payload blocks per sector bitmap block.
+pub fn chunk_ratio(logical_sector_size: u32, block_size: u32) -> u64 {
+    SECTORS_PER_BITMAP_BLOCK * logical_sector_size as u64 / block_size as u64
+}
+
+/// Returns the number of BAT entries for a disk of `disk_size` bytes.
+///
+/// Each chunk of `chunk_ratio` payload entries is followed by a sector
+/// bitmap entry, which is only used by differencing disks.
+pub fn bat_entry_count(disk_size: u64, block_size: u32, chunk_ratio: u64) -> u64 {
+    let blocks = disk_size.div_ceil(block_size as u64);
+    blocks + blocks.saturating_sub(1) / chunk_ratio
+}
+
+/// Returns the BAT index of payload block `block`.
+pub fn bat_index(block: u64, chunk_ratio: u64) -> usize {
+    (block + block / chunk_ratio) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::crc32c_update;
+
+    #[test]
+    fn crc32c() {
+        // The standard CRC-32C check value.
+        assert_eq!(!crc32c_update(!0, b"123456789"), 0xe3069283);
+    }
+}
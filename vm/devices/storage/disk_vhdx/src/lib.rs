@@ -0,0 +1,1040 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A user-mode VHDX disk implementation, for hosts without the Windows VHD
+//! APIs.
+//!
+//! Supports fixed and dynamic VHDX files. Differencing VHDX files are not
+//! supported. Updates to the BAT and metadata go through the log, which is
+//! replayed when a file that was not closed cleanly is opened for write.
+
+#![expect(missing_docs)]
+#![forbid(unsafe_code)]
+
+mod format;
+mod log;
+mod readwriteat;
+
+use self::format::*;
+use self::log::Log;
+use self::log::LogWrite;
+use self::readwriteat::read_exact_at;
+use self::readwriteat::write_all_at;
+use anyhow::Context as _;
+use blocking::unblock;
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::VhdxDiskHandle;
+use guestmem::MemoryRead;
+use guestmem::MemoryWrite;
+use guid::Guid;
+use inspect::Inspect;
+use parking_lot::Mutex;
+use scsi_buffers::RequestBuffers;
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use thiserror::Error;
+use vm_resource::ResolveResource;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::DiskHandleKind;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+pub struct VhdxResolver;
+declare_static_resolver!(VhdxResolver, (DiskHandleKind, VhdxDiskHandle));
+
+#[derive(Debug, Error)]
+pub enum ResolveVhdxDiskError {
+    #[error("failed to open VHDX")]
+    Open(#[source] OpenError),
+    #[error("invalid disk")]
+    InvalidDisk(#[source] disk_backend::InvalidDisk),
+}
+
+impl ResolveResource<DiskHandleKind, VhdxDiskHandle> for VhdxResolver {
+    type Output = ResolvedDisk;
+    type Error = ResolveVhdxDiskError;
+
+    fn resolve(
+        &self,
+        rsrc: VhdxDiskHandle,
+        params: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let disk = VhdxDisk::open(rsrc.0, params.read_only).map_err(ResolveVhdxDiskError::Open)?;
+        ResolvedDisk::new(disk).map_err(ResolveVhdxDiskError::InvalidDisk)
+    }
+}
+
+/// An error encountered while opening or creating a VHDX.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum OpenError {
+    #[error("io error")]
+    Io(#[from] io::Error),
+    #[error("not a VHDX file")]
+    InvalidFileIdentifier,
+    #[error("no valid VHDX header")]
+    InvalidHeader,
+    #[error("the VHDX log must be replayed, which requires opening the file for write")]
+    LogReplayRequired,
+    #[error("invalid VHDX log")]
+    InvalidLog,
+    #[error("no valid VHDX region table")]
+    InvalidRegionTable,
+    #[error("unsupported required region {0}")]
+    UnsupportedRegion(Guid),
+    #[error("missing required region {0}")]
+    MissingRegion(Guid),
+    #[error("invalid VHDX metadata table")]
+    InvalidMetadataTable,
+    #[error("unsupported required metadata item {0}")]
+    UnsupportedMetadata(Guid),
+    #[error("missing or invalid metadata item {0}")]
+    InvalidMetadata(Guid),
+    #[error("differencing VHDX files are not supported")]
+    Differencing,
+    #[error("invalid VHDX block size: {0:#x}")]
+    InvalidBlockSize(u32),
+    #[error("invalid VHDX sector size: {0}")]
+    InvalidSectorSize(u32),
+    #[error("invalid VHDX disk size: {0:#x}")]
+    InvalidDiskSize(u64),
+    #[error("the BAT region is too small for the disk size")]
+    BatTooSmall,
+    #[error("invalid BAT entry {entry:#x} at index {index}")]
+    InvalidBatEntry { index: usize, entry: u64 },
+}
+
+/// The default block size for new dynamic VHDX files.
+pub const DEFAULT_BLOCK_SIZE: u32 = 32 * MB as u32;
+
+/// The layout used by [`VhdxDisk::create`].
+const LOG_OFFSET: u64 = MB;
+const LOG_LENGTH: u64 = MB;
+const METADATA_OFFSET: u64 = 2 * MB;
+const METADATA_LENGTH: u64 = MB;
+const BAT_OFFSET: u64 = 3 * MB;
+
+/// An open VHDX disk.
+#[derive(Inspect)]
+#[inspect(extra = "Self::inspect_extra")]
+pub struct VhdxDisk {
+    #[inspect(flatten)]
+    inner: Arc<Inner>,
+    #[inspect(skip)]
+    resize_event: event_listener::Event,
+}
+
+#[derive(Inspect)]
+struct Inner {
+    #[inspect(skip)]
+    file: File,
+    read_only: bool,
+    #[inspect(hex)]
+    block_size: u32,
+    logical_sector_size: u32,
+    physical_sector_size: u32,
+    #[inspect(skip)]
+    chunk_ratio: u64,
+    disk_id: Guid,
+    #[inspect(skip)] // handled in inspect_extra()
+    disk_size: AtomicU64,
+    /// The file offset of the virtual disk size metadata item, rewritten on
+    /// resize.
+    #[inspect(skip)]
+    disk_size_offset: u64,
+    #[inspect(skip)]
+    bat: Mutex<Bat>,
+    /// Locked after `bat`.
+    #[inspect(skip)]
+    log: Mutex<Log>,
+    allocated_blocks: AtomicU64,
+}
+
+struct Bat {
+    /// The file offset of the BAT region.
+    offset: u64,
+    /// The length of the BAT region, which bounds how far the disk can grow.
+    length: u64,
+    entries: Vec<u64>,
+    /// The file offset at which to allocate the next payload block.
+    next_block_offset: u64,
+}
+
+struct Metadata {
+    block_size: u32,
+    disk_size: u64,
+    disk_size_offset: u64,
+    disk_id: Guid,
+    logical_sector_size: u32,
+    physical_sector_size: u32,
+}
+
+impl VhdxDisk {
+    fn inspect_extra(&self, resp: &mut inspect::Response<'_>) {
+        resp.field_with("bat_capacity", || self.inner.bat.lock().length / 8)
+            .field_mut_with("sector_count", |new_count| {
+                if let Some(new_count) = new_count {
+                    self.resize(new_count.parse().context("invalid sector count")?)?;
+                }
+                anyhow::Ok(self.sector_count())
+            });
+    }
+
+    /// Opens a fixed or dynamic VHDX.
+    ///
+    /// If `read_only` is false, the log is replayed if necessary, and the
+    /// headers are updated to mark the file as modified before any writes are
+    /// issued.
+    pub fn open(file: File, read_only: bool) -> Result<Self, OpenError> {
+        let mut ident = FileIdentifier::new_zeroed();
+        read_exact_at(&file, ident.as_mut_bytes(), 0)?;
+        if ident.signature != FILE_IDENTIFIER_SIGNATURE {
+            return Err(OpenError::InvalidFileIdentifier);
+        }
+
+        let (header_index, header) = read_header(&file)?;
+        if !read_only {
+            let log_length = header.log_length as u64;
+            if log_length == 0
+                || !log_length.is_multiple_of(REGION_ALIGNMENT)
+                || !header.log_offset.is_multiple_of(REGION_ALIGNMENT)
+                || header.log_offset + log_length > file.metadata()?.len()
+            {
+                return Err(OpenError::InvalidLog);
+            }
+        }
+        if header.log_guid != Guid::ZERO {
+            if read_only {
+                return Err(OpenError::LogReplayRequired);
+            }
+            log::replay(&file, &header)?;
+        }
+
+        let file_len = file.metadata()?.len();
+
+        let mut bat_region = None;
+        let mut metadata_region = None;
+        for entry in read_region_table(&file)? {
+            if entry.guid == REGION_BAT {
+                bat_region = Some(entry);
+            } else if entry.guid == REGION_METADATA {
+                metadata_region = Some(entry);
+            } else if entry.required & 1 != 0 {
+                return Err(OpenError::UnsupportedRegion(entry.guid));
+            }
+        }
+        let bat_region = bat_region.ok_or(OpenError::MissingRegion(REGION_BAT))?;
+        let metadata_region = metadata_region.ok_or(OpenError::MissingRegion(REGION_METADATA))?;
+
+        let metadata = read_metadata(&file, &metadata_region)?;
+        let chunk_ratio = chunk_ratio(metadata.logical_sector_size, metadata.block_size);
+        let entry_count =
+            bat_entry_count(metadata.disk_size, metadata.block_size, chunk_ratio) as usize;
+        if entry_count as u64 * 8 > bat_region.length as u64 {
+            return Err(OpenError::BatTooSmall);
+        }
+        let mut entries = vec![0u64; entry_count];
+        read_exact_at(&file, entries.as_mut_bytes(), bat_region.file_offset)?;
+
+        let block_count = metadata.disk_size.div_ceil(metadata.block_size as u64);
+        let mut allocated_blocks = 0u64;
+        for block in 0..block_count {
+            let index = bat_index(block, chunk_ratio);
+            let entry = entries[index];
+            match entry & BAT_STATE_MASK {
+                PAYLOAD_BLOCK_FULLY_PRESENT => {
+                    let offset = entry & BAT_OFFSET_MASK;
+                    if offset < REGION_ALIGNMENT || offset + metadata.block_size as u64 > file_len {
+                        return Err(OpenError::InvalidBatEntry { index, entry });
+                    }
+                    allocated_blocks += 1;
+                }
+                PAYLOAD_BLOCK_NOT_PRESENT
+                | PAYLOAD_BLOCK_UNDEFINED
+                | PAYLOAD_BLOCK_ZERO
+                | PAYLOAD_BLOCK_UNMAPPED => {}
+                _ => return Err(OpenError::InvalidBatEntry { index, entry }),
+            }
+        }
+
+        let mut log = Log::new(header_index, header);
+        if !read_only {
+            // Update the older header with new write GUIDs before modifying
+            // the file, as the spec requires. This also marks a replayed log
+            // empty.
+            log.update_header(&file, |header| {
+                header.file_write_guid = Guid::new_random();
+                header.data_write_guid = Guid::new_random();
+                header.log_guid = Guid::ZERO;
+            })?;
+        }
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                file,
+                read_only,
+                block_size: metadata.block_size,
+                logical_sector_size: metadata.logical_sector_size,
+                physical_sector_size: metadata.physical_sector_size,
+                chunk_ratio,
+                disk_id: metadata.disk_id,
+                disk_size: metadata.disk_size.into(),
+                disk_size_offset: metadata.disk_size_offset,
+                bat: Mutex::new(Bat {
+                    offset: bat_region.file_offset,
+                    length: bat_region.length.into(),
+                    entries,
+                    next_block_offset: file_len.next_multiple_of(REGION_ALIGNMENT),
+                }),
+                log: Mutex::new(log),
+                allocated_blocks: allocated_blocks.into(),
+            }),
+            resize_event: Default::default(),
+        })
+    }
+
+    /// Formats `file` as an empty dynamic VHDX of `disk_size` bytes with
+    /// `block_size` byte payload blocks.
+    ///
+    /// The BAT is sized in whole megabytes, so the disk can later be resized
+    /// up to the size the BAT region can describe.
+    pub fn create(file: &File, disk_size: u64, block_size: u32) -> Result<(), OpenError> {
+        let logical_sector_size: u32 = 512;
+        let physical_sector_size: u32 = 4096;
+
+        if !block_size.is_power_of_two() || !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size)
+        {
+            return Err(OpenError::InvalidBlockSize(block_size));
+        }
+        if disk_size == 0
+            || disk_size > MAX_DISK_SIZE
+            || !disk_size.is_multiple_of(logical_sector_size as u64)
+        {
+            return Err(OpenError::InvalidDiskSize(disk_size));
+        }
+        let chunk_ratio = chunk_ratio(logical_sector_size, block_size);
+        let bat_length =
+            (bat_entry_count(disk_size, block_size, chunk_ratio) * 8).next_multiple_of(MB);
+
+        file.set_len(0)?;
+
+        let mut ident = FileIdentifier::new_zeroed();
+        ident.signature = FILE_IDENTIFIER_SIGNATURE;
+        for (c, d) in "openvmm".encode_utf16().zip(&mut ident.creator) {
+            *d = c;
+        }
+        write_all_at(file, ident.as_bytes(), 0)?;
+
+        let header = Header {
+            signature: HEADER_SIGNATURE,
+            checksum: 0,
+            sequence_number: 0,
+            file_write_guid: Guid::new_random(),
+            data_write_guid: Guid::new_random(),
+            log_guid: Guid::ZERO,
+            log_version: 0,
+            version: HEADER_VERSION,
+            log_length: LOG_LENGTH as u32,
+            log_offset: LOG_OFFSET,
+        };
+        write_header(file, 0, &header)?;
+        write_header(
+            file,
+            1,
+            &Header {
+                sequence_number: 1,
+                ..header
+            },
+        )?;
+
+        let mut table = vec![0; REGION_TABLE_SIZE];
+        let regions = [
+            RegionTableEntry {
+                guid: REGION_BAT,
+                file_offset: BAT_OFFSET,
+                length: bat_length as u32,
+                required: 1,
+            },
+            RegionTableEntry {
+                guid: REGION_METADATA,
+                file_offset: METADATA_OFFSET,
+                length: METADATA_LENGTH as u32,
+                required: 1,
+            },
+        ];
+        RegionTableHeader {
+            signature: REGION_TABLE_SIGNATURE,
+            checksum: 0,
+            entry_count: regions.len() as u32,
+            reserved: 0,
+        }
+        .write_to_prefix(&mut table)
+        .unwrap();
+        table[size_of::<RegionTableHeader>()..][..size_of_val(&regions)]
+            .copy_from_slice(regions.as_bytes());
+        let checksum = checksum(&table);
+        table[4..8].copy_from_slice(&checksum.to_le_bytes());
+        for offset in REGION_TABLE_OFFSETS {
+            write_all_at(file, &table, offset)?;
+        }
+
+        let file_parameters = FileParameters {
+            block_size,
+            flags: 0,
+        };
+        let page_83_data = Guid::new_random();
+        let required = METADATA_FLAG_IS_REQUIRED;
+        let virtual_disk = METADATA_FLAG_IS_VIRTUAL_DISK | METADATA_FLAG_IS_REQUIRED;
+        let items: [(Guid, &[u8], u32); 5] = [
+            (
+                METADATA_FILE_PARAMETERS,
+                file_parameters.as_bytes(),
+                required,
+            ),
+            (
+                METADATA_VIRTUAL_DISK_SIZE,
+                disk_size.as_bytes(),
+                virtual_disk,
+            ),
+            (METADATA_PAGE_83_DATA, page_83_data.as_bytes(), virtual_disk),
+            (
+                METADATA_LOGICAL_SECTOR_SIZE,
+                logical_sector_size.as_bytes(),
+                virtual_disk,
+            ),
+            (
+                METADATA_PHYSICAL_SECTOR_SIZE,
+                physical_sector_size.as_bytes(),
+                virtual_disk,
+            ),
+        ];
+        let mut metadata = vec![0; METADATA_TABLE_SIZE * 2];
+        MetadataTableHeader {
+            signature: METADATA_TABLE_SIGNATURE,
+            reserved: 0,
+            entry_count: items.len() as u16,
+            reserved2: [0; 5],
+        }
+        .write_to_prefix(&mut metadata)
+        .unwrap();
+        let mut item_offset = METADATA_TABLE_SIZE;
+        for (i, &(item_id, data, flags)) in items.iter().enumerate() {
+            MetadataTableEntry {
+                item_id,
+                offset: item_offset as u32,
+                length: data.len() as u32,
+                flags,
+                reserved: 0,
+            }
+            .write_to_prefix(&mut metadata[(i + 1) * size_of::<MetadataTableEntry>()..])
+            .unwrap();
+            metadata[item_offset..][..data.len()].copy_from_slice(data);
+            item_offset += data.len();
+        }
+        write_all_at(file, &metadata, METADATA_OFFSET)?;
+
+        // Extending the file zeroes the BAT, leaving all blocks not present.
+        file.set_len(BAT_OFFSET + bat_length)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    fn resize(&self, new_sector_count: u64) -> anyhow::Result<()> {
+        self.inner.resize(new_sector_count)?;
+        self.resize_event.notify(usize::MAX);
+        Ok(())
+    }
+
+    fn check_range(&self, sector: u64, len: usize) -> Result<u64, DiskError> {
+        let offset = sector
+            .checked_mul(self.inner.logical_sector_size.into())
+            .ok_or(DiskError::IllegalBlock)?;
+        if offset.saturating_add(len as u64) > self.inner.disk_size.load(Ordering::Relaxed) {
+            return Err(DiskError::IllegalBlock);
+        }
+        Ok(offset)
+    }
+}
+
+impl Inner {
+    /// Returns the file offset of payload block `block`, or `None` if the
+    /// block is not allocated.
+    fn block_offset(&self, block: u64) -> Option<u64> {
+        let entry = self.bat.lock().entries[bat_index(block, self.chunk_ratio)];
+        (entry & BAT_STATE_MASK == PAYLOAD_BLOCK_FULLY_PRESENT).then_some(entry & BAT_OFFSET_MASK)
+    }
+
+    /// Allocates payload block `block` at the end of the file, returning its
+    /// file offset.
+    fn allocate_block(&self, block: u64) -> io::Result<u64> {
+        let mut bat = self.bat.lock();
+        let index = bat_index(block, self.chunk_ratio);
+        let entry = bat.entries[index];
+        if entry & BAT_STATE_MASK == PAYLOAD_BLOCK_FULLY_PRESENT {
+            // A concurrent write allocated the block first.
+            return Ok(entry & BAT_OFFSET_MASK);
+        }
+        // Extend the file with a zeroed block before pointing the BAT at it,
+        // so that a torn update never exposes stale data.
+        let offset = bat.next_block_offset;
+        self.file.set_len(offset + self.block_size as u64)?;
+        let entry = offset | PAYLOAD_BLOCK_FULLY_PRESENT;
+        self.log.lock().write(
+            &self.file,
+            &[LogWrite::Data(
+                bat.offset + index as u64 * 8,
+                entry.as_bytes(),
+            )],
+        )?;
+        bat.entries[index] = entry;
+        bat.next_block_offset += self.block_size as u64;
+        self.allocated_blocks.fetch_add(1, Ordering::Relaxed);
+        Ok(offset)
+    }
+
+    fn read(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        let block_size = self.block_size as u64;
+        while !buf.is_empty() {
+            let block_offset = offset % block_size;
+            let len = buf.len().min((block_size - block_offset) as usize);
+            let (this, rest) = std::mem::take(&mut buf).split_at_mut(len);
+            match self.block_offset(offset / block_size) {
+                Some(file_offset) => read_exact_at(&self.file, this, file_offset + block_offset)?,
+                None => this.fill(0),
+            }
+            buf = rest;
+            offset += len as u64;
+        }
+        Ok(())
+    }
+
+    fn write(&self, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+        let block_size = self.block_size as u64;
+        while !buf.is_empty() {
+            let block_offset = offset % block_size;
+            let len = buf.len().min((block_size - block_offset) as usize);
+            let (this, rest) = buf.split_at(len);
+            let block = offset / block_size;
+            let file_offset = match self.block_offset(block) {
+                Some(file_offset) => file_offset,
+                None => self.allocate_block(block)?,
+            };
+            write_all_at(&self.file, this, file_offset + block_offset)?;
+            buf = rest;
+            offset += len as u64;
+        }
+        Ok(())
+    }
+
+    fn resize(&self, new_sector_count: u64) -> anyhow::Result<()> {
+        anyhow::ensure!(!self.read_only, "disk is read only");
+        let new_size = new_sector_count
+            .checked_mul(self.logical_sector_size.into())
+            .filter(|&size| size != 0 && size <= MAX_DISK_SIZE)
+            .context("invalid sector count")?;
+        let mut bat = self.bat.lock();
+        let old_size = self.disk_size.load(Ordering::Relaxed);
+        // FUTURE: support shrinking, which requires trimming blocks past the
+        // new end of the disk.
+        anyhow::ensure!(new_size >= old_size, "shrinking a VHDX is not supported");
+        let entry_count = bat_entry_count(new_size, self.block_size, self.chunk_ratio) as usize;
+        anyhow::ensure!(
+            entry_count as u64 * 8 <= bat.length,
+            "the BAT region is too small for {new_size:#x} bytes"
+        );
+        // Clear the new entries in case the BAT region holds stale data, in
+        // the same log entry as the size update.
+        let old_count = bat.entries.len();
+        self.log.lock().write(
+            &self.file,
+            &[
+                LogWrite::Zero(
+                    bat.offset + old_count as u64 * 8,
+                    (entry_count - old_count) as u64 * 8,
+                ),
+                LogWrite::Data(self.disk_size_offset, new_size.as_bytes()),
+            ],
+        )?;
+        bat.entries.resize(entry_count, 0);
+        self.disk_size.store(new_size, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if !self.read_only {
+            // Every log entry has been applied, so mark the log empty to allow
+            // the file to be opened read-only. If this fails, the log is
+            // replayed on the next writable open.
+            let _ = self.log.get_mut().close(&self.file);
+        }
+    }
+}
+
+/// Reads both headers, returning the index and contents of the valid one with
+/// the highest sequence number.
+fn read_header(file: &File) -> Result<(usize, Header), OpenError> {
+    let mut buf = vec![0; HEADER_SIZE];
+    let mut current: Option<(usize, Header)> = None;
+    for (i, offset) in HEADER_OFFSETS.into_iter().enumerate() {
+        read_exact_at(file, &mut buf, offset)?;
+        let header = Header::read_from_prefix(&buf).unwrap().0;
+        if header.signature != HEADER_SIGNATURE
+            || header.checksum != checksum(&buf)
+            || header.version != HEADER_VERSION
+        {
+            continue;
+        }
+        if current
+            .as_ref()
+            .is_none_or(|(_, h)| header.sequence_number > h.sequence_number)
+        {
+            current = Some((i, header));
+        }
+    }
+    current.ok_or(OpenError::InvalidHeader)
+}
+
+fn write_header(file: &File, index: usize, header: &Header) -> io::Result<()> {
+    let mut buf = vec![0; HEADER_SIZE];
+    header.write_to_prefix(&mut buf).unwrap();
+    let checksum = checksum(&buf);
+    buf[4..8].copy_from_slice(&checksum.to_le_bytes());
+    write_all_at(file, &buf, HEADER_OFFSETS[index])
+}
+
+/// Reads the first valid copy of the region table.
+fn read_region_table(file: &File) -> Result<Vec<RegionTableEntry>, OpenError> {
+    let mut buf = vec![0; REGION_TABLE_SIZE];
+    for offset in REGION_TABLE_OFFSETS {
+        read_exact_at(file, &mut buf, offset)?;
+        let (header, entries) = RegionTableHeader::read_from_prefix(&buf).unwrap();
+        if header.signature != REGION_TABLE_SIGNATURE
+            || header.checksum != checksum(&buf)
+            || header.entry_count > REGION_TABLE_MAX_ENTRIES
+        {
+            continue;
+        }
+        return Ok(entries
+            .chunks_exact(size_of::<RegionTableEntry>())
+            .take(header.entry_count as usize)
+            .map(|entry| RegionTableEntry::read_from_bytes(entry).unwrap())
+            .collect());
+    }
+    Err(OpenError::InvalidRegionTable)
+}
+
+fn read_metadata(file: &File, region: &RegionTableEntry) -> Result<Metadata, OpenError> {
+    let mut table = vec![0; METADATA_TABLE_SIZE];
+    read_exact_at(file, &mut table, region.file_offset)?;
+    let (header, entries) = MetadataTableHeader::read_from_prefix(&table).unwrap();
+    if header.signature != METADATA_TABLE_SIGNATURE
+        || header.entry_count > METADATA_TABLE_MAX_ENTRIES
+    {
+        return Err(OpenError::InvalidMetadataTable);
+    }
+    let entries = entries
+        .chunks_exact(size_of::<MetadataTableEntry>())
+        .take(header.entry_count.into())
+        .map(|entry| MetadataTableEntry::read_from_bytes(entry).unwrap())
+        .collect::<Vec<_>>();
+
+    const KNOWN_ITEMS: [Guid; 5] = [
+        METADATA_FILE_PARAMETERS,
+        METADATA_VIRTUAL_DISK_SIZE,
+        METADATA_PAGE_83_DATA,
+        METADATA_LOGICAL_SECTOR_SIZE,
+        METADATA_PHYSICAL_SECTOR_SIZE,
+    ];
+    for entry in &entries {
+        if entry.flags & METADATA_FLAG_IS_REQUIRED != 0 && !KNOWN_ITEMS.contains(&entry.item_id) {
+            return Err(OpenError::UnsupportedMetadata(entry.item_id));
+        }
+    }
+
+    // Reads a fixed-size item, returning its file offset.
+    let read_item = |item_id: Guid, buf: &mut [u8]| -> Result<u64, OpenError> {
+        let entry = entries
+            .iter()
+            .find(|entry| entry.item_id == item_id)
+            .ok_or(OpenError::InvalidMetadata(item_id))?;
+        if (entry.length as usize) < buf.len()
+            || entry.offset as u64 + buf.len() as u64 > region.length as u64
+        {
+            return Err(OpenError::InvalidMetadata(item_id));
+        }
+        let offset = region.file_offset + entry.offset as u64;
+        read_exact_at(file, buf, offset)?;
+        Ok(offset)
+    };
+
+    let mut file_parameters = FileParameters::new_zeroed();
+    read_item(METADATA_FILE_PARAMETERS, file_parameters.as_mut_bytes())?;
+    if file_parameters.flags & FILE_PARAMETERS_HAS_PARENT != 0 {
+        return Err(OpenError::Differencing);
+    }
+    let block_size = file_parameters.block_size;
+    if !block_size.is_power_of_two() || !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) {
+        return Err(OpenError::InvalidBlockSize(block_size));
+    }
+
+    let mut logical_sector_size = 0u32;
+    read_item(
+        METADATA_LOGICAL_SECTOR_SIZE,
+        logical_sector_size.as_mut_bytes(),
+    )?;
+    if logical_sector_size != 512 && logical_sector_size != 4096 {
+        return Err(OpenError::InvalidSectorSize(logical_sector_size));
+    }
+    let mut physical_sector_size = 0u32;
+    read_item(
+        METADATA_PHYSICAL_SECTOR_SIZE,
+        physical_sector_size.as_mut_bytes(),
+    )?;
+    if physical_sector_size != 512 && physical_sector_size != 4096 {
+        return Err(OpenError::InvalidSectorSize(physical_sector_size));
+    }
+
+    let mut disk_size = 0u64;
+    let disk_size_offset = read_item(METADATA_VIRTUAL_DISK_SIZE, disk_size.as_mut_bytes())?;
+    if disk_size == 0
+        || disk_size > MAX_DISK_SIZE
+        || !disk_size.is_multiple_of(logical_sector_size.into())
+    {
+        return Err(OpenError::InvalidDiskSize(disk_size));
+    }
+
+    let mut disk_id = Guid::ZERO;
+    read_item(METADATA_PAGE_83_DATA, disk_id.as_mut_bytes())?;
+
+    Ok(Metadata {
+        block_size,
+        disk_size,
+        disk_size_offset,
+        disk_id,
+        logical_sector_size,
+        physical_sector_size,
+    })
+}
+
+impl DiskIo for VhdxDisk {
+    fn disk_type(&self) -> &str {
+        "vhdx"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.inner.disk_size.load(Ordering::Relaxed) / self.inner.logical_sector_size as u64
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.inner.logical_sector_size
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.inner.read_only
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        Some(self.inner.disk_id.into())
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        self.inner.physical_sector_size
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        false
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        let offset = self.check_range(sector, buffers.len())?;
+        let mut buffer = vec![0; buffers.len()];
+        let inner = self.inner.clone();
+        let buffer = unblock(move || -> Result<_, io::Error> {
+            inner.read(&mut buffer, offset)?;
+            Ok(buffer)
+        })
+        .await
+        .map_err(DiskError::Io)?;
+        buffers.writer().write(&buffer)?;
+        Ok(())
+    }
+
+    async fn write_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        _fua: bool,
+    ) -> Result<(), DiskError> {
+        if self.inner.read_only {
+            return Err(DiskError::ReadOnly);
+        }
+        let offset = self.check_range(sector, buffers.len())?;
+        let mut buffer = vec![0; buffers.len()];
+        buffers.reader().read(&mut buffer)?;
+        let inner = self.inner.clone();
+        unblock(move || inner.write(&buffer, offset))
+            .await
+            .map_err(DiskError::Io)?;
+        Ok(())
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        let inner = self.inner.clone();
+        unblock(move || inner.file.sync_all())
+            .await
+            .map_err(DiskError::Io)?;
+        Ok(())
+    }
+
+    async fn wait_resize(&self, sector_count: u64) -> u64 {
+        loop {
+            let listen = self.resize_event.listen();
+            let current = self.sector_count();
+            if current != sector_count {
+                break current;
+            }
+            listen.await;
+        }
+    }
+
+    async fn unmap(
+        &self,
+        _sector: u64,
+        _count: u64,
+        _block_level_only: bool,
+    ) -> Result<(), DiskError> {
+        Ok(())
+    }
+
+    fn unmap_behavior(&self) -> disk_backend::UnmapBehavior {
+        disk_backend::UnmapBehavior::Ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BAT_OFFSET;
+    use super::LOG_OFFSET;
+    use super::MB;
+    use super::OpenError;
+    use super::VhdxDisk;
+    use super::format::HEADER_OFFSETS;
+    use super::format::PAYLOAD_BLOCK_FULLY_PRESENT;
+    use super::format::bat_index;
+    use super::format::chunk_ratio;
+    use super::readwriteat::write_all_at;
+    use disk_backend::Disk;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+    use zerocopy::IntoBytes;
+
+    async fn read(disk: &Disk, mem: &GuestMemory, sector: u64, len: usize) -> Vec<u8> {
+        disk.read_vectored(
+            &OwnedRequestBuffers::linear(0, len, true).buffer(mem),
+            sector,
+        )
+        .await
+        .unwrap();
+        let mut buf = vec![0; len];
+        mem.read_at(0, &mut buf).unwrap();
+        buf
+    }
+
+    #[async_test]
+    async fn create_write_reopen() {
+        let file = tempfile::tempfile().unwrap();
+        VhdxDisk::create(&file, 64 * MB, MB as u32).unwrap();
+        let vhdx = Disk::new(VhdxDisk::open(file.try_clone().unwrap(), false).unwrap()).unwrap();
+        assert_eq!(vhdx.sector_count(), 64 * MB / 512);
+
+        // Write across the boundary between the first two blocks.
+        let mem = GuestMemory::allocate(0x2000);
+        let data = (0..0x800_u32).collect::<Vec<_>>();
+        mem.write_at(0, data.as_bytes()).unwrap();
+        let sector = MB / 512 - 4;
+        vhdx.write_vectored(
+            &OwnedRequestBuffers::linear(0, 0x2000, false).buffer(&mem),
+            sector,
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(read(&vhdx, &mem, sector, 0x2000).await, data.as_bytes());
+        // Unallocated blocks read as zero.
+        assert!(
+            read(&vhdx, &mem, 8 * MB / 512, 0x1000)
+                .await
+                .iter()
+                .all(|&b| b == 0)
+        );
+
+        drop(vhdx);
+        let vhdx = Disk::new(VhdxDisk::open(file, true).unwrap()).unwrap();
+        assert_eq!(read(&vhdx, &mem, sector, 0x2000).await, data.as_bytes());
+    }
+
+    #[async_test]
+    async fn resize() {
+        let file = tempfile::tempfile().unwrap();
+        VhdxDisk::create(&file, 16 * MB, MB as u32).unwrap();
+        let vhdx = VhdxDisk::open(file.try_clone().unwrap(), false).unwrap();
+        vhdx.resize(32 * MB / 512).unwrap();
+        vhdx.resize(16 * MB / 512).unwrap_err();
+        drop(vhdx);
+
+        let vhdx = Disk::new(VhdxDisk::open(file, false).unwrap()).unwrap();
+        assert_eq!(vhdx.sector_count(), 32 * MB / 512);
+        let mem = GuestMemory::allocate(0x1000);
+        mem.write_at(0, &[0xcc; 0x1000]).unwrap();
+        vhdx.write_vectored(
+            &OwnedRequestBuffers::linear(0, 0x1000, false).buffer(&mem),
+            32 * MB / 512 - 8,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            read(&vhdx, &mem, 32 * MB / 512 - 8, 0x1000).await,
+            [0xcc; 0x1000]
+        );
+    }
+
+    #[async_test]
+    async fn fixed() {
+        let file = tempfile::tempfile().unwrap();
+        VhdxDisk::create(&file, 4 * MB, MB as u32).unwrap();
+        // Make the VHDX fixed by allocating every block, filling each with
+        // its index plus one.
+        let data_offset = file.metadata().unwrap().len();
+        let chunk_ratio = chunk_ratio(512, MB as u32);
+        for block in 0..4 {
+            let offset = data_offset + block * MB;
+            write_all_at(&file, &vec![block as u8 + 1; MB as usize], offset).unwrap();
+            write_all_at(
+                &file,
+                (offset | PAYLOAD_BLOCK_FULLY_PRESENT).as_bytes(),
+                BAT_OFFSET + bat_index(block, chunk_ratio) as u64 * 8,
+            )
+            .unwrap();
+        }
+
+        let vhdx = Disk::new(VhdxDisk::open(file.try_clone().unwrap(), false).unwrap()).unwrap();
+        let mem = GuestMemory::allocate(0x1000);
+        assert_eq!(read(&vhdx, &mem, 2 * MB / 512, 0x1000).await, [3; 0x1000]);
+        mem.write_at(0, &[0xcc; 0x1000]).unwrap();
+        vhdx.write_vectored(
+            &OwnedRequestBuffers::linear(0, 0x1000, false).buffer(&mem),
+            3 * MB / 512,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            read(&vhdx, &mem, 3 * MB / 512, 0x1000).await,
+            [0xcc; 0x1000]
+        );
+        assert_eq!(
+            read(&vhdx, &mem, 4 * MB / 512 - 8, 0x1000).await,
+            [4; 0x1000]
+        );
+        // Writes to a fixed VHDX do not allocate.
+        assert_eq!(file.metadata().unwrap().len(), data_offset + 4 * MB);
+    }
+
+    #[test]
+    fn corrupt_header() {
+        let file = tempfile::tempfile().unwrap();
+        VhdxDisk::create(&file, 16 * MB, MB as u32).unwrap();
+        // Corrupt the sequence number of the current header. The other copy
+        // is used instead.
+        write_all_at(&file, &[0xff; 8], HEADER_OFFSETS[1] + 8).unwrap();
+        VhdxDisk::open(file.try_clone().unwrap(), true).unwrap();
+        write_all_at(&file, &[0xff; 8], HEADER_OFFSETS[0] + 8).unwrap();
+        assert!(matches!(
+            VhdxDisk::open(file, true),
+            Err(OpenError::InvalidHeader)
+        ));
+    }
+
+    #[test]
+    fn corrupt_bat() {
+        let file = tempfile::tempfile().unwrap();
+        VhdxDisk::create(&file, 16 * MB, MB as u32).unwrap();
+        // A reserved block state.
+        write_all_at(&file, 4u64.as_bytes(), BAT_OFFSET + 8).unwrap();
+        assert!(matches!(
+            VhdxDisk::open(file.try_clone().unwrap(), true),
+            Err(OpenError::InvalidBatEntry { index: 1, .. })
+        ));
+        // A block past the end of the file.
+        let entry = (64 * MB) | PAYLOAD_BLOCK_FULLY_PRESENT;
+        write_all_at(&file, entry.as_bytes(), BAT_OFFSET + 8).unwrap();
+        assert!(matches!(
+            VhdxDisk::open(file, true),
+            Err(OpenError::InvalidBatEntry { index: 1, .. })
+        ));
+    }
+
+    /// Writes a sector of 0xcc to a new VHDX, and then leaks the disk to leave
+    /// the log active and reverts the BAT update, as if the host crashed
+    /// before it reached the disk.
+    async fn write_and_crash(file: &std::fs::File) {
+        VhdxDisk::create(file, 16 * MB, MB as u32).unwrap();
+        let vhdx = Disk::new(VhdxDisk::open(file.try_clone().unwrap(), false).unwrap()).unwrap();
+        let mem = GuestMemory::allocate(0x1000);
+        mem.write_at(0, &[0xcc; 0x1000]).unwrap();
+        vhdx.write_vectored(
+            &OwnedRequestBuffers::linear(0, 0x1000, false).buffer(&mem),
+            0,
+            false,
+        )
+        .await
+        .unwrap();
+        std::mem::forget(vhdx);
+        write_all_at(file, 0u64.as_bytes(), BAT_OFFSET).unwrap();
+    }
+
+    #[async_test]
+    async fn log_replay() {
+        let file = tempfile::tempfile().unwrap();
+        write_and_crash(&file).await;
+        assert!(matches!(
+            VhdxDisk::open(file.try_clone().unwrap(), true),
+            Err(OpenError::LogReplayRequired)
+        ));
+
+        let vhdx = Disk::new(VhdxDisk::open(file.try_clone().unwrap(), false).unwrap()).unwrap();
+        let mem = GuestMemory::allocate(0x1000);
+        assert_eq!(read(&vhdx, &mem, 0, 0x1000).await, [0xcc; 0x1000]);
+        drop(vhdx);
+
+        // The log is marked empty when the disk is closed.
+        VhdxDisk::open(file, true).unwrap();
+    }
+
+    #[async_test]
+    async fn corrupt_log() {
+        let file = tempfile::tempfile().unwrap();
+        write_and_crash(&file).await;
+        write_all_at(&file, &[0xff; 8], LOG_OFFSET + 0x100).unwrap();
+        assert!(matches!(
+            VhdxDisk::open(file, false),
+            Err(OpenError::InvalidLog)
+        ));
+    }
+}
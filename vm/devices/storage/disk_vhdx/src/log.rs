@@ -0,0 +1,424 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The VHDX log, which makes updates to the BAT and metadata regions atomic.
+//!
+//! Each update is written to the log as an entry before it is applied to its
+//! final location. Every entry is applied and flushed before the next one is
+//! written, so each entry is the tail of its own sequence, and only the newest
+//! entry needs to be replayed after a crash. Payload blocks are written
+//! directly, as the spec allows.
+
+use crate::OpenError;
+use crate::format::*;
+use crate::readwriteat::read_exact_at;
+use crate::readwriteat::write_all_at;
+use crate::write_header;
+use guid::Guid;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+use std::fs::File;
+use std::io;
+use zerocopy::FromBytes;
+use zerocopy::IntoBytes;
+
+/// A write to the file made through the log.
+pub enum LogWrite<'a> {
+    /// Write the data at the file offset.
+    Data(u64, &'a [u8]),
+    /// Zero the given number of bytes at the file offset.
+    Zero(u64, u64),
+}
+
+/// An update described by a log entry.
+enum Descriptor {
+    Zero { offset: u64, len: u64 },
+    Data { offset: u64, data: Vec<u8> },
+}
+
+struct Entry {
+    sequence_number: u64,
+    tail: u32,
+    length: u32,
+    flushed_file_offset: u64,
+    last_file_offset: u64,
+    descriptors: Vec<Descriptor>,
+}
+
+/// The state of the log of a writable VHDX.
+pub struct Log {
+    offset: u64,
+    length: u64,
+    /// The current header and the index of the copy holding it.
+    header: Header,
+    header_index: usize,
+    /// The GUID of the entries written by this instance.
+    guid: Guid,
+    /// The offset of the next entry within the log.
+    head: u64,
+    sequence_number: u64,
+}
+
+impl Log {
+    pub fn new(header_index: usize, header: Header) -> Self {
+        Self {
+            offset: header.log_offset,
+            length: header.log_length.into(),
+            header,
+            header_index,
+            guid: Guid::new_random(),
+            head: 0,
+            sequence_number: 1,
+        }
+    }
+
+    /// Writes an updated header to the older header copy, making it current.
+    pub fn update_header(&mut self, file: &File, f: impl FnOnce(&mut Header)) -> io::Result<()> {
+        let mut header = Header {
+            sequence_number: self.header.sequence_number + 1,
+            ..self.header.clone()
+        };
+        f(&mut header);
+        let index = 1 - self.header_index;
+        write_header(file, index, &header)?;
+        file.sync_data()?;
+        self.header = header;
+        self.header_index = index;
+        Ok(())
+    }
+
+    /// Marks the log empty. All entries must have been applied.
+    pub fn close(&mut self, file: &File) -> io::Result<()> {
+        if self.header.log_guid != Guid::ZERO {
+            self.update_header(file, |header| header.log_guid = Guid::ZERO)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `writes` to the log as a single entry and then applies them.
+    ///
+    /// Partially written sectors are read from the file, so the writes must
+    /// not overlap.
+    pub fn write(&mut self, file: &File, writes: &[LogWrite<'_>]) -> io::Result<()> {
+        const SECTOR_SIZE: u64 = LOG_SECTOR_SIZE as u64;
+
+        let mut data_sectors = BTreeMap::<u64, Vec<u8>>::new();
+        let mut zero_ranges = Vec::<(u64, u64)>::new();
+        for write in writes {
+            let (start, len) = match *write {
+                LogWrite::Data(offset, data) => (offset, data.len() as u64),
+                LogWrite::Zero(offset, len) => (offset, len),
+            };
+            let end = start + len;
+            let mut sector = start - start % SECTOR_SIZE;
+            while sector < end {
+                let sector_end = sector + SECTOR_SIZE;
+                let lo = start.max(sector);
+                let hi = end.min(sector_end);
+                if matches!(write, LogWrite::Zero(..))
+                    && lo == sector
+                    && hi == sector_end
+                    && !data_sectors.contains_key(&sector)
+                {
+                    match zero_ranges.last_mut() {
+                        Some((offset, len)) if *offset + *len == sector => *len += SECTOR_SIZE,
+                        _ => zero_ranges.push((sector, SECTOR_SIZE)),
+                    }
+                } else {
+                    let buf = match data_sectors.entry(sector) {
+                        btree_map::Entry::Vacant(entry) => {
+                            let mut buf = vec![0; LOG_SECTOR_SIZE];
+                            read_exact_at(file, &mut buf, sector)?;
+                            entry.insert(buf)
+                        }
+                        btree_map::Entry::Occupied(entry) => entry.into_mut(),
+                    };
+                    let buf = &mut buf[(lo - sector) as usize..(hi - sector) as usize];
+                    match *write {
+                        LogWrite::Data(_, data) => {
+                            buf.copy_from_slice(&data[(lo - start) as usize..(hi - start) as usize])
+                        }
+                        LogWrite::Zero(..) => buf.fill(0),
+                    }
+                }
+                sector = sector_end;
+            }
+        }
+
+        let descriptors = zero_ranges
+            .into_iter()
+            .map(|(offset, len)| Descriptor::Zero { offset, len })
+            .chain(
+                data_sectors
+                    .into_iter()
+                    .map(|(offset, data)| Descriptor::Data { offset, data }),
+            )
+            .collect::<Vec<_>>();
+
+        let entry_len = entry_len(&descriptors) as u64;
+        if entry_len > self.length {
+            return Err(io::Error::other("VHDX log entry does not fit in the log"));
+        }
+        let head = if self.head + entry_len > self.length {
+            0
+        } else {
+            self.head
+        };
+        let entry = self.build_entry(&descriptors, head, file.metadata()?.len());
+        write_all_at(file, &entry, self.offset + head)?;
+        file.sync_data()?;
+        self.head = head + entry.len() as u64;
+        self.sequence_number += 1;
+
+        // The header only needs to reference the log once there is a valid
+        // entry in it.
+        if self.header.log_guid != self.guid {
+            let guid = self.guid;
+            self.update_header(file, |header| header.log_guid = guid)?;
+        }
+
+        apply(file, &descriptors)?;
+        file.sync_data()
+    }
+
+    /// Builds an entry at offset `head` of the log, which is also the entry's
+    /// tail since all previous entries have been applied.
+    fn build_entry(&self, descriptors: &[Descriptor], head: u64, file_len: u64) -> Vec<u8> {
+        let sequence_number = self.sequence_number;
+        let descriptors_len = descriptor_area_len(descriptors.len());
+        let entry_len = entry_len(descriptors);
+
+        let mut entry = vec![0; entry_len];
+        LogEntryHeader {
+            signature: LOG_ENTRY_SIGNATURE,
+            checksum: 0,
+            entry_length: entry_len as u32,
+            tail: head as u32,
+            sequence_number,
+            descriptor_count: descriptors.len() as u32,
+            reserved: 0,
+            log_guid: self.guid,
+            flushed_file_offset: file_len,
+            last_file_offset: file_len,
+        }
+        .write_to_prefix(&mut entry)
+        .unwrap();
+
+        let (descriptor_area, mut data_area) = entry.split_at_mut(descriptors_len);
+        let mut descriptor_area = &mut descriptor_area[size_of::<LogEntryHeader>()..];
+        for descriptor in descriptors {
+            match descriptor {
+                Descriptor::Zero { offset, len } => {
+                    LogZeroDescriptor {
+                        signature: LOG_ZERO_SIGNATURE,
+                        reserved: 0,
+                        zero_length: *len,
+                        file_offset: *offset,
+                        sequence_number,
+                    }
+                    .write_to_prefix(descriptor_area)
+                    .unwrap();
+                }
+                Descriptor::Data { offset, data } => {
+                    LogDataDescriptor {
+                        signature: LOG_DATA_DESCRIPTOR_SIGNATURE,
+                        trailing_bytes: u32::from_le_bytes(
+                            data[LOG_SECTOR_SIZE - 4..].try_into().unwrap(),
+                        ),
+                        leading_bytes: u64::from_le_bytes(data[..8].try_into().unwrap()),
+                        file_offset: *offset,
+                        sequence_number,
+                    }
+                    .write_to_prefix(descriptor_area)
+                    .unwrap();
+                    let (sector, rest) =
+                        std::mem::take(&mut data_area).split_at_mut(LOG_SECTOR_SIZE);
+                    sector[..4].copy_from_slice(LOG_DATA_SECTOR_SIGNATURE.as_bytes());
+                    sector[4..8].copy_from_slice(&((sequence_number >> 32) as u32).to_le_bytes());
+                    sector[8..LOG_SECTOR_SIZE - 4].copy_from_slice(&data[8..LOG_SECTOR_SIZE - 4]);
+                    sector[LOG_SECTOR_SIZE - 4..]
+                        .copy_from_slice(&(sequence_number as u32).to_le_bytes());
+                    data_area = rest;
+                }
+            }
+            descriptor_area = &mut descriptor_area[size_of::<LogDataDescriptor>()..];
+        }
+        let checksum = checksum(&entry);
+        entry[4..8].copy_from_slice(&checksum.to_le_bytes());
+        entry
+    }
+}
+
+/// Returns the length of the sectors holding the entry header and
+/// `descriptor_count` descriptors.
+fn descriptor_area_len(descriptor_count: usize) -> usize {
+    (size_of::<LogEntryHeader>() + descriptor_count * size_of::<LogDataDescriptor>())
+        .next_multiple_of(LOG_SECTOR_SIZE)
+}
+
+fn entry_len(descriptors: &[Descriptor]) -> usize {
+    let data_count = descriptors
+        .iter()
+        .filter(|d| matches!(d, Descriptor::Data { .. }))
+        .count();
+    descriptor_area_len(descriptors.len()) + data_count * LOG_SECTOR_SIZE
+}
+
+fn apply(file: &File, descriptors: &[Descriptor]) -> io::Result<()> {
+    for descriptor in descriptors {
+        match descriptor {
+            Descriptor::Zero { offset, len } => {
+                let mut offset = *offset;
+                let end = offset + len;
+                let zeroes = vec![0; (*len as usize).min(MB as usize)];
+                while offset < end {
+                    let n = zeroes.len().min((end - offset) as usize);
+                    write_all_at(file, &zeroes[..n], offset)?;
+                    offset += n as u64;
+                }
+            }
+            Descriptor::Data { offset, data } => write_all_at(file, data, *offset)?,
+        }
+    }
+    Ok(())
+}
+
+/// Replays the active sequence of the log referenced by `header`.
+pub fn replay(file: &File, header: &Header) -> Result<(), OpenError> {
+    let mut log = vec![0; header.log_length as usize];
+    read_exact_at(file, &mut log, header.log_offset)?;
+    let mut entries = (0..log.len())
+        .step_by(LOG_SECTOR_SIZE)
+        .filter_map(|offset| Some((offset, parse_entry(&log[offset..], header.log_guid)?)))
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|(_, entry)| Reverse(entry.sequence_number));
+    let find = |offset| {
+        entries
+            .iter()
+            .find(|&&(o, _)| o == offset)
+            .map(|(_, entry)| entry)
+    };
+
+    // The active sequence is the one ending with the highest sequence number
+    // whose entries are consecutive from its tail.
+    let sequence = entries
+        .iter()
+        .find_map(|(head, entry)| {
+            let mut sequence = Vec::<&Entry>::new();
+            let mut offset = entry.tail as usize;
+            loop {
+                let next = find(offset)?;
+                if sequence
+                    .last()
+                    .is_some_and(|prev| next.sequence_number != prev.sequence_number + 1)
+                {
+                    return None;
+                }
+                sequence.push(next);
+                if offset == *head {
+                    break Some(sequence);
+                }
+                offset = (offset + next.length as usize) % log.len();
+            }
+        })
+        .ok_or(OpenError::InvalidLog)?;
+
+    let last = sequence.last().unwrap();
+    let file_len = file.metadata()?.len();
+    if file_len < last.flushed_file_offset {
+        return Err(OpenError::InvalidLog);
+    }
+    for entry in &sequence {
+        apply(file, &entry.descriptors)?;
+    }
+    if file_len < last.last_file_offset {
+        file.set_len(last.last_file_offset)?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Parses the log entry at the start of `buf`, returning `None` if there is
+/// no valid entry there.
+fn parse_entry(buf: &[u8], log_guid: Guid) -> Option<Entry> {
+    let header = LogEntryHeader::read_from_prefix(buf).ok()?.0;
+    let len = header.entry_length as usize;
+    if header.signature != LOG_ENTRY_SIGNATURE
+        || header.log_guid != log_guid
+        || len == 0
+        || !len.is_multiple_of(LOG_SECTOR_SIZE)
+        || len > buf.len()
+        || !(header.tail as usize).is_multiple_of(LOG_SECTOR_SIZE)
+    {
+        return None;
+    }
+    let buf = &buf[..len];
+    let descriptors_len = descriptor_area_len(header.descriptor_count as usize);
+    if checksum(buf) != header.checksum || descriptors_len > len {
+        return None;
+    }
+
+    let sequence_number = header.sequence_number;
+    let mut data_sectors = buf[descriptors_len..].chunks_exact(LOG_SECTOR_SIZE);
+    let mut descriptors = Vec::new();
+    for descriptor in buf[size_of::<LogEntryHeader>()..]
+        .chunks_exact(size_of::<LogDataDescriptor>())
+        .take(header.descriptor_count as usize)
+    {
+        let signature = u32::read_from_prefix(descriptor).unwrap().0;
+        if signature == LOG_ZERO_SIGNATURE {
+            let descriptor = LogZeroDescriptor::read_from_bytes(descriptor).unwrap();
+            if descriptor.sequence_number != sequence_number
+                || !descriptor
+                    .file_offset
+                    .is_multiple_of(LOG_SECTOR_SIZE as u64)
+                || !descriptor
+                    .zero_length
+                    .is_multiple_of(LOG_SECTOR_SIZE as u64)
+            {
+                return None;
+            }
+            descriptors.push(Descriptor::Zero {
+                offset: descriptor.file_offset,
+                len: descriptor.zero_length,
+            });
+        } else if signature == LOG_DATA_DESCRIPTOR_SIGNATURE {
+            let descriptor = LogDataDescriptor::read_from_bytes(descriptor).unwrap();
+            let sector = data_sectors.next()?;
+            let field =
+                |offset: usize| u32::from_le_bytes(sector[offset..][..4].try_into().unwrap());
+            if descriptor.sequence_number != sequence_number
+                || !descriptor
+                    .file_offset
+                    .is_multiple_of(LOG_SECTOR_SIZE as u64)
+                || field(0) != LOG_DATA_SECTOR_SIGNATURE
+                || field(4) != (sequence_number >> 32) as u32
+                || field(LOG_SECTOR_SIZE - 4) != sequence_number as u32
+            {
+                return None;
+            }
+            let mut data = sector.to_vec();
+            data[..8].copy_from_slice(descriptor.leading_bytes.as_bytes());
+            data[LOG_SECTOR_SIZE - 4..].copy_from_slice(descriptor.trailing_bytes.as_bytes());
+            descriptors.push(Descriptor::Data {
+                offset: descriptor.file_offset,
+                data,
+            });
+        } else {
+            return None;
+        }
+    }
+    if data_sectors.next().is_some() {
+        return None;
+    }
+
+    Some(Entry {
+        sequence_number,
+        tail: header.tail,
+        length: header.entry_length,
+        flushed_file_offset: header.flushed_file_offset,
+        last_file_offset: header.last_file_offset,
+        descriptors,
+    })
+}
@@ -39,6 +39,10 @@ pub fn new(mem: GuestMemory, nsid: u32, disk: Disk) -> Self {
         }
     }
 
+    pub fn has_volatile_write_cache(&self) -> bool {
+        self.disk.has_volatile_write_cache()
+    }
+
     pub fn identify(&self, buf: &mut [u8]) {
         let id = nvm::IdentifyNamespace::mut_from_prefix(buf).unwrap().0; // TODO: zerocopy: from-prefix (mut_from_prefix): use-rest-of-range (https://github.com/microsoft/openvmm/issues/759)
         let size = self.disk.sector_count();
@@ -609,7 +609,16 @@ fn identify_controller(&self, state: &AdminState) -> spec::IdentifyController {
                 // Namespaces still have to opt in individually via `rescap`.
                 .with_reservations(true),
             vwc: spec::VolatileWriteCache::new()
-                .with_present(true)
+                // The write cache is reported per controller, so keep it
+                // unless every attached namespace is known not to need
+                // flushing.
+                .with_present(
+                    self.namespaces.is_empty()
+                        || self
+                            .namespaces
+                            .values()
+                            .any(|ns| ns.has_volatile_write_cache()),
+                )
                 .with_broadcast_flush_behavior(spec::BroadcastFlushBehavior::NOT_SUPPORTED.0),
             cntrltype: spec::ControllerType::IO_CONTROLLER,
             oacs: spec::OptionalAdminCommandSupport::new()
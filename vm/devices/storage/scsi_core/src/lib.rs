@@ -51,6 +51,20 @@ fn execute_scsi<'a>(
         external_data: &'a RequestBuffers<'a>,
         request: &'a Request,
     ) -> StackFuture<'a, ScsiResult, { ASYNC_SCSI_DISK_STACK_SIZE }>;
+
+    /// Quiesces the device's backing store in preparation for a snapshot.
+    ///
+    /// The default implementation does nothing, which is appropriate for
+    /// devices that do not write to a backing store.
+    fn quiesce(&self) -> StackFuture<'_, anyhow::Result<()>, { ASYNC_SCSI_DISK_STACK_SIZE }> {
+        StackFuture::from(async { Ok(()) })
+    }
+
+    /// Resumes a device previously quiesced with
+    /// [`quiesce`](Self::quiesce).
+    fn thaw(&self) -> StackFuture<'_, anyhow::Result<()>, { ASYNC_SCSI_DISK_STACK_SIZE }> {
+        StackFuture::from(async { Ok(()) })
+    }
 }
 
 /// A SCSI request.
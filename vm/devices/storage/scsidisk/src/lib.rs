@@ -179,7 +179,7 @@ pub fn new(disk: Disk, disk_parameters: DiskParameters) -> Self {
                 physical_sector_size: physical_sector_size
                     .unwrap_or_else(|| disk.physical_sector_size()),
                 support_fua: fua.unwrap_or_else(|| disk.is_fua_respected()),
-                write_cache_enabled: write_cache.unwrap_or(true),
+                write_cache_enabled: write_cache.unwrap_or_else(|| disk.has_volatile_write_cache()),
                 support_odx: odx.unwrap_or(false),
                 support_get_lba_status: get_lba_status,
                 support_unmap: unmap.unwrap_or(disk.unmap_behavior() != UnmapBehavior::Ignored),
@@ -1300,6 +1300,14 @@ fn execute_scsi<'a>(
             self.process_result(result, op)
         })
     }
+
+    fn quiesce(&self) -> StackFuture<'_, anyhow::Result<()>, { ASYNC_SCSI_DISK_STACK_SIZE }> {
+        StackFuture::from_or_box(async move { Ok(self.disk.quiesce().await?) })
+    }
+
+    fn thaw(&self) -> StackFuture<'_, anyhow::Result<()>, { ASYNC_SCSI_DISK_STACK_SIZE }> {
+        StackFuture::from_or_box(async move { Ok(self.disk.thaw().await?) })
+    }
 }
 
 impl Inspect for SimpleScsiDisk {
@@ -3,6 +3,7 @@
 
 //! ScsiDisk basic tests.
 
+use super::test_helpers::TestDisk;
 use super::test_helpers::check_execute_scsi_pass;
 use super::test_helpers::check_guest_memory;
 use super::test_helpers::make_cdb10_request;
@@ -14,6 +15,7 @@
 use super::test_helpers::new_scsi_dvd;
 use crate::SimpleScsiDisk;
 use crate::scsi;
+use disk_backend::Disk;
 use guestmem::GuestMemory;
 use pal_async::async_test;
 use scsi::AdditionalSenseCode;
@@ -27,6 +29,7 @@
 use scsi_core::save_restore::SavedSenseData;
 use scsi_core::save_restore::ScsiDiskSavedState;
 use scsi_core::save_restore::ScsiSavedState;
+use scsidisk_resources::DiskParameters;
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 use zerocopy::IntoBytes;
@@ -188,6 +191,28 @@ fn validate_new_scsi_disk() {
     let _disk = new_scsi_disk(512, 512, 1024, true, false, true);
 }
 
+#[test]
+fn validate_write_cache_from_disk() {
+    let write_cache_enabled = |volatile_write_cache, write_cache| {
+        let (mut disk, _) = TestDisk::new(512, 512, 1024, false, false);
+        disk.volatile_write_cache = volatile_write_cache;
+        let scsi_disk = SimpleScsiDisk::new(
+            Disk::new(disk).unwrap(),
+            DiskParameters {
+                write_cache,
+                ..Default::default()
+            },
+        );
+        scsi_disk.scsi_parameters.write_cache_enabled
+    };
+
+    assert!(write_cache_enabled(true, None));
+    assert!(!write_cache_enabled(false, None));
+    // An explicit setting overrides the disk.
+    assert!(write_cache_enabled(false, Some(true)));
+    assert!(!write_cache_enabled(true, Some(false)));
+}
+
 #[test]
 fn validate_save_restore_scsi_disk_no_change() {
     let (scsi_disk, _state) = new_scsi_disk(512, 4096, 1024, false, false, false);
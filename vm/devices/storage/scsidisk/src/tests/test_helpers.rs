@@ -38,6 +38,7 @@ pub struct TestDisk {
     pub sector_size: u32,
     pub physical_sector_size: u32,
     pub read_only: bool,
+    pub volatile_write_cache: bool,
     pub state: Arc<Mutex<TestDiskStorageState>>,
 }
 
@@ -68,6 +69,7 @@ pub fn new(
             TestDisk {
                 sector_size: logical_sector_size,
                 read_only,
+                volatile_write_cache: true,
                 state: state.clone(),
                 physical_sector_size,
             },
@@ -105,6 +107,10 @@ fn is_fua_respected(&self) -> bool {
         false
     }
 
+    fn has_volatile_write_cache(&self) -> bool {
+        self.volatile_write_cache
+    }
+
     async fn read_vectored(
         &self,
         buffers: &RequestBuffers<'_>,
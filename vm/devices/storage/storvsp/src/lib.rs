@@ -60,6 +60,7 @@
 use std::sync::atomic::Ordering::Relaxed;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Duration;
 use storvsp_resources::ScsiPath;
 use task_control::AsyncRun;
 use task_control::InspectTask;
@@ -79,6 +80,7 @@
 use vmbus_channel::bus::OpenRequest;
 use vmbus_channel::channel::ChannelControl;
 use vmbus_channel::channel::ChannelOpenError;
+use vmbus_channel::channel::DeviceFault;
 use vmbus_channel::channel::DeviceResources;
 use vmbus_channel::channel::RestoreControl;
 use vmbus_channel::channel::SaveRestoreVmbusDevice;
@@ -115,6 +117,8 @@ pub struct StorageDevice {
     max_sub_channel_count: u16,
     protocol: Arc<Protocol>,
     io_queue_depth: u32,
+    queue_affinity: Vec<u32>,
+    interrupt_coalescing: Option<Duration>,
 }
 
 #[derive(Inspect)]
@@ -147,6 +151,10 @@ fn inspect_mut(&mut self, req: inspect::Request<'_>) {
         .field(
             "poll_mode_queue_depth",
             inspect::AtomicMut(&self.controller.poll_mode_queue_depth),
+        )
+        .field(
+            "queue_affinity",
+            inspect::iter_by_index(&self.queue_affinity),
         );
     }
 }
@@ -381,7 +389,20 @@ fn parse_packet<T: RingMem>(
         .transaction_id()
         .ok_or(PacketError::NotTransactional)?;
 
-    let mut reader = packet.reader();
+    // Read the request straight from guest memory when the ring is backed by
+    // it.
+    match packet.read_packet_in_place() {
+        Some(reader) => parse_data_packet(packet, reader, transaction_id, pool),
+        None => parse_data_packet(packet, packet.reader(), transaction_id, pool),
+    }
+}
+
+fn parse_data_packet<T: RingMem>(
+    packet: &queue::DataPacket<'_, T>,
+    mut reader: impl MemoryRead,
+    transaction_id: u64,
+    pool: &mut Vec<Arc<ScsiRequestAndRange>>,
+) -> Result<Packet, PacketError> {
     let header: storvsp_protocol::Packet = reader.read_plain().map_err(PacketError::Access)?;
     // You would expect that this should be limited to the current protocol
     // version's maximum packet size, but this is not what Hyper-V does, and
@@ -840,7 +861,18 @@ async fn run(
 
         match stop.until_stopped(fut).await? {
             Ok(_) => {}
-            Err(e) => tracing::error!(error = e.as_error(), "process_packets error"),
+            Err(e) => {
+                tracing::error!(error = e.as_error(), "process_packets error");
+                // Running out of the ring space reserved for a completion is
+                // a device bug rather than a guest error, so remove the
+                // controller instead of leaving the guest waiting on it.
+                if matches!(e, WorkerError::NotEnoughSpace) {
+                    worker
+                        .inner
+                        .channel_control
+                        .fail(DeviceFault::Unrecoverable(e.into()));
+                }
+            }
         }
         Ok(())
     }
@@ -1434,6 +1466,8 @@ pub fn build_scsi(
         instance_id: Guid,
         max_sub_channel_count: u16,
         io_queue_depth: u32,
+        queue_affinity: Vec<u32>,
+        interrupt_coalescing: Option<Duration>,
     ) -> Self {
         Self::build_inner(
             driver_source,
@@ -1442,6 +1476,8 @@ pub fn build_scsi(
             None,
             max_sub_channel_count,
             io_queue_depth,
+            queue_affinity,
+            interrupt_coalescing,
         )
     }
 
@@ -1478,6 +1514,8 @@ pub fn build_ide(
             Some(path),
             0,
             io_queue_depth,
+            Vec::new(),
+            None,
         )
     }
 
@@ -1488,13 +1526,15 @@ fn build_inner(
         ide_path: Option<ScsiPath>,
         max_sub_channel_count: u16,
         io_queue_depth: u32,
+        queue_affinity: Vec<u32>,
+        interrupt_coalescing: Option<Duration>,
     ) -> Self {
         let workers = (0..max_sub_channel_count + 1)
             .map(|channel_index| WorkerAndDriver {
                 worker: TaskControl::new(WorkerState),
                 driver: driver_source
                     .builder()
-                    .target_vp(0)
+                    .target_vp(worker_vp(&queue_affinity, channel_index, 0))
                     .run_on_target(true)
                     .build(format!("storvsp-{}-{}", instance_id, channel_index)),
             })
@@ -1513,6 +1553,8 @@ fn build_inner(
                 ready: Default::default(),
             }),
             io_queue_depth,
+            queue_affinity,
+            interrupt_coalescing,
         }
     }
 
@@ -1525,7 +1567,11 @@ fn new_worker(
 
         // VMBus doesn't provide a target VP if the channel is not using interrupts. Run on VP 0 in
         // that case.
-        let target_vp = open_request.open_data.target_vp.unwrap_or_default();
+        let target_vp = worker_vp(
+            &self.queue_affinity,
+            channel_index,
+            open_request.open_data.target_vp.unwrap_or_default(),
+        );
         let driver = self
             .driver_source
             .builder()
@@ -1576,6 +1622,16 @@ fn new_worker(
     }
 }
 
+/// Returns the VP on which to process requests for channel `channel_index`,
+/// whose guest-selected target VP is `target_vp`.
+fn worker_vp(queue_affinity: &[u32], channel_index: u16, target_vp: u32) -> u32 {
+    if queue_affinity.is_empty() {
+        target_vp
+    } else {
+        queue_affinity[channel_index as usize % queue_affinity.len()]
+    }
+}
+
 /// A disk that can be added to a SCSI controller.
 #[derive(Clone)]
 pub struct ScsiControllerDisk {
@@ -1643,6 +1699,54 @@ pub fn remove(&self, path: ScsiPath) -> Result<(), ScsiPathNotInUse> {
         }
         Ok(())
     }
+
+    fn disks(&self) -> Vec<(ScsiPath, Arc<dyn AsyncScsiDisk>)> {
+        self.state
+            .disks
+            .read()
+            .iter()
+            .map(|(path, disk)| (*path, disk.disk.clone()))
+            .collect()
+    }
+
+    /// Quiesces the backing stores of all attached devices, in preparation
+    /// for a snapshot.
+    ///
+    /// If any device fails to quiesce, the devices that were already quiesced
+    /// are thawed.
+    pub async fn quiesce(&self) -> anyhow::Result<()> {
+        let disks = self.disks();
+        for (i, (path, disk)) in disks.iter().enumerate() {
+            if let Err(err) = disk.quiesce().await {
+                for (path, disk) in &disks[..i] {
+                    if let Err(err) = disk.thaw().await {
+                        tracing::warn!(
+                            %path,
+                            error = err.as_ref() as &dyn std::error::Error,
+                            "failed to thaw device"
+                        );
+                    }
+                }
+                return Err(err).with_context(|| format!("failed to quiesce device {path}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Resumes all devices quiesced with [`quiesce`](Self::quiesce).
+    ///
+    /// Every device is thawed even if some fail; the first error is returned.
+    pub async fn thaw(&self) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        for (path, disk) in self.disks() {
+            if let Err(err) = disk.thaw().await {
+                if result.is_ok() {
+                    result = Err(err).with_context(|| format!("failed to thaw device {path}"));
+                }
+            }
+        }
+        result
+    }
 }
 
 impl ScsiControllerState {
@@ -1687,6 +1791,7 @@ fn offer(&self) -> OfferParams {
                 interface_name: "scsi".to_owned(),
                 instance_id: self.instance_id,
                 interface_id: storvsp_protocol::SCSI_INTERFACE_ID,
+                interrupt_coalescing: self.interrupt_coalescing,
                 ..Default::default()
             }
         }
@@ -1731,7 +1836,7 @@ async fn close(&mut self, channel_index: u16) {
     async fn retarget_vp(&mut self, channel_index: u16, target_vp: u32) {
         self.workers[channel_index as usize]
             .driver
-            .retarget_vp(target_vp);
+            .retarget_vp(worker_vp(&self.queue_affinity, channel_index, target_vp));
     }
 
     fn start(&mut self) {
@@ -1786,6 +1891,7 @@ mod tests {
     use scsi::srb::SrbStatus;
     use test_with_tracing::test;
     use vmbus_channel::connected_async_channels;
+    use vmcore::vm_task::SingleDriverBackend;
 
     // Discourage `Clone` for `ScsiController` outside the crate, but it is
     // necessary for testing. The fuzzer also uses `TestWorker`, which needs
@@ -2029,6 +2135,38 @@ async fn test_unrecognized_operation(driver: DefaultDriver) {
         }
     }
 
+    #[test]
+    fn test_worker_vp() {
+        assert_eq!(worker_vp(&[], 3, 7), 7);
+        assert_eq!(worker_vp(&[2, 4], 0, 7), 2);
+        assert_eq!(worker_vp(&[2, 4], 3, 7), 4);
+    }
+
+    #[async_test]
+    async fn test_offer_interrupt_coalescing(driver: DefaultDriver) {
+        let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver));
+        let controller = ScsiController::new();
+        let build = |interrupt_coalescing| {
+            StorageDevice::build_scsi(
+                &driver_source,
+                &controller,
+                Guid::new_random(),
+                0,
+                256,
+                Vec::new(),
+                interrupt_coalescing,
+            )
+        };
+
+        assert_eq!(build(None).offer().interrupt_coalescing, None);
+        assert_eq!(
+            build(Some(Duration::from_micros(100)))
+                .offer()
+                .interrupt_coalescing,
+            Some(Duration::from_micros(100))
+        );
+    }
+
     #[async_test]
     async fn test_too_many_subchannels(driver: DefaultDriver) {
         // set up the channels and worker
@@ -69,6 +69,8 @@ async fn resolve(
             resource.instance_id,
             resource.max_sub_channel_count,
             resource.io_queue_depth.unwrap_or(256),
+            resource.queue_affinity,
+            resource.interrupt_coalescing,
         );
 
         for ScsiDeviceAndPath { path, device } in resource.devices {
@@ -143,6 +145,24 @@ async fn handle_requests(
                 }
                 anyhow::Ok(())
             }),
+            ScsiControllerRequest::Quiesce(rpc) => {
+                rpc.handle_failable(async |()| {
+                    if let Some(state) = state.upgrade() {
+                        ScsiController { state }.quiesce().await?;
+                    }
+                    anyhow::Ok(())
+                })
+                .await
+            }
+            ScsiControllerRequest::Thaw(rpc) => {
+                rpc.handle_failable(async |()| {
+                    if let Some(state) = state.upgrade() {
+                        ScsiController { state }.thaw().await?;
+                    }
+                    anyhow::Ok(())
+                })
+                .await
+            }
         }
     }
 }
@@ -9,6 +9,7 @@
 use mesh::MeshPayload;
 use mesh::payload::Protobuf;
 use mesh::rpc::FailableRpc;
+use std::time::Duration;
 use vm_resource::Resource;
 use vm_resource::ResourceId;
 use vm_resource::kind::ScsiDeviceHandleKind;
@@ -54,6 +55,14 @@ pub struct ScsiControllerHandle {
     /// Higher numbers mean that there must be _more_ IOs outstanding to backing storage devices before storvsp
     /// decides to keep interrupts masked.
     pub poll_mode_queue_depth: Option<u32>,
+    /// The VPs on which to process each channel's requests. If non-empty,
+    /// channel N is processed on VP `queue_affinity[N % queue_affinity.len()]`
+    /// instead of the channel's guest-selected target VP.
+    pub queue_affinity: Vec<u32>,
+    /// The minimum interval between guest interrupts on each channel. Signals
+    /// raised within the interval are coalesced into one interrupt. `None`
+    /// delivers every signal immediately.
+    pub interrupt_coalescing: Option<Duration>,
 }
 
 impl ResourceId<VmbusDeviceHandleKind> for ScsiControllerHandle {
@@ -76,4 +85,9 @@ pub enum ScsiControllerRequest {
     AddDevice(FailableRpc<ScsiDeviceAndPath, ()>),
     /// Remove a device.
     RemoveDevice(FailableRpc<ScsiPath, ()>),
+    /// Quiesce the backing stores of all devices, in preparation for a
+    /// snapshot.
+    Quiesce(FailableRpc<(), ()>),
+    /// Resume all devices after a previous `Quiesce`.
+    Thaw(FailableRpc<(), ()>),
 }
@@ -29,22 +29,58 @@
 
 pub struct Plan9FileSystem {
     negotiated_size: AtomicU32,
+    maximum_size: u32,
     fids: RwLock<HashMap<u32, Arc<dyn Fid>>>,
-    root: Arc<LxVolume>,
+    // The export attached when the client's aname does not match a named export.
+    root: Option<Arc<LxVolume>>,
+    exports: HashMap<String, Arc<LxVolume>>,
     debug: bool,
 }
 
 impl Plan9FileSystem {
     pub fn new(root_path: &str, debug: bool) -> lx::Result<Plan9FileSystem> {
-        let root = Arc::new(LxVolume::new(root_path)?);
+        Self::with_exports([("", root_path)], MAXIMUM_REQUEST_BUFFER_SIZE, debug)
+    }
+
+    // Create a file system that serves multiple host directories, selected by the aname the
+    // client passes to Tattach. An export with an empty name is attached for any aname that does
+    // not match another export.
+    //
+    // The negotiated message size is limited to `maximum_size`, for transports that cannot carry
+    // larger messages.
+    pub fn with_exports<'a>(
+        exports: impl IntoIterator<Item = (&'a str, &'a str)>,
+        maximum_size: u32,
+        debug: bool,
+    ) -> lx::Result<Plan9FileSystem> {
+        let mut root = None;
+        let mut volumes = HashMap::new();
+        for (name, path) in exports {
+            let volume = Arc::new(LxVolume::new(path)?);
+            if name.is_empty() {
+                root = Some(volume);
+            } else {
+                volumes.insert(name.to_owned(), volume);
+            }
+        }
+
         Ok(Plan9FileSystem {
             negotiated_size: AtomicU32::new(0),
+            maximum_size: maximum_size
+                .clamp(MINIMUM_REQUEST_BUFFER_SIZE, MAXIMUM_REQUEST_BUFFER_SIZE),
             fids: RwLock::new(HashMap::new()),
             root,
+            exports: volumes,
             debug,
         })
     }
 
+    // Drop all fids and the negotiated size, for when a new client connects.
+    pub fn reset(&self) {
+        self.fids.write().clear();
+        self.negotiated_size.store(0, Ordering::SeqCst);
+    }
+
     // Process a message received from virtio.
     pub fn process_message(&self, message: &[u8], response: &mut [u8]) -> lx::Result<usize> {
         let mut reader = SliceReader::new(message);
@@ -129,7 +165,7 @@ pub fn handle_version(
             return Err(Error::ENOTSUP);
         }
 
-        let negotiated_size = std::cmp::min(message.msize, MAXIMUM_REQUEST_BUFFER_SIZE);
+        let negotiated_size = std::cmp::min(message.msize, self.maximum_size);
 
         // Renegotiation is allowed, particularly because this implementation doesn't really use
         // the size for anything since it's virtio only, but still prevent multiple changes at once.
@@ -152,8 +188,15 @@ pub fn handle_attach(
         message: Tattach<'_>,
         response: &mut SliceWriter<'_>,
     ) -> lx::Result<()> {
-        // Create the fid for the root.
-        let (file, qid) = File::new(Arc::clone(&self.root), message.n_uname)?;
+        // Create the fid for the root of the requested export.
+        let root = message
+            .aname
+            .to_str()
+            .and_then(|name| self.exports.get(name))
+            .or(self.root.as_ref())
+            .ok_or(Error::ENOENT)?;
+
+        let (file, qid) = File::new(Arc::clone(root), message.n_uname)?;
         self.emplace_fid(message.fid, Arc::new(file))?;
         response.qid(&qid)?;
         Ok(())
@@ -336,3 +379,126 @@ fn log_response(response: &[u8]) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE_TATTACH: u8 = 104;
+    const MESSAGE_RATTACH: u8 = 105;
+    const MESSAGE_TWALK: u8 = 110;
+    const MESSAGE_RWALK: u8 = 111;
+    const NOFID: u32 = !0;
+
+    // Sends a message built by `body` and returns the response type and body.
+    fn send(
+        fs: &Plan9FileSystem,
+        message_type: u8,
+        body: impl FnOnce(&mut SliceWriter<'_>) -> lx::Result<()>,
+    ) -> (u8, Vec<u8>) {
+        let mut message = vec![0; 256];
+        let mut writer = SliceWriter::new(&mut message);
+        body(&mut writer).unwrap();
+        writer.header(message_type, 1).unwrap();
+        let size = writer.size();
+        let mut response = vec![0; 256];
+        let size = fs.process_message(&message[..size], &mut response).unwrap();
+        let header = SliceReader::new(&response[..size]).header().unwrap();
+        (header.message_type, response[HEADER_SIZE..size].to_vec())
+    }
+
+    fn attach(fs: &Plan9FileSystem, fid: u32, aname: &str) -> lx::Result<()> {
+        let (message_type, body) = send(fs, MESSAGE_TATTACH, |w| {
+            w.u32(fid)?;
+            w.u32(NOFID)?;
+            w.string(lx::LxStr::from_bytes(b"root"))?;
+            w.string(lx::LxStr::from_bytes(aname.as_bytes()))?;
+            w.u32(0)
+        });
+        check(message_type, MESSAGE_RATTACH, &body)
+    }
+
+    fn walk(fs: &Plan9FileSystem, fid: u32, newfid: u32, name: &str) -> lx::Result<()> {
+        let (message_type, body) = send(fs, MESSAGE_TWALK, |w| {
+            w.u32(fid)?;
+            w.u32(newfid)?;
+            w.u16(1)?;
+            w.string(lx::LxStr::from_bytes(name.as_bytes()))
+        });
+        check(message_type, MESSAGE_RWALK, &body)
+    }
+
+    fn check(message_type: u8, expected: u8, body: &[u8]) -> lx::Result<()> {
+        if message_type == MESSAGE_RLERROR {
+            let errno = u32::from_le_bytes(body[..4].try_into().unwrap());
+            return Err(Error::from_lx(errno as i32));
+        }
+        assert_eq!(message_type, expected);
+        Ok(())
+    }
+
+    // Creates an export directory containing a file with the given name.
+    fn export_dir(file_name: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(file_name), b"").unwrap();
+        dir
+    }
+
+    #[test]
+    fn attach_selects_export_by_aname() {
+        let a = export_dir("in_a");
+        let b = export_dir("in_b");
+        let fs = Plan9FileSystem::with_exports(
+            [
+                ("a", a.path().to_str().unwrap()),
+                ("b", b.path().to_str().unwrap()),
+            ],
+            MAXIMUM_REQUEST_BUFFER_SIZE,
+            false,
+        )
+        .unwrap();
+
+        attach(&fs, 1, "a").unwrap();
+        attach(&fs, 2, "b").unwrap();
+        walk(&fs, 1, 3, "in_a").unwrap();
+        walk(&fs, 2, 4, "in_b").unwrap();
+        assert_eq!(walk(&fs, 1, 5, "in_b"), Err(Error::ENOENT));
+        assert_eq!(walk(&fs, 2, 5, "in_a"), Err(Error::ENOENT));
+    }
+
+    #[test]
+    fn attach_unknown_aname_fails_without_default() {
+        let a = export_dir("in_a");
+        let fs = Plan9FileSystem::with_exports(
+            [("a", a.path().to_str().unwrap())],
+            MAXIMUM_REQUEST_BUFFER_SIZE,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(attach(&fs, 1, "b"), Err(Error::ENOENT));
+        assert_eq!(attach(&fs, 1, ""), Err(Error::ENOENT));
+    }
+
+    #[test]
+    fn attach_unknown_aname_uses_default() {
+        let a = export_dir("in_a");
+        let root = export_dir("in_root");
+        let fs = Plan9FileSystem::with_exports(
+            [
+                ("", root.path().to_str().unwrap()),
+                ("a", a.path().to_str().unwrap()),
+            ],
+            MAXIMUM_REQUEST_BUFFER_SIZE,
+            false,
+        )
+        .unwrap();
+
+        attach(&fs, 1, "b").unwrap();
+        attach(&fs, 2, "").unwrap();
+        walk(&fs, 1, 3, "in_root").unwrap();
+        walk(&fs, 2, 4, "in_root").unwrap();
+        attach(&fs, 5, "a").unwrap();
+        walk(&fs, 5, 6, "in_a").unwrap();
+    }
+}
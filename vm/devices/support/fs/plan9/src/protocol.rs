@@ -8,7 +8,7 @@
 mod macros;
 
 pub const PROTOCOL_VERSION: &str = "9P2000.L";
-const HEADER_SIZE: usize = 7;
+pub const HEADER_SIZE: usize = 7;
 const QID_SIZE: usize = 13;
 
 // These messages use the format:
@@ -12,6 +12,7 @@
 use std::io::IoSlice;
 use task_control::StopTask;
 use thiserror::Error;
+use video_core::DirtyRect;
 use video_core::FramebufferControl;
 use video_core::FramebufferFormat;
 use vmbus_async::async_dgram::AsyncRecv;
@@ -149,22 +150,112 @@ fn parse_packet(buf: &[u8]) -> Result<Request, Error> {
     Ok(request)
 }
 
+/// The size of the VRAM region the guest can map.
+const VRAM_SIZE: usize = 8 * 1024 * 1024;
+
+/// The bytes per pixel of the only supported depth.
+const BYTES_PER_PIXEL: usize = 4;
+
+/// Standard resolutions reported to the guest, in order of preference for
+/// the guest's mode list. Resolutions that do not fit in VRAM are not
+/// reported.
+const STANDARD_RESOLUTIONS: &[(u16, u16)] = &[
+    (640, 480),
+    (800, 600),
+    (1024, 768),
+    (1152, 864),
+    (1280, 720),
+    (1280, 768),
+    (1280, 800),
+    (1280, 960),
+    (1280, 1024),
+    (1360, 768),
+    (1366, 768),
+    (1400, 1050),
+    (1440, 900),
+    (1600, 900),
+    (1600, 1200),
+    (1680, 1050),
+    (1920, 1080),
+    (1920, 1200),
+    (2560, 1440),
+    (2560, 1600),
+];
+
+const DEFAULT_RESOLUTION: (u16, u16) = (1024, 768);
+
+fn fits_in_vram(width: usize, height: usize) -> bool {
+    width * height * BYTES_PER_PIXEL <= VRAM_SIZE
+}
+
+/// Returns the framebuffer format for a guest-requested mode, or `None` if the
+/// mode is not supported.
+///
+/// Any mode is accepted, including ones not in [`STANDARD_RESOLUTIONS`], as
+/// long as it fits in VRAM.
+fn situation_format(situation: &protocol::VideoOutputSituation) -> Option<FramebufferFormat> {
+    let width = u32::from(situation.width_pixels) as usize;
+    let height = u32::from(situation.height_pixels) as usize;
+    let bytes_per_line = u32::from(situation.pitch_bytes) as usize;
+    let offset = u32::from(situation.primary_surface_vram_offset) as usize;
+    if width == 0 || height == 0 || situation.depth_bits as usize != BYTES_PER_PIXEL * 8 {
+        return None;
+    }
+    if bytes_per_line < width * BYTES_PER_PIXEL {
+        return None;
+    }
+    let end = bytes_per_line
+        .checked_mul(height)
+        .and_then(|len| len.checked_add(offset))?;
+    (end <= VRAM_SIZE).then_some(FramebufferFormat {
+        width,
+        height,
+        bytes_per_line,
+        offset,
+    })
+}
+
 /// Vmbus synthetic video device.
 pub struct Video {
+    display: Display,
+}
+
+/// The framebuffer state set up by the guest, which persists across channel
+/// opens.
+struct Display {
     control: Box<dyn FramebufferControl>,
+    vram_gpa: Option<u64>,
+    format: Option<FramebufferFormat>,
+    /// The format was restored and has not yet been applied to the
+    /// framebuffer.
+    restored_format: bool,
 }
 
 impl Video {
     /// Creates a new video device.
     pub fn new(control: Box<dyn FramebufferControl>) -> anyhow::Result<Self> {
-        Ok(Self { control })
+        Ok(Self {
+            display: Display {
+                control,
+                vram_gpa: None,
+                format: None,
+                restored_format: false,
+            },
+        })
     }
 }
 
 /// The video device saved state.
 #[derive(Protobuf, SavedStateRoot)]
 #[mesh(package = "ui.synthvid")]
-pub struct SavedState(ChannelState);
+pub struct SavedState {
+    #[mesh(1)]
+    state: ChannelState,
+    #[mesh(2)]
+    vram_gpa: Option<u64>,
+    #[mesh(3)]
+    format: Option<FramebufferFormat>,
+}
 
 /// The video task.
 pub struct VideoChannel {
@@ -287,7 +378,7 @@ fn offer(&self) -> OfferParams {
                 data3: 0x4dce,
                 data4: [0xae, 0xb7, 0x52, 0xc, 0x7e, 0xf7, 0x61, 0x71],
             },
-            mmio_megabytes: 8,
+            mmio_megabytes: (VRAM_SIZE >> 20) as u16,
             channel_type: ChannelType::Device { pipe_packets: true },
             ..Default::default()
         }
@@ -295,6 +386,8 @@ fn offer(&self) -> OfferParams {
 
     fn inspect(&mut self, req: inspect::Request<'_>, task: Option<&mut VideoChannel>) {
         let mut resp = req.respond();
+        resp.field("vram_gpa", self.display.vram_gpa.map(inspect::AsHex))
+            .field("format", self.display.format);
         if let Some(this) = task {
             let (version, state) = match &this.state {
                 ChannelState::ReadVersion => (None, "read_version"),
@@ -337,7 +430,7 @@ async fn run(
         channel: &mut VideoChannel,
     ) -> Result<(), task_control::Cancelled> {
         stop.until_stopped(async {
-            match channel.process(&mut self.control).await {
+            match channel.process(&mut self.display).await {
                 Ok(()) => {}
                 Err(err) => tracing::error!(error = &err as &dyn std::error::Error, "video error"),
             }
@@ -356,7 +449,11 @@ fn supports_save_restore(
 
 impl SaveRestoreSimpleVmbusDevice for Video {
     fn save_open(&mut self, runner: &Self::Runner) -> Self::SavedState {
-        SavedState(runner.state.clone())
+        SavedState {
+            state: runner.state.clone(),
+            vram_gpa: self.display.vram_gpa,
+            format: self.display.format,
+        }
     }
 
     fn restore_open(
@@ -364,8 +461,18 @@ fn restore_open(
         state: Self::SavedState,
         channel: RawAsyncChannel<GpadlRingMem>,
     ) -> Result<Self::Runner, ChannelOpenError> {
+        let SavedState {
+            state,
+            vram_gpa,
+            format,
+        } = state;
+        // The framebuffer keeps its own mapping across save/restore, so only
+        // the format needs to be reapplied.
+        self.display.vram_gpa = vram_gpa;
+        self.display.format = format;
+        self.display.restored_format = format.is_some();
         let pipe = MessagePipe::new(channel)?;
-        Ok(VideoChannel::new(pipe, state.0))
+        Ok(VideoChannel::new(pipe, state))
     }
 }
 
@@ -398,10 +505,13 @@ async fn send_packet<T: IntoBytes + ?Sized + Immutable + KnownLayout>(
         Ok(())
     }
 
-    async fn process(
-        &mut self,
-        framebuffer: &mut Box<dyn FramebufferControl>,
-    ) -> Result<(), Error> {
+    async fn process(&mut self, display: &mut Display) -> Result<(), Error> {
+        if std::mem::take(&mut display.restored_format) {
+            if let Some(format) = display.format {
+                display.control.set_format(format).await;
+            }
+        }
+        let framebuffer = &mut display.control;
         let mut channel = &mut self.channel;
         loop {
             match &mut self.state {
@@ -459,6 +569,7 @@ async fn process(
                                     address,
                                 } => {
                                     framebuffer.unmap().await;
+                                    display.vram_gpa = address;
                                     if let Some(address) = address {
                                         // N.B. The mapping is preserved until explicitly torn
                                         //      down--UEFI may open the channel, establish the
@@ -472,16 +583,18 @@ async fn process(
                                     user_context,
                                     situation,
                                 } => {
-                                    framebuffer
-                                        .set_format(FramebufferFormat {
-                                            width: u32::from(situation.width_pixels) as usize,
-                                            height: u32::from(situation.height_pixels) as usize,
-                                            bytes_per_line: u32::from(situation.pitch_bytes)
-                                                as usize,
-                                            offset: u32::from(situation.primary_surface_vram_offset)
-                                                as usize,
-                                        })
-                                        .await;
+                                    if let Some(format) = situation_format(&situation) {
+                                        framebuffer.set_format(format).await;
+                                        display.format = Some(format);
+                                    } else {
+                                        tracelimit::warn_ratelimited!(
+                                            width = u32::from(situation.width_pixels),
+                                            height = u32::from(situation.height_pixels),
+                                            pitch = u32::from(situation.pitch_bytes),
+                                            depth = situation.depth_bits,
+                                            "unsupported video mode"
+                                        );
+                                    }
                                     *substate =
                                         ActiveState::SendSituationUpdateAck { user_context };
                                 }
@@ -489,8 +602,20 @@ async fn process(
                                     let _ = (is_visible, x, y);
                                 }
                                 Request::PointerShape => {}
-                                Request::Dirt(_rects) => {
-                                    // TODO: Support dirt requests
+                                Request::Dirt(rects) => {
+                                    let rects = rects
+                                        .iter()
+                                        .map(|rect| DirtyRect {
+                                            left: i32::from(rect.left).max(0) as u32,
+                                            top: i32::from(rect.top).max(0) as u32,
+                                            right: i32::from(rect.right).max(0) as u32,
+                                            bottom: i32::from(rect.bottom).max(0) as u32,
+                                        })
+                                        .filter(|rect| !rect.is_empty())
+                                        .collect::<Vec<_>>();
+                                    if !rects.is_empty() {
+                                        framebuffer.dirty(rects).await;
+                                    }
                                 }
                                 Request::BiosInfo => {
                                     *substate = ActiveState::SendBiosInfo;
@@ -540,47 +665,47 @@ async fn process(
                             *substate = ActiveState::ReadRequest;
                         }
                         ActiveState::SendSupportedResolutions { maximum_count } => {
-                            if maximum_count < protocol::MAXIMUM_RESOLUTIONS_COUNT {
-                                Self::send_packet(
-                                    &mut channel,
-                                    protocol::MESSAGE_SUPPORTED_RESOLUTIONS_RESPONSE,
-                                    &protocol::SupportedResolutionsResponseMessage {
-                                        edid_block: protocol::EDID_BLOCK,
-                                        resolution_count: 0,
-                                        default_resolution_index: 0,
-                                        is_standard: 0,
-                                    },
+                            let resolutions = STANDARD_RESOLUTIONS
+                                .iter()
+                                .filter(|&&(w, h)| fits_in_vram(w.into(), h.into()))
+                                .take(
+                                    maximum_count
+                                        .min(protocol::MAXIMUM_RESOLUTIONS_COUNT)
+                                        .into(),
                                 )
-                                .await?;
-                            } else {
-                                const RESOLUTIONS: &[(u16, u16)] = &[(1024, 768), (1280, 1024)];
+                                .collect::<Vec<_>>();
+                            let default_resolution_index = resolutions
+                                .iter()
+                                .position(|&&r| r == DEFAULT_RESOLUTION)
+                                .unwrap_or(0);
 
-                                let mut packet = Vec::new();
+                            let mut packet = Vec::new();
+                            packet.extend_from_slice(
+                                protocol::SupportedResolutionsResponseMessage {
+                                    edid_block: protocol::EDID_BLOCK,
+                                    resolution_count: resolutions.len().try_into().unwrap(),
+                                    default_resolution_index: default_resolution_index
+                                        .try_into()
+                                        .unwrap(),
+                                    is_standard: 0,
+                                }
+                                .as_bytes(),
+                            );
+                            for r in resolutions {
                                 packet.extend_from_slice(
-                                    protocol::SupportedResolutionsResponseMessage {
-                                        edid_block: protocol::EDID_BLOCK,
-                                        resolution_count: RESOLUTIONS.len().try_into().unwrap(),
-                                        default_resolution_index: 0,
-                                        is_standard: 0,
+                                    protocol::ScreenInfo {
+                                        width: r.0.into(),
+                                        height: r.1.into(),
                                     }
                                     .as_bytes(),
                                 );
-                                for r in RESOLUTIONS {
-                                    packet.extend_from_slice(
-                                        protocol::ScreenInfo {
-                                            width: r.0.into(),
-                                            height: r.1.into(),
-                                        }
-                                        .as_bytes(),
-                                    );
-                                }
-                                Self::send_packet(
-                                    &mut channel,
-                                    protocol::MESSAGE_SUPPORTED_RESOLUTIONS_RESPONSE,
-                                    packet.as_slice(),
-                                )
-                                .await?;
                             }
+                            Self::send_packet(
+                                &mut channel,
+                                protocol::MESSAGE_SUPPORTED_RESOLUTIONS_RESPONSE,
+                                packet.as_slice(),
+                            )
+                            .await?;
                             *substate = ActiveState::ReadRequest;
                         }
                         ActiveState::SendCapability => {
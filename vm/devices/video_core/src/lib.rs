@@ -51,6 +51,43 @@ pub struct FramebufferFormat {
     pub offset: usize,
 }
 
+/// A rectangle of the framebuffer that the guest has updated, in pixels.
+///
+/// `right` and `bottom` are exclusive.
+#[derive(Debug, Copy, Clone, Protobuf, PartialEq, Eq, Inspect)]
+#[mesh(package = "framebuffer")]
+pub struct DirtyRect {
+    /// The leftmost column.
+    #[mesh(1)]
+    pub left: u32,
+    /// The topmost row.
+    #[mesh(2)]
+    pub top: u32,
+    /// One past the rightmost column.
+    #[mesh(3)]
+    pub right: u32,
+    /// One past the bottommost row.
+    #[mesh(4)]
+    pub bottom: u32,
+}
+
+impl DirtyRect {
+    /// Returns whether the rectangle covers no pixels.
+    pub fn is_empty(&self) -> bool {
+        self.left >= self.right || self.top >= self.bottom
+    }
+
+    /// Returns the smallest rectangle containing both `self` and `other`.
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            left: self.left.min(other.left),
+            top: self.top.min(other.top),
+            right: self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+}
+
 /// Functions necessary to control the framebuffer from a video device.
 ///
 /// This trait needs to be async so that an implementation of these functions can be async.
@@ -65,4 +102,6 @@ pub trait FramebufferControl: Send {
     async fn unmap(&mut self);
     /// Updates the framebuffer format.
     async fn set_format(&mut self, format: FramebufferFormat);
+    /// Reports regions of the framebuffer that the guest has updated.
+    async fn dirty(&mut self, rects: Vec<DirtyRect>);
 }
@@ -456,4 +456,7 @@ pub struct Resources {
     pub queues: Vec<QueueResources>,
     pub shared_memory_region: Option<Arc<dyn MappedMemoryRegion>>,
     pub shared_memory_size: u64,
+    /// Signals a device configuration change to the guest, bumping the
+    /// config generation and raising the config-change interrupt.
+    pub config_change: Interrupt,
 }
@@ -20,6 +20,8 @@
 use std::fmt;
 use std::ops::RangeInclusive;
 use std::sync::Arc;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
 use vmcore::device_state::ChangeDeviceState;
 use vmcore::interrupt::Interrupt;
 use vmcore::line_interrupt::LineInterrupt;
@@ -43,7 +45,7 @@ pub struct VirtioMmioDevice {
     events: Vec<pal_event::Event>,
     queues: Vec<QueueParams>,
     device_status: VirtioDeviceStatus,
-    config_generation: u32,
+    config_generation: Arc<AtomicU32>,
     doorbells: VirtioDoorbells,
     interrupt_state: Arc<Mutex<InterruptState>>,
 }
@@ -123,14 +125,14 @@ pub fn new(
             events,
             queues,
             device_status: VirtioDeviceStatus::new(),
-            config_generation: 0,
+            config_generation: Arc::new(AtomicU32::new(0)),
             doorbells: VirtioDoorbells::new(doorbell_registration),
             interrupt_state,
         }
     }
 
     fn update_config_generation(&mut self) {
-        self.config_generation = self.config_generation.wrapping_add(1);
+        self.config_generation.fetch_add(1, Ordering::Relaxed);
         if self.device_status.driver_ok() {
             self.interrupt_state
                 .lock()
@@ -293,7 +295,7 @@ pub(crate) fn read_u32(&self, address: u64) -> u32 {
                     0
                 }
             }
-            0xfc => self.config_generation,
+            0xfc => self.config_generation.load(Ordering::Relaxed),
             offset if offset >= 0x100 => self.device.read_registers_u32(offset - 0x100),
             _ => 0xffffffff,
         }
@@ -356,7 +358,7 @@ pub(crate) fn write_u32(&mut self, address: u64, val: u32) {
                 if val == 0 {
                     let started = self.device_status.driver_ok();
                     self.device_status = VirtioDeviceStatus::new();
-                    self.config_generation = 0;
+                    self.config_generation.store(0, Ordering::Relaxed);
                     if started {
                         self.doorbells.clear();
                         self.device.disable();
@@ -409,11 +411,23 @@ pub(crate) fn write_u32(&mut self, address: u64, val: u32) {
                         })
                         .collect();
 
+                    let config_change = {
+                        let config_generation = self.config_generation.clone();
+                        let interrupt_state = self.interrupt_state.clone();
+                        Interrupt::from_fn(move || {
+                            config_generation.fetch_add(1, Ordering::Relaxed);
+                            interrupt_state
+                                .lock()
+                                .update(true, VIRTIO_MMIO_INTERRUPT_STATUS_CONFIG_CHANGE);
+                        })
+                    };
+
                     self.device.enable(Resources {
                         features: self.driver_feature.clone(),
                         queues,
                         shared_memory_region: None,
                         shared_memory_size: 0,
+                        config_change,
                     });
 
                     self.device_status.set_driver_ok(true);
@@ -40,6 +40,8 @@
 use pci_core::spec::hwid::Subclass;
 use std::io;
 use std::sync::Arc;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
 use vmcore::device_state::ChangeDeviceState;
 use vmcore::interrupt::Interrupt;
 use vmcore::line_interrupt::LineInterrupt;
@@ -84,7 +86,8 @@ pub struct VirtioPciDevice {
     interrupt_status: Arc<Mutex<u32>>,
     #[inspect(hex)]
     device_status: VirtioDeviceStatus,
-    config_generation: u32,
+    #[inspect(with = "|x| x.load(Ordering::Relaxed)")]
+    config_generation: Arc<AtomicU32>,
     config_space: ConfigSpaceType0Emulator,
 
     #[inspect(skip)]
@@ -233,7 +236,7 @@ pub fn new(
             msix_vectors,
             interrupt_status: Arc::new(Mutex::new(0)),
             device_status: VirtioDeviceStatus::new(),
-            config_generation: 0,
+            config_generation: Arc::new(AtomicU32::new(0)),
             interrupt_kind,
             config_space,
             doorbells: VirtioDoorbells::new(doorbell_registration),
@@ -243,7 +246,7 @@ pub fn new(
     }
 
     fn update_config_generation(&mut self) {
-        self.config_generation = self.config_generation.wrapping_add(1);
+        self.config_generation.fetch_add(1, Ordering::Relaxed);
         if self.device_status.driver_ok() {
             *self.interrupt_status.lock() |= 2;
             match &self.interrupt_kind {
@@ -277,7 +280,9 @@ fn read_u32(&mut self, offset: u16) -> u32 {
             }
             16 => (self.queues.len() as u32) << 16 | self.msix_config_vector as u32,
             20 => {
-                self.queue_select << 24 | self.config_generation << 8 | self.device_status.as_u32()
+                self.queue_select << 24
+                    | self.config_generation.load(Ordering::Relaxed) << 8
+                    | self.device_status.as_u32()
             }
             24 => {
                 let size = if queue_select < self.queues.len() {
@@ -402,7 +407,7 @@ fn write_u32(&mut self, address: u64, offset: u16, val: u32) {
                 if val == 0 {
                     let started = self.device_status.driver_ok();
                     self.device_status = VirtioDeviceStatus::new();
-                    self.config_generation = 0;
+                    self.config_generation.store(0, Ordering::Relaxed);
                     if started {
                         self.doorbells.clear();
                         self.device.disable();
@@ -469,11 +474,31 @@ fn write_u32(&mut self, address: u64, offset: u16, val: u32) {
                         })
                         .collect();
 
+                    let config_change = {
+                        let config_generation = self.config_generation.clone();
+                        let interrupt_status = self.interrupt_status.clone();
+                        let interrupt = match &self.interrupt_kind {
+                            InterruptKind::Msix(msix) => msix
+                                .interrupt(self.msix_config_vector)
+                                .unwrap_or_else(Interrupt::null),
+                            InterruptKind::IntX(line) => {
+                                let line = line.clone();
+                                Interrupt::from_fn(move || line.set_level(true))
+                            }
+                        };
+                        Interrupt::from_fn(move || {
+                            config_generation.fetch_add(1, Ordering::Relaxed);
+                            *interrupt_status.lock() |= 2;
+                            interrupt.deliver();
+                        })
+                    };
+
                     self.device.enable(Resources {
                         features: self.driver_feature.clone(),
                         queues,
                         shared_memory_region: self.shared_memory_region.clone(),
                         shared_memory_size: self.shared_memory_size,
+                        config_change,
                     });
 
                     self.device_status.set_driver_ok(true);
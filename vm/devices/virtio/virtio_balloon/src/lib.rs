@@ -0,0 +1,516 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio memory balloon device implementation.
+//!
+//! The host sets a target balloon size, and the guest inflates the balloon by
+//! handing pages back to the host until it reaches the target. Inflated pages
+//! and pages the guest reports as free are returned to the host via the
+//! partition's [`MemoryReclaim`] hook; the guest gets zeroed memory back the
+//! next time it touches them.
+//!
+//! The device supports the statistics queue and free page reporting. It does
+//! not offer free page hinting, which is meant to speed up live migration
+//! rather than to reclaim memory.
+
+#![expect(missing_docs)]
+#![forbid(unsafe_code)]
+
+pub mod resolver;
+
+use bitfield_struct::bitfield;
+use futures::FutureExt;
+use futures::StreamExt;
+use futures_concurrency::future::Race;
+use guestmem::GuestMemory;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use pal_async::wait::PolledWait;
+use parking_lot::Mutex;
+use std::future::pending;
+use std::sync::Arc;
+use virtio::DeviceTraits;
+use virtio::DeviceTraitsSharedMemory;
+use virtio::QueueResources;
+use virtio::Resources;
+use virtio::VirtioDevice;
+use virtio::VirtioQueue;
+use virtio::VirtioQueueCallbackWork;
+use virtio::spec::VirtioDeviceFeatures;
+use virtio_resources::balloon::BalloonRpc;
+use virtio_resources::balloon::BalloonStat;
+use virtio_resources::balloon::BalloonStatus;
+use vmcore::interrupt::Interrupt;
+use vmcore::memory_reclaim::MemoryReclaim;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+
+/// The balloon protocol always uses 4KB pages, regardless of the guest's page
+/// size.
+const PAGE_SIZE: u64 = 4096;
+
+/// The size of a `virtio_balloon_stat` entry: a 16-bit tag followed by a
+/// 64-bit value, packed.
+const STAT_SIZE: usize = 10;
+
+/// The largest statistics buffer the device will read.
+const MAX_STATS_BUFFER: usize = 64 * STAT_SIZE;
+
+/// The largest inflate or deflate buffer the device will read.
+const MAX_PFN_BUFFER: usize = 0x10000;
+
+#[bitfield(u32)]
+struct BalloonFeaturesBank0 {
+    pub must_tell_host: bool,
+    pub stats_vq: bool,
+    pub deflate_on_oom: bool,
+    pub free_page_hint: bool,
+    pub page_poison: bool,
+    pub reporting: bool,
+    #[bits(26)]
+    _reserved: u32,
+}
+
+/// Device configuration registers.
+#[derive(Default)]
+struct BalloonConfig {
+    /// The number of pages the host wants in the balloon.
+    num_pages: u32,
+    /// The number of pages the guest has placed in the balloon.
+    actual: u32,
+}
+
+pub struct Device {
+    driver: VmTaskDriver,
+    memory: GuestMemory,
+    deflate_on_oom: bool,
+    config: Arc<Mutex<BalloonConfig>>,
+    send: mesh::Sender<WorkerMessage>,
+    _task: Task<()>,
+}
+
+impl Device {
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        memory: GuestMemory,
+        reclaim: MemoryReclaim,
+        deflate_on_oom: bool,
+        recv: mesh::Receiver<BalloonRpc>,
+    ) -> Self {
+        let driver = driver_source.simple();
+        let config = Arc::new(Mutex::new(BalloonConfig::default()));
+        let (send, messages) = mesh::channel();
+        let worker = Worker {
+            mem: memory.clone(),
+            reclaim,
+            config: config.clone(),
+            rpc: Some(recv),
+            messages,
+            queues: None,
+            stats: Vec::new(),
+        };
+        let task = driver.spawn("virtio-balloon", worker.run());
+        Self {
+            driver,
+            memory,
+            deflate_on_oom,
+            config,
+            send,
+            _task: task,
+        }
+    }
+
+    fn features(&self) -> BalloonFeaturesBank0 {
+        BalloonFeaturesBank0::new()
+            .with_stats_vq(true)
+            .with_deflate_on_oom(self.deflate_on_oom)
+            .with_reporting(true)
+    }
+
+    fn queue(
+        &self,
+        features: &VirtioDeviceFeatures,
+        resources: QueueResources,
+    ) -> Option<VirtioQueue> {
+        if !resources.params.enable {
+            return None;
+        }
+        let event = PolledWait::new(&self.driver, resources.event)
+            .inspect_err(|err| {
+                tracing::error!(
+                    err = err as &dyn std::error::Error,
+                    "failed creating queue event"
+                );
+            })
+            .ok()?;
+        VirtioQueue::new(
+            features.clone(),
+            resources.params,
+            self.memory.clone(),
+            resources.notify,
+            event,
+        )
+        .inspect_err(|err| {
+            tracing::error!(
+                err = err as &dyn std::error::Error,
+                "failed creating virtio balloon queue"
+            );
+        })
+        .ok()
+    }
+}
+
+impl VirtioDevice for Device {
+    fn traits(&self) -> DeviceTraits {
+        DeviceTraits {
+            device_id: 5,
+            device_features: VirtioDeviceFeatures::new().with_bank(0, self.features().into_bits()),
+            // Inflate, deflate, statistics, and free page reporting.
+            max_queues: 4,
+            // num_pages, actual, free_page_hint_cmd_id, and poison_val.
+            device_register_length: 16,
+            shared_memory: DeviceTraitsSharedMemory { id: 0, size: 0 },
+        }
+    }
+
+    fn read_registers_u32(&self, offset: u16) -> u32 {
+        let config = self.config.lock();
+        match offset {
+            0 => config.num_pages,
+            4 => config.actual,
+            _ => 0,
+        }
+    }
+
+    fn write_registers_u32(&mut self, offset: u16, val: u32) {
+        match offset {
+            4 => self.config.lock().actual = val,
+            _ => {
+                tracelimit::warn_ratelimited!(offset, val, "write to read-only balloon register");
+            }
+        }
+    }
+
+    fn enable(&mut self, resources: Resources) {
+        let features = BalloonFeaturesBank0::from_bits(resources.features.bank(0));
+        let mut queue_resources = resources.queues.into_iter();
+
+        // The statistics and reporting queues are only present if their
+        // features were negotiated, so their indices depend on the features.
+        let mut next_queue = || {
+            let queue_resources = queue_resources.next()?;
+            self.queue(&resources.features, queue_resources)
+        };
+        let inflate = next_queue();
+        let deflate = next_queue();
+        let stats = if features.stats_vq() {
+            next_queue()
+        } else {
+            None
+        };
+        let reporting = if features.reporting() {
+            next_queue()
+        } else {
+            None
+        };
+
+        let (Some(inflate), Some(deflate)) = (inflate, deflate) else {
+            tracelimit::warn_ratelimited!("balloon inflate and deflate queues not enabled");
+            return;
+        };
+
+        self.send.send(WorkerMessage::Enable(Queues {
+            pending_stats: None,
+            inflate,
+            deflate,
+            stats,
+            reporting,
+            config_change: resources.config_change,
+        }));
+    }
+
+    fn disable(&mut self) {
+        self.config.lock().actual = 0;
+        self.send.send(WorkerMessage::Disable);
+    }
+}
+
+enum WorkerMessage {
+    Enable(Queues),
+    Disable,
+}
+
+struct Queues {
+    /// The statistics buffer most recently filled by the guest. Completing it
+    /// asks the guest for updated statistics.
+    ///
+    /// Declared first so that it is dropped (and so completed) before the
+    /// queues.
+    pending_stats: Option<VirtioQueueCallbackWork>,
+    inflate: VirtioQueue,
+    deflate: VirtioQueue,
+    stats: Option<VirtioQueue>,
+    reporting: Option<VirtioQueue>,
+    config_change: Interrupt,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum QueueKind {
+    Inflate,
+    Deflate,
+    Stats,
+    Reporting,
+}
+
+impl Queues {
+    async fn next_work(&mut self) -> (QueueKind, std::io::Result<VirtioQueueCallbackWork>) {
+        async fn next(
+            queue: Option<&mut VirtioQueue>,
+            kind: QueueKind,
+        ) -> (QueueKind, std::io::Result<VirtioQueueCallbackWork>) {
+            match queue {
+                Some(queue) => (kind, queue.next().await.expect("queue never completes")),
+                None => pending().await,
+            }
+        }
+
+        (
+            next(Some(&mut self.inflate), QueueKind::Inflate),
+            next(Some(&mut self.deflate), QueueKind::Deflate),
+            next(self.stats.as_mut(), QueueKind::Stats),
+            next(self.reporting.as_mut(), QueueKind::Reporting),
+        )
+            .race()
+            .await
+    }
+}
+
+struct Worker {
+    mem: GuestMemory,
+    reclaim: MemoryReclaim,
+    config: Arc<Mutex<BalloonConfig>>,
+    rpc: Option<mesh::Receiver<BalloonRpc>>,
+    messages: mesh::Receiver<WorkerMessage>,
+    queues: Option<Queues>,
+    stats: Vec<BalloonStat>,
+}
+
+enum Event {
+    Rpc(Result<BalloonRpc, mesh::RecvError>),
+    Message(Result<WorkerMessage, mesh::RecvError>),
+    Work(QueueKind, std::io::Result<VirtioQueueCallbackWork>),
+}
+
+impl Worker {
+    async fn run(mut self) {
+        loop {
+            let event = {
+                let rpc = &mut self.rpc;
+                let rpc = async {
+                    match rpc {
+                        Some(rpc) => Event::Rpc(rpc.recv().await),
+                        None => pending().await,
+                    }
+                };
+                let message = self.messages.recv().map(Event::Message);
+                let queues = &mut self.queues;
+                let work = async {
+                    match queues {
+                        Some(queues) => {
+                            let (kind, work) = queues.next_work().await;
+                            Event::Work(kind, work)
+                        }
+                        None => pending().await,
+                    }
+                };
+                (rpc, message, work).race().await
+            };
+            match event {
+                Event::Rpc(Ok(rpc)) => self.handle_rpc(rpc),
+                Event::Rpc(Err(_)) => {
+                    // The host has dropped its handle, but the guest can still
+                    // use the device.
+                    self.rpc = None;
+                }
+                Event::Message(Ok(WorkerMessage::Enable(queues))) => {
+                    self.queues = Some(queues);
+                }
+                Event::Message(Ok(WorkerMessage::Disable)) => {
+                    self.queues = None;
+                    self.stats.clear();
+                }
+                Event::Message(Err(_)) => break,
+                Event::Work(kind, Ok(work)) => self.handle_work(kind, work),
+                Event::Work(kind, Err(err)) => {
+                    tracing::error!(
+                        err = &err as &dyn std::error::Error,
+                        ?kind,
+                        "virtio balloon queue error"
+                    );
+                    self.queues = None;
+                }
+            }
+        }
+    }
+
+    fn handle_rpc(&mut self, rpc: BalloonRpc) {
+        match rpc {
+            BalloonRpc::SetTarget(rpc) => rpc.handle_sync(|target| {
+                let num_pages = (target / PAGE_SIZE).try_into().unwrap_or(u32::MAX);
+                let changed = {
+                    let mut config = self.config.lock();
+                    let changed = config.num_pages != num_pages;
+                    config.num_pages = num_pages;
+                    changed
+                };
+                if changed {
+                    if let Some(queues) = &self.queues {
+                        queues.config_change.deliver();
+                    }
+                }
+            }),
+            BalloonRpc::Status(rpc) => rpc.handle_sync(|()| {
+                // Return the buffer to the guest so that it reports fresh
+                // statistics.
+                if let Some(mut work) = self.queues.as_mut().and_then(|q| q.pending_stats.take()) {
+                    work.complete(0);
+                }
+                let config = self.config.lock();
+                BalloonStatus {
+                    target: config.num_pages as u64 * PAGE_SIZE,
+                    actual: config.actual as u64 * PAGE_SIZE,
+                    stats: self.stats.clone(),
+                }
+            }),
+        }
+    }
+
+    fn handle_work(&mut self, kind: QueueKind, mut work: VirtioQueueCallbackWork) {
+        match kind {
+            QueueKind::Inflate => {
+                if let Some(pfns) = self.read_pfns(&work) {
+                    for (gpa, len) in pfn_ranges(&pfns) {
+                        self.reclaim(gpa, len);
+                    }
+                }
+                work.complete(0);
+            }
+            QueueKind::Deflate => {
+                // Deflated pages are already backed on demand, so there is
+                // nothing to do.
+                work.complete(0);
+            }
+            QueueKind::Stats => {
+                let len = (work.get_payload_length(false) as usize).min(MAX_STATS_BUFFER);
+                let mut buf = vec![0; len];
+                match work.read(&self.mem, &mut buf) {
+                    Ok(n) => self.stats = parse_stats(&buf[..n]),
+                    Err(err) => {
+                        tracelimit::warn_ratelimited!(
+                            error = &err as &dyn std::error::Error,
+                            "failed to read balloon statistics"
+                        );
+                    }
+                }
+                // Hold on to the buffer until the host wants new statistics.
+                if let Some(queues) = &mut self.queues {
+                    queues.pending_stats = Some(work);
+                }
+            }
+            QueueKind::Reporting => {
+                for payload in work.payload.iter().filter(|p| p.writeable) {
+                    self.reclaim(payload.address, payload.length.into());
+                }
+                work.complete(0);
+            }
+        }
+    }
+
+    fn read_pfns(&self, work: &VirtioQueueCallbackWork) -> Option<Vec<u32>> {
+        let len = (work.get_payload_length(false) as usize).min(MAX_PFN_BUFFER);
+        let mut buf = vec![0; len];
+        let n = work
+            .read(&self.mem, &mut buf)
+            .inspect_err(|err| {
+                tracelimit::warn_ratelimited!(
+                    error = err as &dyn std::error::Error,
+                    "failed to read balloon page frame numbers"
+                );
+            })
+            .ok()?;
+        Some(
+            buf[..n]
+                .chunks_exact(4)
+                .map(|pfn| u32::from_le_bytes(pfn.try_into().unwrap()))
+                .collect(),
+        )
+    }
+
+    fn reclaim(&self, gpa: u64, len: u64) {
+        if gpa % PAGE_SIZE != 0 || len % PAGE_SIZE != 0 {
+            tracelimit::warn_ratelimited!(gpa, len, "unaligned balloon range");
+            return;
+        }
+        if let Err(err) = self.reclaim.reclaim(gpa, len) {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                gpa,
+                len,
+                "failed to reclaim balloon memory"
+            );
+        }
+    }
+}
+
+/// Coalesces runs of contiguous page frame numbers into `(gpa, len)` ranges.
+fn pfn_ranges(pfns: &[u32]) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for &pfn in pfns {
+        let gpa = pfn as u64 * PAGE_SIZE;
+        match ranges.last_mut() {
+            Some((start, len)) if *start + *len == gpa => *len += PAGE_SIZE,
+            _ => ranges.push((gpa, PAGE_SIZE)),
+        }
+    }
+    ranges
+}
+
+/// Parses an array of packed `virtio_balloon_stat` entries.
+fn parse_stats(buf: &[u8]) -> Vec<BalloonStat> {
+    buf.chunks_exact(STAT_SIZE)
+        .map(|stat| BalloonStat {
+            tag: u16::from_le_bytes(stat[..2].try_into().unwrap()),
+            value: u64::from_le_bytes(stat[2..].try_into().unwrap()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesce_pfns() {
+        assert!(pfn_ranges(&[]).is_empty());
+        assert_eq!(
+            pfn_ranges(&[1, 2, 3, 7, 8, 5]),
+            [(0x1000, 0x3000), (0x7000, 0x2000), (0x5000, 0x1000)]
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let mut buf = Vec::new();
+        for (tag, value) in [(4u16, 0x1234u64), (5, u64::MAX)] {
+            buf.extend_from_slice(&tag.to_le_bytes());
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        // A trailing partial entry is ignored.
+        buf.push(0);
+        let stats = parse_stats(&buf);
+        assert_eq!(stats.len(), 2);
+        assert_eq!((stats[0].tag, stats[0].value), (4, 0x1234));
+        assert_eq!((stats[1].tag, stats[1].value), (5, u64::MAX));
+        assert_eq!(stats[1].name(), Some("total_memory"));
+    }
+}
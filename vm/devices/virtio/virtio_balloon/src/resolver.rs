@@ -0,0 +1,52 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Defines the resource resolver for virtio-balloon devices.
+
+use crate::Device;
+use async_trait::async_trait;
+use virtio::resolve::ResolvedVirtioDevice;
+use virtio::resolve::VirtioResolveInput;
+use virtio_resources::balloon::VirtioBalloonHandle;
+use vm_resource::AsyncResolveResource;
+use vm_resource::IntoResource;
+use vm_resource::PlatformResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::VirtioDeviceHandle;
+use vmcore::memory_reclaim::MemoryReclaimKind;
+
+/// Resolver for virtio-balloon devices.
+pub struct VirtioBalloonResolver;
+
+declare_static_async_resolver! {
+    VirtioBalloonResolver,
+    (VirtioDeviceHandle, VirtioBalloonHandle),
+}
+
+#[async_trait]
+impl AsyncResolveResource<VirtioDeviceHandle, VirtioBalloonHandle> for VirtioBalloonResolver {
+    type Output = ResolvedVirtioDevice;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: VirtioBalloonHandle,
+        input: VirtioResolveInput<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let reclaim = resolver
+            .resolve::<MemoryReclaimKind, _>(PlatformResource.into_resource(), ())
+            .await?;
+
+        let device = Device::new(
+            input.driver_source,
+            input.guest_memory.clone(),
+            reclaim,
+            resource.deflate_on_oom,
+            resource.recv,
+        );
+
+        Ok(device.into())
+    }
+}
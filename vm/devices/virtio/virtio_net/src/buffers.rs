@@ -2,10 +2,12 @@
 // Licensed under the MIT License.
 
 use crate::VirtioNetHeader;
+use crate::VirtioNetHeaderFlags;
 use crate::header_size;
 use guestmem::GuestMemory;
 use net_backend::BufferAccess;
 use net_backend::RxBufferSegment;
+use net_backend::RxChecksumState;
 use net_backend::RxId;
 use net_backend::RxMetadata;
 use parking_lot::Mutex;
@@ -14,23 +16,50 @@
 use zerocopy::FromZeros;
 use zerocopy::IntoBytes;
 
+/// The largest frame delivered by a backend: a full VLAN-tagged Ethernet
+/// frame.
+const MAX_FRAME_LEN: u64 = 1518;
+
+/// A receive packet, made up of one or more descriptors.
 #[derive(Default)]
 struct RxPacket {
-    work: Option<VirtioQueueCallbackWork>,
+    work: Vec<VirtioQueueCallbackWork>,
     len: u32,
 }
 
+impl RxPacket {
+    /// Returns the number of bytes of the packet, including the header, that
+    /// land in each descriptor.
+    fn lengths(&self) -> impl Iterator<Item = u32> + '_ {
+        let mut remaining = self.len;
+        self.work.iter().map(move |work| {
+            let len = remaining.min(work.get_payload_length(true) as u32);
+            remaining -= len;
+            len
+        })
+    }
+}
+
 /// Holds virtio buffers available for a network backend to send data to the client.
 #[derive(Clone)]
 pub struct VirtioWorkPool {
     mem: GuestMemory,
     rx_packets: Arc<Vec<Mutex<RxPacket>>>,
+    /// Descriptors that are not yet part of a packet.
+    partial: Arc<Mutex<Vec<VirtioQueueCallbackWork>>>,
     buffer_segments: Vec<RxBufferSegment>,
+    guest_csum: bool,
+    min_capacity: u64,
 }
 
 impl VirtioWorkPool {
     /// Create a new instance.
-    pub fn new(mem: GuestMemory, queue_size: u16) -> Self {
+    ///
+    /// If `guest_csum` is set, the guest accepts packets marked as having
+    /// validated checksums. If `mrg_rxbuf` is set, descriptors are combined
+    /// until they can hold a full frame, and a packet may span several of
+    /// them.
+    pub fn new(mem: GuestMemory, queue_size: u16, guest_csum: bool, mrg_rxbuf: bool) -> Self {
         Self {
             mem,
             rx_packets: Arc::new(
@@ -38,7 +67,14 @@ pub fn new(mem: GuestMemory, queue_size: u16) -> Self {
                     .map(|_| Mutex::new(RxPacket::default()))
                     .collect(),
             ),
+            partial: Default::default(),
             buffer_segments: Vec::new(),
+            guest_csum,
+            min_capacity: if mrg_rxbuf {
+                header_size() as u64 + MAX_FRAME_LEN
+            } else {
+                0
+            },
         }
     }
 
@@ -47,25 +83,56 @@ pub fn ready(&self) -> Vec<RxId> {
         self.rx_packets
             .iter()
             .enumerate()
-            .filter_map(|(i, e)| e.lock().work.as_ref().map(|_| RxId(i as u32)))
+            .filter_map(|(i, e)| (!e.lock().work.is_empty()).then_some(RxId(i as u32)))
             .collect::<Vec<RxId>>()
     }
 
     /// Add a virtio work instance to the buffers available for use.
-    pub fn queue_work(&self, work: VirtioQueueCallbackWork) -> RxId {
-        let idx = work.descriptor_index();
+    ///
+    /// Returns the ID of the new packet buffer once enough descriptors have
+    /// been queued to hold a frame.
+    pub fn queue_work(&self, work: VirtioQueueCallbackWork) -> Option<RxId> {
+        let mut partial = self.partial.lock();
+        partial.push(work);
+        self.take_packet(&mut partial)
+    }
+
+    /// Combines the oldest partial descriptors into a packet buffer if they
+    /// can hold a frame.
+    fn take_packet(&self, partial: &mut Vec<VirtioQueueCallbackWork>) -> Option<RxId> {
+        let mut capacity = 0;
+        let n = partial.iter().position(|work| {
+            capacity += work.get_payload_length(true);
+            capacity >= self.min_capacity
+        })? + 1;
+        let idx = partial[0].descriptor_index();
         let mut packet = self.rx_packets[idx as usize].lock();
-        assert!(packet.work.is_none());
-        packet.work = Some(work);
+        assert!(packet.work.is_empty());
+        packet.work.extend(partial.drain(..n));
         packet.len = 0;
-        RxId(idx.into())
+        Some(RxId(idx.into()))
     }
 
     /// Notify the client that a receive packet is ready (network packet available).
-    pub fn complete_packet(&self, rx_id: RxId) {
-        let mut packet = self.rx_packets[rx_id.0 as usize].lock();
-        let mut work = packet.work.take().expect("valid packet index");
-        work.complete(packet.len);
+    ///
+    /// Descriptors the packet did not need are combined into new packet
+    /// buffers, whose IDs are appended to `rx_avail`.
+    pub fn complete_packet(&self, rx_id: RxId, rx_avail: &mut Vec<RxId>) {
+        let (work, lengths) = {
+            let mut packet = self.rx_packets[rx_id.0 as usize].lock();
+            assert!(!packet.work.is_empty(), "valid packet index");
+            let lengths = packet.lengths().collect::<Vec<_>>();
+            (std::mem::take(&mut packet.work), lengths)
+        };
+        let mut partial = self.partial.lock();
+        for (i, (mut work, len)) in work.into_iter().zip(lengths).enumerate() {
+            if i == 0 || len != 0 {
+                work.complete(len);
+            } else {
+                partial.push(work);
+            }
+        }
+        rx_avail.extend(std::iter::from_fn(|| self.take_packet(&mut partial)));
     }
 }
 
@@ -76,23 +143,42 @@ fn guest_memory(&self) -> &GuestMemory {
 
     fn write_data(&mut self, id: RxId, data: &[u8]) {
         let mut locked_packet = self.rx_packets[id.0 as usize].lock();
-        let work = locked_packet.work.as_ref().expect("invalid buffer index");
-        if let Err(err) = work.write_at_offset(header_size() as u64, &self.mem, data) {
-            tracing::warn!(
-                len = data.len(),
-                error = &err as &dyn std::error::Error,
-                "rx memory write failure"
-            );
+        let mut offset = header_size() as u64;
+        let mut remaining = data;
+        for work in &locked_packet.work {
+            let capacity = work.get_payload_length(true);
+            if offset >= capacity {
+                offset -= capacity;
+                continue;
+            }
+            let (this, rest) =
+                remaining.split_at(remaining.len().min((capacity - offset) as usize));
+            if let Err(err) = work.write_at_offset(offset, &self.mem, this) {
+                tracing::warn!(
+                    len = data.len(),
+                    error = &err as &dyn std::error::Error,
+                    "rx memory write failure"
+                );
+            }
+            offset = 0;
+            remaining = rest;
+            if remaining.is_empty() {
+                break;
+            }
+        }
+        if !remaining.is_empty() {
+            tracing::warn!(len = data.len(), "rx buffer too small");
         }
         locked_packet.len = (header_size() + data.len()) as u32;
     }
 
     fn guest_addresses(&mut self, id: RxId) -> &[RxBufferSegment] {
         let locked_packet = self.rx_packets[id.0 as usize].lock();
-        let work = locked_packet.work.as_ref().expect("invalid buffer index");
-        self.buffer_segments = work
-            .payload
+        assert!(!locked_packet.work.is_empty(), "invalid buffer index");
+        self.buffer_segments = locked_packet
+            .work
             .iter()
+            .flat_map(|work| &work.payload)
             .filter(|x| x.writeable)
             .map(|p| RxBufferSegment {
                 gpa: p.address,
@@ -105,28 +191,35 @@ fn guest_addresses(&mut self, id: RxId) -> &[RxBufferSegment] {
 
     fn capacity(&self, id: RxId) -> u32 {
         let locked_packet = self.rx_packets[id.0 as usize].lock();
-        let work = locked_packet.work.as_ref().expect("invalid buffer index");
-        work.get_payload_length(true) as u32
+        assert!(!locked_packet.work.is_empty(), "invalid buffer index");
+        locked_packet
+            .work
+            .iter()
+            .map(|work| work.get_payload_length(true) as u32)
+            .sum()
     }
 
     fn write_header(&mut self, id: RxId, metadata: &RxMetadata) {
         assert_eq!(metadata.offset, 0);
         assert!(metadata.len > 0);
 
-        // let flags = if let RxChecksumState::Good = metadata.ip_checksum {
-        //     VirtioNetHeaderFlags::VIRTIO_NET_HDR_F_DATA_VALID.bits()
-        // } else {
-        //     0
-        // };
+        let data_valid = self.guest_csum
+            && metadata.l4_checksum == RxChecksumState::Good
+            && metadata.ip_checksum != RxChecksumState::Bad;
 
+        let locked_packet = self.rx_packets[id.0 as usize].lock();
+        assert_eq!(metadata.len + header_size(), locked_packet.len as usize);
+        let num_buffers = locked_packet.lengths().filter(|&len| len != 0).count();
         let virtio_net_header = VirtioNetHeader {
-            num_buffers: 1,
+            flags: VirtioNetHeaderFlags::new()
+                .with_data_valid(data_valid)
+                .into_bits(),
+            num_buffers: num_buffers as u16,
             ..FromZeros::new_zeroed()
         };
-        let locked_packet = self.rx_packets[id.0 as usize].lock();
-        let work = locked_packet.work.as_ref().expect("invalid buffer index");
-        assert_eq!(metadata.len + header_size(), locked_packet.len as usize);
-        if let Err(err) = work.write(&self.mem, &virtio_net_header.as_bytes()[..header_size()]) {
+        if let Err(err) =
+            locked_packet.work[0].write(&self.mem, &virtio_net_header.as_bytes()[..header_size()])
+        {
             tracing::warn!(
                 error = &err as &dyn std::error::Error,
                 "failure writing header"
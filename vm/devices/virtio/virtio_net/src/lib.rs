@@ -4,9 +4,18 @@
 //! Virtio network device implementation.
 //!
 //! This crate implements a virtio-net device that connects a guest's virtual
-//! NIC to a pluggable [`net_backend::Endpoint`]. It currently operates with a
-//! single queue pair (one RX, one TX) and supports synchronous and asynchronous
-//! TX completion modes depending on the backend.
+//! NIC to a pluggable [`net_backend::Endpoint`]. It supports synchronous and
+//! asynchronous TX completion modes depending on the backend.
+//!
+//! If the endpoint supports multiple queues, the device offers one queue pair
+//! per endpoint queue along with a control queue, and steers receive traffic
+//! across the pairs the guest activates with RSS. Checksum and TCP
+//! segmentation offloads are offered to the guest when the endpoint supports
+//! them.
+//!
+//! With mergeable receive buffers, descriptors are combined until they can
+//! hold a full VLAN-tagged Ethernet frame, and each packet is delivered in as
+//! many of them as it needs.
 
 #![expect(missing_docs)]
 #![forbid(unsafe_code)]
@@ -24,6 +33,7 @@
 use futures::StreamExt;
 use futures_concurrency::future::Race;
 use guestmem::GuestMemory;
+use guestmem::GuestMemoryError;
 use inspect::Inspect;
 use inspect::InspectMut;
 use inspect_counters::Counter;
@@ -31,9 +41,11 @@
 use net_backend::Endpoint;
 use net_backend::EndpointAction;
 use net_backend::QueueConfig;
+use net_backend::RssConfig;
 use net_backend::RxId;
 use net_backend::TxId;
 use net_backend::TxMetadata;
+use net_backend::TxOffloadSupport;
 use net_backend::TxSegment;
 use net_backend::TxSegmentType;
 use net_backend_resources::mac_address::MacAddress;
@@ -57,6 +69,7 @@
 use vmcore::vm_task::VmTaskDriver;
 use vmcore::vm_task::VmTaskDriverSource;
 use zerocopy::FromBytes;
+use zerocopy::FromZeros;
 use zerocopy::Immutable;
 use zerocopy::IntoBytes;
 use zerocopy::KnownLayout;
@@ -138,9 +151,36 @@ struct NetStatus {
 
 const DEFAULT_MTU: u16 = 1514;
 
-#[expect(dead_code)]
 const VIRTIO_NET_MAX_QUEUES: u16 = 0x8000;
 
+// Control queue command classes, commands, and acks.
+const VIRTIO_NET_CTRL_MQ: u8 = 4;
+const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET: u8 = 0;
+const VIRTIO_NET_OK: u8 = 0;
+const VIRTIO_NET_ERR: u8 = 1;
+
+/// The RSS indirection table size to use if the endpoint does not report one.
+const DEFAULT_INDIRECTION_TABLE_SIZE: u16 = 128;
+
+/// The Toeplitz key used to spread receive traffic across queue pairs. The
+/// guest has no way to set one without `VIRTIO_NET_F_RSS`.
+const RSS_KEY: [u8; 40] = [
+    0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f, 0xb0,
+    0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30, 0xf2, 0x0c,
+    0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+];
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_PROTOCOL_UDP: u8 = 17;
+const TCP_CHECKSUM_OFFSET: u16 = 16;
+const UDP_CHECKSUM_OFFSET: u16 = 6;
+
+/// The number of packet bytes read to parse headers for transmit offloads.
+const MAX_OFFLOAD_HEADERS: usize = 256;
+
 #[repr(C)]
 struct NetConfig {
     pub mac: [u8; 6],
@@ -217,9 +257,120 @@ const fn header_size() -> usize {
     offset_of!(VirtioNetHeader, hash_value)
 }
 
+#[derive(Debug, Error)]
+enum TxOffloadError {
+    #[error("failed to read packet headers")]
+    Memory(#[source] GuestMemoryError),
+    #[error("packet headers are truncated")]
+    Truncated,
+    #[error("unsupported ethertype {0:#x}")]
+    UnsupportedEthertype(u16),
+    #[error("unsupported checksum offset {0}")]
+    UnsupportedChecksumOffset(u16),
+    #[error("checksum start does not match the packet headers")]
+    InvalidChecksumStart,
+    #[error("unsupported segmentation type {0:#x}")]
+    UnsupportedGso(u8),
+    #[error("invalid segment size")]
+    InvalidSegmentSize,
+}
+
+/// Translates the offload requests in a transmit header into `metadata`.
+///
+/// `packet` holds the leading bytes of the packet, following the header.
+fn parse_tx_offloads(
+    header: &VirtioNetHeader,
+    packet: &[u8],
+    metadata: &mut TxMetadata,
+) -> Result<(), TxOffloadError> {
+    let flags = VirtioNetHeaderFlags::from_bits(header.flags);
+    let gso = VirtioNetHeaderGso::from_bits(header.gso_type);
+    if !flags.needs_csum() {
+        // Segmentation always comes with checksum offload.
+        if gso.protocol() != VirtioNetHeaderGsoProtocol::NONE {
+            return Err(TxOffloadError::UnsupportedGso(header.gso_type));
+        }
+        return Ok(());
+    }
+
+    let read_u16 = |offset: usize| {
+        packet
+            .get(offset..offset + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or(TxOffloadError::Truncated)
+    };
+    let mut l2_len = 14;
+    let mut ethertype = read_u16(12)?;
+    if ethertype == ETHERTYPE_VLAN {
+        l2_len = 18;
+        ethertype = read_u16(16)?;
+    }
+    let is_ipv4 = match ethertype {
+        ETHERTYPE_IPV4 => true,
+        ETHERTYPE_IPV6 => false,
+        _ => return Err(TxOffloadError::UnsupportedEthertype(ethertype)),
+    };
+    let (is_tcp, protocol) = match header.csum_offset {
+        TCP_CHECKSUM_OFFSET => (true, IP_PROTOCOL_TCP),
+        UDP_CHECKSUM_OFFSET => (false, IP_PROTOCOL_UDP),
+        offset => return Err(TxOffloadError::UnsupportedChecksumOffset(offset)),
+    };
+    let l4_offset = header.csum_start as usize;
+    let l3_len = l4_offset
+        .checked_sub(l2_len)
+        .ok_or(TxOffloadError::InvalidChecksumStart)?;
+    if packet.len() < l4_offset + header.csum_offset as usize + 2 {
+        return Err(TxOffloadError::Truncated);
+    }
+    // Make sure the checksum start is the L4 header of the outermost IP
+    // packet, which is where the endpoint will compute the checksum.
+    if is_ipv4 {
+        let ip = &packet[l2_len..];
+        if l3_len != (ip[0] & 0xf) as usize * 4 || ip[9] != protocol {
+            return Err(TxOffloadError::InvalidChecksumStart);
+        }
+    } else if l3_len < 40 {
+        return Err(TxOffloadError::InvalidChecksumStart);
+    }
+
+    metadata.flags = metadata
+        .flags
+        .with_is_ipv4(is_ipv4)
+        .with_is_ipv6(!is_ipv4)
+        .with_offload_tcp_checksum(is_tcp)
+        .with_offload_udp_checksum(!is_tcp);
+    metadata.l2_len = l2_len as u8;
+    metadata.l3_len = l3_len
+        .try_into()
+        .map_err(|_| TxOffloadError::InvalidChecksumStart)?;
+
+    match gso.protocol() {
+        VirtioNetHeaderGsoProtocol::NONE => {}
+        VirtioNetHeaderGsoProtocol::TCPV4 | VirtioNetHeaderGsoProtocol::TCPV6
+            if is_tcp
+                && is_ipv4 == (gso.protocol() == VirtioNetHeaderGsoProtocol::TCPV4)
+                && !gso.ecn() =>
+        {
+            if header.gso_size == 0 {
+                return Err(TxOffloadError::InvalidSegmentSize);
+            }
+            metadata.flags = metadata
+                .flags
+                .with_offload_tcp_segmentation(true)
+                .with_offload_ip_header_checksum(is_ipv4);
+            metadata.l4_len = (packet[l4_offset + 12] >> 4) * 4;
+            metadata.max_tcp_segment_size = header.gso_size;
+        }
+        _ => return Err(TxOffloadError::UnsupportedGso(header.gso_type)),
+    }
+    Ok(())
+}
+
 struct Adapter {
     driver: VmTaskDriver,
     max_queues: u16,
+    indirection_table_size: u16,
+    tx_offloads: TxOffloadSupport,
     tx_fast_completions: bool,
     mac_address: MacAddress,
 }
@@ -237,14 +388,30 @@ impl Drop for Device {
     fn drop(&mut self) {}
 }
 
+impl Device {
+    fn features(&self) -> NetworkFeaturesBank0 {
+        let offloads = &self.adapter.tx_offloads;
+        let csum = offloads.tcp && offloads.udp;
+        let multiqueue = self.registers.max_virtqueue_pairs > 1;
+        NetworkFeaturesBank0::new()
+            .with_mac(true)
+            .with_csum(csum)
+            .with_host_tso4(csum && offloads.tso)
+            .with_host_tso6(csum && offloads.tso)
+            .with_guest_csum(true)
+            .with_mrg_rxbuf(true)
+            .with_ctrl_vq(multiqueue)
+            .with_mq(multiqueue)
+    }
+}
+
 impl VirtioDevice for Device {
     fn traits(&self) -> DeviceTraits {
-        // TODO: Add network features based on endpoint capabilities (NetworkFeatures::VIRTIO_NET_F_*)
+        let features = self.features();
         DeviceTraits {
             device_id: 1,
-            device_features: VirtioDeviceFeatures::new()
-                .with_bank(0, NetworkFeaturesBank0::new().with_mac(true).into_bits()),
-            max_queues: 2 * self.registers.max_virtqueue_pairs,
+            device_features: VirtioDeviceFeatures::new().with_bank(0, features.into_bits()),
+            max_queues: 2 * self.registers.max_virtqueue_pairs + features.ctrl_vq() as u16,
             device_register_length: size_of::<NetConfig>() as u32,
             shared_memory: DeviceTraitsSharedMemory { id: 0, size: 0 },
         }
@@ -272,7 +439,47 @@ fn read_registers_u32(&self, offset: u16) -> u32 {
     fn write_registers_u32(&mut self, _offset: u16, _val: u32) {}
 
     fn enable(&mut self, resources: Resources) {
+        let features = NetworkFeaturesBank0::from_bits(resources.features.bank(0));
         let mut queue_resources: Vec<_> = resources.queues.into_iter().collect();
+
+        // The control queue follows the data queues.
+        let max_pairs = if features.mq() {
+            self.registers.max_virtqueue_pairs
+        } else {
+            1
+        };
+        let ctrl_index = 2 * max_pairs as usize;
+        let ctrl_resources = (features.ctrl_vq() && queue_resources.len() > ctrl_index)
+            .then(|| queue_resources.remove(ctrl_index));
+        queue_resources.truncate(ctrl_index);
+        let ctrl_queue = ctrl_resources.and_then(|ctrl_resources| {
+            if !ctrl_resources.params.enable {
+                return None;
+            }
+            let event = PolledWait::new(&self.adapter.driver, ctrl_resources.event)
+                .inspect_err(|err| {
+                    tracing::error!(
+                        err = err as &dyn std::error::Error,
+                        "Failed creating queue event"
+                    );
+                })
+                .ok()?;
+            VirtioQueue::new(
+                resources.features.clone(),
+                ctrl_resources.params,
+                self.memory.clone(),
+                ctrl_resources.notify,
+                event,
+            )
+            .inspect_err(|err| {
+                tracing::error!(
+                    err = err as &dyn std::error::Error,
+                    "Failed creating virtio net control queue"
+                );
+            })
+            .ok()
+        });
+
         let mut workers = Vec::with_capacity(queue_resources.len() / 2);
         while queue_resources.len() > 1 {
             let mut next = queue_resources.drain(..2);
@@ -334,12 +541,14 @@ fn enable(&mut self, resources: Resources) {
                 rx_queue_size,
                 tx_queue: tx_queue.unwrap(),
                 tx_queue_size,
+                guest_csum: features.guest_csum(),
+                mrg_rxbuf: features.mrg_rxbuf(),
             });
         }
 
         let (tx, rx) = mesh::channel();
         self.coordinator_send = Some(tx);
-        self.insert_coordinator(rx, workers.len() as u16);
+        self.insert_coordinator(rx, workers.len() as u16, ctrl_queue);
         for (i, virtio_state) in workers.into_iter().enumerate() {
             self.insert_worker(virtio_state, i);
         }
@@ -414,6 +623,7 @@ fn new(rx_queue_size: u16, tx_queue_size: u16) -> Self {
 #[derive(Inspect, Default)]
 struct QueueStats {
     tx_stalled: Counter,
+    tx_invalid_offload: Counter,
     spurious_wakes: Counter,
     rx_packets: Counter,
     tx_packets: Counter,
@@ -429,10 +639,16 @@ struct ActiveState {
 }
 
 impl ActiveState {
-    fn new(mem: GuestMemory, rx_queue_size: u16, tx_queue_size: u16) -> Self {
+    fn new(
+        mem: GuestMemory,
+        rx_queue_size: u16,
+        tx_queue_size: u16,
+        guest_csum: bool,
+        mrg_rxbuf: bool,
+    ) -> Self {
         Self {
             pending_tx_packets: (0..tx_queue_size).map(|_| None).collect(),
-            pending_rx_packets: VirtioWorkPool::new(mem, rx_queue_size),
+            pending_rx_packets: VirtioWorkPool::new(mem, rx_queue_size, guest_csum, mrg_rxbuf),
             data: ProcessingData::new(rx_queue_size, tx_queue_size),
             stats: Default::default(),
         }
@@ -462,15 +678,23 @@ pub fn build(
         endpoint: Box<dyn Endpoint>,
         mac_address: MacAddress,
     ) -> Device {
-        // TODO: Implement VIRTIO_NET_F_MQ and VIRTIO_NET_F_RSS logic based on mulitqueue support.
-        // let multiqueue = endpoint.multiqueue_support();
-        // let max_queues = self.max_queues.clamp(1, multiqueue.max_queues.min(VIRTIO_NET_MAX_QUEUES));
-        let max_queues = 1;
+        // Leave room for the control queue in the 16-bit queue count.
+        let multiqueue = endpoint.multiqueue_support();
+        let max_queues = self
+            .max_queues
+            .clamp(1, multiqueue.max_queues.clamp(1, VIRTIO_NET_MAX_QUEUES - 1));
+        let indirection_table_size = if multiqueue.indirection_table_size != 0 {
+            multiqueue.indirection_table_size
+        } else {
+            DEFAULT_INDIRECTION_TABLE_SIZE
+        };
 
         let driver = driver_source.simple();
         let adapter = Arc::new(Adapter {
             driver,
             max_queues,
+            indirection_table_size,
+            tx_offloads: endpoint.tx_offload_support(),
             tx_fast_completions: endpoint.tx_fast_completions(),
             mac_address,
         });
@@ -516,7 +740,12 @@ fn inspect_mut(&mut self, req: inspect::Request<'_>) {
 }
 
 impl Device {
-    fn insert_coordinator(&mut self, recv: mesh::Receiver<CoordinatorMessage>, num_queues: u16) {
+    fn insert_coordinator(
+        &mut self,
+        recv: mesh::Receiver<CoordinatorMessage>,
+        num_queues: u16,
+        ctrl_queue: Option<VirtioQueue>,
+    ) {
         self.coordinator.insert(
             &self.adapter.driver,
             "virtio-net-coordinator".to_string(),
@@ -526,6 +755,10 @@ fn insert_coordinator(&mut self, recv: mesh::Receiver<CoordinatorMessage>, num_q
                     .map(|_| TaskControl::new(NetQueue { state: None }))
                     .collect(),
                 num_queues,
+                active_pairs: 1,
+                ctrl_queue,
+                pending_ctrl: None,
+                mem: self.memory.clone(),
                 restart: true,
             },
         );
@@ -549,8 +782,11 @@ fn insert_worker(&mut self, virtio_state: VirtioState, idx: usize) {
             self.memory.clone(),
             virtio_state.rx_queue_size,
             virtio_state.tx_queue_size,
+            virtio_state.guest_csum,
+            virtio_state.mrg_rxbuf,
         );
         let worker = Worker {
+            mem: self.memory.clone(),
             virtio_state,
             active_state,
         };
@@ -570,6 +806,13 @@ struct Coordinator {
     recv: mesh::Receiver<CoordinatorMessage>,
     workers: Vec<TaskControl<NetQueue, Worker>>,
     num_queues: u16,
+    /// The number of queue pairs receiving traffic, as set by the guest via
+    /// the control queue.
+    active_pairs: u16,
+    ctrl_queue: Option<VirtioQueue>,
+    /// A control command to acknowledge once the queues have been restarted.
+    pending_ctrl: Option<(VirtioQueueCallbackWork, u8)>,
+    mem: GuestMemory,
     restart: bool,
 }
 
@@ -594,6 +837,7 @@ fn inspect_mut(&mut self, req: inspect::Request<'_>, coordinator: Option<&mut Co
             .field_mut("endpoint", self.endpoint.as_mut());
 
         if let Some(coordinator) = coordinator {
+            resp.field("active_queue_pairs", coordinator.active_pairs);
             resp.fields_mut(
                 "queues",
                 coordinator.workers[..coordinator.num_queues as usize]
@@ -633,27 +877,48 @@ async fn process(
                 }
                 self.restart = false;
             }
+            if let Some((work, ack)) = self.pending_ctrl.take() {
+                self.complete_ctrl(work, ack);
+            }
             self.start_workers();
             enum Message {
                 Internal(CoordinatorMessage),
                 ChannelDisconnected,
                 UpdateFromEndpoint(EndpointAction),
+                Control(Result<VirtioQueueCallbackWork, std::io::Error>),
             }
             let message = {
+                let recv = &mut self.recv;
+                let ctrl_queue = self.ctrl_queue.as_mut();
                 let wait_for_message = async {
-                    let internal_msg = self
-                        .recv
+                    let internal_msg = recv
                         .next()
                         .map(|x| x.map_or(Message::ChannelDisconnected, Message::Internal));
                     let endpoint_restart = state
                         .endpoint
                         .wait_for_endpoint_action()
                         .map(Message::UpdateFromEndpoint);
-                    (internal_msg, endpoint_restart).race().await
+                    let ctrl = async {
+                        match ctrl_queue {
+                            Some(queue) => {
+                                Message::Control(queue.next().await.expect("queue never completes"))
+                            }
+                            None => pending().await,
+                        }
+                    };
+                    (internal_msg, endpoint_restart, ctrl).race().await
                 };
                 stop.until_stopped(wait_for_message).await?
             };
             match message {
+                Message::Control(Ok(work)) => self.handle_ctrl(work),
+                Message::Control(Err(err)) => {
+                    tracing::error!(
+                        error = &err as &dyn std::error::Error,
+                        "virtio net control queue error"
+                    );
+                    self.ctrl_queue = None;
+                }
                 Message::UpdateFromEndpoint(EndpointAction::RestartRequired) => self.restart = true,
                 Message::UpdateFromEndpoint(EndpointAction::LinkStatusNotify(_)) => {
                     tracing::error!("unexpected link status notification")
@@ -667,6 +932,60 @@ enum Message {
         Ok(())
     }
 
+    /// Processes a command from the control queue.
+    fn handle_ctrl(&mut self, work: VirtioQueueCallbackWork) {
+        let mut command = [0; 4];
+        let ack = match work.read(&self.mem, &mut command) {
+            Ok(4) if command[..2] == [VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET] => {
+                let pairs = u16::from_le_bytes([command[2], command[3]]);
+                if (1..=self.num_queues).contains(&pairs) {
+                    if pairs != self.active_pairs {
+                        tracing::debug!(pairs, "setting active queue pairs");
+                        self.active_pairs = pairs;
+                        self.restart = true;
+                    }
+                    VIRTIO_NET_OK
+                } else {
+                    tracelimit::warn_ratelimited!(pairs, "invalid queue pair count");
+                    VIRTIO_NET_ERR
+                }
+            }
+            Ok(_) => {
+                tracelimit::warn_ratelimited!(
+                    class = command[0],
+                    command = command[1],
+                    "unsupported control command"
+                );
+                VIRTIO_NET_ERR
+            }
+            Err(err) => {
+                tracelimit::warn_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to read control command"
+                );
+                VIRTIO_NET_ERR
+            }
+        };
+        // Acknowledge the command once the new configuration is in effect.
+        if self.restart {
+            self.pending_ctrl = Some((work, ack));
+        } else {
+            self.complete_ctrl(work, ack);
+        }
+    }
+
+    fn complete_ctrl(&self, mut work: VirtioQueueCallbackWork, ack: u8) {
+        if let Err(err) = work.write_at_offset(0, &self.mem, &[ack]) {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "failed to write control command ack"
+            );
+            work.complete(0);
+            return;
+        }
+        work.complete(1);
+    }
+
     async fn stop_workers(&mut self) {
         for worker in &mut self.workers {
             worker.stop().await;
@@ -674,13 +993,14 @@ async fn stop_workers(&mut self) {
     }
 
     async fn restart_queues(&mut self, c_state: &mut CoordinatorState) -> Result<(), WorkerError> {
+        let workers = &mut self.workers[..self.num_queues as usize];
+
         // Drop all of the current queues.
-        for worker in &mut self.workers {
+        for worker in workers.iter_mut() {
             worker.task_mut().state = None;
         }
 
-        let (rx_pools, ready_packets): (Vec<_>, Vec<_>) = self
-            .workers
+        let (rx_pools, ready_packets): (Vec<_>, Vec<_>) = workers
             .iter()
             .map(|worker| {
                 let pool = worker
@@ -704,16 +1024,27 @@ async fn restart_queues(&mut self, c_state: &mut CoordinatorState) -> Result<(),
             });
         }
 
+        // Spread receive traffic across the active queue pairs. The remaining
+        // pairs still transmit, but receive nothing.
+        let indirection_table = (0..c_state.adapter.indirection_table_size)
+            .map(|i| i % self.active_pairs)
+            .collect::<Vec<_>>();
+        let rss = (self.active_pairs > 1).then(|| RssConfig {
+            key: &RSS_KEY,
+            indirection_table: &indirection_table,
+            flags: 0,
+        });
+
         let mut queues = Vec::new();
         c_state
             .endpoint
-            .get_queues(queue_config, None, &mut queues)
+            .get_queues(queue_config, rss.as_ref(), &mut queues)
             .await
             .map_err(WorkerError::Endpoint)?;
 
-        assert_eq!(queues.len(), self.workers.len());
+        assert_eq!(queues.len(), workers.len());
 
-        for (worker, queue) in self.workers.iter_mut().zip(queues) {
+        for (worker, queue) in workers.iter_mut().zip(queues) {
             worker.task_mut().state = Some(EndpointQueueState { queue });
         }
 
@@ -749,6 +1080,8 @@ struct VirtioState {
     rx_queue_size: u16,
     tx_queue: VirtioQueue,
     tx_queue_size: u16,
+    guest_csum: bool,
+    mrg_rxbuf: bool,
 }
 
 #[derive(Debug, Error)]
@@ -776,6 +1109,7 @@ enum PacketError {
 }
 
 struct Worker {
+    mem: GuestMemory,
     virtio_state: VirtioState,
     active_state: ActiveState,
 }
@@ -857,9 +1191,11 @@ enum WakeReason {
                         WakeReason::PacketToClient(work) => {
                             tracing::trace!("rx packet");
                             let work = work.map_err(WorkerError::VirtioQueue)?;
-                            epqueue_state
-                                .queue
-                                .rx_avail(&[self.active_state.pending_rx_packets.queue_work(work)]);
+                            if let Some(rx_id) =
+                                self.active_state.pending_rx_packets.queue_work(work)
+                            {
+                                epqueue_state.queue.rx_avail(&[rx_id]);
+                            }
                         }
                     }
                 }
@@ -901,14 +1237,26 @@ fn queue_tx_packet(&mut self, mut work: VirtioQueueCallbackWork) -> Result<(), W
             return Err(WorkerError::Packet(PacketError::Empty));
         }
         let idx = work.descriptor_index();
-        segments[0].ty = TxSegmentType::Head(TxMetadata {
+        let mut metadata = TxMetadata {
             id: TxId(idx.into()),
             segment_count: segments.len().try_into().unwrap(),
             len: (work.get_payload_length(false) as usize - header_size())
                 .try_into()
                 .unwrap(),
             ..Default::default()
-        });
+        };
+        if let Err(err) = self.read_tx_offloads(&work, &mut metadata) {
+            // dropped packet: the guest requested an offload that cannot be
+            // performed.
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "invalid transmit offload request"
+            );
+            self.active_state.stats.tx_invalid_offload.increment();
+            work.complete(0);
+            return Ok(());
+        }
+        segments[0].ty = TxSegmentType::Head(metadata);
         let state = &mut self.active_state;
         state.data.tx_segments.append(&mut segments);
         assert!(state.pending_tx_packets[idx as usize].is_none());
@@ -916,6 +1264,24 @@ fn queue_tx_packet(&mut self, mut work: VirtioQueueCallbackWork) -> Result<(), W
         Ok(())
     }
 
+    fn read_tx_offloads(
+        &self,
+        work: &VirtioQueueCallbackWork,
+        metadata: &mut TxMetadata,
+    ) -> Result<(), TxOffloadError> {
+        let mut buf = [0; header_size() + MAX_OFFLOAD_HEADERS];
+        let len = work
+            .read(&self.mem, &mut buf)
+            .map_err(TxOffloadError::Memory)?;
+        let mut header = VirtioNetHeader::new_zeroed();
+        header.as_mut_bytes()[..header_size()].copy_from_slice(&buf[..header_size()]);
+        parse_tx_offloads(
+            &header,
+            &buf[header_size()..len.max(header_size())],
+            metadata,
+        )
+    }
+
     fn process_virtio_rx(
         &mut self,
         epqueue: &mut dyn net_backend::Queue,
@@ -925,7 +1291,7 @@ fn process_virtio_rx(
         while let Some(Some(work)) = self.virtio_state.rx_queue.next().now_or_never() {
             tracing::trace!("rx packet");
             let work = work.map_err(WorkerError::VirtioQueue)?;
-            rx_ids.push(self.active_state.pending_rx_packets.queue_work(work));
+            rx_ids.extend(self.active_state.pending_rx_packets.queue_work(work));
         }
         if !rx_ids.is_empty() {
             epqueue.rx_avail(rx_ids.as_slice());
@@ -947,9 +1313,15 @@ fn process_endpoint_rx(
             return Ok(false);
         }
 
+        let mut rx_avail = Vec::new();
         for ready_id in state.data.rx_ready[..n].iter() {
             state.stats.rx_packets.increment();
-            state.pending_rx_packets.complete_packet(*ready_id);
+            state
+                .pending_rx_packets
+                .complete_packet(*ready_id, &mut rx_avail);
+        }
+        if !rx_avail.is_empty() {
+            epqueue.rx_avail(&rx_avail);
         }
 
         state.stats.rx_packets_per_wake.add_sample(n as u64);
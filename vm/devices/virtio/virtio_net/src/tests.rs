@@ -41,6 +41,11 @@
 use vmcore::vm_task::VmTaskDriverSource;
 
 use crate::Device;
+use crate::TxOffloadError;
+use crate::VirtioNetHeader;
+use crate::parse_tx_offloads;
+use net_backend::TxMetadata;
+use zerocopy::FromZeros;
 
 // --- Constants ---
 
@@ -56,6 +61,18 @@
 const TX_AVAIL_ADDR: u64 = 0x11000;
 const TX_USED_ADDR: u64 = 0x12000;
 
+// Memory layout for the second queue pair and the control queue, used by the
+// multiqueue tests.
+const RX1_DESC_ADDR: u64 = 0x3000;
+const RX1_AVAIL_ADDR: u64 = 0x4000;
+const RX1_USED_ADDR: u64 = 0x5000;
+const TX1_DESC_ADDR: u64 = 0x6000;
+const TX1_AVAIL_ADDR: u64 = 0x7000;
+const TX1_USED_ADDR: u64 = 0x8000;
+const CTRL_DESC_ADDR: u64 = 0x9000;
+const CTRL_AVAIL_ADDR: u64 = 0xa000;
+const CTRL_USED_ADDR: u64 = 0xb000;
+
 // Data area for TX packet headers and payloads
 const DATA_BASE: u64 = 0x20000;
 const TOTAL_MEM_SIZE: usize = 0x30000;
@@ -307,6 +324,9 @@ fn new_mock_queue(pool: Box<dyn net_backend::BufferAccess>) -> (MockQueue, MockQ
 
 struct MockEndpoint {
     queue_tx: Mutex<Option<mesh::Sender<MockQueueHandle>>>,
+    max_queues: u16,
+    /// Receives the queue count and RSS indirection table of each restart.
+    restart_tx: Option<mesh::Sender<(usize, Option<Vec<u16>>)>>,
 }
 
 impl InspectMut for MockEndpoint {
@@ -324,15 +344,19 @@ fn endpoint_type(&self) -> &'static str {
     async fn get_queues(
         &mut self,
         config: Vec<QueueConfig<'_>>,
-        _rss: Option<&RssConfig<'_>>,
+        rss: Option<&RssConfig<'_>>,
         queues: &mut Vec<Box<dyn net_backend::Queue>>,
     ) -> anyhow::Result<()> {
-        let pool = config.into_iter().next().unwrap().pool;
-        let (queue, handle) = new_mock_queue(pool);
-        if let Some(tx) = self.queue_tx.lock().take() {
-            tx.send(handle);
+        if let Some(restart_tx) = &self.restart_tx {
+            restart_tx.send((config.len(), rss.map(|rss| rss.indirection_table.to_vec())));
+        }
+        for config in config {
+            let (queue, handle) = new_mock_queue(config.pool);
+            if let Some(tx) = self.queue_tx.lock().take() {
+                tx.send(handle);
+            }
+            queues.push(Box::new(queue));
         }
-        queues.push(Box::new(queue));
         Ok(())
     }
 
@@ -348,7 +372,7 @@ fn tx_offload_support(&self) -> TxOffloadSupport {
 
     fn multiqueue_support(&self) -> MultiQueueSupport {
         MultiQueueSupport {
-            max_queues: 1,
+            max_queues: self.max_queues,
             indirection_table_size: 0,
         }
     }
@@ -479,6 +503,52 @@ fn read_rx_used(mem: &GuestMemory, used_idx: &mut u16) -> Option<(u16, u32)> {
     Some((id as u16, len))
 }
 
+/// Wait for the next used ring entry of a queue, returning (desc_id,
+/// bytes_written).
+async fn wait_for_used_entry(
+    driver: &DefaultDriver,
+    mem: &GuestMemory,
+    interrupt: &Event,
+    used_addr: u64,
+    used_idx: &mut u16,
+) -> (u16, u32) {
+    let mut wait = PolledWait::new(driver, interrupt.clone()).unwrap();
+    mesh::CancelContext::new()
+        .with_timeout(Duration::from_secs(5))
+        .until_cancelled(async {
+            loop {
+                if read_used_idx(mem, used_addr) != *used_idx {
+                    let (id, len) = read_used_entry(mem, used_addr, *used_idx);
+                    *used_idx = used_idx.wrapping_add(1);
+                    return (id as u16, len);
+                }
+                wait.wait().await.unwrap();
+            }
+        })
+        .await
+        .expect("timed out waiting for used ring entry")
+}
+
+fn queue_resources(
+    desc_addr: u64,
+    avail_addr: u64,
+    used_addr: u64,
+    event: &Event,
+    notify: Interrupt,
+) -> QueueResources {
+    QueueResources {
+        params: QueueParams {
+            size: QUEUE_SIZE,
+            enable: true,
+            desc_addr,
+            avail_addr,
+            used_addr,
+        },
+        notify,
+        event: event.clone(),
+    }
+}
+
 // --- Test Harness ---
 
 struct TestHarness {
@@ -513,6 +583,8 @@ fn new(driver: &DefaultDriver) -> Self {
         let (queue_tx, queue_handle_rx) = mesh::channel();
         let endpoint = MockEndpoint {
             queue_tx: Mutex::new(Some(queue_tx)),
+            max_queues: 1,
+            restart_tx: None,
         };
 
         let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
@@ -543,11 +615,21 @@ fn new(driver: &DefaultDriver) -> Self {
 
     /// Enable the device and retrieve the MockQueueHandle.
     async fn enable_and_get_handle(&mut self) -> MockQueueHandle {
+        self.enable_with_features_and_get_handle(VirtioDeviceFeatures::new())
+            .await
+    }
+
+    /// Enable the device with the given negotiated features and retrieve the
+    /// MockQueueHandle.
+    async fn enable_with_features_and_get_handle(
+        &mut self,
+        features: VirtioDeviceFeatures,
+    ) -> MockQueueHandle {
         let rx_interrupt = Interrupt::from_event(self.rx_interrupt_event.clone());
         let tx_interrupt = Interrupt::from_event(self.tx_interrupt_event.clone());
 
         let resources = Resources {
-            features: VirtioDeviceFeatures::new(),
+            features,
             queues: vec![
                 // Queue 0: RX
                 QueueResources {
@@ -576,6 +658,7 @@ async fn enable_and_get_handle(&mut self) -> MockQueueHandle {
             ],
             shared_memory_region: None,
             shared_memory_size: 0,
+            config_change: Interrupt::null(),
         };
 
         self.device.enable(resources);
@@ -980,3 +1063,383 @@ async fn rx_large_payload(driver: DefaultDriver) {
         .unwrap();
     assert_eq!(readback, payload, "large payload data mismatch");
 }
+
+/// With mergeable receive buffers, descriptors are combined until they can
+/// hold a full frame. A packet reports how many of them it spans, and the
+/// descriptors it does not need start the next packet buffer.
+#[async_test]
+async fn rx_mergeable_buffers(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver);
+    let features = crate::NetworkFeaturesBank0::new().with_mrg_rxbuf(true);
+    let mut handle = harness
+        .enable_with_features_and_get_handle(
+            VirtioDeviceFeatures::new().with_bank(0, features.into_bits()),
+        )
+        .await;
+
+    // It takes two of these to hold a full frame.
+    let buffer_size: u32 = 1024;
+    let gpas = (0u16..4)
+        .map(|i| harness.post_rx_buffer_and_signal(i, buffer_size))
+        .collect::<Vec<_>>();
+    handle.wait_for_rx_pending().await;
+    handle.wait_for_rx_pending().await;
+
+    let num_buffers = |mem: &GuestMemory, gpa: u64| {
+        let mut buf = [0; 2];
+        mem.read_at(
+            gpa + std::mem::offset_of!(VirtioNetHeader, num_buffers) as u64,
+            &mut buf,
+        )
+        .unwrap();
+        u16::from_le_bytes(buf)
+    };
+
+    // A full-size packet spans the first two descriptors.
+    let payload: Vec<u8> = (0..1500).map(|i| (i % 251) as u8).collect();
+    handle.inject_rx_packet(&payload);
+    assert_eq!(harness.wait_for_rx_used().await, (0, buffer_size));
+    assert_eq!(
+        harness.wait_for_rx_used().await,
+        (1, NET_HEADER_SIZE + payload.len() as u32 - buffer_size)
+    );
+    assert_eq!(num_buffers(&harness.mem, gpas[0]), 2);
+    let split = (buffer_size - NET_HEADER_SIZE) as usize;
+    let mut readback = vec![0u8; payload.len()];
+    harness
+        .mem
+        .read_at(gpas[0] + NET_HEADER_SIZE as u64, &mut readback[..split])
+        .unwrap();
+    harness
+        .mem
+        .read_at(gpas[1], &mut readback[split..])
+        .unwrap();
+    assert_eq!(readback, payload, "merged payload data mismatch");
+
+    // A small packet only needs the first descriptor of its buffer.
+    let payload = b"small packet";
+    handle.inject_rx_packet(payload);
+    assert_eq!(
+        harness.wait_for_rx_used().await,
+        (2, NET_HEADER_SIZE + payload.len() as u32)
+    );
+    assert_eq!(num_buffers(&harness.mem, gpas[2]), 1);
+
+    // The unused descriptor is combined with the next one posted.
+    let gpa = harness.post_rx_buffer_and_signal(4, buffer_size);
+    handle.wait_for_rx_pending().await;
+    handle.inject_rx_packet(payload);
+    assert_eq!(
+        harness.wait_for_rx_used().await,
+        (3, NET_HEADER_SIZE + payload.len() as u32)
+    );
+    assert_eq!(num_buffers(&harness.mem, gpas[3]), 1);
+    let mut readback = [0u8; 2];
+    harness.mem.read_at(gpa, &mut readback).unwrap();
+    assert_eq!(readback, [0, 0], "unused descriptor was written");
+}
+
+/// A single-queue endpoint without offloads gets mergeable receive buffers and
+/// receive checksum validation, but no multiqueue or transmit offloads.
+#[async_test]
+async fn features_single_queue(driver: DefaultDriver) {
+    let harness = TestHarness::new(&driver);
+    let traits = harness.device.traits();
+    let features = crate::NetworkFeaturesBank0::from_bits(traits.device_features.bank(0));
+    assert!(features.mac());
+    assert!(features.mrg_rxbuf());
+    assert!(features.guest_csum());
+    assert!(!features.csum());
+    assert!(!features.host_tso4());
+    assert!(!features.ctrl_vq());
+    assert!(!features.mq());
+    assert_eq!(traits.max_queues, 2);
+}
+
+fn offload_header(flags: u8, gso_type: u8, csum_start: u16, csum_offset: u16) -> VirtioNetHeader {
+    VirtioNetHeader {
+        flags,
+        gso_type,
+        gso_size: 1448,
+        csum_start,
+        csum_offset,
+        ..FromZeros::new_zeroed()
+    }
+}
+
+/// Builds the leading bytes of an Ethernet frame with the given ethertype,
+/// IP header, and L4 header.
+fn offload_packet(ethertype: u16, ip: &[u8], l4: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0; 12];
+    packet.extend_from_slice(&ethertype.to_be_bytes());
+    packet.extend_from_slice(ip);
+    packet.extend_from_slice(l4);
+    packet
+}
+
+#[test]
+fn tx_offload_tcpv4_segmentation() {
+    let mut ip = [0; 20];
+    ip[0] = 0x45;
+    ip[9] = 6;
+    let mut tcp = [0; 20];
+    tcp[12] = 5 << 4;
+    let packet = offload_packet(0x0800, &ip, &tcp);
+    let header = offload_header(1, 1, 34, 16);
+    let mut metadata = TxMetadata::default();
+    parse_tx_offloads(&header, &packet, &mut metadata).unwrap();
+    assert!(metadata.flags.is_ipv4());
+    assert!(metadata.flags.offload_tcp_checksum());
+    assert!(metadata.flags.offload_ip_header_checksum());
+    assert!(metadata.flags.offload_tcp_segmentation());
+    assert_eq!(metadata.l2_len, 14);
+    assert_eq!(metadata.l3_len, 20);
+    assert_eq!(metadata.l4_len, 20);
+    assert_eq!(metadata.max_tcp_segment_size, 1448);
+}
+
+#[test]
+fn tx_offload_udpv6_checksum() {
+    let packet = offload_packet(0x86dd, &[0; 40], &[0; 8]);
+    let header = offload_header(1, 0, 54, 6);
+    let mut metadata = TxMetadata::default();
+    parse_tx_offloads(&header, &packet, &mut metadata).unwrap();
+    assert!(metadata.flags.is_ipv6());
+    assert!(metadata.flags.offload_udp_checksum());
+    assert!(!metadata.flags.offload_tcp_checksum());
+    assert!(!metadata.flags.offload_tcp_segmentation());
+    assert_eq!(metadata.l3_len, 40);
+}
+
+#[test]
+fn tx_offload_rejects_mismatched_headers() {
+    // A TCP checksum offset with a UDP IPv4 header.
+    let mut ip = [0; 20];
+    ip[0] = 0x45;
+    ip[9] = 17;
+    let packet = offload_packet(0x0800, &ip, &[0; 20]);
+    let header = offload_header(1, 0, 34, 16);
+    let mut metadata = TxMetadata::default();
+    assert!(matches!(
+        parse_tx_offloads(&header, &packet, &mut metadata),
+        Err(TxOffloadError::InvalidChecksumStart)
+    ));
+
+    // Segmentation without checksum offload.
+    let header = offload_header(0, 1, 0, 0);
+    assert!(matches!(
+        parse_tx_offloads(&header, &packet, &mut metadata),
+        Err(TxOffloadError::UnsupportedGso(1))
+    ));
+}
+
+// --- Control queue tests ---
+
+/// A device with two queue pairs and a control queue, enabled with
+/// multiqueue negotiated.
+struct MultiQueueHarness {
+    _device: Device,
+    mem: GuestMemory,
+    driver: DefaultDriver,
+    /// Receives the queue count and RSS indirection table of each restart.
+    restart_rx: mesh::Receiver<(usize, Option<Vec<u16>>)>,
+    ctrl_event: Event,
+    ctrl_interrupt_event: Event,
+    ctrl_avail_idx: u16,
+    ctrl_used_idx: u16,
+    next_desc: u16,
+}
+
+impl MultiQueueHarness {
+    fn new(driver: &DefaultDriver) -> Self {
+        let mem = GuestMemory::allocate(TOTAL_MEM_SIZE);
+        let (restart_tx, restart_rx) = mesh::channel();
+        let endpoint = MockEndpoint {
+            queue_tx: Mutex::new(None),
+            max_queues: 2,
+            restart_tx: Some(restart_tx),
+        };
+        let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+        let mac = MacAddress::new([0x00, 0x15, 0x5d, 0xaa, 0xbb, 0xcc]);
+        let mut device =
+            Device::builder().build(&driver_source, mem.clone(), Box::new(endpoint), mac);
+
+        let features =
+            crate::NetworkFeaturesBank0::from_bits(device.traits().device_features.bank(0));
+        assert!(features.mq());
+        assert!(features.ctrl_vq());
+
+        let ctrl_event = Event::new();
+        let ctrl_interrupt_event = Event::new();
+        device.enable(Resources {
+            features: VirtioDeviceFeatures::new().with_bank(0, features.into_bits()),
+            queues: vec![
+                queue_resources(
+                    RX_DESC_ADDR,
+                    RX_AVAIL_ADDR,
+                    RX_USED_ADDR,
+                    &Event::new(),
+                    Interrupt::null(),
+                ),
+                queue_resources(
+                    TX_DESC_ADDR,
+                    TX_AVAIL_ADDR,
+                    TX_USED_ADDR,
+                    &Event::new(),
+                    Interrupt::null(),
+                ),
+                queue_resources(
+                    RX1_DESC_ADDR,
+                    RX1_AVAIL_ADDR,
+                    RX1_USED_ADDR,
+                    &Event::new(),
+                    Interrupt::null(),
+                ),
+                queue_resources(
+                    TX1_DESC_ADDR,
+                    TX1_AVAIL_ADDR,
+                    TX1_USED_ADDR,
+                    &Event::new(),
+                    Interrupt::null(),
+                ),
+                queue_resources(
+                    CTRL_DESC_ADDR,
+                    CTRL_AVAIL_ADDR,
+                    CTRL_USED_ADDR,
+                    &ctrl_event,
+                    Interrupt::from_event(ctrl_interrupt_event.clone()),
+                ),
+            ],
+            shared_memory_region: None,
+            shared_memory_size: 0,
+            config_change: Interrupt::null(),
+        });
+
+        Self {
+            _device: device,
+            mem,
+            driver: driver.clone(),
+            restart_rx,
+            ctrl_event,
+            ctrl_interrupt_event,
+            ctrl_avail_idx: 0,
+            ctrl_used_idx: 0,
+            next_desc: 0,
+        }
+    }
+
+    /// Wait for the device to (re)start its endpoint queues.
+    async fn wait_for_restart(&mut self) -> (usize, Option<Vec<u16>>) {
+        mesh::CancelContext::new()
+            .with_timeout(Duration::from_secs(5))
+            .until_cancelled(self.restart_rx.next())
+            .await
+            .expect("timed out waiting for queue restart")
+            .expect("channel closed")
+    }
+
+    /// Send a control command and return the device's ack.
+    async fn send_ctrl(&mut self, command: [u8; 4]) -> u8 {
+        let desc = self.next_desc;
+        self.next_desc = (self.next_desc + 2) % QUEUE_SIZE;
+        let command_gpa = DATA_BASE + 16 * desc as u64;
+        let ack_gpa = command_gpa + 8;
+        self.mem.write_at(command_gpa, &command).unwrap();
+        self.mem.write_at(ack_gpa, &[0xff]).unwrap();
+        write_descriptor(
+            &self.mem,
+            CTRL_DESC_ADDR,
+            desc,
+            command_gpa,
+            command.len() as u32,
+            DescriptorFlags::new().with_next(true),
+            desc + 1,
+        );
+        write_descriptor(
+            &self.mem,
+            CTRL_DESC_ADDR,
+            desc + 1,
+            ack_gpa,
+            1,
+            DescriptorFlags::new().with_write(true),
+            0,
+        );
+        make_available(&self.mem, CTRL_AVAIL_ADDR, desc, &mut self.ctrl_avail_idx);
+        self.ctrl_event.signal();
+
+        let (used_id, used_len) = wait_for_used_entry(
+            &self.driver,
+            &self.mem,
+            &self.ctrl_interrupt_event,
+            CTRL_USED_ADDR,
+            &mut self.ctrl_used_idx,
+        )
+        .await;
+        assert_eq!((used_id, used_len), (desc, 1));
+        let mut ack = [0];
+        self.mem.read_at(ack_gpa, &mut ack).unwrap();
+        ack[0]
+    }
+}
+
+fn vq_pairs_set(pairs: u16) -> [u8; 4] {
+    let [lo, hi] = pairs.to_le_bytes();
+    [
+        crate::VIRTIO_NET_CTRL_MQ,
+        crate::VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET,
+        lo,
+        hi,
+    ]
+}
+
+/// Setting the active queue pairs restarts the endpoint queues with receive
+/// traffic spread across the active pairs, and acks the command afterwards.
+#[async_test]
+async fn ctrl_vq_pairs_set(driver: DefaultDriver) {
+    let mut harness = MultiQueueHarness::new(&driver);
+
+    // Only the first pair receives until the guest activates more.
+    assert_eq!(harness.wait_for_restart().await, (2, None));
+
+    assert_eq!(
+        harness.send_ctrl(vq_pairs_set(2)).await,
+        crate::VIRTIO_NET_OK
+    );
+    let (queues, table) = harness.wait_for_restart().await;
+    assert_eq!(queues, 2);
+    let table = table.expect("rss enabled");
+    assert_eq!(table.len(), crate::DEFAULT_INDIRECTION_TABLE_SIZE as usize);
+    assert!(table.iter().enumerate().all(|(i, &q)| q == i as u16 % 2));
+
+    // Setting the same count again does not restart the queues; going back
+    // to one pair does.
+    assert_eq!(
+        harness.send_ctrl(vq_pairs_set(2)).await,
+        crate::VIRTIO_NET_OK
+    );
+    assert_eq!(
+        harness.send_ctrl(vq_pairs_set(1)).await,
+        crate::VIRTIO_NET_OK
+    );
+    assert_eq!(harness.wait_for_restart().await, (2, None));
+}
+
+/// Invalid and unsupported control commands are rejected without restarting
+/// the queues.
+#[async_test]
+async fn ctrl_rejects_invalid_commands(driver: DefaultDriver) {
+    let mut harness = MultiQueueHarness::new(&driver);
+    assert_eq!(harness.wait_for_restart().await, (2, None));
+
+    assert_eq!(
+        harness.send_ctrl(vq_pairs_set(0)).await,
+        crate::VIRTIO_NET_ERR
+    );
+    assert_eq!(
+        harness.send_ctrl(vq_pairs_set(3)).await,
+        crate::VIRTIO_NET_ERR
+    );
+    // VIRTIO_NET_CTRL_RX_PROMISC is not supported.
+    assert_eq!(harness.send_ctrl([0, 0, 1, 0]).await, crate::VIRTIO_NET_ERR);
+    assert!(harness.restart_rx.try_recv().is_err());
+}
@@ -101,3 +101,96 @@ impl ResourceId<VirtioDeviceHandle> for VirtioNetHandle {
         const ID: &'static str = "virtio-net";
     }
 }
+
+pub mod vsock {
+    use mesh::MeshPayload;
+    use unix_socket::UnixListener;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::VirtioDeviceHandle;
+
+    #[derive(MeshPayload)]
+    pub struct VirtioVsockHandle {
+        /// The guest's context ID.
+        pub guest_cid: u64,
+        /// Guest connections to host port `P` are relayed to the Unix socket at
+        /// `<base_path>_<P>`.
+        pub base_path: Option<String>,
+        /// Listener for host connections to the guest, using the hybrid vsock
+        /// `CONNECT <port>` protocol.
+        pub listener: Option<UnixListener>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioVsockHandle {
+        const ID: &'static str = "virtio-vsock";
+    }
+}
+
+pub mod balloon {
+    use mesh::MeshPayload;
+    use mesh::rpc::Rpc;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::VirtioDeviceHandle;
+
+    #[derive(MeshPayload)]
+    pub struct VirtioBalloonHandle {
+        /// Allow the guest to deflate the balloon when it runs out of memory.
+        pub deflate_on_oom: bool,
+        /// The channel by which to receive requests from the host.
+        pub recv: mesh::Receiver<BalloonRpc>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioBalloonHandle {
+        const ID: &'static str = "virtio-balloon";
+    }
+
+    /// A request to the balloon device.
+    #[derive(MeshPayload)]
+    pub enum BalloonRpc {
+        /// Set the number of bytes the guest should hand back to the host.
+        SetTarget(Rpc<u64, ()>),
+        /// Get the current balloon size and the guest's most recently reported
+        /// memory statistics.
+        ///
+        /// This also asks the guest to refresh its statistics, so a subsequent
+        /// request will observe newer values.
+        Status(Rpc<(), BalloonStatus>),
+    }
+
+    /// The state of the balloon.
+    #[derive(Debug, MeshPayload)]
+    pub struct BalloonStatus {
+        /// The requested balloon size, in bytes.
+        pub target: u64,
+        /// The balloon size last reported by the guest, in bytes.
+        pub actual: u64,
+        /// The guest's memory statistics.
+        pub stats: Vec<BalloonStat>,
+    }
+
+    /// A guest memory statistic, as defined by the virtio specification.
+    #[derive(Debug, Copy, Clone, MeshPayload)]
+    pub struct BalloonStat {
+        pub tag: u16,
+        pub value: u64,
+    }
+
+    impl BalloonStat {
+        /// Returns the specification name of the statistic, if known.
+        pub fn name(&self) -> Option<&'static str> {
+            let name = match self.tag {
+                0 => "swap_in",
+                1 => "swap_out",
+                2 => "major_faults",
+                3 => "minor_faults",
+                4 => "free_memory",
+                5 => "total_memory",
+                6 => "available_memory",
+                7 => "disk_caches",
+                8 => "hugetlb_allocations",
+                9 => "hugetlb_failures",
+                _ => return None,
+            };
+            Some(name)
+        }
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio socket (vsock) device implementation.
+//!
+//! Guest stream sockets are relayed to Unix sockets on the host using the
+//! [hybrid vsock connection model][1] established by Firecracker, which is
+//! also used by the hvsocket relay in `vmbus_server`:
+//!
+//! * When the guest connects to port `P` on the host (CID 2), the device
+//!   connects to the Unix socket at `<path>_<P>`.
+//! * When a host process connects to the Unix socket listener at `<path>` and
+//!   writes `CONNECT <P>\n`, the device connects to port `P` in the guest. Once
+//!   the guest accepts, the device writes `OK <host port>\n` and begins
+//!   relaying.
+//!
+//! [1]: <https://github.com/firecracker-microvm/firecracker/blob/7b2e87dc65fc45162303e5708b83c379cf1b0426/docs/vsock.md>
+
+#![expect(missing_docs)]
+#![forbid(unsafe_code)]
+
+mod protocol;
+pub mod resolver;
+mod worker;
+
+#[cfg(test)]
+mod tests;
+
+use crate::worker::Queues;
+use crate::worker::Worker;
+use crate::worker::WorkerMessage;
+use guestmem::GuestMemory;
+use pal_async::socket::PolledSocket;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use pal_async::wait::PolledWait;
+use std::path::PathBuf;
+use unix_socket::UnixListener;
+use virtio::DeviceTraits;
+use virtio::DeviceTraitsSharedMemory;
+use virtio::QueueResources;
+use virtio::Resources;
+use virtio::VirtioDevice;
+use virtio::VirtioQueue;
+use virtio::spec::VirtioDeviceFeatures;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+
+pub struct Device {
+    driver: VmTaskDriver,
+    memory: GuestMemory,
+    guest_cid: u64,
+    send: mesh::Sender<WorkerMessage>,
+    _task: Task<()>,
+}
+
+impl Device {
+    /// Creates a new device.
+    ///
+    /// Guest connections to host ports are relayed to Unix sockets at
+    /// `<base_path>_<port>`, and host connections to guest ports are accepted
+    /// on `listener`.
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        memory: GuestMemory,
+        guest_cid: u64,
+        base_path: Option<PathBuf>,
+        listener: Option<UnixListener>,
+    ) -> anyhow::Result<Self> {
+        let driver = driver_source.simple();
+        let listener = listener
+            .map(|listener| PolledSocket::new(&driver, listener))
+            .transpose()?;
+        let (send, recv) = mesh::channel();
+        let worker = Worker::new(
+            driver.clone(),
+            memory.clone(),
+            guest_cid,
+            base_path,
+            listener,
+            recv,
+        );
+        let task = driver.spawn("virtio-vsock", worker.run());
+        Ok(Self {
+            driver,
+            memory,
+            guest_cid,
+            send,
+            _task: task,
+        })
+    }
+
+    fn queue(
+        &self,
+        features: &VirtioDeviceFeatures,
+        resources: QueueResources,
+    ) -> Option<VirtioQueue> {
+        if !resources.params.enable {
+            return None;
+        }
+        let event = PolledWait::new(&self.driver, resources.event)
+            .inspect_err(|err| {
+                tracing::error!(
+                    err = err as &dyn std::error::Error,
+                    "failed creating queue event"
+                );
+            })
+            .ok()?;
+        VirtioQueue::new(
+            features.clone(),
+            resources.params,
+            self.memory.clone(),
+            resources.notify,
+            event,
+        )
+        .inspect_err(|err| {
+            tracing::error!(
+                err = err as &dyn std::error::Error,
+                "failed creating virtio vsock queue"
+            );
+        })
+        .ok()
+    }
+}
+
+impl VirtioDevice for Device {
+    fn traits(&self) -> DeviceTraits {
+        DeviceTraits {
+            device_id: 19,
+            device_features: VirtioDeviceFeatures::new(),
+            // rx, tx, and event.
+            max_queues: 3,
+            device_register_length: size_of::<protocol::Config>() as u32,
+            shared_memory: DeviceTraitsSharedMemory { id: 0, size: 0 },
+        }
+    }
+
+    fn read_registers_u32(&self, offset: u16) -> u32 {
+        match offset {
+            0 => self.guest_cid as u32,
+            4 => (self.guest_cid >> 32) as u32,
+            _ => 0,
+        }
+    }
+
+    fn write_registers_u32(&mut self, offset: u16, val: u32) {
+        tracelimit::warn_ratelimited!(offset, val, "write to read-only vsock register");
+    }
+
+    fn enable(&mut self, resources: Resources) {
+        let mut queue_resources = resources.queues.into_iter();
+        let mut next_queue = || self.queue(&resources.features, queue_resources.next()?);
+        let rx = next_queue();
+        let tx = next_queue();
+        // The event queue is only used to report transport resets after
+        // migration, which this device does not support.
+        let event = next_queue();
+
+        let (Some(rx), Some(tx)) = (rx, tx) else {
+            tracelimit::warn_ratelimited!("vsock rx and tx queues not enabled");
+            return;
+        };
+
+        self.send.send(WorkerMessage::Enable(Queues {
+            rx,
+            tx,
+            _event: event,
+        }));
+    }
+
+    fn disable(&mut self) {
+        self.send.send(WorkerMessage::Disable);
+    }
+}
@@ -0,0 +1,62 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio socket protocol definitions.
+
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+/// The well-known CID of the host.
+pub const HOST_CID: u64 = 2;
+
+/// The packet header that precedes each packet on the rx and tx queues.
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+#[repr(C, packed)]
+pub struct Header {
+    pub src_cid: u64,
+    pub dst_cid: u64,
+    pub src_port: u32,
+    pub dst_port: u32,
+    pub len: u32,
+    pub socket_type: SocketType,
+    pub op: Op,
+    pub flags: u32,
+    pub buf_alloc: u32,
+    pub fwd_cnt: u32,
+}
+
+open_enum::open_enum! {
+    #[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+    pub enum SocketType: u16 {
+        STREAM = 1,
+        SEQPACKET = 2,
+    }
+}
+
+open_enum::open_enum! {
+    #[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+    pub enum Op: u16 {
+        INVALID = 0,
+        REQUEST = 1,
+        RESPONSE = 2,
+        RST = 3,
+        SHUTDOWN = 4,
+        RW = 5,
+        CREDIT_UPDATE = 6,
+        CREDIT_REQUEST = 7,
+    }
+}
+
+/// The peer will receive no more data.
+pub const SHUTDOWN_RECV: u32 = 1;
+/// The peer will send no more data.
+pub const SHUTDOWN_SEND: u32 = 2;
+
+/// The device configuration registers.
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+#[repr(C)]
+pub struct Config {
+    pub guest_cid: u64,
+}
@@ -0,0 +1,422 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Device-level tests that drive the vsock queues from a simulated guest and
+//! relay to real Unix sockets on the host.
+
+use crate::Device;
+use crate::protocol;
+use crate::protocol::Header;
+use crate::protocol::Op;
+use crate::protocol::SocketType;
+use futures::AsyncReadExt;
+use futures::AsyncWriteExt;
+use guestmem::GuestMemory;
+use pal_async::DefaultDriver;
+use pal_async::async_test;
+use pal_async::socket::PolledSocket;
+use pal_async::wait::PolledWait;
+use pal_event::Event;
+use std::future::Future;
+use std::path::Path;
+use std::time::Duration;
+use unix_socket::UnixListener;
+use unix_socket::UnixStream;
+use virtio::QueueResources;
+use virtio::Resources;
+use virtio::VirtioDevice;
+use virtio::queue::QueueParams;
+use virtio::spec::VirtioDeviceFeatures;
+use virtio::spec::queue::DescriptorFlags;
+use vmcore::interrupt::Interrupt;
+use vmcore::vm_task::SingleDriverBackend;
+use vmcore::vm_task::VmTaskDriverSource;
+use zerocopy::FromBytes;
+use zerocopy::IntoBytes;
+
+const QUEUE_SIZE: u16 = 16;
+
+const RX_DESC_ADDR: u64 = 0x0000;
+const RX_AVAIL_ADDR: u64 = 0x1000;
+const RX_USED_ADDR: u64 = 0x2000;
+const TX_DESC_ADDR: u64 = 0x3000;
+const TX_AVAIL_ADDR: u64 = 0x4000;
+const TX_USED_ADDR: u64 = 0x5000;
+
+// Each descriptor gets its own buffer.
+const RX_DATA: u64 = 0x10000;
+const TX_DATA: u64 = 0x20000;
+const BUFFER_SIZE: u32 = 0x1000;
+const TOTAL_MEM_SIZE: usize = 0x30000;
+
+const GUEST_CID: u64 = 3;
+const GUEST_PORT: u32 = 5000;
+const GUEST_BUF_ALLOC: u32 = 0x10000;
+
+async fn with_timeout<T>(fut: impl Future<Output = T>) -> T {
+    mesh::CancelContext::new()
+        .with_timeout(Duration::from_secs(5))
+        .until_cancelled(fut)
+        .await
+        .expect("timed out")
+}
+
+fn write_descriptor(
+    mem: &GuestMemory,
+    desc_addr: u64,
+    index: u16,
+    addr: u64,
+    len: u32,
+    write: bool,
+) {
+    let base = desc_addr + 16 * index as u64;
+    mem.write_at(base, &addr.to_le_bytes()).unwrap();
+    mem.write_at(base + 8, &len.to_le_bytes()).unwrap();
+    mem.write_at(
+        base + 12,
+        &u16::from(DescriptorFlags::new().with_write(write)).to_le_bytes(),
+    )
+    .unwrap();
+    mem.write_at(base + 14, &0u16.to_le_bytes()).unwrap();
+}
+
+/// Makes a descriptor available and bumps the avail index.
+fn make_available(mem: &GuestMemory, avail_addr: u64, index: u16, avail_idx: &mut u16) {
+    mem.write_at(
+        avail_addr + 4 + 2 * (*avail_idx % QUEUE_SIZE) as u64,
+        &index.to_le_bytes(),
+    )
+    .unwrap();
+    *avail_idx = avail_idx.wrapping_add(1);
+    mem.write_at(avail_addr + 2, &avail_idx.to_le_bytes())
+        .unwrap();
+}
+
+/// A simulated guest driver for the vsock device.
+struct TestGuest {
+    _device: Device,
+    mem: GuestMemory,
+    driver: DefaultDriver,
+    rx_event: Event,
+    rx_interrupt: Event,
+    tx_event: Event,
+    rx_avail_idx: u16,
+    rx_used_idx: u16,
+    tx_avail_idx: u16,
+}
+
+impl TestGuest {
+    fn new(driver: &DefaultDriver, base_path: &Path, listener: Option<UnixListener>) -> Self {
+        let mem = GuestMemory::allocate(TOTAL_MEM_SIZE);
+        let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+        let mut device = Device::new(
+            &driver_source,
+            mem.clone(),
+            GUEST_CID,
+            Some(base_path.into()),
+            listener,
+        )
+        .unwrap();
+
+        let rx_event = Event::new();
+        let rx_interrupt = Event::new();
+        let tx_event = Event::new();
+        let queue = |desc_addr, avail_addr, used_addr, event: &Event, notify| QueueResources {
+            params: QueueParams {
+                size: QUEUE_SIZE,
+                enable: true,
+                desc_addr,
+                avail_addr,
+                used_addr,
+            },
+            notify,
+            event: event.clone(),
+        };
+        device.enable(Resources {
+            features: VirtioDeviceFeatures::new(),
+            queues: vec![
+                queue(
+                    RX_DESC_ADDR,
+                    RX_AVAIL_ADDR,
+                    RX_USED_ADDR,
+                    &rx_event,
+                    Interrupt::from_event(rx_interrupt.clone()),
+                ),
+                queue(
+                    TX_DESC_ADDR,
+                    TX_AVAIL_ADDR,
+                    TX_USED_ADDR,
+                    &tx_event,
+                    Interrupt::null(),
+                ),
+            ],
+            shared_memory_region: None,
+            shared_memory_size: 0,
+            config_change: Interrupt::null(),
+        });
+
+        let mut guest = Self {
+            _device: device,
+            mem,
+            driver: driver.clone(),
+            rx_event,
+            rx_interrupt,
+            tx_event,
+            rx_avail_idx: 0,
+            rx_used_idx: 0,
+            tx_avail_idx: 0,
+        };
+        for i in 0..QUEUE_SIZE {
+            guest.post_rx(i);
+        }
+        guest
+    }
+
+    fn post_rx(&mut self, index: u16) {
+        write_descriptor(
+            &self.mem,
+            RX_DESC_ADDR,
+            index,
+            RX_DATA + (index as u64) * BUFFER_SIZE as u64,
+            BUFFER_SIZE,
+            true,
+        );
+        make_available(&self.mem, RX_AVAIL_ADDR, index, &mut self.rx_avail_idx);
+        self.rx_event.signal();
+    }
+
+    fn header(&self, host_port: u32, op: Op, flags: u32, len: u32) -> Header {
+        Header {
+            src_cid: GUEST_CID,
+            dst_cid: protocol::HOST_CID,
+            src_port: GUEST_PORT,
+            dst_port: host_port,
+            len,
+            socket_type: SocketType::STREAM,
+            op,
+            flags,
+            buf_alloc: GUEST_BUF_ALLOC,
+            fwd_cnt: 0,
+        }
+    }
+
+    /// Sends a packet on the tx queue.
+    fn send(&mut self, host_port: u32, op: Op, flags: u32, data: &[u8]) {
+        let index = self.tx_avail_idx % QUEUE_SIZE;
+        let gpa = TX_DATA + (index as u64) * BUFFER_SIZE as u64;
+        let header = self.header(host_port, op, flags, data.len() as u32);
+        self.mem.write_at(gpa, header.as_bytes()).unwrap();
+        self.mem
+            .write_at(gpa + size_of::<Header>() as u64, data)
+            .unwrap();
+        write_descriptor(
+            &self.mem,
+            TX_DESC_ADDR,
+            index,
+            gpa,
+            (size_of::<Header>() + data.len()) as u32,
+            false,
+        );
+        make_available(&self.mem, TX_AVAIL_ADDR, index, &mut self.tx_avail_idx);
+        self.tx_event.signal();
+    }
+
+    /// Receives the next packet from the rx queue.
+    async fn recv(&mut self) -> (Header, Vec<u8>) {
+        let mut wait = PolledWait::new(&self.driver, self.rx_interrupt.clone()).unwrap();
+        let (index, len) = with_timeout(async {
+            loop {
+                let mut used_idx = [0; 2];
+                self.mem.read_at(RX_USED_ADDR + 2, &mut used_idx).unwrap();
+                if u16::from_le_bytes(used_idx) != self.rx_used_idx {
+                    let mut entry = [0; 8];
+                    self.mem
+                        .read_at(
+                            RX_USED_ADDR + 4 + 8 * (self.rx_used_idx % QUEUE_SIZE) as u64,
+                            &mut entry,
+                        )
+                        .unwrap();
+                    self.rx_used_idx = self.rx_used_idx.wrapping_add(1);
+                    break (
+                        u32::from_le_bytes(entry[..4].try_into().unwrap()) as u16,
+                        u32::from_le_bytes(entry[4..].try_into().unwrap()),
+                    );
+                }
+                wait.wait().await.unwrap();
+            }
+        })
+        .await;
+        let mut packet = vec![0; len as usize];
+        self.mem
+            .read_at(RX_DATA + (index as u64) * BUFFER_SIZE as u64, &mut packet)
+            .unwrap();
+        self.post_rx(index);
+        let (header, data) = Header::read_from_prefix(&packet).unwrap();
+        assert_eq!({ header.len } as usize, data.len());
+        (header, data.to_vec())
+    }
+
+    /// Receives the next packet and checks that it is `op` for the
+    /// connection to `host_port`.
+    async fn expect(&mut self, host_port: u32, op: Op) -> (Header, Vec<u8>) {
+        let (header, data) = self.recv().await;
+        assert_eq!({ header.op }, op);
+        assert_eq!({ header.src_cid }, protocol::HOST_CID);
+        assert_eq!({ header.dst_cid }, GUEST_CID);
+        assert_eq!({ header.src_port }, host_port);
+        assert_eq!({ header.dst_port }, GUEST_PORT);
+        (header, data)
+    }
+}
+
+async fn read_exactly(socket: &mut PolledSocket<UnixStream>, len: usize) -> Vec<u8> {
+    let mut buf = vec![0; len];
+    with_timeout(socket.read_exact(&mut buf)).await.unwrap();
+    buf
+}
+
+async fn read_eof(socket: &mut PolledSocket<UnixStream>) {
+    let mut buf = [0; 1];
+    assert_eq!(with_timeout(socket.read(&mut buf)).await.unwrap(), 0);
+}
+
+/// Connects from the guest to a host listener at `<base_path>_<port>`.
+async fn guest_connect(
+    driver: &DefaultDriver,
+    guest: &mut TestGuest,
+    base_path: &Path,
+    port: u32,
+) -> PolledSocket<UnixStream> {
+    let mut path = base_path.as_os_str().to_owned();
+    path.push(format!("_{port}"));
+    let mut listener = PolledSocket::new(driver, UnixListener::bind(path).unwrap()).unwrap();
+    guest.send(port, Op::REQUEST, 0, &[]);
+    guest.expect(port, Op::RESPONSE).await;
+    let (socket, _) = with_timeout(listener.accept()).await.unwrap();
+    PolledSocket::new(driver, socket).unwrap()
+}
+
+#[async_test]
+async fn guest_connect_relays_data(driver: DefaultDriver) {
+    let dir = tempfile::tempdir().unwrap();
+    let base_path = dir.path().join("vsock");
+    let mut guest = TestGuest::new(&driver, &base_path, None);
+    let mut host = guest_connect(&driver, &mut guest, &base_path, 1234).await;
+
+    guest.send(1234, Op::RW, 0, b"hello");
+    assert_eq!(read_exactly(&mut host, 5).await, b"hello");
+
+    host.write_all(b"world").await.unwrap();
+    let (_, data) = guest.expect(1234, Op::RW).await;
+    assert_eq!(data, b"world");
+}
+
+#[async_test]
+async fn guest_connect_without_listener_resets(driver: DefaultDriver) {
+    let dir = tempfile::tempdir().unwrap();
+    let base_path = dir.path().join("vsock");
+    let mut guest = TestGuest::new(&driver, &base_path, None);
+
+    guest.send(1234, Op::REQUEST, 0, &[]);
+    guest.expect(1234, Op::RST).await;
+}
+
+#[async_test]
+async fn host_connect_relays_data(driver: DefaultDriver) {
+    let dir = tempfile::tempdir().unwrap();
+    let base_path = dir.path().join("vsock");
+    let listener = UnixListener::bind(&base_path).unwrap();
+    let mut guest = TestGuest::new(&driver, &base_path, Some(listener));
+
+    // Data following the connect request is relayed once the guest accepts.
+    let mut host = PolledSocket::new(&driver, UnixStream::connect(&base_path).unwrap()).unwrap();
+    host.write_all(format!("CONNECT {GUEST_PORT}\nearly").as_bytes())
+        .await
+        .unwrap();
+
+    let (header, _) = guest.recv().await;
+    assert_eq!({ header.op }, Op::REQUEST);
+    assert_eq!({ header.dst_port }, GUEST_PORT);
+    let host_port = header.src_port;
+    guest.send(host_port, Op::RESPONSE, 0, &[]);
+
+    let ok = format!("OK {host_port}\n");
+    assert_eq!(read_exactly(&mut host, ok.len()).await, ok.as_bytes());
+    let (_, data) = guest.expect(host_port, Op::RW).await;
+    assert_eq!(data, b"early");
+
+    guest.send(host_port, Op::RW, 0, b"reply");
+    assert_eq!(read_exactly(&mut host, 5).await, b"reply");
+}
+
+#[async_test]
+async fn credit_update_excludes_connect_response(driver: DefaultDriver) {
+    let dir = tempfile::tempdir().unwrap();
+    let base_path = dir.path().join("vsock");
+    let listener = UnixListener::bind(&base_path).unwrap();
+    let mut guest = TestGuest::new(&driver, &base_path, Some(listener));
+
+    let mut host = PolledSocket::new(&driver, UnixStream::connect(&base_path).unwrap()).unwrap();
+    host.write_all(format!("CONNECT {GUEST_PORT}\n").as_bytes())
+        .await
+        .unwrap();
+    let (header, _) = guest.recv().await;
+    let host_port = header.src_port;
+    guest.send(host_port, Op::RESPONSE, 0, &[]);
+    let ok = format!("OK {host_port}\n");
+    read_exactly(&mut host, ok.len()).await;
+
+    // Only guest data counts toward the forwarded byte count.
+    guest.send(host_port, Op::RW, 0, b"hello");
+    read_exactly(&mut host, 5).await;
+    guest.send(host_port, Op::CREDIT_REQUEST, 0, &[]);
+    let (header, _) = guest.expect(host_port, Op::CREDIT_UPDATE).await;
+    assert_eq!({ header.fwd_cnt }, 5);
+    assert_eq!({ header.buf_alloc }, 256 * 1024);
+}
+
+#[async_test]
+async fn guest_shutdown_closes_host_socket(driver: DefaultDriver) {
+    let dir = tempfile::tempdir().unwrap();
+    let base_path = dir.path().join("vsock");
+    let mut guest = TestGuest::new(&driver, &base_path, None);
+    let mut host = guest_connect(&driver, &mut guest, &base_path, 1234).await;
+
+    guest.send(
+        1234,
+        Op::SHUTDOWN,
+        protocol::SHUTDOWN_SEND | protocol::SHUTDOWN_RECV,
+        &[],
+    );
+    read_eof(&mut host).await;
+    // With both directions shut down, the device tears down the connection.
+    guest.expect(1234, Op::RST).await;
+}
+
+#[async_test]
+async fn host_close_shuts_down_guest(driver: DefaultDriver) {
+    let dir = tempfile::tempdir().unwrap();
+    let base_path = dir.path().join("vsock");
+    let mut guest = TestGuest::new(&driver, &base_path, None);
+    let host = guest_connect(&driver, &mut guest, &base_path, 1234).await;
+
+    drop(host);
+    let (header, _) = guest.expect(1234, Op::SHUTDOWN).await;
+    assert_eq!({ header.flags }, protocol::SHUTDOWN_SEND);
+}
+
+#[async_test]
+async fn rst(driver: DefaultDriver) {
+    let dir = tempfile::tempdir().unwrap();
+    let base_path = dir.path().join("vsock");
+    let mut guest = TestGuest::new(&driver, &base_path, None);
+    let mut host = guest_connect(&driver, &mut guest, &base_path, 1234).await;
+
+    // A guest reset closes the host socket.
+    guest.send(1234, Op::RST, 0, &[]);
+    read_eof(&mut host).await;
+
+    // Data for the closed connection is answered with a reset.
+    guest.send(1234, Op::RW, 0, b"hello");
+    guest.expect(1234, Op::RST).await;
+}
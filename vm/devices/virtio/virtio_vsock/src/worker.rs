@@ -0,0 +1,733 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The vsock worker, which relays guest connections to host Unix sockets.
+
+use crate::protocol;
+use crate::protocol::Header;
+use crate::protocol::Op;
+use crate::protocol::SocketType;
+use anyhow::Context as _;
+use futures::AsyncRead;
+use futures::AsyncReadExt;
+use futures::AsyncWrite;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use guestmem::GuestMemory;
+use pal_async::socket::PolledSocket;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::future::poll_fn;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use unix_socket::UnixListener;
+use unix_socket::UnixStream;
+use virtio::VirtioQueue;
+use virtio::VirtioQueueCallbackWork;
+use vmcore::vm_task::VmTaskDriver;
+use zerocopy::FromBytes;
+use zerocopy::IntoBytes;
+
+/// The receive buffer space advertised to the guest for each connection.
+const BUF_ALLOC: u32 = 256 * 1024;
+
+/// The largest packet payload the device will read from the tx queue or
+/// stage from a host socket.
+const MAX_PAYLOAD: usize = 64 * 1024;
+
+/// The first port used for host-initiated connections.
+const FIRST_HOST_PORT: u32 = 1024;
+
+pub(crate) enum WorkerMessage {
+    Enable(Queues),
+    Disable,
+}
+
+pub(crate) struct Queues {
+    pub rx: VirtioQueue,
+    pub tx: VirtioQueue,
+    pub _event: Option<VirtioQueue>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct ConnKey {
+    guest_port: u32,
+    host_port: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ConnState {
+    /// The guest requested the connection, and the device is connecting to
+    /// the host socket.
+    Connecting,
+    /// The host requested the connection, and the device is waiting for the
+    /// guest to respond.
+    Requesting,
+    Connected,
+}
+
+struct Connection {
+    state: ConnState,
+    socket: Option<PolledSocket<UnixStream>>,
+    /// Data from the guest waiting to be written to the host socket.
+    to_host: VecDeque<u8>,
+    /// The number of bytes at the front of `to_host` that were generated by
+    /// the device rather than sent by the guest, and so do not count toward
+    /// `fwd_cnt`.
+    preamble_len: usize,
+    /// Data from the host socket waiting to be sent to the guest.
+    to_guest: Vec<u8>,
+    to_guest_offset: usize,
+    /// Bytes written to the host socket.
+    fwd_cnt: u32,
+    /// The `fwd_cnt` last sent to the guest.
+    sent_fwd_cnt: u32,
+    credit_update_queued: bool,
+    /// Bytes sent to the guest.
+    rx_cnt: u32,
+    peer_buf_alloc: u32,
+    peer_fwd_cnt: u32,
+    /// The host socket has reached EOF, and the guest has been told.
+    host_eof: bool,
+    /// The guest will send no more data.
+    guest_send_shutdown: bool,
+    /// The guest will receive no more data.
+    guest_recv_shutdown: bool,
+    /// The write half of the host socket has been shut down.
+    host_write_shutdown: bool,
+}
+
+impl Connection {
+    fn new(state: ConnState, socket: Option<PolledSocket<UnixStream>>) -> Self {
+        Self {
+            state,
+            socket,
+            to_host: VecDeque::new(),
+            preamble_len: 0,
+            to_guest: Vec::new(),
+            to_guest_offset: 0,
+            fwd_cnt: 0,
+            sent_fwd_cnt: 0,
+            credit_update_queued: false,
+            rx_cnt: 0,
+            peer_buf_alloc: 0,
+            peer_fwd_cnt: 0,
+            host_eof: false,
+            guest_send_shutdown: false,
+            guest_recv_shutdown: false,
+            host_write_shutdown: false,
+        }
+    }
+
+    /// The number of bytes the guest is prepared to receive.
+    fn peer_credit(&self) -> u32 {
+        self.peer_buf_alloc
+            .saturating_sub(self.rx_cnt.wrapping_sub(self.peer_fwd_cnt))
+    }
+
+    fn has_data_for_guest(&self) -> bool {
+        self.to_guest_offset < self.to_guest.len()
+    }
+
+    /// Returns true if both directions are done and the connection can be
+    /// torn down.
+    fn is_done(&self) -> bool {
+        self.guest_send_shutdown
+            && self.to_host.is_empty()
+            && (self.host_eof || self.guest_recv_shutdown)
+    }
+
+    /// Performs host socket IO. Returns `Err` if the connection should be
+    /// reset.
+    fn poll_io(&mut self, cx: &mut Context<'_>) -> io::Result<IoProgress> {
+        let mut progress = IoProgress::default();
+        let read = self.state == ConnState::Connected
+            && !self.host_eof
+            && !self.guest_recv_shutdown
+            && !self.has_data_for_guest();
+        let credit = self.peer_credit() as usize;
+        let Some(socket) = &mut self.socket else {
+            return Ok(progress);
+        };
+
+        // Write guest data to the host.
+        while !self.to_host.is_empty() {
+            let (buf, _) = self.to_host.as_slices();
+            match Pin::new(&mut *socket).poll_write(cx, buf) {
+                Poll::Ready(Ok(n)) => {
+                    self.to_host.drain(..n);
+                    let preamble = n.min(self.preamble_len);
+                    self.preamble_len -= preamble;
+                    self.fwd_cnt = self.fwd_cnt.wrapping_add((n - preamble) as u32);
+                }
+                Poll::Ready(Err(err)) => return Err(err),
+                Poll::Pending => break,
+            }
+        }
+        if self.to_host.is_empty() && self.guest_send_shutdown && !self.host_write_shutdown {
+            // Ignore failures; the host may have already closed the socket.
+            let _ = Pin::new(&mut *socket).poll_close(cx);
+            self.host_write_shutdown = true;
+        }
+        if !self.credit_update_queued
+            && self.fwd_cnt.wrapping_sub(self.sent_fwd_cnt) >= BUF_ALLOC / 4
+        {
+            self.credit_update_queued = true;
+            progress.credit_update = true;
+        }
+
+        // Read host data for the guest.
+        if read && credit > 0 {
+            self.to_guest.resize(credit.min(MAX_PAYLOAD), 0);
+            self.to_guest_offset = 0;
+            match Pin::new(&mut *socket).poll_read(cx, &mut self.to_guest) {
+                Poll::Ready(Ok(0)) => {
+                    self.to_guest.clear();
+                    self.host_eof = true;
+                    progress.host_eof = true;
+                }
+                Poll::Ready(Ok(n)) => self.to_guest.truncate(n),
+                Poll::Ready(Err(err)) => return Err(err),
+                Poll::Pending => self.to_guest.clear(),
+            }
+        }
+        Ok(progress)
+    }
+}
+
+#[derive(Default)]
+struct IoProgress {
+    credit_update: bool,
+    host_eof: bool,
+}
+
+/// A control packet waiting for an rx buffer.
+struct ControlPacket {
+    key: ConnKey,
+    op: Op,
+    flags: u32,
+}
+
+enum PendingResult {
+    /// The connection to the host socket for a guest-initiated connection
+    /// completed.
+    GuestConnect {
+        key: ConnKey,
+        result: io::Result<PolledSocket<UnixStream>>,
+    },
+    /// A host process sent a connect request.
+    HostConnect(anyhow::Result<(PolledSocket<UnixStream>, u32)>),
+}
+
+enum Event {
+    Message(Option<WorkerMessage>),
+    Accept(UnixStream),
+    Pending(PendingResult),
+    Tx(VirtioQueueCallbackWork),
+    QueueError(io::Error),
+}
+
+pub(crate) struct Worker {
+    driver: VmTaskDriver,
+    mem: GuestMemory,
+    guest_cid: u64,
+    base_path: Option<PathBuf>,
+    listener: Option<PolledSocket<UnixListener>>,
+    messages: mesh::Receiver<WorkerMessage>,
+    queues: Option<Queues>,
+    connections: HashMap<ConnKey, Connection>,
+    control: VecDeque<ControlPacket>,
+    pending: FuturesUnordered<Pin<Box<dyn Future<Output = PendingResult> + Send>>>,
+    next_host_port: u32,
+}
+
+impl Worker {
+    pub fn new(
+        driver: VmTaskDriver,
+        mem: GuestMemory,
+        guest_cid: u64,
+        base_path: Option<PathBuf>,
+        listener: Option<PolledSocket<UnixListener>>,
+        messages: mesh::Receiver<WorkerMessage>,
+    ) -> Self {
+        Self {
+            driver,
+            mem,
+            guest_cid,
+            base_path,
+            listener,
+            messages,
+            queues: None,
+            connections: HashMap::new(),
+            control: VecDeque::new(),
+            pending: FuturesUnordered::new(),
+            next_host_port: FIRST_HOST_PORT,
+        }
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            match poll_fn(|cx| self.poll_event(cx)).await {
+                Event::Message(Some(WorkerMessage::Enable(queues))) => {
+                    self.queues = Some(queues);
+                }
+                Event::Message(Some(WorkerMessage::Disable)) => self.reset(),
+                Event::Message(None) => break,
+                Event::Accept(socket) => {
+                    let driver = self.driver.clone();
+                    self.pending.push(Box::pin(async move {
+                        PendingResult::HostConnect(read_connect(&driver, socket).await)
+                    }));
+                }
+                Event::Pending(result) => self.handle_pending(result),
+                Event::Tx(work) => self.handle_tx(work),
+                Event::QueueError(err) => {
+                    tracing::error!(
+                        err = &err as &dyn std::error::Error,
+                        "virtio vsock queue error"
+                    );
+                    self.reset();
+                }
+            }
+        }
+    }
+
+    /// Drops all connections and queues.
+    fn reset(&mut self) {
+        self.queues = None;
+        self.connections.clear();
+        self.control.clear();
+        self.pending = FuturesUnordered::new();
+    }
+
+    fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Event> {
+        if let Poll::Ready(message) = self.messages.poll_recv(cx) {
+            return Poll::Ready(Event::Message(message.ok()));
+        }
+
+        // Only accept host connections while the guest driver is active.
+        if self.queues.is_some() {
+            if let Some(listener) = &mut self.listener {
+                match listener.poll_accept(cx) {
+                    Poll::Ready(Ok((socket, _))) => return Poll::Ready(Event::Accept(socket)),
+                    Poll::Ready(Err(err)) => {
+                        tracing::error!(
+                            error = &err as &dyn std::error::Error,
+                            "failed to accept hybrid vsock connection, shutting down listener"
+                        );
+                        self.listener = None;
+                    }
+                    Poll::Pending => {}
+                }
+            }
+        }
+
+        if let Poll::Ready(Some(result)) = self.pending.poll_next_unpin(cx) {
+            return Poll::Ready(Event::Pending(result));
+        }
+
+        let Some(queues) = &mut self.queues else {
+            return Poll::Pending;
+        };
+
+        match queues.tx.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(work))) => return Poll::Ready(Event::Tx(work)),
+            Poll::Ready(Some(Err(err))) => return Poll::Ready(Event::QueueError(err)),
+            Poll::Ready(None) => unreachable!("queue never completes"),
+            Poll::Pending => {}
+        }
+
+        // Relay data between the host sockets and the connection buffers.
+        let mut reset = Vec::new();
+        for (&key, conn) in &mut self.connections {
+            match conn.poll_io(cx) {
+                Ok(progress) => {
+                    if progress.host_eof {
+                        self.control.push_back(ControlPacket {
+                            key,
+                            op: Op::SHUTDOWN,
+                            flags: protocol::SHUTDOWN_SEND,
+                        });
+                    }
+                    if progress.credit_update {
+                        self.control.push_back(ControlPacket {
+                            key,
+                            op: Op::CREDIT_UPDATE,
+                            flags: 0,
+                        });
+                    }
+                    if conn.is_done() {
+                        reset.push(key);
+                    }
+                }
+                Err(err) => {
+                    tracing::debug!(
+                        ?key,
+                        error = &err as &dyn std::error::Error,
+                        "vsock host socket failed"
+                    );
+                    reset.push(key);
+                }
+            }
+        }
+        for key in reset {
+            self.reset_connection(key);
+        }
+
+        // Send pending packets to the guest.
+        let mut sent_data = false;
+        while !self.control.is_empty() || self.connections.values().any(|c| c.has_data_for_guest())
+        {
+            let queues = self.queues.as_mut().unwrap();
+            match queues.rx.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(work))) => sent_data |= self.fill_rx(work),
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Event::QueueError(err)),
+                Poll::Ready(None) => unreachable!("queue never completes"),
+                Poll::Pending => break,
+            }
+        }
+        if sent_data {
+            // Sending data may have drained a connection's buffer, so poll
+            // the host sockets again.
+            cx.waker().wake_by_ref();
+        }
+
+        Poll::Pending
+    }
+
+    fn header(&mut self, key: ConnKey, op: Op, flags: u32, len: u32) -> Header {
+        let fwd_cnt = if let Some(conn) = self.connections.get_mut(&key) {
+            conn.sent_fwd_cnt = conn.fwd_cnt;
+            if op == Op::CREDIT_UPDATE {
+                conn.credit_update_queued = false;
+            }
+            conn.fwd_cnt
+        } else {
+            0
+        };
+        Header {
+            src_cid: protocol::HOST_CID,
+            dst_cid: self.guest_cid,
+            src_port: key.host_port,
+            dst_port: key.guest_port,
+            len,
+            socket_type: SocketType::STREAM,
+            op,
+            flags,
+            buf_alloc: BUF_ALLOC,
+            fwd_cnt,
+        }
+    }
+
+    /// Fills an rx buffer with the next packet for the guest. Returns true if
+    /// the packet carried connection data.
+    fn fill_rx(&mut self, mut work: VirtioQueueCallbackWork) -> bool {
+        let capacity = work.get_payload_length(true) as usize;
+        let Some(data_capacity) = capacity.checked_sub(size_of::<Header>()) else {
+            tracelimit::warn_ratelimited!(capacity, "vsock rx buffer too small");
+            work.complete(0);
+            return false;
+        };
+
+        let (packet, is_data) = if let Some(control) = self.control.pop_front() {
+            let header = self.header(control.key, control.op, control.flags, 0);
+            (header.as_bytes().to_vec(), false)
+        } else {
+            let Some((&key, conn)) = self
+                .connections
+                .iter_mut()
+                .find(|(_, conn)| conn.has_data_for_guest())
+            else {
+                work.complete(0);
+                return false;
+            };
+            let data = &conn.to_guest[conn.to_guest_offset..];
+            let data = &data[..data.len().min(data_capacity)];
+            let mut packet = Vec::with_capacity(size_of::<Header>() + data.len());
+            packet.extend_from_slice(&[0; size_of::<Header>()]);
+            packet.extend_from_slice(data);
+            let len = data.len();
+            conn.to_guest_offset += len;
+            conn.rx_cnt = conn.rx_cnt.wrapping_add(len as u32);
+            let header = self.header(key, Op::RW, 0, len as u32);
+            packet[..size_of::<Header>()].copy_from_slice(header.as_bytes());
+            (packet, true)
+        };
+
+        if let Err(err) = work.write(&self.mem, &packet) {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "failed to write vsock rx packet"
+            );
+            work.complete(0);
+        } else {
+            work.complete(packet.len() as u32);
+        }
+        is_data
+    }
+
+    fn handle_tx(&mut self, mut work: VirtioQueueCallbackWork) {
+        let len = (work.get_payload_length(false) as usize).min(size_of::<Header>() + MAX_PAYLOAD);
+        let mut buf = vec![0; len];
+        let result = work.read(&self.mem, &mut buf);
+        work.complete(0);
+        let n = match result {
+            Ok(n) => n,
+            Err(err) => {
+                tracelimit::warn_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to read vsock tx packet"
+                );
+                return;
+            }
+        };
+        let Ok((header, data)) = Header::read_from_prefix(&buf[..n]) else {
+            tracelimit::warn_ratelimited!(n, "vsock tx packet too small");
+            return;
+        };
+        let len = header.len as usize;
+        let data = &data[..data.len().min(len)];
+        self.handle_packet(header, data);
+    }
+
+    fn handle_packet(&mut self, header: Header, data: &[u8]) {
+        // Copy the fields out of the packed header.
+        let Header {
+            src_cid,
+            dst_cid,
+            src_port,
+            dst_port,
+            len: _,
+            socket_type,
+            op,
+            flags,
+            buf_alloc,
+            fwd_cnt,
+        } = header;
+        let key = ConnKey {
+            guest_port: src_port,
+            host_port: dst_port,
+        };
+
+        if src_cid != self.guest_cid
+            || dst_cid != protocol::HOST_CID
+            || socket_type != SocketType::STREAM
+        {
+            tracelimit::warn_ratelimited!(
+                src_cid,
+                dst_cid,
+                ?socket_type,
+                "unsupported vsock packet"
+            );
+            if op != Op::RST {
+                self.queue_control(key, Op::RST);
+            }
+            return;
+        }
+
+        if let Some(conn) = self.connections.get_mut(&key) {
+            conn.peer_buf_alloc = buf_alloc;
+            conn.peer_fwd_cnt = fwd_cnt;
+        }
+
+        match op {
+            Op::REQUEST => {
+                if self.connections.contains_key(&key) {
+                    self.reset_connection(key);
+                    return;
+                }
+                let Some(base_path) = &self.base_path else {
+                    self.queue_control(key, Op::RST);
+                    return;
+                };
+                let mut path = base_path.as_os_str().to_owned();
+                path.push(format!("_{}", key.host_port));
+                let mut conn = Connection::new(ConnState::Connecting, None);
+                conn.peer_buf_alloc = buf_alloc;
+                conn.peer_fwd_cnt = fwd_cnt;
+                self.connections.insert(key, conn);
+                let driver = self.driver.clone();
+                self.pending.push(Box::pin(async move {
+                    let result = PolledSocket::connect_unix(&driver, path).await;
+                    PendingResult::GuestConnect { key, result }
+                }));
+            }
+            Op::RESPONSE => match self.connections.get_mut(&key) {
+                Some(conn) if conn.state == ConnState::Requesting => {
+                    let ok = format!("OK {}\n", key.host_port);
+                    conn.state = ConnState::Connected;
+                    conn.preamble_len = ok.len();
+                    conn.to_host.extend(ok.as_bytes());
+                    tracing::debug!(?key, "connected host to guest");
+                }
+                _ => self.reset_connection(key),
+            },
+            Op::RST => {
+                self.connections.remove(&key);
+            }
+            Op::SHUTDOWN => {
+                if let Some(conn) = self.connections.get_mut(&key) {
+                    conn.guest_recv_shutdown |= flags & protocol::SHUTDOWN_RECV != 0;
+                    conn.guest_send_shutdown |= flags & protocol::SHUTDOWN_SEND != 0;
+                    if conn.guest_recv_shutdown {
+                        conn.to_guest.clear();
+                        conn.to_guest_offset = 0;
+                    }
+                } else {
+                    self.queue_control(key, Op::RST);
+                }
+            }
+            Op::RW => match self.connections.get_mut(&key) {
+                Some(conn) if conn.state == ConnState::Connected && !conn.guest_send_shutdown => {
+                    // The device's connect response does not count against
+                    // the guest's credit.
+                    if conn.to_host.len() - conn.preamble_len + data.len() > BUF_ALLOC as usize {
+                        tracelimit::warn_ratelimited!(?key, "guest exceeded vsock credit");
+                        self.reset_connection(key);
+                    } else {
+                        conn.to_host.extend(data);
+                    }
+                }
+                _ => self.reset_connection(key),
+            },
+            Op::CREDIT_UPDATE => {}
+            Op::CREDIT_REQUEST => {
+                if let Some(conn) = self.connections.get_mut(&key) {
+                    if !conn.credit_update_queued {
+                        conn.credit_update_queued = true;
+                        self.queue_control(key, Op::CREDIT_UPDATE);
+                    }
+                } else {
+                    self.queue_control(key, Op::RST);
+                }
+            }
+            op => {
+                tracelimit::warn_ratelimited!(?op, "unknown vsock op");
+                self.reset_connection(key);
+            }
+        }
+    }
+
+    fn handle_pending(&mut self, result: PendingResult) {
+        match result {
+            PendingResult::GuestConnect { key, result } => {
+                let Some(conn) = self
+                    .connections
+                    .get_mut(&key)
+                    .filter(|conn| conn.state == ConnState::Connecting)
+                else {
+                    return;
+                };
+                match result {
+                    Ok(socket) => {
+                        tracing::debug!(?key, "connected guest to host");
+                        conn.socket = Some(socket);
+                        conn.state = ConnState::Connected;
+                        self.queue_control(key, Op::RESPONSE);
+                    }
+                    Err(err) => {
+                        tracing::debug!(
+                            ?key,
+                            error = &err as &dyn std::error::Error,
+                            "failed to connect to host vsock listener"
+                        );
+                        self.reset_connection(key);
+                    }
+                }
+            }
+            PendingResult::HostConnect(Ok((socket, guest_port))) => {
+                let key = self.allocate_host_port(guest_port);
+                self.connections
+                    .insert(key, Connection::new(ConnState::Requesting, Some(socket)));
+                self.queue_control(key, Op::REQUEST);
+            }
+            PendingResult::HostConnect(Err(err)) => {
+                tracing::warn!(
+                    error = err.as_ref() as &dyn std::error::Error,
+                    "hybrid vsock connect request failed"
+                );
+            }
+        }
+    }
+
+    fn allocate_host_port(&mut self, guest_port: u32) -> ConnKey {
+        loop {
+            let key = ConnKey {
+                guest_port,
+                host_port: self.next_host_port,
+            };
+            self.next_host_port = self
+                .next_host_port
+                .checked_add(1)
+                .unwrap_or(FIRST_HOST_PORT);
+            if !self.connections.contains_key(&key) {
+                break key;
+            }
+        }
+    }
+
+    fn queue_control(&mut self, key: ConnKey, op: Op) {
+        self.control.push_back(ControlPacket { key, op, flags: 0 });
+    }
+
+    /// Drops a connection and tells the guest it has been reset.
+    fn reset_connection(&mut self, key: ConnKey) {
+        self.connections.remove(&key);
+        self.control.retain(|packet| packet.key != key);
+        self.queue_control(key, Op::RST);
+    }
+}
+
+/// Reads a hybrid vsock connect request from a host socket.
+async fn read_connect(
+    driver: &VmTaskDriver,
+    socket: UnixStream,
+) -> anyhow::Result<(PolledSocket<UnixStream>, u32)> {
+    let mut socket = PolledSocket::new(driver, socket)?;
+    let mut buf = [0; "CONNECT 4294967295\n".len()];
+    let mut i = 0;
+    while i == 0 || buf[i - 1] != b'\n' {
+        if i == buf.len() {
+            anyhow::bail!("connect request did not fit");
+        }
+        // Read a byte at a time so that any data following the request stays
+        // in the socket, to be relayed to the guest once it accepts.
+        let n = socket
+            .read(&mut buf[i..i + 1])
+            .await
+            .context("failed to read connect request")?;
+        if n == 0 {
+            anyhow::bail!("no connect request");
+        }
+        i += n;
+    }
+    let port = parse_connect(&buf[..i]).context("invalid connect request")?;
+    tracing::debug!(port, "got hybrid vsock connect request");
+    Ok((socket, port))
+}
+
+/// Parses a `CONNECT <port>\n` request.
+fn parse_connect(line: &[u8]) -> Option<u32> {
+    let port = line.strip_prefix(b"CONNECT ")?.strip_suffix(b"\n")?;
+    std::str::from_utf8(port).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_connect;
+
+    #[test]
+    fn connect_request() {
+        assert_eq!(parse_connect(b"CONNECT 1234\n"), Some(1234));
+        assert_eq!(parse_connect(b"CONNECT 1234"), None);
+        assert_eq!(
+            parse_connect(b"CONNECT 00000000-facb-11e6-bd58-64006a7986d3\n"),
+            None
+        );
+        assert_eq!(parse_connect(b"LISTEN 1234\n"), None);
+    }
+}
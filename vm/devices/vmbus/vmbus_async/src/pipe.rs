@@ -132,11 +132,16 @@ fn from(e: TryWriteError) -> Self {
     }
 }
 
+/// The default number of bytes written per packet in byte mode.
+const DEFAULT_BYTE_PACKET_SIZE: usize = 2048;
+
 #[derive(Debug)]
 struct PipeWriteState {
     state: WriteState,
     raw: bool,
     max_payload_len: usize,
+    /// The number of bytes written per packet in byte mode.
+    packet_size: usize,
 }
 
 impl PipeWriteState {
@@ -145,6 +150,7 @@ fn new(ptrs: ring::OutgoingOffset, raw: bool, max_payload_len: usize) -> Self {
             state: WriteState::new(ptrs),
             raw,
             max_payload_len,
+            packet_size: cmp::min(DEFAULT_BYTE_PACKET_SIZE, max_payload_len),
         }
     }
 
@@ -201,25 +207,29 @@ fn try_write_message(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize, TryWriteE
         Ok(len)
     }
 
-    /// Tries to write `buf` into the ring as a series of packets, possibly
-    /// sending only a portion of the bytes. Returns `Ok(None)` if the ring is
-    /// full.
-    fn try_write_bytes(&mut self, buf: &[u8]) -> Result<usize, TryWriteError> {
-        if buf.is_empty() {
+    /// Tries to write `bufs` into the ring as a series of packets, possibly
+    /// sending only a portion of the bytes. Returns
+    /// `Err(TryWriteError::Full(_))` if the ring is full.
+    fn try_write_bytes(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize, TryWriteError> {
+        let total: usize = bufs.iter().map(|x| x.len()).sum();
+        if total == 0 {
             return Ok(0);
         }
 
-        const CHUNK_SIZE: usize = 2048;
-        // Write in packets of CHUNK_SIZE bytes so that the opposite endpoint can remove
-        // packets as it reads data, freeing up more space for writes.
+        // Write in packets of `packet_size` bytes so that the opposite endpoint
+        // can remove packets as it reads data, freeing up more space for
+        // writes. A packet may gather bytes from several buffers.
+        let mut bufs = bufs.iter().map(|buf| &**buf);
+        let mut buf: &[u8] = &[];
         let mut written = 0;
         let mut outgoing = self.write.state.ptrs.clone();
-        for buf in buf.chunks(CHUNK_SIZE) {
+        while written < total {
+            let len = cmp::min(self.write.packet_size, total - written);
             match self.core.out_ring().write(
                 &mut outgoing,
                 &ring::OutgoingPacket {
                     transaction_id: 0,
-                    size: buf.len() + size_of::<ring::PipeHeader>(),
+                    size: len + size_of::<ring::PipeHeader>(),
                     typ: ring::OutgoingPacketType::InBandNoCompletion,
                 },
             ) {
@@ -228,12 +238,22 @@ fn try_write_bytes(&mut self, buf: &[u8]) -> Result<usize, TryWriteError> {
                     writer.write(
                         ring::PipeHeader {
                             packet_type: ring::PIPE_PACKET_TYPE_DATA,
-                            len: buf.len() as u32,
+                            len: len as u32,
                         }
                         .as_bytes(),
                     )?;
-                    writer.write(buf)?;
-                    written += buf.len();
+                    let mut remaining = len;
+                    while remaining > 0 {
+                        if buf.is_empty() {
+                            buf = bufs.next().unwrap();
+                            continue;
+                        }
+                        let (this, rest) = buf.split_at(cmp::min(remaining, buf.len()));
+                        writer.write(this)?;
+                        remaining -= this.len();
+                        buf = rest;
+                    }
+                    written += len;
                 }
                 Err(ring::WriteError::Full(n)) => {
                     if written > 0 {
@@ -289,8 +309,12 @@ fn poll_op<F, R>(&mut self, cx: &mut Context<'_>, mut f: F) -> Poll<Result<R, Er
         }
     }
 
-    fn poll_write_bytes(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, Error>> {
-        self.poll_op(cx, |this| this.try_write_bytes(buf))
+    fn poll_write_bytes(
+        &mut self,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, Error>> {
+        self.poll_op(cx, |this| this.try_write_bytes(bufs))
     }
 
     fn poll_write_message(
@@ -593,12 +617,7 @@ fn new_inner(channel: RawAsyncChannel<M>, raw: bool) -> io::Result<Self> {
             // Fall back to the ring size.
             channel.out_ring.maximum_packet_size() - ring::PacketSize::in_band(0)
         } else {
-            // There is a protocol-specified maximum size.
-            cmp::min(
-                ring::MAXIMUM_PIPE_PACKET_SIZE,
-                channel.out_ring.maximum_packet_size()
-                    - ring::PacketSize::in_band(size_of::<ring::PipeHeader>()),
-            )
+            max_pipe_payload_len(&channel)
         };
 
         let incoming = channel.in_ring.incoming().map_err(Error::Ring)?;
@@ -645,19 +664,40 @@ pub fn try_send_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<()> {
     }
 }
 
+/// Returns the maximum payload length of a pipe packet on `channel`'s outgoing
+/// ring.
+fn max_pipe_payload_len<M: RingMem>(channel: &RawAsyncChannel<M>) -> usize {
+    // There is a protocol-specified maximum size.
+    cmp::min(
+        ring::MAXIMUM_PIPE_PACKET_SIZE,
+        channel.out_ring.maximum_packet_size()
+            - ring::PacketSize::in_band(size_of::<ring::PipeHeader>()),
+    )
+}
+
 impl<M: RingMem> BytePipe<M> {
     /// Creates a new pipe from an open channel.
     pub fn new(channel: RawAsyncChannel<M>) -> io::Result<Self> {
+        let max_payload_len = max_pipe_payload_len(&channel);
         let incoming = channel.in_ring.incoming().map_err(Error::Ring)?;
         let outgoing = channel.out_ring.outgoing().map_err(Error::Ring)?;
 
         Ok(Self(Pipe {
             core: Core::new(channel),
             read: PipeReadState::new(incoming, false, 0),
-            write: PipeWriteState::new(outgoing, false, 0),
+            write: PipeWriteState::new(outgoing, false, max_payload_len),
         }))
     }
 
+    /// Sets the maximum number of bytes written per ring packet.
+    ///
+    /// Writes larger than this are split across multiple packets so that the
+    /// opposite endpoint can free ring space as it reads. The size is limited
+    /// to the largest packet the ring and the pipe protocol allow.
+    pub fn set_write_packet_size(&mut self, size: usize) {
+        self.0.write.packet_size = size.clamp(1, self.0.write.max_payload_len);
+    }
+
     /// Splits the pipe into read and write halves so that reads and writes may
     /// be concurrently issued.
     pub fn split(&mut self) -> (ByteReadHalf<'_, M>, ByteWriteHalf<'_, M>) {
@@ -713,7 +753,20 @@ fn poll_write(
         this.0
             .write
             .writer(&this.0.core)
-            .poll_write_bytes(cx, buf)
+            .poll_write_bytes(cx, &[IoSlice::new(buf)])
+            .map_err(Into::into)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.0
+            .write
+            .writer(&this.0.core)
+            .poll_write_bytes(cx, bufs)
             .map_err(Into::into)
     }
 
@@ -740,7 +793,19 @@ fn poll_write(
         let this = self.get_mut();
         this.write
             .writer(this.core)
-            .poll_write_bytes(cx, buf)
+            .poll_write_bytes(cx, &[IoSlice::new(buf)])
+            .map_err(Into::into)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.write
+            .writer(this.core)
+            .poll_write_bytes(cx, bufs)
             .map_err(Into::into)
     }
 
@@ -916,6 +981,7 @@ mod tests {
     use pal_async::async_test;
     use pal_async::timer::PolledTimer;
     use std::io::ErrorKind;
+    use std::io::IoSlice;
     use std::time::Duration;
     use zerocopy::IntoBytes;
 
@@ -992,4 +1058,31 @@ async fn test_byte_pipe(driver: DefaultDriver) {
         };
         futures::future::join(guest_write, host_read).await;
     }
+
+    #[async_test]
+    async fn test_byte_pipe_vectored(driver: DefaultDriver) {
+        let (mut host, mut guest) = connected_byte_pipes(4096);
+        guest.set_write_packet_size(300);
+        let guest_write = async {
+            let a: Vec<_> = (0..1000_u16).collect();
+            let b: Vec<_> = (1000..5000_u16).collect();
+            let n = guest
+                .write_vectored(&[IoSlice::new(a.as_bytes()), IoSlice::new(b.as_bytes())])
+                .await
+                .unwrap();
+            // Only part of the data fits in the ring, split across packets
+            // that span both buffers.
+            assert!(n > a.as_bytes().len() && n < 10000);
+            let rest = [a.as_bytes(), b.as_bytes()].concat();
+            guest.write_all(&rest[n..]).await.unwrap();
+        };
+        let host_read = async {
+            let mut timer = PolledTimer::new(&driver);
+            timer.sleep(Duration::from_millis(200)).await;
+            let mut v = [0_u16; 5000];
+            host.read_exact(v.as_mut_bytes()).await.unwrap();
+            assert!(v.iter().copied().eq(0..5000_u16));
+        };
+        futures::future::join(guest_write, host_read).await;
+    }
 }
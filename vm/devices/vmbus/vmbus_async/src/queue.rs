@@ -13,6 +13,7 @@
 use guestmem::MemoryRead;
 use guestmem::MemoryWrite;
 use guestmem::ranges::PagedRange;
+use guestmem::ranges::PagedRangeReader;
 use inspect::Inspect;
 use ring::OutgoingPacketType;
 use ring::TransferPageRange;
@@ -29,6 +30,7 @@
 use vmbus_ring::FlatRingMem;
 use vmbus_ring::IncomingPacketType;
 use vmbus_ring::IncomingRing;
+use vmbus_ring::Ring;
 use vmbus_ring::RingMem;
 use vmbus_ring::gparange::MultiPagedRangeBuf;
 use zerocopy::IntoBytes;
@@ -229,6 +231,19 @@ pub fn reader(&self) -> vmbus_ring::RingRangeReader<'_, T> {
         self.payload.reader(self.ring)
     }
 
+    /// A reader for the data payload that reads it directly from guest memory
+    /// rather than through the ring's mapping.
+    ///
+    /// Returns `None` if the ring is not backed by guest memory, in which case
+    /// use [`Self::reader`]. The reader is borrowed from the packet, so it
+    /// cannot outlive the packet's removal from the ring. As with
+    /// [`Self::reader`], the opposite endpoint may mutate the payload while it
+    /// is being read.
+    pub fn read_packet_in_place(&self) -> Option<PagedRangeReader<'_>> {
+        let mem = self.ring.mem().guest_memory()?;
+        Some(self.payload.paged_range(self.ring)?.reader(mem))
+    }
+
     /// The packet's transaction ID. Set if and only if a completion packet was
     /// requested.
     pub fn transaction_id(&self) -> Option<u64> {
@@ -411,6 +426,7 @@ pub fn poll_read_batch<'b>(
 
     /// Tries to get a reader for the next batch of packets.
     pub fn try_read_batch(&mut self) -> Result<ReadBatch<'_, M>, TryReadError> {
+        let _audit = alloc_audit::hot_path!("vmbus_async::queue::try_read_batch").enter();
         if self
             .core
             .in_ring()
@@ -625,6 +641,7 @@ pub fn can_write(&mut self, send_size: usize) -> Result<bool, Error> {
     ///
     /// Fails with `TryReadError::Full(send_size)` if the ring is full.
     pub fn try_write(&mut self, packet: &OutgoingPacket<'_, '_>) -> Result<(), TryWriteError> {
+        let _audit = alloc_audit::hot_path!("vmbus_async::queue::try_write").enter();
         let size = packet.payload.iter().fold(0, |a, p| a + p.len());
         let ring_packet = ring::OutgoingPacket {
             transaction_id: packet.transaction_id,
@@ -653,6 +670,7 @@ pub fn try_write_aligned(
         packet_type: OutgoingPacketType<'_>,
         data: &[u64],
     ) -> Result<(), TryWriteError> {
+        let _audit = alloc_audit::hot_path!("vmbus_async::queue::try_write_aligned").enter();
         let size = data.len() * 8;
         let ring_packet = ring::OutgoingPacket {
             transaction_id,
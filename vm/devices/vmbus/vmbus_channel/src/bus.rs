@@ -129,6 +129,9 @@ pub enum ChannelServerRequest {
     /// A channel can also be revoked by dropping it. This request is only necessary if you need to
     /// wait for the revoke operation to complete.
     Revoke(Rpc<(), ()>),
+    /// A request to inspect the server's state for the channel, as seen by
+    /// the guest.
+    Inspect(inspect::Deferred),
 }
 
 /// The result of a [`ChannelServerRequest::Restore`] operation.
@@ -292,6 +295,14 @@ pub struct OfferParams {
     /// external GPADLs and GPA direct ranges. This is only used when hardware
     /// isolation is in use.
     pub allow_confidential_external_memory: bool,
+    /// The minimum interval between host-to-guest interrupts for the channel,
+    /// or `None` to deliver every interrupt immediately. Interrupts signaled
+    /// within the interval are coalesced into one delivered at its end.
+    pub interrupt_coalescing: Option<Duration>,
+    /// Advisory performance hints conveyed to the guest in the offer. These
+    /// are ignored for [`ChannelType::Interface`] channels, whose user-defined
+    /// data belongs to the device.
+    pub perf_hints: Option<PerfHints>,
 }
 
 impl OfferParams {
@@ -305,6 +316,48 @@ pub fn key(&self) -> OfferKey {
     }
 }
 
+/// Advisory performance hints for a channel.
+///
+/// These are encoded in the channel offer as
+/// [`protocol::ChannelPerfHints`] for enlightened guest drivers.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, MeshPayload)]
+pub struct PerfHints {
+    /// The target latency for a single request.
+    pub latency: Option<Duration>,
+    /// The expected peak throughput, in megabytes per second.
+    pub throughput_mbps: Option<u32>,
+}
+
+impl PerfHints {
+    /// Returns empty hints.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the target latency for a single request.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Sets the expected peak throughput, in megabytes per second.
+    pub fn with_throughput_mbps(mut self, throughput_mbps: u32) -> Self {
+        self.throughput_mbps = Some(throughput_mbps);
+        self
+    }
+
+    /// Returns the hints in their wire format.
+    pub fn to_protocol(&self) -> protocol::ChannelPerfHints {
+        protocol::ChannelPerfHints::new(
+            // Round a non-zero latency up so that it is not mistaken for no hint.
+            self.latency.map_or(0, |latency| {
+                latency.as_micros().clamp(1, u32::MAX.into()) as u32
+            }),
+            self.throughput_mbps.unwrap_or(0),
+        )
+    }
+}
+
 /// The channel type.
 #[derive(Debug, Copy, Clone, MeshPayload, Inspect)]
 #[inspect(external_tag)]
@@ -123,10 +123,11 @@ pub struct ChannelResources {
     pub event: Notify,
 }
 
-/// Control object for enabling subchannels.
+/// Control object for enabling subchannels and reporting device faults.
 #[derive(Debug, Default, Clone)]
 pub struct ChannelControl {
     send: Option<mesh::Sender<u16>>,
+    fault_send: Option<mesh::Sender<DeviceFault>>,
     max: u16,
 }
 
@@ -156,6 +157,29 @@ pub fn enable_subchannels(&self, count: u16) -> Result<(), TooManySubchannels> {
     pub fn max_subchannels(&self) -> u16 {
         self.max
     }
+
+    /// Reports that the device has failed and cannot continue.
+    ///
+    /// The device's channels are revoked from the guest, as if the device had
+    /// been surprise removed, instead of the failure taking down the VM. The
+    /// device is kept, with the fault visible via inspect, until its handle is
+    /// revoked. Only the first fault is recorded.
+    pub fn fail(&self, fault: DeviceFault) {
+        if let Some(send) = &self.fault_send {
+            send.send(fault);
+        }
+    }
+}
+
+/// A fatal device failure, reported with [`ChannelControl::fail`].
+#[derive(Debug, Error)]
+pub enum DeviceFault {
+    /// An internal invariant of the device no longer holds.
+    #[error("device invariant violated: {0}")]
+    InvariantViolated(&'static str),
+    /// The device hit an error that it cannot recover from.
+    #[error("unrecoverable device error")]
+    Unrecoverable(#[source] anyhow::Error),
 }
 
 /// A handle to an offered channel.
@@ -347,12 +371,14 @@ async fn offer_generic(
         .collect();
 
     let (subchannel_enable_send, subchannel_enable_recv) = mesh::channel();
+    let (fault_send, fault_recv) = mesh::channel();
     channel.install(DeviceResources {
         offer_resources: offer_result,
         gpadl_map: gpadl_map.clone().view(),
         channels: resources,
         channel_control: ChannelControl {
             send: Some(subchannel_enable_send),
+            fault_send: Some(fault_send),
             max: max_subchannels,
         },
     });
@@ -365,6 +391,7 @@ async fn offer_generic(
             events,
             gpadl_map,
             subchannel_enable_recv,
+            fault_recv,
         );
         device
             .run_channel(bus.as_ref(), channel.as_mut(), state_req_recv)
@@ -440,6 +467,9 @@ struct Device {
     events: Vec<Notify>,
     gpadl_map: Arc<GpadlMap>,
     subchannel_enable_recv: mesh::Receiver<u16>,
+    fault_recv: mesh::Receiver<DeviceFault>,
+    /// The fault that caused the device's channels to be revoked.
+    fault: Option<DeviceFault>,
 }
 
 impl Device {
@@ -449,6 +479,7 @@ fn new(
         events: Vec<Notify>,
         gpadl_map: Arc<GpadlMap>,
         subchannel_enable_recv: mesh::Receiver<u16>,
+        fault_recv: mesh::Receiver<DeviceFault>,
     ) -> Self {
         let open: Vec<bool> = vec![false];
         let subchannel_gpadls: Vec<BTreeSet<GpadlId>> = vec![];
@@ -464,6 +495,8 @@ fn new(
             events,
             gpadl_map,
             subchannel_enable_recv,
+            fault_recv,
+            fault: None,
         }
     }
 
@@ -477,6 +510,7 @@ async fn run_channel(
         enum Event {
             Request(usize, Option<ChannelRequest>),
             EnableSubchannels(u16),
+            Fault(DeviceFault),
             StateRequest(Result<StateRequest, RecvError>),
         }
 
@@ -490,7 +524,10 @@ enum Event {
                 (&mut self.requests).map(map_request),
                 select(
                     (&mut self.subchannel_enable_recv).map(Event::EnableSubchannels),
-                    (&mut state_req_recv).map(Event::StateRequest),
+                    select(
+                        (&mut self.fault_recv).map(Event::Fault),
+                        (&mut state_req_recv).map(Event::StateRequest),
+                    ),
                 ),
             );
             if let Some(event) = s.next().await {
@@ -500,9 +537,12 @@ enum Event {
                     }
                     Event::Request(_idx, None) => continue,
                     Event::EnableSubchannels(count) => {
-                        let offer = channel.offer();
-                        let _ = self.enable_channels(bus, &offer, count as usize + 1).await;
+                        if self.fault.is_none() {
+                            let offer = channel.offer();
+                            let _ = self.enable_channels(bus, &offer, count as usize + 1).await;
+                        }
                     }
+                    Event::Fault(fault) => self.handle_fault(fault, channel).await,
                     Event::StateRequest(Ok(request)) => {
                         self.handle_state_request(request, channel, bus).await;
                     }
@@ -513,8 +553,12 @@ enum Event {
                 }
             }
         }
-        // Revoke the channel.
-        drop(self.server_requests);
+        self.revoke(channel).await;
+    }
+
+    /// Revokes the channel and its subchannels, closing any that are open.
+    async fn revoke(&mut self, channel: &mut dyn VmbusDevice) {
+        self.server_requests.clear();
         // Wait for the revokes to finish.
         // When vmbus (sub)channels are closed, `self.requests` ends up with stale
         // channels i.e. (self.requests.value.is_none()) that are not getting cleaned
@@ -529,12 +573,29 @@ enum Event {
         }
 
         for subchannel_idx in (0..self.open.len()).rev() {
-            if self.open[subchannel_idx] {
+            if std::mem::take(&mut self.open[subchannel_idx]) {
                 channel.close(subchannel_idx as u16).await;
             }
         }
     }
 
+    /// Surprise removes the device from the guest after it reports a fault.
+    async fn handle_fault(&mut self, fault: DeviceFault, channel: &mut dyn VmbusDevice) {
+        if self.fault.is_some() {
+            return;
+        }
+        tracing::error!(
+            error = &fault as &dyn std::error::Error,
+            "device failed, revoking channels"
+        );
+        // Requests pended while stopped are for the revoked channels.
+        if let DeviceState::Stopped(pending_messages) = &mut self.state {
+            pending_messages.clear();
+        }
+        self.revoke(channel).await;
+        self.fault = Some(fault);
+    }
+
     #[instrument(level = "debug", skip_all, fields(channel_idx, ?request))]
     async fn handle_channel_request(
         &mut self,
@@ -568,8 +629,7 @@ async fn handle_channel_request(
                 .await
             }
             ChannelRequest::Gpadl(rpc) => rpc.handle_sync(|gpadl| {
-                self.handle_gpadl(gpadl.id, gpadl.count, gpadl.buf, channel_idx);
-                true
+                self.handle_gpadl(gpadl.id, gpadl.count, gpadl.buf, channel_idx)
             }),
             ChannelRequest::TeardownGpadl(rpc) => {
                 self.handle_teardown_gpadl(rpc, channel_idx);
@@ -638,14 +698,24 @@ async fn handle_close(&mut self, channel_idx: usize, channel: &mut dyn VmbusDevi
         }
     }
 
-    fn handle_gpadl(&mut self, id: GpadlId, count: u16, buf: Vec<u64>, channel_idx: usize) {
-        self.gpadl_map.add(
-            id,
-            MultiPagedRangeBuf::from_range_buffer(count.into(), buf).unwrap(),
-        );
+    /// Adds a GPADL, returning whether it was accepted.
+    fn handle_gpadl(&mut self, id: GpadlId, count: u16, buf: Vec<u64>, channel_idx: usize) -> bool {
+        let buf = match MultiPagedRangeBuf::from_range_buffer(count.into(), buf) {
+            Ok(buf) => buf,
+            Err(err) => {
+                tracelimit::warn_ratelimited!(
+                    gpadl_id = id.0,
+                    error = &err as &dyn std::error::Error,
+                    "invalid gpadl"
+                );
+                return false;
+            }
+        };
+        self.gpadl_map.add(id, buf);
         if channel_idx > 0 {
             self.subchannel_gpadls[channel_idx - 1].insert(id);
         }
+        true
     }
 
     fn handle_teardown_gpadl(&mut self, rpc: Rpc<GpadlId, ()>, channel_idx: usize) {
@@ -714,6 +784,11 @@ async fn handle_state_request(
             }
             StateRequest::Save(rpc) => {
                 rpc.handle_failable(async |()| {
+                    // A failed device has no channels to restore, so let it
+                    // be reoffered instead.
+                    if self.fault.is_some() {
+                        return Ok(None);
+                    }
                     if let Some(channel) = channel.supports_save_restore() {
                         channel.save().await.map(Some)
                     } else {
@@ -724,6 +799,9 @@ async fn handle_state_request(
             }
             StateRequest::Restore(rpc) => {
                 rpc.handle_failable(async |buffer| {
+                    if let Some(fault) = &self.fault {
+                        anyhow::bail!("device failed: {fault}");
+                    }
                     let channel = channel
                         .supports_save_restore()
                         .context("saved state not supported")?;
@@ -741,7 +819,10 @@ async fn handle_state_request(
                 .await;
             }
             StateRequest::Inspect(deferred) => {
-                deferred.inspect(&mut *channel);
+                deferred.respond(|resp| {
+                    resp.field("fault", self.fault.as_ref().map(inspect::AsDisplay))
+                        .merge(&mut *channel);
+                });
             }
         }
     }
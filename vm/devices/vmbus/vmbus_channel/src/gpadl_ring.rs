@@ -19,6 +19,7 @@
 use ring::IncomingRing;
 use ring::OutgoingRing;
 use std::fmt::Debug;
+use std::sync::Arc;
 use std::sync::atomic::AtomicU8;
 use std::sync::atomic::AtomicU32;
 use vmbus_ring as ring;
@@ -128,13 +129,20 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 #[derive(Debug, Clone)]
 pub struct GpadlRingMem {
     ring: ring::PagedRingMem<GpadlPagedMemory>,
+    /// The data page gpns, stored twice in a row for [`ring::RingMem::data_gpns`].
+    data_gpns: Arc<[u64]>,
+    mem: GuestMemory,
 }
 
 impl GpadlRingMem {
     /// Creates a new ring memory backed by `gpadl` and `mem`.
     pub fn new(gpadl: AlignedGpadlView, mem: &GuestMemory) -> Result<Self, GuestMemoryError> {
+        let data_gpns = &gpadl.gpns()[1..];
+        let data_gpns = data_gpns.iter().chain(data_gpns).copied().collect();
         Ok(Self {
             ring: ring::PagedRingMem::new(GpadlPagedMemory::new(gpadl, mem)?),
+            data_gpns,
+            mem: mem.clone(),
         })
     }
 }
@@ -169,6 +177,16 @@ fn write_aligned(&self, addr: usize, data: &[u8]) {
     fn control(&self) -> &[AtomicU32; vmbus_ring::CONTROL_WORD_COUNT] {
         self.ring.control()
     }
+
+    #[inline]
+    fn data_gpns(&self) -> Option<&[u64]> {
+        Some(&self.data_gpns)
+    }
+
+    #[inline]
+    fn guest_memory(&self) -> Option<&GuestMemory> {
+        Some(&self.mem)
+    }
 }
 
 /// A ring buffer error.
@@ -0,0 +1,205 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Support for opening and closing groups of channels, such as a primary
+//! channel and its subchannels, as a unit.
+//!
+//! [`open_all`] implements the all-or-nothing semantics for any channel
+//! request interface; [`open_channels`] and [`close_channels`] apply it to the
+//! bus-side [`ChannelRequest`] interface, and `vmbus_client::group` applies it
+//! to the client's.
+
+use crate::bus::ChannelRequest;
+use crate::bus::OpenRequest;
+use futures::FutureExt;
+use futures::future::join_all;
+use mesh::rpc::RpcError;
+use mesh::rpc::RpcSend;
+use std::future::Future;
+use thiserror::Error;
+
+/// An error opening a group of channels.
+#[derive(Debug, Error)]
+#[error("failed to open channel {index} of the group")]
+pub struct GroupOpenError<E> {
+    /// The index of the first channel in the group that failed to open.
+    pub index: usize,
+    /// The error opening the channel.
+    #[source]
+    pub error: E,
+}
+
+/// An error opening a single channel via [`ChannelRequest::Open`].
+#[derive(Debug, Error)]
+pub enum ChannelOpenError {
+    /// The open request was rejected.
+    #[error("the channel open request was rejected")]
+    Rejected,
+    /// The open request could not be delivered.
+    #[error("the channel open request could not be delivered")]
+    Rpc(#[source] RpcError),
+}
+
+/// Opens a group of channels with all-or-nothing semantics.
+///
+/// `open` is called to start opening each channel, and the resulting futures
+/// are awaited concurrently. If all of them succeed, the outputs are returned
+/// in the same order as `requests`. If any of them fail, `close` is called for
+/// each channel that was opened successfully before returning the error, so
+/// that on failure no channel in the group is left open.
+pub async fn open_all<H, R, T, E, Open, Close>(
+    requests: impl IntoIterator<Item = (H, R)>,
+    mut open: impl FnMut(&H, R) -> Open,
+    mut close: impl FnMut(&H) -> Close,
+) -> Result<Vec<T>, GroupOpenError<E>>
+where
+    Open: Future<Output = Result<T, E>>,
+    Close: Future<Output = ()>,
+{
+    let (handles, opens): (Vec<_>, Vec<_>) = requests
+        .into_iter()
+        .map(|(handle, request)| {
+            let open = open(&handle, request);
+            (handle, open)
+        })
+        .unzip();
+
+    let mut opened = Vec::with_capacity(handles.len());
+    let mut failure = None;
+    for (index, result) in join_all(opens).await.into_iter().enumerate() {
+        match result {
+            Ok(output) => opened.push((index, output)),
+            Err(error) => {
+                failure.get_or_insert(GroupOpenError { index, error });
+            }
+        }
+    }
+
+    let Some(err) = failure else {
+        return Ok(opened.into_iter().map(|(_, output)| output).collect());
+    };
+
+    tracing::warn!(
+        index = err.index,
+        "channel group open failed, closing opened channels"
+    );
+    join_all(opened.iter().map(|&(index, _)| close(&handles[index]))).await;
+    Err(err)
+}
+
+/// Opens a group of bus channels with all-or-nothing semantics. See
+/// [`open_all`].
+pub async fn open_channels(
+    requests: impl IntoIterator<Item = (&mesh::Sender<ChannelRequest>, OpenRequest)>,
+) -> Result<(), GroupOpenError<ChannelOpenError>> {
+    open_all(
+        requests,
+        |send, request| {
+            send.call(ChannelRequest::Open, request)
+                .map(|result| match result {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err(ChannelOpenError::Rejected),
+                    Err(err) => Err(ChannelOpenError::Rpc(err)),
+                })
+        },
+        |send| send.call(ChannelRequest::Close, ()).map(drop),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Closes a group of channels, completing when all of the close requests have
+/// been processed.
+pub async fn close_channels(senders: impl IntoIterator<Item = &mesh::Sender<ChannelRequest>>) {
+    join_all(
+        senders
+            .into_iter()
+            .map(|send| send.call(ChannelRequest::Close, ())),
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::open_all;
+    use futures::FutureExt;
+    use pal_async::async_test;
+    use parking_lot::Mutex;
+
+    /// Opens channels `0..count`, failing the ones in `fail`, and returns the
+    /// result along with the channels that were closed.
+    async fn run(count: usize, fail: &[usize]) -> (Result<Vec<usize>, usize>, Vec<usize>) {
+        let closed = Mutex::new(Vec::new());
+        let result = open_all(
+            (0..count).map(|i| (i, i * 10)),
+            |&i, request| {
+                let result = if fail.contains(&i) {
+                    Err(i)
+                } else {
+                    Ok(request)
+                };
+                async move { result }
+            },
+            |&i| {
+                closed.lock().push(i);
+                async {}
+            },
+        )
+        .await
+        .map_err(|err| {
+            assert_eq!(err.index, err.error);
+            err.index
+        });
+        (result, closed.into_inner())
+    }
+
+    #[async_test]
+    async fn test_open_all_success() {
+        assert_eq!(run(3, &[]).await, (Ok(vec![0, 10, 20]), vec![]));
+    }
+
+    #[async_test]
+    async fn test_open_all_empty() {
+        assert_eq!(run(0, &[]).await, (Ok(vec![]), vec![]));
+    }
+
+    #[async_test]
+    async fn test_open_all_rollback() {
+        // Only the channels that opened are closed, and the first failure is
+        // reported.
+        assert_eq!(run(4, &[1, 3]).await, (Err(1), vec![0, 2]));
+        assert_eq!(run(2, &[0, 1]).await, (Err(0), vec![]));
+    }
+
+    #[async_test]
+    async fn test_open_all_concurrent() {
+        // The first open completes only after the second one has run, so this
+        // only finishes if the opens are awaited concurrently.
+        let (send, recv) = futures::channel::oneshot::channel::<()>();
+        let mut send = Some(send);
+        let mut recv = Some(recv);
+        let result = open_all(
+            [(0, ()), (1, ())],
+            |&i, ()| {
+                if i == 0 {
+                    let recv = recv.take().unwrap();
+                    async move {
+                        recv.await.unwrap();
+                        Ok::<_, ()>(0)
+                    }
+                    .boxed()
+                } else {
+                    let send = send.take().unwrap();
+                    async move {
+                        send.send(()).unwrap();
+                        Ok(1)
+                    }
+                    .boxed()
+                }
+            },
+            |_| async {},
+        )
+        .await;
+        assert_eq!(result.unwrap(), vec![0, 1]);
+    }
+}
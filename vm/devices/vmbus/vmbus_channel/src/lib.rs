@@ -9,6 +9,7 @@
 pub mod channel;
 pub mod gpadl;
 pub mod gpadl_ring;
+pub mod group;
 pub mod offer;
 pub mod resources;
 pub mod simple;
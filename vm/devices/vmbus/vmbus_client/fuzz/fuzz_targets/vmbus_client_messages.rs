@@ -0,0 +1,238 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Fuzzes the vmbus client's handling of messages from a hostile host.
+
+#![cfg_attr(all(target_os = "linux", target_env = "gnu"), no_main)]
+#![expect(missing_docs)]
+
+use arbitrary::Arbitrary;
+use arbitrary::Unstructured;
+use futures::FutureExt;
+use futures::StreamExt;
+use futures::future::OptionFuture;
+use guid::Guid;
+use pal_async::DefaultPool;
+use pal_event::Event;
+use std::collections::VecDeque;
+use std::pin::pin;
+use std::task::Context;
+use std::task::Poll;
+use vmbus_async::async_dgram::AsyncRecv;
+use vmbus_client::PollPostMessage;
+use vmbus_client::SynicEventClient;
+use vmbus_client::VmbusClientBuilder;
+use vmbus_client::VmbusMessageSource;
+use vmbus_client::pacing::PostError;
+use vmbus_core::protocol;
+use vmbus_core::trace::TRACE_MAGIC;
+use vmbus_core::trace::TraceDirection;
+use vmbus_core::trace::read_trace;
+use xtask_fuzz::fuzz_eprintln;
+use xtask_fuzz::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+enum FuzzMessage {
+    /// Arbitrary bytes, which may not even contain a message header.
+    Raw(Vec<u8>),
+    /// A message with a well-formed header, to get past header parsing.
+    Typed { message_type: u8, payload: Vec<u8> },
+}
+
+impl FuzzMessage {
+    fn into_bytes(self) -> Vec<u8> {
+        let mut data = match self {
+            FuzzMessage::Raw(data) => data,
+            FuzzMessage::Typed {
+                message_type,
+                payload,
+            } => {
+                let mut data = Vec::new();
+                data.extend_from_slice(&u32::from(message_type).to_ne_bytes());
+                data.extend_from_slice(&0u32.to_ne_bytes());
+                data.extend_from_slice(&payload);
+                data
+            }
+        };
+        data.truncate(protocol::MAX_MESSAGE_SIZE);
+        data
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+struct FuzzInput {
+    /// Whether to start connecting before delivering host messages, so that
+    /// the connection handshake can be reached.
+    connect: bool,
+    messages: Vec<FuzzMessage>,
+}
+
+impl FuzzInput {
+    /// Parses the fuzzer input. A message trace recorded by the server or
+    /// client is accepted as is, replaying its host messages while
+    /// connecting, so recorded traces can seed the corpus.
+    fn parse(input: &[u8]) -> arbitrary::Result<Self> {
+        if input.starts_with(&TRACE_MAGIC)
+            && let Ok(records) = read_trace(input)
+        {
+            return Ok(Self {
+                connect: true,
+                messages: records
+                    .into_iter()
+                    .filter(|record| record.direction == TraceDirection::HostToGuest)
+                    .map(|record| FuzzMessage::Raw(record.data))
+                    .collect(),
+            });
+        }
+        Unstructured::new(input).arbitrary()
+    }
+}
+
+/// A batch of host messages, and a sender to notify once the client has
+/// handled them all.
+type Batch = (VecDeque<Vec<u8>>, mesh::OneshotSender<()>);
+
+/// A message source that delivers batches of host messages.
+struct FuzzMessageSource {
+    batch_recv: mesh::Receiver<Batch>,
+    batch: Option<Batch>,
+}
+
+impl AsyncRecv for FuzzMessageSource {
+    fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+        bufs: &mut [std::io::IoSliceMut<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        loop {
+            if let Some((mut messages, done)) = self.batch.take() {
+                if let Some(message) = messages.pop_front() {
+                    let mut remaining = message.as_slice();
+                    let mut total_size = 0;
+                    for buf in bufs.iter_mut() {
+                        let size = buf.len().min(remaining.len());
+                        buf[..size].copy_from_slice(&remaining[..size]);
+                        remaining = &remaining[size..];
+                        total_size += size;
+                    }
+                    self.batch = Some((messages, done));
+                    // A zero-length read is end of stream, which the client
+                    // treats as fatal, so skip empty messages.
+                    if total_size == 0 {
+                        continue;
+                    }
+                    return Poll::Ready(Ok(total_size));
+                }
+                // The client only reads the next message after handling the
+                // previous one, so the whole batch has been handled.
+                done.send(());
+            }
+            match self.batch_recv.poll_recv(cx) {
+                Poll::Ready(Ok(batch)) => self.batch = Some(batch),
+                Poll::Ready(Err(_)) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl VmbusMessageSource for FuzzMessageSource {}
+
+/// Accepts all messages posted by the client, reporting them to the harness.
+struct FuzzPoster {
+    send: mesh::Sender<u32>,
+}
+
+impl PollPostMessage for FuzzPoster {
+    fn poll_post_message(
+        &mut self,
+        _cx: &mut Context<'_>,
+        _connection_id: u32,
+        typ: u32,
+        _msg: &[u8],
+    ) -> Poll<Result<(), PostError>> {
+        self.send.send(typ);
+        Poll::Ready(Ok(()))
+    }
+}
+
+struct NoopSynicEvents;
+
+impl SynicEventClient for NoopSynicEvents {
+    fn map_event(&self, _event_flag: u16, _event: &Event) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn unmap_event(&self, _event_flag: u16) {}
+
+    fn signal_event(&self, _connection_id: u32, _event_flag: u16) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn do_fuzz(input: &[u8]) -> arbitrary::Result<()> {
+    let input = FuzzInput::parse(input)?;
+    fuzz_eprintln!("{:?}", input);
+
+    DefaultPool::run_with(async |driver| {
+        let (batch_send, batch_recv) = mesh::channel();
+        let (post_send, mut post_recv) = mesh::channel();
+        let mut client = VmbusClientBuilder::new(
+            &driver,
+            NoopSynicEvents,
+            FuzzMessageSource {
+                batch_recv,
+                batch: None,
+            },
+            FuzzPoster { send: post_send },
+        )
+        .build(&driver);
+        client.start();
+
+        let mut connect = pin!(OptionFuture::from(
+            input
+                .connect
+                .then(|| client.connect(0, None, Guid::ZERO).fuse())
+        ));
+
+        if input.connect {
+            // Wait for the client to send its initiate contact message, so
+            // that the host messages are handled while connecting.
+            futures::select! { // race semantics
+                _ = post_recv.next() => {}
+                _ = connect => unreachable!("connect cannot complete without the host"),
+            }
+        }
+
+        let messages = input
+            .messages
+            .into_iter()
+            .map(FuzzMessage::into_bytes)
+            .collect();
+        let (done_send, done_recv) = mesh::oneshot();
+        batch_send.send((messages, done_send));
+
+        // Keep the connect request alive until the client has handled every
+        // message, since the host may complete it along the way.
+        let mut done = done_recv.fuse();
+        loop {
+            futures::select! { // merge semantics
+                r = done => {
+                    r.expect("client task exited");
+                    break;
+                }
+                _ = connect => {}
+                _ = post_recv.next() => {}
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fuzz_target!(|input: &[u8]| {
+    xtask_fuzz::init_tracing_if_repro();
+
+    let _ = do_fuzz(input);
+
+    // Always keep the corpus, since errors are a reasonable outcome.
+});
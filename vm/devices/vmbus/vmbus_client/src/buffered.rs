@@ -0,0 +1,178 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A [`VmbusMessageSource`] built on a raw synic message reader.
+//!
+//! [`VmbusMessageSource::pause_message_stream`] requires the source to return
+//! the messages that were already pending when it was paused, and then EOF.
+//! [`BufferedMessageSource`] implements this once on top of any
+//! [`PollSynicMessage`], so that readers of the synic message page only need
+//! to return messages as they arrive.
+
+use crate::VmbusMessageSource;
+use hvdef::HvMessage;
+use std::collections::VecDeque;
+use std::io;
+use std::io::IoSliceMut;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+use std::task::ready;
+use vmbus_async::async_dgram::AsyncRecv;
+
+/// Reads messages from the synic, for example from the SINT's slot in the
+/// message page.
+pub trait PollSynicMessage: Send {
+    /// Polls for the next message, consuming it from the synic.
+    fn poll_message(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<HvMessage>>;
+
+    /// Called when the stream is paused or resumed. The reader is not polled
+    /// while paused; this is for readers that must also tell the synic to
+    /// stop delivering messages.
+    fn set_paused(&mut self, paused: bool) {
+        let _ = paused;
+    }
+}
+
+/// A [`VmbusMessageSource`] that implements pausing on top of a
+/// [`PollSynicMessage`].
+///
+/// When paused, messages the reader has ready are drained into an internal
+/// buffer. The buffer is returned to the client, followed by EOF. Messages that
+/// arrive after the pause are left in the synic until the stream is resumed.
+pub struct BufferedMessageSource<R> {
+    reader: R,
+    buffered: VecDeque<HvMessage>,
+    paused: bool,
+}
+
+impl<R: PollSynicMessage> BufferedMessageSource<R> {
+    /// Creates a new message source reading from `reader`.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffered: VecDeque::new(),
+            paused: false,
+        }
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Moves the messages the reader has ready into the buffer.
+    fn drain_ready(&mut self) {
+        let mut cx = Context::from_waker(Waker::noop());
+        while let Poll::Ready(r) = self.reader.poll_message(&mut cx) {
+            match r {
+                Ok(msg) => self.buffered.push_back(msg),
+                Err(err) => {
+                    tracelimit::warn_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "failed to read synic message while pausing"
+                    );
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn copy_payload(msg: &HvMessage, mut bufs: &mut [IoSliceMut<'_>]) -> usize {
+    let mut remaining = msg.payload();
+    let mut total_size = 0;
+    while !remaining.is_empty() && !bufs.is_empty() {
+        let size = bufs[0].len().min(remaining.len());
+        bufs[0][..size].copy_from_slice(&remaining[..size]);
+        remaining = &remaining[size..];
+        bufs = &mut bufs[1..];
+        total_size += size;
+    }
+    total_size
+}
+
+impl<R: PollSynicMessage> AsyncRecv for BufferedMessageSource<R> {
+    fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        if let Some(msg) = self.buffered.pop_front() {
+            return Poll::Ready(Ok(copy_payload(&msg, bufs)));
+        }
+        if self.paused {
+            return Poll::Ready(Ok(0));
+        }
+        let msg = ready!(self.reader.poll_message(cx))?;
+        Poll::Ready(Ok(copy_payload(&msg, bufs)))
+    }
+}
+
+impl<R: PollSynicMessage> VmbusMessageSource for BufferedMessageSource<R> {
+    fn pause_message_stream(&mut self) {
+        if !self.paused {
+            self.drain_ready();
+            self.paused = true;
+            self.reader.set_paused(true);
+        }
+    }
+
+    fn resume_message_stream(&mut self) {
+        if self.paused {
+            self.paused = false;
+            self.reader.set_paused(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hvdef::HvMessageType;
+    use pal_async::async_test;
+    use vmbus_async::async_dgram::AsyncRecvExt;
+
+    struct TestReader(mesh::Receiver<HvMessage>);
+
+    impl PollSynicMessage for TestReader {
+        fn poll_message(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<HvMessage>> {
+            self.0
+                .poll_recv(cx)
+                .map_err(|_| io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    fn msg(n: u8) -> HvMessage {
+        HvMessage::new(HvMessageType::HvMessageTypeNone, 0, &[n])
+    }
+
+    #[async_test]
+    async fn test_pause_returns_pending_then_eof() {
+        let (send, recv) = mesh::channel();
+        let mut source = BufferedMessageSource::new(TestReader(recv));
+        let mut buf = [0; 1];
+
+        send.send(msg(1));
+        assert_eq!(source.recv(&mut buf).await.unwrap(), 1);
+        assert_eq!(buf[0], 1);
+
+        send.send(msg(2));
+        send.send(msg(3));
+        source.pause_message_stream();
+
+        // Sent after pausing, so not returned until resumed.
+        send.send(msg(4));
+
+        for expected in [2, 3] {
+            assert_eq!(source.recv(&mut buf).await.unwrap(), 1);
+            assert_eq!(buf[0], expected);
+        }
+        assert_eq!(source.recv(&mut buf).await.unwrap(), 0);
+        assert_eq!(source.recv(&mut buf).await.unwrap(), 0);
+
+        source.resume_message_stream();
+        assert_eq!(source.recv(&mut buf).await.unwrap(), 1);
+        assert_eq!(buf[0], 4);
+    }
+}
@@ -10,6 +10,7 @@
 use anyhow::Context as _;
 use futures::FutureExt;
 use futures_concurrency::future::Race;
+use inspect::Inspect;
 use inspect::InspectMut;
 use mesh::rpc::RpcSend;
 use pal_async::driver::SpawnDriver;
@@ -36,6 +37,7 @@
 use vmcore::notify::PolledNotify;
 
 /// Input parameters when opening a vmbus channel.
+#[derive(Debug, Copy, Clone, Inspect)]
 pub struct OpenParams {
     /// The number of pages to use for the ring buffer.
     pub ring_pages: u16,
@@ -43,6 +45,37 @@ pub struct OpenParams {
     pub ring_offset_in_pages: u16,
 }
 
+impl OpenParams {
+    /// The minimum number of pages in each of the incoming and outgoing
+    /// rings: one for the control page and at least one for data.
+    pub const MIN_RING_PAGES: u16 = 2;
+
+    /// Returns parameters for a ring buffer of `ring_pages` pages, split
+    /// evenly between the outgoing and incoming rings.
+    pub fn split_evenly(ring_pages: u16) -> Self {
+        Self {
+            ring_pages,
+            ring_offset_in_pages: ring_pages / 2,
+        }
+    }
+
+    /// Validates that both the outgoing and incoming rings are large enough
+    /// to be used.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let out_pages = self.ring_offset_in_pages;
+        let in_pages = self.ring_pages.checked_sub(self.ring_offset_in_pages);
+        if out_pages < Self::MIN_RING_PAGES || in_pages.is_none_or(|n| n < Self::MIN_RING_PAGES) {
+            anyhow::bail!(
+                "invalid ring layout ({} pages, offset {}): each ring needs at least {} pages",
+                self.ring_pages,
+                self.ring_offset_in_pages,
+                Self::MIN_RING_PAGES
+            );
+        }
+        Ok(())
+    }
+}
+
 /// The memory type used for the vmbus channel ring buffer.
 pub type MemoryBlockRingMem = SingleMappedRingMem<MemoryBlockView>;
 
@@ -53,6 +86,7 @@ pub async fn open_channel(
     params: OpenParams,
     dma_client: &dyn DmaClient,
 ) -> anyhow::Result<RawAsyncChannel<MemoryBlockRingMem>> {
+    params.validate()?;
     let gpadl =
         dma_client.allocate_dma_buffer(vmbus_ring::PAGE_SIZE * params.ring_pages as usize)?;
 
@@ -124,6 +158,7 @@ async fn open(
                     },
                     incoming_event: Some(host_to_guest.clone()),
                     use_vtl2_connection_id: true,
+                    redirect_interrupt: false,
                 },
             )
             .await?;
@@ -272,3 +307,45 @@ fn poll_for_signal(&self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Ch
         self.host_to_guest.poll_wait(cx).map(Ok)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::OpenParams;
+
+    #[test]
+    fn test_split_evenly() {
+        let params = OpenParams::split_evenly(20);
+        assert_eq!((params.ring_pages, params.ring_offset_in_pages), (20, 10));
+        params.validate().unwrap();
+
+        // With an odd page count, the extra page goes to the incoming ring.
+        let params = OpenParams::split_evenly(5);
+        assert_eq!((params.ring_pages, params.ring_offset_in_pages), (5, 2));
+        params.validate().unwrap();
+    }
+
+    #[test]
+    fn test_validate_too_small() {
+        for ring_pages in [0, 1, 2, 3] {
+            OpenParams::split_evenly(ring_pages).validate().unwrap_err();
+        }
+        OpenParams::split_evenly(OpenParams::MIN_RING_PAGES * 2)
+            .validate()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_validate_offset() {
+        let params = |ring_pages, ring_offset_in_pages| OpenParams {
+            ring_pages,
+            ring_offset_in_pages,
+        };
+        params(10, 2).validate().unwrap();
+        params(10, 8).validate().unwrap();
+        params(10, 1).validate().unwrap_err();
+        params(10, 9).validate().unwrap_err();
+        // The offset is beyond the end of the ring.
+        params(10, 11).validate().unwrap_err();
+        params(u16::MAX, u16::MAX).validate().unwrap_err();
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Structured lifecycle events reported by the vmbus client.
+//!
+//! Unlike trace logs, these events are intended to be consumed
+//! programmatically, e.g. to export telemetry about slow host responses.
+
+use inspect::Inspect;
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+use vmbus_channel::bus::OfferKey;
+use vmbus_core::VersionInfo;
+use vmbus_core::protocol::ChannelId;
+use vmbus_core::protocol::GpadlId;
+
+/// A state transition observed by the vmbus client.
+#[derive(Debug, Clone)]
+pub struct ClientEvent {
+    /// The time at which the transition occurred.
+    pub timestamp: Instant,
+    /// The transition.
+    pub kind: ClientEventKind,
+}
+
+/// The kind of state transition reported by a [`ClientEvent`].
+///
+/// Events that complete a request to the host include the latency between
+/// sending the request and receiving the response, if known.
+#[derive(Debug, Clone)]
+pub enum ClientEventKind {
+    /// The host accepted a protocol version.
+    VersionNegotiated {
+        version: VersionInfo,
+        latency: Option<Duration>,
+    },
+    /// The host failed to accept any supported protocol version, or refused
+    /// the connection.
+    ConnectFailed { latency: Option<Duration> },
+    /// The host delivered all initial offers, and the client is connected.
+    Connected {
+        offer_count: usize,
+        latency: Option<Duration>,
+    },
+    /// The host completed an unload request.
+    Disconnected { latency: Option<Duration> },
+    /// The host offered a channel.
+    ChannelOffered {
+        channel_id: ChannelId,
+        key: OfferKey,
+    },
+    /// The host completed an open request.
+    ChannelOpened {
+        channel_id: ChannelId,
+        key: OfferKey,
+        latency: Option<Duration>,
+    },
+    /// The host failed an open request.
+    ChannelOpenFailed {
+        channel_id: ChannelId,
+        key: OfferKey,
+        status: u32,
+        latency: Option<Duration>,
+    },
+    /// The client closed a channel.
+    ChannelClosed {
+        channel_id: ChannelId,
+        key: OfferKey,
+    },
+    /// The host rescinded a channel offer.
+    ChannelRescinded {
+        channel_id: ChannelId,
+        key: OfferKey,
+    },
+    /// The host completed a GPADL creation request.
+    GpadlCreated {
+        channel_id: ChannelId,
+        gpadl_id: GpadlId,
+        latency: Option<Duration>,
+    },
+    /// The host failed a GPADL creation request.
+    GpadlCreateFailed {
+        channel_id: ChannelId,
+        gpadl_id: GpadlId,
+        status: i32,
+        latency: Option<Duration>,
+    },
+    /// The host completed a GPADL teardown request.
+    GpadlTornDown {
+        channel_id: ChannelId,
+        gpadl_id: GpadlId,
+        latency: Option<Duration>,
+    },
+}
+
+/// An operation whose latency is tracked while waiting for the host.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum PendingOperation {
+    Connect,
+    Unload,
+    Open(ChannelId),
+    CreateGpadl(GpadlId),
+    TeardownGpadl(GpadlId),
+}
+
+/// Sends [`ClientEvent`]s to a subscriber, tracking when outstanding
+/// operations were started so that response latency can be reported.
+#[derive(Inspect)]
+pub(crate) struct ClientEventSink {
+    #[inspect(with = "|x| x.is_some()")]
+    send: Option<mesh::Sender<ClientEvent>>,
+    #[inspect(with = "|x| x.len()")]
+    started: HashMap<PendingOperation, Instant>,
+}
+
+impl ClientEventSink {
+    pub fn new(send: Option<mesh::Sender<ClientEvent>>) -> Self {
+        Self {
+            send,
+            started: HashMap::new(),
+        }
+    }
+
+    pub fn into_sender(self) -> Option<mesh::Sender<ClientEvent>> {
+        self.send
+    }
+
+    /// Records the start of an operation. If the operation is already being
+    /// tracked (e.g. when retrying version negotiation), the original start
+    /// time is kept.
+    pub fn start(&mut self, op: PendingOperation) {
+        if self.send.is_some() {
+            self.started.entry(op).or_insert_with(Instant::now);
+        }
+    }
+
+    /// Returns the time since an operation started, without finishing it.
+    pub fn elapsed(&self, op: PendingOperation) -> Option<Duration> {
+        self.started.get(&op).map(|start| start.elapsed())
+    }
+
+    /// Stops tracking an operation, returning the time since it started.
+    pub fn finish(&mut self, op: PendingOperation) -> Option<Duration> {
+        self.started.remove(&op).map(|start| start.elapsed())
+    }
+
+    /// Sends an event to the subscriber, if any.
+    pub fn emit(&mut self, kind: ClientEventKind) {
+        if let Some(send) = &self.send {
+            if send.is_closed() {
+                self.send = None;
+                self.started.clear();
+                return;
+            }
+            send.send(ClientEvent {
+                timestamp: Instant::now(),
+                kind,
+            });
+        }
+    }
+}
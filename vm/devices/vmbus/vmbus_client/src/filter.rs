@@ -2,7 +2,8 @@
 // Licensed under the MIT License.
 
 //! Support for filtering vmbus offers. This is useful for redirecting offers to
-//! separate client drivers.
+//! separate client drivers, and for hiding or rewriting offers before they
+//! are relayed to a guest.
 
 use crate::ConnectResult;
 use crate::OfferInfo;
@@ -16,6 +17,25 @@
 use std::pin::pin;
 use vmbus_core::protocol::OfferChannel;
 
+/// Decides which offers from the host a [`VmbusClient`](crate::VmbusClient)
+/// delivers, and what they look like.
+///
+/// Set with [`VmbusClientBuilder::offer_filter`](crate::VmbusClientBuilder::offer_filter).
+pub trait OfferFilter: Send {
+    /// Returns the offer to deliver, possibly modified, or `None` to hide it.
+    ///
+    /// A hidden channel is released as if its [`OfferInfo`] had been dropped.
+    /// Changes only affect the delivered offer; the client still uses the
+    /// host's offer when communicating with the host.
+    fn filter(&mut self, offer: OfferChannel) -> Option<OfferChannel>;
+}
+
+impl<F: FnMut(OfferChannel) -> Option<OfferChannel> + Send> OfferFilter for F {
+    fn filter(&mut self, offer: OfferChannel) -> Option<OfferChannel> {
+        self(offer)
+    }
+}
+
 /// A filter.
 ///
 /// Create using [`ClientFilterBuilder`].
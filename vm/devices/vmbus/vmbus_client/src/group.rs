@@ -0,0 +1,47 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Support for opening and closing groups of channels, such as a primary
+//! channel and its subchannels, as a unit.
+//!
+//! This applies [`vmbus_channel::group::open_all`] to the client's
+//! [`ChannelRequest`] interface.
+
+use crate::ChannelRequest;
+use crate::OpenOutput;
+use crate::OpenRequest;
+use futures::FutureExt;
+use futures::future::join_all;
+use mesh::error::RemoteError;
+use mesh::rpc::RpcError;
+use mesh::rpc::RpcSend;
+
+/// An error opening a group of channels.
+pub type GroupOpenError = vmbus_channel::group::GroupOpenError<RpcError<RemoteError>>;
+
+/// Opens a group of channels with all-or-nothing semantics.
+///
+/// If all of the opens succeed, the outputs are returned in the same order as
+/// `requests`. Otherwise, the channels that were opened successfully are
+/// closed again before returning the error.
+pub async fn open_channels(
+    requests: impl IntoIterator<Item = (&mesh::Sender<ChannelRequest>, OpenRequest)>,
+) -> Result<Vec<OpenOutput>, GroupOpenError> {
+    vmbus_channel::group::open_all(
+        requests,
+        |send, request| send.call_failable(ChannelRequest::Open, request),
+        |send| send.call(ChannelRequest::Close, ()).map(drop),
+    )
+    .await
+}
+
+/// Closes a group of channels, completing when the client has processed all
+/// of the close requests.
+pub async fn close_channels(senders: impl IntoIterator<Item = &mesh::Sender<ChannelRequest>>) {
+    join_all(
+        senders
+            .into_iter()
+            .map(|send| send.call(ChannelRequest::Close, ())),
+    )
+    .await;
+}
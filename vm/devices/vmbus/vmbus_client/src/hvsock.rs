@@ -4,29 +4,83 @@
 use crate::OfferInfo;
 use inspect::Inspect;
 use mesh::rpc::Rpc;
+use pal_async::timer::Instant;
+use std::time::Duration;
 use vmbus_core::HvsockConnectRequest;
 use vmbus_core::protocol;
 
+/// The result of a guest-to-host hvsocket connect request.
+#[derive(Debug)]
+pub enum HvsockConnectResult {
+    /// The host offered a channel for the connection.
+    Connected(OfferInfo),
+    /// The host rejected the connection with the given status.
+    Failed(i32),
+    /// The host did not respond within the timeout.
+    TimedOut,
+    /// The client was stopped before the host responded.
+    Cancelled,
+}
+
 /// Tracks guest-to-host hvsocket requests that the host has not responded to yet.
 #[derive(Inspect)]
 pub(crate) struct HvsockRequestTracker {
-    #[inspect(with = "|x| inspect::iter_by_index(x).map_value(|x| x.input())")]
-    pending_requests: Vec<Request>,
+    #[inspect(with = "|x| inspect::iter_by_index(x).map_value(|x| x.rpc.input())")]
+    pending_requests: Vec<PendingRequest>,
+    #[inspect(debug)]
+    timeout: Duration,
 }
 
-pub(crate) type Request = Rpc<HvsockConnectRequest, Option<OfferInfo>>;
+pub(crate) type Request = Rpc<HvsockConnectRequest, HvsockConnectResult>;
+
+struct PendingRequest {
+    rpc: Request,
+    deadline: Instant,
+}
 
 impl HvsockRequestTracker {
-    /// Create a new request tracker.
-    pub fn new() -> Self {
+    /// The default time to wait for the host to respond to a request.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Create a new request tracker that fails requests that the host has
+    /// not responded to within `timeout`.
+    pub fn new(timeout: Duration) -> Self {
         Self {
             pending_requests: Vec::new(),
+            timeout,
         }
     }
 
-    /// Adds a new request to be tracked.
-    pub fn add_request(&mut self, request: Request) {
-        self.pending_requests.push(request);
+    /// The time to wait for the host to respond to a request.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Adds a new request to be tracked, made at `now`.
+    pub fn add_request(&mut self, request: Request, now: Instant) {
+        self.pending_requests.push(PendingRequest {
+            rpc: request,
+            deadline: now.saturating_add(self.timeout),
+        });
+    }
+
+    /// Returns the time at which the next request times out, if any.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending_requests.iter().map(|r| r.deadline).min()
+    }
+
+    /// Removes and returns the requests that have timed out as of `now`.
+    pub fn take_expired(&mut self, now: Instant) -> Vec<Request> {
+        let mut expired = Vec::new();
+        let mut i = 0;
+        while i < self.pending_requests.len() {
+            if self.pending_requests[i].deadline <= now {
+                expired.push(self.pending_requests.swap_remove(i).rpc);
+            } else {
+                i += 1;
+            }
+        }
+        expired
     }
 
     /// Checks if a result from the host matches a request, and if so removes it.
@@ -39,10 +93,10 @@ pub fn check_result(&mut self, result: &protocol::TlConnectResult) -> Option<Req
             return None;
         }
         if let Some(index) = self.pending_requests.iter().position(|request| {
-            request.input().service_id == result.service_id
-                && request.input().endpoint_id == result.endpoint_id
+            request.rpc.input().service_id == result.service_id
+                && request.rpc.input().endpoint_id == result.endpoint_id
         }) {
-            let rpc = self.pending_requests.swap_remove(index);
+            let rpc = self.pending_requests.swap_remove(index).rpc;
             Some(rpc)
         } else {
             tracing::warn!(?result, "Result for unknown hvsock request");
@@ -65,14 +119,14 @@ pub fn check_offer(&mut self, offer: &protocol::OfferChannel) -> Option<Request>
         // Since silo_id isn't part of the result message, it doesn't need to be checked here
         // either.
         let Some(index) = self.pending_requests.iter().position(|request| {
-            request.input().service_id == offer.interface_id
-                && request.input().endpoint_id == offer.instance_id
+            request.rpc.input().service_id == offer.interface_id
+                && request.rpc.input().endpoint_id == offer.instance_id
         }) else {
             tracing::warn!(?offer, "Channel offer for unknown hvsock request");
             return None;
         };
 
-        let rpc = self.pending_requests.swap_remove(index);
+        let rpc = self.pending_requests.swap_remove(index).rpc;
         tracing::debug!(request = ?rpc.input(), "channel offer matches hvsocket request");
         Some(rpc)
     }
@@ -89,7 +143,7 @@ mod tests {
 
     #[test]
     fn test_check_result() {
-        let mut tracker = HvsockRequestTracker::new();
+        let mut tracker = HvsockRequestTracker::new(HvsockRequestTracker::DEFAULT_TIMEOUT);
         let request = HvsockConnectRequest {
             service_id: Guid::new_random(),
             endpoint_id: Guid::new_random(),
@@ -97,7 +151,7 @@ fn test_check_result() {
             hosted_silo_unaware: false,
         };
 
-        tracker.add_request(Rpc::detached(request));
+        tracker.add_request(Rpc::detached(request), Instant::from_nanos(0));
         assert_eq!(1, tracker.pending_requests.len());
 
         // Endpoint ID mismatch.
@@ -132,7 +186,7 @@ fn test_check_result() {
 
     #[test]
     fn test_check_offer() {
-        let mut tracker = HvsockRequestTracker::new();
+        let mut tracker = HvsockRequestTracker::new(HvsockRequestTracker::DEFAULT_TIMEOUT);
         let request = HvsockConnectRequest {
             service_id: Guid::new_random(),
             endpoint_id: Guid::new_random(),
@@ -140,7 +194,7 @@ fn test_check_offer() {
             hosted_silo_unaware: false,
         };
 
-        tracker.add_request(Rpc::detached(request));
+        tracker.add_request(Rpc::detached(request), Instant::from_nanos(0));
         assert_eq!(1, tracker.pending_requests.len());
 
         // Endpoint ID mismatch.
@@ -170,6 +224,52 @@ fn test_check_offer() {
         assert!(tracker.check_offer(&offer).is_none());
     }
 
+    #[test]
+    fn test_take_expired() {
+        let mut tracker = HvsockRequestTracker::new(Duration::from_secs(1));
+        assert!(tracker.next_deadline().is_none());
+
+        let request = || HvsockConnectRequest {
+            service_id: Guid::new_random(),
+            endpoint_id: Guid::new_random(),
+            silo_id: Guid::new_random(),
+            hosted_silo_unaware: false,
+        };
+        let start = Instant::from_nanos(0);
+        tracker.add_request(Rpc::detached(request()), start);
+        tracker.add_request(
+            Rpc::detached(request()),
+            start.saturating_add(Duration::from_secs(2)),
+        );
+        assert_eq!(
+            tracker.next_deadline(),
+            Some(start.saturating_add(Duration::from_secs(1)))
+        );
+
+        assert!(
+            tracker
+                .take_expired(start.saturating_add(Duration::from_millis(500)))
+                .is_empty()
+        );
+        assert_eq!(
+            tracker
+                .take_expired(start.saturating_add(Duration::from_secs(1)))
+                .len(),
+            1
+        );
+        assert_eq!(
+            tracker.next_deadline(),
+            Some(start.saturating_add(Duration::from_secs(3)))
+        );
+        assert_eq!(
+            tracker
+                .take_expired(start.saturating_add(Duration::from_secs(5)))
+                .len(),
+            1
+        );
+        assert!(tracker.next_deadline().is_none());
+    }
+
     fn create_offer(
         interface_id: Guid,
         instance_id: Guid,
@@ -6,12 +6,29 @@
 #![expect(missing_docs)]
 #![forbid(unsafe_code)]
 
+pub mod buffered;
 pub mod driver;
+pub mod event;
 pub mod filter;
+pub mod group;
 mod hvsock;
+pub mod monitor;
+pub mod pacing;
 pub mod saved_state;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
+pub use self::event::ClientEvent;
+pub use self::event::ClientEventKind;
+pub use self::hvsock::HvsockConnectResult;
 pub use self::saved_state::SavedState;
+use crate::event::ClientEventSink;
+use crate::event::PendingOperation;
+use crate::filter::OfferFilter;
+use crate::monitor::MonitorPages;
+use crate::monitor::MonitorSignal;
+use crate::pacing::PostBackoff;
+use crate::pacing::PostError;
 use anyhow::Context as _;
 use anyhow::Result;
 use futures::FutureExt;
@@ -19,13 +36,19 @@
 use futures::future::OptionFuture;
 use futures::stream::SelectAll;
 use futures_concurrency::future::Race;
+use guestmem::GuestMemory;
 use guid::Guid;
 use inspect::Inspect;
+use inspect::InspectMut;
+use inspect_counters::Counter;
 use mesh::rpc::FailableRpc;
 use mesh::rpc::Rpc;
 use mesh::rpc::RpcSend;
+use pal_async::driver::Driver;
 use pal_async::task::Spawn;
 use pal_async::task::Task;
+use pal_async::timer::Instant;
+use pal_async::timer::PolledTimer;
 use pal_event::Event;
 use std::collections::HashMap;
 use std::collections::VecDeque;
@@ -41,6 +64,7 @@
 use std::sync::atomic::Ordering;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Duration;
 use thiserror::Error;
 use vmbus_async::async_dgram::AsyncRecv;
 use vmbus_async::async_dgram::AsyncRecvExt;
@@ -60,7 +84,10 @@
 use vmbus_core::protocol::Message;
 use vmbus_core::protocol::OpenChannelFlags;
 use vmbus_core::protocol::Version;
+use vmbus_core::trace::TraceDirection;
+use vmbus_core::trace::TraceWriter;
 use vmcore::interrupt::Interrupt;
+use vmcore::monitor::MonitorId;
 use vmcore::synic::MonitorPageGpas;
 use zerocopy::Immutable;
 use zerocopy::IntoBytes;
@@ -68,7 +95,13 @@
 
 const SINT: u8 = 2;
 const VTL: u8 = 0;
-const SUPPORTED_VERSIONS: &[Version] = &[Version::Iron, Version::Copper];
+const SUPPORTED_VERSIONS: &[Version] = &[
+    Version::Win10Rs3_1,
+    Version::Win10Rs4,
+    Version::Win10Rs5,
+    Version::Iron,
+    Version::Copper,
+];
 const SUPPORTED_FEATURE_FLAGS: FeatureFlags = FeatureFlags::new()
     .with_guest_specified_signal_parameters(true)
     .with_channel_interrupt_redirection(true)
@@ -76,6 +109,16 @@
     .with_client_id(true)
     .with_pause_resume(true);
 
+/// The maximum number of client and channel requests queued while the client
+/// is stopped. Further requests are left unread in their channels until the
+/// client is started.
+const MAX_STOPPED_REQUESTS: usize = 64;
+
+/// The maximum number of channels the client tracks. Further offers are
+/// ignored, so that a misbehaving host cannot grow the channel list without
+/// bound.
+const MAX_CHANNELS: usize = 0x10000;
+
 /// The client interface synic events.
 pub trait SynicEventClient: Send + Sync {
     /// Maps an incoming event signal on SINT7 to `event`.
@@ -98,14 +141,20 @@ fn pause_message_stream(&mut self) {}
     fn resume_message_stream(&mut self) {}
 }
 
+/// Posts messages to the host.
 pub trait PollPostMessage: Send {
+    /// Posts a message.
+    ///
+    /// Implementations should not retry internally. If the host's message
+    /// queue is full, return [`PostError::InsufficientBuffers`]; the client
+    /// will queue the message and retry it with backoff.
     fn poll_post_message(
         &mut self,
         cx: &mut Context<'_>,
         connection_id: u32,
         typ: u32,
         msg: &[u8],
-    ) -> Poll<()>;
+    ) -> Poll<Result<(), PostError>>;
 }
 
 #[derive(Inspect)]
@@ -126,6 +175,8 @@ pub enum ConnectError {
     NoSupportedVersions,
     #[error("failed to connect to the server: {0:?}")]
     FailedToConnect(ConnectionState),
+    #[error("the client was reset while connecting")]
+    Reset,
 }
 
 #[derive(Clone)]
@@ -138,11 +189,71 @@ pub struct VmbusClientBuilder {
     event_client: Arc<dyn SynicEventClient>,
     msg_source: Box<dyn VmbusMessageSource>,
     msg_client: Box<dyn PollPostMessage>,
+    backoff: PostBackoff,
+    event_send: Option<mesh::Sender<ClientEvent>>,
+    monitor_pages: Option<Arc<MonitorPages>>,
+    hvsock_timer: PolledTimer,
+    hvsock_connect_timeout: Duration,
+    feature_flags: FeatureFlags,
+    offer_filter: Option<Box<dyn OfferFilter>>,
+    unexpected_message_policy: UnexpectedMessagePolicy,
+    message_trace: Option<TraceWriter>,
+}
+
+/// How the client handles messages from the host that it cannot parse or that
+/// a client should never receive, such as messages meant for a vmbus server.
+///
+/// Such messages are always counted, and the count is available via inspect.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Inspect)]
+pub enum UnexpectedMessagePolicy {
+    /// Ignore the message.
+    Ignore,
+    /// Log a rate-limited warning and ignore the message.
+    Warn,
+    /// Log an error and unload the connection to the host.
+    Disconnect,
+}
+
+fn inspect_unexpected_message_policy(policy: &mut UnexpectedMessagePolicy) -> impl '_ + InspectMut {
+    inspect::adhoc_mut(|req| match req.update() {
+        Ok(req) => {
+            let new_policy = match req.new_value() {
+                "ignore" => Some(UnexpectedMessagePolicy::Ignore),
+                "warn" => Some(UnexpectedMessagePolicy::Warn),
+                "disconnect" => Some(UnexpectedMessagePolicy::Disconnect),
+                _ => None,
+            };
+            if let Some(new_policy) = new_policy {
+                *policy = new_policy;
+                let value = req.new_value().to_owned();
+                req.succeed(value);
+            } else {
+                req.fail("expected ignore, warn, or disconnect");
+            }
+        }
+        Err(req) => policy.inspect(req),
+    })
+}
+
+impl Default for UnexpectedMessagePolicy {
+    /// Debug builds disconnect so that host bugs are noticed; release builds
+    /// warn so that production deployments keep running.
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            Self::Disconnect
+        } else {
+            Self::Warn
+        }
+    }
 }
 
 impl VmbusClientBuilder {
     /// Creates a new instance of the builder with the given synic input.
+    ///
+    /// `driver` is used to wait before retrying messages that could not be
+    /// posted because the host's message queue was full.
     pub fn new(
+        driver: &(impl Driver + ?Sized),
         event_client: impl SynicEventClient + 'static,
         msg_source: impl VmbusMessageSource + 'static,
         msg_client: impl PollPostMessage + 'static,
@@ -151,9 +262,73 @@ pub fn new(
             event_client: Arc::new(event_client),
             msg_source: Box::new(msg_source),
             msg_client: Box::new(msg_client),
+            backoff: PostBackoff::new(driver),
+            event_send: None,
+            monitor_pages: None,
+            hvsock_timer: PolledTimer::new(driver),
+            hvsock_connect_timeout: hvsock::HvsockRequestTracker::DEFAULT_TIMEOUT,
+            feature_flags: SUPPORTED_FEATURE_FLAGS,
+            offer_filter: None,
+            unexpected_message_policy: UnexpectedMessagePolicy::default(),
+            message_trace: None,
         }
     }
 
+    /// Uses `mem` to access the child-to-parent monitor page passed to
+    /// [`VmbusClient::connect`] or [`VmbusClientAccess::modify`], allowing offers
+    /// with an allocated monitor ID to provide a [`MonitorSignal`].
+    pub fn monitor_memory(mut self, mem: GuestMemory) -> Self {
+        self.monitor_pages = Some(Arc::new(MonitorPages::new(mem)));
+        self
+    }
+
+    /// Sets how long to wait for the host to respond to
+    /// [`VmbusClientAccess::connect_hvsock`] before failing the request with
+    /// [`HvsockConnectResult::TimedOut`].
+    pub fn hvsock_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.hvsock_connect_timeout = timeout;
+        self
+    }
+
+    /// Limits the feature flags advertised to the host when connecting to
+    /// those set in `mask`.
+    ///
+    /// This allows a relay to avoid negotiating features, such as channel
+    /// interrupt redirection, that the layer below it cannot support.
+    pub fn feature_flags(mut self, mask: FeatureFlags) -> Self {
+        self.feature_flags = SUPPORTED_FEATURE_FLAGS & mask;
+        self
+    }
+
+    /// Applies `filter` to offers before they are delivered, from
+    /// [`VmbusClient::connect`], the offer receiver, or restore.
+    ///
+    /// Offers requested with [`VmbusClientAccess::connect_hvsock`] are not
+    /// filtered.
+    pub fn offer_filter(mut self, filter: impl OfferFilter + 'static) -> Self {
+        self.offer_filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Sets how to handle unexpected messages from the host.
+    pub fn unexpected_message_policy(mut self, policy: UnexpectedMessagePolicy) -> Self {
+        self.unexpected_message_policy = policy;
+        self
+    }
+
+    /// Records every control-plane message received from or sent to the host
+    /// to `trace`, for debugging or for replay.
+    pub fn message_trace(mut self, trace: TraceWriter) -> Self {
+        self.message_trace = Some(trace);
+        self
+    }
+
+    /// Reports connection and channel lifecycle events to `send`.
+    pub fn event_sender(mut self, send: mesh::Sender<ClientEvent>) -> Self {
+        self.event_send = Some(send);
+        self
+    }
+
     /// Creates a new instance with a receiver for incoming synic messages.
     pub fn build(self, spawner: &impl Spawn) -> VmbusClient {
         let (task_send, task_recv) = mesh::channel();
@@ -162,15 +337,21 @@ pub fn build(self, spawner: &impl Spawn) -> VmbusClient {
         let inner = ClientTaskInner {
             messages: OutgoingMessages {
                 poster: self.msg_client,
+                backoff: self.backoff,
                 queued: VecDeque::new(),
                 state: OutgoingMessageState::Paused,
+                deferred: Counter::new(),
+                trace: self.message_trace,
             },
             teardown_gpadls: HashMap::new(),
             channel_requests: SelectAll::new(),
             synic: SynicState {
                 event_flag_state: Vec::new(),
                 event_client: self.event_client,
+                monitor_page: None,
+                monitor_pages: self.monitor_pages,
             },
+            events: ClientEventSink::new(self.event_send),
         };
 
         let mut task = ClientTask {
@@ -178,11 +359,19 @@ pub fn build(self, spawner: &impl Spawn) -> VmbusClient {
             channels: ChannelList::default(),
             task_recv,
             running: false,
+            stopped_requests: VecDeque::new(),
             msg_source: self.msg_source,
             client_request_recv,
             state: ClientState::Disconnected,
             modify_request: None,
-            hvsock_tracker: hvsock::HvsockRequestTracker::new(),
+            queued_modify_requests: VecDeque::new(),
+            hvsock_tracker: hvsock::HvsockRequestTracker::new(self.hvsock_connect_timeout),
+            hvsock_timer: self.hvsock_timer,
+            feature_flags: self.feature_flags,
+            offer_filter: self.offer_filter,
+            next_open_id: 1,
+            unexpected_message_policy: self.unexpected_message_policy,
+            unexpected_messages: Counter::new(),
         };
 
         let task = spawner.spawn("vmbus client", async move {
@@ -222,20 +411,38 @@ pub async fn connect(
             .unwrap()
     }
 
-    pub async fn unload(self) {
+    /// Unloads the connection to the server.
+    ///
+    /// Once the server confirms the unload, all channels are revoked and the
+    /// client can connect again.
+    pub async fn unload(&mut self) {
         self.access
             .client_request_send
             .call(ClientRequest::Unload, ())
             .await
             .unwrap();
+    }
 
-        self.sever().await;
+    /// Forgets the connection to the server without unloading it, revoking
+    /// all channels, so that the client can connect again.
+    ///
+    /// This is for when the server has already discarded the connection, such
+    /// as after host servicing. Pending requests to the server are failed.
+    pub async fn reset(&mut self) {
+        self.task_send
+            .call(TaskRequest::Reset, ())
+            .await
+            .expect("Failed to send reset request")
     }
 
     pub fn access(&self) -> &VmbusClientAccess {
         &self.access
     }
 
+    /// Starts processing messages from the host and requests from devices.
+    ///
+    /// Requests made while the client was stopped are handled first, in the
+    /// order they were received, before any further messages from the host.
     pub fn start(&mut self) {
         self.task_send.send(TaskRequest::Start);
     }
@@ -271,6 +478,24 @@ pub async fn post_restore(&mut self) {
             .expect("Failed to send post-restore request");
     }
 
+    /// Returns the client's connection status.
+    pub async fn status(&self) -> ClientStatus {
+        self.task_send
+            .call(TaskRequest::Status, ())
+            .await
+            .expect("Failed to send status request")
+    }
+
+    /// Returns a snapshot of the client's internal state, for tests.
+    #[cfg(any(test, feature = "testing"))]
+    pub async fn snapshot(&self) -> testing::ClientSnapshot {
+        self.task_send
+            .call(TaskRequest::Snapshot, ())
+            .await
+            .expect("Failed to send snapshot request")
+    }
+
+    #[cfg(test)]
     async fn sever(self) -> VmbusClientBuilder {
         drop(self.task_send);
         let task = self.task.await;
@@ -278,6 +503,15 @@ async fn sever(self) -> VmbusClientBuilder {
             event_client: task.inner.synic.event_client,
             msg_source: task.msg_source,
             msg_client: task.inner.messages.poster,
+            backoff: task.inner.messages.backoff,
+            event_send: task.inner.events.into_sender(),
+            monitor_pages: task.inner.synic.monitor_pages,
+            hvsock_timer: task.hvsock_timer,
+            hvsock_connect_timeout: task.hvsock_tracker.timeout(),
+            feature_flags: task.feature_flags,
+            offer_filter: task.offer_filter,
+            unexpected_message_policy: task.unexpected_message_policy,
+            message_trace: task.inner.messages.trace,
         }
     }
 }
@@ -289,7 +523,45 @@ pub struct ConnectResult {
     pub offer_recv: mesh::Receiver<OfferInfo>,
 }
 
+/// The state of the client's connection to the host.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    /// The client is not connected.
+    Disconnected,
+    /// The client is negotiating the protocol version.
+    Connecting,
+    /// The client negotiated a version and is waiting for the initial offers.
+    RequestingOffers,
+    /// The client is connected.
+    Connected,
+    /// The client is unloading.
+    Disconnecting,
+}
+
+/// The client's connection status, returned by [`VmbusClient::status`].
+#[derive(Debug, Clone)]
+pub struct ClientStatus {
+    /// The state of the connection.
+    pub state: ConnectionStatus,
+    /// The negotiated version and feature flags, if a version has been
+    /// negotiated.
+    pub version: Option<VersionInfo>,
+    /// Whether the client is processing messages, i.e. it is started.
+    pub running: bool,
+    /// The number of channels the client is tracking, including revoked
+    /// channels that have not been released yet.
+    pub channels: usize,
+    /// The number of open channels.
+    pub open_channels: usize,
+    /// The number of GPADLs, including those being created or torn down.
+    pub gpadls: usize,
+}
+
 impl VmbusClientAccess {
+    /// Modifies the connection, completing with the host's response.
+    ///
+    /// Concurrent requests are sent to the host one at a time, in the order
+    /// they were made.
     pub async fn modify(&self, request: ModifyConnectionRequest) -> ConnectionState {
         self.client_request_send
             .call(ClientRequest::Modify, request)
@@ -297,13 +569,15 @@ pub async fn modify(&self, request: ModifyConnectionRequest) -> ConnectionState
             .expect("Failed to send modify request")
     }
 
+    /// Requests a guest-to-host hvsocket connection, completing when the host
+    /// responds to this request or the request times out.
     pub fn connect_hvsock(
         &self,
         request: HvsockConnectRequest,
-    ) -> impl Future<Output = Option<OfferInfo>> + use<> {
+    ) -> impl Future<Output = HvsockConnectResult> + use<> {
         self.client_request_send
             .call(ClientRequest::HvsockConnect, request)
-            .map(|r| r.ok().flatten())
+            .map(|r| r.unwrap_or(HvsockConnectResult::Cancelled))
     }
 }
 
@@ -312,6 +586,11 @@ pub struct OpenRequest {
     pub open_data: OpenData,
     pub incoming_event: Option<Event>,
     pub use_vtl2_connection_id: bool,
+    /// Asks the host to send the channel's interrupts to the SINT and VTL the
+    /// client connected with, using `open_data.event_flag`. Requires the
+    /// `channel_interrupt_redirection` feature. This is implied by
+    /// `incoming_event`.
+    pub redirect_interrupt: bool,
 }
 
 #[derive(Debug)]
@@ -331,6 +610,15 @@ pub enum ChannelRequest {
     Gpadl(FailableRpc<GpadlRequest, ()>),
     TeardownGpadl(Rpc<GpadlId, ()>),
     Modify(Rpc<ModifyRequest, i32>),
+    /// Releases the channel: closes it if open, tears down its GPADLs, and
+    /// releases the offer back to the host once it has been rescinded.
+    ///
+    /// No further requests for the channel are processed after this.
+    Release(Rpc<(), ()>),
+    /// Inspects the client's state for the channel, as seen by the host.
+    ///
+    /// Unlike other requests, this is handled while the client is stopped.
+    Inspect(inspect::Deferred),
 }
 
 #[derive(Debug)]
@@ -348,6 +636,8 @@ fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             ChannelRequest::Gpadl(_) => "Gpadl",
             ChannelRequest::TeardownGpadl(_) => "TeardownGpadl",
             ChannelRequest::Modify(_) => "Modify",
+            ChannelRequest::Release(_) => "Release",
+            ChannelRequest::Inspect(_) => "Inspect",
         };
         fmt.pad(s)
     }
@@ -375,6 +665,20 @@ pub enum RestoreError {
 
     #[error("failed to offer restored channel")]
     OfferFailed(#[source] anyhow::Error),
+
+    #[error("saved state has channels or gpadls but the client is disconnected")]
+    PendingOperationsWhileDisconnected,
+
+    #[error("invalid event flag for an in-flight open")]
+    InvalidEventFlag(#[source] anyhow::Error),
+
+    #[error(
+        "saved state version {version} requires version {compatible_version}, newer than this client"
+    )]
+    IncompatibleSavedState {
+        version: u32,
+        compatible_version: u32,
+    },
 }
 
 /// Provides the offer details from the server in addition to both a channel
@@ -384,18 +688,36 @@ pub struct OfferInfo {
     pub offer: protocol::OfferChannel,
     #[inspect(skip)]
     pub guest_to_host_interrupt: Interrupt,
+    /// Signals the host through the monitor page, if the host allocated a
+    /// monitor ID for the channel and the client was built with
+    /// [`VmbusClientBuilder::monitor_memory`].
+    #[inspect(skip)]
+    pub monitor_signal: Option<MonitorSignal>,
     #[inspect(skip)]
     pub request_send: mesh::Sender<ChannelRequest>,
     #[inspect(skip)]
     pub revoke_recv: mesh::OneshotReceiver<()>,
 }
 
+impl OfferInfo {
+    /// Replaces [`Self::guest_to_host_interrupt`] with an interrupt that
+    /// signals the host through the monitor page, if available. Returns false
+    /// if the channel does not support monitored interrupts.
+    pub fn use_monitored_interrupt(&mut self) -> bool {
+        let Some(signal) = &self.monitor_signal else {
+            return false;
+        };
+        self.guest_to_host_interrupt = signal.interrupt();
+        true
+    }
+}
+
 #[derive(Debug)]
 enum ClientRequest {
     Connect(Rpc<ConnectRequest, Result<ConnectResult, ConnectError>>),
     Unload(Rpc<(), ()>),
     Modify(Rpc<ModifyConnectionRequest, ConnectionState>),
-    HvsockConnect(Rpc<HvsockConnectRequest, Option<OfferInfo>>),
+    HvsockConnect(Rpc<HvsockConnectRequest, HvsockConnectResult>),
 }
 
 impl std::fmt::Display for ClientRequest {
@@ -410,6 +732,13 @@ fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     }
 }
 
+/// A request received while the client was stopped, to be handled once it is
+/// started.
+enum StoppedRequest {
+    Client(ClientRequest),
+    Channel(ChannelId, Option<ChannelRequest>),
+}
+
 enum TaskRequest {
     Inspect(inspect::Deferred),
     Save(Rpc<(), SavedState>),
@@ -417,6 +746,10 @@ enum TaskRequest {
     PostRestore(Rpc<(), ()>),
     Start,
     Stop(Rpc<(), ()>),
+    Status(Rpc<(), ClientStatus>),
+    Reset(Rpc<(), ()>),
+    #[cfg(any(test, feature = "testing"))]
+    Snapshot(Rpc<(), testing::ClientSnapshot>),
 }
 
 /// The overall state machine used to drive which actions the client can legally
@@ -456,6 +789,16 @@ enum ClientState {
 }
 
 impl ClientState {
+    fn status(&self) -> ConnectionStatus {
+        match self {
+            ClientState::Disconnected => ConnectionStatus::Disconnected,
+            ClientState::Connecting { .. } => ConnectionStatus::Connecting,
+            ClientState::Connected { .. } => ConnectionStatus::Connected,
+            ClientState::RequestingOffers { .. } => ConnectionStatus::RequestingOffers,
+            ClientState::Disconnecting { .. } => ConnectionStatus::Disconnecting,
+        }
+    }
+
     fn get_version(&self) -> Option<VersionInfo> {
         match self {
             ClientState::Connected { version, .. } => Some(*version),
@@ -505,6 +848,17 @@ fn from(value: ModifyConnectionRequest) -> Self {
 /// The per-channel state which dictates which whether or not a channel can
 /// request an Open/Close. As GPADLs can happen outside this loop there is no
 /// state tied to GPADL actions.
+///
+/// The legal transitions are:
+///
+/// * `Offered` -> `Opening` on an open request.
+/// * `Opening` -> `Opened` or `Offered` on the open result.
+/// * `Opening` -> `ClosingAfterOpen` on a close request.
+/// * `ClosingAfterOpen` -> `Offered` on the open result, closing the channel
+///   if the open succeeded.
+/// * `Restored` -> `Opened` when claimed, or `Offered` after restore if not.
+/// * `Opened` -> `Offered` on a close request.
+/// * Any state but `Revoked` -> `Revoked` on rescind.
 #[derive(Debug, Inspect)]
 #[inspect(external_tag)]
 enum ChannelState {
@@ -512,12 +866,21 @@ enum ChannelState {
     Offered,
     /// The channel has requested the server to be opened.
     Opening {
+        /// The ID sent with the open request, echoed back by the server in
+        /// the open result.
+        open_id: u32,
         redirected_event_flag: Option<u16>,
         #[inspect(skip)]
         redirected_event: Option<Event>,
         #[inspect(skip)]
         rpc: FailableRpc<(), OpenOutput>,
     },
+    /// The channel was closed while its open request was in flight. The open
+    /// is undone once the host responds to it.
+    ClosingAfterOpen {
+        open_id: u32,
+        redirected_event_flag: Option<u16>,
+    },
     /// The channel has been restored but not claimed.
     Restored,
     /// The channel has been successfully opened.
@@ -534,6 +897,7 @@ impl std::fmt::Display for ChannelState {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let s = match self {
             ChannelState::Opening { .. } => "Opening",
+            ChannelState::ClosingAfterOpen { .. } => "ClosingAfterOpen",
             ChannelState::Offered => "Offered",
             ChannelState::Opened { .. } => "Opened",
             ChannelState::Restored => "Restored",
@@ -556,6 +920,45 @@ struct Channel {
     gpadls: HashMap<GpadlId, GpadlState>,
     is_client_released: bool,
     connection_id: Arc<AtomicU32>,
+    stats: ChannelStats,
+}
+
+/// Per-channel counters, to help diagnose stuck channels.
+#[derive(Debug, Inspect)]
+struct ChannelStats {
+    opens_attempted: Counter,
+    opens_succeeded: Counter,
+    gpadls_created: Counter,
+    gpadls_torn_down: Counter,
+    modify_requests: Counter,
+    /// When the channel last changed state.
+    #[inspect(
+        rename = "ms_since_last_transition",
+        with = "|x| Instant::now().saturating_sub(*x).as_millis() as u64"
+    )]
+    last_transition: Instant,
+    /// The status of the most recent open, GPADL, or modify response from the
+    /// host.
+    #[inspect(hex)]
+    last_host_status: u32,
+}
+
+impl ChannelStats {
+    fn new() -> Self {
+        Self {
+            opens_attempted: Counter::new(),
+            opens_succeeded: Counter::new(),
+            gpadls_created: Counter::new(),
+            gpadls_torn_down: Counter::new(),
+            modify_requests: Counter::new(),
+            last_transition: Instant::now(),
+            last_host_status: 0,
+        }
+    }
+
+    fn transition(&mut self) {
+        self.last_transition = Instant::now();
+    }
 }
 
 impl Channel {
@@ -564,23 +967,45 @@ fn pending_request(&self) -> Option<&'static str> {
             return Some("modify");
         }
         self.gpadls.iter().find_map(|(_, gpadl)| match gpadl {
-            GpadlState::Offered(_) => Some("creating gpadl"),
+            GpadlState::Offered { .. } => Some("creating gpadl"),
             GpadlState::Created => None,
             GpadlState::TearingDown { .. } => Some("tearing down gpadl"),
         })
     }
 }
 
-#[derive(Inspect)]
+#[derive(InspectMut)]
 struct ClientTask {
     #[inspect(flatten)]
     inner: ClientTaskInner,
     channels: ChannelList,
     state: ClientState,
     hvsock_tracker: hvsock::HvsockRequestTracker,
+    #[inspect(skip)]
+    hvsock_timer: PolledTimer,
+    /// The feature flags advertised to the host when connecting.
+    feature_flags: FeatureFlags,
+    #[inspect(skip)]
+    offer_filter: Option<Box<dyn OfferFilter>>,
+    /// The ID to use for the next open request.
+    next_open_id: u32,
+    /// Writable, so that a host bug can be worked around (or caught) without
+    /// restarting.
+    #[inspect(mut, with = "inspect_unexpected_message_policy")]
+    unexpected_message_policy: UnexpectedMessagePolicy,
+    /// Messages from the host that could not be parsed or that a client should
+    /// not receive.
+    unexpected_messages: Counter,
     running: bool,
+    /// Requests received while stopped, handled on start.
+    #[inspect(with = "|x| x.len()")]
+    stopped_requests: VecDeque<StoppedRequest>,
     #[inspect(with = "|x| x.is_some()")]
     modify_request: Option<Rpc<ModifyConnectionRequest, ConnectionState>>,
+    /// Modify requests waiting for `modify_request` to complete, issued in
+    /// order.
+    #[inspect(with = "|x| x.len()")]
+    queued_modify_requests: VecDeque<Rpc<ModifyConnectionRequest, ConnectionState>>,
     #[inspect(skip)]
     msg_source: Box<dyn VmbusMessageSource>,
     #[inspect(skip)]
@@ -601,7 +1026,7 @@ fn handle_initiate_contact(
             return;
         };
         let feature_flags = if version >= Version::Copper {
-            SUPPORTED_FEATURE_FLAGS
+            self.feature_flags
         } else {
             FeatureFlags::new()
         };
@@ -626,6 +1051,7 @@ fn handle_initiate_contact(
         };
 
         self.state = ClientState::Connecting { version, rpc };
+        self.inner.events.start(PendingOperation::Connect);
         if version < Version::Copper {
             self.inner.messages.send(&msg.initiate_contact)
         } else {
@@ -634,12 +1060,15 @@ fn handle_initiate_contact(
     }
 
     fn handle_unload(&mut self, rpc: Rpc<(), ()>) {
-        tracing::debug!(%self.state, "VmBus client disconnecting");
-        self.state = ClientState::Disconnecting {
-            version: self.state.get_version().expect("invalid state for unload"),
-            rpc,
+        let Some(version) = self.state.get_version() else {
+            tracing::warn!(client_state = %self.state, "invalid client state for unload");
+            rpc.complete(());
+            return;
         };
+        tracing::debug!(%self.state, "VmBus client disconnecting");
+        self.state = ClientState::Disconnecting { version, rpc };
 
+        self.inner.events.start(PendingOperation::Unload);
         self.inner.messages.send(&protocol::Unload {});
     }
 
@@ -653,8 +1082,9 @@ fn handle_modify(&mut self, request: Rpc<ModifyConnectionRequest, ConnectionStat
         }
 
         if self.modify_request.is_some() {
-            tracing::warn!("Duplicate ModifyConnection request");
-            request.complete(ConnectionState::FAILED_UNKNOWN_FAILURE);
+            // The host handles one ModifyConnection at a time, so issue this
+            // one when the current one completes.
+            self.queued_modify_requests.push_back(request);
             return;
         }
 
@@ -663,13 +1093,31 @@ fn handle_modify(&mut self, request: Rpc<ModifyConnectionRequest, ConnectionStat
         self.inner.messages.send(&message);
     }
 
-    fn handle_tl_connect(&mut self, rpc: Rpc<HvsockConnectRequest, Option<OfferInfo>>) {
-        // The client only supports protocol versions which use the newer message format.
+    fn handle_tl_connect(&mut self, rpc: Rpc<HvsockConnectRequest, HvsockConnectResult>) {
         // The host will not send a TlConnectRequestResult message on success, so a response to this
         // message is not guaranteed.
-        let message = protocol::TlConnectRequest2::from(*rpc.input());
-        self.hvsock_tracker.add_request(rpc);
-        self.inner.messages.send(&message);
+        let request = *rpc.input();
+        let message = protocol::TlConnectRequest2::from(request);
+        let supports_silo_id = self
+            .state
+            .get_version()
+            .is_none_or(|version| version.version >= Version::Win10Rs5);
+
+        self.hvsock_tracker.add_request(rpc, Instant::now());
+        if supports_silo_id {
+            self.inner.messages.send(&message);
+        } else {
+            // Older hosts only understand the original message, which has no
+            // silo ID, so the request is always handled in the host's default
+            // silo.
+            if request.silo_id != Guid::ZERO {
+                tracelimit::warn_ratelimited!(
+                    silo_id = %request.silo_id,
+                    "hvsock silo ID not supported by the negotiated version, ignoring"
+                );
+            }
+            self.inner.messages.send(&message.base);
+        }
     }
 
     fn handle_client_request(&mut self, request: ClientRequest) {
@@ -697,6 +1145,10 @@ fn handle_version_response(&mut self, msg: protocol::VersionResponse2) {
         };
         if msg.version_response.version_supported > 0 {
             if msg.version_response.connection_state != ConnectionState::SUCCESSFUL {
+                let latency = self.inner.events.finish(PendingOperation::Connect);
+                self.inner
+                    .events
+                    .emit(ClientEventKind::ConnectFailed { latency });
                 rpc.complete(Err(ConnectError::FailedToConnect(
                     msg.version_response.connection_state,
                 )));
@@ -704,7 +1156,7 @@ fn handle_version_response(&mut self, msg: protocol::VersionResponse2) {
             }
 
             let feature_flags = if version >= Version::Copper {
-                FeatureFlags::from(msg.supported_features)
+                FeatureFlags::from(msg.supported_features) & self.feature_flags
             } else {
                 FeatureFlags::new()
             };
@@ -714,6 +1166,14 @@ fn handle_version_response(&mut self, msg: protocol::VersionResponse2) {
                 feature_flags,
             };
 
+            // Keep tracking the connect operation until all offers are
+            // delivered, but report the negotiation latency now.
+            let latency = self.inner.events.elapsed(PendingOperation::Connect);
+            self.inner
+                .events
+                .emit(ClientEventKind::VersionNegotiated { version, latency });
+
+            self.inner.synic.set_monitor_page(rpc.input().monitor_page);
             self.inner.messages.send(&protocol::RequestOffers {});
             self.state = ClientState::RequestingOffers {
                 version,
@@ -728,6 +1188,10 @@ fn handle_version_response(&mut self, msg: protocol::VersionResponse2) {
                 .unwrap();
 
             if index == 0 {
+                let latency = self.inner.events.finish(PendingOperation::Connect);
+                self.inner
+                    .events
+                    .emit(ClientEventKind::ConnectFailed { latency });
                 rpc.complete(Err(ConnectError::NoSupportedVersions));
                 return;
             }
@@ -757,6 +1221,14 @@ fn create_channel_core(
         let (revoke_send, revoke_recv) = mesh::oneshot();
 
         let connection_id = Arc::new(AtomicU32::new(0));
+        let guest_to_host_interrupt = self
+            .inner
+            .synic
+            .guest_to_host_interrupt(connection_id.clone());
+        let monitor_signal = self
+            .inner
+            .synic
+            .monitor_signal(&offer, &guest_to_host_interrupt);
         self.channels.0.insert(
             offer.channel_id,
             Channel {
@@ -767,6 +1239,7 @@ fn create_channel_core(
                 gpadls: HashMap::new(),
                 is_client_released: false,
                 connection_id: connection_id.clone(),
+                stats: ChannelStats::new(),
             },
         );
 
@@ -776,16 +1249,42 @@ fn create_channel_core(
 
         Ok(OfferInfo {
             offer,
-            guest_to_host_interrupt: self.inner.synic.guest_to_host_interrupt(connection_id),
+            guest_to_host_interrupt,
+            monitor_signal,
             revoke_recv,
             request_send,
         })
     }
 
     fn handle_offer(&mut self, offer: protocol::OfferChannel) {
-        let offer_info = self
-            .create_channel(offer)
-            .expect("channel should not exist");
+        if !matches!(
+            self.state,
+            ClientState::Connected { .. } | ClientState::RequestingOffers { .. }
+        ) {
+            tracelimit::warn_ratelimited!(
+                client_state = %self.state,
+                channel_id = offer.channel_id.0,
+                "invalid client state for OfferChannel"
+            );
+            return;
+        }
+        if self.channels.0.len() >= MAX_CHANNELS {
+            tracelimit::warn_ratelimited!(
+                channel_id = offer.channel_id.0,
+                "too many channels, ignoring offer"
+            );
+            return;
+        }
+        let offer_info = match self.create_channel(offer) {
+            Ok(offer_info) => offer_info,
+            Err(err) => {
+                tracelimit::warn_ratelimited!(
+                    error = err.as_ref() as &dyn std::error::Error,
+                    "invalid offer"
+                );
+                return;
+            }
+        };
 
         tracing::info!(
                 state = %self.state,
@@ -795,9 +1294,14 @@ fn handle_offer(&mut self, offer: protocol::OfferChannel) {
                 subchannel_index = offer.subchannel_index,
                 "received offer");
 
+        self.inner.events.emit(ClientEventKind::ChannelOffered {
+            channel_id: offer.channel_id,
+            key: OfferKey::from(&offer),
+        });
+
         if let Some(offer) = self.hvsock_tracker.check_offer(&offer_info.offer) {
-            offer.complete(Some(offer_info));
-        } else {
+            offer.complete(HvsockConnectResult::Connected(offer_info));
+        } else if let Some(offer_info) = self.filter_offer(offer_info) {
             match &mut self.state {
                 ClientState::Connected { offer_send, .. } => {
                     offer_send.send(offer_info);
@@ -805,42 +1309,85 @@ fn handle_offer(&mut self, offer: protocol::OfferChannel) {
                 ClientState::RequestingOffers { offers, .. } => {
                     offers.push(offer_info);
                 }
-                state => unreachable!("invalid client state for OfferChannel: {state}"),
+                state => unreachable!("client state validated above: {state}"),
             }
         }
     }
 
+    /// Applies the offer filter, returning `None` if the offer is hidden. The
+    /// channel of a hidden offer is released when the offer is dropped.
+    fn filter_offer(&mut self, mut offer_info: OfferInfo) -> Option<OfferInfo> {
+        if let Some(filter) = &mut self.offer_filter {
+            let key = OfferKey::from(&offer_info.offer);
+            let Some(offer) = filter.filter(offer_info.offer) else {
+                tracing::info!(%key, "offer hidden by filter");
+                return None;
+            };
+            offer_info.offer = offer;
+        }
+        Some(offer_info)
+    }
+
     fn handle_rescind(&mut self, rescind: protocol::RescindChannelOffer) -> TriedRelease {
-        let mut channel = self.channels.get_mut(rescind.channel_id);
+        let Some(mut channel) = self
+            .channels
+            .get_mut_for_host(rescind.channel_id, "RescindChannelOffer")
+        else {
+            return TriedRelease(());
+        };
+        if matches!(channel.state, ChannelState::Revoked) {
+            tracelimit::warn_ratelimited!(
+                channel_id = rescind.channel_id.0,
+                "channel already revoked"
+            );
+            return TriedRelease(());
+        }
         tracing::info!(
             state = %self.state,
             channel_id = rescind.channel_id.0,
             key = %OfferKey::from(&channel.offer),
             "received rescind"
         );
+        channel.stats.transition();
         let event_flag = match std::mem::replace(&mut channel.state, ChannelState::Revoked) {
             ChannelState::Offered => None,
             ChannelState::Opening {
+                open_id: _,
                 redirected_event_flag,
                 redirected_event: _,
                 rpc,
             } => {
+                self.inner
+                    .events
+                    .finish(PendingOperation::Open(rescind.channel_id));
                 rpc.fail(anyhow::anyhow!("channel revoked"));
                 redirected_event_flag
             }
+            ChannelState::ClosingAfterOpen {
+                open_id: _,
+                redirected_event_flag,
+            } => {
+                self.inner
+                    .events
+                    .finish(PendingOperation::Open(rescind.channel_id));
+                redirected_event_flag
+            }
             ChannelState::Restored => None,
             ChannelState::Opened {
                 redirected_event_flag,
                 redirected_event: _,
             } => redirected_event_flag,
-            ChannelState::Revoked => {
-                panic!("channel id {:?} already revoked", rescind.channel_id);
-            }
+            ChannelState::Revoked => unreachable!("channel state validated above"),
         };
         if let Some(event_flag) = event_flag {
             self.inner.synic.free_event_flag(event_flag);
         }
 
+        self.inner.events.emit(ClientEventKind::ChannelRescinded {
+            channel_id: rescind.channel_id,
+            key: OfferKey::from(&channel.offer),
+        });
+
         // Drop the channel and send the revoked message to the client.
         channel.revoke_send.take().unwrap().send(());
 
@@ -855,6 +1402,11 @@ fn handle_offers_delivered(&mut self) {
                 offers,
             } => {
                 tracing::info!(version = ?version, "VmBus client connected, offers delivered");
+                let latency = self.inner.events.finish(PendingOperation::Connect);
+                self.inner.events.emit(ClientEventKind::Connected {
+                    offer_count: offers.len(),
+                    latency,
+                });
                 let (offer_send, offer_recv) = mesh::channel();
                 self.state = ClientState::Connected {
                     version,
@@ -874,65 +1426,181 @@ fn handle_offers_delivered(&mut self) {
     }
 
     fn handle_gpadl_created(&mut self, request: protocol::GpadlCreated) -> TriedRelease {
-        let mut channel = self.channels.get_mut(request.channel_id);
+        let Some(mut channel) = self
+            .channels
+            .get_mut_for_host(request.channel_id, "GpadlCreated")
+        else {
+            return TriedRelease(());
+        };
         let Some(gpadl_state) = channel.gpadls.get_mut(&request.gpadl_id) else {
-            panic!("GpadlCreated for unknown gpadl {:#x}", request.gpadl_id.0);
+            tracelimit::warn_ratelimited!(
+                channel_id = request.channel_id.0,
+                gpadl_id = request.gpadl_id.0,
+                "GpadlCreated for unknown gpadl"
+            );
+            return TriedRelease(());
         };
+        if !matches!(gpadl_state, GpadlState::Offered { .. }) {
+            tracelimit::warn_ratelimited!(
+                channel_id = request.channel_id.0,
+                gpadl_id = request.gpadl_id.0,
+                state = ?gpadl_state,
+                "invalid gpadl state for GpadlCreated"
+            );
+            return TriedRelease(());
+        }
 
-        let rpc = match std::mem::replace(gpadl_state, GpadlState::Created) {
-            GpadlState::Offered(rpc) => rpc,
-            old_state => {
-                panic!(
-                    "invalid state {old_state:?} for gpadl {:#x}:{:#x}",
-                    request.channel_id.0, request.gpadl_id.0
-                );
-            }
+        let GpadlState::Offered { rpc, teardown_rpcs } =
+            std::mem::replace(gpadl_state, GpadlState::Created)
+        else {
+            unreachable!("gpadl state validated above");
         };
 
         let gpadl_created = request.status == protocol::STATUS_SUCCESS;
+        channel.stats.last_host_status = request.status as u32;
+        // A GPADL restored while being created has no requester.
+        let abandoned = rpc.is_none();
+        let latency = self
+            .inner
+            .events
+            .finish(PendingOperation::CreateGpadl(request.gpadl_id));
         if gpadl_created {
-            rpc.complete(Ok(()));
+            channel.stats.gpadls_created.increment();
+            self.inner.events.emit(ClientEventKind::GpadlCreated {
+                channel_id: request.channel_id,
+                gpadl_id: request.gpadl_id,
+                latency,
+            });
+            if let Some(rpc) = rpc {
+                rpc.complete(Ok(()));
+            }
+            if matches!(channel.state, ChannelState::Revoked) {
+                // The host released the GPADL with the channel.
+                for rpc in teardown_rpcs {
+                    rpc.complete(());
+                }
+            } else if channel.is_client_released || abandoned || !teardown_rpcs.is_empty() {
+                // The channel was released or the GPADL torn down while it
+                // was being created, or nobody is waiting for it.
+                let state =
+                    self.inner
+                        .teardown_gpadl(request.channel_id, request.gpadl_id, teardown_rpcs);
+                channel.gpadls.insert(request.gpadl_id, state);
+            }
         } else {
+            self.inner.events.emit(ClientEventKind::GpadlCreateFailed {
+                channel_id: request.channel_id,
+                gpadl_id: request.gpadl_id,
+                status: request.status,
+                latency,
+            });
             channel.gpadls.remove(&request.gpadl_id).unwrap();
-            rpc.fail(anyhow::anyhow!(
-                "gpadl creation failed: {:#x}",
-                request.status
-            ));
+            if let Some(rpc) = rpc {
+                rpc.fail(anyhow::anyhow!(
+                    "gpadl creation failed: {:#x}",
+                    request.status
+                ));
+            }
+            // There is nothing to tear down.
+            for rpc in teardown_rpcs {
+                rpc.complete(());
+            }
         };
         channel.try_release(&mut self.inner.messages)
     }
 
     fn handle_open_result(&mut self, result: protocol::OpenResult) {
-        let mut channel = self.channels.get_mut(result.channel_id);
+        let Some(mut channel) = self
+            .channels
+            .get_mut_for_host(result.channel_id, "OpenResult")
+        else {
+            return;
+        };
         tracing::debug!(
             channel_id = result.channel_id.0,
             key = %OfferKey::from(&channel.offer),
+            open_id = result.open_id,
             result = result.status,
             "received open result"
         );
 
         let channel_opened = result.status == protocol::STATUS_SUCCESS as u32;
-        let old_state = std::mem::replace(&mut channel.state, ChannelState::Offered);
-        let ChannelState::Opening {
-            redirected_event_flag,
-            redirected_event,
-            rpc,
-        } = old_state
-        else {
+        let open_id = match &channel.state {
+            ChannelState::Opening { open_id, .. }
+            | ChannelState::ClosingAfterOpen { open_id, .. } => *open_id,
+            state => {
+                tracing::warn!(
+                    key = %OfferKey::from(&channel.offer),
+                    old_state = ?state,
+                    channel_opened,
+                    "invalid state for open result"
+                );
+                return;
+            }
+        };
+
+        if result.open_id != open_id {
+            // This is the result of an earlier open request, not the one that
+            // is currently outstanding.
             tracing::warn!(
                 key = %OfferKey::from(&channel.offer),
-                old_state = ?channel.state,
+                open_id,
+                result_open_id = result.open_id,
                 channel_opened,
-                "invalid state for open result"
+                "mismatched open ID for open result"
             );
-            channel.state = old_state;
             return;
-        };
+        }
 
+        channel.stats.last_host_status = result.status;
+        channel.stats.transition();
+        let latency = self
+            .inner
+            .events
+            .finish(PendingOperation::Open(result.channel_id));
+        let (redirected_event_flag, redirected_event, rpc) =
+            match std::mem::replace(&mut channel.state, ChannelState::Offered) {
+                ChannelState::Opening {
+                    open_id: _,
+                    redirected_event_flag,
+                    redirected_event,
+                    rpc,
+                } => (redirected_event_flag, redirected_event, rpc),
+                ChannelState::ClosingAfterOpen {
+                    open_id: _,
+                    redirected_event_flag,
+                } => {
+                    // Nobody is waiting for the open anymore, so undo it.
+                    tracing::info!(
+                        channel_id = result.channel_id.0,
+                        key = %OfferKey::from(&channel.offer),
+                        channel_opened,
+                        "open completed after close"
+                    );
+                    if channel_opened {
+                        channel.state = ChannelState::Opened {
+                            redirected_event_flag,
+                            redirected_event: None,
+                        };
+                        self.inner.close_channel(result.channel_id, &mut channel);
+                    } else if let Some(event_flag) = redirected_event_flag {
+                        self.inner.synic.free_event_flag(event_flag);
+                    }
+                    return;
+                }
+                _ => unreachable!("state validated above"),
+            };
+        let key = OfferKey::from(&channel.offer);
         if !channel_opened {
             if let Some(event_flag) = redirected_event_flag {
                 self.inner.synic.free_event_flag(event_flag);
             }
+            self.inner.events.emit(ClientEventKind::ChannelOpenFailed {
+                channel_id: result.channel_id,
+                key,
+                status: result.status,
+                latency,
+            });
             rpc.fail(anyhow::anyhow!("open failed: {:#x}", result.status));
             return;
         }
@@ -941,15 +1609,30 @@ fn handle_open_result(&mut self, result: protocol::OpenResult) {
             redirected_event_flag,
             redirected_event,
         };
+        channel.stats.opens_succeeded.increment();
 
+        self.inner.events.emit(ClientEventKind::ChannelOpened {
+            channel_id: result.channel_id,
+            key,
+            latency,
+        });
         rpc.complete(Ok(OpenOutput {
             redirected_event_flag,
         }));
+
+        if channel.is_client_released {
+            // The channel was released while the open was in progress.
+            self.inner.close_channel(result.channel_id, &mut channel);
+        }
     }
 
     fn handle_gpadl_torndown(&mut self, request: protocol::GpadlTorndown) -> TriedRelease {
         let Some(channel_id) = self.inner.teardown_gpadls.remove(&request.gpadl_id) else {
-            panic!("gpadl {:#x} not in teardown list", request.gpadl_id.0);
+            tracelimit::warn_ratelimited!(
+                gpadl_id = request.gpadl_id.0,
+                "GpadlTorndown for gpadl not in teardown list"
+            );
+            return TriedRelease(());
         };
 
         let mut channel = self.channels.get_mut(channel_id);
@@ -969,6 +1652,17 @@ fn handle_gpadl_torndown(&mut self, request: protocol::GpadlTorndown) -> TriedRe
             panic!("gpadl should be tearing down if in teardown list, state = {gpadl_state:?}");
         };
 
+        let latency = self
+            .inner
+            .events
+            .finish(PendingOperation::TeardownGpadl(request.gpadl_id));
+        channel.stats.gpadls_torn_down.increment();
+        self.inner.events.emit(ClientEventKind::GpadlTornDown {
+            channel_id,
+            gpadl_id: request.gpadl_id,
+            latency,
+        });
+
         for rpc in rpcs {
             rpc.complete(());
         }
@@ -979,6 +1673,12 @@ fn handle_unload_complete(&mut self) {
         match std::mem::replace(&mut self.state, ClientState::Disconnected) {
             ClientState::Disconnecting { version: _, rpc } => {
                 tracing::info!("VmBus client disconnected");
+                // The host releases all channels and GPADLs on unload.
+                self.reset_channels();
+                let latency = self.inner.events.finish(PendingOperation::Unload);
+                self.inner
+                    .events
+                    .emit(ClientEventKind::Disconnected { latency });
                 rpc.complete(());
             }
             state => {
@@ -989,7 +1689,15 @@ fn handle_unload_complete(&mut self) {
 
     fn handle_modify_complete(&mut self, response: protocol::ModifyConnectionResponse) {
         if let Some(request) = self.modify_request.take() {
-            request.complete(response.connection_state)
+            if response.connection_state == ConnectionState::SUCCESSFUL {
+                self.inner
+                    .synic
+                    .set_monitor_page(request.input().monitor_page);
+            }
+            request.complete(response.connection_state);
+            if let Some(next) = self.queued_modify_requests.pop_front() {
+                self.handle_modify(next);
+            }
         } else {
             tracing::warn!("Unexpected modify complete request");
         }
@@ -999,27 +1707,55 @@ fn handle_modify_channel_response(
         &mut self,
         response: protocol::ModifyChannelResponse,
     ) -> TriedRelease {
-        let mut channel = self.channels.get_mut(response.channel_id);
+        let Some(mut channel) = self
+            .channels
+            .get_mut_for_host(response.channel_id, "ModifyChannelResponse")
+        else {
+            return TriedRelease(());
+        };
         let Some(sender) = channel.modify_response_send.take() else {
-            panic!(
-                "unexpected modify channel response for channel {:#x}",
-                response.channel_id.0
+            tracelimit::warn_ratelimited!(
+                channel_id = response.channel_id.0,
+                "unexpected modify channel response"
             );
+            return TriedRelease(());
         };
 
+        channel.stats.last_host_status = response.status as u32;
         sender.complete(response.status);
         channel.try_release(&mut self.inner.messages)
     }
 
     fn handle_tl_connect_result(&mut self, response: protocol::TlConnectResult) {
         if let Some(rpc) = self.hvsock_tracker.check_result(&response) {
-            rpc.complete(None);
+            rpc.complete(HvsockConnectResult::Failed(response.status));
+        }
+    }
+
+    fn handle_hvsock_timeout(&mut self) {
+        for rpc in self.hvsock_tracker.take_expired(Instant::now()) {
+            tracelimit::warn_ratelimited!(
+                request = ?rpc.input(),
+                "host did not respond to hvsock connect request"
+            );
+            rpc.complete(HvsockConnectResult::TimedOut);
         }
     }
 
     /// Returns false if the message was a pause complete message.
     fn handle_synic_message(&mut self, data: &[u8]) -> bool {
-        let msg = Message::parse(data, self.state.get_version()).unwrap();
+        record_trace(
+            &mut self.inner.messages.trace,
+            TraceDirection::HostToGuest,
+            data,
+        );
+        let msg = match Message::parse(data, self.state.get_version()) {
+            Ok(msg) => msg,
+            Err(err) => {
+                self.handle_unexpected_message(&err, "failed to parse message from host");
+                return true;
+            }
+        };
         tracing::trace!(?msg, "received client message from synic");
 
         match msg {
@@ -1066,7 +1802,7 @@ fn handle_synic_message(&mut self, data: &[u8]) -> bool {
             Message::TlConnectResult(response, ..) => self.handle_tl_connect_result(response),
             // Unsupported messages.
             Message::CloseReservedChannelResponse(..) => {
-                todo!("Unsupported message {msg:?}")
+                self.handle_unexpected_message(&msg, "unsupported message from host");
             }
             Message::PauseResponse(..) => {
                 return false;
@@ -1091,12 +1827,28 @@ fn handle_synic_message(&mut self, data: &[u8]) -> bool {
             | Message::ModifyConnection(..)
             | Message::Pause(..)
             | Message::Resume(..) => {
-                unreachable!("Client received server message {msg:?}");
+                self.handle_unexpected_message(&msg, "client received server message");
             }
         }
         true
     }
 
+    fn handle_unexpected_message(&mut self, msg: &dyn std::fmt::Debug, reason: &'static str) {
+        self.unexpected_messages.increment();
+        match self.unexpected_message_policy {
+            UnexpectedMessagePolicy::Ignore => {}
+            UnexpectedMessagePolicy::Warn => {
+                tracelimit::warn_ratelimited!(?msg, "{reason}");
+            }
+            UnexpectedMessagePolicy::Disconnect => {
+                tracing::error!(?msg, "{reason}, disconnecting from host");
+                if !matches!(self.state, ClientState::Disconnecting { .. }) {
+                    self.handle_unload(Rpc::detached(()));
+                }
+            }
+        }
+    }
+
     fn handle_open_channel(
         &mut self,
         channel_id: ChannelId,
@@ -1124,24 +1876,36 @@ fn handle_open_channel(
         let (request, rpc) = rpc.split();
         let open_data = &request.open_data;
 
-        let supports_interrupt_redirection =
-            if let ClientState::Connected { version, .. } = self.state {
-                version.feature_flags.guest_specified_signal_parameters()
-                    || version.feature_flags.channel_interrupt_redirection()
-            } else {
-                false
-            };
+        let feature_flags = if let ClientState::Connected { version, .. } = self.state {
+            version.feature_flags
+        } else {
+            FeatureFlags::new()
+        };
+
+        // Fail requests for parameters the host does not support, rather than
+        // silently dropping them.
+        let specifies_signal = request.incoming_event.is_some()
+            || request.use_vtl2_connection_id
+            || open_data.event_flag != channel_id.0 as u16;
+        if specifies_signal && !feature_flags.guest_specified_signal_parameters() {
+            rpc.fail(anyhow::anyhow!(
+                "host does not support specifying the event flag or connection ID"
+            ));
+            return;
+        }
 
-        if !supports_interrupt_redirection && open_data.event_flag != channel_id.0 as u16 {
+        let redirect_interrupt = request.redirect_interrupt || request.incoming_event.is_some();
+        if redirect_interrupt && !feature_flags.channel_interrupt_redirection() {
             rpc.fail(anyhow::anyhow!(
-                "host does not support specifying the event flag"
+                "host does not support redirecting interrupts"
             ));
             return;
         }
 
+        let open_id = self.next_open_id;
         let open_channel = protocol::OpenChannel {
             channel_id,
-            open_id: 0,
+            open_id,
             ring_buffer_gpadl_id: open_data.ring_gpadl_id,
             target_vp: open_data
                 .target_vp
@@ -1151,12 +1915,6 @@ fn handle_open_channel(
         };
 
         let connection_id = if request.use_vtl2_connection_id {
-            if !supports_interrupt_redirection {
-                rpc.fail(anyhow::anyhow!(
-                    "host does not support specfiying the connection ID"
-                ));
-                return;
-            }
             protocol::ConnectionId::new(channel_id.0, 2.try_into().unwrap(), 7).0
         } else {
             open_data.connection_id
@@ -1164,16 +1922,8 @@ fn handle_open_channel(
 
         // No failure paths after the one for allocating the event flag, since
         // otherwise we would need to free the event flag.
-        let mut flags = OpenChannelFlags::new();
+        let flags = OpenChannelFlags::new().with_redirect_interrupt(redirect_interrupt);
         let event_flag = if let Some(event) = &request.incoming_event {
-            if !supports_interrupt_redirection {
-                rpc.fail(anyhow::anyhow!(
-                    "host does not support redirecting interrupts"
-                ));
-                return;
-            }
-
-            flags.set_redirect_interrupt(true);
             match self.inner.synic.allocate_event_flag(event) {
                 Ok(flag) => flag,
                 Err(err) => {
@@ -1185,7 +1935,9 @@ fn handle_open_channel(
             open_data.event_flag
         };
 
-        if supports_interrupt_redirection {
+        if feature_flags.guest_specified_signal_parameters()
+            || feature_flags.channel_interrupt_redirection()
+        {
             self.inner.messages.send(&protocol::OpenChannel2 {
                 open_channel,
                 connection_id,
@@ -1199,7 +1951,12 @@ fn handle_open_channel(
         channel
             .connection_id
             .store(connection_id, Ordering::Release);
+        self.inner.events.start(PendingOperation::Open(channel_id));
+        self.next_open_id = self.next_open_id.wrapping_add(1);
+        channel.stats.opens_attempted.increment();
+        channel.stats.transition();
         channel.state = ChannelState::Opening {
+            open_id,
             redirected_event_flag: (request.incoming_event.is_some()).then_some(event_flag),
             redirected_event: request.incoming_event,
             rpc,
@@ -1230,6 +1987,7 @@ fn handle_restore_channel(
         channel
             .connection_id
             .store(request.connection_id, Ordering::Release);
+        channel.stats.transition();
         channel.state = ChannelState::Opened {
             redirected_event_flag: request.redirected_event_flag,
             redirected_event: request.incoming_event,
@@ -1244,7 +2002,13 @@ fn handle_gpadl(&mut self, channel_id: ChannelId, rpc: FailableRpc<GpadlRequest,
         let mut channel = self.channels.get_mut(channel_id);
         if channel
             .gpadls
-            .insert(request.id, GpadlState::Offered(rpc))
+            .insert(
+                request.id,
+                GpadlState::Offered {
+                    rpc: Some(rpc),
+                    teardown_rpcs: Vec::new(),
+                },
+            )
             .is_some()
         {
             panic!(
@@ -1278,11 +2042,18 @@ fn handle_gpadl(&mut self, channel_id: ChannelId, rpc: FailableRpc<GpadlRequest,
             count: request.count,
         };
 
+        self.inner
+            .events
+            .start(PendingOperation::CreateGpadl(request.id));
         self.inner
             .messages
             .send_with_data(&message, first.as_bytes());
 
-        // Send GpadlBody messages for the remaining values.
+        // Queue GpadlBody messages for the remaining values. These are not
+        // posted inline: large GPADLs can require many body messages, which
+        // would overflow the host's message queue. Instead, they are drained
+        // by the flush path, which waits for the host when its queue is full
+        // and stops accepting new requests until the batch has been sent.
         let message = protocol::GpadlBody {
             rsvd: 0,
             gpadl_id: request.id,
@@ -1290,49 +2061,42 @@ fn handle_gpadl(&mut self, channel_id: ChannelId, rpc: FailableRpc<GpadlRequest,
         for chunk in remaining.chunks(protocol::GpadlBody::MAX_DATA_VALUES) {
             self.inner
                 .messages
-                .send_with_data(&message, chunk.as_bytes());
+                .queue_with_data(&message, chunk.as_bytes());
         }
     }
 
+    /// Tears down a GPADL, completing `rpc` once the host has released it.
+    ///
+    /// Teardown is idempotent: requests for a GPADL that is already being torn
+    /// down complete along with the first request, and requests for a GPADL
+    /// that no longer exists complete immediately.
     fn handle_gpadl_teardown(&mut self, channel_id: ChannelId, rpc: Rpc<GpadlId, ()>) {
         let (gpadl_id, rpc) = rpc.split();
         let mut channel = self.channels.get_mut(channel_id);
+        let revoked = matches!(channel.state, ChannelState::Revoked);
         let Some(gpadl_state) = channel.gpadls.get_mut(&gpadl_id) else {
-            tracing::warn!(
+            tracing::debug!(
                 gpadl_id = gpadl_id.0,
                 channel_id = channel_id.0,
                 key = %OfferKey::from(&channel.offer),
-                "Gpadl teardown for unknown gpadl or revoked channel"
+                "gpadl teardown for unknown or already torn down gpadl"
             );
+            rpc.complete(());
             return;
         };
 
         match gpadl_state {
-            GpadlState::Offered(_) => {
-                tracing::warn!(
-                    gpadl_id = gpadl_id.0,
-                    channel_id = channel_id.0,
-                    key = %OfferKey::from(&channel.offer),
-                    "gpadl teardown for offered gpadl"
-                );
+            GpadlState::Offered { teardown_rpcs, .. } => {
+                // Tear down the GPADL once the host has created it.
+                teardown_rpcs.push(rpc);
+            }
+            GpadlState::Created if revoked => {
+                // The host released the GPADL with the channel.
+                channel.gpadls.remove(&gpadl_id);
+                rpc.complete(());
             }
             GpadlState::Created => {
-                *gpadl_state = GpadlState::TearingDown { rpcs: vec![rpc] };
-                // The caller must guarantee that GPADL teardown requests are only made
-                // for unique GPADL IDs. This is currently enforced in vmbus_server by
-                // blocking GPADL teardown messages for reserved channels.
-                assert!(
-                    self.inner
-                        .teardown_gpadls
-                        .insert(gpadl_id, channel_id)
-                        .is_none(),
-                    "Gpadl state validated above"
-                );
-
-                self.inner.messages.send(&protocol::GpadlTeardown {
-                    channel_id,
-                    gpadl_id,
-                });
+                *gpadl_state = self.inner.teardown_gpadl(channel_id, gpadl_id, vec![rpc]);
             }
             GpadlState::TearingDown { rpcs } => {
                 rpcs.push(rpc);
@@ -1342,22 +2106,47 @@ fn handle_gpadl_teardown(&mut self, channel_id: ChannelId, rpc: Rpc<GpadlId, ()>
 
     fn handle_close_channel(&mut self, channel_id: ChannelId) {
         let mut channel = self.channels.get_mut(channel_id);
-        self.inner.close_channel(channel_id, &mut channel);
-    }
-
-    fn handle_modify_channel(&mut self, channel_id: ChannelId, rpc: Rpc<ModifyRequest, i32>) {
-        // The client doesn't support versions below Iron, so we always expect the host to send a
-        // ModifyChannelResponse. This means we don't need to worry about sending a ChannelResponse
-        // if that weren't supported.
-        assert!(self.check_version(Version::Iron));
-        let mut channel = self.channels.get_mut(channel_id);
-        if channel.modify_response_send.is_some() {
-            panic!("duplicate channel modify request {channel_id:?}");
-        }
-
-        let (request, response) = rpc.split();
-        channel.modify_response_send = Some(response);
-        let payload = match request {
+        match std::mem::replace(&mut channel.state, ChannelState::Offered) {
+            ChannelState::Opening {
+                open_id,
+                redirected_event_flag,
+                redirected_event: _,
+                rpc,
+            } => {
+                // The host does not support canceling an open, so close the
+                // channel once the open completes.
+                tracing::info!(
+                    channel_id = channel_id.0,
+                    key = %OfferKey::from(&channel.offer),
+                    open_id,
+                    "channel closed while opening"
+                );
+                rpc.fail(anyhow::anyhow!("channel closed while opening"));
+                channel.stats.transition();
+                channel.state = ChannelState::ClosingAfterOpen {
+                    open_id,
+                    redirected_event_flag,
+                };
+            }
+            state => {
+                channel.state = state;
+                self.inner.close_channel(channel_id, &mut channel);
+            }
+        }
+    }
+
+    fn handle_modify_channel(&mut self, channel_id: ChannelId, rpc: Rpc<ModifyRequest, i32>) {
+        // Hosts before Iron apply the request without sending a ModifyChannelResponse, so the
+        // request is completed as soon as it's sent.
+        let expect_response = self.check_version(Version::Iron);
+        let mut channel = self.channels.get_mut(channel_id);
+        if channel.modify_response_send.is_some() {
+            panic!("duplicate channel modify request {channel_id:?}");
+        }
+
+        let (request, response) = rpc.split();
+        channel.stats.modify_requests.increment();
+        let payload = match request {
             ModifyRequest::TargetVp { target_vp } => protocol::ModifyChannel {
                 channel_id,
                 target_vp,
@@ -1365,6 +2154,11 @@ fn handle_modify_channel(&mut self, channel_id: ChannelId, rpc: Rpc<ModifyReques
         };
 
         self.inner.messages.send(&payload);
+        if expect_response {
+            channel.modify_response_send = Some(response);
+        } else {
+            response.complete(protocol::STATUS_SUCCESS);
+        }
     }
 
     fn handle_channel_request(&mut self, channel_id: ChannelId, request: ChannelRequest) {
@@ -1379,13 +2173,44 @@ fn handle_channel_request(&mut self, channel_id: ChannelId, request: ChannelRequ
                 req.handle_sync(|()| self.handle_close_channel(channel_id))
             }
             ChannelRequest::Modify(req) => self.handle_modify_channel(channel_id, req),
+            ChannelRequest::Release(req) => req.handle_sync(|()| {
+                self.handle_release_channel(channel_id);
+            }),
+            ChannelRequest::Inspect(deferred) => {
+                if let Some(channel) = self.channels.0.get(&channel_id) {
+                    deferred.inspect(channel);
+                } else {
+                    deferred.value("removed");
+                }
+            }
+        }
+    }
+
+    fn status(&self) -> ClientStatus {
+        ClientStatus {
+            state: self.state.status(),
+            version: self.state.get_version(),
+            running: self.running,
+            channels: self.channels.0.len(),
+            open_channels: self
+                .channels
+                .0
+                .values()
+                .filter(|channel| matches!(channel.state, ChannelState::Opened { .. }))
+                .count(),
+            gpadls: self
+                .channels
+                .0
+                .values()
+                .map(|channel| channel.gpadls.len())
+                .sum(),
         }
     }
 
     async fn handle_task(&mut self, task: TaskRequest) {
         match task {
             TaskRequest::Inspect(deferred) => {
-                deferred.inspect(&*self);
+                deferred.inspect(&mut *self);
             }
             TaskRequest::Save(rpc) => rpc.handle_sync(|()| self.handle_save()),
             TaskRequest::Restore(rpc) => {
@@ -1394,7 +2219,178 @@ async fn handle_task(&mut self, task: TaskRequest) {
             TaskRequest::PostRestore(rpc) => rpc.handle_sync(|()| self.handle_post_restore()),
             TaskRequest::Start => self.handle_start(),
             TaskRequest::Stop(rpc) => rpc.handle(async |()| self.handle_stop().await).await,
+            TaskRequest::Status(rpc) => rpc.handle_sync(|()| self.status()),
+            TaskRequest::Reset(rpc) => rpc.handle_sync(|()| self.handle_reset()),
+            #[cfg(any(test, feature = "testing"))]
+            TaskRequest::Snapshot(rpc) => rpc.handle_sync(|()| testing::ClientSnapshot {
+                state: self.state.to_string(),
+                unexpected_messages: self.unexpected_messages.get(),
+                channels: self
+                    .channels
+                    .0
+                    .iter()
+                    .map(|(&id, channel)| (id, channel.state.to_string()))
+                    .collect(),
+            }),
+        }
+    }
+
+    fn handle_reset(&mut self) {
+        tracing::info!(client_state = %self.state, "resetting VmBus client");
+        match std::mem::replace(&mut self.state, ClientState::Disconnected) {
+            ClientState::Disconnected | ClientState::Connected { .. } => {}
+            ClientState::Connecting { version: _, rpc } => {
+                self.inner.events.finish(PendingOperation::Connect);
+                rpc.complete(Err(ConnectError::Reset));
+            }
+            ClientState::RequestingOffers {
+                version: _,
+                rpc,
+                offers: _,
+            } => {
+                self.inner.events.finish(PendingOperation::Connect);
+                rpc.complete(Err(ConnectError::Reset));
+            }
+            ClientState::Disconnecting { version: _, rpc } => {
+                self.inner.events.finish(PendingOperation::Unload);
+                rpc.complete(());
+            }
+        }
+        self.reset_channels();
+    }
+
+    /// Drops all per-channel state, notifying devices that their channels
+    /// have been revoked, after the host has forgotten the connection.
+    ///
+    /// Nothing is sent to the host, and any requests still waiting for it are
+    /// failed, except GPADL teardowns, which the host has implicitly
+    /// completed.
+    fn reset_channels(&mut self) {
+        for (channel_id, channel) in self.channels.0.drain() {
+            let event_flag = match channel.state {
+                ChannelState::Opening {
+                    open_id: _,
+                    redirected_event_flag,
+                    redirected_event: _,
+                    rpc,
+                } => {
+                    self.inner.events.finish(PendingOperation::Open(channel_id));
+                    rpc.fail(anyhow::anyhow!("client reset"));
+                    redirected_event_flag
+                }
+                ChannelState::ClosingAfterOpen {
+                    open_id: _,
+                    redirected_event_flag,
+                } => {
+                    self.inner.events.finish(PendingOperation::Open(channel_id));
+                    redirected_event_flag
+                }
+                ChannelState::Opened {
+                    redirected_event_flag,
+                    redirected_event: _,
+                } => redirected_event_flag,
+                ChannelState::Offered | ChannelState::Restored | ChannelState::Revoked => None,
+            };
+            if let Some(event_flag) = event_flag {
+                self.inner.synic.free_event_flag(event_flag);
+            }
+
+            for (gpadl_id, gpadl_state) in channel.gpadls {
+                match gpadl_state {
+                    GpadlState::Offered { rpc, teardown_rpcs } => {
+                        self.inner
+                            .events
+                            .finish(PendingOperation::CreateGpadl(gpadl_id));
+                        if let Some(rpc) = rpc {
+                            rpc.fail(anyhow::anyhow!("client reset"));
+                        }
+                        for rpc in teardown_rpcs {
+                            rpc.complete(());
+                        }
+                    }
+                    GpadlState::Created => {}
+                    GpadlState::TearingDown { rpcs } => {
+                        self.inner
+                            .events
+                            .finish(PendingOperation::TeardownGpadl(gpadl_id));
+                        for rpc in rpcs {
+                            rpc.complete(());
+                        }
+                    }
+                }
+            }
+
+            // Channels that were already rescinded have notified their
+            // devices.
+            if let Some(revoke_send) = channel.revoke_send {
+                self.inner.events.emit(ClientEventKind::ChannelRescinded {
+                    channel_id,
+                    key: OfferKey::from(&channel.offer),
+                });
+                revoke_send.send(());
+            }
+        }
+
+        self.inner.teardown_gpadls.clear();
+        // Dropping the request streams fails any further requests from the
+        // devices of the old channels.
+        self.inner.channel_requests = SelectAll::new();
+        self.inner.messages.queued.clear();
+        self.inner.synic.set_monitor_page(None);
+        self.stopped_requests
+            .retain(|request| matches!(request, StoppedRequest::Client(_)));
+        for request in self
+            .modify_request
+            .take()
+            .into_iter()
+            .chain(self.queued_modify_requests.drain(..))
+        {
+            request.complete(ConnectionState::FAILED_UNKNOWN_FAILURE);
+        }
+        // Dropping the pending requests cancels them.
+        self.hvsock_tracker = hvsock::HvsockRequestTracker::new(self.hvsock_tracker.timeout());
+    }
+
+    /// Releases a channel at the request of the device, before or after the
+    /// host rescinds it.
+    fn handle_release_channel(&mut self, channel_id: ChannelId) -> TriedRelease {
+        let mut channel = self.channels.get_mut(channel_id);
+        tracing::info!(
+            channel_id = channel_id.0,
+            key = %OfferKey::from(&channel.offer),
+            channel_state = %channel.state,
+            "channel released by client"
+        );
+        channel.is_client_released = true;
+
+        // Stop processing requests for the channel, since it may be removed
+        // (and its ID reused by the host) before the request stream is
+        // dropped.
+        if let Some(requests) = self
+            .inner
+            .channel_requests
+            .iter_mut()
+            .find(|requests| requests.value() == Some(&channel_id))
+        {
+            requests.end();
+        }
+
+        // If an open is in progress, the channel is closed when it completes.
+        if let ChannelState::Opened { .. } = channel.state {
+            self.inner.close_channel(channel_id, &mut channel);
+        }
+
+        // The host releases any remaining GPADLs when the channel is revoked,
+        // so they only need to be torn down explicitly before that.
+        if !matches!(channel.state, ChannelState::Revoked) {
+            for (&gpadl_id, gpadl_state) in &mut channel.gpadls {
+                if let GpadlState::Created = gpadl_state {
+                    *gpadl_state = self.inner.teardown_gpadl(channel_id, gpadl_id, Vec::new());
+                }
+            }
         }
+
+        channel.try_release(&mut self.inner.messages)
     }
 
     /// Makes sure a channel is closed if the channel request stream was dropped.
@@ -1423,6 +2419,18 @@ fn handle_start(&mut self) {
         self.msg_source.resume_message_stream();
         self.inner.messages.resume();
         self.running = true;
+
+        for request in std::mem::take(&mut self.stopped_requests) {
+            match request {
+                StoppedRequest::Client(request) => self.handle_client_request(request),
+                StoppedRequest::Channel(id, Some(request)) => {
+                    self.handle_channel_request(id, request)
+                }
+                StoppedRequest::Channel(id, None) => {
+                    self.handle_device_removal(id);
+                }
+            }
+        }
     }
 
     async fn handle_stop(&mut self) {
@@ -1531,17 +2539,33 @@ async fn run(&mut self) {
                     .then(|| self.inner.messages.flush_messages().fuse()),
             );
 
-            let mut client_request_recv = OptionFuture::from(
-                (self.running && !host_backed_up).then(|| self.client_request_recv.next()),
-            );
+            // While stopped, requests are queued up to a limit and handled on
+            // start. Past the limit, they are left in their channels, so that
+            // senders see the same backpressure as when the host is backed
+            // up.
+            let accept_requests = if self.running {
+                !host_backed_up
+            } else {
+                self.stopped_requests.len() < MAX_STOPPED_REQUESTS
+            };
+
+            let mut client_request_recv =
+                OptionFuture::from(accept_requests.then(|| self.client_request_recv.next()));
 
             let mut channel_requests = OptionFuture::from(
-                (self.running && !host_backed_up)
-                    .then(|| self.inner.channel_requests.select_next_some()),
+                accept_requests.then(|| self.inner.channel_requests.select_next_some()),
+            );
+
+            let hvsock_timeout = OptionFuture::from(
+                self.running
+                    .then(|| self.hvsock_tracker.next_deadline())
+                    .flatten()
+                    .map(|deadline| self.hvsock_timer.sleep_until(deadline).fuse()),
             );
 
             futures::select! { // merge semantics
                 _r = pin!(flush_messages) => {}
+                _r = pin!(hvsock_timeout) => self.handle_hvsock_timeout(),
                 r = self.task_recv.next() => {
                     if let Some(task) = r {
                         self.handle_task(task).await;
@@ -1551,17 +2575,23 @@ async fn run(&mut self) {
                 }
                 r = client_request_recv => {
                     if let Some(Some(request)) = r {
-                        self.handle_client_request(request);
+                        if self.running {
+                            self.handle_client_request(request);
+                        } else {
+                            self.stopped_requests.push_back(StoppedRequest::Client(request));
+                        }
                     } else {
                         break;
                     }
                 }
                 r = channel_requests => {
-                    match r.unwrap() {
-                        (id, Some(request)) => self.handle_channel_request(id, request),
-                        (id, _) => {
-                            self.handle_device_removal(id);
-                        }
+                    let (id, request) = r.unwrap();
+                    if !self.running && !matches!(request, Some(ChannelRequest::Inspect(_))) {
+                        self.stopped_requests.push_back(StoppedRequest::Channel(id, request));
+                    } else if let Some(request) = request {
+                        self.handle_channel_request(id, request);
+                    } else {
+                        self.handle_device_removal(id);
                     }
                 }
                 r = message_recv => {
@@ -1585,6 +2615,31 @@ async fn run(&mut self) {
 }
 
 impl ClientTaskInner {
+    /// Sends a teardown request for a created GPADL, returning the GPADL's new
+    /// state.
+    fn teardown_gpadl(
+        &mut self,
+        channel_id: ChannelId,
+        gpadl_id: GpadlId,
+        rpcs: Vec<Rpc<(), ()>>,
+    ) -> GpadlState {
+        // The caller must guarantee that GPADL teardown requests are only made
+        // for unique GPADL IDs. This is currently enforced in vmbus_server by
+        // blocking GPADL teardown messages for reserved channels.
+        assert!(
+            self.teardown_gpadls.insert(gpadl_id, channel_id).is_none(),
+            "gpadl {:#x} already tearing down",
+            gpadl_id.0
+        );
+
+        self.events.start(PendingOperation::TeardownGpadl(gpadl_id));
+        self.messages.send(&protocol::GpadlTeardown {
+            channel_id,
+            gpadl_id,
+        });
+        GpadlState::TearingDown { rpcs }
+    }
+
     fn close_channel(&mut self, channel_id: ChannelId, channel: &mut Channel) {
         if let ChannelState::Opened {
             redirected_event_flag,
@@ -1601,8 +2656,13 @@ fn close_channel(&mut self, channel_id: ChannelId, channel: &mut Channel) {
             );
 
             self.messages.send(&protocol::CloseChannel { channel_id });
+            channel.stats.transition();
             channel.state = ChannelState::Offered;
             channel.connection_id.store(0, Ordering::Release);
+            self.events.emit(ClientEventKind::ChannelClosed {
+                channel_id,
+                key: OfferKey::from(&channel.offer),
+            });
         } else {
             tracing::warn!(
                 channel_id = channel_id.0,
@@ -1617,8 +2677,17 @@ fn close_channel(&mut self, channel_id: ChannelId, channel: &mut Channel) {
 #[derive(Debug, Inspect)]
 #[inspect(external_tag)]
 enum GpadlState {
-    /// GpadlHeader has been sent to the host.
-    Offered(#[inspect(skip)] FailableRpc<(), ()>),
+    /// GpadlHeader has been sent to the host. The RPC is `None` if the GPADL
+    /// was restored from saved state, in which case it is torn down once
+    /// created.
+    Offered {
+        #[inspect(skip)]
+        rpc: Option<FailableRpc<(), ()>>,
+        /// Teardown requests made during creation. The GPADL is torn down
+        /// once created if there are any.
+        #[inspect(skip)]
+        teardown_rpcs: Vec<Rpc<(), ()>>,
+    },
     /// Host has responded with GpadlCreated.
     Created,
     /// GpadlTeardown message has been sent to the host.
@@ -1632,9 +2701,36 @@ enum GpadlState {
 struct OutgoingMessages {
     #[inspect(skip)]
     poster: Box<dyn PollPostMessage>,
+    backoff: PostBackoff,
     #[inspect(with = "|x| x.len()")]
     queued: VecDeque<OutgoingMessage>,
     state: OutgoingMessageState,
+    /// The number of messages that could not be posted immediately.
+    deferred: Counter,
+    #[inspect(with = "Option::is_some")]
+    trace: Option<TraceWriter>,
+}
+
+/// Appends a message to the trace, if any, disabling tracing on failure.
+fn record_trace(trace: &mut Option<TraceWriter>, direction: TraceDirection, data: &[u8]) {
+    if let Some(writer) = trace {
+        if let Err(err) = writer.record(direction, data) {
+            tracing::error!(
+                error = &err as &dyn std::error::Error,
+                "failed to write message trace, disabling tracing"
+            );
+            *trace = None;
+        }
+    }
+}
+
+/// Handles the result of posting a message after any transient failures have
+/// been retried.
+fn check_post_result(r: Result<(), PostError>) {
+    if let Err(err) = r {
+        // There is no way to recover the protocol state if a message is lost.
+        panic!("failed to post message: {err:?}");
+    }
 }
 
 #[derive(Inspect, PartialEq, Eq, Debug)]
@@ -1662,31 +2758,54 @@ fn send_with_data<
         tracing::trace!(typ = ?T::MESSAGE_TYPE, "Sending message to host");
         let msg = OutgoingMessage::with_data(msg, data);
         if self.queued.is_empty() && self.state == OutgoingMessageState::Running {
-            let r = self.poster.poll_post_message(
-                &mut Context::from_waker(std::task::Waker::noop()),
-                protocol::VMBUS_MESSAGE_REDIRECT_CONNECTION_ID,
-                1,
-                msg.data(),
-            );
-            if let Poll::Ready(()) = r {
+            let mut cx = Context::from_waker(std::task::Waker::noop());
+            let r = self.backoff.poll_post(&mut cx, |cx| {
+                self.poster.poll_post_message(
+                    cx,
+                    protocol::VMBUS_MESSAGE_REDIRECT_CONNECTION_ID,
+                    1,
+                    msg.data(),
+                )
+            });
+            if let Poll::Ready(r) = r {
+                check_post_result(r);
+                record_trace(&mut self.trace, TraceDirection::GuestToHost, msg.data());
                 return;
             }
         }
         tracing::trace!("queueing message");
+        self.deferred.increment();
         self.queued.push_back(msg);
     }
 
+    /// Queues a message to be sent by [`Self::flush_messages`], without trying
+    /// to post it immediately.
+    fn queue_with_data<
+        T: IntoBytes + protocol::VmbusMessage + std::fmt::Debug + Immutable + KnownLayout,
+    >(
+        &mut self,
+        msg: &T,
+        data: &[u8],
+    ) {
+        tracing::trace!(typ = ?T::MESSAGE_TYPE, "Queueing message to host");
+        self.queued.push_back(OutgoingMessage::with_data(msg, data));
+    }
+
     async fn flush_messages(&mut self) {
         let mut send = async |msg: &OutgoingMessage| {
-            poll_fn(|cx| {
-                self.poster.poll_post_message(
-                    cx,
-                    protocol::VMBUS_MESSAGE_REDIRECT_CONNECTION_ID,
-                    1,
-                    msg.data(),
-                )
+            let r = poll_fn(|cx| {
+                self.backoff.poll_post(cx, |cx| {
+                    self.poster.poll_post_message(
+                        cx,
+                        protocol::VMBUS_MESSAGE_REDIRECT_CONNECTION_ID,
+                        1,
+                        msg.data(),
+                    )
+                })
             })
-            .await
+            .await;
+            check_post_result(r);
+            record_trace(&mut self.trace, TraceDirection::GuestToHost, msg.data());
         };
         match self.state {
             OutgoingMessageState::Running => {
@@ -1741,6 +2860,7 @@ struct ClientTaskInner {
     #[inspect(skip)]
     channel_requests: SelectAll<TaggedStream<ChannelId, mesh::Receiver<ChannelRequest>>>,
     synic: SynicState,
+    events: ClientEventSink,
 }
 
 #[derive(Inspect)]
@@ -1749,6 +2869,8 @@ struct SynicState {
     event_client: Arc<dyn SynicEventClient>,
     #[inspect(iter_by_index)]
     event_flag_state: Vec<bool>,
+    monitor_page: Option<MonitorPageGpas>,
+    monitor_pages: Option<Arc<MonitorPages>>,
 }
 
 #[derive(Inspect, Default)]
@@ -1813,6 +2935,22 @@ fn revoked_channel_with_pending_request(&self) -> Option<(ChannelId, &'static st
         })
     }
 
+    /// Returns the channel referenced by a message from the host, or `None`
+    /// if the host referenced a channel the client does not know about.
+    fn get_mut_for_host(&mut self, channel_id: ChannelId, message: &str) -> Option<ChannelRef<'_>> {
+        match self.0.entry(channel_id) {
+            hash_map::Entry::Occupied(entry) => Some(ChannelRef(entry)),
+            hash_map::Entry::Vacant(_) => {
+                tracelimit::warn_ratelimited!(
+                    channel_id = channel_id.0,
+                    host_message = message,
+                    "host message for unknown channel"
+                );
+                None
+            }
+        }
+    }
+
     #[track_caller]
     fn get_mut(&mut self, channel_id: ChannelId) -> ChannelRef<'_> {
         match self.0.entry(channel_id) {
@@ -1845,6 +2983,28 @@ fn guest_to_host_interrupt(&self, connection_id: Arc<AtomicU32>) -> Interrupt {
         })
     }
 
+    fn monitor_signal(
+        &self,
+        offer: &protocol::OfferChannel,
+        direct: &Interrupt,
+    ) -> Option<MonitorSignal> {
+        if offer.monitor_allocated == 0 {
+            return None;
+        }
+        Some(MonitorSignal::new(
+            MonitorId(offer.monitor_id),
+            self.monitor_pages.clone()?,
+            direct.clone(),
+        ))
+    }
+
+    fn set_monitor_page(&mut self, monitor_page: Option<MonitorPageGpas>) {
+        self.monitor_page = monitor_page;
+        if let Some(pages) = &self.monitor_pages {
+            pages.set(monitor_page);
+        }
+    }
+
     const MAX_EVENT_FLAGS: u16 = 2047;
 
     fn allocate_event_flag(&mut self, event: &Event) -> Result<u16> {
@@ -1870,6 +3030,23 @@ fn allocate_event_flag(&mut self, event: &Event) -> Result<u16> {
     }
 
     fn restore_event_flag(&mut self, flag: u16, event: &Event) -> Result<()> {
+        let i = self.unused_event_flag_index(flag)?;
+        self.event_client
+            .map_event(flag, event)
+            .context("failed to map event")?;
+        self.event_flag_state[i] = true;
+        Ok(())
+    }
+
+    /// Marks `flag` as in use without mapping an event to it, for an open that
+    /// was in flight when the client was saved.
+    fn reserve_event_flag(&mut self, flag: u16) -> Result<()> {
+        let i = self.unused_event_flag_index(flag)?;
+        self.event_flag_state[i] = true;
+        Ok(())
+    }
+
+    fn unused_event_flag_index(&mut self, flag: u16) -> Result<usize> {
         let i = (flag as usize)
             .checked_sub(1)
             .context("invalid event flag")?;
@@ -1882,11 +3059,7 @@ fn restore_event_flag(&mut self, flag: u16, event: &Event) -> Result<()> {
         if self.event_flag_state[i] {
             anyhow::bail!("event flag already in use");
         }
-        self.event_client
-            .map_event(flag, event)
-            .context("failed to map event")?;
-        self.event_flag_state[i] = true;
-        Ok(())
+        Ok(i)
     }
 
     fn free_event_flag(&mut self, flag: u16) {
@@ -1899,359 +3072,93 @@ fn free_event_flag(&mut self, flag: u16) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::TestServer;
+    use crate::testing::check_message;
+    use crate::testing::check_message_with_data;
+    use crate::testing::in_msg;
+    use crate::testing::parse_message;
+    use crate::testing::test_init;
+    use crate::testing::test_init_with;
     use futures_concurrency::future::Join;
     use guid::Guid;
     use pal_async::DefaultDriver;
     use pal_async::async_test;
-    use pal_async::timer::PolledTimer;
     use protocol::TargetInfo;
-    use std::fmt::Debug;
-    use std::task::ready;
-    use std::time::Duration;
     use test_with_tracing::test;
-    use vmbus_core::protocol::MessageHeader;
     use vmbus_core::protocol::MessageType;
     use vmbus_core::protocol::OfferFlags;
     use vmbus_core::protocol::UserDefinedData;
-    use vmbus_core::protocol::VmbusMessage;
-    use zerocopy::FromBytes;
+    use vmbus_core::trace::TraceBuffer;
     use zerocopy::FromZeros;
-    use zerocopy::Immutable;
-    use zerocopy::IntoBytes;
-    use zerocopy::KnownLayout;
 
     const VMBUS_TEST_CLIENT_ID: Guid = guid::guid!("e6e6e6e6-e6e6-e6e6-e6e6-e6e6e6e6e6e6");
 
-    fn in_msg<T: IntoBytes + Immutable + KnownLayout>(message_type: MessageType, t: T) -> Vec<u8> {
-        let mut data = Vec::new();
-        data.extend_from_slice(&message_type.0.to_ne_bytes());
-        data.extend_from_slice(&0u32.to_ne_bytes());
-        data.extend_from_slice(t.as_bytes());
-        data
-    }
-
-    #[track_caller]
-    fn check_message<T>(msg: OutgoingMessage, chk: T)
-    where
-        T: IntoBytes + FromBytes + Immutable + KnownLayout + Debug + VmbusMessage,
-    {
-        check_message_with_data(msg, chk, &[]);
+    #[async_test]
+    async fn test_initiate_contact_success(driver: DefaultDriver) {
+        let (mut server, client) = test_init(&driver);
+        let _recv = client
+            .access
+            .client_request_send
+            .call(ClientRequest::Connect, ConnectRequest::default());
+        check_message(
+            server.next().await.unwrap(),
+            protocol::InitiateContact2 {
+                initiate_contact: protocol::InitiateContact {
+                    version_requested: Version::Copper as u32,
+                    target_message_vp: 0,
+                    interrupt_page_or_target_info: TargetInfo::new()
+                        .with_sint(2)
+                        .with_vtl(0)
+                        .with_feature_flags(SUPPORTED_FEATURE_FLAGS.into())
+                        .into(),
+                    parent_to_child_monitor_page_gpa: 0,
+                    child_to_parent_monitor_page_gpa: 0,
+                },
+                ..FromZeros::new_zeroed()
+            },
+        );
     }
 
-    #[track_caller]
-    fn check_message_with_data<T>(msg: OutgoingMessage, chk: T, data: &[u8])
-    where
-        T: IntoBytes + FromBytes + Immutable + KnownLayout + Debug + VmbusMessage,
-    {
-        let chk_data = OutgoingMessage::with_data(&chk, data);
-        if msg.data() != chk_data.data() {
-            let (header, rest) = MessageHeader::read_from_prefix(msg.data()).unwrap();
-            assert_eq!(header.message_type(), <T as VmbusMessage>::MESSAGE_TYPE);
-            let (msg, rest) = T::read_from_prefix(rest).expect("incorrect message size");
-            if msg.as_bytes() != chk.as_bytes() {
-                panic!("mismatched messages, expected {:#?}, got {:#?}", chk, msg);
-            }
-            if rest != data {
-                panic!("mismatched data, expected {:#?}, got {:#?}", data, rest);
-            }
-        }
-    }
+    #[async_test]
+    async fn test_connect_success(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let client_connect = client.connect(0, None, Guid::ZERO);
 
-    struct TestServer {
-        messages: mesh::Receiver<OutgoingMessage>,
-        send: mesh::Sender<Vec<u8>>,
-    }
+        let server_connect = async {
+            check_message(
+                server.next().await.unwrap(),
+                protocol::InitiateContact2 {
+                    initiate_contact: protocol::InitiateContact {
+                        version_requested: Version::Copper as u32,
+                        target_message_vp: 0,
+                        interrupt_page_or_target_info: TargetInfo::new()
+                            .with_sint(2)
+                            .with_vtl(0)
+                            .with_feature_flags(SUPPORTED_FEATURE_FLAGS.into())
+                            .into(),
+                        parent_to_child_monitor_page_gpa: 0,
+                        child_to_parent_monitor_page_gpa: 0,
+                    },
+                    ..FromZeros::new_zeroed()
+                },
+            );
 
-    impl TestServer {
-        async fn next(&mut self) -> Option<OutgoingMessage> {
-            self.messages.next().await
-        }
+            server.send(in_msg(
+                MessageType::VERSION_RESPONSE,
+                protocol::VersionResponse2 {
+                    version_response: protocol::VersionResponse {
+                        version_supported: 1,
+                        connection_state: ConnectionState::SUCCESSFUL,
+                        padding: 0,
+                        selected_version_or_connection_id: 0,
+                    },
+                    supported_features: SUPPORTED_FEATURE_FLAGS.into_bits(),
+                },
+            ));
 
-        fn send(&self, msg: Vec<u8>) {
-            self.send.send(msg);
-        }
-
-        async fn connect(&mut self, client: &mut VmbusClient) -> ConnectResult {
-            self.connect_with_channels(client, |_| {}).await
-        }
-
-        async fn connect_with_channels(
-            &mut self,
-            client: &mut VmbusClient,
-            send_offers: impl FnOnce(&mut Self),
-        ) -> ConnectResult {
-            let client_connect = client.connect(0, None, Guid::ZERO);
-
-            let server_connect = async {
-                let _ = self.next().await.unwrap();
-
-                self.send(in_msg(
-                    MessageType::VERSION_RESPONSE,
-                    protocol::VersionResponse2 {
-                        version_response: protocol::VersionResponse {
-                            version_supported: 1,
-                            connection_state: ConnectionState::SUCCESSFUL,
-                            padding: 0,
-                            selected_version_or_connection_id: 0,
-                        },
-                        supported_features: SUPPORTED_FEATURE_FLAGS.into(),
-                    },
-                ));
-
-                check_message(self.next().await.unwrap(), protocol::RequestOffers {});
-
-                send_offers(self);
-                self.send(in_msg(MessageType::ALL_OFFERS_DELIVERED, [0x00]));
-            };
-
-            let (connection, ()) = (client_connect, server_connect).join().await;
-
-            let connection = connection.unwrap();
-            assert_eq!(connection.version.version, Version::Copper);
-            assert_eq!(connection.version.feature_flags, SUPPORTED_FEATURE_FLAGS);
-            connection
-        }
-
-        async fn get_channel(&mut self, client: &mut VmbusClient) -> OfferInfo {
-            let [channel] = self
-                .get_channels(client, 1)
-                .await
-                .offers
-                .try_into()
-                .unwrap();
-            channel
-        }
-
-        async fn get_channels(&mut self, client: &mut VmbusClient, count: usize) -> ConnectResult {
-            self.connect_with_channels(client, |this| {
-                for i in 0..count {
-                    let offer = protocol::OfferChannel {
-                        interface_id: Guid::new_random(),
-                        instance_id: Guid::new_random(),
-                        rsvd: [0; 4],
-                        flags: OfferFlags::new(),
-                        mmio_megabytes: 0,
-                        user_defined: UserDefinedData::new_zeroed(),
-                        subchannel_index: 0,
-                        mmio_megabytes_optional: 0,
-                        channel_id: ChannelId(i as u32),
-                        monitor_id: 0,
-                        monitor_allocated: 0,
-                        is_dedicated: 0,
-                        connection_id: 0,
-                    };
-
-                    this.send(in_msg(MessageType::OFFER_CHANNEL, offer));
-                }
-            })
-            .await
-        }
-
-        async fn stop_client(&mut self, client: &mut VmbusClient) {
-            let client_stop = client.stop();
-            let server_stop = async {
-                check_message(self.next().await.unwrap(), protocol::Pause);
-                self.send(in_msg(MessageType::PAUSE_RESPONSE, protocol::PauseResponse));
-            };
-            (client_stop, server_stop).join().await;
-        }
-
-        async fn start_client(&mut self, client: &mut VmbusClient) {
-            client.start();
-            check_message(self.next().await.unwrap(), protocol::Resume);
-        }
-    }
-
-    struct TestServerClient {
-        sender: mesh::Sender<OutgoingMessage>,
-        timer: PolledTimer,
-        deadline: Option<pal_async::timer::Instant>,
-    }
-
-    impl PollPostMessage for TestServerClient {
-        fn poll_post_message(
-            &mut self,
-            cx: &mut Context<'_>,
-            _connection_id: u32,
-            _typ: u32,
-            msg: &[u8],
-        ) -> Poll<()> {
-            loop {
-                if let Some(deadline) = self.deadline {
-                    ready!(self.timer.poll_until(cx, deadline));
-                    self.deadline = None;
-                }
-                // Randomly choose whether to delay the message.
-                //
-                // FUTURE: use some kind of deterministic test framework for this to
-                // allow for reproducible tests.
-                let mut b = [0];
-                getrandom::fill(&mut b).unwrap();
-                if b[0] % 4 == 0 {
-                    self.deadline =
-                        Some(pal_async::timer::Instant::now() + Duration::from_millis(10));
-                } else {
-                    let msg = OutgoingMessage::from_message(msg).unwrap();
-                    tracing::info!(
-                        msg = ?MessageHeader::read_from_prefix(msg.data()),
-                        "sending message"
-                    );
-                    self.sender.send(msg);
-                    break Poll::Ready(());
-                }
-            }
-        }
-    }
-
-    struct NoopSynicEvents;
-
-    impl SynicEventClient for NoopSynicEvents {
-        fn map_event(&self, _event_flag: u16, _event: &Event) -> std::io::Result<()> {
-            Ok(())
-        }
-
-        fn unmap_event(&self, _event_flag: u16) {}
-
-        fn signal_event(&self, _connection_id: u32, _event_flag: u16) -> std::io::Result<()> {
-            Err(std::io::ErrorKind::Unsupported.into())
-        }
-    }
-
-    struct TestMessageSource {
-        msg_recv: mesh::Receiver<Vec<u8>>,
-        paused: bool,
-    }
-
-    impl AsyncRecv for TestMessageSource {
-        fn poll_recv(
-            &mut self,
-            cx: &mut Context<'_>,
-            mut bufs: &mut [std::io::IoSliceMut<'_>],
-        ) -> Poll<std::io::Result<usize>> {
-            let value = match self.msg_recv.poll_recv(cx) {
-                Poll::Ready(v) => v.unwrap(),
-                Poll::Pending => {
-                    if self.paused {
-                        return Poll::Ready(Ok(0));
-                    } else {
-                        return Poll::Pending;
-                    }
-                }
-            };
-            let mut remaining = value.as_slice();
-            let mut total_size = 0;
-            while !remaining.is_empty() && !bufs.is_empty() {
-                let size = bufs[0].len().min(remaining.len());
-                bufs[0][..size].copy_from_slice(&remaining[..size]);
-                remaining = &remaining[size..];
-                bufs = &mut bufs[1..];
-                total_size += size;
-            }
-
-            Ok(total_size).into()
-        }
-    }
-
-    impl VmbusMessageSource for TestMessageSource {
-        fn pause_message_stream(&mut self) {
-            self.paused = true;
-        }
-
-        fn resume_message_stream(&mut self) {
-            self.paused = false;
-        }
-    }
-
-    fn test_init(driver: &DefaultDriver) -> (TestServer, VmbusClient) {
-        let (msg_send, msg_recv) = mesh::channel();
-        let (synic_send, synic_recv) = mesh::channel();
-        let server = TestServer {
-            messages: synic_recv,
-            send: msg_send,
-        };
-        let mut client = VmbusClientBuilder::new(
-            NoopSynicEvents,
-            TestMessageSource {
-                msg_recv,
-                paused: false,
-            },
-            TestServerClient {
-                sender: synic_send,
-                deadline: None,
-                timer: PolledTimer::new(driver),
-            },
-        )
-        .build(driver);
-        client.start();
-        (server, client)
-    }
-
-    #[async_test]
-    async fn test_initiate_contact_success(driver: DefaultDriver) {
-        let (mut server, client) = test_init(&driver);
-        let _recv = client
-            .access
-            .client_request_send
-            .call(ClientRequest::Connect, ConnectRequest::default());
-        check_message(
-            server.next().await.unwrap(),
-            protocol::InitiateContact2 {
-                initiate_contact: protocol::InitiateContact {
-                    version_requested: Version::Copper as u32,
-                    target_message_vp: 0,
-                    interrupt_page_or_target_info: TargetInfo::new()
-                        .with_sint(2)
-                        .with_vtl(0)
-                        .with_feature_flags(SUPPORTED_FEATURE_FLAGS.into())
-                        .into(),
-                    parent_to_child_monitor_page_gpa: 0,
-                    child_to_parent_monitor_page_gpa: 0,
-                },
-                ..FromZeros::new_zeroed()
-            },
-        );
-    }
-
-    #[async_test]
-    async fn test_connect_success(driver: DefaultDriver) {
-        let (mut server, mut client) = test_init(&driver);
-        let client_connect = client.connect(0, None, Guid::ZERO);
-
-        let server_connect = async {
-            check_message(
-                server.next().await.unwrap(),
-                protocol::InitiateContact2 {
-                    initiate_contact: protocol::InitiateContact {
-                        version_requested: Version::Copper as u32,
-                        target_message_vp: 0,
-                        interrupt_page_or_target_info: TargetInfo::new()
-                            .with_sint(2)
-                            .with_vtl(0)
-                            .with_feature_flags(SUPPORTED_FEATURE_FLAGS.into())
-                            .into(),
-                        parent_to_child_monitor_page_gpa: 0,
-                        child_to_parent_monitor_page_gpa: 0,
-                    },
-                    ..FromZeros::new_zeroed()
-                },
-            );
-
-            server.send(in_msg(
-                MessageType::VERSION_RESPONSE,
-                protocol::VersionResponse2 {
-                    version_response: protocol::VersionResponse {
-                        version_supported: 1,
-                        connection_state: ConnectionState::SUCCESSFUL,
-                        padding: 0,
-                        selected_version_or_connection_id: 0,
-                    },
-                    supported_features: SUPPORTED_FEATURE_FLAGS.into_bits(),
-                },
-            ));
-
-            check_message(server.next().await.unwrap(), protocol::RequestOffers {});
-            server.send(in_msg(MessageType::ALL_OFFERS_DELIVERED, [0x00]));
-        };
+            check_message(server.next().await.unwrap(), protocol::RequestOffers {});
+            server.send(in_msg(MessageType::ALL_OFFERS_DELIVERED, [0x00]));
+        };
 
         let (connection, ()) = (client_connect, server_connect).join().await;
         let connection = connection.unwrap();
@@ -2344,6 +3251,54 @@ async fn test_client_id(driver: DefaultDriver) {
         );
     }
 
+    #[async_test]
+    async fn test_feature_flags_mask(driver: DefaultDriver) {
+        let feature_flags = SUPPORTED_FEATURE_FLAGS.with_channel_interrupt_redirection(false);
+        let (mut server, client) =
+            test_init_with(&driver, |builder| builder.feature_flags(feature_flags));
+        let recv = client
+            .access
+            .client_request_send
+            .call(ClientRequest::Connect, ConnectRequest::default());
+
+        check_message(
+            server.next().await.unwrap(),
+            protocol::InitiateContact2 {
+                initiate_contact: protocol::InitiateContact {
+                    version_requested: Version::Copper as u32,
+                    target_message_vp: 0,
+                    interrupt_page_or_target_info: TargetInfo::new()
+                        .with_sint(2)
+                        .with_vtl(0)
+                        .with_feature_flags(feature_flags.into())
+                        .into(),
+                    parent_to_child_monitor_page_gpa: 0,
+                    child_to_parent_monitor_page_gpa: 0,
+                },
+                client_id: Guid::ZERO,
+            },
+        );
+
+        // Features the host reports but that were not advertised are not used.
+        server.send(in_msg(
+            MessageType::VERSION_RESPONSE,
+            protocol::VersionResponse2 {
+                version_response: protocol::VersionResponse {
+                    version_supported: 1,
+                    connection_state: ConnectionState::SUCCESSFUL,
+                    padding: 0,
+                    selected_version_or_connection_id: 0,
+                },
+                supported_features: SUPPORTED_FEATURE_FLAGS.into(),
+            },
+        ));
+
+        check_message(server.next().await.unwrap(), protocol::RequestOffers {});
+        server.send(in_msg(MessageType::ALL_OFFERS_DELIVERED, [0x00]));
+        let connection = recv.await.unwrap().unwrap();
+        assert_eq!(connection.version.feature_flags, feature_flags);
+    }
+
     #[async_test]
     async fn test_version_negotiation(driver: DefaultDriver) {
         let (mut server, mut client) = test_init(&driver);
@@ -2415,61 +3370,863 @@ async fn test_version_negotiation(driver: DefaultDriver) {
     }
 
     #[async_test]
-    async fn test_open_channel_success(driver: DefaultDriver) {
+    async fn test_version_negotiation_pre_rs5(driver: DefaultDriver) {
         let (mut server, mut client) = test_init(&driver);
-        let channel = server.get_channel(&mut client).await;
+        let client_connect = client.connect(0, None, Guid::ZERO);
 
-        let recv = channel.request_send.call(
-            ChannelRequest::Open,
-            OpenRequest {
-                open_data: OpenData {
-                    target_vp: Some(0),
-                    ring_offset: 0,
-                    ring_gpadl_id: GpadlId(0),
-                    event_flag: 0,
-                    connection_id: 0,
-                    user_data: UserDefinedData::new_zeroed(),
+        let server_connect = async {
+            // Reject everything newer than Win10Rs4.
+            for version in [Version::Copper, Version::Iron, Version::Win10Rs5] {
+                let request: protocol::InitiateContact =
+                    parse_message(&server.next().await.unwrap());
+                assert_eq!(request.version_requested, version as u32);
+
+                server.send(in_msg(
+                    MessageType::VERSION_RESPONSE,
+                    protocol::VersionResponse {
+                        version_supported: 0,
+                        connection_state: ConnectionState::SUCCESSFUL,
+                        padding: 0,
+                        selected_version_or_connection_id: 0,
+                    },
+                ));
+            }
+
+            check_message(
+                server.next().await.unwrap(),
+                protocol::InitiateContact {
+                    version_requested: Version::Win10Rs4 as u32,
+                    target_message_vp: 0,
+                    interrupt_page_or_target_info: TargetInfo::new()
+                        .with_sint(2)
+                        .with_vtl(0)
+                        .with_feature_flags(FeatureFlags::new().into())
+                        .into(),
+                    parent_to_child_monitor_page_gpa: 0,
+                    child_to_parent_monitor_page_gpa: 0,
                 },
-                incoming_event: None,
-                use_vtl2_connection_id: false,
+            );
+
+            server.send(in_msg(
+                MessageType::VERSION_RESPONSE,
+                protocol::VersionResponse {
+                    version_supported: 1,
+                    connection_state: ConnectionState::SUCCESSFUL,
+                    padding: 0,
+                    selected_version_or_connection_id: 1,
+                },
+            ));
+
+            check_message(server.next().await.unwrap(), protocol::RequestOffers {});
+            server.send(in_msg(MessageType::OFFER_CHANNEL, test_offer(0)));
+            server.send(in_msg(MessageType::ALL_OFFERS_DELIVERED, [0x00]));
+        };
+
+        let (connection, ()) = (client_connect, server_connect).join().await;
+        let connection = connection.unwrap();
+        assert_eq!(connection.version.version, Version::Win10Rs4);
+        assert_eq!(connection.version.feature_flags, FeatureFlags::new());
+
+        // The host doesn't understand silo IDs, so the original connect
+        // message is sent.
+        let request = HvsockConnectRequest {
+            service_id: Guid::new_random(),
+            endpoint_id: Guid::new_random(),
+            silo_id: Guid::new_random(),
+            hosted_silo_unaware: false,
+        };
+        let _resp = client.access().connect_hvsock(request);
+        check_message(
+            server.next().await.unwrap(),
+            protocol::TlConnectRequest {
+                service_id: request.service_id,
+                endpoint_id: request.endpoint_id,
             },
         );
 
+        // The host won't send a ModifyChannelResponse, so the request
+        // completes once it's sent.
+        let [channel] = connection.offers.try_into().unwrap();
+        let recv = channel.request_send.call(
+            ChannelRequest::Modify,
+            ModifyRequest::TargetVp { target_vp: 1 },
+        );
         check_message(
             server.next().await.unwrap(),
-            protocol::OpenChannel2 {
-                open_channel: protocol::OpenChannel {
-                    channel_id: ChannelId(0),
-                    open_id: 0,
-                    ring_buffer_gpadl_id: GpadlId(0),
-                    target_vp: 0,
-                    downstream_ring_buffer_page_offset: 0,
-                    user_data: UserDefinedData::new_zeroed(),
-                },
-                connection_id: 0,
-                event_flag: 0,
-                flags: Default::default(),
+            protocol::ModifyChannel {
+                channel_id: ChannelId(0),
+                target_vp: 1,
             },
         );
+        assert_eq!(recv.await.unwrap(), protocol::STATUS_SUCCESS);
+    }
 
+    #[async_test]
+    async fn test_snapshot(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let snapshot = client.snapshot().await;
+        assert_eq!(snapshot.state, "Disconnected");
+        assert!(snapshot.channels.is_empty());
+
+        let connection = server.get_channels(&mut client, 2).await;
+        let _open = connection.offers[1]
+            .request_send
+            .call(ChannelRequest::Open, open_request());
+        parse_message::<protocol::OpenChannel2>(&server.next().await.unwrap());
+
+        let snapshot = client.snapshot().await;
+        assert_eq!(snapshot.state, "Connected");
+        assert_eq!(
+            snapshot.channels.into_iter().collect::<Vec<_>>(),
+            [
+                (ChannelId(0), "Offered".to_owned()),
+                (ChannelId(1), "Opening".to_owned())
+            ]
+        );
+    }
+
+    #[async_test]
+    async fn test_status(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let status = client.status().await;
+        assert_eq!(status.state, ConnectionStatus::Disconnected);
+        assert!(status.version.is_none());
+        assert!(status.running);
+        assert_eq!(status.channels, 0);
+
+        let connection = server.get_channels(&mut client, 2).await;
+        let status = client.status().await;
+        assert_eq!(status.state, ConnectionStatus::Connected);
+        assert_eq!(status.version, Some(connection.version));
+        assert_eq!(status.channels, 2);
+        assert_eq!(status.open_channels, 0);
+        assert_eq!(status.gpadls, 0);
+
+        server.stop_client(&mut client).await;
+        let status = client.status().await;
+        assert_eq!(status.state, ConnectionStatus::Connected);
+        assert!(!status.running);
+    }
+
+    #[async_test]
+    async fn test_reconnect_after_unload(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let channel = server.get_channel(&mut client).await;
+
+        let client_unload = client.unload();
+        let server_unload = async {
+            check_message(server.next().await.unwrap(), protocol::Unload {});
+            server.send(in_msg(
+                MessageType::UNLOAD_COMPLETE,
+                protocol::UnloadComplete {},
+            ));
+        };
+        ((), ()) = (client_unload, server_unload).join().await;
+
+        channel.revoke_recv.await.unwrap();
+        let status = client.status().await;
+        assert_eq!(status.state, ConnectionStatus::Disconnected);
+        assert_eq!(status.channels, 0);
+
+        // The host reuses the channel ID after reconnecting.
+        let channel = server.get_channel(&mut client).await;
+        assert_eq!(channel.offer.channel_id, ChannelId(0));
+        assert_eq!(client.status().await.channels, 1);
+    }
+
+    #[async_test]
+    async fn test_reset(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let channel = server.get_channel(&mut client).await;
+        let open = channel
+            .request_send
+            .call_failable(ChannelRequest::Open, open_request());
+        check_open_channel(server.next().await.unwrap(), 1);
+
+        client.reset().await;
+        open.await.unwrap_err();
+        channel.revoke_recv.await.unwrap();
+        let status = client.status().await;
+        assert_eq!(status.state, ConnectionStatus::Disconnected);
+        assert_eq!(status.channels, 0);
+
+        // Requests for channels from before the reset fail.
+        channel
+            .request_send
+            .call_failable(ChannelRequest::Open, open_request())
+            .await
+            .unwrap_err();
+
+        server.get_channel(&mut client).await;
+        assert_eq!(client.status().await.state, ConnectionStatus::Connected);
+    }
+
+    #[async_test]
+    async fn test_inspect_channel_while_stopped(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let channel = server.get_channel(&mut client).await;
+
+        let client_stop = client.stop();
+        let server_stop = async {
+            check_message(server.next().await.unwrap(), protocol::Pause);
+            server.send(in_msg(MessageType::PAUSE_RESPONSE, protocol::PauseResponse));
+        };
+        (client_stop, server_stop).join().await;
+
+        let mut inspection = inspect::inspect(
+            "is_client_released",
+            inspect::send(&channel.request_send, ChannelRequest::Inspect),
+        );
+        inspection.resolve().await;
+        assert!(matches!(
+            inspection.results(),
+            inspect::Node::Value(inspect::Value {
+                kind: inspect::ValueKind::Bool(false),
+                ..
+            })
+        ));
+    }
+
+    #[async_test]
+    async fn test_update_unexpected_message_policy(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init_with(&driver, |builder| {
+            builder.unexpected_message_policy(UnexpectedMessagePolicy::Disconnect)
+        });
+        let mut connection = server.get_channels(&mut client, 1).await;
+
+        // With the policy changed to ignore, an unexpected message no longer
+        // disconnects the client.
+        let value = inspect::update("unexpected_message_policy", "ignore", &client)
+            .await
+            .unwrap();
+        assert!(matches!(value.kind, inspect::ValueKind::String(s) if s == "ignore"));
+        server.send(in_msg(
+            MessageType::REQUEST_OFFERS,
+            protocol::RequestOffers {},
+        ));
+        server.send(in_msg(MessageType::OFFER_CHANNEL, test_offer(1)));
+        let offer = connection.offer_recv.next().await.unwrap();
+        assert_eq!(offer.offer.channel_id, ChannelId(1));
+        let snapshot = client.snapshot().await;
+        assert_eq!(snapshot.state, "Connected");
+        assert_eq!(snapshot.unexpected_messages, 1);
+
+        // Invalid policies are rejected.
+        inspect::update("unexpected_message_policy", "bogus", &client)
+            .await
+            .unwrap_err();
+
+        // Changing it back makes the next unexpected message disconnect.
+        inspect::update("unexpected_message_policy", "disconnect", &client)
+            .await
+            .unwrap();
+        server.send(in_msg(
+            MessageType::REQUEST_OFFERS,
+            protocol::RequestOffers {},
+        ));
+        check_message(server.next().await.unwrap(), protocol::Unload {});
+        server.send(in_msg(
+            MessageType::UNLOAD_COMPLETE,
+            protocol::UnloadComplete {},
+        ));
+        let snapshot = client.snapshot().await;
+        assert_eq!(snapshot.state, "Disconnected");
+        assert_eq!(snapshot.unexpected_messages, 2);
+    }
+
+    #[async_test]
+    async fn test_open_channel_success(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let channel = server.get_channel(&mut client).await;
+
+        let recv = channel.request_send.call(
+            ChannelRequest::Open,
+            OpenRequest {
+                open_data: OpenData {
+                    target_vp: Some(0),
+                    ring_offset: 0,
+                    ring_gpadl_id: GpadlId(0),
+                    event_flag: 0,
+                    connection_id: 0,
+                    user_data: UserDefinedData::new_zeroed(),
+                },
+                incoming_event: None,
+                use_vtl2_connection_id: false,
+                redirect_interrupt: false,
+            },
+        );
+
+        check_message(
+            server.next().await.unwrap(),
+            protocol::OpenChannel2 {
+                open_channel: protocol::OpenChannel {
+                    channel_id: ChannelId(0),
+                    open_id: 1,
+                    ring_buffer_gpadl_id: GpadlId(0),
+                    target_vp: 0,
+                    downstream_ring_buffer_page_offset: 0,
+                    user_data: UserDefinedData::new_zeroed(),
+                },
+                connection_id: 0,
+                event_flag: 0,
+                flags: Default::default(),
+            },
+        );
+
+        server.send(in_msg(
+            MessageType::OPEN_CHANNEL_RESULT,
+            protocol::OpenResult {
+                channel_id: ChannelId(0),
+                open_id: 1,
+                status: protocol::STATUS_SUCCESS as u32,
+            },
+        ));
+
+        recv.await.unwrap().unwrap();
+    }
+
+    #[async_test]
+    async fn test_open_channel_redirect_interrupt(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let channel = server.get_channel(&mut client).await;
+
+        let recv = channel.request_send.call(
+            ChannelRequest::Open,
+            OpenRequest {
+                redirect_interrupt: true,
+                ..open_request()
+            },
+        );
+
+        check_message(
+            server.next().await.unwrap(),
+            protocol::OpenChannel2 {
+                open_channel: protocol::OpenChannel {
+                    channel_id: ChannelId(0),
+                    open_id: 1,
+                    ring_buffer_gpadl_id: GpadlId(0),
+                    target_vp: 0,
+                    downstream_ring_buffer_page_offset: 0,
+                    user_data: UserDefinedData::new_zeroed(),
+                },
+                connection_id: 0,
+                event_flag: 0,
+                flags: OpenChannelFlags::new().with_redirect_interrupt(true),
+            },
+        );
+
+        server.send(in_msg(
+            MessageType::OPEN_CHANNEL_RESULT,
+            open_result(1, protocol::STATUS_SUCCESS as u32),
+        ));
+
+        let output = recv.await.unwrap().unwrap();
+        assert_eq!(output.redirected_event_flag, None);
+    }
+
+    #[async_test]
+    async fn test_open_channel_unsupported_redirect(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init_with(&driver, |builder| {
+            builder.feature_flags(SUPPORTED_FEATURE_FLAGS.with_channel_interrupt_redirection(false))
+        });
+        let channel = server.get_channel(&mut client).await;
+
+        // The request fails rather than opening the channel without
+        // redirection.
+        channel
+            .request_send
+            .call(
+                ChannelRequest::Open,
+                OpenRequest {
+                    redirect_interrupt: true,
+                    ..open_request()
+                },
+            )
+            .await
+            .unwrap()
+            .unwrap_err();
+
+        let open = channel
+            .request_send
+            .call(ChannelRequest::Open, open_request());
+        check_open_channel(server.next().await.unwrap(), 1);
+        server.send(in_msg(
+            MessageType::OPEN_CHANNEL_RESULT,
+            open_result(1, protocol::STATUS_SUCCESS as u32),
+        ));
+        open.await.unwrap().unwrap();
+    }
+
+    fn open_request() -> OpenRequest {
+        OpenRequest {
+            open_data: OpenData {
+                target_vp: Some(0),
+                ring_offset: 0,
+                ring_gpadl_id: GpadlId(0),
+                event_flag: 0,
+                connection_id: 0,
+                user_data: UserDefinedData::new_zeroed(),
+            },
+            incoming_event: None,
+            use_vtl2_connection_id: false,
+            redirect_interrupt: false,
+        }
+    }
+
+    fn check_open_channel(msg: OutgoingMessage, open_id: u32) {
+        check_message(
+            msg,
+            protocol::OpenChannel2 {
+                open_channel: protocol::OpenChannel {
+                    channel_id: ChannelId(0),
+                    open_id,
+                    ring_buffer_gpadl_id: GpadlId(0),
+                    target_vp: 0,
+                    downstream_ring_buffer_page_offset: 0,
+                    user_data: UserDefinedData::new_zeroed(),
+                },
+                connection_id: 0,
+                event_flag: 0,
+                flags: Default::default(),
+            },
+        );
+    }
+
+    fn open_result(open_id: u32, status: u32) -> protocol::OpenResult {
+        protocol::OpenResult {
+            channel_id: ChannelId(0),
+            open_id,
+            status,
+        }
+    }
+
+    #[async_test]
+    async fn test_close_while_opening(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let channel = server.get_channel(&mut client).await;
+
+        let open = channel
+            .request_send
+            .call(ChannelRequest::Open, open_request());
+        check_open_channel(server.next().await.unwrap(), 1);
+
+        channel
+            .request_send
+            .call(ChannelRequest::Close, ())
+            .await
+            .unwrap();
+        open.await.unwrap().unwrap_err();
+
+        // The open succeeds after the close, so the client closes the channel.
+        server.send(in_msg(
+            MessageType::OPEN_CHANNEL_RESULT,
+            open_result(1, protocol::STATUS_SUCCESS as u32),
+        ));
+        check_message(
+            server.next().await.unwrap(),
+            protocol::CloseChannel {
+                channel_id: ChannelId(0),
+            },
+        );
+
+        // The channel can be opened again.
+        let open = channel
+            .request_send
+            .call(ChannelRequest::Open, open_request());
+        check_open_channel(server.next().await.unwrap(), 2);
+        server.send(in_msg(
+            MessageType::OPEN_CHANNEL_RESULT,
+            open_result(2, protocol::STATUS_SUCCESS as u32),
+        ));
+        open.await.unwrap().unwrap();
+    }
+
+    #[async_test]
+    async fn test_close_while_opening_open_fails(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let channel = server.get_channel(&mut client).await;
+
+        let open = channel
+            .request_send
+            .call(ChannelRequest::Open, open_request());
+        check_open_channel(server.next().await.unwrap(), 1);
+        channel
+            .request_send
+            .call(ChannelRequest::Close, ())
+            .await
+            .unwrap();
+        open.await.unwrap().unwrap_err();
+
+        // Opening again is not allowed until the first open completes.
+        channel
+            .request_send
+            .call(ChannelRequest::Open, open_request())
+            .await
+            .unwrap()
+            .unwrap_err();
+
+        // The failed open leaves nothing to close, so the next message is the
+        // release after the rescind.
+        server.send(in_msg(
+            MessageType::OPEN_CHANNEL_RESULT,
+            open_result(1, protocol::STATUS_UNSUCCESSFUL as u32),
+        ));
+        server.send(in_msg(
+            MessageType::RESCIND_CHANNEL_OFFER,
+            protocol::RescindChannelOffer {
+                channel_id: ChannelId(0),
+            },
+        ));
+        channel.revoke_recv.await.unwrap();
+        channel
+            .request_send
+            .call(ChannelRequest::Release, ())
+            .await
+            .unwrap();
+        check_message(
+            server.next().await.unwrap(),
+            protocol::RelIdReleased {
+                channel_id: ChannelId(0),
+            },
+        );
+    }
+
+    #[async_test]
+    async fn test_rescind_while_closing_after_open(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let channel = server.get_channel(&mut client).await;
+
+        let open = channel
+            .request_send
+            .call(ChannelRequest::Open, open_request());
+        check_open_channel(server.next().await.unwrap(), 1);
+        channel
+            .request_send
+            .call(ChannelRequest::Close, ())
+            .await
+            .unwrap();
+        open.await.unwrap().unwrap_err();
+
+        server.send(in_msg(
+            MessageType::RESCIND_CHANNEL_OFFER,
+            protocol::RescindChannelOffer {
+                channel_id: ChannelId(0),
+            },
+        ));
+        channel.revoke_recv.await.unwrap();
+
+        // The host does not respond to the open after the rescind, and the
+        // channel can be released without closing it.
+        channel
+            .request_send
+            .call(ChannelRequest::Release, ())
+            .await
+            .unwrap();
+        check_message(
+            server.next().await.unwrap(),
+            protocol::RelIdReleased {
+                channel_id: ChannelId(0),
+            },
+        );
+    }
+
+    #[async_test]
+    async fn test_open_channel_fail(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let channel = server.get_channel(&mut client).await;
+
+        let recv = channel.request_send.call(
+            ChannelRequest::Open,
+            OpenRequest {
+                open_data: OpenData {
+                    target_vp: Some(0),
+                    ring_offset: 0,
+                    ring_gpadl_id: GpadlId(0),
+                    event_flag: 0,
+                    connection_id: 0,
+                    user_data: UserDefinedData::new_zeroed(),
+                },
+                incoming_event: None,
+                use_vtl2_connection_id: false,
+                redirect_interrupt: false,
+            },
+        );
+
+        check_message(
+            server.next().await.unwrap(),
+            protocol::OpenChannel2 {
+                open_channel: protocol::OpenChannel {
+                    channel_id: ChannelId(0),
+                    open_id: 1,
+                    ring_buffer_gpadl_id: GpadlId(0),
+                    target_vp: 0,
+                    downstream_ring_buffer_page_offset: 0,
+                    user_data: UserDefinedData::new_zeroed(),
+                },
+                connection_id: 0,
+                event_flag: 0,
+                flags: Default::default(),
+            },
+        );
+
+        server.send(in_msg(
+            MessageType::OPEN_CHANNEL_RESULT,
+            protocol::OpenResult {
+                channel_id: ChannelId(0),
+                open_id: 1,
+                status: protocol::STATUS_UNSUCCESSFUL as u32,
+            },
+        ));
+
+        recv.await.unwrap().unwrap_err();
+    }
+
+    #[async_test]
+    async fn test_open_channel_group_rollback(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let channels = server.get_channels(&mut client, 2).await.offers;
+
+        let client_open = group::open_channels(channels.iter().map(|channel| {
+            (
+                &channel.request_send,
+                OpenRequest {
+                    open_data: OpenData {
+                        target_vp: Some(0),
+                        ring_offset: 0,
+                        ring_gpadl_id: GpadlId(0),
+                        event_flag: 0,
+                        connection_id: 0,
+                        user_data: UserDefinedData::new_zeroed(),
+                    },
+                    incoming_event: None,
+                    use_vtl2_connection_id: false,
+                    redirect_interrupt: false,
+                },
+            )
+        }));
+
+        let server_open = async {
+            // The open requests may arrive in either order.
+            let mut open_ids = [0; 2];
+            for _ in 0..2 {
+                let open = parse_message::<protocol::OpenChannel2>(&server.next().await.unwrap());
+                open_ids[open.open_channel.channel_id.0 as usize] = open.open_channel.open_id;
+            }
+
+            server.send(in_msg(
+                MessageType::OPEN_CHANNEL_RESULT,
+                protocol::OpenResult {
+                    channel_id: ChannelId(0),
+                    open_id: open_ids[0],
+                    status: protocol::STATUS_SUCCESS as u32,
+                },
+            ));
+            server.send(in_msg(
+                MessageType::OPEN_CHANNEL_RESULT,
+                protocol::OpenResult {
+                    channel_id: ChannelId(1),
+                    open_id: open_ids[1],
+                    status: protocol::STATUS_UNSUCCESSFUL as u32,
+                },
+            ));
+
+            // The channel that was opened is closed again.
+            check_message(
+                server.next().await.unwrap(),
+                protocol::CloseChannel {
+                    channel_id: ChannelId(0),
+                },
+            );
+        };
+
+        let (result, ()) = (client_open, server_open).join().await;
+        assert_eq!(result.unwrap_err().index, 1);
+    }
+
+    #[async_test]
+    async fn test_open_channel_concurrent(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let channels = server.get_channels(&mut client, 2).await.offers;
+
+        let open = |channel: &OfferInfo| {
+            channel.request_send.call_failable(
+                ChannelRequest::Open,
+                OpenRequest {
+                    open_data: OpenData {
+                        target_vp: Some(0),
+                        ring_offset: 0,
+                        ring_gpadl_id: GpadlId(0),
+                        event_flag: 0,
+                        connection_id: 0,
+                        user_data: UserDefinedData::new_zeroed(),
+                    },
+                    incoming_event: None,
+                    use_vtl2_connection_id: false,
+                    redirect_interrupt: false,
+                },
+            )
+        };
+
+        let recv0 = open(&channels[0]);
+        let open0 = parse_message::<protocol::OpenChannel2>(&server.next().await.unwrap());
+        let recv1 = open(&channels[1]);
+        let open1 = parse_message::<protocol::OpenChannel2>(&server.next().await.unwrap());
+        assert_ne!(open0.open_channel.open_id, open1.open_channel.open_id);
+
+        // A result with a stale open ID is ignored.
+        server.send(in_msg(
+            MessageType::OPEN_CHANNEL_RESULT,
+            protocol::OpenResult {
+                channel_id: ChannelId(1),
+                open_id: open0.open_channel.open_id,
+                status: protocol::STATUS_UNSUCCESSFUL as u32,
+            },
+        ));
+
+        // Complete the opens in the reverse order.
+        server.send(in_msg(
+            MessageType::OPEN_CHANNEL_RESULT,
+            protocol::OpenResult {
+                channel_id: ChannelId(1),
+                open_id: open1.open_channel.open_id,
+                status: protocol::STATUS_SUCCESS as u32,
+            },
+        ));
         server.send(in_msg(
             MessageType::OPEN_CHANNEL_RESULT,
             protocol::OpenResult {
                 channel_id: ChannelId(0),
-                open_id: 0,
+                open_id: open0.open_channel.open_id,
                 status: protocol::STATUS_SUCCESS as u32,
             },
         ));
 
-        recv.await.unwrap().unwrap();
+        recv1.await.unwrap();
+        recv0.await.unwrap();
     }
 
     #[async_test]
-    async fn test_open_channel_fail(driver: DefaultDriver) {
+    async fn test_modify_channel(driver: DefaultDriver) {
         let (mut server, mut client) = test_init(&driver);
         let channel = server.get_channel(&mut client).await;
 
+        // N.B. A real server requires the channel to be open before sending this, but the test
+        //      server doesn't care.
         let recv = channel.request_send.call(
+            ChannelRequest::Modify,
+            ModifyRequest::TargetVp { target_vp: 1 },
+        );
+
+        check_message(
+            server.next().await.unwrap(),
+            protocol::ModifyChannel {
+                channel_id: ChannelId(0),
+                target_vp: 1,
+            },
+        );
+
+        server.send(in_msg(
+            MessageType::MODIFY_CHANNEL_RESPONSE,
+            protocol::ModifyChannelResponse {
+                channel_id: ChannelId(0),
+                status: protocol::STATUS_SUCCESS,
+            },
+        ));
+
+        let status = recv.await.unwrap();
+        assert_eq!(status, protocol::STATUS_SUCCESS);
+    }
+
+    #[async_test]
+    async fn test_save_restore_connected(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        server.connect(&mut client).await;
+        server.stop_client(&mut client).await;
+        let s0 = client.save().await;
+        let builder = client.sever().await;
+        let mut client = builder.build(&driver);
+        client.restore(s0.clone()).await.unwrap();
+
+        let s1 = client.save().await;
+
+        assert_eq!(s0, s1);
+    }
+
+    #[async_test]
+    async fn test_save_restore_connected_with_channel(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let c0 = server.get_channel(&mut client).await;
+        server.stop_client(&mut client).await;
+        let s0 = client.save().await;
+        let builder = client.sever().await;
+        let mut client = builder.build(&driver);
+        let connection = client.restore(s0.clone()).await.unwrap().unwrap();
+        let s1 = client.save().await;
+        assert_eq!(s0, s1);
+        assert_eq!(connection.offers[0].offer, c0.offer);
+    }
+
+    #[async_test]
+    async fn test_save_restore_connected_with_revoked_channel(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let c0 = server.get_channel(&mut client).await;
+        server.send(in_msg(
+            MessageType::RESCIND_CHANNEL_OFFER,
+            protocol::RescindChannelOffer {
+                channel_id: ChannelId(0),
+            },
+        ));
+        c0.revoke_recv.await.unwrap();
+        let rpc = c0.request_send.call(
+            ChannelRequest::Modify,
+            ModifyRequest::TargetVp { target_vp: 1 },
+        );
+
+        check_message(
+            server.next().await.unwrap(),
+            protocol::ModifyChannel {
+                channel_id: ChannelId(0),
+                target_vp: 1,
+            },
+        );
+
+        let client_stop = client.stop();
+        let server_stop = async {
+            server.send(in_msg(
+                MessageType::MODIFY_CHANNEL_RESPONSE,
+                protocol::ModifyChannelResponse {
+                    channel_id: ChannelId(0),
+                    status: protocol::STATUS_SUCCESS,
+                },
+            ));
+            check_message(server.next().await.unwrap(), protocol::Pause);
+            server.send(in_msg(MessageType::PAUSE_RESPONSE, protocol::PauseResponse));
+        };
+        (client_stop, server_stop).join().await;
+
+        rpc.await.unwrap();
+
+        let s0 = client.save().await;
+        let builder = client.sever().await;
+        let mut client = builder.build(&driver);
+        let connection = client.restore(s0.clone()).await.unwrap().unwrap();
+        let s1 = client.save().await;
+        assert_eq!(s0, s1);
+        assert!(connection.offers.is_empty());
+        server.start_client(&mut client).await;
+        check_message(
+            server.next().await.unwrap(),
+            protocol::RelIdReleased {
+                channel_id: ChannelId(0),
+            },
+        );
+    }
+
+    #[async_test]
+    async fn test_save_restore_pending_open(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let channel = server.get_channel(&mut client).await;
+        let _recv = channel.request_send.call(
             ChannelRequest::Open,
             OpenRequest {
                 open_data: OpenData {
@@ -2482,15 +4239,15 @@ async fn test_open_channel_fail(driver: DefaultDriver) {
                 },
                 incoming_event: None,
                 use_vtl2_connection_id: false,
+                redirect_interrupt: false,
             },
         );
-
         check_message(
             server.next().await.unwrap(),
             protocol::OpenChannel2 {
                 open_channel: protocol::OpenChannel {
                     channel_id: ChannelId(0),
-                    open_id: 0,
+                    open_id: 1,
                     ring_buffer_gpadl_id: GpadlId(0),
                     target_vp: 0,
                     downstream_ring_buffer_page_offset: 0,
@@ -2502,132 +4259,253 @@ async fn test_open_channel_fail(driver: DefaultDriver) {
             },
         );
 
-        server.send(in_msg(
-            MessageType::OPEN_CHANNEL_RESULT,
-            protocol::OpenResult {
+        server.stop_client(&mut client).await;
+        let s0 = client.save().await;
+        assert_eq!(
+            s0.channels[0].state,
+            saved_state::ChannelState::Opening {
+                open_id: 1,
+                redirected_event_flag: None,
+            }
+        );
+        let builder = client.sever().await;
+        let mut client = builder.build(&driver);
+        let _connection = client.restore(s0.clone()).await.unwrap().unwrap();
+        let s1 = client.save().await;
+        assert_eq!(s0, s1);
+
+        // The restored open is undone once the host completes it.
+        server.start_client(&mut client).await;
+        server.send(in_msg(
+            MessageType::OPEN_CHANNEL_RESULT,
+            protocol::OpenResult {
+                channel_id: ChannelId(0),
+                open_id: 1,
+                status: protocol::STATUS_SUCCESS as u32,
+            },
+        ));
+        check_message(
+            server.next().await.unwrap(),
+            protocol::CloseChannel {
+                channel_id: ChannelId(0),
+            },
+        );
+    }
+
+    #[async_test]
+    async fn test_save_restore_pending_redirected_open(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let channels = server.get_channels(&mut client, 2).await.offers;
+        let event = Event::new();
+        let open_request = || OpenRequest {
+            open_data: OpenData {
+                target_vp: Some(0),
+                ring_offset: 0,
+                ring_gpadl_id: GpadlId(0),
+                event_flag: 0,
+                connection_id: 0,
+                user_data: UserDefinedData::new_zeroed(),
+            },
+            incoming_event: Some(event.clone()),
+            use_vtl2_connection_id: false,
+            redirect_interrupt: false,
+        };
+
+        let _recv = channels[0]
+            .request_send
+            .call(ChannelRequest::Open, open_request());
+        let open = parse_message::<protocol::OpenChannel2>(&server.next().await.unwrap());
+        assert_eq!(open.event_flag, 1);
+
+        server.stop_client(&mut client).await;
+        let s0 = client.save().await;
+        assert_eq!(
+            s0.channels[0].state,
+            saved_state::ChannelState::Opening {
+                open_id: 1,
+                redirected_event_flag: Some(1),
+            }
+        );
+        let builder = client.sever().await;
+        let mut client = builder.build(&driver);
+        let connection = client.restore(s0.clone()).await.unwrap().unwrap();
+        let s1 = client.save().await;
+        assert_eq!(s0, s1);
+        server.start_client(&mut client).await;
+
+        // The restored open still holds its event flag, so another open gets a
+        // different one.
+        let _recv = connection.offers[1]
+            .request_send
+            .call(ChannelRequest::Open, open_request());
+        let open = parse_message::<protocol::OpenChannel2>(&server.next().await.unwrap());
+        assert_eq!(open.event_flag, 2);
+
+        // The flag is released when the restored open is undone.
+        server.send(in_msg(
+            MessageType::OPEN_CHANNEL_RESULT,
+            protocol::OpenResult {
+                channel_id: ChannelId(0),
+                open_id: 1,
+                status: protocol::STATUS_SUCCESS as u32,
+            },
+        ));
+        check_message(
+            server.next().await.unwrap(),
+            protocol::CloseChannel {
+                channel_id: ChannelId(0),
+            },
+        );
+        let _recv = connection.offers[0]
+            .request_send
+            .call(ChannelRequest::Open, open_request());
+        let open = parse_message::<protocol::OpenChannel2>(&server.next().await.unwrap());
+        assert_eq!(open.open_channel.channel_id, ChannelId(0));
+        assert_eq!(open.event_flag, 1);
+    }
+
+    #[async_test]
+    async fn test_save_restore_pending_gpadl(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let channel = server.get_channel(&mut client).await;
+        let _recv = channel.request_send.call(
+            ChannelRequest::Gpadl,
+            GpadlRequest {
+                id: GpadlId(1),
+                count: 1,
+                buf: vec![5],
+            },
+        );
+        check_message_with_data(
+            server.next().await.unwrap(),
+            protocol::GpadlHeader {
+                channel_id: ChannelId(0),
+                gpadl_id: GpadlId(1),
+                len: 8,
+                count: 1,
+            },
+            0x5u64.as_bytes(),
+        );
+
+        server.stop_client(&mut client).await;
+        let s0 = client.save().await;
+        assert_eq!(s0.gpadls[0].state, saved_state::GpadlState::Offered);
+        let builder = client.sever().await;
+        let mut client = builder.build(&driver);
+        let _connection = client.restore(s0.clone()).await.unwrap().unwrap();
+        let s1 = client.save().await;
+        assert_eq!(s0, s1);
+
+        // The restored GPADL is torn down once the host creates it.
+        server.start_client(&mut client).await;
+        server.send(in_msg(
+            MessageType::GPADL_CREATED,
+            protocol::GpadlCreated {
+                channel_id: ChannelId(0),
+                gpadl_id: GpadlId(1),
+                status: protocol::STATUS_SUCCESS,
+            },
+        ));
+        check_message(
+            server.next().await.unwrap(),
+            protocol::GpadlTeardown {
+                channel_id: ChannelId(0),
+                gpadl_id: GpadlId(1),
+            },
+        );
+    }
+
+    fn gpadl_request(id: u32) -> GpadlRequest {
+        GpadlRequest {
+            id: GpadlId(id),
+            count: 1,
+            buf: vec![5],
+        }
+    }
+
+    fn check_gpadl_header(msg: OutgoingMessage, id: u32) {
+        check_message_with_data(
+            msg,
+            protocol::GpadlHeader {
                 channel_id: ChannelId(0),
-                open_id: 0,
-                status: protocol::STATUS_UNSUCCESSFUL as u32,
+                gpadl_id: GpadlId(id),
+                len: 8,
+                count: 1,
             },
-        ));
-
-        recv.await.unwrap().unwrap_err();
+            0x5u64.as_bytes(),
+        );
     }
 
     #[async_test]
-    async fn test_modify_channel(driver: DefaultDriver) {
+    async fn test_requests_while_stopped(driver: DefaultDriver) {
         let (mut server, mut client) = test_init(&driver);
         let channel = server.get_channel(&mut client).await;
+        server.stop_client(&mut client).await;
 
-        // N.B. A real server requires the channel to be open before sending this, but the test
-        //      server doesn't care.
-        let recv = channel.request_send.call(
-            ChannelRequest::Modify,
-            ModifyRequest::TargetVp { target_vp: 1 },
-        );
+        let gpadl = channel
+            .request_send
+            .call(ChannelRequest::Gpadl, gpadl_request(1));
+        let open = channel
+            .request_send
+            .call(ChannelRequest::Open, open_request());
 
-        check_message(
-            server.next().await.unwrap(),
-            protocol::ModifyChannel {
-                channel_id: ChannelId(0),
-                target_vp: 1,
-            },
-        );
+        // The requests are handled in order once the client starts.
+        server.start_client(&mut client).await;
+        check_gpadl_header(server.next().await.unwrap(), 1);
+        check_open_channel(server.next().await.unwrap(), 1);
 
         server.send(in_msg(
-            MessageType::MODIFY_CHANNEL_RESPONSE,
-            protocol::ModifyChannelResponse {
+            MessageType::GPADL_CREATED,
+            protocol::GpadlCreated {
                 channel_id: ChannelId(0),
+                gpadl_id: GpadlId(1),
                 status: protocol::STATUS_SUCCESS,
             },
         ));
-
-        let status = recv.await.unwrap();
-        assert_eq!(status, protocol::STATUS_SUCCESS);
+        gpadl.await.unwrap().unwrap();
+        server.send(in_msg(
+            MessageType::OPEN_CHANNEL_RESULT,
+            open_result(1, protocol::STATUS_SUCCESS as u32),
+        ));
+        open.await.unwrap().unwrap();
     }
 
     #[async_test]
-    async fn test_save_restore_connected(driver: DefaultDriver) {
+    async fn test_requests_while_stopped_over_limit(driver: DefaultDriver) {
         let (mut server, mut client) = test_init(&driver);
-        server.connect(&mut client).await;
+        let channel = server.get_channel(&mut client).await;
         server.stop_client(&mut client).await;
-        let s0 = client.save().await;
-        let builder = client.sever().await;
-        let mut client = builder.build(&driver);
-        client.restore(s0.clone()).await.unwrap();
 
-        let s1 = client.save().await;
+        // Requests past the queue limit stay in the channel, and are still
+        // handled in order after the queued ones.
+        let count = MAX_STOPPED_REQUESTS as u32 + 4;
+        let _recvs = (1..=count)
+            .map(|id| {
+                channel
+                    .request_send
+                    .call(ChannelRequest::Gpadl, gpadl_request(id))
+            })
+            .collect::<Vec<_>>();
 
-        assert_eq!(s0, s1);
+        server.start_client(&mut client).await;
+        for id in 1..=count {
+            check_gpadl_header(server.next().await.unwrap(), id);
+        }
     }
 
     #[async_test]
-    async fn test_save_restore_connected_with_channel(driver: DefaultDriver) {
+    async fn test_restore_pending_operations_while_disconnected(driver: DefaultDriver) {
         let (mut server, mut client) = test_init(&driver);
-        let c0 = server.get_channel(&mut client).await;
+        server.get_channel(&mut client).await;
         server.stop_client(&mut client).await;
-        let s0 = client.save().await;
-        let builder = client.sever().await;
-        let mut client = builder.build(&driver);
-        let connection = client.restore(s0.clone()).await.unwrap().unwrap();
-        let s1 = client.save().await;
-        assert_eq!(s0, s1);
-        assert_eq!(connection.offers[0].offer, c0.offer);
-    }
-
-    #[async_test]
-    async fn test_save_restore_connected_with_revoked_channel(driver: DefaultDriver) {
-        let (mut server, mut client) = test_init(&driver);
-        let c0 = server.get_channel(&mut client).await;
-        server.send(in_msg(
-            MessageType::RESCIND_CHANNEL_OFFER,
-            protocol::RescindChannelOffer {
-                channel_id: ChannelId(0),
-            },
-        ));
-        c0.revoke_recv.await.unwrap();
-        let rpc = c0.request_send.call(
-            ChannelRequest::Modify,
-            ModifyRequest::TargetVp { target_vp: 1 },
-        );
-
-        check_message(
-            server.next().await.unwrap(),
-            protocol::ModifyChannel {
-                channel_id: ChannelId(0),
-                target_vp: 1,
-            },
-        );
-
-        let client_stop = client.stop();
-        let server_stop = async {
-            server.send(in_msg(
-                MessageType::MODIFY_CHANNEL_RESPONSE,
-                protocol::ModifyChannelResponse {
-                    channel_id: ChannelId(0),
-                    status: protocol::STATUS_SUCCESS,
-                },
-            ));
-            check_message(server.next().await.unwrap(), protocol::Pause);
-            server.send(in_msg(MessageType::PAUSE_RESPONSE, protocol::PauseResponse));
-        };
-        (client_stop, server_stop).join().await;
-
-        rpc.await.unwrap();
-
-        let s0 = client.save().await;
+        let mut s0 = client.save().await;
+        s0.client_state = saved_state::ClientState::Disconnected;
         let builder = client.sever().await;
         let mut client = builder.build(&driver);
-        let connection = client.restore(s0.clone()).await.unwrap().unwrap();
-        let s1 = client.save().await;
-        assert_eq!(s0, s1);
-        assert!(connection.offers.is_empty());
-        server.start_client(&mut client).await;
-        check_message(
-            server.next().await.unwrap(),
-            protocol::RelIdReleased {
-                channel_id: ChannelId(0),
-            },
+        let err = client.restore(s0).await.unwrap_err();
+        assert!(
+            matches!(err, RestoreError::PendingOperationsWhileDisconnected),
+            "{err:?}"
         );
     }
 
@@ -2740,6 +4618,177 @@ async fn test_gpadl_success(driver: DefaultDriver) {
         rpc.await.unwrap();
     }
 
+    #[async_test]
+    async fn test_gpadl_teardown_idempotent(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let channel = server.get_channel(&mut client).await;
+        let recv = channel.request_send.call(
+            ChannelRequest::Gpadl,
+            GpadlRequest {
+                id: GpadlId(1),
+                count: 1,
+                buf: vec![5],
+            },
+        );
+        parse_message::<protocol::GpadlHeader>(&server.next().await.unwrap());
+
+        // Teardown of a GPADL being created waits for it to be created.
+        let teardown1 = channel
+            .request_send
+            .call(ChannelRequest::TeardownGpadl, GpadlId(1));
+
+        // Teardown of an unknown GPADL completes immediately. Since channel
+        // requests are handled in order, this also ensures the first teardown
+        // was handled.
+        channel
+            .request_send
+            .call(ChannelRequest::TeardownGpadl, GpadlId(2))
+            .await
+            .unwrap();
+
+        server.send(in_msg(
+            MessageType::GPADL_CREATED,
+            protocol::GpadlCreated {
+                channel_id: ChannelId(0),
+                gpadl_id: GpadlId(1),
+                status: protocol::STATUS_SUCCESS,
+            },
+        ));
+        recv.await.unwrap().unwrap();
+        check_message(
+            server.next().await.unwrap(),
+            protocol::GpadlTeardown {
+                channel_id: ChannelId(0),
+                gpadl_id: GpadlId(1),
+            },
+        );
+
+        // A duplicate request completes with the first.
+        let teardown2 = channel
+            .request_send
+            .call(ChannelRequest::TeardownGpadl, GpadlId(1));
+        channel
+            .request_send
+            .call(ChannelRequest::TeardownGpadl, GpadlId(2))
+            .await
+            .unwrap();
+
+        server.send(in_msg(
+            MessageType::GPADL_TORNDOWN,
+            protocol::GpadlTorndown {
+                gpadl_id: GpadlId(1),
+            },
+        ));
+        teardown1.await.unwrap();
+        teardown2.await.unwrap();
+
+        // Tearing down again is a no-op.
+        channel
+            .request_send
+            .call(ChannelRequest::TeardownGpadl, GpadlId(1))
+            .await
+            .unwrap();
+    }
+
+    #[async_test]
+    async fn test_client_events(driver: DefaultDriver) {
+        let (event_send, mut event_recv) = mesh::channel();
+        let (mut server, mut client) =
+            test_init_with(&driver, |builder| builder.event_sender(event_send));
+        let channel = server.get_channel(&mut client).await;
+
+        let event = event_recv.next().await.unwrap();
+        assert!(
+            matches!(
+                event.kind,
+                ClientEventKind::VersionNegotiated { version, latency: Some(_) }
+                    if version.version == Version::Copper
+            ),
+            "{event:?}"
+        );
+        let event = event_recv.next().await.unwrap();
+        assert!(
+            matches!(
+                event.kind,
+                ClientEventKind::ChannelOffered {
+                    channel_id: ChannelId(0),
+                    ..
+                }
+            ),
+            "{event:?}"
+        );
+        let event = event_recv.next().await.unwrap();
+        assert!(
+            matches!(
+                event.kind,
+                ClientEventKind::Connected {
+                    offer_count: 1,
+                    latency: Some(_)
+                }
+            ),
+            "{event:?}"
+        );
+
+        let recv = channel.request_send.call(
+            ChannelRequest::Gpadl,
+            GpadlRequest {
+                id: GpadlId(1),
+                count: 1,
+                buf: vec![5],
+            },
+        );
+
+        let _ = server.next().await.unwrap();
+        server.send(in_msg(
+            MessageType::GPADL_CREATED,
+            protocol::GpadlCreated {
+                channel_id: ChannelId(0),
+                gpadl_id: GpadlId(1),
+                status: protocol::STATUS_SUCCESS,
+            },
+        ));
+
+        recv.await.unwrap().unwrap();
+        let event = event_recv.next().await.unwrap();
+        assert!(
+            matches!(
+                event.kind,
+                ClientEventKind::GpadlCreated {
+                    channel_id: ChannelId(0),
+                    gpadl_id: GpadlId(1),
+                    latency: Some(_),
+                }
+            ),
+            "{event:?}"
+        );
+
+        let rpc = channel
+            .request_send
+            .call(ChannelRequest::TeardownGpadl, GpadlId(1));
+
+        let _ = server.next().await.unwrap();
+        server.send(in_msg(
+            MessageType::GPADL_TORNDOWN,
+            protocol::GpadlTorndown {
+                gpadl_id: GpadlId(1),
+            },
+        ));
+
+        rpc.await.unwrap();
+        let event = event_recv.next().await.unwrap();
+        assert!(
+            matches!(
+                event.kind,
+                ClientEventKind::GpadlTornDown {
+                    channel_id: ChannelId(0),
+                    gpadl_id: GpadlId(1),
+                    latency: Some(_),
+                }
+            ),
+            "{event:?}"
+        );
+    }
+
     #[async_test]
     async fn test_gpadl_fail(driver: DefaultDriver) {
         let (mut server, mut client) = test_init(&driver);
@@ -2886,17 +4935,52 @@ async fn test_gpadl_with_revoke(driver: DefaultDriver) {
         );
 
         server.send(in_msg(
-            MessageType::GPADL_TORNDOWN,
-            protocol::GpadlTorndown {
-                gpadl_id: GpadlId(2),
+            MessageType::GPADL_TORNDOWN,
+            protocol::GpadlTorndown {
+                gpadl_id: GpadlId(2),
+            },
+        ));
+
+        rpc.await.unwrap();
+
+        check_message(
+            server.next().await.unwrap(),
+            protocol::RelIdReleased { channel_id },
+        );
+    }
+
+    #[async_test]
+    async fn test_offer_filter(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init_with(&driver, |builder| {
+            builder.offer_filter(|mut offer: protocol::OfferChannel| {
+                if offer.channel_id == ChannelId(1) {
+                    return None;
+                }
+                offer.mmio_megabytes = 4;
+                Some(offer)
+            })
+        });
+
+        let connection = server.get_channels(&mut client, 3).await;
+        let delivered = connection
+            .offers
+            .iter()
+            .map(|offer| (offer.offer.channel_id, offer.offer.mmio_megabytes))
+            .collect::<Vec<_>>();
+        assert_eq!(delivered, [(ChannelId(0), 4), (ChannelId(2), 4)]);
+
+        // The hidden channel is released once the host rescinds it.
+        server.send(in_msg(
+            MessageType::RESCIND_CHANNEL_OFFER,
+            protocol::RescindChannelOffer {
+                channel_id: ChannelId(1),
             },
         ));
-
-        rpc.await.unwrap();
-
         check_message(
             server.next().await.unwrap(),
-            protocol::RelIdReleased { channel_id },
+            protocol::RelIdReleased {
+                channel_id: ChannelId(1),
+            },
         );
     }
 
@@ -2933,6 +5017,141 @@ async fn test_modify_connection(driver: DefaultDriver) {
         assert_eq!(ConnectionState::FAILED_LOW_RESOURCES, result);
     }
 
+    #[async_test]
+    async fn test_modify_connection_queued(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        server.connect(&mut client).await;
+        let request = |gpa| ModifyConnectionRequest {
+            monitor_page: Some(MonitorPageGpas {
+                child_to_parent: gpa,
+                parent_to_child: gpa + 1,
+            }),
+        };
+        let call1 = client
+            .access
+            .client_request_send
+            .call(ClientRequest::Modify, request(5));
+        let call2 = client
+            .access
+            .client_request_send
+            .call(ClientRequest::Modify, request(7));
+
+        check_message(
+            server.next().await.unwrap(),
+            protocol::ModifyConnection {
+                child_to_parent_monitor_page_gpa: 5,
+                parent_to_child_monitor_page_gpa: 6,
+            },
+        );
+
+        server.send(in_msg(
+            MessageType::MODIFY_CONNECTION_RESPONSE,
+            protocol::ModifyConnectionResponse {
+                connection_state: ConnectionState::SUCCESSFUL,
+            },
+        ));
+
+        assert_eq!(call1.await.unwrap(), ConnectionState::SUCCESSFUL);
+        check_message(
+            server.next().await.unwrap(),
+            protocol::ModifyConnection {
+                child_to_parent_monitor_page_gpa: 7,
+                parent_to_child_monitor_page_gpa: 8,
+            },
+        );
+
+        server.send(in_msg(
+            MessageType::MODIFY_CONNECTION_RESPONSE,
+            protocol::ModifyConnectionResponse {
+                connection_state: ConnectionState::FAILED_LOW_RESOURCES,
+            },
+        ));
+
+        assert_eq!(call2.await.unwrap(), ConnectionState::FAILED_LOW_RESOURCES);
+    }
+
+    async fn modify_monitor_page(
+        server: &mut TestServer,
+        client: &VmbusClient,
+        monitor_page: Option<MonitorPageGpas>,
+    ) {
+        let call = client.access.client_request_send.call(
+            ClientRequest::Modify,
+            ModifyConnectionRequest { monitor_page },
+        );
+        let _ = server.next().await.unwrap();
+        server.send(in_msg(
+            MessageType::MODIFY_CONNECTION_RESPONSE,
+            protocol::ModifyConnectionResponse {
+                connection_state: ConnectionState::SUCCESSFUL,
+            },
+        ));
+        assert_eq!(call.await.unwrap(), ConnectionState::SUCCESSFUL);
+    }
+
+    #[async_test]
+    async fn test_monitor_signal(driver: DefaultDriver) {
+        let mem = GuestMemory::allocate(0x10000);
+        let (mut server, mut client) =
+            test_init_with(&driver, |builder| builder.monitor_memory(mem.clone()));
+
+        let connection = server
+            .connect_with_channels(&mut client, |server| {
+                for (channel_id, monitor_allocated) in [(0, 1), (1, 0)] {
+                    server.send(in_msg(
+                        MessageType::OFFER_CHANNEL,
+                        protocol::OfferChannel {
+                            interface_id: Guid::new_random(),
+                            instance_id: Guid::new_random(),
+                            rsvd: [0; 4],
+                            flags: OfferFlags::new(),
+                            mmio_megabytes: 0,
+                            user_defined: UserDefinedData::new_zeroed(),
+                            subchannel_index: 0,
+                            mmio_megabytes_optional: 0,
+                            channel_id: ChannelId(channel_id),
+                            monitor_id: 37,
+                            monitor_allocated,
+                            is_dedicated: 0,
+                            connection_id: 0,
+                        },
+                    ));
+                }
+            })
+            .await;
+
+        let [mut monitored, mut unmonitored] = connection.offers.try_into().unwrap();
+        assert!(!unmonitored.use_monitored_interrupt());
+        assert!(monitored.use_monitored_interrupt());
+        let signal = monitored.monitor_signal.clone().unwrap();
+        assert_eq!(signal.monitor_id(), MonitorId(37));
+        assert!(!signal.is_monitored());
+
+        modify_monitor_page(
+            &mut server,
+            &client,
+            Some(MonitorPageGpas {
+                parent_to_child: 0x2000,
+                child_to_parent: 0x3000,
+            }),
+        )
+        .await;
+        assert!(signal.is_monitored());
+
+        // Monitor ID 37 is bit 5 of the second trigger group.
+        monitored.guest_to_host_interrupt.deliver();
+        let pending: u32 = mem.read_plain(0x3000 + 8 + 8).unwrap();
+        assert_eq!(pending, 1 << 5);
+
+        // Without a monitor page, signals fall back to the direct interrupt.
+        modify_monitor_page(&mut server, &client, None).await;
+        assert!(!signal.is_monitored());
+        mem.write_plain(0x3000 + 8 + 8, &0u32).unwrap();
+        signal.signal();
+        let pending: u32 = mem.read_plain(0x3000 + 8 + 8).unwrap();
+        assert_eq!(pending, 0);
+    }
+
     #[async_test]
     async fn test_hvsock(driver: DefaultDriver) {
         let (mut server, mut client) = test_init(&driver);
@@ -2967,7 +5186,37 @@ async fn test_hvsock(driver: DefaultDriver) {
         ));
 
         let result = resp.await;
-        assert!(result.is_none());
+        assert!(
+            matches!(
+                result,
+                HvsockConnectResult::Failed(protocol::STATUS_CONNECTION_REFUSED)
+            ),
+            "{result:?}"
+        );
+    }
+
+    #[async_test]
+    async fn test_hvsock_timeout(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init_with(&driver, |builder| {
+            builder.hvsock_connect_timeout(Duration::from_millis(10))
+        });
+        server.connect(&mut client).await;
+        let request = HvsockConnectRequest {
+            service_id: Guid::new_random(),
+            endpoint_id: Guid::new_random(),
+            silo_id: Guid::new_random(),
+            hosted_silo_unaware: false,
+        };
+
+        let resp = client.access().connect_hvsock(request);
+        server.next().await.unwrap();
+
+        // The host never responds.
+        let result = resp.await;
+        assert!(
+            matches!(result, HvsockConnectResult::TimedOut),
+            "{result:?}"
+        );
     }
 
     #[async_test]
@@ -2976,7 +5225,7 @@ async fn test_synic_event_flags(driver: DefaultDriver) {
         let connection = server.get_channels(&mut client, 5).await;
         let event = Event::new();
 
-        for _ in 0..5 {
+        for iteration in 0..5 {
             for (i, channel) in connection.offers.iter().enumerate() {
                 let recv = channel.request_send.call(
                     ChannelRequest::Open,
@@ -2991,17 +5240,19 @@ async fn test_synic_event_flags(driver: DefaultDriver) {
                         },
                         incoming_event: Some(event.clone()),
                         use_vtl2_connection_id: false,
+                        redirect_interrupt: false,
                     },
                 );
 
                 let expected_event_flag = i as u16 + 1;
+                let open_id = (iteration * connection.offers.len() + i + 1) as u32;
 
                 check_message(
                     server.next().await.unwrap(),
                     protocol::OpenChannel2 {
                         open_channel: protocol::OpenChannel {
                             channel_id: channel.offer.channel_id,
-                            open_id: 0,
+                            open_id,
                             ring_buffer_gpadl_id: GpadlId(0),
                             target_vp: 0,
                             downstream_ring_buffer_page_offset: 0,
@@ -3017,7 +5268,7 @@ async fn test_synic_event_flags(driver: DefaultDriver) {
                     MessageType::OPEN_CHANNEL_RESULT,
                     protocol::OpenResult {
                         channel_id: channel.offer.channel_id,
-                        open_id: 0,
+                        open_id,
                         status: protocol::STATUS_SUCCESS as u32,
                     },
                 ));
@@ -3074,14 +5325,32 @@ async fn test_revoke(driver: DefaultDriver) {
                     },
                     incoming_event: None,
                     use_vtl2_connection_id: false,
+                    redirect_interrupt: false,
                 },
             )
             .await
             .unwrap_err();
     }
 
+    fn test_offer(channel_id: u32) -> protocol::OfferChannel {
+        protocol::OfferChannel {
+            interface_id: Guid::new_random(),
+            instance_id: Guid::new_random(),
+            rsvd: [0; 4],
+            flags: OfferFlags::new(),
+            mmio_megabytes: 0,
+            user_defined: UserDefinedData::new_zeroed(),
+            subchannel_index: 0,
+            mmio_megabytes_optional: 0,
+            channel_id: ChannelId(channel_id),
+            monitor_id: 0,
+            monitor_allocated: 0,
+            is_dedicated: 0,
+            connection_id: 0,
+        }
+    }
+
     #[async_test]
-    #[should_panic(expected = "channel should not exist")]
     async fn test_reoffer_in_use_rel_id(driver: DefaultDriver) {
         let (mut server, mut client) = test_init(&driver);
         let mut connection = server.get_channels(&mut client, 1).await;
@@ -3096,26 +5365,122 @@ async fn test_reoffer_in_use_rel_id(driver: DefaultDriver) {
 
         channel.revoke_recv.await.unwrap();
 
-        // This offer will cause a panic since the rel id is still in use.
-        let offer = protocol::OfferChannel {
-            interface_id: Guid::new_random(),
-            instance_id: Guid::new_random(),
-            rsvd: [0; 4],
-            flags: OfferFlags::new(),
-            mmio_megabytes: 0,
-            user_defined: UserDefinedData::new_zeroed(),
-            subchannel_index: 0,
-            mmio_megabytes_optional: 0,
-            channel_id: ChannelId(0),
-            monitor_id: 0,
-            monitor_allocated: 0,
-            is_dedicated: 0,
-            connection_id: 0,
-        };
+        // This offer is ignored since the rel id is still in use.
+        server.send(in_msg(MessageType::OFFER_CHANNEL, test_offer(0)));
+        server.send(in_msg(MessageType::OFFER_CHANNEL, test_offer(1)));
 
-        server.send(in_msg(MessageType::OFFER_CHANNEL, offer));
+        let offer = connection.offer_recv.next().await.unwrap();
+        assert_eq!(offer.offer.channel_id, ChannelId(1));
+    }
+
+    #[async_test]
+    async fn test_message_trace_replay(driver: DefaultDriver) {
+        let buf = TraceBuffer::new();
+        let (mut server, mut client) =
+            test_init_with(&driver, |builder| builder.message_trace(buf.writer()));
+        server.get_channels(&mut client, 2).await;
+
+        let records = buf.records().unwrap();
+        // InitiateContact, VersionResponse, RequestOffers, two offers, and
+        // AllOffersDelivered.
+        assert_eq!(records.len(), 6);
+        assert_eq!(records[0].direction, TraceDirection::GuestToHost);
+        assert_eq!(records[1].direction, TraceDirection::HostToGuest);
+
+        // Replaying the host messages against a new client produces the same
+        // client messages and offers.
+        let (mut server, mut client) = test_init(&driver);
+        let (connection, ()) = (client.connect(0, None, Guid::ZERO), server.replay(&records))
+            .join()
+            .await;
+        assert_eq!(connection.unwrap().offers.len(), 2);
+    }
+
+    #[async_test]
+    async fn test_invalid_host_messages(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init_with(&driver, |builder| {
+            builder.unexpected_message_policy(UnexpectedMessagePolicy::Warn)
+        });
+        let mut connection = server.get_channels(&mut client, 1).await;
 
-        connection.offer_recv.next().await;
+        // None of these should affect the client.
+        server.send(vec![0xff; 3]);
+        server.send(in_msg(MessageType::OFFER_CHANNEL, [0u8; 4]));
+        server.send(in_msg(
+            MessageType::REQUEST_OFFERS,
+            protocol::RequestOffers {},
+        ));
+        server.send(in_msg(
+            MessageType::RESCIND_CHANNEL_OFFER,
+            protocol::RescindChannelOffer {
+                channel_id: ChannelId(5),
+            },
+        ));
+        server.send(in_msg(
+            MessageType::OPEN_CHANNEL_RESULT,
+            protocol::OpenResult {
+                channel_id: ChannelId(5),
+                open_id: 0,
+                status: protocol::STATUS_SUCCESS as u32,
+            },
+        ));
+        server.send(in_msg(
+            MessageType::GPADL_CREATED,
+            protocol::GpadlCreated {
+                channel_id: ChannelId(0),
+                gpadl_id: GpadlId(1),
+                status: protocol::STATUS_SUCCESS,
+            },
+        ));
+        server.send(in_msg(
+            MessageType::GPADL_TORNDOWN,
+            protocol::GpadlTorndown {
+                gpadl_id: GpadlId(1),
+            },
+        ));
+        server.send(in_msg(
+            MessageType::MODIFY_CHANNEL_RESPONSE,
+            protocol::ModifyChannelResponse {
+                channel_id: ChannelId(0),
+                status: protocol::STATUS_SUCCESS,
+            },
+        ));
+        server.send(in_msg(MessageType::OFFER_CHANNEL, test_offer(1)));
+
+        let offer = connection.offer_recv.next().await.unwrap();
+        assert_eq!(offer.offer.channel_id, ChannelId(1));
+        let snapshot = client.snapshot().await;
+        assert_eq!(snapshot.state, "Connected");
+        assert_eq!(snapshot.unexpected_messages, 3);
+        assert_eq!(
+            snapshot.channels.into_iter().collect::<Vec<_>>(),
+            [
+                (ChannelId(0), "Offered".to_owned()),
+                (ChannelId(1), "Offered".to_owned())
+            ]
+        );
+    }
+
+    #[async_test]
+    async fn test_unexpected_message_disconnect(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init_with(&driver, |builder| {
+            builder.unexpected_message_policy(UnexpectedMessagePolicy::Disconnect)
+        });
+        let _connection = server.get_channels(&mut client, 1).await;
+
+        server.send(in_msg(
+            MessageType::REQUEST_OFFERS,
+            protocol::RequestOffers {},
+        ));
+        check_message(server.next().await.unwrap(), protocol::Unload {});
+        server.send(in_msg(
+            MessageType::UNLOAD_COMPLETE,
+            protocol::UnloadComplete {},
+        ));
+
+        let snapshot = client.snapshot().await;
+        assert_eq!(snapshot.state, "Disconnected");
+        assert_eq!(snapshot.unexpected_messages, 1);
     }
 
     #[async_test]
@@ -3181,6 +5546,7 @@ async fn test_release_revoke_and_reoffer(driver: DefaultDriver) {
                 },
                 incoming_event: None,
                 use_vtl2_connection_id: false,
+                redirect_interrupt: false,
             },
         );
 
@@ -3190,7 +5556,7 @@ async fn test_release_revoke_and_reoffer(driver: DefaultDriver) {
                 protocol::OpenChannel2 {
                     open_channel: protocol::OpenChannel {
                         channel_id: ChannelId(0),
-                        open_id: 0,
+                        open_id: 1,
                         ring_buffer_gpadl_id: GpadlId(0),
                         target_vp: 0,
                         downstream_ring_buffer_page_offset: 0,
@@ -3205,7 +5571,7 @@ async fn test_release_revoke_and_reoffer(driver: DefaultDriver) {
                 MessageType::OPEN_CHANNEL_RESULT,
                 protocol::OpenResult {
                     channel_id: ChannelId(0),
-                    open_id: 0,
+                    open_id: 1,
                     status: protocol::STATUS_SUCCESS as u32,
                 },
             ));
@@ -3259,4 +5625,89 @@ async fn test_release_revoke_and_reoffer(driver: DefaultDriver) {
         // New offer should come through.
         connection.offer_recv.next().await.unwrap();
     }
+
+    #[async_test]
+    async fn test_client_release_before_rescind(driver: DefaultDriver) {
+        let (mut server, mut client) = test_init(&driver);
+        let mut connection = server.get_channels(&mut client, 1).await;
+        let [channel] = connection.offers.try_into().unwrap();
+
+        let recv = channel.request_send.call_failable(
+            ChannelRequest::Gpadl,
+            GpadlRequest {
+                id: GpadlId(1),
+                count: 1,
+                buf: vec![5],
+            },
+        );
+
+        let _ = server.next().await.unwrap();
+        server.send(in_msg(
+            MessageType::GPADL_CREATED,
+            protocol::GpadlCreated {
+                channel_id: ChannelId(0),
+                gpadl_id: GpadlId(1),
+                status: protocol::STATUS_SUCCESS,
+            },
+        ));
+        recv.await.unwrap();
+
+        // Releasing the channel tears down its GPADLs, but the channel ID is
+        // not released until the host rescinds the offer.
+        channel
+            .request_send
+            .call(ChannelRequest::Release, ())
+            .await
+            .unwrap();
+
+        check_message(
+            server.next().await.unwrap(),
+            protocol::GpadlTeardown {
+                channel_id: ChannelId(0),
+                gpadl_id: GpadlId(1),
+            },
+        );
+        server.send(in_msg(
+            MessageType::GPADL_TORNDOWN,
+            protocol::GpadlTorndown {
+                gpadl_id: GpadlId(1),
+            },
+        ));
+
+        server.send(in_msg(
+            MessageType::RESCIND_CHANNEL_OFFER,
+            protocol::RescindChannelOffer {
+                channel_id: ChannelId(0),
+            },
+        ));
+
+        check_message(
+            server.next().await.unwrap(),
+            protocol::RelIdReleased {
+                channel_id: ChannelId(0),
+            },
+        );
+
+        // Dropping the request sender after the release has no effect.
+        drop(channel.request_send);
+
+        let offer = protocol::OfferChannel {
+            interface_id: Guid::new_random(),
+            instance_id: Guid::new_random(),
+            rsvd: [0; 4],
+            flags: OfferFlags::new(),
+            mmio_megabytes: 0,
+            user_defined: UserDefinedData::new_zeroed(),
+            subchannel_index: 0,
+            mmio_megabytes_optional: 0,
+            channel_id: ChannelId(0),
+            monitor_id: 0,
+            monitor_allocated: 0,
+            is_dedicated: 0,
+            connection_id: 0,
+        };
+
+        server.send(in_msg(MessageType::OFFER_CHANNEL, offer));
+        connection.offer_recv.next().await.unwrap();
+    }
 }
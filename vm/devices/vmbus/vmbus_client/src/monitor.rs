@@ -0,0 +1,132 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Support for signaling the host through the child-to-parent monitor page
+//! (MNF) instead of signaling the channel's event directly.
+
+use guestmem::GuestMemory;
+use hvdef::HvMonitorPage;
+use hvdef::HvMonitorTriggerGroup;
+use inspect::Inspect;
+use std::mem::offset_of;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use vmcore::interrupt::Interrupt;
+use vmcore::monitor::MonitorId;
+use vmcore::synic::MonitorPageGpas;
+
+const NO_MONITOR_PAGE: u64 = 0;
+
+/// The monitor pages currently in use by the connection, shared with each
+/// channel's [`MonitorSignal`].
+#[derive(Debug, Inspect)]
+pub(crate) struct MonitorPages {
+    #[inspect(skip)]
+    mem: GuestMemory,
+    #[inspect(hex)]
+    child_to_parent: AtomicU64,
+}
+
+impl MonitorPages {
+    pub fn new(mem: GuestMemory) -> Self {
+        Self {
+            mem,
+            child_to_parent: AtomicU64::new(NO_MONITOR_PAGE),
+        }
+    }
+
+    /// Updates the monitor page that subsequent signals will be written to.
+    pub fn set(&self, monitor_page: Option<MonitorPageGpas>) {
+        let gpa = monitor_page.map_or(NO_MONITOR_PAGE, |page| page.child_to_parent);
+        self.child_to_parent.store(gpa, Ordering::Release);
+    }
+
+    fn child_to_parent(&self) -> Option<u64> {
+        let gpa = self.child_to_parent.load(Ordering::Acquire);
+        (gpa != NO_MONITOR_PAGE).then_some(gpa)
+    }
+
+    /// Sets the pending bit for `monitor_id`. Returns false if there is no
+    /// monitor page or it could not be written.
+    fn set_pending(&self, monitor_id: MonitorId) -> bool {
+        let Some(page_gpa) = self.child_to_parent() else {
+            return false;
+        };
+
+        let group = monitor_id.0 as u64 / 32;
+        let bit = 1u32 << (monitor_id.0 % 32);
+        let gpa = page_gpa
+            + offset_of!(HvMonitorPage, trigger_group) as u64
+            + group * size_of::<HvMonitorTriggerGroup>() as u64
+            + offset_of!(HvMonitorTriggerGroup, pending) as u64;
+
+        let mut current = 0;
+        loop {
+            match self.mem.compare_exchange(gpa, current, current | bit) {
+                Ok(Ok(_)) => break true,
+                Ok(Err(value)) => {
+                    if value & bit != 0 {
+                        break true;
+                    }
+                    current = value;
+                }
+                Err(err) => {
+                    tracelimit::warn_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        monitor_id = monitor_id.0,
+                        "failed to write monitor page"
+                    );
+                    break false;
+                }
+            }
+        }
+    }
+}
+
+/// A handle for signaling a channel through its monitor ID.
+///
+/// Signals set the channel's pending bit in the child-to-parent monitor page.
+/// If the connection has no monitor page, for example after it was removed
+/// with a [`ModifyConnectionRequest`](crate::ModifyConnectionRequest), the
+/// channel's event is signaled directly instead.
+#[derive(Debug, Clone)]
+pub struct MonitorSignal {
+    monitor_id: MonitorId,
+    pages: Arc<MonitorPages>,
+    direct: Interrupt,
+}
+
+impl MonitorSignal {
+    pub(crate) fn new(monitor_id: MonitorId, pages: Arc<MonitorPages>, direct: Interrupt) -> Self {
+        Self {
+            monitor_id,
+            pages,
+            direct,
+        }
+    }
+
+    /// The monitor ID allocated to the channel by the host.
+    pub fn monitor_id(&self) -> MonitorId {
+        self.monitor_id
+    }
+
+    /// Returns true if signals are currently delivered through the monitor
+    /// page.
+    pub fn is_monitored(&self) -> bool {
+        self.pages.child_to_parent().is_some()
+    }
+
+    /// Signals the host.
+    pub fn signal(&self) {
+        if !self.pages.set_pending(self.monitor_id) {
+            self.direct.deliver();
+        }
+    }
+
+    /// Returns an interrupt object that calls [`Self::signal`].
+    pub fn interrupt(&self) -> Interrupt {
+        let this = self.clone();
+        Interrupt::from_fn(move || this.signal())
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Pacing support for posting messages to a host whose message queue may be
+//! full.
+
+use inspect::Inspect;
+use inspect_counters::Counter;
+use pal_async::driver::Driver;
+use pal_async::timer::Instant;
+use pal_async::timer::PolledTimer;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+use thiserror::Error;
+
+/// An error posting a message to the host.
+#[derive(Debug, Error)]
+pub enum PostError {
+    /// The host's message queue is full (HV_STATUS_INSUFFICIENT_BUFFERS).
+    /// This is a transient condition: the message should be posted again
+    /// after the host has had time to drain its queue.
+    #[error("host message queue is full")]
+    InsufficientBuffers,
+    /// Posting failed for a reason that retrying will not fix.
+    #[error("failed to post message")]
+    Other(#[source] std::io::Error),
+}
+
+/// Retries posting messages with exponential backoff while the host reports
+/// that its message queue is full.
+#[derive(Inspect)]
+pub(crate) struct PostBackoff {
+    #[inspect(skip)]
+    timer: PolledTimer,
+    #[inspect(skip)]
+    deadline: Option<Instant>,
+    #[inspect(debug)]
+    next_wait: Duration,
+    /// The number of times posting failed because the queue was full.
+    queue_full: Counter,
+    /// The number of messages that were posted only after retrying.
+    retried: Counter,
+    #[inspect(skip)]
+    retrying: bool,
+}
+
+impl PostBackoff {
+    const INITIAL_WAIT: Duration = Duration::from_millis(1);
+    const MAX_WAIT: Duration = Duration::from_secs(1);
+
+    /// Creates a new instance using `driver` for backoff timers.
+    pub fn new(driver: &(impl Driver + ?Sized)) -> Self {
+        Self {
+            timer: PolledTimer::new(driver),
+            deadline: None,
+            next_wait: Self::INITIAL_WAIT,
+            queue_full: Counter::new(),
+            retried: Counter::new(),
+            retrying: false,
+        }
+    }
+
+    /// Polls `post` until it completes with something other than
+    /// [`PostError::InsufficientBuffers`], waiting longer after each
+    /// consecutive such failure.
+    pub fn poll_post(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut post: impl FnMut(&mut Context<'_>) -> Poll<Result<(), PostError>>,
+    ) -> Poll<Result<(), PostError>> {
+        loop {
+            if let Some(deadline) = self.deadline {
+                ready!(self.timer.poll_until(cx, deadline));
+                self.deadline = None;
+            }
+            match ready!(post(cx)) {
+                Err(PostError::InsufficientBuffers) => {
+                    tracing::debug!(wait = ?self.next_wait, "host message queue full, retrying");
+                    self.queue_full.increment();
+                    self.retrying = true;
+                    self.deadline = Some(Instant::now() + self.next_wait);
+                    // Wait longer each time.
+                    if self.next_wait < Self::MAX_WAIT {
+                        self.next_wait *= 2;
+                    }
+                }
+                r => {
+                    if std::mem::take(&mut self.retrying) {
+                        self.retried.increment();
+                    }
+                    self.next_wait = Self::INITIAL_WAIT;
+                    break Poll::Ready(r);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use std::future::poll_fn;
+
+    #[async_test]
+    async fn test_backoff(driver: DefaultDriver) {
+        let mut backoff = PostBackoff::new(&driver);
+        let mut attempts = 0;
+        poll_fn(|cx| {
+            backoff.poll_post(cx, |_| {
+                attempts += 1;
+                Poll::Ready(if attempts <= 3 {
+                    Err(PostError::InsufficientBuffers)
+                } else {
+                    Ok(())
+                })
+            })
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(attempts, 4);
+        assert_eq!(backoff.queue_full.get(), 3);
+        assert_eq!(backoff.retried.get(), 1);
+        assert_eq!(backoff.next_wait, PostBackoff::INITIAL_WAIT);
+
+        // Posting succeeds immediately without waiting.
+        poll_fn(|cx| backoff.poll_post(cx, |_| Poll::Ready(Ok(()))))
+            .await
+            .unwrap();
+        assert_eq!(backoff.queue_full.get(), 3);
+        assert_eq!(backoff.retried.get(), 1);
+
+        // Other errors are not retried.
+        poll_fn(|cx| {
+            backoff.poll_post(cx, |_| {
+                Poll::Ready(Err(PostError::Other(std::io::ErrorKind::Other.into())))
+            })
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(backoff.queue_full.get(), 3);
+    }
+}
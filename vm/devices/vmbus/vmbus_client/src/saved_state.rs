@@ -16,6 +16,22 @@
 use vmbus_core::protocol::ChannelId;
 use vmbus_core::protocol::FeatureFlags;
 use vmbus_core::protocol::GpadlId;
+use vmcore::synic::MonitorPageGpas;
+
+/// The saved state schema revision written by this client.
+///
+/// * 0: unversioned. Has no in-flight operations or `next_open_id`.
+/// * 1: adds the version fields, in-flight opens (with their redirected event
+///   flag) and GPADL creations, and `next_open_id`.
+///
+/// Bump this when changing the schema, and add the upgrade from the previous
+/// revision to [`SavedState::upgrade`].
+const SAVED_STATE_VERSION: u32 = 1;
+
+/// The oldest revision that can restore state written by this client. Bump
+/// this to [`SAVED_STATE_VERSION`] when older clients would misinterpret the
+/// new schema rather than just ignore the new fields.
+const COMPATIBLE_VERSION: u32 = 1;
 
 impl super::ClientTask {
     pub fn handle_save(&mut self) -> SavedState {
@@ -39,10 +55,15 @@ pub fn handle_save(&mut self) -> SavedState {
                 super::ClientState::Connecting { .. } => {
                     unreachable!("Cannot save in Connecting state.")
                 }
-                super::ClientState::Connected { version, .. } => ClientState::Connected {
-                    version: version.version as u32,
-                    feature_flags: version.feature_flags.into(),
-                },
+                super::ClientState::Connected { version, .. } => {
+                    let monitor_page = self.inner.synic.monitor_page.unwrap_or_default();
+                    ClientState::Connected {
+                        version: version.version as u32,
+                        feature_flags: version.feature_flags.into(),
+                        parent_to_child_monitor_page_gpa: monitor_page.parent_to_child,
+                        child_to_parent_monitor_page_gpa: monitor_page.child_to_parent,
+                    }
+                }
                 super::ClientState::RequestingOffers { .. } => {
                     unreachable!("Cannot save in RequestingOffers state.")
                 }
@@ -97,6 +118,9 @@ pub fn handle_save(&mut self) -> SavedState {
                 })
                 .collect(),
             pending_messages,
+            next_open_id: self.next_open_id,
+            version: SAVED_STATE_VERSION,
+            compatible_version: COMPATIBLE_VERSION,
         }
     }
 
@@ -111,14 +135,31 @@ pub fn handle_restore(
             channels,
             gpadls,
             pending_messages,
-        } = saved_state;
-
-        let (version, feature_flags) = match client_state {
-            ClientState::Disconnected => return Ok(None),
+            next_open_id,
+            version: _,
+            compatible_version: _,
+        } = saved_state.upgrade()?;
+
+        let (version, feature_flags, monitor_page) = match client_state {
+            ClientState::Disconnected => {
+                if !channels.is_empty() || !gpadls.is_empty() {
+                    return Err(RestoreError::PendingOperationsWhileDisconnected);
+                }
+                return Ok(None);
+            }
             ClientState::Connected {
                 version,
                 feature_flags,
-            } => (version, feature_flags),
+                parent_to_child_monitor_page_gpa,
+                child_to_parent_monitor_page_gpa,
+            } => (
+                version,
+                feature_flags,
+                MonitorPageGpas {
+                    parent_to_child: parent_to_child_monitor_page_gpa,
+                    child_to_parent: child_to_parent_monitor_page_gpa,
+                },
+            ),
         };
 
         let version = super::SUPPORTED_VERSIONS
@@ -137,18 +178,23 @@ pub fn handle_restore(
             feature_flags,
         };
 
+        self.inner
+            .synic
+            .set_monitor_page((monitor_page != MonitorPageGpas::default()).then_some(monitor_page));
+
         let (offer_send, offer_recv) = mesh::channel();
         self.state = super::ClientState::Connected {
             version,
             offer_send,
         };
+        self.next_open_id = next_open_id;
 
         let mut restored_channels = Vec::new();
         for saved_channel in channels {
             let offer_info = self.restore_channel(saved_channel)?;
             let key = offer_key(&offer_info.offer);
             tracing::info!(%key, state = %saved_channel.state, "channel restored");
-            restored_channels.push(offer_info);
+            restored_channels.extend(self.filter_offer(offer_info));
         }
 
         for gpadl in gpadls {
@@ -211,14 +257,11 @@ pub fn handle_post_restore(&mut self) {
                     // FUTURE: wait for GPADL teardown so that everything is in a clean
                     // state after this.
                     match gpadl_state {
-                        crate::GpadlState::Offered(_) => unreachable!(),
+                        // Restored GPADLs are torn down once created.
+                        crate::GpadlState::Offered { .. } => {}
                         crate::GpadlState::Created => {
-                            self.inner.teardown_gpadls.insert(gpadl_id, channel_id);
-                            self.inner.messages.send(&protocol::GpadlTeardown {
-                                channel_id,
-                                gpadl_id,
-                            });
-                            *gpadl_state = crate::GpadlState::TearingDown { rpcs: Vec::new() };
+                            *gpadl_state =
+                                self.inner.teardown_gpadl(channel_id, gpadl_id, Vec::new());
                         }
                         crate::GpadlState::TearingDown { .. } => {}
                     }
@@ -228,6 +271,16 @@ pub fn handle_post_restore(&mut self) {
     }
 
     fn restore_channel(&mut self, channel: Channel) -> Result<OfferInfo, RestoreError> {
+        if let ChannelState::Opening {
+            redirected_event_flag: Some(flag),
+            ..
+        } = channel.state
+        {
+            self.inner
+                .synic
+                .reserve_event_flag(flag)
+                .map_err(RestoreError::InvalidEventFlag)?;
+        }
         self.create_channel_core(channel.offer.into(), channel.state.restore())
             .map_err(RestoreError::OfferFailed)
     }
@@ -244,6 +297,33 @@ pub struct SavedState {
     pub gpadls: Vec<Gpadl>,
     #[mesh(4)]
     pub pending_messages: Vec<PendingMessage>,
+    #[mesh(5)]
+    pub next_open_id: u32,
+    /// The schema revision this state was saved with.
+    #[mesh(6)]
+    pub version: u32,
+    /// The oldest schema revision that can restore this state.
+    #[mesh(7)]
+    pub compatible_version: u32,
+}
+
+impl SavedState {
+    /// Converts state saved by any compatible client to the current revision.
+    fn upgrade(mut self) -> Result<Self, RestoreError> {
+        if self.compatible_version > SAVED_STATE_VERSION {
+            return Err(RestoreError::IncompatibleSavedState {
+                version: self.version,
+                compatible_version: self.compatible_version,
+            });
+        }
+        if self.version == 0 {
+            // Open IDs started at 1 and were not saved.
+            self.next_open_id = 1;
+        }
+        self.version = SAVED_STATE_VERSION;
+        self.compatible_version = COMPATIBLE_VERSION;
+        Ok(self)
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Protobuf)]
@@ -264,6 +344,10 @@ pub enum ClientState {
         version: u32,
         #[mesh(2)]
         feature_flags: u32,
+        #[mesh(3)]
+        parent_to_child_monitor_page_gpa: u64,
+        #[mesh(4)]
+        child_to_parent_monitor_page_gpa: u64,
     },
 }
 
@@ -285,15 +369,35 @@ pub enum ChannelState {
     Offered,
     #[mesh(2)]
     Opened,
+    /// An open request was in flight. The open is undone when the host
+    /// responds, since the requester did not survive the save.
+    #[mesh(3)]
+    Opening {
+        #[mesh(1)]
+        open_id: u32,
+        /// The event flag allocated for the open, which stays in use until
+        /// the host responds.
+        #[mesh(2)]
+        redirected_event_flag: Option<u16>,
+    },
 }
 
 impl ChannelState {
     fn save(state: &super::ChannelState) -> Option<Self> {
         let s = match state {
             super::ChannelState::Offered => Self::Offered,
-            super::ChannelState::Opening { .. } => {
-                unreachable!("Cannot save channel in opening state.")
+            super::ChannelState::Opening {
+                open_id,
+                redirected_event_flag,
+                ..
             }
+            | super::ChannelState::ClosingAfterOpen {
+                open_id,
+                redirected_event_flag,
+            } => Self::Opening {
+                open_id: *open_id,
+                redirected_event_flag: *redirected_event_flag,
+            },
             super::ChannelState::Restored | super::ChannelState::Opened { .. } => Self::Opened,
             super::ChannelState::Revoked => return None,
         };
@@ -304,6 +408,13 @@ fn restore(self) -> super::ChannelState {
         match self {
             ChannelState::Offered => super::ChannelState::Offered,
             ChannelState::Opened => super::ChannelState::Restored,
+            ChannelState::Opening {
+                open_id,
+                redirected_event_flag,
+            } => super::ChannelState::ClosingAfterOpen {
+                open_id,
+                redirected_event_flag,
+            },
         }
     }
 }
@@ -313,6 +424,7 @@ fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ChannelState::Offered => write!(fmt, "Offered"),
             ChannelState::Opened => write!(fmt, "Opened"),
+            ChannelState::Opening { .. } => write!(fmt, "Opening"),
         }
     }
 }
@@ -324,12 +436,16 @@ pub enum GpadlState {
     Created,
     #[mesh(2)]
     TearingDown,
+    /// A GPADL header was sent but the host had not responded. The GPADL is
+    /// torn down once created, since the requester did not survive the save.
+    #[mesh(3)]
+    Offered,
 }
 
 impl GpadlState {
     fn save(value: &super::GpadlState) -> Self {
         match value {
-            super::GpadlState::Offered(..) => unreachable!("Cannot save gpadl in offered state."),
+            super::GpadlState::Offered { .. } => Self::Offered,
             super::GpadlState::Created => Self::Created,
             super::GpadlState::TearingDown { .. } => Self::TearingDown,
         }
@@ -339,6 +455,10 @@ fn restore(self) -> super::GpadlState {
         match self {
             GpadlState::Created => super::GpadlState::Created,
             GpadlState::TearingDown => super::GpadlState::TearingDown { rpcs: Vec::new() },
+            GpadlState::Offered => super::GpadlState::Offered {
+                rpc: None,
+                teardown_rpcs: Vec::new(),
+            },
         }
     }
 }
@@ -429,3 +549,155 @@ fn offer_key(offer: &protocol::OfferChannel) -> OfferKey {
         subchannel_index: offer.subchannel_index,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Channel;
+    use super::ChannelState;
+    use super::ClientState;
+    use super::Gpadl;
+    use super::GpadlState;
+    use super::Offer;
+    use super::PendingMessage;
+    use super::SavedState;
+    use crate::RestoreError;
+    use guid::Guid;
+    use mesh::payload::Protobuf;
+    use vmbus_core::protocol::Version;
+
+    /// The unversioned schema, before revision 1.
+    mod v0 {
+        use super::Channel;
+        use super::Gpadl;
+        use super::PendingMessage;
+        use mesh::payload::Protobuf;
+
+        #[derive(Protobuf)]
+        #[mesh(package = "vmbus.client")]
+        pub struct SavedState {
+            #[mesh(1)]
+            pub client_state: ClientState,
+            #[mesh(2)]
+            pub channels: Vec<Channel>,
+            #[mesh(3)]
+            pub gpadls: Vec<Gpadl>,
+            #[mesh(4)]
+            pub pending_messages: Vec<PendingMessage>,
+        }
+
+        #[derive(Protobuf)]
+        #[mesh(package = "vmbus.client")]
+        pub enum ClientState {
+            #[mesh(2)]
+            Connected {
+                #[mesh(1)]
+                version: u32,
+                #[mesh(2)]
+                feature_flags: u32,
+            },
+        }
+    }
+
+    /// A hypothetical future schema with a new field.
+    #[derive(Protobuf)]
+    #[mesh(package = "vmbus.client")]
+    struct Future {
+        #[mesh(1)]
+        client_state: ClientState,
+        #[mesh(6)]
+        version: u32,
+        #[mesh(7)]
+        compatible_version: u32,
+        #[mesh(8)]
+        new_field: u64,
+    }
+
+    fn offer() -> Offer {
+        Offer {
+            interface_id: Guid::ZERO,
+            instance_id: Guid::ZERO,
+            flags: 0,
+            mmio_megabytes: 0,
+            user_defined: [0; 120],
+            subchannel_index: 0,
+            mmio_megabytes_optional: 0,
+            channel_id: 1,
+            monitor_id: 0xff,
+            monitor_allocated: 0,
+            is_dedicated: 1,
+            connection_id: 0,
+        }
+    }
+
+    #[test]
+    fn test_upgrade_v0() {
+        let channel = Channel {
+            id: 1,
+            state: ChannelState::Opened,
+            offer: offer(),
+        };
+        let gpadl = Gpadl {
+            gpadl_id: 2,
+            channel_id: 1,
+            state: GpadlState::Created,
+        };
+        let old = v0::SavedState {
+            client_state: v0::ClientState::Connected {
+                version: Version::Copper as u32,
+                feature_flags: 0,
+            },
+            channels: vec![channel],
+            gpadls: vec![gpadl.clone()],
+            pending_messages: Vec::new(),
+        };
+
+        let state: SavedState = mesh::payload::decode(&mesh::payload::encode(old)).unwrap();
+        assert_eq!(state.version, 0);
+        let state = state.upgrade().unwrap();
+        assert_eq!(
+            state,
+            SavedState {
+                client_state: ClientState::Connected {
+                    version: Version::Copper as u32,
+                    feature_flags: 0,
+                    parent_to_child_monitor_page_gpa: 0,
+                    child_to_parent_monitor_page_gpa: 0,
+                },
+                channels: vec![channel],
+                gpadls: vec![gpadl],
+                pending_messages: Vec::new(),
+                next_open_id: 1,
+                version: super::SAVED_STATE_VERSION,
+                compatible_version: super::COMPATIBLE_VERSION,
+            }
+        );
+    }
+
+    #[test]
+    fn test_upgrade_future() {
+        let future = |compatible_version| Future {
+            client_state: ClientState::Disconnected,
+            version: super::SAVED_STATE_VERSION + 1,
+            compatible_version,
+            new_field: 5,
+        };
+
+        // A newer client that left the schema compatible can be restored.
+        let state: SavedState =
+            mesh::payload::decode(&mesh::payload::encode(future(super::SAVED_STATE_VERSION)))
+                .unwrap();
+        let state = state.upgrade().unwrap();
+        assert_eq!(state.version, super::SAVED_STATE_VERSION);
+
+        // One that did not is rejected.
+        let state: SavedState = mesh::payload::decode(&mesh::payload::encode(future(
+            super::SAVED_STATE_VERSION + 1,
+        )))
+        .unwrap();
+        let err = state.upgrade().unwrap_err();
+        assert!(
+            matches!(err, RestoreError::IncompatibleSavedState { .. }),
+            "{err:?}"
+        );
+    }
+}
@@ -0,0 +1,376 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Test support for the vmbus client, for crates that want to test against a
+//! real client without a host.
+//!
+//! [`test_init`] creates a client connected to a [`TestServer`], which plays
+//! the host: it receives the client's messages, and injects host messages.
+//! [`VmbusClient::snapshot`] returns the client's internal state for
+//! white-box assertions.
+
+use crate::ConnectResult;
+use crate::OfferInfo;
+use crate::PollPostMessage;
+use crate::SUPPORTED_FEATURE_FLAGS;
+use crate::SynicEventClient;
+use crate::VmbusClient;
+use crate::VmbusClientBuilder;
+use crate::VmbusMessageSource;
+use crate::pacing::PostError;
+use futures::StreamExt;
+use futures_concurrency::future::Join;
+use guid::Guid;
+use pal_async::DefaultDriver;
+use pal_event::Event;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::task::Context;
+use std::task::Poll;
+use vmbus_async::async_dgram::AsyncRecv;
+use vmbus_core::OutgoingMessage;
+use vmbus_core::protocol;
+use vmbus_core::protocol::ChannelId;
+use vmbus_core::protocol::ConnectionState;
+use vmbus_core::protocol::FeatureFlags;
+use vmbus_core::protocol::MessageHeader;
+use vmbus_core::protocol::MessageType;
+use vmbus_core::protocol::OfferFlags;
+use vmbus_core::protocol::UserDefinedData;
+use vmbus_core::protocol::Version;
+use vmbus_core::protocol::VmbusMessage;
+use vmbus_core::trace::TraceDirection;
+use vmbus_core::trace::TraceRecord;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+/// A snapshot of the client's internal state, returned by
+/// [`VmbusClient::snapshot`].
+#[derive(Debug, Clone)]
+pub struct ClientSnapshot {
+    /// The connection state, e.g. `Connected`.
+    pub state: String,
+    /// The number of unexpected messages received from the host.
+    pub unexpected_messages: u64,
+    /// The state of each channel, e.g. `Opened`.
+    pub channels: BTreeMap<ChannelId, String>,
+}
+
+/// Formats a host message of type `message_type` with payload `t`, for
+/// [`TestServer::send`].
+pub fn in_msg<T: IntoBytes + Immutable + KnownLayout>(message_type: MessageType, t: T) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&message_type.0.to_ne_bytes());
+    data.extend_from_slice(&0u32.to_ne_bytes());
+    data.extend_from_slice(t.as_bytes());
+    data
+}
+
+/// Checks that `msg` is the message `chk`.
+#[track_caller]
+pub fn check_message<T>(msg: OutgoingMessage, chk: T)
+where
+    T: IntoBytes + FromBytes + Immutable + KnownLayout + Debug + VmbusMessage,
+{
+    check_message_with_data(msg, chk, &[]);
+}
+
+/// Checks that `msg` is the message `chk`, followed by `data`.
+#[track_caller]
+pub fn check_message_with_data<T>(msg: OutgoingMessage, chk: T, data: &[u8])
+where
+    T: IntoBytes + FromBytes + Immutable + KnownLayout + Debug + VmbusMessage,
+{
+    let chk_data = OutgoingMessage::with_data(&chk, data);
+    if msg.data() != chk_data.data() {
+        let (header, rest) = MessageHeader::read_from_prefix(msg.data()).unwrap();
+        assert_eq!(header.message_type(), <T as VmbusMessage>::MESSAGE_TYPE);
+        let (msg, rest) = T::read_from_prefix(rest).expect("incorrect message size");
+        if msg.as_bytes() != chk.as_bytes() {
+            panic!("mismatched messages, expected {:#?}, got {:#?}", chk, msg);
+        }
+        if rest != data {
+            panic!("mismatched data, expected {:#?}, got {:#?}", data, rest);
+        }
+    }
+}
+
+/// Parses `msg` as a message of type `T`.
+#[track_caller]
+pub fn parse_message<T>(msg: &OutgoingMessage) -> T
+where
+    T: FromBytes + Immutable + KnownLayout + VmbusMessage,
+{
+    let (header, rest) = MessageHeader::read_from_prefix(msg.data()).unwrap();
+    assert_eq!(header.message_type(), <T as VmbusMessage>::MESSAGE_TYPE);
+    T::read_from_prefix(rest).expect("incorrect message size").0
+}
+
+/// The host side of a client created with [`test_init`].
+pub struct TestServer {
+    messages: mesh::Receiver<OutgoingMessage>,
+    send: mesh::Sender<Vec<u8>>,
+    /// The feature flags the client is expected to negotiate.
+    feature_flags: FeatureFlags,
+}
+
+impl TestServer {
+    /// Returns the next message sent by the client.
+    pub async fn next(&mut self) -> Option<OutgoingMessage> {
+        self.messages.next().await
+    }
+
+    /// Injects a message from the host, as formatted by [`in_msg`].
+    pub fn send(&self, msg: Vec<u8>) {
+        self.send.send(msg);
+    }
+
+    /// Completes the client's connection with no offers.
+    pub async fn connect(&mut self, client: &mut VmbusClient) -> ConnectResult {
+        self.connect_with_channels(client, |_| {}).await
+    }
+
+    /// Completes the client's connection, calling `send_offers` to send offers
+    /// before all offers are delivered.
+    pub async fn connect_with_channels(
+        &mut self,
+        client: &mut VmbusClient,
+        send_offers: impl FnOnce(&mut Self),
+    ) -> ConnectResult {
+        let client_connect = client.connect(0, None, Guid::ZERO);
+        let (connection, ()) = (client_connect, self.accept_connect(send_offers))
+            .join()
+            .await;
+
+        let connection = connection.unwrap();
+        assert_eq!(connection.version.version, Version::Copper);
+        assert_eq!(connection.version.feature_flags, self.feature_flags);
+        connection
+    }
+
+    /// Plays the host side of a connection started by the client, calling
+    /// `send_offers` to send offers before all offers are delivered.
+    pub async fn accept_connect(&mut self, send_offers: impl FnOnce(&mut Self)) {
+        let _ = self.next().await.unwrap();
+
+        self.send(in_msg(
+            MessageType::VERSION_RESPONSE,
+            protocol::VersionResponse2 {
+                version_response: protocol::VersionResponse {
+                    version_supported: 1,
+                    connection_state: ConnectionState::SUCCESSFUL,
+                    padding: 0,
+                    selected_version_or_connection_id: 0,
+                },
+                supported_features: SUPPORTED_FEATURE_FLAGS.into(),
+            },
+        ));
+
+        check_message(self.next().await.unwrap(), protocol::RequestOffers {});
+
+        send_offers(self);
+        self.send(in_msg(MessageType::ALL_OFFERS_DELIVERED, [0x00]));
+    }
+
+    /// Connects the client with a single offer and returns it.
+    pub async fn get_channel(&mut self, client: &mut VmbusClient) -> OfferInfo {
+        let [channel] = self
+            .get_channels(client, 1)
+            .await
+            .offers
+            .try_into()
+            .unwrap();
+        channel
+    }
+
+    /// Connects the client with `count` offers, using channel IDs starting at
+    /// zero.
+    pub async fn get_channels(&mut self, client: &mut VmbusClient, count: usize) -> ConnectResult {
+        self.connect_with_channels(client, |this| {
+            for i in 0..count {
+                let offer = protocol::OfferChannel {
+                    interface_id: Guid::new_random(),
+                    instance_id: Guid::new_random(),
+                    rsvd: [0; 4],
+                    flags: OfferFlags::new(),
+                    mmio_megabytes: 0,
+                    user_defined: UserDefinedData::new_zeroed(),
+                    subchannel_index: 0,
+                    mmio_megabytes_optional: 0,
+                    channel_id: ChannelId(i as u32),
+                    monitor_id: 0,
+                    monitor_allocated: 0,
+                    is_dedicated: 0,
+                    connection_id: 0,
+                };
+
+                this.send(in_msg(MessageType::OFFER_CHANNEL, offer));
+            }
+        })
+        .await
+    }
+
+    /// Stops the client, completing its pause request.
+    pub async fn stop_client(&mut self, client: &mut VmbusClient) {
+        let client_stop = client.stop();
+        let server_stop = async {
+            check_message(self.next().await.unwrap(), protocol::Pause);
+            self.send(in_msg(MessageType::PAUSE_RESPONSE, protocol::PauseResponse));
+        };
+        (client_stop, server_stop).join().await;
+    }
+
+    /// Starts the client, checking for its resume request.
+    pub async fn start_client(&mut self, client: &mut VmbusClient) {
+        client.start();
+        check_message(self.next().await.unwrap(), protocol::Resume);
+    }
+
+    /// Replays the host side of a message trace against the client.
+    ///
+    /// Host messages are injected in order, and each client message in the
+    /// trace is checked against the next message the client sends. The caller
+    /// must drive any client requests, such as [`VmbusClient::connect`], that
+    /// cause the client to send messages.
+    pub async fn replay(&mut self, records: &[TraceRecord]) {
+        for (i, record) in records.iter().enumerate() {
+            match record.direction {
+                TraceDirection::HostToGuest => self.send(record.data.clone()),
+                TraceDirection::GuestToHost => {
+                    let msg = self.next().await.expect("client stopped");
+                    assert_eq!(
+                        msg.data(),
+                        record.data.as_slice(),
+                        "mismatched client message at trace record {i}"
+                    );
+                }
+            }
+        }
+    }
+}
+
+struct TestServerClient {
+    sender: mesh::Sender<OutgoingMessage>,
+}
+
+impl PollPostMessage for TestServerClient {
+    fn poll_post_message(
+        &mut self,
+        _cx: &mut Context<'_>,
+        _connection_id: u32,
+        _typ: u32,
+        msg: &[u8],
+    ) -> Poll<Result<(), PostError>> {
+        // Randomly report that the host's queue is full to exercise the
+        // client's retry path.
+        //
+        // FUTURE: use some kind of deterministic test framework for this to
+        // allow for reproducible tests.
+        let mut b = [0];
+        getrandom::fill(&mut b).unwrap();
+        if b[0] % 4 == 0 {
+            return Poll::Ready(Err(PostError::InsufficientBuffers));
+        }
+        let msg = OutgoingMessage::from_message(msg).unwrap();
+        tracing::info!(
+            msg = ?MessageHeader::read_from_prefix(msg.data()),
+            "sending message"
+        );
+        self.sender.send(msg);
+        Poll::Ready(Ok(()))
+    }
+}
+
+struct NoopSynicEvents;
+
+impl SynicEventClient for NoopSynicEvents {
+    fn map_event(&self, _event_flag: u16, _event: &Event) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn unmap_event(&self, _event_flag: u16) {}
+
+    fn signal_event(&self, _connection_id: u32, _event_flag: u16) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+struct TestMessageSource {
+    msg_recv: mesh::Receiver<Vec<u8>>,
+    paused: bool,
+}
+
+impl AsyncRecv for TestMessageSource {
+    fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut bufs: &mut [std::io::IoSliceMut<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let value = match self.msg_recv.poll_recv(cx) {
+            Poll::Ready(v) => v.unwrap(),
+            Poll::Pending => {
+                if self.paused {
+                    return Poll::Ready(Ok(0));
+                } else {
+                    return Poll::Pending;
+                }
+            }
+        };
+        let mut remaining = value.as_slice();
+        let mut total_size = 0;
+        while !remaining.is_empty() && !bufs.is_empty() {
+            let size = bufs[0].len().min(remaining.len());
+            bufs[0][..size].copy_from_slice(&remaining[..size]);
+            remaining = &remaining[size..];
+            bufs = &mut bufs[1..];
+            total_size += size;
+        }
+
+        Ok(total_size).into()
+    }
+}
+
+impl VmbusMessageSource for TestMessageSource {
+    fn pause_message_stream(&mut self) {
+        self.paused = true;
+    }
+
+    fn resume_message_stream(&mut self) {
+        self.paused = false;
+    }
+}
+
+/// Creates a client connected to a new [`TestServer`], and starts it.
+pub fn test_init(driver: &DefaultDriver) -> (TestServer, VmbusClient) {
+    test_init_with(driver, |builder| builder)
+}
+
+/// Creates a client as in [`test_init`], using `f` to configure the builder.
+pub fn test_init_with(
+    driver: &DefaultDriver,
+    f: impl FnOnce(VmbusClientBuilder) -> VmbusClientBuilder,
+) -> (TestServer, VmbusClient) {
+    let (msg_send, msg_recv) = mesh::channel();
+    let (synic_send, synic_recv) = mesh::channel();
+    let client = VmbusClientBuilder::new(
+        driver,
+        NoopSynicEvents,
+        TestMessageSource {
+            msg_recv,
+            paused: false,
+        },
+        TestServerClient { sender: synic_send },
+    );
+    let client = f(client);
+    let server = TestServer {
+        messages: synic_recv,
+        send: msg_send,
+        feature_flags: client.feature_flags,
+    };
+    let mut client = client.build(driver);
+    client.start();
+    (server, client)
+}
@@ -18,8 +18,6 @@
 use hvdef::HvMessageHeader;
 use pal_async::driver::Driver;
 use pal_async::pipe::PolledPipe;
-use pal_async::timer::Instant;
-use pal_async::timer::PolledTimer;
 use std::io;
 use std::io::IoSliceMut;
 use std::os::fd::AsFd;
@@ -27,12 +25,12 @@
 use std::sync::Arc;
 use std::task::Poll;
 use std::task::ready;
-use std::time::Duration;
 use vmbus_async::async_dgram::AsyncRecv;
 use vmbus_client::PollPostMessage;
 use vmbus_client::SynicEventClient;
 use vmbus_client::VmbusClientBuilder;
 use vmbus_client::VmbusMessageSource;
+use vmbus_client::pacing::PostError;
 use zerocopy::IntoBytes;
 
 /// Returns a [`VmbusClientBuilder`] configured to use the Linux HCL driver.
@@ -41,9 +39,6 @@ pub fn vmbus_client_builder<T: Driver + ?Sized>(driver: &T) -> anyhow::Result<Vm
     let hcl_vmbus = Arc::new(HclVmbus::new().context("failed to open hcl_vmbus")?);
     let poster = HclSynicPoster {
         hcl_vmbus: Arc::clone(&hcl_vmbus),
-        timer: PolledTimer::new(driver),
-        deadline: None,
-        next_wait: INITIAL_WAIT,
     };
     let synic = HclSynicEvents {
         hcl_vmbus: Arc::clone(&hcl_vmbus),
@@ -57,53 +52,31 @@ pub fn vmbus_client_builder<T: Driver + ?Sized>(driver: &T) -> anyhow::Result<Vm
     let pipe = PolledPipe::new(driver, vmbus_fd).context("failed to created PolledPipe")?;
     let msg_source = HclMessageSource { pipe, hcl_vmbus };
 
-    Ok(VmbusClientBuilder::new(synic, msg_source, poster))
+    Ok(VmbusClientBuilder::new(driver, synic, msg_source, poster))
 }
 
 struct HclSynicPoster {
     hcl_vmbus: Arc<HclVmbus>,
-    timer: PolledTimer,
-    deadline: Option<Instant>,
-    next_wait: Duration,
 }
 
-const INITIAL_WAIT: Duration = Duration::from_millis(1);
-
 impl PollPostMessage for HclSynicPoster {
     fn poll_post_message(
         &mut self,
-        cx: &mut std::task::Context<'_>,
+        _cx: &mut std::task::Context<'_>,
         connection_id: u32,
         typ: u32,
         msg: &[u8],
-    ) -> Poll<()> {
-        loop {
-            if let Some(deadline) = self.deadline {
-                ready!(self.timer.poll_until(cx, deadline));
-                self.deadline = None;
+    ) -> Poll<Result<(), PostError>> {
+        let r = match self.hcl_vmbus.post_message(connection_id, typ.into(), msg) {
+            Ok(()) => Ok(()),
+            // The host is backed up in handling these messages. The client
+            // will wait for a while before trying again.
+            Err(HypercallError::Hypervisor(HvError::InsufficientBuffers)) => {
+                Err(PostError::InsufficientBuffers)
             }
-            // If we receive HV_STATUS_INSUFFICIENT_BUFFERS, the host is backed
-            // up in handling these messages. Wait for a while before trying
-            // again.
-            let ret = self.hcl_vmbus.post_message(connection_id, typ.into(), msg);
-            match ret {
-                Ok(()) => {
-                    self.next_wait = INITIAL_WAIT;
-                    break Poll::Ready(());
-                }
-                Err(HypercallError::Hypervisor(HvError::InsufficientBuffers)) => {
-                    tracing::debug!("received HV_STATUS_INSUFFICIENT_BUFFERS, retrying");
-                    self.deadline = Some(Instant::now() + self.next_wait);
-                    // Wait longer each time.
-                    if self.next_wait < Duration::from_secs(1) {
-                        self.next_wait *= 2;
-                    }
-                }
-                Err(err) => {
-                    panic!("received error code from post message call {}", err);
-                }
-            }
-        }
+            Err(err) => Err(PostError::Other(io::Error::other(err))),
+        };
+        Poll::Ready(r)
     }
 }
 
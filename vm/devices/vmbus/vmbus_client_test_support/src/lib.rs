@@ -0,0 +1,340 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A simulated VMBus host for testing code built on [`vmbus_client`].
+//!
+//! [`TestHostBuilder`] creates a [`VmbusClientBuilder`] connected to a
+//! [`TestHost`]. The host can answer messages from the client automatically
+//! from a script, report that its message queue is full to exercise the
+//! client's retry path, and delay the messages it sends to simulate latency.
+//! Messages that are not scripted are returned by [`TestHost::next`], so tests
+//! only need to handle the messages they are interested in.
+
+#![forbid(unsafe_code)]
+
+pub use vmbus_client::testing::check_message;
+pub use vmbus_client::testing::check_message_with_data;
+pub use vmbus_client::testing::in_msg;
+pub use vmbus_client::testing::parse_message;
+
+use futures::StreamExt;
+use pal_async::DefaultDriver;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use pal_async::timer::Instant;
+use pal_async::timer::PolledTimer;
+use pal_event::Event;
+use std::io;
+use std::io::IoSliceMut;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+use vmbus_async::async_dgram::AsyncRecv;
+use vmbus_client::PollPostMessage;
+use vmbus_client::SynicEventClient;
+use vmbus_client::VmbusClientBuilder;
+use vmbus_client::VmbusMessageSource;
+use vmbus_client::pacing::PostError;
+use vmbus_core::OutgoingMessage;
+use vmbus_core::protocol;
+use vmbus_core::protocol::ConnectionState;
+use vmbus_core::protocol::FeatureFlags;
+use vmbus_core::protocol::MessageHeader;
+use vmbus_core::protocol::MessageType;
+use zerocopy::FromBytes;
+
+type Responder = Box<dyn FnMut(&OutgoingMessage) -> Vec<Vec<u8>> + Send>;
+
+/// A builder for a [`TestHost`] and the client connected to it.
+pub struct TestHostBuilder {
+    busy_every: Option<u32>,
+    latency: Duration,
+    scripts: Vec<(MessageType, Responder)>,
+}
+
+impl Default for TestHostBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestHostBuilder {
+    /// Creates a builder for a host with no script, faults, or latency.
+    pub fn new() -> Self {
+        Self {
+            busy_every: None,
+            latency: Duration::ZERO,
+            scripts: Vec::new(),
+        }
+    }
+
+    /// Reports that the host's message queue is full for every `n`th attempt
+    /// by the client to post a message. The client is expected to retry.
+    pub fn busy_every(mut self, n: u32) -> Self {
+        assert!(n > 1, "every post would fail");
+        self.busy_every = Some(n);
+        self
+    }
+
+    /// Delays each message sent to the client by `latency`.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Responds to each message of type `message_type` from the client with
+    /// the messages returned by `f`, formatted with [`in_msg`]. Scripted
+    /// messages are not returned by [`TestHost::next`].
+    pub fn respond(
+        mut self,
+        message_type: MessageType,
+        f: impl 'static + Send + FnMut(&OutgoingMessage) -> Vec<Vec<u8>>,
+    ) -> Self {
+        self.scripts.push((message_type, Box::new(f)));
+        self
+    }
+
+    /// Scripts the host to accept every connection, negotiating
+    /// `feature_flags` and offering no channels.
+    pub fn accept_connections(self, feature_flags: FeatureFlags) -> Self {
+        self.respond(MessageType::INITIATE_CONTACT, move |_| {
+            vec![in_msg(
+                MessageType::VERSION_RESPONSE,
+                protocol::VersionResponse2 {
+                    version_response: protocol::VersionResponse {
+                        version_supported: 1,
+                        connection_state: ConnectionState::SUCCESSFUL,
+                        padding: 0,
+                        selected_version_or_connection_id: 0,
+                    },
+                    supported_features: feature_flags.into(),
+                },
+            )]
+        })
+        .respond(MessageType::REQUEST_OFFERS, |_| {
+            vec![in_msg(MessageType::ALL_OFFERS_DELIVERED, [0u8])]
+        })
+    }
+
+    /// Builds the host, returning it and a builder for a client connected to
+    /// it.
+    pub fn build(self, driver: &DefaultDriver) -> (TestHost, VmbusClientBuilder) {
+        let (to_client_send, to_client_recv) = mesh::channel();
+        let (posted_send, posted_recv) = mesh::channel();
+        let (messages_send, messages_recv) = mesh::channel();
+        let task = driver.spawn(
+            "vmbus-test-host",
+            run_host(
+                posted_recv,
+                messages_send,
+                to_client_send.clone(),
+                self.scripts,
+            ),
+        );
+        let client = VmbusClientBuilder::new(
+            driver,
+            NoopSynicEvents,
+            HostMessageSource {
+                recv: to_client_recv,
+                timer: PolledTimer::new(driver),
+                latency: self.latency,
+                pending: None,
+                paused: false,
+            },
+            HostPoster {
+                send: posted_send,
+                busy_every: self.busy_every,
+                attempts: 0,
+            },
+        );
+        let host = TestHost {
+            messages: messages_recv,
+            send: to_client_send,
+            _task: task,
+        };
+        (host, client)
+    }
+}
+
+/// A simulated host, created by [`TestHostBuilder`].
+pub struct TestHost {
+    messages: mesh::Receiver<OutgoingMessage>,
+    send: mesh::Sender<Vec<u8>>,
+    _task: Task<()>,
+}
+
+impl TestHost {
+    /// Returns the next message from the client that was not handled by the
+    /// script.
+    pub async fn next(&mut self) -> Option<OutgoingMessage> {
+        self.messages.next().await
+    }
+
+    /// Sends a message to the client, formatted with [`in_msg`].
+    pub fn send(&self, msg: Vec<u8>) {
+        self.send.send(msg);
+    }
+}
+
+async fn run_host(
+    mut posted: mesh::Receiver<OutgoingMessage>,
+    messages: mesh::Sender<OutgoingMessage>,
+    to_client: mesh::Sender<Vec<u8>>,
+    mut scripts: Vec<(MessageType, Responder)>,
+) {
+    while let Some(msg) = posted.next().await {
+        let message_type = MessageHeader::read_from_prefix(msg.data())
+            .ok()
+            .map(|(header, _)| header.message_type());
+        let script = scripts
+            .iter_mut()
+            .find(|(script_type, _)| Some(*script_type) == message_type);
+        if let Some((_, respond)) = script {
+            for response in respond(&msg) {
+                to_client.send(response);
+            }
+        } else {
+            messages.send(msg);
+        }
+    }
+}
+
+struct HostPoster {
+    send: mesh::Sender<OutgoingMessage>,
+    busy_every: Option<u32>,
+    attempts: u32,
+}
+
+impl PollPostMessage for HostPoster {
+    fn poll_post_message(
+        &mut self,
+        _cx: &mut Context<'_>,
+        _connection_id: u32,
+        _typ: u32,
+        msg: &[u8],
+    ) -> Poll<Result<(), PostError>> {
+        self.attempts = self.attempts.wrapping_add(1);
+        if self.busy_every.is_some_and(|n| self.attempts % n == 0) {
+            return Poll::Ready(Err(PostError::InsufficientBuffers));
+        }
+        let msg = OutgoingMessage::from_message(msg)
+            .map_err(|err| PostError::Other(io::Error::new(io::ErrorKind::InvalidInput, err)))?;
+        self.send.send(msg);
+        Poll::Ready(Ok(()))
+    }
+}
+
+struct NoopSynicEvents;
+
+impl SynicEventClient for NoopSynicEvents {
+    fn map_event(&self, _event_flag: u16, _event: &Event) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn unmap_event(&self, _event_flag: u16) {}
+
+    fn signal_event(&self, _connection_id: u32, _event_flag: u16) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+struct HostMessageSource {
+    recv: mesh::Receiver<Vec<u8>>,
+    timer: PolledTimer,
+    latency: Duration,
+    /// A received message and the time it is delivered.
+    pending: Option<(Vec<u8>, Instant)>,
+    paused: bool,
+}
+
+impl AsyncRecv for HostMessageSource {
+    fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        if self.pending.is_none() {
+            match self.recv.poll_recv(cx) {
+                Poll::Ready(Ok(msg)) => {
+                    self.pending = Some((msg, Instant::now() + self.latency));
+                }
+                // Once the host is gone, no more messages arrive.
+                Poll::Ready(Err(_)) | Poll::Pending => {
+                    return if self.paused {
+                        Poll::Ready(Ok(0))
+                    } else {
+                        Poll::Pending
+                    };
+                }
+            }
+        }
+
+        let (_, deadline) = self.pending.as_ref().unwrap();
+        if !self.latency.is_zero() {
+            ready!(self.timer.poll_until(cx, *deadline));
+        }
+        let (msg, _) = self.pending.take().unwrap();
+        let mut remaining = msg.as_slice();
+        let mut total_size = 0;
+        while !remaining.is_empty() && !bufs.is_empty() {
+            let size = bufs[0].len().min(remaining.len());
+            bufs[0][..size].copy_from_slice(&remaining[..size]);
+            remaining = &remaining[size..];
+            bufs = &mut bufs[1..];
+            total_size += size;
+        }
+        Poll::Ready(Ok(total_size))
+    }
+}
+
+impl VmbusMessageSource for HostMessageSource {
+    fn pause_message_stream(&mut self) {
+        self.paused = true;
+    }
+
+    fn resume_message_stream(&mut self) {
+        self.paused = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::Either;
+    use guid::Guid;
+    use pal_async::async_test;
+    use std::pin::pin;
+    use test_with_tracing::test;
+
+    #[async_test]
+    async fn test_scripted_connect(driver: DefaultDriver) {
+        let feature_flags = FeatureFlags::new().with_modify_connection(true);
+        let (_host, client) = TestHostBuilder::new()
+            .accept_connections(feature_flags)
+            .busy_every(2)
+            .latency(Duration::from_millis(10))
+            .build(&driver);
+        let mut client = client.build(&driver);
+        client.start();
+
+        let connection = client.connect(0, None, Guid::ZERO).await.unwrap();
+        assert_eq!(connection.version.feature_flags, feature_flags);
+        assert!(connection.offers.is_empty());
+    }
+
+    #[async_test]
+    async fn test_unscripted(driver: DefaultDriver) {
+        let (mut host, client) = TestHostBuilder::new().build(&driver);
+        let mut client = client.build(&driver);
+        client.start();
+
+        let connect = pin!(client.connect(0, None, Guid::ZERO));
+        let msg = match futures::future::select(connect, pin!(host.next())).await {
+            Either::Left(_) => panic!("connected without a response"),
+            Either::Right((msg, _)) => msg.unwrap(),
+        };
+        let (header, _) = MessageHeader::read_from_prefix(msg.data()).unwrap();
+        assert_eq!(header.message_type(), MessageType::INITIATE_CONTACT);
+    }
+}
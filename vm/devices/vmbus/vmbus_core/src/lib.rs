@@ -5,6 +5,7 @@
 #![forbid(unsafe_code)]
 
 pub mod protocol;
+pub mod trace;
 
 use futures::FutureExt;
 use futures::StreamExt;
@@ -36,6 +37,11 @@ pub fn new(t: T, s: S) -> Self {
     pub fn value(&self) -> Option<&T> {
         self.0.as_ref()
     }
+
+    /// Ends the stream without yielding any more items from the inner stream.
+    pub fn end(&mut self) {
+        self.0 = None;
+    }
 }
 
 impl<T: Clone, S: futures::Stream + Unpin> futures::Stream for TaggedStream<T, S>
@@ -432,6 +432,21 @@ pub fn as_hvsock_params_mut(&mut self) -> &mut HvsockUserDefinedParameters {
         )
         .expect("from bytes should not fail")
     }
+
+    /// Returns the channel performance hints, if present.
+    ///
+    /// This is only meaningful for offers whose user-defined data is not owned
+    /// by the device; see [`ChannelPerfHints`].
+    pub fn perf_hints(&self) -> Option<ChannelPerfHints> {
+        let hints = ChannelPerfHints::read_from_bytes(&self.0[PERF_HINTS_OFFSET..]).unwrap();
+        (hints.signature == PERF_HINTS_SIGNATURE && hints.version == PERF_HINTS_VERSION)
+            .then_some(hints)
+    }
+
+    /// Stores channel performance hints at the end of the user-defined data.
+    pub fn set_perf_hints(&mut self, hints: ChannelPerfHints) {
+        hints.write_to(&mut self.0[PERF_HINTS_OFFSET..]).unwrap();
+    }
 }
 
 impl Deref for UserDefinedData {
@@ -506,38 +521,8 @@ pub struct OfferChannel {
 
 impl OfferChannel {
     fn inspect_extra(&self, resp: &mut inspect::Response<'_>) {
-        // TODO: There doesn't exist a single crate that has all these interface
-        // IDs. Today they're defined in each individual crate, but we don't
-        // want to include all those crates as dependencies here.
-        //
-        // In the future, it might make sense to have a common protocol crate
-        // that has all of these defined, but for now just redefine the most
-        // common ones here. Add more as needed.
-        const SHUTDOWN_IC: Guid = guid::guid!("0e0b6031-5213-4934-818b-38d90ced39db");
-        const KVP_IC: Guid = guid::guid!("a9a0f4e7-5a45-4d96-b827-8a841e8c03e6");
-        const VSS_IC: Guid = guid::guid!("35fa2e29-ea23-4236-96ae-3a6ebacba440");
-        const TIMESYNC_IC: Guid = guid::guid!("9527e630-d0ae-497b-adce-e80ab0175caf");
-        const HEARTBEAT_IC: Guid = guid::guid!("57164f39-9115-4e78-ab55-382f3bd5422d");
-        const RDV_IC: Guid = guid::guid!("276aacf4-ac15-426c-98dd-7521ad3f01fe");
-
-        const INHERITED_ACTIVATION: Guid = guid::guid!("3375baf4-9e15-4b30-b765-67acb10d607b");
-
-        const NET: Guid = guid::guid!("f8615163-df3e-46c5-913f-f2d2f965ed0e");
-        const SCSI: Guid = guid::guid!("ba6163d9-04a1-4d29-b605-72e2ffb1dc7f");
-        const VPCI: Guid = guid::guid!("44c4f61d-4444-4400-9d52-802e27ede19f");
-
-        resp.field_with("interface_name", || match self.interface_id {
-            SHUTDOWN_IC => "shutdown_ic",
-            KVP_IC => "kvp_ic",
-            VSS_IC => "vss_ic",
-            TIMESYNC_IC => "timesync_ic",
-            HEARTBEAT_IC => "heartbeat_ic",
-            RDV_IC => "rdv_ic",
-            INHERITED_ACTIVATION => "inherited_activation",
-            NET => "net",
-            SCSI => "scsi",
-            VPCI => "vpci",
-            _ => "unknown",
+        resp.field_with("interface_name", || {
+            vmbus_interface_registry::interface_name(&self.interface_id)
         });
     }
 }
@@ -605,6 +590,57 @@ pub fn new(is_for_guest_accept: bool, is_for_guest_container: bool, silo_id: Gui
     }
 }
 
+/// Signature identifying [`ChannelPerfHints`] in an offer's user-defined data.
+pub const PERF_HINTS_SIGNATURE: u32 = u32::from_le_bytes(*b"PRFH");
+
+/// The current version of [`ChannelPerfHints`].
+pub const PERF_HINTS_VERSION: u16 = 1;
+
+/// The offset of [`ChannelPerfHints`] within the user-defined data.
+pub const PERF_HINTS_OFFSET: usize = size_of::<UserDefinedData>() - size_of::<ChannelPerfHints>();
+
+/// Advisory performance hints for a channel, stored in the last 16 bytes of
+/// the offer's user-defined data.
+///
+/// The host sets these to tell an enlightened guest driver how the channel is
+/// expected to be used, so that the driver can size its ring buffers and tune
+/// interrupt moderation. Guests that do not understand the hints ignore them,
+/// and a guest must not rely on them for correctness.
+///
+/// The hints are present only if `signature` is [`PERF_HINTS_SIGNATURE`] and
+/// `version` is [`PERF_HINTS_VERSION`]. A zero value in any hint field means
+/// there is no hint for that field. Pipe and hvsocket parameters occupy the
+/// start of the user-defined data, so the hints can coexist with them. Offers
+/// that set `enumerate_device_interface` without `named_pipe_mode` carry
+/// device-defined user data, so they never have hints and guests should not
+/// look for them.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct ChannelPerfHints {
+    pub signature: u32,
+    pub version: u16,
+    pub reserved: u16,
+    /// The target latency for a single request, in microseconds.
+    pub latency_us: u32,
+    /// The expected peak throughput, in megabytes per second.
+    pub throughput_mbps: u32,
+}
+
+impl ChannelPerfHints {
+    /// Returns hints with the given values, where zero means no hint.
+    pub fn new(latency_us: u32, throughput_mbps: u32) -> Self {
+        Self {
+            signature: PERF_HINTS_SIGNATURE,
+            version: PERF_HINTS_VERSION,
+            reserved: 0,
+            latency_us,
+            throughput_mbps,
+        }
+    }
+}
+
+const _: () = assert!(PERF_HINTS_OFFSET >= size_of::<HvsockUserDefinedParameters>());
+
 open_enum! {
     /// Possible values for the `PipeUserDefinedParameters::pipe_type` field.
     #[derive(IntoBytes, FromBytes, Immutable, KnownLayout)]
@@ -0,0 +1,201 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A compact, replayable trace format for vmbus control-plane messages.
+//!
+//! A trace file starts with [`TRACE_MAGIC`] and a little-endian `u32` format
+//! version, followed by one record per message: a direction byte, a
+//! little-endian `u16` length, and the raw message bytes, including the
+//! message header.
+//!
+//! openvmm records a trace of the VTL0 server with `--vmbus-message-trace`.
+//! A trace file is also a valid input for the `fuzz_vmbus_client_messages`
+//! fuzzer, which replays its host messages against a client, so traces can
+//! be added to its corpus and reproduced with `cargo xtask fuzz run`.
+
+use crate::protocol::MAX_MESSAGE_SIZE;
+use parking_lot::Mutex;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::sync::Arc;
+
+/// The magic value at the start of a trace file.
+pub const TRACE_MAGIC: [u8; 8] = *b"VMBTRACE";
+
+/// The current trace format version.
+pub const TRACE_VERSION: u32 = 1;
+
+/// The direction of a traced message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TraceDirection {
+    /// A message sent by the host (server) to the guest (client).
+    HostToGuest,
+    /// A message sent by the guest (client) to the host (server).
+    GuestToHost,
+}
+
+impl TraceDirection {
+    fn to_byte(self) -> u8 {
+        match self {
+            TraceDirection::HostToGuest => 0,
+            TraceDirection::GuestToHost => 1,
+        }
+    }
+
+    fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0 => Some(TraceDirection::HostToGuest),
+            1 => Some(TraceDirection::GuestToHost),
+            _ => None,
+        }
+    }
+}
+
+/// A single traced message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub direction: TraceDirection,
+    pub data: Vec<u8>,
+}
+
+/// Writes control-plane messages to a trace.
+pub struct TraceWriter {
+    writer: Box<dyn Write + Send>,
+}
+
+impl std::fmt::Debug for TraceWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TraceWriter").finish_non_exhaustive()
+    }
+}
+
+impl TraceWriter {
+    /// Creates a new trace writer, writing the trace header to `writer`.
+    pub fn new(mut writer: impl 'static + Write + Send) -> io::Result<Self> {
+        writer.write_all(&TRACE_MAGIC)?;
+        writer.write_all(&TRACE_VERSION.to_le_bytes())?;
+        Ok(Self {
+            writer: Box::new(writer),
+        })
+    }
+
+    /// Appends a message to the trace.
+    ///
+    /// Messages longer than [`MAX_MESSAGE_SIZE`] are truncated.
+    pub fn record(&mut self, direction: TraceDirection, data: &[u8]) -> io::Result<()> {
+        let data = &data[..data.len().min(MAX_MESSAGE_SIZE)];
+        self.writer.write_all(&[direction.to_byte()])?;
+        self.writer.write_all(&(data.len() as u16).to_le_bytes())?;
+        self.writer.write_all(data)?;
+        self.writer.flush()
+    }
+}
+
+/// An in-memory trace that can be read while it is being written.
+#[derive(Debug, Clone, Default)]
+pub struct TraceBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl TraceBuffer {
+    /// Creates a new, empty trace buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a writer that appends to this buffer.
+    pub fn writer(&self) -> TraceWriter {
+        TraceWriter::new(self.clone()).expect("writes to memory cannot fail")
+    }
+
+    /// Returns the raw trace written so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.lock().clone()
+    }
+
+    /// Reads the records written so far.
+    pub fn records(&self) -> io::Result<Vec<TraceRecord>> {
+        read_trace(self.0.lock().as_slice())
+    }
+}
+
+impl Write for TraceBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads all records from a trace written by [`TraceWriter`].
+pub fn read_trace(mut reader: impl Read) -> io::Result<Vec<TraceRecord>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+    let mut header = [0; 12];
+    reader.read_exact(&mut header)?;
+    if header[..8] != TRACE_MAGIC {
+        return Err(invalid("not a vmbus trace"));
+    }
+    if u32::from_le_bytes(header[8..].try_into().unwrap()) != TRACE_VERSION {
+        return Err(invalid("unsupported vmbus trace version"));
+    }
+    let mut records = Vec::new();
+    loop {
+        let mut direction = [0];
+        match reader.read_exact(&mut direction) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+        let direction =
+            TraceDirection::from_byte(direction[0]).ok_or_else(|| invalid("invalid direction"))?;
+        let mut len = [0; 2];
+        reader.read_exact(&mut len)?;
+        let len = u16::from_le_bytes(len) as usize;
+        if len > MAX_MESSAGE_SIZE {
+            return Err(invalid("message too large"));
+        }
+        let mut data = vec![0; len];
+        reader.read_exact(&mut data)?;
+        records.push(TraceRecord { direction, data });
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TraceBuffer;
+    use super::TraceDirection;
+    use super::TraceRecord;
+    use super::read_trace;
+
+    #[test]
+    fn test_round_trip() {
+        let buf = TraceBuffer::new();
+        let mut writer = buf.writer();
+        writer
+            .record(TraceDirection::GuestToHost, &[1, 2, 3, 4])
+            .unwrap();
+        writer.record(TraceDirection::HostToGuest, &[]).unwrap();
+
+        let data = buf.contents();
+        assert_eq!(
+            buf.records().unwrap(),
+            [
+                TraceRecord {
+                    direction: TraceDirection::GuestToHost,
+                    data: vec![1, 2, 3, 4],
+                },
+                TraceRecord {
+                    direction: TraceDirection::HostToGuest,
+                    data: Vec::new(),
+                },
+            ]
+        );
+
+        // Truncated records are an error.
+        assert!(read_trace(&data[..data.len() - 1]).is_err());
+        assert!(read_trace(&b"NOTTRACE\x01\0\0\0"[..]).is_err());
+    }
+}
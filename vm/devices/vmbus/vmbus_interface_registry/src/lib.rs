@@ -0,0 +1,247 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A registry of well-known VMBus interface IDs.
+//!
+//! Each device's protocol crate defines the interface ID it offers, but
+//! components that handle channels generically (the client, the server, and
+//! the relay) should not depend on every device crate just to give channels a
+//! readable name. This crate lists the known interfaces in one place so that
+//! names are consistent across all of them.
+
+#![forbid(unsafe_code)]
+
+use guid::Guid;
+
+/// The kind of channel an interface is normally offered as.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChannelKind {
+    /// A device channel.
+    Device {
+        /// If true, ring buffer packets contain pipe headers.
+        pipe_packets: bool,
+    },
+    /// An interface channel, with interface-specific user-defined data in the
+    /// offer.
+    Interface,
+    /// A pipe channel.
+    Pipe {
+        /// If true, the pipe uses message mode. Otherwise, it uses byte mode.
+        message_mode: bool,
+    },
+}
+
+/// A well-known VMBus interface.
+#[derive(Debug, Copy, Clone)]
+pub struct KnownInterface {
+    /// The interface ID, from the channel offer.
+    pub id: Guid,
+    /// The short name used for the interface in inspect output and logs.
+    pub name: &'static str,
+    /// A human-readable description of the interface.
+    pub description: &'static str,
+    /// The kind of channel the interface is offered as.
+    pub kind: ChannelKind,
+    /// Whether the device may offer subchannels.
+    pub subchannels: bool,
+}
+
+const fn known(
+    id: Guid,
+    name: &'static str,
+    description: &'static str,
+    kind: ChannelKind,
+    subchannels: bool,
+) -> KnownInterface {
+    KnownInterface {
+        id,
+        name,
+        description,
+        kind,
+        subchannels,
+    }
+}
+
+const DEVICE: ChannelKind = ChannelKind::Device {
+    pipe_packets: false,
+};
+const DEVICE_PIPE_PACKETS: ChannelKind = ChannelKind::Device { pipe_packets: true };
+const MESSAGE_PIPE: ChannelKind = ChannelKind::Pipe { message_mode: true };
+
+/// All known interfaces.
+pub const KNOWN_INTERFACES: &[KnownInterface] = &[
+    // Integration components
+    known(
+        guid::guid!("0e0b6031-5213-4934-818b-38d90ced39db"),
+        "shutdown_ic",
+        "shutdown integration component",
+        MESSAGE_PIPE,
+        false,
+    ),
+    known(
+        guid::guid!("a9a0f4e7-5a45-4d96-b827-8a841e8c03e6"),
+        "kvp_ic",
+        "key-value pair exchange integration component",
+        MESSAGE_PIPE,
+        false,
+    ),
+    known(
+        guid::guid!("35fa2e29-ea23-4236-96ae-3a6ebacba440"),
+        "vss_ic",
+        "volume shadow copy integration component",
+        MESSAGE_PIPE,
+        false,
+    ),
+    known(
+        guid::guid!("9527e630-d0ae-497b-adce-e80ab0175caf"),
+        "timesync_ic",
+        "time synchronization integration component",
+        MESSAGE_PIPE,
+        false,
+    ),
+    known(
+        guid::guid!("57164f39-9115-4e78-ab55-382f3bd5422d"),
+        "heartbeat_ic",
+        "heartbeat integration component",
+        MESSAGE_PIPE,
+        false,
+    ),
+    known(
+        guid::guid!("276aacf4-ac15-426c-98dd-7521ad3f01fe"),
+        "rdv_ic",
+        "remote desktop virtualization integration component",
+        MESSAGE_PIPE,
+        false,
+    ),
+    known(
+        guid::guid!("3375baf4-9e15-4b30-b765-67acb10d607b"),
+        "inherited_activation",
+        "inherited activation",
+        DEVICE,
+        false,
+    ),
+    // Synthetic devices
+    known(
+        guid::guid!("f8615163-df3e-46c5-913f-f2d2f965ed0e"),
+        "net",
+        "synthetic network adapter",
+        DEVICE,
+        true,
+    ),
+    known(
+        guid::guid!("ba6163d9-04a1-4d29-b605-72e2ffb1dc7f"),
+        "scsi",
+        "synthetic SCSI controller",
+        DEVICE,
+        true,
+    ),
+    known(
+        guid::guid!("32412632-86cb-44a2-9b5c-50d1417354f5"),
+        "ide-accel",
+        "IDE accelerator",
+        ChannelKind::Interface,
+        false,
+    ),
+    known(
+        guid::guid!("44c4f61d-4444-4400-9d52-802e27ede19f"),
+        "vpci",
+        "virtual PCI bus",
+        DEVICE,
+        false,
+    ),
+    known(
+        guid::guid!("f912ad6d-2b17-48ea-bd65-f927a61c7684"),
+        "keyboard",
+        "synthetic keyboard",
+        DEVICE,
+        false,
+    ),
+    known(
+        guid::guid!("cfa8b69e-5b4a-4cc0-b98b-8ba1a1f3f95a"),
+        "mouse",
+        "synthetic mouse",
+        DEVICE_PIPE_PACKETS,
+        false,
+    ),
+    known(
+        guid::guid!("da0a7802-e377-4aac-8e77-0558eb1073f8"),
+        "video",
+        "synthetic video",
+        DEVICE_PIPE_PACKETS,
+        false,
+    ),
+    known(
+        guid::guid!("8b60ccf6-709f-4c11-90b5-229c959a9e6a"),
+        "serial",
+        "synthetic serial port",
+        MESSAGE_PIPE,
+        false,
+    ),
+    known(
+        guid::guid!("c376c1c3-d276-48d2-90a9-c04748072c60"),
+        "vmbfs",
+        "VMBus file system",
+        DEVICE_PIPE_PACKETS,
+        false,
+    ),
+    known(
+        guid::guid!("6a051f25-56ba-4cb5-ad1c-fa8a2d06bcfd"),
+        "latency_probe",
+        "VMBus round-trip latency probe",
+        MESSAGE_PIPE,
+        false,
+    ),
+    known(
+        guid::guid!("3b6f2a39-8f35-4e0b-9c2f-5a4bbd1f7e61"),
+        "9p",
+        "9P2000.L file sharing",
+        MESSAGE_PIPE,
+        false,
+    ),
+    // Paravisor devices
+    known(
+        guid::guid!("8dedd1aa-9056-49e4-bfd6-1bf90dc38ef0"),
+        "get",
+        "guest emulation transport",
+        MESSAGE_PIPE,
+        false,
+    ),
+    known(
+        guid::guid!("427b03e7-4ceb-4286-b5fc-486f4a1dd439"),
+        "guest_crash",
+        "guest crash dump",
+        MESSAGE_PIPE,
+        false,
+    ),
+];
+
+/// Returns the known interface with the given ID.
+pub fn lookup(interface_id: &Guid) -> Option<&'static KnownInterface> {
+    KNOWN_INTERFACES.iter().find(|i| &i.id == interface_id)
+}
+
+/// Returns the name of the interface with the given ID, or `"unknown"`.
+pub fn interface_name(interface_id: &Guid) -> &'static str {
+    lookup(interface_id).map_or("unknown", |i| i.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_unique() {
+        let mut ids = HashSet::new();
+        let mut names = HashSet::new();
+        for interface in KNOWN_INTERFACES {
+            assert!(ids.insert(interface.id), "{}", interface.name);
+            assert!(names.insert(interface.name), "{}", interface.name);
+        }
+        assert_eq!(
+            interface_name(&guid::guid!("f8615163-df3e-46c5-913f-f2d2f965ed0e")),
+            "net"
+        );
+        assert_eq!(interface_name(&Guid::ZERO), "unknown");
+    }
+}
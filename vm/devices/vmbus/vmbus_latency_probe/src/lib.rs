@@ -0,0 +1,342 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A VMBus device that measures the round-trip latency of the VMBus path.
+//!
+//! The device periodically sends a probe to the guest, which echoes it back.
+//! Since each probe crosses the full path between the host and the guest,
+//! including any relay in between, the round-trip times reflect the health of
+//! that path. Percentiles of the most recent round-trip times are reported via
+//! inspect.
+
+#![forbid(unsafe_code)]
+
+pub mod resolver;
+
+use async_trait::async_trait;
+use futures::FutureExt;
+use inspect::Inspect;
+use inspect::InspectMut;
+use inspect_counters::Counter;
+use pal_async::timer::Instant;
+use pal_async::timer::PolledTimer;
+use std::collections::VecDeque;
+use std::time::Duration;
+use task_control::StopTask;
+use thiserror::Error;
+use vmbus_async::async_dgram::AsyncRecvExt;
+use vmbus_async::async_dgram::AsyncSendExt;
+use vmbus_async::pipe::MessagePipe;
+use vmbus_channel::RawAsyncChannel;
+use vmbus_channel::bus::ChannelType;
+use vmbus_channel::bus::OfferParams;
+use vmbus_channel::channel::ChannelOpenError;
+use vmbus_channel::gpadl_ring::GpadlRingMem;
+use vmbus_channel::simple::SaveRestoreSimpleVmbusDevice;
+use vmbus_channel::simple::SimpleVmbusDevice;
+use vmbus_latency_probe_protocol as protocol;
+use vmbus_ring::RingMem;
+use vmcore::save_restore::NoSavedState;
+use vmcore::vm_task::VmTaskDriver;
+use zerocopy::FromBytes;
+use zerocopy::IntoBytes;
+
+/// The number of recent round-trip times that percentiles are computed over.
+const WINDOW: usize = 1024;
+
+/// The time to wait for a probe to be echoed before counting it as lost.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error("pipe failed")]
+    PipeFailure(#[source] std::io::Error),
+    #[error("invalid response size {0}")]
+    InvalidResponseSize(usize),
+}
+
+/// Percentiles of recent round-trip times, in microseconds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Inspect)]
+pub struct Percentiles {
+    /// The number of round-trip times the percentiles were computed over.
+    pub samples: usize,
+    /// The median round-trip time.
+    pub p50_us: u64,
+    /// The 90th percentile round-trip time.
+    pub p90_us: u64,
+    /// The 99th percentile round-trip time.
+    pub p99_us: u64,
+    /// The longest round-trip time.
+    pub max_us: u64,
+}
+
+/// Round-trip time statistics.
+#[derive(Debug, Default, Inspect)]
+#[inspect(extra = "Self::inspect_extra")]
+pub struct LatencyStats {
+    probes_sent: Counter,
+    responses: Counter,
+    /// Probes that were not echoed before the timeout.
+    timeouts: Counter,
+    /// Responses to probes that had already timed out.
+    late_responses: Counter,
+    #[inspect(skip)]
+    recent: VecDeque<Duration>,
+}
+
+impl LatencyStats {
+    fn record(&mut self, rtt: Duration) {
+        self.responses.increment();
+        if self.recent.len() == WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(rtt);
+    }
+
+    /// Returns the percentiles of the recent round-trip times, or `None` if
+    /// no probes have been echoed yet.
+    pub fn percentiles(&self) -> Option<Percentiles> {
+        let mut sorted = self
+            .recent
+            .iter()
+            .map(|rtt| rtt.as_micros() as u64)
+            .collect::<Vec<_>>();
+        if sorted.is_empty() {
+            return None;
+        }
+        sorted.sort_unstable();
+        // Nearest-rank percentile.
+        let percentile = |p: usize| sorted[(sorted.len() * p).div_ceil(100) - 1];
+        Some(Percentiles {
+            samples: sorted.len(),
+            p50_us: percentile(50),
+            p90_us: percentile(90),
+            p99_us: percentile(99),
+            max_us: percentile(100),
+        })
+    }
+
+    fn inspect_extra(&self, resp: &mut inspect::Response<'_>) {
+        resp.field("rtt", self.percentiles());
+    }
+}
+
+/// The host side of the latency probe device.
+#[derive(InspectMut)]
+pub struct LatencyProbe {
+    #[inspect(skip)]
+    driver: VmTaskDriver,
+    interval: Duration,
+    stats: LatencyStats,
+    #[inspect(skip)]
+    sequence: u64,
+}
+
+impl LatencyProbe {
+    /// Creates a new device that sends a probe every `interval`.
+    pub fn new(driver: VmTaskDriver, interval: Duration) -> Self {
+        Self {
+            driver,
+            interval,
+            stats: LatencyStats::default(),
+            sequence: 0,
+        }
+    }
+
+    /// Returns the round-trip time statistics.
+    pub fn stats(&self) -> &LatencyStats {
+        &self.stats
+    }
+
+    fn new_runner(
+        &self,
+        channel: RawAsyncChannel<GpadlRingMem>,
+    ) -> Result<ProbeChannel, ChannelOpenError> {
+        let pipe = MessagePipe::new(channel)?;
+        Ok(ProbeChannel::new(pipe, PolledTimer::new(&self.driver)))
+    }
+}
+
+#[async_trait]
+impl SimpleVmbusDevice for LatencyProbe {
+    type Runner = ProbeChannel;
+    type SavedState = NoSavedState;
+
+    fn offer(&self) -> OfferParams {
+        OfferParams {
+            interface_name: "latency_probe".to_owned(),
+            interface_id: protocol::INTERFACE_ID,
+            instance_id: protocol::INSTANCE_ID,
+            channel_type: ChannelType::Pipe { message_mode: true },
+            ..Default::default()
+        }
+    }
+
+    fn inspect(&mut self, req: inspect::Request<'_>, _runner: Option<&mut ProbeChannel>) {
+        req.respond().merge(self);
+    }
+
+    fn open(
+        &mut self,
+        channel: RawAsyncChannel<GpadlRingMem>,
+        _guest_memory: guestmem::GuestMemory,
+    ) -> Result<Self::Runner, ChannelOpenError> {
+        self.new_runner(channel)
+    }
+
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        channel: &mut ProbeChannel,
+    ) -> Result<(), task_control::Cancelled> {
+        stop.until_stopped(async {
+            if let Err(err) = channel.process(self).await {
+                tracing::error!(
+                    error = &err as &dyn std::error::Error,
+                    "latency probe channel failed"
+                );
+            }
+        })
+        .await
+    }
+
+    fn supports_save_restore(
+        &mut self,
+    ) -> Option<
+        &mut dyn SaveRestoreSimpleVmbusDevice<SavedState = Self::SavedState, Runner = Self::Runner>,
+    > {
+        Some(self)
+    }
+}
+
+impl SaveRestoreSimpleVmbusDevice for LatencyProbe {
+    fn save_open(&mut self, _runner: &Self::Runner) -> Self::SavedState {
+        NoSavedState
+    }
+
+    fn restore_open(
+        &mut self,
+        NoSavedState: Self::SavedState,
+        channel: RawAsyncChannel<GpadlRingMem>,
+    ) -> Result<Self::Runner, ChannelOpenError> {
+        self.new_runner(channel)
+    }
+}
+
+/// The latency probe task.
+pub struct ProbeChannel<T: RingMem = GpadlRingMem> {
+    pipe: MessagePipe<T>,
+    timer: PolledTimer,
+}
+
+impl<T: RingMem + Unpin> ProbeChannel<T> {
+    fn new(pipe: MessagePipe<T>, timer: PolledTimer) -> Self {
+        Self { pipe, timer }
+    }
+
+    async fn process(&mut self, state: &mut LatencyProbe) -> Result<(), Error> {
+        let mut buf = [0; protocol::MAX_MESSAGE_SIZE];
+        loop {
+            self.timer.sleep(state.interval).await;
+
+            // Sequence numbers are not reset when the channel is reopened, so
+            // a response to a probe sent before a stop is not mistaken for a
+            // response to a later probe.
+            state.sequence = state.sequence.wrapping_add(1);
+            let probe = protocol::Probe {
+                sequence: state.sequence,
+            };
+            let sent = Instant::now();
+            self.pipe
+                .send(probe.as_bytes())
+                .await
+                .map_err(Error::PipeFailure)?;
+            state.stats.probes_sent.increment();
+
+            loop {
+                let n = futures::select! { // race semantics
+                    r = self.pipe.recv(&mut buf).fuse() => r.map_err(Error::PipeFailure)?,
+                    _ = self.timer.sleep_until(sent + PROBE_TIMEOUT).fuse() => {
+                        state.stats.timeouts.increment();
+                        break;
+                    }
+                };
+                if n == 0 {
+                    return Ok(());
+                }
+                let response = protocol::Probe::read_from_prefix(&buf[..n])
+                    .map_err(|_| Error::InvalidResponseSize(n))?
+                    .0; // TODO: zerocopy: map_err (https://github.com/microsoft/openvmm/issues/759)
+                if response.sequence == probe.sequence {
+                    state.stats.record(Instant::now() - sent);
+                    break;
+                }
+                state.stats.late_responses.increment();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use pal_async::task::Spawn;
+    use vmbus_async::pipe::connected_message_pipes;
+    use vmcore::vm_task::SingleDriverBackend;
+    use vmcore::vm_task::VmTaskDriverSource;
+
+    #[test]
+    fn test_percentiles() {
+        let mut stats = LatencyStats::default();
+        assert_eq!(stats.percentiles(), None);
+        for us in (1..=100).rev() {
+            stats.record(Duration::from_micros(us));
+        }
+        assert_eq!(
+            stats.percentiles(),
+            Some(Percentiles {
+                samples: 100,
+                p50_us: 50,
+                p90_us: 90,
+                p99_us: 99,
+                max_us: 100,
+            })
+        );
+
+        stats.record(Duration::from_micros(7));
+        assert_eq!(stats.percentiles().unwrap().samples, 101);
+        for _ in 0..WINDOW {
+            stats.record(Duration::from_micros(7));
+        }
+        let percentiles = stats.percentiles().unwrap();
+        assert_eq!(percentiles.samples, WINDOW);
+        assert_eq!(percentiles.max_us, 7);
+    }
+
+    #[async_test]
+    async fn test_echo(driver: DefaultDriver) {
+        let (host, mut guest) = connected_message_pipes(4096);
+        let _echo = driver.spawn("echo", async move {
+            let mut buf = [0; protocol::MAX_MESSAGE_SIZE];
+            loop {
+                let n = guest.recv(&mut buf).await.unwrap();
+                guest.send(&buf[..n]).await.unwrap();
+            }
+        });
+
+        let vm_driver = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone())).simple();
+        let mut state = LatencyProbe::new(vm_driver, Duration::from_millis(1));
+        let mut channel = ProbeChannel::new(host, PolledTimer::new(&driver));
+        let mut timer = PolledTimer::new(&driver);
+        futures::select! { // race semantics
+            _ = channel.process(&mut state).fuse() => panic!("probe task exited"),
+            _ = timer.sleep(Duration::from_millis(100)).fuse() => {}
+        }
+
+        assert!(state.stats.responses.get() > 0);
+        assert_eq!(state.stats.timeouts.get(), 0);
+        assert!(state.stats.percentiles().is_some());
+    }
+}
@@ -0,0 +1,36 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Provides a resolver for the latency probe device.
+
+use crate::LatencyProbe;
+use std::convert::Infallible;
+use vm_resource::ResolveResource;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::VmbusDeviceHandleKind;
+use vmbus_channel::resources::ResolveVmbusDeviceHandleParams;
+use vmbus_channel::resources::ResolvedVmbusDevice;
+use vmbus_channel::simple::SimpleDeviceWrapper;
+use vmbus_latency_probe_resources::LatencyProbeHandle;
+
+/// Resolver for the latency probe device.
+pub struct LatencyProbeResolver;
+
+declare_static_resolver! {
+    LatencyProbeResolver,
+    (VmbusDeviceHandleKind, LatencyProbeHandle),
+}
+
+impl ResolveResource<VmbusDeviceHandleKind, LatencyProbeHandle> for LatencyProbeResolver {
+    type Output = ResolvedVmbusDevice;
+    type Error = Infallible;
+
+    fn resolve(
+        &self,
+        resource: LatencyProbeHandle,
+        input: ResolveVmbusDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let device = LatencyProbe::new(input.driver_source.simple(), resource.interval);
+        Ok(SimpleDeviceWrapper::new(input.driver_source.simple(), device).into())
+    }
+}
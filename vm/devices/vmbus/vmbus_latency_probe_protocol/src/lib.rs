@@ -0,0 +1,35 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Protocol definitions for the VMBus latency probe device.
+//!
+//! The host periodically sends a [`Probe`] message over a message-mode pipe.
+//! The guest echoes every message back unmodified.
+
+#![forbid(unsafe_code)]
+
+use guid::Guid;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+// {6a051f25-56ba-4cb5-ad1c-fa8a2d06bcfd}
+/// VMBus interface type GUID.
+pub const INTERFACE_ID: Guid = guid::guid!("6a051f25-56ba-4cb5-ad1c-fa8a2d06bcfd");
+
+// {8b4adc13-a5a6-407f-a786-f2006247064c}
+/// VMBus instance GUID.
+pub const INSTANCE_ID: Guid = guid::guid!("8b4adc13-a5a6-407f-a786-f2006247064c");
+
+/// Maximum message size for all messages.
+pub const MAX_MESSAGE_SIZE: usize = 64;
+
+/// A probe sent by the host, to be echoed by the guest.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct Probe {
+    /// The probe's sequence number, used by the host to match a response to
+    /// its probe.
+    pub sequence: u64,
+}
@@ -0,0 +1,22 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resources for the VMBus latency probe device.
+
+#![forbid(unsafe_code)]
+
+use mesh::MeshPayload;
+use std::time::Duration;
+use vm_resource::ResourceId;
+use vm_resource::kind::VmbusDeviceHandleKind;
+
+/// A handle to a VMBus latency probe device.
+#[derive(MeshPayload)]
+pub struct LatencyProbeHandle {
+    /// The time between probes.
+    pub interval: Duration,
+}
+
+impl ResourceId<VmbusDeviceHandleKind> for LatencyProbeHandle {
+    const ID: &'static str = "vmbus_latency_probe";
+}
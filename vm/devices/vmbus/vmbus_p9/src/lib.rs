@@ -0,0 +1,236 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A VMBus device that shares host directories with the guest over the
+//! 9P2000.L protocol.
+//!
+//! This provides file sharing for guests that cannot use virtio-fs or
+//! virtio-9p, such as guests on hosts without virtio support. Each 9P message
+//! is carried in a single message on a message-mode pipe, so the negotiated
+//! message size is limited to the maximum pipe packet size.
+//!
+//! The device serves a table of exports, selected by the attach name the guest
+//! passes when mounting. Open files cannot be saved, so the channel is revoked
+//! and reoffered across save/restore.
+
+#![forbid(unsafe_code)]
+#![cfg(any(windows, target_os = "linux"))]
+
+pub mod resolver;
+
+use async_trait::async_trait;
+use guid::Guid;
+use inspect::InspectMut;
+use inspect_counters::Counter;
+use plan9::Plan9FileSystem;
+use task_control::StopTask;
+use thiserror::Error;
+use vmbus_async::async_dgram::AsyncRecvExt;
+use vmbus_async::async_dgram::AsyncSendExt;
+use vmbus_async::pipe::MessagePipe;
+use vmbus_channel::RawAsyncChannel;
+use vmbus_channel::bus::ChannelType;
+use vmbus_channel::bus::OfferParams;
+use vmbus_channel::channel::ChannelOpenError;
+use vmbus_channel::gpadl_ring::GpadlRingMem;
+use vmbus_channel::simple::SaveRestoreSimpleVmbusDevice;
+use vmbus_channel::simple::SimpleVmbusDevice;
+use vmbus_ring::RingMem;
+use vmcore::save_restore::NoSavedState;
+
+// {3b6f2a39-8f35-4e0b-9c2f-5a4bbd1f7e61}
+/// VMBus interface type GUID.
+pub const INTERFACE_ID: Guid = guid::guid!("3b6f2a39-8f35-4e0b-9c2f-5a4bbd1f7e61");
+
+// {d0e7c9a4-1b52-4a8e-8f6d-0c3e9f4b2a17}
+/// VMBus instance GUID.
+pub const INSTANCE_ID: Guid = guid::guid!("d0e7c9a4-1b52-4a8e-8f6d-0c3e9f4b2a17");
+
+/// The maximum size of a 9P message, in either direction.
+pub const MAX_MESSAGE_SIZE: u32 = vmbus_ring::MAXIMUM_PIPE_PACKET_SIZE as u32;
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error("pipe failed")]
+    PipeFailure(#[source] std::io::Error),
+    #[error("failed to process 9p message")]
+    Protocol(#[source] plan9::Error),
+}
+
+#[derive(Debug, Default, inspect::Inspect)]
+struct Stats {
+    messages: Counter,
+}
+
+/// The host side of the VMBus 9P device.
+#[derive(InspectMut)]
+pub struct VmbusPlan9Device {
+    #[inspect(skip)]
+    fs: Plan9FileSystem,
+    stats: Stats,
+}
+
+impl VmbusPlan9Device {
+    /// Creates a new device serving `fs`.
+    ///
+    /// `fs` should limit the message size to [`MAX_MESSAGE_SIZE`].
+    pub fn new(fs: Plan9FileSystem) -> Self {
+        Self {
+            fs,
+            stats: Stats::default(),
+        }
+    }
+
+    fn new_runner(
+        &self,
+        channel: RawAsyncChannel<GpadlRingMem>,
+    ) -> Result<Plan9Channel, ChannelOpenError> {
+        // A new channel is a new client, so none of the previous client's
+        // fids are valid.
+        self.fs.reset();
+        let pipe = MessagePipe::new(channel)?;
+        Ok(Plan9Channel::new(pipe))
+    }
+}
+
+#[async_trait]
+impl SimpleVmbusDevice for VmbusPlan9Device {
+    type Runner = Plan9Channel;
+    type SavedState = NoSavedState;
+
+    fn offer(&self) -> OfferParams {
+        OfferParams {
+            interface_name: "9p".to_owned(),
+            interface_id: INTERFACE_ID,
+            instance_id: INSTANCE_ID,
+            channel_type: ChannelType::Pipe { message_mode: true },
+            ..Default::default()
+        }
+    }
+
+    fn inspect(&mut self, req: inspect::Request<'_>, _runner: Option<&mut Plan9Channel>) {
+        req.respond().merge(self);
+    }
+
+    fn open(
+        &mut self,
+        channel: RawAsyncChannel<GpadlRingMem>,
+        _guest_memory: guestmem::GuestMemory,
+    ) -> Result<Self::Runner, ChannelOpenError> {
+        self.new_runner(channel)
+    }
+
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        channel: &mut Plan9Channel,
+    ) -> Result<(), task_control::Cancelled> {
+        stop.until_stopped(async {
+            if let Err(err) = channel.process(self).await {
+                tracing::error!(error = &err as &dyn std::error::Error, "9p channel failed");
+            }
+        })
+        .await
+    }
+
+    fn supports_save_restore(
+        &mut self,
+    ) -> Option<
+        &mut dyn SaveRestoreSimpleVmbusDevice<SavedState = Self::SavedState, Runner = Self::Runner>,
+    > {
+        None
+    }
+}
+
+/// The 9P channel task.
+pub struct Plan9Channel<T: RingMem = GpadlRingMem> {
+    pipe: MessagePipe<T>,
+    message: Vec<u8>,
+    response: Vec<u8>,
+}
+
+impl<T: RingMem + Unpin> Plan9Channel<T> {
+    fn new(pipe: MessagePipe<T>) -> Self {
+        Self {
+            pipe,
+            message: vec![0; MAX_MESSAGE_SIZE as usize],
+            response: vec![0; MAX_MESSAGE_SIZE as usize],
+        }
+    }
+
+    async fn process(&mut self, state: &mut VmbusPlan9Device) -> Result<(), Error> {
+        loop {
+            let n = self
+                .pipe
+                .recv(&mut self.message)
+                .await
+                .map_err(Error::PipeFailure)?;
+            if n == 0 {
+                return Ok(());
+            }
+            state.stats.messages.increment();
+            // Errors from individual requests are returned to the guest as
+            // Rlerror. Only a malformed message header fails here.
+            let len = state
+                .fs
+                .process_message(&self.message[..n], &mut self.response)
+                .map_err(Error::Protocol)?;
+            self.pipe
+                .send(&self.response[..len])
+                .await
+                .map_err(Error::PipeFailure)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use pal_async::task::Spawn;
+    use vmbus_async::pipe::connected_message_pipes;
+
+    const TVERSION: u8 = 100;
+    const RVERSION: u8 = 101;
+
+    fn tversion(msize: u32) -> Vec<u8> {
+        let version = b"9P2000.L";
+        let mut message = Vec::new();
+        message.extend_from_slice(&[0; 4]);
+        message.push(TVERSION);
+        message.extend_from_slice(&0xffffu16.to_le_bytes());
+        message.extend_from_slice(&msize.to_le_bytes());
+        message.extend_from_slice(&(version.len() as u16).to_le_bytes());
+        message.extend_from_slice(version);
+        let len = message.len() as u32;
+        message[..4].copy_from_slice(&len.to_le_bytes());
+        message
+    }
+
+    #[async_test]
+    async fn test_version_limited_to_pipe(driver: DefaultDriver) {
+        let dir = tempfile::tempdir().unwrap();
+        let fs = Plan9FileSystem::with_exports(
+            [("share", dir.path().to_str().unwrap())],
+            MAX_MESSAGE_SIZE,
+            false,
+        )
+        .unwrap();
+        let mut state = VmbusPlan9Device::new(fs);
+
+        let (host, mut guest) = connected_message_pipes(0x10000);
+        let _task = driver.spawn("9p", async move {
+            let mut channel = Plan9Channel::new(host);
+            channel.process(&mut state).await.unwrap();
+        });
+
+        guest.send(&tversion(256 * 1024)).await.unwrap();
+        let mut response = [0; 64];
+        let n = guest.recv(&mut response).await.unwrap();
+        assert_eq!(response[4], RVERSION);
+        let msize = u32::from_le_bytes(response[7..11].try_into().unwrap());
+        assert_eq!(msize, MAX_MESSAGE_SIZE);
+        assert_eq!(n, 11 + 2 + 8);
+    }
+}
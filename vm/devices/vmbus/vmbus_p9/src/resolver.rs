@@ -0,0 +1,47 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Provides a resolver for the VMBus 9P device.
+
+use crate::MAX_MESSAGE_SIZE;
+use crate::VmbusPlan9Device;
+use anyhow::Context;
+use plan9::Plan9FileSystem;
+use vm_resource::ResolveResource;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::VmbusDeviceHandleKind;
+use vmbus_channel::resources::ResolveVmbusDeviceHandleParams;
+use vmbus_channel::resources::ResolvedVmbusDevice;
+use vmbus_channel::simple::SimpleDeviceWrapper;
+use vmbus_p9_resources::VmbusPlan9Handle;
+
+/// Resolver for the VMBus 9P device.
+pub struct VmbusPlan9Resolver;
+
+declare_static_resolver! {
+    VmbusPlan9Resolver,
+    (VmbusDeviceHandleKind, VmbusPlan9Handle),
+}
+
+impl ResolveResource<VmbusDeviceHandleKind, VmbusPlan9Handle> for VmbusPlan9Resolver {
+    type Output = ResolvedVmbusDevice;
+    type Error = anyhow::Error;
+
+    fn resolve(
+        &self,
+        resource: VmbusPlan9Handle,
+        input: ResolveVmbusDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let fs = Plan9FileSystem::with_exports(
+            resource
+                .exports
+                .iter()
+                .map(|export| (export.name.as_str(), export.path.as_str())),
+            MAX_MESSAGE_SIZE,
+            resource.debug,
+        )
+        .context("failed to open 9p exports")?;
+        let device = VmbusPlan9Device::new(fs);
+        Ok(SimpleDeviceWrapper::new(input.driver_source.simple(), device).into())
+    }
+}
@@ -0,0 +1,34 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resources for the VMBus 9P file sharing device.
+
+#![forbid(unsafe_code)]
+
+use mesh::MeshPayload;
+use vm_resource::ResourceId;
+use vm_resource::kind::VmbusDeviceHandleKind;
+
+/// A handle to a VMBus 9P file sharing device.
+#[derive(MeshPayload)]
+pub struct VmbusPlan9Handle {
+    /// The host directories to share.
+    pub exports: Vec<Plan9Export>,
+    /// Whether to log every 9P message.
+    pub debug: bool,
+}
+
+/// A host directory shared with the guest.
+#[derive(MeshPayload)]
+pub struct Plan9Export {
+    /// The name the guest passes as the attach name (`aname`) to mount this
+    /// export. The export with an empty name, if any, is mounted for any name
+    /// that does not match another export.
+    pub name: String,
+    /// The host path of the directory.
+    pub path: String,
+}
+
+impl ResourceId<VmbusDeviceHandleKind> for VmbusPlan9Handle {
+    const ID: &'static str = "vmbus_9p";
+}
@@ -30,6 +30,7 @@
 use inspect::InspectMut;
 use mesh::rpc::FailableRpc;
 use mesh::rpc::Rpc;
+use mesh::rpc::RpcError;
 use mesh::rpc::RpcSend;
 use pal_async::driver::SpawnDriver;
 use pal_async::task::Spawn;
@@ -68,6 +69,7 @@
 use vmcore::interrupt::Interrupt;
 use vmcore::notify::Notify;
 use vmcore::notify::PolledNotify;
+use vmcore::synic::MonitorPageGpas;
 
 pub enum InterceptChannelRequest {
     Start,
@@ -77,8 +79,37 @@ pub enum InterceptChannelRequest {
     Offer(client::OfferInfo),
 }
 
+/// A request to the handler registered for an interface ID, which can claim
+/// host offers of that interface to terminate them in VTL2.
+pub enum InterceptInterfaceRequest {
+    /// Claims the offer. The handler responds with the channel for the device
+    /// that will handle the offer, or `None` to relay it to the guest as
+    /// usual.
+    Claim(Rpc<protocol::OfferChannel, Option<mesh::Sender<InterceptChannelRequest>>>),
+}
+
+/// Handles claim requests for an interface by claiming the first instance
+/// offered for `device` and declining any other instance, which is relayed to
+/// the guest as usual.
+pub async fn claim_first_instance(
+    mut recv: mesh::Receiver<InterceptInterfaceRequest>,
+    device: mesh::Sender<InterceptChannelRequest>,
+) {
+    let mut claimed = None;
+    while let Some(request) = recv.next().await {
+        match request {
+            InterceptInterfaceRequest::Claim(rpc) => rpc.handle_sync(|offer| {
+                (*claimed.get_or_insert(offer.instance_id) == offer.instance_id)
+                    .then(|| device.clone())
+            }),
+        }
+    }
+}
+
+// Channel interrupt redirection is only needed to relay interrupts for guests
+// that use the channel bitmap, so it is not required up front; opening a
+// channel for such a guest fails if the host does not support it.
 const REQUIRED_FEATURE_FLAGS: FeatureFlags = FeatureFlags::new()
-    .with_channel_interrupt_redirection(true)
     .with_guest_specified_signal_parameters(true)
     .with_modify_connection(true);
 
@@ -97,6 +128,11 @@ pub struct HostVmbusTransport {
 
 impl HostVmbusTransport {
     /// Create a new instance of the host vmbus relay.
+    ///
+    /// Offers whose instance ID is in `intercept_list` are sent to the
+    /// corresponding device instead of being relayed. Offers whose interface
+    /// ID is in `intercept_interfaces` are first offered to the corresponding
+    /// handler, which can claim them.
     pub async fn new(
         driver: impl SpawnDriver + Clone,
         control: Arc<VmbusServerControl>,
@@ -105,14 +141,9 @@ pub async fn new(
         vmbus_client: client::VmbusClientAccess,
         connection: client::ConnectResult,
         intercept_list: Vec<(Guid, mesh::Sender<InterceptChannelRequest>)>,
+        intercept_interfaces: Vec<(Guid, mesh::Sender<InterceptInterfaceRequest>)>,
     ) -> Result<Self> {
-        if connection.version.feature_flags & REQUIRED_FEATURE_FLAGS != REQUIRED_FEATURE_FLAGS {
-            anyhow::bail!(
-                "host must support required feature flags. \
-                 Required: {REQUIRED_FEATURE_FLAGS:?}, actual: {:?}.",
-                connection.version.feature_flags
-            );
-        }
+        check_version(&connection.version)?;
 
         let mut relay_task = RelayTask::new(
             Arc::new(driver.clone()),
@@ -124,6 +155,7 @@ pub async fn new(
         );
 
         relay_task.intercept_channels.extend(intercept_list);
+        relay_task.intercept_interfaces.extend(intercept_interfaces);
 
         for offer in connection.offers {
             relay_task.handle_offer(offer).await?;
@@ -162,6 +194,45 @@ pub async fn restore(&self, state: SavedState) -> Result<()> {
             .await
             .unwrap()
     }
+
+    /// Renegotiates the connection to the host, to pick up protocol versions
+    /// or feature flags that became available after host servicing without
+    /// rebooting the guest. Returns the newly negotiated version.
+    ///
+    /// This unloads `client`'s connection, which revokes its channels, and
+    /// connects again with the given parameters. The relayed channels are
+    /// revoked from the guest before the channels of the new connection are
+    /// offered, so the relay must be running.
+    pub async fn renegotiate(
+        &self,
+        client: &mut client::VmbusClient,
+        target_message_vp: u32,
+        monitor_page: Option<MonitorPageGpas>,
+        client_id: Guid,
+    ) -> Result<VersionInfo> {
+        client.unload().await;
+        let connection = client
+            .connect(target_message_vp, monitor_page, client_id)
+            .await
+            .context("failed to reconnect to the host")?;
+        let version = connection.version;
+        self.task_send
+            .call(TaskRequest::Reconnect, connection)
+            .await
+            .unwrap()?;
+        Ok(version)
+    }
+}
+
+fn check_version(version: &VersionInfo) -> Result<()> {
+    if version.feature_flags & REQUIRED_FEATURE_FLAGS != REQUIRED_FEATURE_FLAGS {
+        anyhow::bail!(
+            "host must support required feature flags. \
+             Required: {REQUIRED_FEATURE_FLAGS:?}, actual: {:?}.",
+            version.feature_flags
+        );
+    }
+    Ok(())
 }
 
 /// State needed to relay host-to-guest interrupts.
@@ -239,13 +310,16 @@ struct RelayChannel {
     /// Receives requests from the server.
     #[inspect(skip)]
     server_request_recv: mesh::Receiver<ChannelRequest>,
-    #[inspect(skip)]
+    /// Sends requests to the server. Inspected as the guest's view of the
+    /// channel.
+    #[inspect(rename = "guest", send = "ChannelServerRequest::Inspect")]
     server_request_send: mesh::Sender<ChannelServerRequest>,
     /// Closed when the channel has been revoked.
     #[inspect(skip)]
     revoke_recv: mesh::OneshotReceiver<()>,
-    /// Sends requests to the client
-    #[inspect(skip)]
+    /// Sends requests to the client. Inspected as the host's view of the
+    /// channel.
+    #[inspect(rename = "host", send = "client::ChannelRequest::Inspect")]
     request_send: mesh::Sender<client::ChannelRequest>,
     /// Indicates whether or not interrupts should be relayed. This is shared with the relay server
     /// connection, which sets this to true only if the guest uses the channel bitmap.
@@ -294,6 +368,7 @@ async fn handle_open_channel(&mut self, open_request: &OpenRequest) -> Result<()
                     open_data: open_request.open_data,
                     incoming_event,
                     use_vtl2_connection_id: false,
+                    redirect_interrupt: false,
                 },
             )
             .await?;
@@ -530,6 +605,7 @@ enum TaskRequest {
     Inspect(inspect::Deferred),
     Save(Rpc<(), SavedState>),
     Restore(Rpc<SavedState, Result<()>>),
+    Reconnect(Rpc<client::ConnectResult, Result<()>>),
     Start,
     Stop(Rpc<(), ()>),
 }
@@ -550,6 +626,14 @@ struct RelayTask {
     channel_workers: FuturesUnordered<Task<ChannelId>>,
     #[inspect(with = "|x| inspect::iter_by_key(x).map_value(|_| ())")]
     intercept_channels: HashMap<Guid, mesh::Sender<InterceptChannelRequest>>,
+    #[inspect(with = "|x| inspect::iter_by_key(x).map_value(|_| ())")]
+    intercept_interfaces: HashMap<Guid, mesh::Sender<InterceptInterfaceRequest>>,
+    /// Offers waiting for a handler to decide whether to claim them, by
+    /// instance ID, in the order they arrived.
+    #[inspect(with = "|x| inspect::iter_by_key(x).map_value(|x| x.len())")]
+    claiming: HashMap<Guid, Vec<client::OfferInfo>>,
+    #[inspect(skip)]
+    claim_requests: FuturesUnordered<ClaimRequestFuture>,
     use_interrupt_relay: Arc<AtomicBool>,
     #[inspect(skip)]
     server_response_send: mesh::Sender<ModifyRelayResponse>,
@@ -560,8 +644,21 @@ struct RelayTask {
     running: bool,
 }
 
-type HvsockRequestFuture =
-    Pin<Box<dyn Future<Output = (HvsockConnectRequest, Option<client::OfferInfo>)> + Sync + Send>>;
+type HvsockRequestFuture = Pin<
+    Box<dyn Future<Output = (HvsockConnectRequest, client::HvsockConnectResult)> + Sync + Send>,
+>;
+
+type ClaimRequestFuture = Pin<
+    Box<
+        dyn Future<
+                Output = (
+                    Guid,
+                    Result<Option<mesh::Sender<InterceptChannelRequest>>, RpcError>,
+                ),
+            > + Sync
+            + Send,
+    >,
+>;
 
 impl RelayTask {
     fn new(
@@ -580,6 +677,9 @@ fn new(
             channels: HashMap::new(),
             channel_workers: FuturesUnordered::new(),
             intercept_channels: HashMap::new(),
+            intercept_interfaces: HashMap::new(),
+            claiming: HashMap::new(),
+            claim_requests: FuturesUnordered::new(),
             use_interrupt_relay: Arc::new(AtomicBool::new(false)),
             server_response_send,
             hvsock_relay,
@@ -609,6 +709,9 @@ async fn handle_start(&mut self) {
     }
 
     async fn handle_stop(&mut self) {
+        // Settle pending claims so that every offer is either relayed or
+        // intercepted before the channels are stopped and saved.
+        self.finish_claims().await;
         if self.running {
             // Stop all the channels before the relay itself can stop.
             join_all(self.channels.values().map(|c| match c {
@@ -638,9 +741,37 @@ async fn handle_stop(&mut self) {
         }
     }
 
+    /// Handles an offer received from the client. If the offer's interface has
+    /// a handler, the offer is held until the handler decides whether to claim
+    /// it; otherwise it is added right away.
+    async fn handle_offer(&mut self, offer: client::OfferInfo) -> Result<()> {
+        let instance_id = offer.offer.instance_id;
+
+        // Keep later offers for the same instance, such as subchannels, behind
+        // the pending claim.
+        if let Some(offers) = self.claiming.get_mut(&instance_id) {
+            offers.push(offer);
+            return Ok(());
+        }
+
+        if !self.intercept_channels.contains_key(&instance_id)
+            && let Some(handler) = self.intercept_interfaces.get(&offer.offer.interface_id)
+        {
+            // Wait for the handler off this task, so that a slow handler does
+            // not hold up other offers and requests.
+            let claim = handler.call(InterceptInterfaceRequest::Claim, offer.offer);
+            self.claim_requests
+                .push(Box::pin(claim.map(move |result| (instance_id, result))));
+            self.claiming.insert(instance_id, vec![offer]);
+            return Ok(());
+        }
+
+        self.add_offer(offer).await
+    }
+
     /// Translates an offer received from the client to a server offer.
     /// Additionally, sets up all the appropriate channels.
-    async fn handle_offer(&mut self, offer: client::OfferInfo) -> Result<()> {
+    async fn add_offer(&mut self, offer: client::OfferInfo) -> Result<()> {
         let channel_id = offer.offer.channel_id.0;
 
         if let Some(intercept) = self.intercept_channels.get(&offer.offer.instance_id) {
@@ -676,7 +807,9 @@ async fn handle_offer(&mut self, offer: client::OfferInfo) -> Result<()> {
         };
 
         let params = OfferParamsInternal {
-            interface_name: "host relay".to_owned(),
+            interface_name: vmbus_interface_registry::lookup(&offer.offer.interface_id)
+                .map_or("host relay", |interface| interface.name)
+                .to_owned(),
             instance_id: offer.offer.instance_id,
             interface_id: offer.offer.interface_id,
             mmio_megabytes: offer.offer.mmio_megabytes,
@@ -693,6 +826,8 @@ async fn handle_offer(&mut self, offer: client::OfferInfo) -> Result<()> {
                 .with_confidential_ring_buffer(false)
                 .with_confidential_external_memory(false),
             user_defined: offer.offer.user_defined,
+            // The host delivers interrupts for relayed channels.
+            interrupt_coalescing: None,
         };
 
         let key = params.key();
@@ -747,6 +882,55 @@ async fn handle_offer(&mut self, offer: client::OfferInfo) -> Result<()> {
         Ok(())
     }
 
+    /// Completes a claim for the offers of `instance_id`. A claimed instance is
+    /// added to the intercept list, so its offers, and any later ones, go to
+    /// the handler's device. Otherwise the offers are relayed as usual.
+    async fn handle_claim_response(
+        &mut self,
+        instance_id: Guid,
+        result: Result<Option<mesh::Sender<InterceptChannelRequest>>, RpcError>,
+    ) {
+        let offers = self.claiming.remove(&instance_id).unwrap_or_default();
+        match result {
+            Ok(Some(intercept)) => {
+                tracing::info!(%instance_id, "intercepting host offer");
+                // The device missed the start request if the relay is
+                // already running.
+                if self.running {
+                    intercept.send(InterceptChannelRequest::Start);
+                }
+                self.intercept_channels.insert(instance_id, intercept);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                tracing::error!(
+                    err = &err as &dyn std::error::Error,
+                    %instance_id,
+                    "failed to claim host offer, relaying it instead"
+                );
+            }
+        }
+
+        for offer in offers {
+            let offer_channel = offer.offer;
+            if let Err(err) = self.add_offer(offer).await {
+                tracing::error!(
+                    error = err.as_ref() as &dyn std::error::Error,
+                    offer = ?offer_channel,
+                    "failed to add claimed offer"
+                );
+            }
+        }
+    }
+
+    /// Waits for all pending claims to complete.
+    async fn finish_claims(&mut self) {
+        while !self.claiming.is_empty() {
+            let (instance_id, result) = self.claim_requests.next().await.expect("claim pending");
+            self.handle_claim_response(instance_id, result).await;
+        }
+    }
+
     async fn handle_revoked(&mut self, channel_id: ChannelId) {
         // The task has already completed, so just remove the channel from the list.
         self.channels
@@ -808,16 +992,16 @@ fn handle_hvsock_request(&mut self, request: HvsockConnectRequest) {
         tracing::debug!(request = ?request, "received hvsock connect request");
         let fut = self.vmbus_client.connect_hvsock(request);
         self.hvsock_requests
-            .push(Box::pin(fut.map(move |offer| (request, offer))));
+            .push(Box::pin(fut.map(move |result| (request, result))));
     }
 
     async fn handle_hvsock_response(
         &mut self,
         request: HvsockConnectRequest,
-        offer: Option<client::OfferInfo>,
+        result: client::HvsockConnectResult,
     ) {
-        let success = if let Some(offer) = offer {
-            match self.handle_offer(offer).await {
+        let success = match result {
+            client::HvsockConnectResult::Connected(offer) => match self.handle_offer(offer).await {
                 Ok(()) => true,
                 Err(err) => {
                     tracing::error!(
@@ -827,9 +1011,16 @@ async fn handle_hvsock_response(
                     );
                     false
                 }
+            },
+            client::HvsockConnectResult::Failed(status) => {
+                tracing::debug!(?request, status, "host rejected hvsock request");
+                false
             }
-        } else {
-            false
+            client::HvsockConnectResult::TimedOut => {
+                tracing::warn!(?request, "hvsock request timed out");
+                false
+            }
+            client::HvsockConnectResult::Cancelled => false,
         };
         self.hvsock_relay
             .response_send
@@ -849,16 +1040,49 @@ async fn handle_offer_request(&mut self, request: client::OfferInfo) -> Result<(
         Ok(())
     }
 
+    /// Switches to a new connection to the host, made after the previous one
+    /// was unloaded, returning the receiver for its offers.
+    async fn handle_reconnect(
+        &mut self,
+        connection: client::ConnectResult,
+    ) -> Result<mesh::Receiver<client::OfferInfo>> {
+        check_version(&connection.version)?;
+
+        // The channels of the previous connection have all been revoked by
+        // the host. Settle their pending claims, then wait for the relayed
+        // channels to be revoked from the guest so that the new offers do not
+        // conflict with them. Intercepted channels are revoked by their
+        // devices.
+        self.finish_claims().await;
+        self.channels
+            .retain(|_, channel| matches!(channel, ChannelInfo::Relay(_)));
+        while !self.channels.is_empty() {
+            let channel_id = self
+                .channel_workers
+                .next()
+                .await
+                .expect("relayed channels have workers");
+            self.handle_revoked(channel_id).await;
+        }
+
+        tracing::info!(version = ?connection.version, "reconnected to host");
+        self.version = connection.version;
+        for offer in connection.offers {
+            self.handle_offer(offer).await?;
+        }
+        Ok(connection.offer_recv)
+    }
+
     async fn run(
         &mut self,
         server_recv: mesh::Receiver<vmbus_server::ModifyRelayRequest>,
-        mut offer_recv: mesh::Receiver<client::OfferInfo>,
+        mut offers: mesh::Receiver<client::OfferInfo>,
         mut task_recv: mesh::Receiver<TaskRequest>,
     ) -> Result<()> {
         let mut server_recv = server_recv.fuse();
         loop {
             let mut offer_recv =
-                OptionFuture::from(self.running.then(|| offer_recv.select_next_some()));
+                OptionFuture::from(self.running.then(|| offers.select_next_some()));
 
             futures::select! { // merge semantics
                 r = server_recv.select_next_some() => {
@@ -870,6 +1094,9 @@ async fn run(
                 r = self.hvsock_requests.select_next_some() => {
                     self.handle_hvsock_response(r.0, r.1).await;
                 }
+                r = self.claim_requests.select_next_some() => {
+                    self.handle_claim_response(r.0, r.1).await;
+                }
                 r = offer_recv => {
                     self.handle_offer_request(r.unwrap()).await?;
                 }
@@ -882,6 +1109,14 @@ async fn run(
                         TaskRequest::Restore(rpc) => rpc.handle(async |state|  {
                             self.handle_restore(state).await
                         }).await,
+                        TaskRequest::Reconnect(rpc) => {
+                            // Needed to avoid conflicting offers borrow.
+                            drop(offer_recv);
+                            rpc.handle(async |connection| {
+                                offers = self.handle_reconnect(connection).await?;
+                                Ok(())
+                            }).await
+                        }
                         TaskRequest::Start => self.handle_start().await,
                         TaskRequest::Stop(rpc) => rpc.handle(async |()| self.handle_stop().await).await,
                     }
@@ -893,3 +1128,397 @@ async fn run(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guestmem::GuestMemory;
+    use hvdef::Vtl;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use vmbus_channel::bus::OpenData;
+    use vmbus_client::testing::TestServer;
+    use vmbus_client::testing::check_message;
+    use vmbus_client::testing::in_msg;
+    use vmbus_client::testing::test_init_with;
+    use vmbus_core::protocol::MessageType;
+    use vmbus_core::protocol::OfferFlags;
+    use vmbus_core::protocol::UserDefinedData;
+    use vmbus_server::HvsockRelayChannel;
+    use vmbus_server::VmbusRelayChannel;
+    use vmbus_server::VmbusServer;
+    use vmcore::synic::EventPort;
+    use vmcore::synic::GuestEventPort;
+    use vmcore::synic::GuestMessagePort;
+    use vmcore::synic::HypervisorError;
+    use vmcore::synic::MessagePort;
+    use vmcore::synic::MonitorInfo;
+    use vmcore::synic::SynicPortAccess;
+    use zerocopy::FromZeros;
+
+    /// A synic for a vmbus server that no guest connects to.
+    struct NullSynic;
+
+    impl SynicPortAccess for NullSynic {
+        fn add_message_port(
+            &self,
+            _connection_id: u32,
+            _minimum_vtl: Vtl,
+            _port: Arc<dyn MessagePort>,
+        ) -> Result<Box<dyn Sync + Send>, vmcore::synic::Error> {
+            Ok(Box::new(()))
+        }
+
+        fn add_event_port(
+            &self,
+            _connection_id: u32,
+            _minimum_vtl: Vtl,
+            _port: Arc<dyn EventPort>,
+            _monitor_info: Option<MonitorInfo>,
+        ) -> Result<Box<dyn Sync + Send>, vmcore::synic::Error> {
+            Ok(Box::new(()))
+        }
+
+        fn new_guest_message_port(
+            &self,
+            _vtl: Vtl,
+            _vp: u32,
+            _sint: u8,
+        ) -> Result<Box<dyn GuestMessagePort>, HypervisorError> {
+            Err(HypervisorError("no guest".into()))
+        }
+
+        fn new_guest_event_port(
+            &self,
+            _port_id: u32,
+            _vtl: Vtl,
+            _vp: u32,
+            _sint: u8,
+            _flag: u16,
+            _monitor_info: Option<MonitorInfo>,
+        ) -> Result<Box<dyn GuestEventPort>, HypervisorError> {
+            Err(HypervisorError("no guest".into()))
+        }
+
+        fn prefer_os_events(&self) -> bool {
+            false
+        }
+    }
+
+    /// Sends an offer for each of `offers`, given as (interface ID, instance
+    /// ID, subchannel index), using channel IDs starting at zero.
+    fn send_offers(server: &mut TestServer, offers: &[(Guid, Guid, u16)]) {
+        for (i, &(interface_id, instance_id, subchannel_index)) in offers.iter().enumerate() {
+            server.send(in_msg(
+                MessageType::OFFER_CHANNEL,
+                protocol::OfferChannel {
+                    interface_id,
+                    instance_id,
+                    rsvd: [0; 4],
+                    flags: OfferFlags::new(),
+                    mmio_megabytes: 0,
+                    user_defined: UserDefinedData::new_zeroed(),
+                    subchannel_index,
+                    mmio_megabytes_optional: 0,
+                    channel_id: ChannelId(i as u32),
+                    monitor_id: 0,
+                    monitor_allocated: 0,
+                    is_dedicated: 1,
+                    connection_id: 0,
+                },
+            ));
+        }
+    }
+
+    /// Connects a client whose host sends `offers`, as for [`send_offers`].
+    async fn connect_with_offers(
+        driver: &DefaultDriver,
+        offers: &[(Guid, Guid, u16)],
+    ) -> (TestServer, client::VmbusClient, client::ConnectResult) {
+        let (mut server, mut client) = test_init_with(driver, |builder| {
+            builder.feature_flags(REQUIRED_FEATURE_FLAGS)
+        });
+        let connection = server
+            .connect_with_channels(&mut client, |server| send_offers(server, offers))
+            .await;
+        (server, client, connection)
+    }
+
+    fn vmbus_server(driver: &DefaultDriver) -> VmbusServer {
+        VmbusServer::builder(driver.clone(), Arc::new(NullSynic), GuestMemory::empty())
+            .build()
+            .unwrap()
+    }
+
+    #[async_test]
+    async fn test_claim_offers(driver: DefaultDriver) {
+        let interface_id = Guid::new_random();
+        let claimed = Guid::new_random();
+        let declined = Guid::new_random();
+        let other = Guid::new_random();
+        let (_host, client, connection) = connect_with_offers(
+            &driver,
+            &[
+                (interface_id, claimed, 0),
+                (interface_id, claimed, 1),
+                (interface_id, declined, 0),
+                (Guid::new_random(), other, 0),
+            ],
+        )
+        .await;
+
+        let vmbus = vmbus_server(&driver);
+        let mut task = RelayTask::new(
+            Arc::new(driver.clone()),
+            vmbus.control(),
+            mesh::channel().0,
+            HvsockRelayChannel::new().relay_half,
+            client.access().clone(),
+            connection.version,
+        );
+        let (claim_send, claim_recv) = mesh::channel();
+        task.intercept_interfaces.insert(interface_id, claim_send);
+
+        for offer in connection.offers {
+            task.handle_offer(offer).await.unwrap();
+        }
+
+        // Only the offer of the interface without a handler has been added;
+        // the subchannel waits behind its instance's claim.
+        assert_eq!(task.channels.len(), 1);
+        assert!(matches!(
+            task.channels[&ChannelId(3)],
+            ChannelInfo::Relay(_)
+        ));
+        assert_eq!(task.claiming[&claimed].len(), 2);
+        assert_eq!(task.claiming[&declined].len(), 1);
+
+        let (device_send, mut device_recv) = mesh::channel();
+        let _claimant = driver.spawn("claim", claim_first_instance(claim_recv, device_send));
+        task.finish_claims().await;
+
+        for channel_id in [0, 1] {
+            let InterceptChannelRequest::Offer(offer) = device_recv.try_recv().unwrap() else {
+                panic!("expected offer");
+            };
+            assert_eq!(offer.offer.channel_id, ChannelId(channel_id));
+            assert!(matches!(
+                task.channels[&ChannelId(channel_id)],
+                ChannelInfo::Intercept(id) if id == claimed
+            ));
+        }
+        device_recv.try_recv().unwrap_err();
+        assert!(matches!(
+            task.channels[&ChannelId(2)],
+            ChannelInfo::Relay(_)
+        ));
+        assert!(task.claiming.is_empty());
+    }
+
+    #[async_test]
+    async fn test_claim_does_not_block_relay(driver: DefaultDriver) {
+        let interface_id = Guid::new_random();
+        let instance_id = Guid::new_random();
+        let (_host, client, connection) =
+            connect_with_offers(&driver, &[(interface_id, instance_id, 0)]).await;
+
+        let vmbus = vmbus_server(&driver);
+        let (claim_send, mut claim_recv) = mesh::channel();
+        let transport = HostVmbusTransport::new(
+            driver.clone(),
+            vmbus.control(),
+            VmbusRelayChannel::new().relay_half,
+            HvsockRelayChannel::new().relay_half,
+            client.access().clone(),
+            connection,
+            Vec::new(),
+            vec![(interface_id, claim_send)],
+        )
+        .await
+        .unwrap();
+
+        // The relay keeps handling requests while the claim is pending.
+        let mut inspection = inspect::inspect(&format!("claiming/{instance_id}"), &transport);
+        inspection.resolve().await;
+        assert!(matches!(
+            inspection.results(),
+            inspect::Node::Value(inspect::Value {
+                kind: inspect::ValueKind::Unsigned(1),
+                ..
+            })
+        ));
+
+        let InterceptInterfaceRequest::Claim(rpc) = claim_recv.next().await.unwrap();
+        assert_eq!(rpc.input().instance_id, instance_id);
+        rpc.complete(None);
+
+        // Stopping settles the claim, relaying the declined offer.
+        transport.stop().await;
+        let mut inspection = inspect::inspect("claiming", &transport);
+        inspection.resolve().await;
+        assert!(matches!(inspection.results(), inspect::Node::Dir(entries) if entries.is_empty()));
+    }
+
+    /// Connects a client that negotiates only the features the relay requires,
+    /// and returns a relay channel task for its only offer.
+    async fn relay_channel(
+        driver: &DefaultDriver,
+        use_interrupt_relay: bool,
+    ) -> (TestServer, client::VmbusClient, RelayChannelTask) {
+        let (mut server, mut client) = test_init_with(driver, |builder| {
+            builder.feature_flags(REQUIRED_FEATURE_FLAGS)
+        });
+        let offer = server.get_channel(&mut client).await;
+        let task = RelayChannelTask {
+            driver: Arc::new(driver.clone()),
+            channel: RelayChannel {
+                channel_id: offer.offer.channel_id,
+                key: OfferKey {
+                    interface_id: offer.offer.interface_id,
+                    instance_id: offer.offer.instance_id,
+                    subchannel_index: offer.offer.subchannel_index,
+                },
+                relay_request_recv: mesh::channel().1,
+                server_request_recv: mesh::channel().1,
+                server_request_send: mesh::channel().0,
+                revoke_recv: offer.revoke_recv,
+                request_send: offer.request_send,
+                use_interrupt_relay: Arc::new(AtomicBool::new(use_interrupt_relay)),
+                interrupt_relay: None,
+                gpadls_tearing_down: FuturesUnordered::new(),
+                is_open: false,
+            },
+            running: true,
+        };
+        (server, client, task)
+    }
+
+    /// An open request as sent by the server, which chooses its own event flag
+    /// and connection ID for the channel.
+    fn open_request() -> OpenRequest {
+        OpenRequest::new(
+            OpenData {
+                target_vp: Some(0),
+                ring_offset: 1,
+                ring_gpadl_id: GpadlId(1),
+                event_flag: 5,
+                connection_id: 0x1005,
+                user_data: UserDefinedData::new_zeroed(),
+            },
+            Interrupt::null(),
+            FeatureFlags::new(),
+            OfferFlags::new(),
+        )
+    }
+
+    #[async_test]
+    async fn test_open_channel(driver: DefaultDriver) {
+        let (mut server, _client, mut task) = relay_channel(&driver, false).await;
+
+        let open = task.handle_open_channel(&open_request());
+        let host = async {
+            // The server's signal parameters are forwarded to the host, which
+            // signals the guest directly.
+            check_message(
+                server.next().await.unwrap(),
+                protocol::OpenChannel2 {
+                    open_channel: protocol::OpenChannel {
+                        channel_id: ChannelId(0),
+                        open_id: 1,
+                        ring_buffer_gpadl_id: GpadlId(1),
+                        target_vp: 0,
+                        downstream_ring_buffer_page_offset: 1,
+                        user_data: UserDefinedData::new_zeroed(),
+                    },
+                    connection_id: 0x1005,
+                    event_flag: 5,
+                    flags: Default::default(),
+                },
+            );
+            server.send(in_msg(
+                MessageType::OPEN_CHANNEL_RESULT,
+                protocol::OpenResult {
+                    channel_id: ChannelId(0),
+                    open_id: 1,
+                    status: protocol::STATUS_SUCCESS as u32,
+                },
+            ));
+        };
+
+        let (result, ()) = futures::future::join(open, host).await;
+        result.unwrap();
+        assert!(task.channel.is_open);
+        assert!(task.channel.interrupt_relay.is_none());
+    }
+
+    #[async_test]
+    async fn test_open_channel_interrupt_relay_unsupported(driver: DefaultDriver) {
+        let (_server, _client, mut task) = relay_channel(&driver, true).await;
+
+        // Relaying interrupts requires channel interrupt redirection, which the
+        // host did not negotiate.
+        task.handle_open_channel(&open_request()).await.unwrap_err();
+        assert!(!task.channel.is_open);
+    }
+
+    #[async_test]
+    async fn test_renegotiate(driver: DefaultDriver) {
+        let intercepted = Guid::new_random();
+        let relayed = Guid::new_random();
+        let offers = [
+            (Guid::new_random(), intercepted, 0),
+            (Guid::new_random(), relayed, 0),
+        ];
+        let (mut host, mut client, connection) = connect_with_offers(&driver, &offers).await;
+
+        let vmbus = vmbus_server(&driver);
+        let (device_send, mut device_recv) = mesh::channel();
+        let transport = HostVmbusTransport::new(
+            driver.clone(),
+            vmbus.control(),
+            VmbusRelayChannel::new().relay_half,
+            HvsockRelayChannel::new().relay_half,
+            client.access().clone(),
+            connection,
+            vec![(intercepted, device_send)],
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+        transport.start();
+        vmbus.start();
+        let InterceptChannelRequest::Offer(_) = device_recv.next().await.unwrap() else {
+            panic!("expected offer");
+        };
+        let InterceptChannelRequest::Start = device_recv.next().await.unwrap() else {
+            panic!("expected start");
+        };
+
+        // The host offers the same channels, with the same channel IDs, on the
+        // new connection. Offering the relayed one again fails unless the old
+        // one has been revoked from the guest first.
+        let renegotiate = transport.renegotiate(&mut client, 0, None, Guid::ZERO);
+        let host_side = async {
+            check_message(host.next().await.unwrap(), protocol::Unload {});
+            host.send(in_msg(
+                MessageType::UNLOAD_COMPLETE,
+                protocol::UnloadComplete {},
+            ));
+            host.accept_connect(|host| send_offers(host, &offers)).await;
+        };
+        let (version, ()) = futures::future::join(renegotiate, host_side).await;
+        assert_eq!(version.unwrap().feature_flags, REQUIRED_FEATURE_FLAGS);
+
+        let InterceptChannelRequest::Offer(offer) = device_recv.next().await.unwrap() else {
+            panic!("expected offer");
+        };
+        assert_eq!(offer.offer.instance_id, intercepted);
+
+        let mut inspection = inspect::inspect("channels", &transport);
+        inspection.resolve().await;
+        let inspect::Node::Dir(entries) = inspection.results() else {
+            panic!("expected channels");
+        };
+        assert_eq!(entries.len(), 2);
+    }
+}
@@ -45,6 +45,9 @@ pub async fn handle_restore(&mut self, state: SavedState) -> Result<()> {
             channels,
         } = state;
 
+        // The channels being restored may still be waiting on a claim.
+        self.finish_claims().await;
+
         self.use_interrupt_relay
             .store(use_interrupt_relay, Ordering::SeqCst);
 
@@ -484,6 +484,7 @@ async fn open_channel(
             },
             incoming_event: Some(event.clone()),
             use_vtl2_connection_id: true,
+            redirect_interrupt: false,
         };
 
         request_send
@@ -10,6 +10,9 @@
 use criterion::Throughput;
 use criterion::criterion_group;
 use criterion::criterion_main;
+use guestmem::GuestMemory;
+use guestmem::LockedPages;
+use guestmem::ranges::PagedRange;
 use safeatomic::AsAtomicBytes;
 use safeatomic::AtomicSliceOps;
 use std::hint::black_box;
@@ -23,7 +26,7 @@
 
 criterion_main!(benches);
 
-criterion_group!(benches, paged_ring_mem,);
+criterion_group!(benches, paged_ring_mem, in_place_read,);
 
 #[derive(Debug)]
 struct PageRefs<'a>(&'a [&'a [AtomicU8; PAGE_SIZE]]);
@@ -73,6 +76,50 @@ fn write_at(&self, addr: usize, data: &[u8]) {
     }
 }
 
+struct LockedRing(LockedPages);
+
+impl PagedMemory for LockedRing {
+    fn control(&self) -> &[AtomicU8; PAGE_SIZE] {
+        self.0.pages()[0]
+    }
+
+    fn data_page_count(&self) -> usize {
+        (self.0.pages().len() - 1) / 2
+    }
+
+    fn data(&self, page: usize) -> &[AtomicU8; PAGE_SIZE] {
+        self.0.pages()[page + 1]
+    }
+}
+
+/// A ring in guest memory, as used by a vmbus device.
+struct GuestRingMem {
+    ring: PagedRingMem<LockedRing>,
+    data_gpns: Vec<u64>,
+}
+
+impl RingMem for GuestRingMem {
+    fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    fn read_at(&self, addr: usize, data: &mut [u8]) {
+        self.ring.read_at(addr, data)
+    }
+
+    fn write_at(&self, addr: usize, data: &[u8]) {
+        self.ring.write_at(addr, data)
+    }
+
+    fn control(&self) -> &[AtomicU32; CONTROL_WORD_COUNT] {
+        self.ring.control()
+    }
+
+    fn data_gpns(&self) -> Option<&[u64]> {
+        Some(&self.data_gpns)
+    }
+}
+
 fn paged_ring_mem(c: &mut Criterion) {
     let mut pages = vec![[0u8; PAGE_SIZE]; 12];
     let pages: Vec<_> = pages
@@ -153,3 +200,37 @@ fn paged_ring_mem(c: &mut Criterion) {
     }
     group.finish();
 }
+
+/// Compares copying a payload out of the ring and then to its destination
+/// against reading it in place from guest memory.
+fn in_place_read(c: &mut Criterion) {
+    let gm = GuestMemory::allocate(13 * PAGE_SIZE);
+    let gpns: Vec<u64> = (0..13).chain(1..13).collect();
+    let mem = GuestRingMem {
+        ring: PagedRingMem::new(LockedRing(gm.lock_gpns(false, &gpns).unwrap())),
+        data_gpns: gpns[1..].to_vec(),
+    };
+
+    let mut staging = [0; 8192];
+    let mut dest = [0; 8192];
+
+    let mut group = c.benchmark_group("in_place_read");
+    for size in &[256usize, 8192] {
+        group
+            .throughput(Throughput::Bytes(*size as u64))
+            .bench_with_input(BenchmarkId::new("copy", size), size, |b, &i| {
+                b.iter(|| {
+                    mem.read_at(black_box(4088), &mut staging[..i]);
+                    black_box(&mut dest[..i]).copy_from_slice(&staging[..i]);
+                });
+            })
+            .bench_with_input(BenchmarkId::new("in_place", size), size, |b, &i| {
+                b.iter(|| {
+                    let range =
+                        PagedRange::new(black_box(4088), i, mem.data_gpns().unwrap()).unwrap();
+                    gm.read_range(&range, black_box(&mut dest[..i])).unwrap();
+                });
+            });
+    }
+    group.finish();
+}
@@ -23,6 +23,7 @@
 
 use crate::gparange::GpaRange;
 use guestmem::AccessError;
+use guestmem::GuestMemory;
 use guestmem::MemoryRead;
 use guestmem::MemoryWrite;
 use guestmem::ranges::PagedRange;
@@ -275,6 +276,18 @@ pub fn reader<'a, T: Ring>(&self, ring: &'a T) -> RingRangeReader<'a, T::Memory>
         }
     }
 
+    /// Returns the range's location in guest memory, so that it can be
+    /// accessed in place instead of copied out of the ring.
+    ///
+    /// Returns `None` if the ring is not backed by guest memory.
+    pub fn paged_range<'a, T: Ring>(&self, ring: &'a T) -> Option<PagedRange<'a>> {
+        let gpns = ring.mem().data_gpns()?;
+        Some(
+            PagedRange::new(self.off as usize, self.size as usize, gpns)
+                .expect("ring range is within the doubled data pages"),
+        )
+    }
+
     /// Returns the length of the range.
     pub fn len(&self) -> usize {
         self.size as usize
@@ -473,6 +486,19 @@ fn write_aligned(&self, addr: usize, data: &[u8]) {
 
     /// Returns the length of the ring in bytes.
     fn len(&self) -> usize;
+
+    /// Returns the guest page numbers backing the data portion of the ring,
+    /// listed twice so that any range wrapping (once) at the end of the ring
+    /// is contiguous, or `None` if the ring is not backed by guest memory.
+    fn data_gpns(&self) -> Option<&[u64]> {
+        None
+    }
+
+    /// Returns the guest memory that [`Self::data_gpns`] refers to, or `None`
+    /// if the ring is not backed by guest memory.
+    fn guest_memory(&self) -> Option<&GuestMemory> {
+        None
+    }
 }
 
 /// Implementation of `RingMem` for references. Useful for tests.
@@ -497,6 +523,14 @@ fn read_aligned(&self, addr: usize, data: &mut [u8]) {
     fn write_aligned(&self, addr: usize, data: &[u8]) {
         (*self).write_aligned(addr, data)
     }
+
+    fn data_gpns(&self) -> Option<&[u64]> {
+        (*self).data_gpns()
+    }
+
+    fn guest_memory(&self) -> Option<&GuestMemory> {
+        (*self).guest_memory()
+    }
 }
 
 #[derive(Debug)]
@@ -1331,6 +1365,7 @@ fn ring_free(size: u32, inp: u32, outp: u32) -> u32 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use guestmem::LockedPages;
 
     fn write_simple<T: RingMem>(out_ring: &mut OutgoingRing<T>, buf: &[u8]) -> Option<bool> {
         let mut outgoing = out_ring.outgoing().unwrap();
@@ -1425,4 +1460,84 @@ fn test_pending_send_size() {
         assert!(read_simple(&mut in_ring).1);
         assert!(!read_simple(&mut in_ring).1);
     }
+
+    /// A ring in guest memory: the control page at gpn 0, followed by the data
+    /// pages.
+    struct GuestRing {
+        ring: PagedRingMem<LockedRing>,
+        data_gpns: Vec<u64>,
+    }
+
+    struct LockedRing(LockedPages);
+
+    impl PagedMemory for LockedRing {
+        fn control(&self) -> &[AtomicU8; PAGE_SIZE] {
+            self.0.pages()[0]
+        }
+
+        fn data_page_count(&self) -> usize {
+            (self.0.pages().len() - 1) / 2
+        }
+
+        fn data(&self, page: usize) -> &[AtomicU8; PAGE_SIZE] {
+            self.0.pages()[page + 1]
+        }
+    }
+
+    impl RingMem for GuestRing {
+        fn len(&self) -> usize {
+            self.ring.len()
+        }
+
+        fn read_at(&self, addr: usize, data: &mut [u8]) {
+            self.ring.read_at(addr, data)
+        }
+
+        fn write_at(&self, addr: usize, data: &[u8]) {
+            self.ring.write_at(addr, data)
+        }
+
+        fn control(&self) -> &[AtomicU32; CONTROL_WORD_COUNT] {
+            self.ring.control()
+        }
+
+        fn data_gpns(&self) -> Option<&[u64]> {
+            Some(&self.data_gpns)
+        }
+    }
+
+    #[test]
+    fn test_paged_range_wrap() {
+        let gm = GuestMemory::allocate(5 * PAGE_SIZE);
+        let gpns: Vec<u64> = (0..5).chain(1..5).collect();
+        let rmem = GuestRing {
+            ring: PagedRingMem::new(LockedRing(gm.lock_gpns(false, &gpns).unwrap())),
+            data_gpns: gpns[1..].to_vec(),
+        };
+        let mut in_ring = IncomingRing::new(&rmem).unwrap();
+        let mut out_ring = OutgoingRing::new(&rmem).unwrap();
+
+        // Advance the ring so that the next packet's payload wraps from the
+        // last data page to the first.
+        for _ in 0..4 {
+            write_simple(&mut out_ring, &[0; 4000]).unwrap();
+            read_simple(&mut in_ring);
+        }
+        let p: Vec<u8> = (0..4000).map(|i| i as u8).collect();
+        write_simple(&mut out_ring, &p).unwrap();
+
+        let mut incoming = in_ring.incoming().unwrap();
+        let payload = in_ring.read(&mut incoming).unwrap().payload;
+        let range = payload.paged_range(&in_ring).unwrap();
+        // The payload follows a 16-byte descriptor after four 4024-byte
+        // packets.
+        assert_eq!(range.offset(), 16112 - 3 * PAGE_SIZE);
+        assert_eq!(range.len(), p.len());
+        assert_eq!(range.gpns(), &[4, 1]);
+
+        let mut buf = vec![0; p.len()];
+        gm.read_range(&range, &mut buf).unwrap();
+        assert_eq!(buf, p);
+        assert_eq!(payload.reader(&in_ring).read_all().unwrap(), p);
+    }
 }
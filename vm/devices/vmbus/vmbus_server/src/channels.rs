@@ -649,6 +649,9 @@ pub struct OfferParamsInternal {
     pub offer_order: Option<u64>,
     pub flags: OfferFlags,
     pub user_defined: UserDefinedData,
+    /// The minimum interval between host-to-guest interrupts, if they are
+    /// coalesced by the server.
+    pub interrupt_coalescing: Option<Duration>,
 }
 
 impl OfferParamsInternal {
@@ -684,6 +687,12 @@ fn from(value: OfferParams) -> Self {
             } => {
                 flags.set_enumerate_device_interface(true);
                 user_defined = interface_user_defined;
+                if value.perf_hints.is_some() {
+                    tracing::warn!(
+                        interface_name = %value.interface_name,
+                        "ignoring perf hints for interface channel"
+                    );
+                }
             }
             ChannelType::Pipe { message_mode } => {
                 flags.set_enumerate_device_interface(true);
@@ -710,6 +719,12 @@ fn from(value: OfferParams) -> Self {
             }
         };
 
+        if let Some(hints) = value.perf_hints {
+            if !matches!(value.channel_type, ChannelType::Interface { .. }) {
+                user_defined.set_perf_hints(hints.to_protocol());
+            }
+        }
+
         Self {
             interface_name: value.interface_name,
             instance_id: value.instance_id,
@@ -721,6 +736,7 @@ fn from(value: OfferParams) -> Self {
             offer_order: value.offer_order,
             user_defined,
             flags,
+            interrupt_coalescing: value.interrupt_coalescing,
         }
     }
 }
@@ -827,6 +843,7 @@ fn inspect_state(&self, resp: &mut inspect::Response<'_>) {
             .field("guest_specified_event_flag", event_flag)
             .field("guest_specified_connection_id", connection_id)
             .field("reserved_connection_target", reserved_target)
+            .field("interrupt_coalescing", self.offer.interrupt_coalescing)
             .binary("offer_flags", self.offer.flags.into_bits());
     }
 
@@ -1864,6 +1881,20 @@ pub fn offer_channel(&mut self, offer: OfferParamsInternal) -> Result<OfferId, O
         Ok(offer_id)
     }
 
+    /// Inspects a single channel, as it appears under `channels` when
+    /// inspecting the server.
+    pub fn inspect_channel(&self, offer_id: OfferId, req: inspect::Request<'_>) {
+        let channel = &self.inner.channels[offer_id];
+        let mut resp = req.respond();
+        channel.inspect_state(&mut resp);
+        if !matches!(channel.state, ChannelState::Revoked) {
+            resp.merge(inspect::adhoc(|req| {
+                self.notifier
+                    .inspect(self.inner.get_version(), offer_id, req)
+            }));
+        }
+    }
+
     /// Revokes a channel by ID.
     pub fn revoke_channel(&mut self, offer_id: OfferId) {
         let channel = &mut self.inner.channels[offer_id];
@@ -2669,6 +2669,39 @@ fn reset(&mut self) {
     }
 }
 
+#[test]
+fn test_offer_perf_hints() {
+    let hints = vmbus_channel::bus::PerfHints::new()
+        .with_latency(Duration::from_micros(50))
+        .with_throughput_mbps(2000);
+
+    let offer = OfferParamsInternal::from(OfferParams {
+        interface_name: "test".to_owned(),
+        channel_type: ChannelType::Pipe { message_mode: true },
+        perf_hints: Some(hints),
+        ..Default::default()
+    });
+    assert_eq!(
+        offer.user_defined.perf_hints(),
+        Some(protocol::ChannelPerfHints::new(50, 2000))
+    );
+    assert_eq!(
+        offer.user_defined.as_pipe_params().pipe_type,
+        protocol::PipeType::MESSAGE
+    );
+
+    // Interface channels own their user-defined data.
+    let offer = OfferParamsInternal::from(OfferParams {
+        interface_name: "test".to_owned(),
+        channel_type: ChannelType::Interface {
+            user_defined: UserDefinedData::new_zeroed(),
+        },
+        perf_hints: Some(hints),
+        ..Default::default()
+    });
+    assert_eq!(offer.user_defined.perf_hints(), None);
+}
+
 fn in_msg<T: IntoBytes + Immutable + KnownLayout>(
     message_type: protocol::MessageType,
     t: T,
@@ -6,6 +6,10 @@
 //! This supports the [hybrid vsock connection model][1] established by
 //! Firecracker, extended to support Hyper-V sockets as well.
 //!
+//! In-process host components can also listen for guest connections to a
+//! service ID directly with [`HvsockRelay::listen`], in which case the
+//! connections are not relayed to Unix sockets.
+//!
 //! [1]: <https://github.com/firecracker-microvm/firecracker/blob/7b2e87dc65fc45162303e5708b83c379cf1b0426/docs/vsock.md>
 
 use super::Guid;
@@ -13,19 +17,28 @@
 use crate::ring::RingMem;
 use anyhow::Context;
 use fs_err::PathExt;
+use futures::AsyncRead;
 use futures::AsyncReadExt;
+use futures::AsyncWrite;
 use futures::AsyncWriteExt;
 use futures::StreamExt;
 use futures_concurrency::stream::Merge;
 use mesh::CancelContext;
+use mesh::rpc::Rpc;
+use mesh::rpc::RpcSend;
 use pal_async::driver::SpawnDriver;
 use pal_async::socket::PolledSocket;
 use pal_async::task::Spawn;
 use pal_async::task::Task;
+use std::collections::HashMap;
+use std::collections::hash_map;
 use std::io::ErrorKind;
 use std::path::Path;
 use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
 use std::time::Duration;
 use unicycle::FuturesUnordered;
 use unix_socket::UnixListener;
@@ -34,6 +47,7 @@
 use vmbus_channel::bus::ChannelType;
 use vmbus_channel::bus::OfferParams;
 use vmbus_channel::bus::ParentBus;
+use vmbus_channel::gpadl_ring::GpadlRingMem;
 use vmbus_channel::offer::Offer;
 use vmbus_core::HvsockConnectRequest;
 use vmbus_core::HvsockConnectResult;
@@ -47,6 +61,7 @@ pub struct HvsockRelay {
 
 enum RelayRequest {
     AddTask(Task<()>),
+    Listen(Rpc<(Guid, mesh::Sender<HvsockStream>), ()>),
 }
 
 struct RelayInner {
@@ -74,6 +89,7 @@ pub fn new(
             inner: inner.clone(),
             tasks: Default::default(),
             hybrid_vsock_path,
+            listeners: HashMap::new(),
         };
 
         let (host_send, host_recv) = mesh::channel();
@@ -141,6 +157,85 @@ pub fn connect(
             recv.await?
         }
     }
+
+    /// Listens for guest connections to `service_id`.
+    ///
+    /// Guest connections to the service made after this returns are delivered
+    /// to the returned listener instead of being relayed to the hybrid vsock
+    /// path. Dropping the listener stops listening. If there is already a
+    /// listener for the service, it is replaced.
+    pub async fn listen(&self, service_id: Guid) -> anyhow::Result<HvsockListener> {
+        let (send, recv) = mesh::channel();
+        self.host_send
+            .call(RelayRequest::Listen, (service_id, send))
+            .await
+            .context("hvsock relay shut down")?;
+        Ok(HvsockListener { service_id, recv })
+    }
+}
+
+/// A host listener for guest hvsocket connections, from
+/// [`HvsockRelay::listen`].
+pub struct HvsockListener {
+    service_id: Guid,
+    recv: mesh::Receiver<HvsockStream>,
+}
+
+impl HvsockListener {
+    /// The service ID being listened on.
+    pub fn service_id(&self) -> Guid {
+        self.service_id
+    }
+
+    /// Waits for the next guest connection.
+    ///
+    /// Fails if the relay has been shut down.
+    pub async fn accept(&mut self) -> anyhow::Result<HvsockStream> {
+        self.recv.recv().await.context("hvsock relay shut down")
+    }
+}
+
+/// A guest hvsocket connection accepted by an [`HvsockListener`].
+pub struct HvsockStream {
+    pipe: BytePipe<GpadlRingMem>,
+    endpoint_id: Guid,
+    // Keep the offer alive until the pipe is closed.
+    _offer: Offer,
+}
+
+impl HvsockStream {
+    /// The endpoint ID the guest assigned to the connection.
+    pub fn endpoint_id(&self) -> Guid {
+        self.endpoint_id
+    }
+}
+
+impl AsyncRead for HvsockStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.pipe).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for HvsockStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.pipe).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_close(cx)
+    }
 }
 
 struct ListenerWorker {
@@ -330,6 +425,7 @@ struct HvsockRelayWorker {
     tasks: FuturesUnordered<Task<()>>,
     inner: Arc<RelayInner>,
     hybrid_vsock_path: Option<PathBuf>,
+    listeners: HashMap<Guid, mesh::Sender<HvsockStream>>,
 }
 
 impl HvsockRelayWorker {
@@ -359,6 +455,9 @@ enum Event {
                     RelayRequest::AddTask(task) => {
                         self.tasks.push(task);
                     }
+                    RelayRequest::Listen(rpc) => rpc.handle_sync(|(service_id, send)| {
+                        self.listeners.insert(service_id, send);
+                    }),
                 },
                 Event::TaskDone(()) => {}
             }
@@ -375,6 +474,36 @@ fn handle_connect_from_guest(&mut self, request: HvsockConnectRequest) {
             send: self.guest_send.clone(),
             request,
         };
+
+        if let hash_map::Entry::Occupied(entry) = self.listeners.entry(request.service_id) {
+            if entry.get().is_closed() {
+                entry.remove();
+            } else {
+                let listener = entry.get().clone();
+                let inner = self.inner.clone();
+                let task = self.inner.driver.spawn(
+                    format!(
+                        "hvsock listener accept {}:{}",
+                        request.service_id, request.endpoint_id
+                    ),
+                    async move {
+                        match inner.accept_guest_connect(pending).await {
+                            Ok(stream) => listener.send(stream),
+                            Err(err) => {
+                                tracelimit::error_ratelimited!(
+                                    request = ?&request,
+                                    err = err.as_ref() as &dyn std::error::Error,
+                                    "hvsock listener accept error"
+                                );
+                            }
+                        }
+                    },
+                );
+                self.tasks.push(task);
+                return;
+            }
+        }
+
         let (path, is_specific_path) = {
             if let Some(hybrid_vsock_path) = &self.hybrid_vsock_path {
                 (hybrid_vsock_path.to_owned(), false)
@@ -415,6 +544,51 @@ fn handle_connect_from_guest(&mut self, request: HvsockConnectRequest) {
 }
 
 impl RelayInner {
+    /// Accepts a guest connection request on behalf of an in-process
+    /// listener.
+    async fn accept_guest_connect(
+        &self,
+        pending: PendingConnection,
+    ) -> anyhow::Result<HvsockStream> {
+        let request = pending.request;
+        let mut offer = Offer::new(
+            self.driver.as_ref(),
+            self.vmbus.as_ref(),
+            OfferParams {
+                interface_name: "hvsocket".to_owned(),
+                instance_id: request.endpoint_id,
+                interface_id: request.service_id,
+                channel_type: ChannelType::HvSocket {
+                    is_connect: false,
+                    is_for_container: false,
+                    silo_id: Guid::ZERO,
+                },
+                ..Default::default()
+            },
+        )
+        .await
+        .context("failed to offer channel")?;
+
+        pending.done(true);
+
+        // Give the guest a few seconds to open the channel.
+        let channel = CancelContext::new()
+            .with_timeout(Duration::from_secs(5))
+            .until_cancelled(offer.wait_for_open(self.driver.as_ref()))
+            .await
+            .context("guest did not open hvsocket channel")??
+            .accept()
+            .channel;
+
+        let pipe = BytePipe::new(channel).context("failed to create vmbus pipe")?;
+        tracing::debug!(?request, "accepted hvsocket connection for host listener");
+        Ok(HvsockStream {
+            pipe,
+            endpoint_id: request.endpoint_id,
+            _offer: offer,
+        })
+    }
+
     async fn relay_guest_connect_to_host(
         &self,
         pending: PendingConnection,
@@ -606,19 +780,230 @@ async fn relay_connected<T: RingMem + Unpin>(
 
 #[cfg(test)]
 mod tests {
+    use super::HvsockRelay;
     use super::relay_connected;
+    use crate::HvsockRelayChannel;
     use crate::ring::FlatRingMem;
+    use async_trait::async_trait;
     use futures::AsyncReadExt;
     use futures::AsyncWriteExt;
+    use futures::StreamExt;
+    use guestmem::GuestMemory;
+    use guid::Guid;
+    use mesh::rpc::RpcSend;
     use pal_async::DefaultDriver;
     use pal_async::async_test;
     use pal_async::driver::Driver;
     use pal_async::socket::PolledSocket;
     use pal_async::task::Spawn;
     use pal_async::task::Task;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::task::Context;
+    use std::task::Poll;
+    use unix_socket::UnixListener;
     use unix_socket::UnixStream;
     use vmbus_async::pipe::BytePipe;
     use vmbus_async::pipe::connected_byte_pipes;
+    use vmbus_channel::ChannelClosed;
+    use vmbus_channel::RawAsyncChannel;
+    use vmbus_channel::SignalVmbusChannel;
+    use vmbus_channel::bus::ChannelRequest;
+    use vmbus_channel::bus::GpadlRequest;
+    use vmbus_channel::bus::OfferInput;
+    use vmbus_channel::bus::OfferResources;
+    use vmbus_channel::bus::OpenData;
+    use vmbus_channel::bus::OpenRequest;
+    use vmbus_channel::bus::ParentBus;
+    use vmbus_channel::gpadl::GpadlId;
+    use vmbus_channel::gpadl::GpadlMap;
+    use vmbus_channel::gpadl_ring::AlignedGpadlView;
+    use vmbus_channel::gpadl_ring::GpadlRingMem;
+    use vmbus_core::HvsockConnectRequest;
+    use vmbus_core::HvsockConnectResult;
+    use vmbus_core::protocol::UserDefinedData;
+    use vmbus_ring::IncomingRing;
+    use vmbus_ring::OutgoingRing;
+    use vmbus_ring::PAGE_SIZE;
+    use vmbus_ring::gparange::MultiPagedRangeBuf;
+    use vmcore::interrupt::Interrupt;
+    use vmcore::slim_event::SlimEvent;
+    use zerocopy::FromZeros;
+
+    const SERVICE_ID: Guid = guid::guid!("c6e1a4b6-3f1e-4a57-9d4b-2b0f6f1de5a1");
+
+    /// Pages per channel, split evenly between the two rings.
+    const RING_PAGES: usize = 4;
+    const MAX_CHANNELS: usize = 4;
+
+    #[derive(Clone)]
+    struct TestBus {
+        memory: GuestMemory,
+        offers: mesh::Sender<OfferInput>,
+    }
+
+    #[async_trait]
+    impl ParentBus for TestBus {
+        async fn add_child(&self, request: OfferInput) -> anyhow::Result<OfferResources> {
+            self.offers.send(request);
+            Ok(OfferResources::new(self.memory.clone(), None))
+        }
+
+        fn clone_bus(&self) -> Box<dyn ParentBus> {
+            Box::new(self.clone())
+        }
+
+        fn use_event(&self) -> bool {
+            false
+        }
+    }
+
+    struct GuestSignal {
+        event: Arc<SlimEvent>,
+        interrupt: Interrupt,
+    }
+
+    impl SignalVmbusChannel for GuestSignal {
+        fn signal_remote(&self) {
+            self.interrupt.deliver();
+        }
+
+        fn poll_for_signal(&self, cx: &mut Context<'_>) -> Poll<Result<(), ChannelClosed>> {
+            self.event.poll_wait(cx).map(Ok)
+        }
+    }
+
+    /// The guest side of the vmbus server, issuing hvsocket connect requests
+    /// and opening the channels the relay offers.
+    struct TestGuest {
+        memory: GuestMemory,
+        offers: mesh::Receiver<OfferInput>,
+        requests: mesh::Sender<HvsockConnectRequest>,
+        results: mesh::Receiver<HvsockConnectResult>,
+        next_channel: usize,
+    }
+
+    impl TestGuest {
+        fn new_relay(
+            driver: &DefaultDriver,
+            hybrid_vsock_path: Option<PathBuf>,
+        ) -> (HvsockRelay, Self) {
+            let memory = GuestMemory::allocate(MAX_CHANNELS * RING_PAGES * PAGE_SIZE);
+            let (offer_send, offers) = mesh::channel();
+            let bus = TestBus {
+                memory: memory.clone(),
+                offers: offer_send,
+            };
+            let channel = HvsockRelayChannel::new();
+            let relay = HvsockRelay::new(
+                driver.clone(),
+                Arc::new(bus),
+                channel.relay_half,
+                hybrid_vsock_path,
+                None,
+            )
+            .unwrap();
+            let guest = Self {
+                memory,
+                offers,
+                requests: channel.server_half.request_send,
+                results: channel.server_half.response_receive,
+                next_channel: 0,
+            };
+            (relay, guest)
+        }
+
+        /// Connects to `service_id` and opens the resulting channel, returning
+        /// the endpoint ID and the guest end of the connection.
+        async fn connect(&mut self, service_id: Guid) -> (Guid, BytePipe<GpadlRingMem>) {
+            let request = HvsockConnectRequest {
+                service_id,
+                endpoint_id: Guid::new_random(),
+                silo_id: Guid::ZERO,
+                hosted_silo_unaware: false,
+            };
+            self.requests.send(request);
+            let result = self.results.next().await.unwrap();
+            assert_eq!(result.endpoint_id, request.endpoint_id);
+            assert!(result.success);
+
+            let offer = self.offers.next().await.unwrap();
+            assert_eq!(offer.params.interface_id, service_id);
+            assert_eq!(offer.params.instance_id, request.endpoint_id);
+            (request.endpoint_id, self.open(offer).await)
+        }
+
+        async fn open(&mut self, offer: OfferInput) -> BytePipe<GpadlRingMem> {
+            assert!(self.next_channel < MAX_CHANNELS);
+            let first_page = self.next_channel * RING_PAGES;
+            self.next_channel += 1;
+
+            let gpadl_id = GpadlId(self.next_channel as u32);
+            let buf: Vec<u64> = std::iter::once((RING_PAGES * PAGE_SIZE) as u64)
+                .chain((first_page..first_page + RING_PAGES).map(|page| page as u64))
+                .collect();
+            let gpadl = GpadlRequest {
+                id: gpadl_id,
+                count: 1,
+                buf: buf.clone(),
+            };
+            assert!(
+                offer
+                    .request_send
+                    .call(ChannelRequest::Gpadl, gpadl)
+                    .await
+                    .unwrap()
+            );
+
+            let event = Arc::new(SlimEvent::new());
+            let interrupt = {
+                let event = event.clone();
+                Interrupt::from_fn(move || event.signal())
+            };
+            let open_request = OpenRequest {
+                open_data: OpenData {
+                    target_vp: Some(0),
+                    ring_offset: (RING_PAGES / 2) as u32,
+                    ring_gpadl_id: gpadl_id,
+                    event_flag: 1,
+                    connection_id: 1,
+                    user_data: UserDefinedData::new_zeroed(),
+                },
+                interrupt,
+                use_confidential_ring: false,
+                use_confidential_external_memory: false,
+            };
+            assert!(
+                offer
+                    .request_send
+                    .call(ChannelRequest::Open, open_request)
+                    .await
+                    .unwrap()
+            );
+
+            // The guest's outgoing ring is the host's incoming ring.
+            let gpadl_map = GpadlMap::new();
+            gpadl_map.add(
+                gpadl_id,
+                MultiPagedRangeBuf::from_range_buffer(1, buf).unwrap(),
+            );
+            let gpadl = AlignedGpadlView::new(gpadl_map.view().map(gpadl_id).unwrap())
+                .ok()
+                .unwrap();
+            let (out_gpadl, in_gpadl) = gpadl.split((RING_PAGES / 2) as u32).ok().unwrap();
+            let channel = RawAsyncChannel {
+                in_ring: IncomingRing::new(GpadlRingMem::new(in_gpadl, &self.memory).unwrap())
+                    .unwrap(),
+                out_ring: OutgoingRing::new(GpadlRingMem::new(out_gpadl, &self.memory).unwrap())
+                    .unwrap(),
+                signal: Box::new(GuestSignal {
+                    event,
+                    interrupt: offer.event.clone(),
+                }),
+            };
+            BytePipe::new(channel).unwrap()
+        }
+    }
 
     fn setup_relay<T: Driver + Spawn>(
         driver: &T,
@@ -709,4 +1094,64 @@ async fn test_relay_forward_channel_shutdown(driver: DefaultDriver) {
         drop(s);
         task.await.unwrap();
     }
+
+    #[async_test]
+    async fn test_listen_accept(driver: DefaultDriver) {
+        let (relay, mut guest) = TestGuest::new_relay(&driver, None);
+        let mut listener = relay.listen(SERVICE_ID).await.unwrap();
+        assert_eq!(listener.service_id(), SERVICE_ID);
+
+        let (endpoint_id, mut c) = guest.connect(SERVICE_ID).await;
+        let mut s = listener.accept().await.unwrap();
+        assert_eq!(s.endpoint_id(), endpoint_id);
+
+        let d = b"abcd";
+        let mut v = [0; 4];
+
+        c.write_all(d).await.unwrap();
+        s.read_exact(&mut v).await.unwrap();
+        assert_eq!(&v, d);
+
+        s.write_all(d).await.unwrap();
+        c.read_exact(&mut v).await.unwrap();
+        assert_eq!(&v, d);
+    }
+
+    #[async_test]
+    async fn test_listen_dropped_falls_back(driver: DefaultDriver) {
+        let dir = tempfile::tempdir().unwrap();
+        let base_path = dir.path().join("hvsock");
+        let mut path = base_path.clone().into_os_string();
+        path.push(format!("_{SERVICE_ID}"));
+        let host_listener = UnixListener::bind(&path).unwrap();
+
+        let (relay, mut guest) = TestGuest::new_relay(&driver, Some(base_path));
+        let listener = relay.listen(SERVICE_ID).await.unwrap();
+        drop(listener);
+
+        // The connection is relayed to the hybrid vsock listener instead.
+        let (_, mut c) = guest.connect(SERVICE_ID).await;
+        let (s, _) = host_listener.accept().unwrap();
+        let mut s = PolledSocket::new(&driver, s).unwrap();
+
+        let d = b"abcd";
+        let mut v = [0; 4];
+        c.write_all(d).await.unwrap();
+        s.read_exact(&mut v).await.unwrap();
+        assert_eq!(&v, d);
+    }
+
+    #[async_test]
+    async fn test_listen_replaced(driver: DefaultDriver) {
+        let (relay, mut guest) = TestGuest::new_relay(&driver, None);
+        let mut old_listener = relay.listen(SERVICE_ID).await.unwrap();
+        let mut listener = relay.listen(SERVICE_ID).await.unwrap();
+
+        // The replaced listener no longer receives connections.
+        assert!(old_listener.accept().await.is_err());
+
+        let (endpoint_id, _c) = guest.connect(SERVICE_ID).await;
+        let s = listener.accept().await.unwrap();
+        assert_eq!(s.endpoint_id(), endpoint_id);
+    }
 }
@@ -0,0 +1,272 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Coalescing of host-to-guest channel interrupts.
+
+use anyhow::Context as _;
+use futures::task::AtomicWaker;
+use inspect::Inspect;
+use pal_async::driver::SpawnDriver;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use pal_async::timer::PolledTimer;
+use std::future::poll_fn;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::task::Poll;
+use std::time::Duration;
+use vmcore::interrupt::Interrupt;
+
+/// No interrupt has been delivered within the interval.
+const IDLE: u8 = 0;
+/// An interrupt has been delivered within the interval.
+const HOLDOFF: u8 = 1;
+/// An interrupt was signaled during the holdoff and must be delivered when it
+/// ends.
+const HOLDOFF_PENDING: u8 = 2;
+
+/// The first holdoff of a burst is `interval >> ADAPTIVE_SHIFT`.
+const ADAPTIVE_SHIFT: u32 = 3;
+
+/// Limits the rate of interrupts delivered to the guest for a channel.
+///
+/// An interrupt signaled while idle is delivered immediately and starts a
+/// holdoff. Interrupts signaled during the holdoff are coalesced into a single
+/// interrupt delivered when it ends, which starts another holdoff.
+///
+/// The holdoff adapts to the interrupt rate: the first holdoff of a burst is
+/// short, and each consecutive holdoff that coalesced an interrupt doubles it,
+/// up to `interval`. This keeps latency low for occasional interrupts while
+/// batching sustained ones, without ever delaying an interrupt by more than
+/// `interval`.
+///
+/// The interval can be changed via inspect while the channel is open. The
+/// change lasts until the channel is closed, and takes effect at the start of
+/// the next burst.
+#[derive(Inspect)]
+#[inspect(extra = "Self::inspect_extra")]
+pub(crate) struct CoalescedInterrupt {
+    #[inspect(flatten)]
+    shared: Arc<Shared>,
+    #[inspect(skip)]
+    _task: Task<()>,
+}
+
+#[derive(Inspect)]
+struct Shared {
+    #[inspect(skip)]
+    state: AtomicU8,
+    #[inspect(skip)]
+    waker: AtomicWaker,
+    #[inspect(skip)]
+    target: Interrupt,
+    /// The maximum holdoff, in microseconds.
+    #[inspect(skip)]
+    interval_us: AtomicU64,
+    signaled: AtomicU64,
+    delivered: AtomicU64,
+    /// The number of holdoffs that ended with a coalesced interrupt.
+    extended_holdoffs: AtomicU64,
+    /// The length of the current or most recent holdoff.
+    holdoff_us: AtomicU64,
+}
+
+impl Shared {
+    fn deliver(&self) {
+        self.delivered.fetch_add(1, Ordering::Relaxed);
+        self.target.deliver();
+    }
+
+    fn signal(&self) {
+        self.signaled.fetch_add(1, Ordering::Relaxed);
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            let new_state = match state {
+                IDLE => HOLDOFF,
+                HOLDOFF => HOLDOFF_PENDING,
+                _ => return,
+            };
+            match self.state.compare_exchange_weak(
+                state,
+                new_state,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(s) => state = s,
+            }
+        }
+        if state == IDLE {
+            self.deliver();
+            self.waker.wake();
+        }
+    }
+}
+
+impl CoalescedInterrupt {
+    /// Returns the coalescer and the interrupt to give to the device in place
+    /// of `target`.
+    pub fn new(
+        driver: &Arc<dyn SpawnDriver>,
+        target: Interrupt,
+        interval: Duration,
+    ) -> (Self, Interrupt) {
+        let shared = Arc::new(Shared {
+            state: AtomicU8::new(IDLE),
+            waker: AtomicWaker::new(),
+            target,
+            interval_us: AtomicU64::new(interval.as_micros() as u64),
+            signaled: AtomicU64::new(0),
+            delivered: AtomicU64::new(0),
+            extended_holdoffs: AtomicU64::new(0),
+            holdoff_us: AtomicU64::new(0),
+        });
+        let timer = PolledTimer::new(driver.as_ref());
+        let task = driver.spawn(
+            "vmbus-interrupt-coalescing",
+            run_holdoff(shared.clone(), timer),
+        );
+        let interrupt = Interrupt::from_fn({
+            let shared = shared.clone();
+            move || shared.signal()
+        });
+        (
+            Self {
+                shared,
+                _task: task,
+            },
+            interrupt,
+        )
+    }
+
+    fn inspect_extra(&self, resp: &mut inspect::Response<'_>) {
+        resp.field_mut_with("interval_us", |new_value| {
+            if let Some(new_value) = new_value {
+                let interval_us = new_value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|&n| n != 0)
+                    .context("expected a nonzero number of microseconds")?;
+                self.shared
+                    .interval_us
+                    .store(interval_us, Ordering::Relaxed);
+            }
+            anyhow::Ok(self.shared.interval_us.load(Ordering::Relaxed))
+        })
+        // Interrupts saved by coalescing.
+        .field(
+            "coalesced",
+            self.shared
+                .signaled
+                .load(Ordering::Relaxed)
+                .saturating_sub(self.shared.delivered.load(Ordering::Relaxed)),
+        );
+    }
+}
+
+/// Ends each holdoff once it elapses, delivering any interrupt signaled during
+/// it.
+async fn run_holdoff(shared: Arc<Shared>, mut timer: PolledTimer) {
+    loop {
+        poll_fn(|cx| {
+            shared.waker.register(cx.waker());
+            if shared.state.load(Ordering::Acquire) == IDLE {
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        })
+        .await;
+
+        let interval = Duration::from_micros(shared.interval_us.load(Ordering::Relaxed));
+        let mut holdoff = interval / (1 << ADAPTIVE_SHIFT);
+        loop {
+            shared
+                .holdoff_us
+                .store(holdoff.as_micros() as u64, Ordering::Relaxed);
+            timer.sleep(holdoff).await;
+            if shared
+                .state
+                .compare_exchange(HOLDOFF, IDLE, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                break;
+            }
+            // An interrupt was signaled during the holdoff. Deliver it and
+            // start a longer holdoff, since interrupts are arriving quickly.
+            shared.state.store(HOLDOFF, Ordering::Release);
+            shared.extended_holdoffs.fetch_add(1, Ordering::Relaxed);
+            shared.deliver();
+            holdoff = (holdoff * 2).min(interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CoalescedInterrupt;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use pal_async::driver::SpawnDriver;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+    use vmcore::interrupt::Interrupt;
+
+    #[async_test]
+    async fn test_coalesce(driver: DefaultDriver) {
+        let driver: Arc<dyn SpawnDriver> = Arc::new(driver);
+        let (send, mut recv) = mesh::channel();
+        let (coalesced, interrupt) = CoalescedInterrupt::new(
+            &driver,
+            Interrupt::from_fn(move || send.send(())),
+            Duration::from_millis(10),
+        );
+
+        // The first interrupt is delivered immediately, and the rest are
+        // coalesced until the holdoff ends.
+        interrupt.deliver();
+        interrupt.deliver();
+        interrupt.deliver();
+        assert_eq!(coalesced.shared.delivered.load(Ordering::Relaxed), 1);
+        recv.recv().await.unwrap();
+        recv.recv().await.unwrap();
+        assert_eq!(coalesced.shared.signaled.load(Ordering::Relaxed), 3);
+        assert_eq!(coalesced.shared.delivered.load(Ordering::Relaxed), 2);
+        assert_eq!(
+            coalesced.shared.extended_holdoffs.load(Ordering::Relaxed),
+            1
+        );
+    }
+
+    #[async_test]
+    async fn test_update_interval(driver: DefaultDriver) {
+        let driver: Arc<dyn SpawnDriver> = Arc::new(driver);
+        let (send, mut recv) = mesh::channel();
+        let (coalesced, interrupt) = CoalescedInterrupt::new(
+            &driver,
+            Interrupt::from_fn(move || send.send(())),
+            Duration::from_millis(10),
+        );
+
+        let value = inspect::update("interval_us", "80000", &coalesced)
+            .await
+            .unwrap();
+        assert!(matches!(value.kind, inspect::ValueKind::Unsigned(80000)));
+        inspect::update("interval_us", "0", &coalesced)
+            .await
+            .unwrap_err();
+        inspect::update("interval_us", "bogus", &coalesced)
+            .await
+            .unwrap_err();
+
+        // The next burst starts with a holdoff based on the new interval.
+        interrupt.deliver();
+        interrupt.deliver();
+        recv.recv().await.unwrap();
+        recv.recv().await.unwrap();
+        assert_eq!(coalesced.shared.holdoff_us.load(Ordering::Relaxed), 10000);
+    }
+}
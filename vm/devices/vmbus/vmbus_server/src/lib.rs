@@ -8,8 +8,10 @@
 pub mod channels;
 pub mod event;
 pub mod hvsock;
+mod interrupt_coalescing;
 mod monitor;
 mod proxyintegration;
+mod ring_integrity;
 #[cfg(test)]
 mod tests;
 
@@ -41,6 +43,7 @@
 use guestmem::GuestMemory;
 use hvdef::Vtl;
 use inspect::Inspect;
+use interrupt_coalescing::CoalescedInterrupt;
 use mesh::payload::Protobuf;
 use mesh::rpc::FailableRpc;
 use mesh::rpc::Rpc;
@@ -56,6 +59,8 @@
 #[cfg(windows)]
 pub use proxyintegration::ProxyServerInfo;
 use ring::PAGE_SIZE;
+use ring_integrity::RingIntegrityReport;
+pub use ring_integrity::RingPageHash;
 use std::collections::HashMap;
 use std::future;
 use std::future::Future;
@@ -75,6 +80,7 @@
 use vmbus_channel::bus::OpenData;
 use vmbus_channel::bus::OpenRequest;
 use vmbus_channel::bus::ParentBus;
+use vmbus_channel::bus::PerfHints;
 use vmbus_channel::bus::RestoreResult;
 use vmbus_channel::gpadl::GpadlMap;
 use vmbus_channel::gpadl_ring::AlignedGpadlView;
@@ -87,6 +93,8 @@
 use vmbus_core::VersionInfo;
 use vmbus_core::protocol;
 pub use vmbus_core::protocol::GpadlId;
+use vmbus_core::trace::TraceDirection;
+use vmbus_core::trace::TraceWriter;
 #[cfg(windows)]
 use vmbus_proxy::ProxyHandle;
 use vmbus_ring as ring;
@@ -142,6 +150,9 @@ pub struct VmbusServerBuilder<T: SpawnDriver> {
     send_messages_while_stopped: bool,
     channel_unstick_delay: Option<Duration>,
     use_absolute_channel_order: bool,
+    hash_ring_pages: bool,
+    message_trace: Option<TraceWriter>,
+    perf_hints: HashMap<Guid, PerfHints>,
 }
 
 #[derive(mesh::MeshPayload)]
@@ -285,6 +296,10 @@ pub struct SavedState {
     // unstick_channels() function will be called to mitigate the issue.
     #[mesh(2)]
     pub lost_synic_bug_fixed: bool,
+    // Hashes of the open channels' ring control pages, if enabled at save. These are verified
+    // after restore to detect guest memory corruption during servicing.
+    #[mesh(3)]
+    pub ring_page_hashes: Vec<RingPageHash>,
 }
 
 const MESSAGE_CONNECTION_ID: u32 = 1;
@@ -313,6 +328,9 @@ pub fn new(spawner: T, synic: Arc<dyn SynicPortAccess>, gm: GuestMemory) -> Self
             send_messages_while_stopped: false,
             channel_unstick_delay: Some(Duration::from_millis(100)),
             use_absolute_channel_order: false,
+            hash_ring_pages: false,
+            message_trace: None,
+            perf_hints: HashMap::new(),
         }
     }
 
@@ -448,6 +466,30 @@ pub fn use_absolute_channel_order(mut self, assign: bool) -> Self {
         self
     }
 
+    /// Sets whether to hash the ring buffer control pages of open channels at save.
+    ///
+    /// The hashes are stored in the saved state and verified after restore, with mismatches
+    /// reported via tracing and inspect. This is intended for debugging guest memory corruption
+    /// during servicing.
+    pub fn hash_ring_pages(mut self, enable: bool) -> Self {
+        self.hash_ring_pages = enable;
+        self
+    }
+
+    /// Records every control-plane message received from or sent to the guest to `trace`, for
+    /// debugging or for replay.
+    pub fn message_trace(mut self, trace: Option<TraceWriter>) -> Self {
+        self.message_trace = trace;
+        self
+    }
+
+    /// Sets the performance hints to offer to the guest for the channels with
+    /// the given instance ID, overriding any hints provided by the device.
+    pub fn channel_perf_hints(mut self, instance_id: Guid, hints: PerfHints) -> Self {
+        self.perf_hints.insert(instance_id, hints);
+        self
+    }
+
     /// Creates a new instance of the server.
     ///
     /// When the object is dropped, all channels will be closed and revoked
@@ -510,6 +552,7 @@ pub fn build(self) -> anyhow::Result<VmbusServer> {
             send: offer_send,
             use_event: self.synic.prefer_os_events(),
             force_confidential_external_memory: self.force_confidential_external_memory,
+            perf_hints: Arc::new(self.perf_hints),
         });
 
         let mut server = channels::Server::new(
@@ -588,6 +631,8 @@ pub fn build(self) -> anyhow::Result<VmbusServer> {
             shared_event_port: None,
             reset_done: Vec::new(),
             mnf_support: self.enable_mnf.then(MnfSupport::default),
+            driver: Arc::new(self.spawner.clone()),
+            message_trace: self.message_trace,
         };
 
         let (task_send, task_recv) = mesh::channel();
@@ -605,6 +650,8 @@ pub fn build(self) -> anyhow::Result<VmbusServer> {
             unstick_on_start: false,
             channel_unstickers: FuturesUnordered::new(),
             channel_unstick_delay: self.channel_unstick_delay,
+            hash_ring_pages: self.hash_ring_pages,
+            ring_integrity: None,
         };
 
         let task = self.spawner.spawn("vmbus server", async move {
@@ -727,6 +774,9 @@ struct ServerTask {
     unstick_on_start: bool,
     channel_unstickers: FuturesUnordered<Pin<Box<dyn Send + Future<Output = OfferInstanceId>>>>,
     channel_unstick_delay: Option<Duration>,
+    hash_ring_pages: bool,
+    /// The result of verifying the ring page hashes in the last restored saved state.
+    ring_integrity: Option<RingIntegrityReport>,
 }
 
 struct ServerTaskInner {
@@ -754,6 +804,8 @@ struct ServerTaskInner {
     /// Stores information needed to support MNF. If `None`, this server doesn't support MNF (in
     /// the case of OpenHCL, that means it will be handled by the relay host).
     mnf_support: Option<MnfSupport>,
+    driver: Arc<dyn SpawnDriver>,
+    message_trace: Option<TraceWriter>,
 }
 
 #[derive(Debug)]
@@ -786,6 +838,7 @@ struct Channel {
     // reopened, or the guest sends an unload message.
     reserved_state: ReservedState,
     unstick_state: ChannelUnstickState,
+    interrupt_coalescing: Option<Duration>,
 }
 
 struct ReservedState {
@@ -798,6 +851,7 @@ struct ChannelOpenState {
     _event_port: Box<dyn Send>,
     guest_event_port: Option<Box<dyn GuestEventPort>>,
     host_to_guest_interrupt: Interrupt,
+    interrupt_coalescing: Option<CoalescedInterrupt>,
 }
 
 impl ChannelOpenState {
@@ -821,6 +875,7 @@ impl ServerTask {
     fn handle_offer(&mut self, mut info: OfferInfo) -> anyhow::Result<()> {
         let key = info.params.key();
         let flags = info.params.flags;
+        let interrupt_coalescing = info.params.interrupt_coalescing;
 
         if self.inner.mnf_support.is_some() && self.inner.synic.monitor_support().is_some() {
             // If this server is handling MnF, ignore any relayed monitor IDs but still enable MnF
@@ -863,6 +918,7 @@ fn handle_offer(&mut self, mut info: OfferInfo) -> anyhow::Result<()> {
                     target: ConnectionTarget { vp: 0, sint: 0 },
                 },
                 unstick_state: ChannelUnstickState::None,
+                interrupt_coalescing,
             },
         );
 
@@ -888,6 +944,23 @@ fn handle_revoke(&mut self, id: OfferInstanceId) {
         }
     }
 
+    fn handle_inspect_channel(&mut self, id: OfferInstanceId, deferred: inspect::Deferred) {
+        // Once the channel has been revoked, its offer ID may be reused.
+        if self
+            .inner
+            .channels
+            .get(&id.offer_id)
+            .is_some_and(|channel| channel.seq == id.seq)
+        {
+            let server = self.server.with_notifier(&mut self.inner);
+            deferred.inspect(inspect::adhoc(|req| {
+                server.inspect_channel(id.offer_id, req)
+            }));
+        } else {
+            deferred.value("revoked");
+        }
+    }
+
     fn handle_response(
         &mut self,
         offer_id: OfferId,
@@ -1057,6 +1130,8 @@ async fn handle_request(&mut self, request: VmbusRequest) {
                         .field("running", self.inner.running)
                         .field("hvsock_requests", self.inner.hvsock_requests)
                         .field("channel_unstick_delay", self.channel_unstick_delay)
+                        .field("hash_ring_pages", self.hash_ring_pages)
+                        .field("ring_integrity", &self.ring_integrity)
                         .field_mut_with("unstick_channels", |v| {
                             let v: inspect::ValueKind = if let Some(v) = v {
                                 if v == "force" {
@@ -1081,6 +1156,11 @@ async fn handle_request(&mut self, request: VmbusRequest) {
             VmbusRequest::Save(rpc) => rpc.handle_sync(|()| SavedState {
                 server: self.server.save(),
                 lost_synic_bug_fixed: true,
+                ring_page_hashes: if self.hash_ring_pages {
+                    self.ring_page_hashes()
+                } else {
+                    Vec::new()
+                },
             }),
             VmbusRequest::Restore(rpc) => {
                 rpc.handle(async |state| {
@@ -1102,7 +1182,17 @@ async fn handle_request(&mut self, request: VmbusRequest) {
 
                     self.server
                         .with_notifier(&mut self.inner)
-                        .restore(state.server)
+                        .restore(state.server)?;
+
+                    // Check the ring pages before any device can resume and modify them.
+                    self.ring_integrity = (!state.ring_page_hashes.is_empty()).then(|| {
+                        ring_integrity::verify(
+                            &self.inner.gm,
+                            self.inner.private_gm.as_ref(),
+                            &state.ring_page_hashes,
+                        )
+                    });
+                    Ok(())
                 })
                 .await
             }
@@ -1186,6 +1276,12 @@ fn handle_tl_connect_result(&mut self, result: HvsockConnectResult) {
     }
 
     fn handle_synic_message(&mut self, message: SynicMessage) {
+        let _audit = alloc_audit::hot_path!("vmbus_server::handle_synic_message").enter();
+        trace_message(
+            &mut self.inner.message_trace,
+            TraceDirection::GuestToHost,
+            &message.data,
+        );
         match self
             .server
             .with_notifier(&mut self.inner)
@@ -1237,11 +1333,21 @@ async fn run(
             // Try to send any pending messages while the VM is running.
             let has_pending_messages = self.server.has_pending_messages();
             let message_port = self.inner.message_port.as_mut();
+            let message_trace = &mut self.inner.message_trace;
             let mut flush_pending_messages =
                 OptionFuture::from((running_not_resetting && has_pending_messages).then(|| {
                     poll_fn(|cx| {
                         self.server.poll_flush_pending_messages(|msg| {
-                            message_port.poll_post_message(cx, VMBUS_MESSAGE_TYPE, msg.data())
+                            let r =
+                                message_port.poll_post_message(cx, VMBUS_MESSAGE_TYPE, msg.data());
+                            if r.is_ready() {
+                                trace_message(
+                                    message_trace,
+                                    TraceDirection::HostToGuest,
+                                    msg.data(),
+                                );
+                            }
+                            r
                         })
                     })
                     .fuse()
@@ -1297,7 +1403,10 @@ async fn run(
                             }),
                             ChannelServerRequest::Revoke(rpc) => rpc.handle_sync(|_| {
                                 self.handle_revoke(id);
-                            })
+                            }),
+                            ChannelServerRequest::Inspect(deferred) => {
+                                self.handle_inspect_channel(id, deferred)
+                            }
                         },
                         (id, None) => self.handle_revoke(id),
                     }
@@ -1329,6 +1438,55 @@ async fn run(
         }
     }
 
+    /// Hashes the control pages of the rings of all open channels, for saving so that they can be
+    /// verified after restore.
+    fn ring_page_hashes(&self) -> Vec<RingPageHash> {
+        let Some(version) = self.server.get_version() else {
+            return Vec::new();
+        };
+
+        let mut hashes = Vec::new();
+        for channel in self.inner.channels.values() {
+            let ChannelState::Open(state) = &channel.state else {
+                continue;
+            };
+            let open_data = &state.open_params.open_data;
+            let Some((in_gpadl, out_gpadl)) = channel
+                .gpadls
+                .clone()
+                .view()
+                .map(open_data.ring_gpadl_id)
+                .ok()
+                .and_then(|gpadl| AlignedGpadlView::new(gpadl).ok())
+                .and_then(|aligned| aligned.split(open_data.ring_offset).ok())
+            else {
+                tracing::warn!(channel = %channel.key, "could not find ring to hash");
+                continue;
+            };
+
+            let gm = self.inner.get_gm_for_channel(version, channel);
+            let private = !std::ptr::eq(gm, &self.inner.gm);
+            for gpadl in [in_gpadl, out_gpadl] {
+                let gpn = gpadl.gpns()[0];
+                match ring_integrity::hash_page(gm, gpn) {
+                    Ok(crc) => hashes.push(RingPageHash {
+                        key: channel.key,
+                        gpn,
+                        private,
+                        crc,
+                    }),
+                    Err(err) => tracing::warn!(
+                        channel = %channel.key,
+                        gpn,
+                        error = &err as &dyn std::error::Error,
+                        "could not hash ring control page"
+                    ),
+                }
+            }
+        }
+        hashes
+    }
+
     /// Wakes the guest and optionally the host for every open channel. If `force`, always wakes
     /// them. If `!force`, only wake for rings that are in the state where a notification is
     /// expected.
@@ -1678,6 +1836,7 @@ fn inspect(&self, version: Option<VersionInfo>, offer_id: OfferId, req: inspect:
                 channel.gpadls.clone(),
                 &state.open_params.open_data,
             );
+            resp.field("interrupt_coalescing", &state.interrupt_coalescing);
         }
     }
 
@@ -1737,14 +1896,22 @@ fn send_message(&mut self, message: &OutgoingMessage, target: MessageTarget) ->
 
         // If this returns Pending, the channels module will queue the message and the ServerTask
         // main loop will try to send it again later.
-        matches!(
+        let sent = matches!(
             port.poll_post_message(
                 &mut std::task::Context::from_waker(std::task::Waker::noop()),
                 VMBUS_MESSAGE_TYPE,
                 message.data()
             ),
             Poll::Ready(())
-        )
+        );
+        if sent {
+            trace_message(
+                &mut self.message_trace,
+                TraceDirection::HostToGuest,
+                message.data(),
+            );
+        }
+        sent
     }
 
     fn notify_hvsock(&mut self, request: &HvsockConnectRequest) {
@@ -1771,6 +1938,19 @@ fn unload_complete(&mut self) {
     }
 }
 
+/// Records a message to `trace`, disabling tracing if the write fails.
+fn trace_message(trace: &mut Option<TraceWriter>, direction: TraceDirection, data: &[u8]) {
+    if let Some(writer) = trace {
+        if let Err(err) = writer.record(direction, data) {
+            tracing::error!(
+                error = &err as &dyn std::error::Error,
+                "failed to write message trace, disabling tracing"
+            );
+            *trace = None;
+        }
+    }
+}
+
 impl ServerTaskInner {
     fn open_channel(
         &mut self,
@@ -1811,7 +1991,8 @@ fn open_channel(
             (open_params.open_data.target_vp, open_params.event_flag)
         };
 
-        let (guest_event_port, interrupt) = if let Some(target_vp) = target_vp {
+        let (guest_event_port, interrupt, interrupt_coalescing) = if let Some(target_vp) = target_vp
+        {
             let (target_vtl, target_sint) = if open_params.flags.redirect_interrupt() {
                 (self.redirect_vtl, self.redirect_sint)
             } else {
@@ -1833,11 +2014,20 @@ fn open_channel(
                 open_params.event_flag,
             );
 
-            (Some(guest_event_port), interrupt)
+            let (interrupt_coalescing, interrupt) = match channel.interrupt_coalescing {
+                Some(interval) => {
+                    let (coalesced, interrupt) =
+                        CoalescedInterrupt::new(&self.driver, interrupt, interval);
+                    (Some(coalesced), interrupt)
+                }
+                None => (None, interrupt),
+            };
+
+            (Some(guest_event_port), interrupt, interrupt_coalescing)
         } else {
             // Use a dummy interrupt which does nothing, but make sure it has an event to avoid
             // proxy_integration from trying to wrap it.
-            (None, Interrupt::null_event())
+            (None, Interrupt::null_event(), None)
         };
 
         // Delete any previously reserved state.
@@ -1859,6 +2049,7 @@ fn open_channel(
             _event_port: event_port,
             guest_event_port,
             host_to_guest_interrupt: interrupt.clone(),
+            interrupt_coalescing,
         }));
         Ok((channel, interrupt))
     }
@@ -2052,6 +2243,7 @@ pub struct VmbusServerControl {
     send: mesh::Sender<OfferRequest>,
     use_event: bool,
     force_confidential_external_memory: bool,
+    perf_hints: Arc<HashMap<Guid, PerfHints>>,
 }
 
 impl VmbusServerControl {
@@ -2082,8 +2274,13 @@ pub async fn force_reset(&self) -> anyhow::Result<()> {
     }
 
     async fn offer(&self, request: OfferInput) -> anyhow::Result<OfferResources> {
+        let mut params = request.params;
+        if let Some(hints) = self.perf_hints.get(&params.instance_id) {
+            params.perf_hints = Some(*hints);
+        }
+
         let mut offer_info = OfferInfo {
-            params: request.params.into(),
+            params: params.into(),
             event: request.event,
             request_send: request.request_send,
             server_request_recv: request.server_request_recv,
@@ -595,8 +595,12 @@ async fn handle_offer_core(
             ((device_order.unwrap_or(NonZeroU32::MAX).get() as u64) << 32) | proxy_id as u64
         });
 
+        // Name the channel after its interface, as a local device would be.
+        let interface_name = vmbus_interface_registry::lookup(&interface_id)
+            .map_or("proxy", |interface| interface.name);
+
         let new_offer = OfferParams {
-            interface_name: "proxy".to_owned(),
+            interface_name: interface_name.to_owned(),
             instance_id,
             interface_id,
             mmio_megabytes: offer.MmioMegabytes,
@@ -609,6 +613,7 @@ async fn handle_offer_core(
                 .then(|| Duration::from_nanos(offer.InterruptLatencyIn100nsUnits * 100)),
             offer_order,
             allow_confidential_external_memory: false,
+            interrupt_coalescing: None,
         };
         let (request_send, request_recv) = mesh::channel();
         let (server_request_send, server_request_recv) = mesh::channel();
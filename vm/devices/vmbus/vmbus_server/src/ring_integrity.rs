@@ -0,0 +1,119 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Optional checking that ring buffer control pages are unchanged across save
+//! and restore, to help debug guest memory corruption during servicing.
+
+use guestmem::GuestMemory;
+use guestmem::GuestMemoryError;
+use inspect::Inspect;
+use mesh::payload::Protobuf;
+use vmbus_channel::bus::OfferKey;
+use vmbus_ring::PAGE_SIZE;
+
+/// The hash of a ring buffer control page, recorded at save.
+#[derive(Debug, Clone, Protobuf)]
+#[mesh(package = "vmbus.server")]
+pub struct RingPageHash {
+    #[mesh(1)]
+    pub key: OfferKey,
+    #[mesh(2)]
+    pub gpn: u64,
+    /// Whether the page was read through the private guest memory.
+    #[mesh(3)]
+    pub private: bool,
+    #[mesh(4)]
+    pub crc: u32,
+}
+
+pub(crate) fn hash_page(gm: &GuestMemory, gpn: u64) -> Result<u32, GuestMemoryError> {
+    let mut page = [0; PAGE_SIZE];
+    gm.read_at(gpn * PAGE_SIZE as u64, &mut page)?;
+    Ok(crc32fast::hash(&page))
+}
+
+/// The result of verifying the saved hashes after restore.
+#[derive(Debug, Inspect)]
+pub(crate) struct RingIntegrityReport {
+    checked: usize,
+    #[inspect(iter_by_index)]
+    mismatches: Vec<RingPageMismatch>,
+}
+
+#[derive(Debug, Inspect)]
+struct RingPageMismatch {
+    key: OfferKey,
+    #[inspect(hex)]
+    gpn: u64,
+    #[inspect(hex)]
+    expected: u32,
+    /// The hash of the page after restore, or `None` if it could not be read.
+    actual: Option<u32>,
+}
+
+/// Rehashes the pages in `hashes`, reporting any that changed since save.
+pub(crate) fn verify(
+    gm: &GuestMemory,
+    private_gm: Option<&GuestMemory>,
+    hashes: &[RingPageHash],
+) -> RingIntegrityReport {
+    let mut mismatches = Vec::new();
+    for hash in hashes {
+        let gm = if hash.private { private_gm } else { Some(gm) };
+        let actual = gm.and_then(|gm| hash_page(gm, hash.gpn).ok());
+        if actual != Some(hash.crc) {
+            tracing::error!(
+                key = %hash.key,
+                gpn = hash.gpn,
+                expected = hash.crc,
+                actual,
+                "ring control page changed across save and restore"
+            );
+            mismatches.push(RingPageMismatch {
+                key: hash.key,
+                gpn: hash.gpn,
+                expected: hash.crc,
+                actual,
+            });
+        }
+    }
+    if mismatches.is_empty() {
+        tracing::info!(count = hashes.len(), "ring control pages verified");
+    }
+    RingIntegrityReport {
+        checked: hashes.len(),
+        mismatches,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RingPageHash;
+    use super::hash_page;
+    use super::verify;
+    use guestmem::GuestMemory;
+    use vmbus_channel::bus::OfferKey;
+
+    #[test]
+    fn test_verify() {
+        let gm = GuestMemory::allocate(0x3000);
+        let key = OfferKey {
+            interface_id: guid::Guid::new_random(),
+            instance_id: guid::Guid::new_random(),
+            subchannel_index: 0,
+        };
+        let hashes = [1, 2].map(|gpn| RingPageHash {
+            key,
+            gpn,
+            private: false,
+            crc: hash_page(&gm, gpn).unwrap(),
+        });
+        assert!(verify(&gm, None, &hashes).mismatches.is_empty());
+
+        gm.write_at(0x2000, &[1]).unwrap();
+        let report = verify(&gm, None, &hashes);
+        assert_eq!(report.checked, 2);
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(report.mismatches[0].gpn, 2);
+    }
+}
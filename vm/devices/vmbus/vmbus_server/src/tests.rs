@@ -21,6 +21,7 @@
 use vmbus_channel::channel::offer_channel;
 use vmbus_core::protocol::ChannelId;
 use vmbus_core::protocol::VmbusMessage;
+use vmbus_core::trace::TraceBuffer;
 use vmcore::synic::MonitorInfo;
 use vmcore::synic::SynicMonitorAccess;
 use vmcore::synic::SynicPortAccess;
@@ -274,6 +275,8 @@ async fn restore(&self) {
 struct TestEnvBuilder {
     spawner: DefaultDriver,
     allow_allocated_monitor_pages: bool,
+    message_trace: Option<TraceWriter>,
+    perf_hints: Vec<(Guid, PerfHints)>,
 }
 
 impl TestEnvBuilder {
@@ -281,6 +284,8 @@ fn new(spawner: DefaultDriver) -> Self {
         Self {
             spawner,
             allow_allocated_monitor_pages: false,
+            message_trace: None,
+            perf_hints: Vec::new(),
         }
     }
 
@@ -289,6 +294,16 @@ fn allow_allocated_monitor_pages(mut self, allow: bool) -> Self {
         self
     }
 
+    fn message_trace(mut self, trace: TraceWriter) -> Self {
+        self.message_trace = Some(trace);
+        self
+    }
+
+    fn channel_perf_hints(mut self, instance_id: Guid, hints: PerfHints) -> Self {
+        self.perf_hints.push((instance_id, hints));
+        self
+    }
+
     fn build(self) -> TestEnv {
         let (message_send, message_recv) = mesh::channel();
         let synic = Arc::new(MockSynic::new(
@@ -297,8 +312,13 @@ fn build(self) -> TestEnv {
             self.allow_allocated_monitor_pages,
         ));
         let gm = GuestMemory::empty();
-        let vmbus = VmbusServerBuilder::new(self.spawner, synic.clone(), gm)
+        let mut vmbus = VmbusServerBuilder::new(self.spawner, synic.clone(), gm);
+        for (instance_id, hints) in self.perf_hints {
+            vmbus = vmbus.channel_perf_hints(instance_id, hints);
+        }
+        let vmbus = vmbus
             .enable_mnf(true)
+            .message_trace(self.message_trace)
             .build()
             .unwrap();
 
@@ -347,6 +367,8 @@ async fn offer(&self, id: u32, allow_confidential_external_memory: bool) -> Test
                 mnf_interrupt_latency: None,
                 offer_order: None,
                 allow_confidential_external_memory,
+                interrupt_coalescing: None,
+                perf_hints: None,
             },
         };
 
@@ -782,6 +804,80 @@ async fn test_stopped_child(spawner: DefaultDriver) {
     env.vmbus.stop().await;
 }
 
+#[async_test]
+async fn test_message_trace(spawner: DefaultDriver) {
+    let trace = TraceBuffer::new();
+    let mut env = TestEnvBuilder::new(spawner)
+        .message_trace(trace.writer())
+        .build();
+    let mut channels = Vec::new();
+    for id in 1..=8 {
+        channels.push(env.offer(id, false).await);
+    }
+    env.vmbus.start();
+
+    // The mock message port is sometimes not ready, so some of these messages
+    // are queued and sent later by the server's flush path.
+    env.initiate_contact(
+        protocol::Version::Copper,
+        protocol::FeatureFlags::new(),
+        false,
+        false,
+    );
+    let mut received = vec![env.message_recv.next().await.unwrap()];
+    env.synic.send_message(protocol::RequestOffers {});
+    // Eight offers and AllOffersDelivered.
+    for _ in 0..9 {
+        received.push(env.message_recv.next().await.unwrap());
+    }
+
+    let records = trace.records().unwrap();
+    let traced = |direction| {
+        records
+            .iter()
+            .filter(|record| record.direction == direction)
+            .map(|record| record.data.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(traced(TraceDirection::HostToGuest), received);
+    assert_eq!(traced(TraceDirection::GuestToHost).len(), 2);
+}
+
+#[async_test]
+async fn test_channel_perf_hints(spawner: DefaultDriver) {
+    let instance_id = Guid {
+        data1: 1,
+        ..Guid::ZERO
+    };
+    let mut env = TestEnvBuilder::new(spawner)
+        .channel_perf_hints(
+            instance_id,
+            PerfHints::new()
+                .with_latency(Duration::from_micros(100))
+                .with_throughput_mbps(500),
+        )
+        .build();
+    let _channel1 = env.offer(1, false).await;
+    let _channel2 = env.offer(2, false).await;
+    env.vmbus.start();
+
+    env.initiate_contact(
+        protocol::Version::Copper,
+        protocol::FeatureFlags::new(),
+        false,
+        false,
+    );
+    env.expect_response(protocol::MessageType::VERSION_RESPONSE)
+        .await;
+    env.synic.send_message(protocol::RequestOffers {});
+    for _ in 0..2 {
+        let offer: protocol::OfferChannel = env.get_response().await;
+        let expected =
+            (offer.instance_id == instance_id).then(|| protocol::ChannelPerfHints::new(100, 500));
+        assert_eq!(offer.user_defined.perf_hints(), expected);
+    }
+}
+
 #[async_test]
 async fn test_confidential_connection(spawner: DefaultDriver) {
     let mut env = TestEnv::new(spawner);
@@ -49,6 +49,7 @@ struct GlobalHvState {
 }
 
 #[derive(Inspect)]
+#[inspect(extra = "Self::inspect_extra")]
 struct MutableHvState {
     #[inspect(hex, with = "|&x| u64::from(x)")]
     hypercall_reg: hvdef::hypercall::MsrHypercallContents,
@@ -73,6 +74,10 @@ fn new() -> Self {
         }
     }
 
+    fn inspect_extra(&self, resp: &mut inspect::Response<'_>) {
+        resp.display("guest_os", &self.guest_os_id.family());
+    }
+
     fn reset(&mut self, prot_access: &mut dyn VtlProtectAccess) {
         let Self {
             hypercall_reg,
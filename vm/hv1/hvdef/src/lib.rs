@@ -1194,6 +1194,77 @@ pub fn open_source(&self) -> Option<HvGuestOsOpenSource> {
         pub fn as_u64(&self) -> u64 {
             self.0
         }
+
+        /// Classifies the guest OS from the reported ID.
+        pub fn family(&self) -> GuestOsFamily {
+            if self.0 == 0 {
+                return GuestOsFamily::Unknown;
+            }
+            if let Some(os) = self.open_source() {
+                match HvGuestOsOpenSourceType(os.os_type()) {
+                    HvGuestOsOpenSourceType::LINUX => GuestOsFamily::Linux {
+                        version: os.version(),
+                    },
+                    HvGuestOsOpenSourceType::FREEBSD => GuestOsFamily::FreeBsd {
+                        version: os.version(),
+                    },
+                    os_type => GuestOsFamily::OtherOpenSource(os_type),
+                }
+            } else {
+                let os = HvGuestOsMicrosoft::from(self.0);
+                match HvGuestOsMicrosoftIds(os.os_id()) {
+                    HvGuestOsMicrosoftIds::WINDOWS_NT => GuestOsFamily::Windows,
+                    os_id => GuestOsFamily::OtherMicrosoft(os_id),
+                }
+            }
+        }
+    }
+
+    /// The guest OS family, as inferred from [`HvGuestOsId`].
+    ///
+    /// This is the only guest OS detection the VMM performs. The guest OS ID
+    /// register is the one source every enlightened guest reports; IC version
+    /// strings and disk inspection are deliberately not consulted, since they
+    /// are only available late (or not at all) and can be spoofed by the
+    /// guest's user mode. Per-OS quirks stay with the devices that need them
+    /// (e.g. netvsp's ring size optimization), keyed off this value, rather than
+    /// being applied centrally.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum GuestOsFamily {
+        /// The guest has not reported its identity.
+        Unknown,
+        /// Windows NT and its descendants.
+        Windows,
+        /// A Microsoft OS other than Windows NT.
+        OtherMicrosoft(HvGuestOsMicrosoftIds),
+        /// Linux. The kernel version is encoded as
+        /// `(major << 16) | (minor << 8) | patch`.
+        Linux { version: u32 },
+        /// FreeBSD, with its `__FreeBSD_version`.
+        FreeBsd { version: u32 },
+        /// Another open source OS.
+        OtherOpenSource(HvGuestOsOpenSourceType),
+    }
+
+    impl core::fmt::Display for GuestOsFamily {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match *self {
+                GuestOsFamily::Unknown => f.pad("unknown"),
+                GuestOsFamily::Windows => f.pad("windows"),
+                GuestOsFamily::OtherMicrosoft(os_id) => write!(f, "microsoft ({:#x})", os_id.0),
+                GuestOsFamily::Linux { version } => write!(
+                    f,
+                    "linux {}.{}.{}",
+                    version >> 16,
+                    (version >> 8) & 0xff,
+                    version & 0xff
+                ),
+                GuestOsFamily::FreeBsd { version } => write!(f, "freebsd ({version})"),
+                GuestOsFamily::OtherOpenSource(os_type) => {
+                    write!(f, "open source ({:#x})", os_type.0)
+                }
+            }
+        }
     }
 
     pub const HV_INTERCEPT_ACCESS_MASK_NONE: u32 = 0x00;
@@ -0,0 +1,396 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Tracking of the guest memory pages written by devices, for features such as
+//! migration that must find every page that changed.
+//!
+//! [`GuestMemoryDirtyTracker::new`] wraps a [`GuestMemory`] in a new one whose
+//! write bitmap diverts the first write to each page to a slow path while
+//! tracking is active. The slow path marks the page dirty and makes it
+//! writable again, so that later writes to the page take the fast path until
+//! the dirty pages are next collected.
+//!
+//! Only writes made through the wrapped `GuestMemory` are observed. Writes by
+//! the guest's processors must be tracked by the hypervisor. Locked pages, such
+//! as ring buffers, can be written through their mapping at any time, so they
+//! are reported as dirty for as long as they stay locked.
+
+use crate::BitmapInfo;
+use crate::GuestMemory;
+use crate::GuestMemoryAccess;
+use crate::GuestMemoryBackingError;
+use crate::PAGE_SIZE;
+use crate::PageFaultAction;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::ptr::NonNull;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+/// Tracks the pages written through a guest memory object created by
+/// [`GuestMemoryDirtyTracker::new`].
+#[derive(Clone)]
+pub struct GuestMemoryDirtyTracker {
+    state: Arc<DirtyState>,
+}
+
+struct DirtyState {
+    read_bitmap: Box<[AtomicU8]>,
+    /// A clear bit diverts writes to the page to [`DirtyState::mark_dirty`].
+    write_bitmap: Box<[AtomicU8]>,
+    dirty: Box<[AtomicU64]>,
+    len: u64,
+    tracking: AtomicBool,
+    /// The lock count of each locked page.
+    locked: Mutex<HashMap<u64, usize>>,
+    /// Whether the underlying memory needs its locked pages to be unlocked.
+    inner_locks: AtomicBool,
+}
+
+impl GuestMemoryDirtyTracker {
+    /// Returns a tracker and a new guest memory object that accesses the same
+    /// memory as `gm`, with writes tracked while tracking is active.
+    ///
+    /// Returns `None` if `gm` is not backed by a single mapping without
+    /// access bitmaps (see [`GuestMemory::full_mapping`]).
+    pub fn new(gm: &GuestMemory) -> Option<(Self, GuestMemory)> {
+        let (mapping, len) = gm.full_mapping()?;
+        let len = len as u64;
+        let page_count = len.div_ceil(PAGE_SIZE as u64);
+        let bitmap = || {
+            (0..page_count.div_ceil(8))
+                .map(|_| AtomicU8::new(!0))
+                .collect()
+        };
+        let state = Arc::new(DirtyState {
+            read_bitmap: bitmap(),
+            write_bitmap: bitmap(),
+            dirty: (0..page_count.div_ceil(64))
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            len,
+            tracking: AtomicBool::new(false),
+            locked: Mutex::new(HashMap::new()),
+            inner_locks: AtomicBool::new(false),
+        });
+        let tracked = DirtyTrackedMemory {
+            mapping: NonNull::new(mapping).unwrap(),
+            gm: gm.clone(),
+            state: state.clone(),
+        };
+        let gm = GuestMemory::new(gm.inner.debug_name.clone(), tracked);
+        Some((Self { state }, gm))
+    }
+
+    /// Starts tracking writes, forgetting any writes tracked so far.
+    pub fn start(&self) {
+        self.state.tracking.store(true, Ordering::SeqCst);
+        for word in &self.state.dirty {
+            word.store(0, Ordering::SeqCst);
+        }
+        for byte in &self.state.write_bitmap {
+            byte.store(0, Ordering::SeqCst);
+        }
+        // Ensure that no thread is still writing via the mapping without
+        // having seen the cleared bitmap.
+        crate::rcu().synchronize_blocking();
+    }
+
+    /// Stops tracking writes.
+    pub fn stop(&self) {
+        self.state.tracking.store(false, Ordering::SeqCst);
+        for byte in &self.state.write_bitmap {
+            byte.store(!0, Ordering::SeqCst);
+        }
+    }
+
+    /// Gets and clears the set of pages in `gpa..gpa + len` written since
+    /// tracking was started or since the last call, setting one bit per page
+    /// in `bitmap`. Locked pages are always reported.
+    ///
+    /// The data of the reported pages must be read after this returns, since
+    /// writes may still be in flight until then.
+    ///
+    /// Panics if the range is not page aligned or is out of range, or if
+    /// `bitmap` is too small.
+    pub fn get_dirty_pages(&self, gpa: u64, len: u64, bitmap: &mut [u64]) {
+        let page_size = PAGE_SIZE as u64;
+        assert!(
+            gpa % page_size == 0
+                && len % page_size == 0
+                && gpa
+                    .checked_add(len)
+                    .is_some_and(|end| end <= self.state.len),
+            "invalid dirty page range {gpa:#x}+{len:#x}"
+        );
+        let first = gpa / page_size;
+        let page_count = len / page_size;
+        let bitmap = &mut bitmap[..page_count.div_ceil(64) as usize];
+        bitmap.fill(0);
+
+        let mut set = |gpn: u64| {
+            let page = gpn - first;
+            bitmap[page as usize / 64] |= 1 << (page % 64);
+        };
+
+        // Clear each dirty bit before write protecting its page again, so that
+        // a write that slips in between is reported by the next call.
+        let mut protected = false;
+        let end = first + page_count;
+        let mut gpn = first;
+        while gpn < end {
+            let bits = (end - gpn).min(64 - gpn % 64);
+            let mask = if bits == 64 {
+                !0
+            } else {
+                ((1 << bits) - 1) << (gpn % 64)
+            };
+            let mut dirty =
+                self.state.dirty[(gpn / 64) as usize].fetch_and(!mask, Ordering::SeqCst) & mask;
+            while dirty != 0 {
+                let dirty_gpn = gpn - gpn % 64 + dirty.trailing_zeros() as u64;
+                dirty &= dirty - 1;
+                self.state.write_bitmap[(dirty_gpn / 8) as usize]
+                    .fetch_and(!(1 << (dirty_gpn % 8)), Ordering::SeqCst);
+                set(dirty_gpn);
+                protected = true;
+            }
+            gpn += bits;
+        }
+
+        for &gpn in self.state.locked.lock().keys() {
+            if (first..end).contains(&gpn) {
+                set(gpn);
+            }
+        }
+
+        if protected {
+            // Wait for any writes that passed the bitmap check before the
+            // pages were protected again.
+            crate::rcu().synchronize_blocking();
+        }
+    }
+}
+
+impl DirtyState {
+    /// Marks the pages in `gpa..gpa + len` dirty and makes them writable.
+    fn mark_dirty(&self, gpa: u64, len: u64) {
+        if !self.tracking.load(Ordering::SeqCst) {
+            return;
+        }
+        let page_size = PAGE_SIZE as u64;
+        let end = gpa.saturating_add(len).min(self.len);
+        for gpn in gpa / page_size..end.div_ceil(page_size) {
+            // Make the page writable before marking it dirty, so that a
+            // collection that sees the dirty bit protects the page again after
+            // this.
+            self.write_bitmap[(gpn / 8) as usize].fetch_or(1 << (gpn % 8), Ordering::SeqCst);
+            self.dirty[(gpn / 64) as usize].fetch_or(1 << (gpn % 64), Ordering::SeqCst);
+        }
+    }
+}
+
+/// The [`GuestMemoryAccess`] implementation for tracked memory.
+struct DirtyTrackedMemory {
+    mapping: NonNull<u8>,
+    gm: GuestMemory,
+    state: Arc<DirtyState>,
+}
+
+// SAFETY: `mapping` is the full mapping of `gm`, which is kept alive by this
+// object.
+unsafe impl Send for DirtyTrackedMemory {}
+// SAFETY: see above.
+unsafe impl Sync for DirtyTrackedMemory {}
+
+// SAFETY: the mapping and its length come from `gm`, and the bitmaps are
+// owned by `state`, which lives as long as this object.
+unsafe impl GuestMemoryAccess for DirtyTrackedMemory {
+    fn mapping(&self) -> Option<NonNull<u8>> {
+        Some(self.mapping)
+    }
+
+    fn max_address(&self) -> u64 {
+        self.state.len
+    }
+
+    fn access_bitmap(&self) -> Option<BitmapInfo> {
+        Some(BitmapInfo {
+            read_bitmap: NonNull::from(&*self.state.read_bitmap).cast(),
+            write_bitmap: NonNull::from(&*self.state.write_bitmap).cast(),
+            bit_offset: 0,
+        })
+    }
+
+    fn page_fault(
+        &self,
+        address: u64,
+        len: usize,
+        write: bool,
+        bitmap_failure: bool,
+    ) -> PageFaultAction {
+        if bitmap_failure && write && self.state.tracking.load(Ordering::SeqCst) {
+            self.state.mark_dirty(address, len as u64);
+            PageFaultAction::Retry
+        } else {
+            // Failures in the underlying memory, and writes that raced with
+            // tracking being stopped, are handled by the fallbacks, which
+            // forward to `gm`.
+            PageFaultAction::Fallback
+        }
+    }
+
+    unsafe fn read_fallback(
+        &self,
+        addr: u64,
+        dest: *mut u8,
+        len: usize,
+    ) -> Result<(), GuestMemoryBackingError> {
+        // SAFETY: guaranteed by the caller.
+        unsafe { self.gm.read_ptr(addr, dest, len) }
+    }
+
+    unsafe fn write_fallback(
+        &self,
+        addr: u64,
+        src: *const u8,
+        len: usize,
+    ) -> Result<(), GuestMemoryBackingError> {
+        self.state.mark_dirty(addr, len as u64);
+        // SAFETY: guaranteed by the caller.
+        unsafe { self.gm.write_ptr(addr, src, len) }
+    }
+
+    fn fill_fallback(&self, addr: u64, val: u8, len: usize) -> Result<(), GuestMemoryBackingError> {
+        self.state.mark_dirty(addr, len as u64);
+        self.gm.fill_at_inner(addr, val, len)
+    }
+
+    fn compare_exchange_fallback(
+        &self,
+        addr: u64,
+        current: &mut [u8],
+        new: &[u8],
+    ) -> Result<bool, GuestMemoryBackingError> {
+        self.state.mark_dirty(addr, new.len() as u64);
+        self.gm.compare_exchange_bytes_inner(addr, current, new)
+    }
+
+    fn expose_va(&self, address: u64, len: u64) -> Result<(), GuestMemoryBackingError> {
+        self.gm.inner.imp.expose_va(address, len)
+    }
+
+    fn base_iova(&self) -> Option<u64> {
+        self.gm.inner.regions[0].base_iova
+    }
+
+    fn lock_gpns(&self, gpns: &[u64]) -> Result<bool, GuestMemoryBackingError> {
+        if self.gm.inner.imp.lock_gpns(gpns)? {
+            self.state.inner_locks.store(true, Ordering::Relaxed);
+        }
+        let mut locked = self.state.locked.lock();
+        for &gpn in gpns {
+            *locked.entry(gpn).or_default() += 1;
+        }
+        // Ask to be told when the pages are unlocked.
+        Ok(true)
+    }
+
+    fn unlock_gpns(&self, gpns: &[u64]) {
+        {
+            let mut locked = self.state.locked.lock();
+            for &gpn in gpns {
+                let count = locked.get_mut(&gpn).expect("page was locked");
+                *count -= 1;
+                if *count == 0 {
+                    locked.remove(&gpn);
+                }
+                // The page may have been written while it was locked.
+                self.state
+                    .mark_dirty(gpn * PAGE_SIZE as u64, PAGE_SIZE as u64);
+            }
+        }
+        if self.state.inner_locks.load(Ordering::Relaxed) {
+            self.gm.inner.imp.unlock_gpns(gpns);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GuestMemoryDirtyTracker;
+    use crate::GuestMemory;
+    use crate::PAGE_SIZE;
+
+    const PAGES: u64 = 16;
+
+    fn dirty_pages(tracker: &GuestMemoryDirtyTracker) -> Vec<u64> {
+        let mut bitmap = [0];
+        tracker.get_dirty_pages(0, PAGES * PAGE_SIZE as u64, &mut bitmap);
+        (0..PAGES).filter(|&n| bitmap[0] & (1 << n) != 0).collect()
+    }
+
+    #[test]
+    fn writes_are_reported() {
+        let base = GuestMemory::allocate(PAGES as usize * PAGE_SIZE);
+        let (tracker, gm) = GuestMemoryDirtyTracker::new(&base).unwrap();
+        tracker.start();
+
+        gm.write_at(PAGE_SIZE as u64, &[1]).unwrap();
+        gm.fill_at(3 * PAGE_SIZE as u64, 2, 1).unwrap();
+        gm.write_at(6 * PAGE_SIZE as u64 - 1, &[3, 3]).unwrap();
+        gm.compare_exchange(9 * PAGE_SIZE as u64, 0u32, 4)
+            .unwrap()
+            .unwrap();
+        let mut data = [0; 4];
+        gm.read_at(12 * PAGE_SIZE as u64, &mut data).unwrap();
+        assert_eq!(dirty_pages(&tracker), [1, 3, 5, 6, 9]);
+
+        // Each write is reported once, and pages are tracked again after
+        // being reported.
+        assert!(dirty_pages(&tracker).is_empty());
+        gm.write_at(PAGE_SIZE as u64 + 8, &[5]).unwrap();
+        assert_eq!(dirty_pages(&tracker), [1]);
+
+        // The writes reached the underlying memory.
+        assert_eq!(base.read_plain::<u8>(PAGE_SIZE as u64 + 8).unwrap(), 5);
+        assert_eq!(base.read_plain::<u32>(9 * PAGE_SIZE as u64).unwrap(), 4);
+    }
+
+    #[test]
+    fn writes_are_not_tracked_when_stopped() {
+        let base = GuestMemory::allocate(PAGES as usize * PAGE_SIZE);
+        let (tracker, gm) = GuestMemoryDirtyTracker::new(&base).unwrap();
+        gm.write_at(0, &[1]).unwrap();
+        tracker.start();
+        assert!(dirty_pages(&tracker).is_empty());
+        gm.write_at(2 * PAGE_SIZE as u64, &[1]).unwrap();
+        tracker.stop();
+        gm.write_at(4 * PAGE_SIZE as u64, &[1]).unwrap();
+
+        // Starting again forgets the earlier writes.
+        tracker.start();
+        assert!(dirty_pages(&tracker).is_empty());
+        gm.write_at(7 * PAGE_SIZE as u64, &[1]).unwrap();
+        assert_eq!(dirty_pages(&tracker), [7]);
+    }
+
+    #[test]
+    fn locked_pages_are_reported() {
+        let base = GuestMemory::allocate(PAGES as usize * PAGE_SIZE);
+        let (tracker, gm) = GuestMemoryDirtyTracker::new(&base).unwrap();
+        let locked = gm.lock_gpns(false, &[2]).unwrap();
+        tracker.start();
+        assert_eq!(dirty_pages(&tracker), [2]);
+        assert_eq!(dirty_pages(&tracker), [2]);
+
+        // The page is reported once more after it is unlocked, for the writes
+        // made while it was locked.
+        drop(locked);
+        assert_eq!(dirty_pages(&tracker), [2]);
+        assert!(dirty_pages(&tracker).is_empty());
+    }
+}
@@ -7,7 +7,11 @@
 #![expect(unsafe_code)]
 #![expect(missing_docs)]
 
+#[cfg(feature = "bitmap")]
+pub mod dirty;
 pub mod ranges;
+#[cfg(feature = "bitmap")]
+pub mod watch;
 
 use self::ranges::PagedRange;
 use inspect::Inspect;
@@ -1796,33 +1800,77 @@ pub fn compare_exchange<T: IntoBytes + FromBytes + Immutable + KnownLayout + Cop
         self.with_op(
             Some((gpa, len as u64)),
             GuestMemoryOperation::CompareExchange,
-            || {
-                // Assume that if write is allowed, then read is allowed.
-                self.run_on_mapping(
-                    AccessType::Write,
+            || self.compare_exchange_inner(gpa, current, new),
+        )
+    }
+
+    fn compare_exchange_inner<T: IntoBytes + FromBytes + Immutable + KnownLayout + Copy>(
+        &self,
+        gpa: u64,
+        current: T,
+        new: T,
+    ) -> Result<Result<T, T>, GuestMemoryBackingError> {
+        let len = size_of_val(&new);
+        // Assume that if write is allowed, then read is allowed.
+        self.run_on_mapping(
+            AccessType::Write,
+            gpa,
+            len,
+            (),
+            |(), dest| {
+                // SAFETY: dest..dest+len is guaranteed by the caller to be a valid
+                // buffer for writes.
+                unsafe { trycopy::try_compare_exchange(dest.cast(), current, new) }
+            },
+            |()| {
+                let mut current = current;
+                let success = self.inner.imp.compare_exchange_fallback(
                     gpa,
-                    len,
-                    (),
-                    |(), dest| {
-                        // SAFETY: dest..dest+len is guaranteed by the caller to be a valid
-                        // buffer for writes.
-                        unsafe { trycopy::try_compare_exchange(dest.cast(), current, new) }
-                    },
-                    |()| {
-                        let mut current = current;
-                        let success = self.inner.imp.compare_exchange_fallback(
-                            gpa,
-                            current.as_mut_bytes(),
-                            new.as_bytes(),
-                        )?;
-
-                        Ok(if success { Ok(new) } else { Err(current) })
-                    },
-                )
+                    current.as_mut_bytes(),
+                    new.as_bytes(),
+                )?;
+
+                Ok(if success { Ok(new) } else { Err(current) })
             },
         )
     }
 
+    /// Performs a compare exchange of `current` with `new`, which are 1, 2, 4,
+    /// or 8 bytes long, for a [`GuestMemoryAccess::compare_exchange_fallback`]
+    /// implementation that forwards to `self`.
+    #[cfg(feature = "bitmap")]
+    fn compare_exchange_bytes_inner(
+        &self,
+        gpa: u64,
+        current: &mut [u8],
+        new: &[u8],
+    ) -> Result<bool, GuestMemoryBackingError> {
+        fn exchange<T: IntoBytes + FromBytes + Immutable + KnownLayout + Copy>(
+            gm: &GuestMemory,
+            gpa: u64,
+            current: &mut [u8],
+            new: &[u8],
+        ) -> Result<bool, GuestMemoryBackingError> {
+            let expected = T::read_from_bytes(current).unwrap();
+            let new = T::read_from_bytes(new).unwrap();
+            match gm.compare_exchange_inner(gpa, expected, new)? {
+                Ok(_) => Ok(true),
+                Err(actual) => {
+                    current.copy_from_slice(actual.as_bytes());
+                    Ok(false)
+                }
+            }
+        }
+
+        match new.len() {
+            1 => exchange::<u8>(self, gpa, current, new),
+            2 => exchange::<u16>(self, gpa, current, new),
+            4 => exchange::<u32>(self, gpa, current, new),
+            8 => exchange::<u64>(self, gpa, current, new),
+            _ => unreachable!(),
+        }
+    }
+
     /// Reads an object from guest memory at address `gpa`.
     ///
     /// If the object is 1, 2, 4, or 8 bytes and the address is naturally
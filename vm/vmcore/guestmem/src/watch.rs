@@ -0,0 +1,549 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Guest memory watchpoints, for finding out which code is reading or writing
+//! a range of guest physical memory.
+//!
+//! [`GuestMemoryWatcher::new`] wraps a [`GuestMemory`] in a new one that uses
+//! the access bitmaps to divert accesses to watched pages to a slow path.
+//! Accesses that overlap a watchpoint are recorded, along with a backtrace of
+//! the accessing thread, and passed to an optional handler. Accesses to
+//! unwatched pages go directly through the mapping as usual.
+//!
+//! Only accesses made through the wrapped `GuestMemory` are observed. In
+//! particular, this does not observe accesses by the guest's processors, and
+//! it only observes accesses to locked pages (such as ring buffers) at the
+//! time they are locked.
+
+use crate::BitmapInfo;
+use crate::GuestMemory;
+use crate::GuestMemoryAccess;
+use crate::GuestMemoryBackingError;
+use crate::PAGE_SIZE;
+use crate::PageFaultAction;
+use inspect::Inspect;
+use parking_lot::Mutex;
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::ptr::NonNull;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering;
+use thiserror::Error;
+
+/// The number of recent hits to keep for inspection.
+const RECENT_HIT_COUNT: usize = 64;
+
+/// A watchpoint on a range of guest physical memory.
+#[derive(Debug, Copy, Clone, Inspect)]
+pub struct Watchpoint {
+    /// The first guest physical address of the range.
+    #[inspect(hex)]
+    pub gpa: u64,
+    /// The length of the range in bytes.
+    #[inspect(hex)]
+    pub len: u64,
+    /// Whether to report reads of the range.
+    pub read: bool,
+    /// Whether to report writes to the range, including fills and compare
+    /// exchanges.
+    pub write: bool,
+    /// Whether the hit handler should break into the debugger when the
+    /// watchpoint is hit. This is not interpreted by this module; it is
+    /// passed through in [`WatchpointHit::break_on_hit`].
+    pub break_on_hit: bool,
+}
+
+/// An access that hit a watchpoint.
+#[derive(Debug, Clone, Inspect)]
+pub struct WatchpointHit {
+    /// The ID of the watchpoint that was hit.
+    pub id: u64,
+    /// The guest physical address of the access.
+    #[inspect(hex)]
+    pub gpa: u64,
+    /// The length of the access in bytes.
+    pub len: usize,
+    /// Whether the access was a write.
+    pub write: bool,
+    /// The watchpoint's [`Watchpoint::break_on_hit`] value.
+    pub break_on_hit: bool,
+    /// The name of the thread that made the access, if it has one.
+    pub thread: Option<String>,
+    /// The backtrace of the access. This is only captured if backtraces are
+    /// enabled via the `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` environment
+    /// variables.
+    #[inspect(display)]
+    pub backtrace: Arc<Backtrace>,
+}
+
+/// An error returned by [`GuestMemoryWatcher::add`].
+#[derive(Debug, Error)]
+pub enum WatchError {
+    #[error("watchpoint range {gpa:#x}+{len:#x} is empty or out of range")]
+    InvalidRange { gpa: u64, len: u64 },
+    #[error("watchpoint must watch reads, writes, or both")]
+    NoAccess,
+}
+
+/// Manages the watchpoints of a guest memory object created by
+/// [`GuestMemoryWatcher::new`].
+#[derive(Clone)]
+pub struct GuestMemoryWatcher {
+    state: Arc<WatchState>,
+}
+
+type HitHandler = Arc<dyn Fn(&WatchpointHit) + Send + Sync>;
+
+struct WatchState {
+    read_bitmap: Box<[AtomicU8]>,
+    write_bitmap: Box<[AtomicU8]>,
+    len: u64,
+    inner: Mutex<WatchStateInner>,
+    handler: Mutex<Option<HitHandler>>,
+}
+
+struct WatchStateInner {
+    next_id: u64,
+    watchpoints: Vec<WatchpointEntry>,
+    recent_hits: VecDeque<WatchpointHit>,
+}
+
+#[derive(Inspect)]
+struct WatchpointEntry {
+    #[inspect(skip)]
+    id: u64,
+    #[inspect(flatten)]
+    watchpoint: Watchpoint,
+    hits: u64,
+}
+
+impl WatchpointEntry {
+    fn overlaps(&self, gpa: u64, len: u64) -> bool {
+        self.watchpoint.gpa < gpa + len && gpa < self.watchpoint.gpa + self.watchpoint.len
+    }
+}
+
+impl GuestMemoryWatcher {
+    /// Returns a watcher and a new guest memory object that accesses the
+    /// same memory as `gm`, subject to the watcher's watchpoints.
+    ///
+    /// Returns `None` if `gm` is not backed by a single mapping without
+    /// access bitmaps (see [`GuestMemory::full_mapping`]).
+    pub fn new(gm: &GuestMemory) -> Option<(Self, GuestMemory)> {
+        let (mapping, len) = gm.full_mapping()?;
+        let len = len as u64;
+        let bitmap_len = len.div_ceil(PAGE_SIZE as u64).div_ceil(8) as usize;
+        let bitmap = || (0..bitmap_len).map(|_| AtomicU8::new(!0)).collect();
+        let state = Arc::new(WatchState {
+            read_bitmap: bitmap(),
+            write_bitmap: bitmap(),
+            len,
+            inner: Mutex::new(WatchStateInner {
+                next_id: 0,
+                watchpoints: Vec::new(),
+                recent_hits: VecDeque::new(),
+            }),
+            handler: Mutex::new(None),
+        });
+        let watched = WatchedMemory {
+            mapping: NonNull::new(mapping).unwrap(),
+            gm: gm.clone(),
+            state: state.clone(),
+        };
+        let gm = GuestMemory::new(gm.inner.debug_name.clone(), watched);
+        Some((Self { state }, gm))
+    }
+
+    /// Adds a watchpoint, returning its ID.
+    pub fn add(&self, watchpoint: Watchpoint) -> Result<u64, WatchError> {
+        if watchpoint.len == 0
+            || watchpoint
+                .gpa
+                .checked_add(watchpoint.len)
+                .is_none_or(|end| end > self.state.len)
+        {
+            return Err(WatchError::InvalidRange {
+                gpa: watchpoint.gpa,
+                len: watchpoint.len,
+            });
+        }
+        if !watchpoint.read && !watchpoint.write {
+            return Err(WatchError::NoAccess);
+        }
+        let id = {
+            let mut inner = self.state.inner.lock();
+            let id = inner.next_id;
+            inner.next_id += 1;
+            inner.watchpoints.push(WatchpointEntry {
+                id,
+                watchpoint,
+                hits: 0,
+            });
+            self.state
+                .update_bitmaps(&inner.watchpoints, watchpoint.gpa, watchpoint.len);
+            id
+        };
+        // Ensure that no thread is still accessing the newly watched pages
+        // via the mapping.
+        crate::rcu().synchronize_blocking();
+        Ok(id)
+    }
+
+    /// Removes the watchpoint with ID `id`. Returns `false` if there is no
+    /// such watchpoint.
+    pub fn remove(&self, id: u64) -> bool {
+        let mut inner = self.state.inner.lock();
+        let Some(i) = inner.watchpoints.iter().position(|w| w.id == id) else {
+            return false;
+        };
+        let entry = inner.watchpoints.remove(i);
+        self.state.update_bitmaps(
+            &inner.watchpoints,
+            entry.watchpoint.gpa,
+            entry.watchpoint.len,
+        );
+        true
+    }
+
+    /// Sets the function to call on each watchpoint hit.
+    ///
+    /// The handler is called synchronously on the thread making the access,
+    /// before the access is performed, so it should not block.
+    pub fn set_hit_handler(&self, handler: impl Fn(&WatchpointHit) + Send + Sync + 'static) {
+        *self.state.handler.lock() = Some(Arc::new(handler));
+    }
+}
+
+impl Inspect for GuestMemoryWatcher {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        let inner = self.state.inner.lock();
+        req.respond()
+            .field(
+                "watchpoints",
+                inspect::iter_by_key(inner.watchpoints.iter().map(|w| (w.id, w))),
+            )
+            .field(
+                "recent_hits",
+                inspect::iter_by_index(inner.recent_hits.iter()),
+            );
+    }
+}
+
+impl WatchState {
+    /// Recomputes the bitmap bits for the pages in `gpa..gpa+len` from
+    /// `watchpoints`.
+    fn update_bitmaps(&self, watchpoints: &[WatchpointEntry], gpa: u64, len: u64) {
+        let page_size = PAGE_SIZE as u64;
+        for gpn in gpa / page_size..(gpa + len).div_ceil(page_size) {
+            let (mut read, mut write) = (true, true);
+            for w in watchpoints {
+                if w.overlaps(gpn * page_size, page_size) {
+                    read &= !w.watchpoint.read;
+                    write &= !w.watchpoint.write;
+                }
+            }
+            let index = (gpn / 8) as usize;
+            let mask = 1 << (gpn % 8);
+            for (bitmap, allowed) in [(&self.read_bitmap, read), (&self.write_bitmap, write)] {
+                if allowed {
+                    bitmap[index].fetch_or(mask, Ordering::Relaxed);
+                } else {
+                    bitmap[index].fetch_and(!mask, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    /// Records any watchpoint hits for an access and passes them to the hit
+    /// handler.
+    fn check(&self, gpa: u64, len: usize, write: bool) {
+        let hits = {
+            let mut inner = self.inner.lock();
+            let mut hits = Vec::new();
+            for w in &mut inner.watchpoints {
+                let watched = if write {
+                    w.watchpoint.write
+                } else {
+                    w.watchpoint.read
+                };
+                if watched && w.overlaps(gpa, len as u64) {
+                    w.hits += 1;
+                    hits.push((w.id, w.watchpoint.break_on_hit));
+                }
+            }
+            if hits.is_empty() {
+                return;
+            }
+            let thread = std::thread::current().name().map(str::to_owned);
+            let backtrace = Arc::new(Backtrace::capture());
+            let hits = hits
+                .into_iter()
+                .map(|(id, break_on_hit)| WatchpointHit {
+                    id,
+                    gpa,
+                    len,
+                    write,
+                    break_on_hit,
+                    thread: thread.clone(),
+                    backtrace: backtrace.clone(),
+                })
+                .collect::<Vec<_>>();
+            for hit in &hits {
+                if inner.recent_hits.len() == RECENT_HIT_COUNT {
+                    inner.recent_hits.pop_front();
+                }
+                inner.recent_hits.push_back(hit.clone());
+            }
+            hits
+        };
+        let handler = self.handler.lock().clone();
+        if let Some(handler) = handler {
+            for hit in &hits {
+                handler(hit);
+            }
+        }
+    }
+}
+
+/// The [`GuestMemoryAccess`] implementation for watched memory.
+struct WatchedMemory {
+    mapping: NonNull<u8>,
+    gm: GuestMemory,
+    state: Arc<WatchState>,
+}
+
+// SAFETY: `mapping` is the full mapping of `gm`, which is kept alive by this
+// object.
+unsafe impl Send for WatchedMemory {}
+// SAFETY: see above.
+unsafe impl Sync for WatchedMemory {}
+
+// SAFETY: the mapping and its length come from `gm`, and the bitmaps are
+// owned by `state`, which lives as long as this object.
+unsafe impl GuestMemoryAccess for WatchedMemory {
+    fn mapping(&self) -> Option<NonNull<u8>> {
+        Some(self.mapping)
+    }
+
+    fn max_address(&self) -> u64 {
+        self.state.len
+    }
+
+    fn access_bitmap(&self) -> Option<BitmapInfo> {
+        Some(BitmapInfo {
+            read_bitmap: NonNull::from(&*self.state.read_bitmap).cast(),
+            write_bitmap: NonNull::from(&*self.state.write_bitmap).cast(),
+            bit_offset: 0,
+        })
+    }
+
+    fn page_fault(
+        &self,
+        _address: u64,
+        _len: usize,
+        _write: bool,
+        _bitmap_failure: bool,
+    ) -> PageFaultAction {
+        // Watched pages and failures in the underlying memory are both
+        // handled by the fallbacks, which forward to `gm`.
+        PageFaultAction::Fallback
+    }
+
+    unsafe fn read_fallback(
+        &self,
+        addr: u64,
+        dest: *mut u8,
+        len: usize,
+    ) -> Result<(), GuestMemoryBackingError> {
+        self.state.check(addr, len, false);
+        // SAFETY: guaranteed by the caller.
+        unsafe { self.gm.read_ptr(addr, dest, len) }
+    }
+
+    unsafe fn write_fallback(
+        &self,
+        addr: u64,
+        src: *const u8,
+        len: usize,
+    ) -> Result<(), GuestMemoryBackingError> {
+        self.state.check(addr, len, true);
+        // SAFETY: guaranteed by the caller.
+        unsafe { self.gm.write_ptr(addr, src, len) }
+    }
+
+    fn fill_fallback(&self, addr: u64, val: u8, len: usize) -> Result<(), GuestMemoryBackingError> {
+        self.state.check(addr, len, true);
+        self.gm.fill_at_inner(addr, val, len)
+    }
+
+    fn compare_exchange_fallback(
+        &self,
+        addr: u64,
+        current: &mut [u8],
+        new: &[u8],
+    ) -> Result<bool, GuestMemoryBackingError> {
+        self.state.check(addr, new.len(), true);
+        self.gm.compare_exchange_bytes_inner(addr, current, new)
+    }
+
+    fn expose_va(&self, address: u64, len: u64) -> Result<(), GuestMemoryBackingError> {
+        self.gm.inner.imp.expose_va(address, len)
+    }
+
+    fn base_iova(&self) -> Option<u64> {
+        self.gm.inner.regions[0].base_iova
+    }
+
+    fn lock_gpns(&self, gpns: &[u64]) -> Result<bool, GuestMemoryBackingError> {
+        self.gm.inner.imp.lock_gpns(gpns)
+    }
+
+    fn unlock_gpns(&self, gpns: &[u64]) {
+        self.gm.inner.imp.unlock_gpns(gpns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GuestMemoryWatcher;
+    use super::Watchpoint;
+    use super::WatchpointHit;
+    use crate::GuestMemory;
+    use crate::PAGE_SIZE;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    const PAGES: usize = 4;
+
+    fn watcher() -> (
+        GuestMemoryWatcher,
+        GuestMemory,
+        Arc<Mutex<Vec<WatchpointHit>>>,
+    ) {
+        let base = GuestMemory::allocate(PAGES * PAGE_SIZE);
+        let (watcher, gm) = GuestMemoryWatcher::new(&base).unwrap();
+        let hits = Arc::new(Mutex::new(Vec::new()));
+        watcher.set_hit_handler({
+            let hits = hits.clone();
+            move |hit| hits.lock().push(hit.clone())
+        });
+        (watcher, gm, hits)
+    }
+
+    fn hits(hits: &Mutex<Vec<WatchpointHit>>) -> Vec<(u64, u64, usize, bool)> {
+        hits.lock()
+            .drain(..)
+            .map(|hit| (hit.id, hit.gpa, hit.len, hit.write))
+            .collect()
+    }
+
+    #[test]
+    fn watched_accesses_are_reported() {
+        let (watcher, gm, recorded) = watcher();
+        let gpa = PAGE_SIZE as u64 + 0x10;
+        let read_id = watcher
+            .add(Watchpoint {
+                gpa,
+                len: 4,
+                read: true,
+                write: false,
+                break_on_hit: false,
+            })
+            .unwrap();
+        let write_id = watcher
+            .add(Watchpoint {
+                gpa,
+                len: 4,
+                read: false,
+                write: true,
+                break_on_hit: true,
+            })
+            .unwrap();
+
+        gm.write_at(gpa + 2, &[1, 2, 3, 4]).unwrap();
+        let mut data = [0; 8];
+        gm.read_at(gpa - 4, &mut data).unwrap();
+        assert_eq!(data, [0, 0, 0, 0, 0, 0, 1, 2]);
+        gm.fill_at(gpa, 0, 1).unwrap();
+        assert_eq!(
+            hits(&recorded),
+            [
+                (write_id, gpa + 2, 4, true),
+                (read_id, gpa - 4, 8, false),
+                (write_id, gpa, 1, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn unwatched_accesses_are_not_reported() {
+        let (watcher, gm, recorded) = watcher();
+        let gpa = PAGE_SIZE as u64 + 0x10;
+        watcher
+            .add(Watchpoint {
+                gpa,
+                len: 4,
+                read: false,
+                write: true,
+                break_on_hit: false,
+            })
+            .unwrap();
+
+        // Other pages do not go through the watcher at all.
+        gm.write_at(0, &[1; 8]).unwrap();
+        gm.write_at(2 * PAGE_SIZE as u64, &[1; 8]).unwrap();
+        // Accesses to the watched page outside the range, and reads of a
+        // write-only range, are not reported.
+        gm.write_at(gpa + 4, &[1; 8]).unwrap();
+        let mut data = [0; 4];
+        gm.read_at(gpa, &mut data).unwrap();
+        assert!(hits(&recorded).is_empty());
+        assert_eq!(gm.read_plain::<u8>(gpa + 4).unwrap(), 1);
+    }
+
+    #[test]
+    fn removed_watchpoints_are_not_reported() {
+        let (watcher, gm, recorded) = watcher();
+        let watchpoint = Watchpoint {
+            gpa: 0,
+            len: PAGE_SIZE as u64,
+            read: true,
+            write: true,
+            break_on_hit: false,
+        };
+        let id = watcher.add(watchpoint).unwrap();
+        let other = watcher.add(watchpoint).unwrap();
+        assert!(watcher.remove(id));
+        assert!(!watcher.remove(id));
+
+        gm.write_at(0, &[1]).unwrap();
+        assert_eq!(hits(&recorded), [(other, 0, 1, true)]);
+
+        assert!(watcher.remove(other));
+        gm.write_at(0, &[2]).unwrap();
+        gm.read_plain::<u8>(0).unwrap();
+        assert!(hits(&recorded).is_empty());
+        assert_eq!(gm.read_plain::<u8>(0).unwrap(), 2);
+    }
+
+    #[test]
+    fn compare_exchange_is_reported() {
+        let (watcher, gm, recorded) = watcher();
+        let gpa = 3 * PAGE_SIZE as u64;
+        let id = watcher
+            .add(Watchpoint {
+                gpa,
+                len: 8,
+                read: false,
+                write: true,
+                break_on_hit: false,
+            })
+            .unwrap();
+
+        assert_eq!(gm.compare_exchange(gpa, 0u64, 5).unwrap(), Ok(5));
+        assert_eq!(gm.compare_exchange(gpa, 0u64, 6).unwrap(), Err(5));
+        assert_eq!(gm.read_plain::<u64>(gpa).unwrap(), 5);
+        assert_eq!(hits(&recorded), [(id, gpa, 8, true), (id, gpa, 8, true)]);
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Types for reporting guest lifecycle events observed by devices.
+
+#![forbid(unsafe_code)]
+
+use mesh::MeshPayload;
+use std::convert::Infallible;
+use std::sync::Arc;
+use vm_resource::CanResolveTo;
+use vm_resource::PlatformResource;
+use vm_resource::ResolveResource;
+use vm_resource::ResourceKind;
+
+/// A guest lifecycle event observed by a device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, MeshPayload)]
+pub enum GuestEvent {
+    /// The guest started answering the heartbeat integration component.
+    HeartbeatUp,
+    /// A shutdown request was delivered to the guest.
+    ShutdownRequested,
+    /// The guest reported a crash and started writing a crash dump.
+    Crashed,
+}
+
+/// A resource kind for reporting [`GuestEvent`]s to the VMM.
+///
+/// Only the platform resource makes sense for this resource kind, since the
+/// events describe the whole VM.
+pub enum GuestEventKind {}
+
+impl ResourceKind for GuestEventKind {
+    const NAME: &'static str = "guest_event";
+}
+
+impl CanResolveTo<GuestEventSink> for GuestEventKind {
+    type Input<'a> = ();
+}
+
+/// A handle for reporting [`GuestEvent`]s to the VMM.
+#[derive(Clone)]
+pub struct GuestEventSink(Arc<dyn Fn(GuestEvent) + Send + Sync>);
+
+impl GuestEventSink {
+    /// Creates a new sink that calls `report` for each event.
+    pub fn new(report: impl Fn(GuestEvent) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(report))
+    }
+
+    /// Reports `event`.
+    pub fn report(&self, event: GuestEvent) {
+        (self.0)(event)
+    }
+}
+
+impl ResolveResource<GuestEventKind, PlatformResource> for GuestEventSink {
+    type Output = GuestEventSink;
+    type Error = Infallible;
+
+    fn resolve(
+        &self,
+        PlatformResource: PlatformResource,
+        (): (),
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(self.clone())
+    }
+}
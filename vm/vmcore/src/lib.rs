@@ -10,10 +10,13 @@
 extern crate self as vmcore;
 
 pub mod device_state;
+pub mod guest_event;
 pub mod interrupt;
 pub mod isa_dma_channel;
 pub mod line_interrupt;
 pub mod local_only;
+pub mod memory_hot_add;
+pub mod memory_reclaim;
 pub mod monitor;
 pub mod non_volatile_store;
 pub mod notify;
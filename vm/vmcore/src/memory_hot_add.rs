@@ -0,0 +1,83 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Types for adding RAM to a running VM.
+
+#![forbid(unsafe_code)]
+
+use async_trait::async_trait;
+use std::convert::Infallible;
+use std::ops::Range;
+use std::sync::Arc;
+use vm_resource::CanResolveTo;
+use vm_resource::PlatformResource;
+use vm_resource::ResolveResource;
+use vm_resource::ResourceKind;
+
+/// Trait for adding RAM to the guest physical address space.
+#[async_trait]
+pub trait HotAddMemory: Send + Sync {
+    /// Returns the guest physical address range reserved for hot-added RAM.
+    fn hot_add_range(&self) -> Range<u64>;
+
+    /// Backs the guest physical range `gpa..gpa + len` with RAM and maps it
+    /// into the guest.
+    ///
+    /// The range must be within [`hot_add_range`](Self::hot_add_range) and
+    /// must be aligned to [`HOT_ADD_ALIGNMENT`]. Adding a range that has
+    /// already been added succeeds without changing its contents.
+    async fn hot_add(&self, gpa: u64, len: u64) -> std::io::Result<()>;
+}
+
+/// The granularity at which RAM can be hot added.
+pub const HOT_ADD_ALIGNMENT: u64 = 128 << 20;
+
+/// A resource kind for hot adding guest RAM.
+///
+/// Only the platform resource makes sense for this resource kind, since the
+/// partition's memory manager owns the guest physical address space.
+pub enum MemoryHotAddKind {}
+
+impl ResourceKind for MemoryHotAddKind {
+    const NAME: &'static str = "memory_hot_add";
+}
+
+impl CanResolveTo<MemoryHotAdd> for MemoryHotAddKind {
+    type Input<'a> = ();
+}
+
+/// A handle for hot adding guest RAM.
+#[derive(Clone)]
+pub struct MemoryHotAdd(Arc<dyn HotAddMemory>);
+
+impl MemoryHotAdd {
+    /// Creates a new handle.
+    pub fn new<T: HotAddMemory + 'static>(hot_add: T) -> Self {
+        Self(Arc::new(hot_add))
+    }
+
+    /// Returns the guest physical address range reserved for hot-added RAM.
+    pub fn hot_add_range(&self) -> Range<u64> {
+        self.0.hot_add_range()
+    }
+
+    /// Backs the guest physical range `gpa..gpa + len` with RAM.
+    ///
+    /// See [`HotAddMemory::hot_add`].
+    pub async fn hot_add(&self, gpa: u64, len: u64) -> std::io::Result<()> {
+        self.0.hot_add(gpa, len).await
+    }
+}
+
+impl ResolveResource<MemoryHotAddKind, PlatformResource> for MemoryHotAdd {
+    type Output = MemoryHotAdd;
+    type Error = Infallible;
+
+    fn resolve(
+        &self,
+        PlatformResource: PlatformResource,
+        (): (),
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(self.clone())
+    }
+}
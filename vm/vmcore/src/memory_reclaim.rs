@@ -0,0 +1,68 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Types for returning the host memory backing guest RAM to the host.
+
+#![forbid(unsafe_code)]
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use vm_resource::CanResolveTo;
+use vm_resource::PlatformResource;
+use vm_resource::ResolveResource;
+use vm_resource::ResourceKind;
+
+/// Trait for releasing the host memory backing guest RAM.
+pub trait ReclaimMemory: Send + Sync {
+    /// Releases the host memory backing the guest RAM at `gpa..gpa + len`.
+    ///
+    /// The contents of the range are lost: afterwards, the guest reads zeroes
+    /// from it, and the host allocates memory for it again on demand. `gpa`
+    /// and `len` must be page aligned, and the range must be RAM.
+    fn reclaim(&self, gpa: u64, len: u64) -> std::io::Result<()>;
+}
+
+/// A resource kind for reclaiming guest RAM.
+///
+/// Only the platform resource makes sense for this resource kind, since the
+/// partition's memory manager owns the backing for all guest RAM.
+pub enum MemoryReclaimKind {}
+
+impl ResourceKind for MemoryReclaimKind {
+    const NAME: &'static str = "memory_reclaim";
+}
+
+impl CanResolveTo<MemoryReclaim> for MemoryReclaimKind {
+    type Input<'a> = ();
+}
+
+/// A handle for releasing the host memory backing guest RAM.
+#[derive(Clone)]
+pub struct MemoryReclaim(Arc<dyn ReclaimMemory>);
+
+impl MemoryReclaim {
+    /// Creates a new handle.
+    pub fn new<T: ReclaimMemory + 'static>(reclaim: T) -> Self {
+        Self(Arc::new(reclaim))
+    }
+
+    /// Releases the host memory backing the guest RAM at `gpa..gpa + len`.
+    ///
+    /// See [`ReclaimMemory::reclaim`].
+    pub fn reclaim(&self, gpa: u64, len: u64) -> std::io::Result<()> {
+        self.0.reclaim(gpa, len)
+    }
+}
+
+impl ResolveResource<MemoryReclaimKind, PlatformResource> for MemoryReclaim {
+    type Output = MemoryReclaim;
+    type Error = Infallible;
+
+    fn resolve(
+        &self,
+        PlatformResource: PlatformResource,
+        (): (),
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(self.clone())
+    }
+}
@@ -325,6 +325,10 @@ pub mod thread {
     use pal_async::DefaultPool;
     use pal_async::driver::Driver;
     use pal_async::task::Spawn;
+    use std::sync::Arc;
+
+    /// A function that places the calling thread near the given VP.
+    type VpAffinityFn = Arc<dyn Fn(u32) + Send + Sync>;
 
     /// A backend for [`VmTaskDriverSource`](super::VmTaskDriverSource) based on
     /// individual threads.
@@ -332,16 +336,42 @@ pub mod thread {
     /// If no target VP is specified, this backend will spawn tasks and IO a
     /// default single-threaded IO driver. If a target VP is specified, the
     /// backend will spawn a separate thread and spawn tasks and IOs there.
-    #[derive(Debug)]
     pub struct ThreadDriverBackend {
         default_driver: DefaultDriver,
+        vp_affinity: Option<VpAffinityFn>,
+    }
+
+    impl std::fmt::Debug for ThreadDriverBackend {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("ThreadDriverBackend")
+                .field("default_driver", &self.default_driver)
+                .field("vp_affinity", &self.vp_affinity.is_some())
+                .finish()
+        }
     }
 
     impl ThreadDriverBackend {
         /// Returns a new backend, using `default_driver` to back task drivers
         /// that did not specify a target VP.
         pub fn new(default_driver: DefaultDriver) -> Self {
-            Self { default_driver }
+            Self {
+                default_driver,
+                vp_affinity: None,
+            }
+        }
+
+        /// Calls `set_affinity` on each dedicated thread with the thread's
+        /// target VP, and again whenever the driver is retargeted to a
+        /// different VP.
+        ///
+        /// This allows the caller to pin device threads near the host CPUs
+        /// running the corresponding VPs.
+        pub fn with_vp_affinity(
+            mut self,
+            set_affinity: impl Fn(u32) + Send + Sync + 'static,
+        ) -> Self {
+            self.vp_affinity = Some(Arc::new(set_affinity));
+            self
         }
     }
 
@@ -355,27 +385,53 @@ fn build(
             _run_on_target: bool,
         ) -> Self::Driver {
             // Build a standalone thread for this device if a target VP was specified.
-            if target_vp.is_some() {
+            if let Some(target_vp) = target_vp {
                 let (_, driver) = DefaultPool::spawn_on_thread(name);
-                ThreadDriver {
+                let driver = ThreadDriver {
                     inner: driver,
                     has_dedicated_thread: true,
-                }
+                    vp_affinity: self.vp_affinity.clone(),
+                };
+                driver.set_affinity(target_vp);
+                driver
             } else {
                 ThreadDriver {
                     inner: self.default_driver.clone(),
                     has_dedicated_thread: false,
+                    vp_affinity: None,
                 }
             }
         }
     }
 
     /// The driver for [`ThreadDriverBackend`].
-    #[derive(Debug, Inspect)]
+    #[derive(Inspect)]
     pub struct ThreadDriver {
         #[inspect(skip)]
         inner: DefaultDriver,
         has_dedicated_thread: bool,
+        #[inspect(skip)]
+        vp_affinity: Option<VpAffinityFn>,
+    }
+
+    impl std::fmt::Debug for ThreadDriver {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("ThreadDriver")
+                .field("inner", &self.inner)
+                .field("has_dedicated_thread", &self.has_dedicated_thread)
+                .finish()
+        }
+    }
+
+    impl ThreadDriver {
+        fn set_affinity(&self, target_vp: u32) {
+            if let Some(vp_affinity) = self.vp_affinity.clone() {
+                // Run on the dedicated thread so that it is the one affected.
+                self.inner
+                    .spawn("vp-affinity", async move { vp_affinity(target_vp) })
+                    .detach();
+            }
+        }
     }
 
     impl TargetedDriver for ThreadDriver {
@@ -387,6 +443,8 @@ fn driver(&self) -> &dyn Driver {
             &self.inner
         }
 
-        fn retarget_vp(&self, _target_vp: u32) {}
+        fn retarget_vp(&self, target_vp: u32) {
+            self.set_affinity(target_vp);
+        }
     }
 }
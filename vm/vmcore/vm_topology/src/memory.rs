@@ -142,7 +142,33 @@ pub fn new(
         pci_mmio_gaps: &[MemoryRange],
         vtl2_range: Option<MemoryRange>,
     ) -> Result<Self, Error> {
-        if ram_size == 0 || ram_size & (PAGE_SIZE - 1) != 0 {
+        Self::new_with_nodes(
+            &[ram_size],
+            mmio_gaps,
+            pci_ecam_gaps,
+            pci_mmio_gaps,
+            vtl2_range,
+        )
+    }
+
+    /// Makes a new memory layout like [`new`](Self::new), but with RAM split
+    /// across NUMA nodes.
+    ///
+    /// `node_ram_sizes` contains the number of bytes of RAM in each node,
+    /// indexed by vnode. Each node's RAM is placed after the previous node's,
+    /// so a node's RAM may be split across multiple ranges by the gaps.
+    pub fn new_with_nodes(
+        node_ram_sizes: &[u64],
+        mmio_gaps: &[MemoryRange],
+        pci_ecam_gaps: &[MemoryRange],
+        pci_mmio_gaps: &[MemoryRange],
+        vtl2_range: Option<MemoryRange>,
+    ) -> Result<Self, Error> {
+        if node_ram_sizes.is_empty()
+            || node_ram_sizes
+                .iter()
+                .any(|&size| size == 0 || size & (PAGE_SIZE - 1) != 0)
+        {
             return Err(Error::BadSize);
         }
 
@@ -160,25 +186,30 @@ pub fn new(
         validate_ranges(&combined_gaps)?;
 
         let mut ram = Vec::new();
-        let mut remaining = ram_size;
-        let mut remaining_gaps = combined_gaps.iter().cloned();
-        let mut last_end = 0;
-
-        while remaining > 0 {
-            let (this, next_end) = if let Some(gap) = remaining_gaps.next() {
-                (remaining.min(gap.start() - last_end), gap.end())
-            } else {
-                (remaining, 0)
-            };
-
-            if this > 0 {
+        let mut remaining_gaps = combined_gaps.iter().peekable();
+        let mut next = 0;
+
+        for (vnode, &size) in node_ram_sizes.iter().enumerate() {
+            let mut remaining = size;
+            while remaining > 0 {
+                let limit = match remaining_gaps.peek() {
+                    Some(gap) if gap.start() <= next => {
+                        next = gap.end();
+                        remaining_gaps.next();
+                        continue;
+                    }
+                    Some(gap) => gap.start(),
+                    None => u64::MAX,
+                };
+
+                let this = remaining.min(limit - next);
                 ram.push(MemoryRangeWithNode {
-                    range: MemoryRange::new(last_end..last_end + this),
-                    vnode: 0,
+                    range: MemoryRange::new(next..next + this),
+                    vnode: vnode as u32,
                 });
+                remaining -= this;
+                next += this;
             }
-            remaining -= this;
-            last_end = next_end;
         }
 
         Self::build(
@@ -444,6 +475,46 @@ fn layout() {
         assert_eq!(layout.end_of_layout(), TB + 2 * GB);
     }
 
+    #[test]
+    fn layout_with_nodes() {
+        let mmio = &[
+            MemoryRange::new(GB..2 * GB),
+            MemoryRange::new(3 * GB..4 * GB),
+        ];
+
+        let layout =
+            MemoryLayout::new_with_nodes(&[GB + GB / 2, GB, 2 * GB], mmio, &[], &[], None).unwrap();
+        assert_eq!(
+            layout.ram(),
+            &[
+                MemoryRangeWithNode {
+                    range: MemoryRange::new(0..GB),
+                    vnode: 0
+                },
+                MemoryRangeWithNode {
+                    range: MemoryRange::new(2 * GB..2 * GB + GB / 2),
+                    vnode: 0
+                },
+                MemoryRangeWithNode {
+                    range: MemoryRange::new(2 * GB + GB / 2..3 * GB),
+                    vnode: 1
+                },
+                MemoryRangeWithNode {
+                    range: MemoryRange::new(4 * GB..4 * GB + GB / 2),
+                    vnode: 1
+                },
+                MemoryRangeWithNode {
+                    range: MemoryRange::new(4 * GB + GB / 2..6 * GB + GB / 2),
+                    vnode: 2
+                },
+            ]
+        );
+        assert_eq!(layout.ram_size(), 4 * GB + GB / 2);
+
+        MemoryLayout::new_with_nodes(&[], mmio, &[], &[], None).unwrap_err();
+        MemoryLayout::new_with_nodes(&[GB, 0], mmio, &[], &[], None).unwrap_err();
+    }
+
     #[test]
     fn bad_layout() {
         MemoryLayout::new(TB + 1, &[], &[], &[], None).unwrap_err();
@@ -57,6 +57,7 @@ pub trait ArchTopology: Sized {
 pub struct TopologyBuilder<T: ArchTopology> {
     vps_per_socket: u32,
     smt_enabled: bool,
+    vnodes: Option<Vec<u32>>,
     arch: T::BuilderState,
 }
 
@@ -91,6 +92,14 @@ pub enum InvalidTopology {
     /// VpInfo indices must be linear and start at 0
     #[error("vp indices don't start at 0 or don't count up")]
     InvalidVpIndices,
+    /// The number of vnodes does not match the number of processors.
+    #[error("{vnodes} vnodes specified for {proc_count} processors")]
+    VnodeCountMismatch {
+        /// The number of vnodes specified.
+        vnodes: usize,
+        /// The number of processors requested.
+        proc_count: u32,
+    },
     /// Failed to query the topology information from Device Tree.
     #[error("failed to query memory topology from device tree")]
     StdIoError(#[source] std::io::Error),
@@ -116,6 +125,28 @@ pub fn smt_enabled(&mut self, enabled: bool) -> &mut Self {
         self.smt_enabled = enabled;
         self
     }
+
+    /// Sets the virtual NUMA node of each processor, indexed by VP index.
+    ///
+    /// If this is not set, the default is architecture specific.
+    pub fn vnodes(&mut self, vnodes: Vec<u32>) -> &mut Self {
+        self.vnodes = Some(vnodes);
+        self
+    }
+
+    /// Returns the configured vnode for each of `proc_count` processors, or
+    /// `None` if they have not been configured.
+    fn configured_vnodes(&self, proc_count: u32) -> Result<Option<&[u32]>, InvalidTopology> {
+        match &self.vnodes {
+            Some(vnodes) if vnodes.len() != proc_count as usize => {
+                Err(InvalidTopology::VnodeCountMismatch {
+                    vnodes: vnodes.len(),
+                    proc_count,
+                })
+            }
+            vnodes => Ok(vnodes.as_deref()),
+        }
+    }
 }
 
 impl<
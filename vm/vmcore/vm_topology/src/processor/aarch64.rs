@@ -84,11 +84,15 @@ pub fn new_aarch64(gic: GicInfo, pmu_gsiv: u32) -> Self {
         Self {
             vps_per_socket: 1,
             smt_enabled: false,
+            vnodes: None,
             arch: Aarch64TopologyBuilderState { gic, pmu_gsiv },
         }
     }
 
     /// Builds a processor topology with `proc_count` processors.
+    ///
+    /// Unless configured via [`vnodes`](Self::vnodes), all processors are in
+    /// NUMA node 0.
     pub fn build(
         &self,
         proc_count: u32,
@@ -99,6 +103,7 @@ pub fn build(
                 max: u8::MAX.into(),
             });
         }
+        let vnodes = self.configured_vnodes(proc_count)?;
         let mpidrs = (0..proc_count).map(|vp_index| {
             // TODO: construct mpidr appropriately for the specified
             // topology.
@@ -115,7 +120,7 @@ pub fn build(
         self.build_with_vp_info(mpidrs.enumerate().map(|(id, mpidr)| Aarch64VpInfo {
             base: VpInfo {
                 vp_index: VpIndex::new(id as u32),
-                vnode: 0,
+                vnode: vnodes.map_or(0, |vnodes| vnodes[id]),
             },
             mpidr,
             gicr: self.arch.gic.gic_redistributors_base
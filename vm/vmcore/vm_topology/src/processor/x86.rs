@@ -78,6 +78,7 @@ pub fn new_x86() -> Self {
         Self {
             vps_per_socket: 1,
             smt_enabled: false,
+            vnodes: None,
             arch: Default::default(),
         }
     }
@@ -181,6 +182,7 @@ pub fn from_cpuid(
         Ok(Self {
             smt_enabled: threads_per_core > 1 && vps_per_socket > 1,
             vps_per_socket,
+            vnodes: None,
             arch: Default::default(),
         })
     }
@@ -199,16 +201,19 @@ pub fn x2apic(&mut self, x2apic: X2ApicState) -> &mut Self {
     }
 
     /// Builds a processor topology with `proc_count` processors.
+    ///
+    /// Unless configured via [`vnodes`](Self::vnodes), each socket is its
+    /// own NUMA node.
     pub fn build(
         &self,
         proc_count: u32,
     ) -> Result<ProcessorTopology<X86Topology>, InvalidTopology> {
         let vps_per_socket = self.vps_per_socket.next_power_of_two();
         let socket_offset = self.arch.apic_id_offset / vps_per_socket;
+        let vnodes = self.configured_vnodes(proc_count)?;
         let vps = (0..proc_count).map(|n| {
             let vp_index = VpIndex::new(n);
-            // FUTURE: support multiple NUMA nodes per socket.
-            let vnode = n / vps_per_socket;
+            let vnode = vnodes.map_or(n / vps_per_socket, |vnodes| vnodes[n as usize]);
             let socket = socket_offset + n / self.vps_per_socket;
             let proc = n % self.vps_per_socket;
             let apic_id = socket * vps_per_socket + proc;
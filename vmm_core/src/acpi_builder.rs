@@ -45,6 +45,11 @@ pub struct AcpiTablesBuilder<'a, T: AcpiTopology> {
     ///
     /// If and only if this is set, then the PPTT table will be generated.
     pub cache_topology: Option<&'a CacheTopology>,
+    /// The distances between the virtual NUMA nodes, indexed by vnode. Each
+    /// row must have an entry for each vnode.
+    ///
+    /// If and only if this is set, then the SLIT table will be generated.
+    pub numa_distances: Option<&'a [Vec<u8>]>,
     /// The PCIe topology.
     ///
     /// If and only if this has root complexes, then an MCFG will be generated.
@@ -184,6 +189,25 @@ fn with_srat<F, R>(&self, f: F) -> R
         ))
     }
 
+    fn with_slit<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&acpi::builder::Table<'_>) -> R,
+    {
+        let distances = self.numa_distances.expect("numa distances are required");
+        let mut slit_extra: Vec<u8> = Vec::new();
+        for row in distances {
+            assert_eq!(row.len(), distances.len(), "numa distances must be square");
+            slit_extra.extend_from_slice(row);
+        }
+
+        (f)(&acpi::builder::Table::new_dyn(
+            acpi_spec::slit::SLIT_REVISION,
+            None,
+            &acpi_spec::slit::SlitHeader::new(distances.len() as u64),
+            &[slit_extra.as_slice()],
+        ))
+    }
+
     fn with_madt<F, R>(&self, f: F) -> R
     where
         F: FnOnce(&acpi::builder::Table<'_>) -> R,
@@ -571,6 +595,9 @@ fn build_acpi_tables_inner(&self, gpa: u64, dsdt: &[u8]) -> BuiltAcpiTables {
 
         self.with_madt(|t| b.append(t));
         self.with_srat(|t| b.append(t));
+        if self.numa_distances.is_some() {
+            self.with_slit(|t| b.append(t));
+        }
         if !self.pcie_host_bridges.is_empty() {
             self.with_mcfg(|t| b.append(t));
         }
@@ -595,6 +622,15 @@ pub fn build_srat(&self) -> Vec<u8> {
         self.with_srat(|t| t.to_vec(&OEM_INFO))
     }
 
+    /// Helper method to construct a SLIT without constructing the rest of the
+    /// ACPI tables.
+    ///
+    /// # Panics
+    /// Panics if `self.numa_distances` is not set.
+    pub fn build_slit(&self) -> Vec<u8> {
+        self.with_slit(|t| t.to_vec(&OEM_INFO))
+    }
+
     /// Helper method to construct a MCFG without constructing the rest of the
     /// ACPI tables.
     pub fn build_mcfg(&self) -> Vec<u8> {
@@ -645,6 +681,7 @@ fn new_builder<'a>(
             processor_topology,
             mem_layout,
             cache_topology: None,
+            numa_distances: None,
             pcie_host_bridges,
             with_ioapic: true,
             with_pic: false,
@@ -745,4 +782,23 @@ fn test_basic_pcie_topology() {
         })
         .unwrap();
     }
+
+    #[test]
+    fn test_slit() {
+        let mem = new_mem();
+        let topology = TopologyBuilder::new_x86().build(4).unwrap();
+        let distances = [vec![10, 21], vec![21, 10]];
+        let mut builder = new_builder(&mem, &topology, &vec![]);
+        builder.numa_distances = Some(&distances);
+        let slit = builder.build_slit();
+
+        let header_len = size_of::<acpi_spec::Header>();
+        assert_eq!(&slit[..4], b"SLIT");
+        assert_eq!(slit.len(), header_len + 8 + 4);
+        assert_eq!(
+            u64::from_le_bytes(slit[header_len..header_len + 8].try_into().unwrap()),
+            2
+        );
+        assert_eq!(&slit[header_len + 8..], &[10, 21, 21, 10]);
+    }
 }
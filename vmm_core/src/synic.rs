@@ -1,10 +1,16 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+use futures::StreamExt;
+use futures_concurrency::future::Race;
 use hvdef::HvError;
 use hvdef::HvResult;
 use hvdef::Vtl;
 use inspect::Inspect;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use pal_async::timer::Instant;
+use pal_async::timer::PolledTimer;
 use parking_lot::Mutex;
 use std::collections::HashMap;
 use std::collections::hash_map;
@@ -13,6 +19,7 @@
 use std::sync::Weak;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Duration;
 use virt::Synic;
 use virt::VpIndex;
 use vmcore::synic::EventPort;
@@ -23,19 +30,31 @@
 use vmcore::synic::MonitorPageGpas;
 use vmcore::synic::SynicMonitorAccess;
 use vmcore::synic::SynicPortAccess;
+use vmcore::vm_task::VmTaskDriver;
 
 pub struct SynicPorts {
     partition: Arc<dyn Synic>,
     ports: Arc<PortMap>,
+    deferred_send: mesh::Sender<DeferredSignal>,
+    _monitor_scan: Task<()>,
 }
 
 type PortMap = Mutex<HashMap<u32, Port>>;
 
 impl SynicPorts {
-    pub fn new(partition: Arc<dyn Synic>) -> Self {
+    /// Creates a new instance. `driver` is used to deliver signals to
+    /// monitored ports once their latency has elapsed.
+    pub fn new(partition: Arc<dyn Synic>, driver: VmTaskDriver) -> Self {
+        let (deferred_send, deferred_recv) = mesh::channel();
+        let monitor_scan = driver.spawn(
+            "synic-monitor-scan",
+            run_monitor_scan(driver.clone(), deferred_recv),
+        );
         Self {
             partition,
             ports: Default::default(),
+            deferred_send,
+            _monitor_scan: monitor_scan,
         }
     }
 
@@ -50,6 +69,7 @@ pub fn on_post_message(
         if let Some(Port {
             port_type: PortType::Message(port),
             minimum_vtl,
+            ..
         }) = port
         {
             if vtl < minimum_vtl {
@@ -77,12 +97,17 @@ pub fn on_signal_event(&self, vtl: Vtl, connection_id: u32, flag_number: u16) ->
         if let Some(Port {
             port_type: PortType::Event(port),
             minimum_vtl,
+            monitor,
         }) = port
         {
             if vtl < minimum_vtl {
                 Err(HvError::OperationDenied)
             } else {
-                port.handle_event(flag_number);
+                if let Some(monitor) = monitor {
+                    monitor.signal(&self.deferred_send, flag_number);
+                } else {
+                    port.handle_event(flag_number);
+                }
                 Ok(())
             }
         } else {
@@ -106,6 +131,7 @@ fn add_message_port(
                 e.insert(Port {
                     port_type: PortType::Message(port),
                     minimum_vtl,
+                    monitor: None,
                 });
             }
         }
@@ -132,6 +158,19 @@ fn add_event_port(
             None
         };
 
+        // Signals to a monitored port are batched for the monitor's latency,
+        // as the hypervisor does when scanning the monitor page.
+        let monitor = monitor_info
+            .as_ref()
+            .filter(|info| !info.latency.is_zero())
+            .map(|info| {
+                Arc::new(MonitoredPort {
+                    port: port.clone(),
+                    latency: info.latency,
+                    pending: Mutex::new(Vec::new()),
+                })
+            });
+
         match self.ports.lock().entry(connection_id) {
             hash_map::Entry::Occupied(_) => {
                 return Err(vmcore::synic::Error::ConnectionIdInUse(connection_id));
@@ -140,6 +179,7 @@ fn add_event_port(
                 e.insert(Port {
                     port_type: PortType::Event(port),
                     minimum_vtl,
+                    monitor,
                 });
             }
         }
@@ -235,6 +275,107 @@ fn drop(&mut self) {
 struct Port {
     port_type: PortType,
     minimum_vtl: Vtl,
+    monitor: Option<Arc<MonitoredPort>>,
+}
+
+/// An event port whose signals are delivered after the monitor latency.
+struct MonitoredPort {
+    port: Arc<dyn EventPort>,
+    latency: Duration,
+    /// The flags signaled since the last delivery. A deferred signal is
+    /// queued when the first flag is added, and repeated signals of the same
+    /// flag are coalesced.
+    pending: Mutex<Vec<u16>>,
+}
+
+impl Debug for MonitoredPort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MonitoredPort")
+            .field("latency", &self.latency)
+            .field("pending", &*self.pending.lock())
+            .finish()
+    }
+}
+
+impl MonitoredPort {
+    fn signal(self: &Arc<Self>, send: &mesh::Sender<DeferredSignal>, flag: u16) {
+        let mut pending = self.pending.lock();
+        if pending.contains(&flag) {
+            return;
+        }
+        pending.push(flag);
+        if pending.len() == 1 {
+            send.send(DeferredSignal {
+                deadline: Instant::now() + self.latency,
+                port: Arc::downgrade(self),
+            });
+        }
+    }
+}
+
+/// A signal to a monitored port that is waiting for its latency to elapse.
+struct DeferredSignal {
+    deadline: Instant,
+    /// Weak so that signals to removed ports are dropped.
+    port: Weak<MonitoredPort>,
+}
+
+impl DeferredSignal {
+    fn deliver(&self) {
+        if let Some(port) = self.port.upgrade() {
+            // Take the pending flags first so that a signal that arrives while
+            // delivering starts a new batch instead of being lost.
+            let flags = std::mem::take(&mut *port.pending.lock());
+            for flag in flags {
+                port.port.handle_event(flag);
+            }
+        }
+    }
+}
+
+/// Delivers deferred signals to monitored ports once their deadlines pass.
+async fn run_monitor_scan(driver: VmTaskDriver, mut recv: mesh::Receiver<DeferredSignal>) {
+    enum Event {
+        Signal(DeferredSignal),
+        Timeout,
+        Done,
+    }
+
+    let mut timer = PolledTimer::new(&driver);
+    let mut deferred = Vec::<DeferredSignal>::new();
+    loop {
+        let next_deadline = deferred.iter().map(|signal| signal.deadline).min();
+        let event = (
+            async { recv.next().await.map_or(Event::Done, Event::Signal) },
+            async {
+                match next_deadline {
+                    Some(deadline) => {
+                        timer.sleep_until(deadline).await;
+                        Event::Timeout
+                    }
+                    None => std::future::pending().await,
+                }
+            },
+        )
+            .race()
+            .await;
+
+        match event {
+            Event::Signal(signal) => deferred.push(signal),
+            Event::Timeout => {
+                let now = Instant::now();
+                deferred.retain(|signal| {
+                    if signal.deadline <= now {
+                        signal.deliver();
+                        false
+                    } else {
+                        true
+                    }
+                });
+            }
+            Event::Done => break,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -278,3 +419,77 @@ fn inspect(&self, req: inspect::Request<'_>) {
         req.respond().field("message_port_vp", self.vp.index());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use vmcore::vm_task::SingleDriverBackend;
+    use vmcore::vm_task::VmTaskDriverSource;
+
+    const LATENCY: Duration = Duration::from_millis(10);
+
+    struct RecordingPort(mesh::Sender<u16>);
+
+    impl EventPort for RecordingPort {
+        fn handle_event(&self, flag: u16) {
+            self.0.send(flag);
+        }
+    }
+
+    fn monitored_port() -> (Arc<MonitoredPort>, mesh::Receiver<u16>) {
+        let (send, recv) = mesh::channel();
+        let port = Arc::new(MonitoredPort {
+            port: Arc::new(RecordingPort(send)),
+            latency: LATENCY,
+            pending: Mutex::new(Vec::new()),
+        });
+        (port, recv)
+    }
+
+    fn start_scan(driver: DefaultDriver) -> (Task<()>, mesh::Sender<DeferredSignal>) {
+        let driver = VmTaskDriverSource::new(SingleDriverBackend::new(driver)).simple();
+        let (send, recv) = mesh::channel();
+        let task = driver.spawn("monitor-scan", run_monitor_scan(driver.clone(), recv));
+        (task, send)
+    }
+
+    #[async_test]
+    async fn test_monitor_scan_coalesces_per_flag(driver: DefaultDriver) {
+        let (_task, send) = start_scan(driver);
+        let (port, mut events) = monitored_port();
+
+        let start = Instant::now();
+        port.signal(&send, 1);
+        port.signal(&send, 2);
+        port.signal(&send, 1);
+        assert_eq!(events.next().await, Some(1));
+        assert_eq!(events.next().await, Some(2));
+        assert!(Instant::now() - start >= LATENCY);
+        assert!(events.try_recv().is_err());
+
+        // A signal after delivery starts a new batch.
+        port.signal(&send, 2);
+        assert_eq!(events.next().await, Some(2));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[async_test]
+    async fn test_monitor_scan_separate_ports(driver: DefaultDriver) {
+        let (_task, send) = start_scan(driver);
+        let (port1, mut events1) = monitored_port();
+        let (port2, mut events2) = monitored_port();
+        let (removed, mut removed_events) = monitored_port();
+
+        port1.signal(&send, 3);
+        removed.signal(&send, 3);
+        port2.signal(&send, 3);
+        drop(removed);
+
+        assert_eq!(events1.next().await, Some(3));
+        assert_eq!(events2.next().await, Some(3));
+        // Signals to a removed port are dropped along with the port.
+        assert_eq!(removed_events.next().await, None);
+    }
+}
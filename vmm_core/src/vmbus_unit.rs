@@ -18,6 +18,7 @@
 use vm_resource::Resource;
 use vm_resource::ResourceResolver;
 use vm_resource::kind::VmbusDeviceHandleKind;
+use vmbus_channel::bus::OfferKey;
 use vmbus_channel::channel::ChannelHandle;
 use vmbus_channel::channel::VmbusDevice;
 use vmbus_channel::channel::offer_channel;
@@ -217,6 +218,25 @@ async fn restore(&mut self, state: SavedStateBlob) -> Result<(), RestoreError> {
     }
 }
 
+/// A vmbus device resolved from a resource and registered as a state unit.
+#[derive(Debug)]
+pub struct VmbusDeviceUnit {
+    key: OfferKey,
+    unit: SpawnedUnit<ChannelUnit<dyn VmbusDevice>>,
+}
+
+impl VmbusDeviceUnit {
+    /// Gets the offer key of the device's channel.
+    pub fn key(&self) -> OfferKey {
+        self.key
+    }
+
+    /// Removes the unit and revokes the channel, returning the device.
+    pub async fn revoke(self) -> Box<dyn VmbusDevice> {
+        self.unit.remove().await.0.revoke().await.unwrap()
+    }
+}
+
 /// Offers a channel, creates a unit for it, and adds it to `state_units`.
 pub async fn offer_vmbus_device_handle_unit(
     driver_source: &VmTaskDriverSource,
@@ -224,7 +244,7 @@ pub async fn offer_vmbus_device_handle_unit(
     vmbus: &VmbusServerHandle,
     resolver: &ResourceResolver,
     resource: Resource<VmbusDeviceHandleKind>,
-) -> anyhow::Result<SpawnedUnit<ChannelUnit<dyn VmbusDevice>>> {
+) -> anyhow::Result<VmbusDeviceUnit> {
     let channel = resolver
         .resolve(resource, ResolveVmbusDeviceHandleParams { driver_source })
         .await?;
@@ -238,5 +258,8 @@ pub async fn offer_vmbus_device_handle_unit(
         .spawn(driver_source.simple(), |recv| {
             run_async_unit(ChannelUnit(handle), recv)
         })?;
-    Ok(unit)
+    Ok(VmbusDeviceUnit {
+        key: offer.key(),
+        unit,
+    })
 }
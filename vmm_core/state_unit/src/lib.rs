@@ -44,8 +44,10 @@
 use mesh::rpc::Rpc;
 use mesh::rpc::RpcError;
 use mesh::rpc::RpcSend;
+use pal_async::driver::Driver;
 use pal_async::task::Spawn;
 use pal_async::task::Task;
+use pal_async::timer::PolledTimer;
 use parking_lot::Mutex;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
@@ -58,6 +60,7 @@
 use std::sync::Weak;
 use std::sync::atomic::AtomicU32;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 use std::time::Instant;
 use thiserror::Error;
 use tracing::Instrument;
@@ -207,6 +210,21 @@ pub async fn apply(self, unit: &mut impl StateUnit) {
 pub struct StateUnits {
     inner: Arc<Mutex<Inner>>,
     running: bool,
+    slow_units: Option<SlowUnitReporting>,
+}
+
+/// Configuration for reporting units that are slow to change state.
+struct SlowUnitReporting {
+    driver: Box<dyn Driver>,
+    timeout: Duration,
+}
+
+impl Debug for SlowUnitReporting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SlowUnitReporting")
+            .field("timeout", &self.timeout)
+            .finish()
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Inspect)]
@@ -234,6 +252,8 @@ struct Unit {
     dependencies: Vec<u64>,
     dependents: Vec<u64>,
     state: State,
+    slow_timeout: Option<Duration>,
+    slow_state_changes: u64,
 }
 
 /// An error returned when a state unit name is already in use.
@@ -334,6 +354,7 @@ fn inspect(&self, req: inspect::Request<'_>) {
                     });
                 }
                 resp.field("unit_state", unit.state)
+                    .counter("slow_state_changes", unit.slow_state_changes)
                     .merge(&inspect::send(&unit.send, StateRequest::Inspect));
             });
         }
@@ -421,9 +442,25 @@ pub fn new() -> Self {
                 names: HashMap::new(),
             })),
             running: false,
+            slow_units: None,
         }
     }
 
+    /// Reports units that take longer than `timeout` to complete a state
+    /// change, using `driver` for timers.
+    ///
+    /// A slow unit is logged with its name and the operation, and again once it
+    /// completes. Each unit's `slow_state_changes` inspect counter tracks how
+    /// often this has happened. The operation still waits for it, so that the units are
+    /// always fully stopped before a save. Use [`UnitBuilder::slow_timeout`] to
+    /// set a different timeout for an individual unit.
+    pub fn report_slow_units(&mut self, driver: impl Driver, timeout: Duration) {
+        self.slow_units = Some(SlowUnitReporting {
+            driver: Box::new(driver),
+            timeout,
+        });
+    }
+
     /// Returns an inspector that can be used to inspect the state units while
     /// state transitions are in process.
     pub fn inspector(&self) -> StateUnitsInspector {
@@ -444,6 +481,7 @@ pub fn add(&self, name: impl Into<Arc<str>>) -> UnitBuilder<'_> {
             name: name.into(),
             dependencies: Vec::new(),
             dependents: Vec::new(),
+            slow_timeout: None,
         }
     }
 
@@ -673,11 +711,17 @@ async fn run_op<I: 'static, R: 'static + Send>(
                     let ready_set = ready_set.clone();
                     let deps = deps(unit).to_vec();
                     let fut = state_change(name.clone(), unit, request, input);
+                    let slow = self.slow_units.as_ref().map(|slow| {
+                        (
+                            PolledTimer::new(&slow.driver),
+                            unit.slow_timeout.unwrap_or(slow.timeout),
+                        )
+                    });
                     let recv = async move {
                         ready_set.wait(op, id, &deps).await;
-                        let r = fut.await;
+                        let (r, was_slow) = report_if_slow(op, &name, slow, fut).await;
                         ready_set.done(id, true);
-                        (name, id, r)
+                        (name, id, r, was_slow)
                     };
                     done.push(recv);
                     unit.state = interim_state;
@@ -697,7 +741,10 @@ async fn run_op<I: 'static, R: 'static + Send>(
         let mut inner = self.inner.lock();
         let r = results
             .into_iter()
-            .filter_map(|(name, id, r)| {
+            .filter_map(|(name, id, r, was_slow)| {
+                if was_slow && let Some(unit) = inner.units.get_mut(&id) {
+                    unit.slow_state_changes += 1;
+                }
                 match r {
                     Ok(Some(r)) => Some((name, r)),
                     Ok(None) => None,
@@ -819,6 +866,41 @@ fn state_change<I: 'static, R: 'static + Send, Req: FnOnce(Rpc<I, R>) -> StateRe
     }
 }
 
+/// Waits for `fut`, logging if it takes longer than the timeout in `slow`.
+///
+/// Returns the output of `fut` and whether it was reported as slow.
+async fn report_if_slow<T>(
+    op: &str,
+    name: &str,
+    slow: Option<(PolledTimer, Duration)>,
+    fut: impl Future<Output = T>,
+) -> (T, bool) {
+    let Some((mut timer, timeout)) = slow else {
+        return (fut.await, false);
+    };
+    let start = Instant::now();
+    let mut fut = pin!(fut);
+    let mut sleep = pin!(timer.sleep(timeout));
+    match futures::future::select(fut.as_mut(), sleep.as_mut()).await {
+        futures::future::Either::Left((r, _)) => return (r, false),
+        futures::future::Either::Right(_) => {}
+    }
+    tracing::warn!(
+        device = name,
+        operation = op,
+        ?timeout,
+        "device is slow to complete state change"
+    );
+    let r = fut.await;
+    tracing::warn!(
+        device = name,
+        operation = op,
+        duration = ?Instant::now() - start,
+        "slow device completed state change"
+    );
+    (r, true)
+}
+
 /// A builder returned by [`StateUnits::add`].
 #[derive(Debug)]
 #[must_use]
@@ -827,6 +909,7 @@ pub struct UnitBuilder<'a> {
     name: Arc<str>,
     dependencies: Vec<u64>,
     dependents: Vec<u64>,
+    slow_timeout: Option<Duration>,
 }
 
 impl UnitBuilder<'_> {
@@ -848,6 +931,14 @@ pub fn dependency_of(mut self, handle: &UnitHandle) -> Self {
         self
     }
 
+    /// Sets the time this unit may take to change state before it is reported
+    /// as slow, overriding the timeout passed to
+    /// [`StateUnits::report_slow_units`].
+    pub fn slow_timeout(mut self, timeout: Duration) -> Self {
+        self.slow_timeout = Some(timeout);
+        self
+    }
+
     fn handle_id(&self, handle: &UnitHandle) -> u64 {
         // Ensure this handle is associated with this set of state units.
         assert_eq!(
@@ -891,6 +982,8 @@ pub fn build(mut self, send: Sender<StateRequest>) -> Result<UnitHandle, NameInU
                     dependencies: self.dependencies,
                     dependents: self.dependents,
                     state: State::Stopped,
+                    slow_timeout: self.slow_timeout,
+                    slow_state_changes: 0,
                 },
             );
             let unit_id = UnitId {
@@ -1237,4 +1330,55 @@ async fn test_dep_no_saved_state(driver: DefaultDriver) {
 
         units.restore(state).await.unwrap();
     }
+
+    #[async_test]
+    async fn test_slow_unit(driver: DefaultDriver) {
+        let mut units = StateUnits::new();
+        units.report_slow_units(driver.clone(), Duration::from_secs(60));
+
+        let shared_val = Arc::new(AtomicBool::new(false));
+
+        // Restoring this unit takes longer than its timeout, but the
+        // operation still waits for it to complete.
+        let slow = units
+            .add("slow")
+            .slow_timeout(Duration::from_millis(10))
+            .spawn(&driver, |recv| {
+                run_unit(
+                    TestUnitSetDep {
+                        dep: shared_val.clone(),
+                        driver: driver.clone(),
+                    },
+                    recv,
+                )
+            })
+            .unwrap();
+
+        let _a = units
+            .add("a")
+            .depends_on(slow.handle())
+            .spawn(&driver, |recv| {
+                run_unit(
+                    TestUnit {
+                        value: Arc::new(AtomicBool::new(true)),
+                        dep: Some(shared_val.clone()),
+                        support_saved_state: true,
+                    },
+                    recv,
+                )
+            })
+            .unwrap();
+
+        let state = units.save().await.unwrap();
+        units.restore(state).await.unwrap();
+        assert!(shared_val.load(Ordering::Relaxed));
+
+        // Only the slow unit's restore exceeded its timeout.
+        let slow_state_changes = |name: &str| {
+            let inner = units.inner.lock();
+            inner.units[&inner.names[name]].slow_state_changes
+        };
+        assert_eq!(slow_state_changes("slow"), 1);
+        assert_eq!(slow_state_changes("a"), 0);
+    }
 }
@@ -43,6 +43,32 @@ fn pin_range(&self, _addr: u64, _size: u64) -> anyhow::Result<()> {
         Ok(())
     }
 
+    /// Starts or stops logging guest writes to the ranges mapped within the
+    /// given range. Starting logging clears any previously logged writes.
+    ///
+    /// Ranges mapped within the given range while logging is enabled are also
+    /// logged.
+    ///
+    /// Returns `false` if the partition does not support dirty page logging.
+    fn set_dirty_logging(&self, addr: u64, size: u64, enable: bool) -> anyhow::Result<bool> {
+        let _ = (addr, size, enable);
+        Ok(false)
+    }
+
+    /// Gets and clears the set of pages in the given range that the guest has
+    /// written since logging was enabled or since the last call, setting one
+    /// bit per 4KB page in `bitmap`.
+    ///
+    /// The specified range must completely contain any ranges mapped with
+    /// `map_range` that it overlaps. Bits for pages that are not mapped are
+    /// cleared.
+    ///
+    /// Fails if logging is not enabled for the range.
+    fn get_dirty_pages(&self, addr: u64, size: u64, bitmap: &mut [u64]) -> anyhow::Result<()> {
+        let _ = (addr, size, bitmap);
+        anyhow::bail!("dirty page logging not supported")
+    }
+
     /// Maps a range residing in a remote process.
     ///
     /// This may fail if the range overlaps any other mapped range.
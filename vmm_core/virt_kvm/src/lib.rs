@@ -24,6 +24,7 @@
 
 pub use arch::Kvm;
 use arch::KvmVpInner;
+use hvdef::HV_PAGE_SIZE;
 use hvdef::Vtl;
 use std::sync::atomic::Ordering;
 use virt::VpIndex;
@@ -55,6 +56,8 @@ pub enum KvmError {
 struct KvmMemoryRange {
     host_addr: *mut u8,
     range: MemoryRange,
+    readonly: bool,
+    log_dirty: bool,
 }
 
 unsafe impl Sync for KvmMemoryRange {}
@@ -64,6 +67,9 @@ unsafe impl Send for KvmMemoryRange {}
 struct KvmMemoryRangeState {
     #[inspect(flatten, iter_by_index)]
     ranges: Vec<Option<KvmMemoryRange>>,
+    /// The ranges with dirty page logging enabled.
+    #[inspect(iter_by_index)]
+    dirty_log_ranges: Vec<MemoryRange>,
 }
 
 #[derive(Inspect)]
@@ -169,13 +175,23 @@ unsafe fn map_region(
             state.ranges.push(None);
         }
         let slot_to_use = slot_to_use.unwrap();
+        let range = MemoryRange::new(addr..addr + size as u64);
+        let log_dirty = state.dirty_log_ranges.iter().any(|r| r.contains(&range));
         unsafe {
-            self.kvm
-                .set_user_memory_region(slot_to_use as u32, data, size, addr, readonly)?
+            self.kvm.set_user_memory_region(
+                slot_to_use as u32,
+                data,
+                size,
+                addr,
+                readonly,
+                log_dirty,
+            )?
         };
         state.ranges[slot_to_use] = Some(KvmMemoryRange {
             host_addr: data,
-            range: MemoryRange::new(addr..addr + size as u64),
+            range,
+            readonly,
+            log_dirty,
         });
         Ok(())
     }
@@ -188,6 +204,24 @@ fn memory_mapper(&self, vtl: Vtl) -> Arc<dyn virt::PartitionMemoryMap> {
     }
 }
 
+/// Returns the number of words in a dirty page bitmap for `range`.
+fn dirty_bitmap_len(range: MemoryRange) -> usize {
+    (range.len() / HV_PAGE_SIZE).div_ceil(64) as usize
+}
+
+/// Sets the bits for the dirty pages in a slot's `slot_bitmap` in `bitmap`,
+/// where the slot starts at page `first_page` of `bitmap`'s range.
+fn merge_dirty_bitmap(bitmap: &mut [u64], first_page: u64, slot_bitmap: &[u64]) {
+    for (i, &word) in slot_bitmap.iter().enumerate() {
+        let mut word = word;
+        while word != 0 {
+            let page = first_page + i as u64 * 64 + word.trailing_zeros() as u64;
+            bitmap[page as usize / 64] |= 1 << (page % 64);
+            word &= word - 1;
+        }
+    }
+}
+
 // TODO: figure out a better abstraction that works for both KVM and WHP.
 impl virt::PartitionMemoryMap for KvmPartitionInner {
     unsafe fn map_range(
@@ -217,6 +251,7 @@ fn unmap_range(&self, addr: u64, size: u64) -> anyhow::Result<()> {
                         0,
                         0,
                         false,
+                        false,
                     )?;
                 }
                 *entry = None;
@@ -229,4 +264,102 @@ fn unmap_range(&self, addr: u64, size: u64) -> anyhow::Result<()> {
         }
         Ok(())
     }
+
+    fn set_dirty_logging(&self, addr: u64, size: u64, enable: bool) -> anyhow::Result<bool> {
+        let range = MemoryRange::new(addr..addr + size);
+        let mut state = self.memory.lock();
+        let state = &mut *state;
+        if enable {
+            state.dirty_log_ranges.push(range);
+        } else {
+            state.dirty_log_ranges.retain(|r| r != &range);
+        }
+        for (slot, entry) in state.ranges.iter_mut().enumerate() {
+            let Some(kvm_range) = entry else { continue };
+            if !range.contains(&kvm_range.range) {
+                continue;
+            }
+            let log_dirty = state
+                .dirty_log_ranges
+                .iter()
+                .any(|r| r.contains(&kvm_range.range));
+            if log_dirty == kvm_range.log_dirty {
+                if log_dirty {
+                    // Discard the writes logged so far.
+                    let mut bitmap = vec![0; dirty_bitmap_len(kvm_range.range)];
+                    // SAFETY: the bitmap has a bit for every page in the slot.
+                    unsafe { self.kvm.get_dirty_log(slot as u32, &mut bitmap)? };
+                }
+                continue;
+            }
+            // SAFETY: this changes the flags of an existing slot without
+            // changing the memory it references.
+            unsafe {
+                self.kvm.set_user_memory_region(
+                    slot as u32,
+                    kvm_range.host_addr,
+                    kvm_range.range.len() as usize,
+                    kvm_range.range.start(),
+                    kvm_range.readonly,
+                    log_dirty,
+                )?;
+            }
+            kvm_range.log_dirty = log_dirty;
+        }
+        Ok(true)
+    }
+
+    fn get_dirty_pages(&self, addr: u64, size: u64, bitmap: &mut [u64]) -> anyhow::Result<()> {
+        let range = MemoryRange::new(addr..addr + size);
+        let len = dirty_bitmap_len(range);
+        anyhow::ensure!(bitmap.len() >= len, "dirty bitmap too small");
+        bitmap[..len].fill(0);
+
+        let state = self.memory.lock();
+        let mut slot_bitmap = Vec::new();
+        for (slot, entry) in state.ranges.iter().enumerate() {
+            let Some(kvm_range) = entry else { continue };
+            if !range.contains(&kvm_range.range) {
+                anyhow::ensure!(
+                    !range.overlaps(&kvm_range.range),
+                    "range {range} partially overlaps mapped range {}",
+                    kvm_range.range
+                );
+                continue;
+            }
+            anyhow::ensure!(
+                kvm_range.log_dirty,
+                "dirty page logging not enabled for {}",
+                kvm_range.range
+            );
+            slot_bitmap.clear();
+            slot_bitmap.resize(dirty_bitmap_len(kvm_range.range), 0);
+            // SAFETY: the bitmap has a bit for every page in the slot.
+            unsafe { self.kvm.get_dirty_log(slot as u32, &mut slot_bitmap)? };
+            let first_page = (kvm_range.range.start() - addr) / HV_PAGE_SIZE;
+            merge_dirty_bitmap(bitmap, first_page, &slot_bitmap);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::merge_dirty_bitmap;
+
+    #[test]
+    fn merge_aligned_slot() {
+        let mut bitmap = [0; 3];
+        merge_dirty_bitmap(&mut bitmap, 64, &[0b101, 1 << 63]);
+        assert_eq!(bitmap, [0, 0b101, 1 << 63]);
+    }
+
+    #[test]
+    fn merge_unaligned_slot() {
+        // The slot starts partway into a word, so its pages straddle the
+        // range's words.
+        let mut bitmap = [1, 0, 0];
+        merge_dirty_bitmap(&mut bitmap, 62, &[0b111, 1 << 63]);
+        assert_eq!(bitmap, [1 | (0b11 << 62), 1, 1 << 61]);
+    }
 }
@@ -140,6 +140,8 @@ fn run(
                         }],
                         requests: None,
                         poll_mode_queue_depth: None,
+                        queue_affinity: Vec::new(),
+                        interrupt_coalescing: None,
                     }
                     .into_resource(),
                 ))
@@ -268,6 +268,8 @@ async fn servicing_shutdown_ic(
                         io_queue_depth: None,
                         requests: None,
                         poll_mode_queue_depth: None,
+                        queue_affinity: Vec::new(),
+                        interrupt_coalescing: None,
                     }
                     .into_resource(),
                 ));
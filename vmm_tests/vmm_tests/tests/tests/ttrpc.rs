@@ -12,10 +12,17 @@
 use pal_async::task::Spawn;
 use petri::ResolvedArtifact;
 use petri_artifacts_vmm_test::artifacts;
+use std::fs::File;
 use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
 use std::process::Stdio;
 use unix_socket::UnixStream;
 
+/// The instance ID of the heartbeat IC, which OpenVMM offers to every VM
+/// created over ttrpc.
+const HEARTBEAT_INSTANCE_ID: Guid = guid::guid!("fd149e91-82e0-4a7d-afa6-2a4166cbd7c0");
+
 petri::test!(test_ttrpc_interface, |resolver| {
     // Only supported on x86_64 for now.
     if petri_artifacts_common::tags::MachineArch::host()
@@ -33,24 +40,7 @@ fn test_ttrpc_interface(
     params: petri::PetriTestParams<'_>,
     [openvmm, kernel_path, initrd_path]: [ResolvedArtifact; 3],
 ) -> anyhow::Result<()> {
-    let mut socket_path = std::env::temp_dir();
-    socket_path.push(Guid::new_random().to_string());
-
-    tracing::info!(socket_path = %socket_path.display(), "launching OpenVMM with ttrpc");
-
-    let (stderr_read, stderr_write) = pal::pipe_pair()?;
-    let mut child = std::process::Command::new(openvmm)
-        .arg("--ttrpc")
-        .arg(&socket_path)
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(stderr_write)
-        .spawn()?;
-
-    // Wait for stdout to close.
-    let mut stdout = child.stdout.take().context("failed to take stdout")?;
-    let mut b = [0];
-    assert_eq!(stdout.read(&mut b)?, 0);
+    let (mut child, socket_path, stderr_read) = launch_openvmm(&openvmm)?;
 
     DefaultPool::run_with(async |driver| {
         let driver = driver;
@@ -76,35 +66,7 @@ fn test_ttrpc_interface(
                 .call()
                 .start(
                     vmservice::Vm::CreateVm,
-                    vmservice::CreateVmRequest {
-                        config: Some(vmservice::VmConfig {
-                            memory_config: Some(vmservice::MemoryConfig {
-                                memory_mb: 256,
-                                ..Default::default()
-                            }),
-                            processor_config: Some(vmservice::ProcessorConfig {
-                                processor_count: 2,
-                                ..Default::default()
-                            }),
-                            boot_config: Some(vmservice::vm_config::BootConfig::DirectBoot(
-                                vmservice::DirectBoot {
-                                    kernel_path: kernel_path.get().to_string_lossy().to_string(),
-                                    initrd_path: initrd_path.get().to_string_lossy().to_string(),
-                                    kernel_cmdline:
-                                        "console=ttyS0 rdinit=/bin/busybox panic=-1 -- poweroff -f"
-                                            .to_string(),
-                                },
-                            )),
-                            serial_config: Some(vmservice::SerialConfig {
-                                ports: vec![vmservice::serial_config::Config {
-                                    port: 0,
-                                    socket_path: com1_path.to_string_lossy().into(),
-                                }],
-                            }),
-                            ..Default::default()
-                        }),
-                        log_id: String::new(),
-                    },
+                    create_vm_request(&kernel_path, &initrd_path, &com1_path, ""),
                 )
                 .await
                 .unwrap();
@@ -144,6 +106,36 @@ fn test_ttrpc_interface(
                     waiter.await.unwrap();
 
                     if i == 0 {
+                        let mut events = Vec::new();
+                        while events.last().is_none_or(|e: &vmservice::VmEvent| {
+                            e.r#type() != vmservice::vm_event::Type::Halted
+                        }) {
+                            events.extend(
+                                client
+                                    .call()
+                                    .start(vmservice::Vm::WaitEventsVm, ())
+                                    .await
+                                    .unwrap()
+                                    .events,
+                            );
+                        }
+                        // The guest may or may not get far enough to answer
+                        // heartbeats before it powers off.
+                        let types = events
+                            .iter()
+                            .map(|e| e.r#type())
+                            .filter(|&t| t != vmservice::vm_event::Type::HeartbeatUp)
+                            .collect::<Vec<_>>();
+                        assert_eq!(
+                            types,
+                            [
+                                vmservice::vm_event::Type::Resumed,
+                                vmservice::vm_event::Type::BootStarted,
+                                vmservice::vm_event::Type::Halted,
+                            ]
+                        );
+                        assert_eq!(events.last().unwrap().halt_reason, "power_off");
+
                         client
                             .call()
                             .start(vmservice::Vm::TeardownVm, ())
@@ -178,3 +170,243 @@ fn test_ttrpc_interface(
 
     Ok(())
 }
+
+/// Launches OpenVMM with a ttrpc server, returning the process, the path to
+/// the ttrpc socket, and OpenVMM's stderr.
+fn launch_openvmm(
+    openvmm: &ResolvedArtifact,
+) -> anyhow::Result<(std::process::Child, PathBuf, File)> {
+    let mut socket_path = std::env::temp_dir();
+    socket_path.push(Guid::new_random().to_string());
+
+    tracing::info!(socket_path = %socket_path.display(), "launching OpenVMM with ttrpc");
+
+    let (stderr_read, stderr_write) = pal::pipe_pair()?;
+    let mut child = std::process::Command::new(openvmm)
+        .arg("--ttrpc")
+        .arg(&socket_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(stderr_write)
+        .spawn()?;
+
+    // Wait for stdout to close.
+    let mut stdout = child.stdout.take().context("failed to take stdout")?;
+    let mut b = [0];
+    assert_eq!(stdout.read(&mut b)?, 0);
+
+    Ok((child, socket_path, stderr_read))
+}
+
+/// Returns a request to create a Linux VM that powers off as soon as it
+/// boots, with COM1 connected to `com1_path`.
+fn create_vm_request(
+    kernel_path: &ResolvedArtifact,
+    initrd_path: &ResolvedArtifact,
+    com1_path: &Path,
+    restore_snapshot_path: &str,
+) -> vmservice::CreateVmRequest {
+    vmservice::CreateVmRequest {
+        config: Some(vmservice::VmConfig {
+            memory_config: Some(vmservice::MemoryConfig {
+                memory_mb: 256,
+                ..Default::default()
+            }),
+            processor_config: Some(vmservice::ProcessorConfig {
+                processor_count: 2,
+                ..Default::default()
+            }),
+            boot_config: Some(vmservice::vm_config::BootConfig::DirectBoot(
+                vmservice::DirectBoot {
+                    kernel_path: kernel_path.get().to_string_lossy().to_string(),
+                    initrd_path: initrd_path.get().to_string_lossy().to_string(),
+                    kernel_cmdline: "console=ttyS0 rdinit=/bin/busybox panic=-1 -- poweroff -f"
+                        .to_string(),
+                },
+            )),
+            serial_config: Some(vmservice::SerialConfig {
+                ports: vec![vmservice::serial_config::Config {
+                    port: 0,
+                    socket_path: com1_path.to_string_lossy().into(),
+                }],
+            }),
+            ..Default::default()
+        }),
+        log_id: String::new(),
+        restore_snapshot_path: restore_snapshot_path.to_string(),
+    }
+}
+
+petri::test!(test_ttrpc_management, |resolver| {
+    // Only supported on x86_64 for now.
+    if petri_artifacts_common::tags::MachineArch::host()
+        != petri_artifacts_common::tags::MachineArch::X86_64
+    {
+        return None;
+    }
+    let openvmm = resolver.require(artifacts::OPENVMM_NATIVE);
+    let kernel = resolver.require(artifacts::loadable::LINUX_DIRECT_TEST_KERNEL_NATIVE);
+    let initrd = resolver.require(artifacts::loadable::LINUX_DIRECT_TEST_INITRD_NATIVE);
+    Some([openvmm.erase(), kernel.erase(), initrd.erase()])
+});
+
+/// Tests the ttrpc requests for runtime VM management: capabilities, reset,
+/// vmbus device removal, and saving and restoring snapshots.
+fn test_ttrpc_management(
+    params: petri::PetriTestParams<'_>,
+    [openvmm, kernel_path, initrd_path]: [ResolvedArtifact; 3],
+) -> anyhow::Result<()> {
+    let (mut child, socket_path, stderr_read) = launch_openvmm(&openvmm)?;
+    let mut snapshot_path = std::env::temp_dir();
+    snapshot_path.push(Guid::new_random().to_string());
+
+    DefaultPool::run_with(async |driver| {
+        let driver = driver;
+        let _stderr_task = driver.spawn(
+            "stderr",
+            petri::log_task(
+                params.logger.log_file("stderr").unwrap(),
+                PolledPipe::new(&driver, stderr_read).unwrap(),
+                "openvmm stderr",
+            ),
+        );
+
+        let client = mesh_rpc::Client::new(
+            &driver,
+            mesh_rpc::client::UnixDialier::new(driver.clone(), socket_path.clone()),
+        );
+
+        // Capabilities are available before a VM is created.
+        let capabilities = client
+            .call()
+            .start(vmservice::Vm::CapabilitiesVm, ())
+            .await
+            .unwrap();
+        assert!(capabilities.api_version >= 1);
+        assert!(capabilities.supported_resources.iter().any(|r| {
+            r.resource() == vmservice::capabilities_vm_response::Resource::VmbusDevice
+                && r.remove
+                && !r.add
+        }));
+
+        // VM requests fail before a VM is created.
+        client
+            .call()
+            .start(vmservice::Vm::ResetVm, ())
+            .await
+            .unwrap_err();
+
+        let mut com1_path = std::env::temp_dir();
+        com1_path.push(Guid::new_random().to_string());
+        client
+            .call()
+            .start(
+                vmservice::Vm::CreateVm,
+                create_vm_request(&kernel_path, &initrd_path, &com1_path, ""),
+            )
+            .await
+            .unwrap();
+
+        // Snapshot the VM before it first runs, while it still has all of
+        // its devices.
+        client
+            .call()
+            .start(
+                vmservice::Vm::SaveSnapshotVm,
+                vmservice::SaveSnapshotVmRequest {
+                    path: snapshot_path.to_string_lossy().into(),
+                },
+            )
+            .await
+            .unwrap();
+        assert_ne!(std::fs::metadata(&snapshot_path).unwrap().len(), 0);
+
+        // Remove the heartbeat IC, which is offered to every VM. Removing it
+        // again fails since it is gone.
+        let remove_heartbeat = || {
+            client.call().start(
+                vmservice::Vm::ModifyResource,
+                vmservice::ModifyResourceRequest {
+                    r#type: vmservice::ModifyType::Remove as i32,
+                    resource: Some(vmservice::modify_resource_request::Resource::VmbusDevice(
+                        vmservice::VmbusDevice {
+                            instance_id: HEARTBEAT_INSTANCE_ID.to_string(),
+                        },
+                    )),
+                },
+            )
+        };
+        remove_heartbeat().await.unwrap();
+        remove_heartbeat().await.unwrap_err();
+
+        // Reset the VM and wait for the reset event.
+        client
+            .call()
+            .start(vmservice::Vm::ResetVm, ())
+            .await
+            .unwrap();
+        let mut events = Vec::new();
+        while events
+            .last()
+            .is_none_or(|e: &vmservice::VmEvent| e.r#type() != vmservice::vm_event::Type::Reset)
+        {
+            events.extend(
+                client
+                    .call()
+                    .start(vmservice::Vm::WaitEventsVm, ())
+                    .await
+                    .unwrap()
+                    .events,
+            );
+        }
+
+        client
+            .call()
+            .start(vmservice::Vm::TeardownVm, ())
+            .await
+            .unwrap();
+
+        // Restore the snapshot and let the guest run to power off.
+        let mut com1_path = std::env::temp_dir();
+        com1_path.push(Guid::new_random().to_string());
+        client
+            .call()
+            .start(
+                vmservice::Vm::CreateVm,
+                create_vm_request(
+                    &kernel_path,
+                    &initrd_path,
+                    &com1_path,
+                    &snapshot_path.to_string_lossy(),
+                ),
+            )
+            .await
+            .unwrap();
+
+        let com1 = UnixStream::connect(&com1_path).unwrap();
+        let _com1_task = driver.spawn(
+            "com1",
+            petri::log_task(
+                params.logger.log_file("linux").unwrap(),
+                PolledSocket::new(&driver, com1).unwrap(),
+                "linux com1",
+            ),
+        );
+
+        let waiter = client.call().start(vmservice::Vm::WaitVm, ());
+        client
+            .call()
+            .start(vmservice::Vm::ResumeVm, ())
+            .await
+            .unwrap();
+        waiter.await.unwrap();
+
+        let _ = client.call().start(vmservice::Vm::Quit, ()).await;
+    });
+
+    child.wait()?;
+    let _ = std::fs::remove_file(&socket_path);
+    let _ = std::fs::remove_file(&snapshot_path);
+
+    Ok(())
+}
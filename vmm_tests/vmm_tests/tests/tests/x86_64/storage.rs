@@ -226,6 +226,8 @@ async fn storvsp(config: PetriVmBuilder<OpenVmmPetriBackend>) -> Result<(), anyh
                         io_queue_depth: None,
                         requests: None,
                         poll_mode_queue_depth: None,
+                        queue_affinity: Vec::new(),
+                        interrupt_coalescing: None,
                     }
                     .into_resource(),
                 ));
@@ -505,6 +507,8 @@ async fn openhcl_linux_storvsp_dvd(
                         io_queue_depth: None,
                         requests: None,
                         poll_mode_queue_depth: None,
+                        queue_affinity: Vec::new(),
+                        interrupt_coalescing: None,
                     }
                     .into_resource(),
                 ));
@@ -272,4 +272,18 @@ fn read_line(&mut self, line: u16, data: &mut [u8]) {
     fn resolution(&mut self) -> (u16, u16) {
         self.0.resolution()
     }
+
+    fn poll_resolution_change(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<()> {
+        self.0.poll_format_change(cx).map(drop)
+    }
+
+    fn take_dirty(&mut self) -> Option<vnc::Rect> {
+        let rect = self.0.take_dirty()?;
+        Some(vnc::Rect {
+            x: rect.left as u16,
+            y: rect.top as u16,
+            width: (rect.right - rect.left) as u16,
+            height: (rect.bottom - rect.top) as u16,
+        })
+    }
 }
@@ -15,6 +15,8 @@
 use futures::channel::mpsc;
 use futures::future::OptionFuture;
 use pal_async::socket::PolledSocket;
+use std::task::Context;
+use std::task::Poll;
 use thiserror::Error;
 use zerocopy::FromZeros;
 use zerocopy::IntoBytes;
@@ -33,10 +35,30 @@ pub enum Error {
     DesktopResizeNotSupported,
 }
 
+/// A rectangle of the framebuffer, in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
 /// A trait used to retrieve data from a framebuffer.
 pub trait Framebuffer: Send + Sync {
     fn resolution(&mut self) -> (u16, u16);
     fn read_line(&mut self, line: u16, data: &mut [u8]);
+    /// Returns the region that has changed since the last call, or `None` if
+    /// it is not known, in which case the whole framebuffer is sent.
+    fn take_dirty(&mut self) -> Option<Rect> {
+        None
+    }
+    /// Polls for a change to the resolution, so that the client can be
+    /// resized without waiting for the next update.
+    fn poll_resolution_change(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let _ = cx;
+        Poll::Pending
+    }
 }
 
 pub const HID_MOUSE_MAX_ABS_VALUE: u32 = 0x7FFFu32;
@@ -167,6 +189,7 @@ async fn run_internal(&mut self) -> Result<(), Error> {
         socket.write_all(name).await?;
 
         let mut ready_for_update = false;
+        let mut full_update = true;
         let mut scancode_state = scancode::State::new();
         loop {
             let mut socket_ready = false;
@@ -176,8 +199,13 @@ async fn run_internal(&mut self) -> Result<(), Error> {
             let mut update: OptionFuture<_> = ready_for_update
                 .then(|| update_recv.select_next_some())
                 .into();
+            let fb = &mut self.fb;
+            let resized = std::future::poll_fn(|cx| fb.poll_resolution_change(cx));
             futures::select! { // merge semantics
                 _ = update => update_ready = true,
+                // Resize the client as soon as possible. If the client is not
+                // ready for an update, the change is picked up by the next one.
+                _ = resized.fuse() => update_ready = true,
                 r = socket.read(message_type.as_mut_bytes()).fuse() => {
                     if r? == 0 {
                         return Ok(())
@@ -195,6 +223,7 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                     // Send the new desktop size.
                     width = new_width;
                     height = new_height;
+                    full_update = true;
                     socket
                         .write_all(
                             rfb::FramebufferUpdate {
@@ -218,7 +247,24 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                         )
                         .await?;
                 } else {
-                    // Send the update. Just update the whole framebuffer for now.
+                    // Send the update, limited to the region the guest
+                    // reported as changed, if any.
+                    let dirty = self.fb.take_dirty();
+                    let rect = dirty
+                        .filter(|rect| {
+                            !full_update
+                                && rect.width > 0
+                                && rect.height > 0
+                                && rect.x.checked_add(rect.width).is_some_and(|r| r <= width)
+                                && rect.y.checked_add(rect.height).is_some_and(|b| b <= height)
+                        })
+                        .unwrap_or(Rect {
+                            x: 0,
+                            y: 0,
+                            width,
+                            height,
+                        });
+                    full_update = false;
                     socket
                         .write_all(
                             rfb::FramebufferUpdate {
@@ -232,47 +278,45 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                     socket
                         .write_all(
                             rfb::Rectangle {
-                                x: 0.into(),
-                                y: 0.into(),
-                                width: width.into(),
-                                height: height.into(),
+                                x: rect.x.into(),
+                                y: rect.y.into(),
+                                width: rect.width.into(),
+                                height: rect.height.into(),
                                 encoding_type: rfb::ENCODING_TYPE_RAW.into(),
                             }
                             .as_bytes(),
                         )
                         .await?;
                     let mut src_line = vec![0u32; width as usize];
+                    let columns = rect.x as usize..(rect.x + rect.width) as usize;
+                    let rows = rect.y..rect.y + rect.height;
                     let dest_depth = fmt.bits_per_pixel as usize / 8;
                     let shift_r = 24 - fmt.red_max.get().count_ones();
                     let shift_g = 16 - fmt.green_max.get().count_ones();
                     let shift_b = 8 - fmt.red_max.get().count_ones();
+                    let convert = |p: u32| {
+                        let (r, g, b) = (p & 0xff0000, p & 0xff00, p & 0xff);
+                        r >> shift_r << fmt.red_shift
+                            | g >> shift_g << fmt.green_shift
+                            | b >> shift_b << fmt.blue_shift
+                    };
                     match dest_depth {
                         1 => {
-                            let mut line = vec![0u8; width as usize];
-                            for y in 0..height {
+                            let mut line = vec![0u8; columns.len()];
+                            for y in rows {
                                 self.fb.read_line(y, src_line.as_mut_bytes());
-                                for x in 0..width as usize {
-                                    let p = src_line[x];
-                                    let (r, g, b) = (p & 0xff0000, p & 0xff00, p & 0xff);
-                                    let p2 = r >> shift_r << fmt.red_shift
-                                        | g >> shift_g << fmt.green_shift
-                                        | b >> shift_b << fmt.blue_shift;
-                                    line[x] = p2 as u8;
+                                for (dest, &p) in line.iter_mut().zip(&src_line[columns.clone()]) {
+                                    *dest = convert(p) as u8;
                                 }
                                 socket.write_all(&line).await?;
                             }
                         }
                         2 => {
-                            let mut line = vec![0u16; width as usize];
-                            for y in 0..height {
+                            let mut line = vec![0u16; columns.len()];
+                            for y in rows {
                                 self.fb.read_line(y, src_line.as_mut_bytes());
-                                for x in 0..width as usize {
-                                    let p = src_line[x];
-                                    let (r, g, b) = (p & 0xff0000, p & 0xff00, p & 0xff);
-                                    let p2 = r >> shift_r << fmt.red_shift
-                                        | g >> shift_g << fmt.green_shift
-                                        | b >> shift_b << fmt.blue_shift;
-                                    line[x] = p2 as u16;
+                                for (dest, &p) in line.iter_mut().zip(&src_line[columns.clone()]) {
+                                    *dest = convert(p) as u16;
                                 }
                                 socket.write_all(line.as_bytes()).await?;
                             }
@@ -281,22 +325,19 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                             && shift_g == fmt.green_shift as u32
                             && shift_b == fmt.blue_shift as u32 =>
                         {
-                            for y in 0..height {
+                            for y in rows {
                                 self.fb.read_line(y, src_line.as_mut_bytes());
-                                socket.write_all(src_line.as_bytes()).await?;
+                                socket
+                                    .write_all(src_line[columns.clone()].as_bytes())
+                                    .await?;
                             }
                         }
                         4 => {
-                            let mut line = vec![0u32; width as usize];
-                            for y in 0..height {
+                            let mut line = vec![0u32; columns.len()];
+                            for y in rows {
                                 self.fb.read_line(y, src_line.as_mut_bytes());
-                                for x in (width as usize & !3)..width as usize {
-                                    let p = src_line[x];
-                                    let (r, g, b) = (p & 0xff0000, p & 0xff00, p & 0xff);
-                                    let p2 = r >> shift_r << fmt.red_shift
-                                        | g >> shift_g << fmt.green_shift
-                                        | b >> shift_b << fmt.blue_shift;
-                                    line[x] = p2;
+                                for (dest, &p) in line.iter_mut().zip(&src_line[columns.clone()]) {
+                                    *dest = convert(p);
                                 }
                                 socket.write_all(line.as_bytes()).await?;
                             }
@@ -350,6 +391,9 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                     rfb::CS_MESSAGE_FRAMEBUFFER_UPDATE_REQUEST => {
                         let mut input = rfb::FramebufferUpdateRequest::new_zeroed();
                         socket.read_exact(&mut input.as_mut_bytes()[1..]).await?;
+                        if input.incremental == 0 {
+                            full_update = true;
+                        }
                         ready_for_update = true;
                     }
                     rfb::CS_MESSAGE_KEY_EVENT => {